@@ -22,7 +22,7 @@ local function check_binary()
 end
 
 -- Check if lockdir is accessible — same resolution as the plugin/binary
--- (SHAREDSERVER_LOCKDIR, then XDG_RUNTIME_DIR, then /tmp).
+-- (SHAREDSERVER_LOCKDIR, then XDG_RUNTIME_DIR, then /tmp/sharedserver-<uid>).
 local function check_lockdir()
     local ok, sharedserver = pcall(require, "sharedserver")
     if not ok then
@@ -35,6 +35,11 @@ local function check_lockdir()
     local stat = vim.loop.fs_stat(lockdir)
     if stat then
         if stat.type == "directory" then
+            -- The binary refuses a lock directory someone else owns
+            local uid = (vim.uv or vim.loop).getuid()
+            if stat.uid ~= uid then
+                return false, "Directory is owned by UID " .. stat.uid .. ", not you (UID " .. uid .. "): " .. lockdir
+            end
             -- Check if writable
             local test_file = lockdir .. "/.health_check_test"
             local f = io.open(test_file, "w")
@@ -50,7 +55,7 @@ local function check_lockdir()
         end
     else
         -- Try to create it
-        local success = vim.fn.mkdir(lockdir, "p")
+        local success = vim.fn.mkdir(lockdir, "p", 448)
         if success == 1 then
             return true, lockdir
         else
@@ -7,7 +7,8 @@ M._servers = {}
 -- Matches the Rust sharedserver logic:
 -- 1. Check SHAREDSERVER_LOCKDIR env var
 -- 2. Use XDG_RUNTIME_DIR/sharedserver if set
--- 3. Fall back to /tmp/sharedserver
+-- 3. Fall back to /tmp/sharedserver-<uid>, per user so users sharing /tmp
+--    don't trip over each other's locks
 M._get_lockdir = function()
     local lockdir_env = os.getenv("SHAREDSERVER_LOCKDIR")
     if lockdir_env then
@@ -19,7 +20,7 @@ M._get_lockdir = function()
         return xdg_runtime .. "/sharedserver"
     end
 
-    return "/tmp/sharedserver"
+    return "/tmp/sharedserver-" .. (vim.uv or vim.loop).getuid()
 end
 
 -- Default configuration
@@ -129,7 +130,7 @@ M._call_sharedserver = function(args, opts)
 
     -- Set SHAREDSERVER_LOCKDIR to system-wide cache directory
     local lockdir = M._get_lockdir()
-    vim.fn.mkdir(lockdir, "p")
+    vim.fn.mkdir(lockdir, "p", 448) -- 0700, as the binary creates it
 
     local env = vim.tbl_extend("force", vim.fn.environ(), {
         SHAREDSERVER_LOCKDIR = lockdir,
@@ -425,7 +426,7 @@ M._start_with_sharedserver = function(name, server, config)
 
     -- Set SHAREDSERVER_LOCKDIR environment variable
     local lockdir = M._get_lockdir()
-    vim.fn.mkdir(lockdir, "p")
+    vim.fn.mkdir(lockdir, "p", 448) -- 0700, as the binary creates it
 
     local env = vim.tbl_extend("force", vim.fn.environ(), {
         SHAREDSERVER_LOCKDIR = lockdir,
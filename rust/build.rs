@@ -0,0 +1,11 @@
+fn main() {
+    #[cfg(feature = "grpc")]
+    {
+        println!("cargo:rerun-if-changed=proto/sharedserver.proto");
+        let descriptors = protox::compile(["sharedserver.proto"], ["proto"])
+            .expect("Failed to compile proto/sharedserver.proto");
+        tonic_prost_build::configure()
+            .compile_fds(descriptors)
+            .expect("Failed to generate the gRPC code");
+    }
+}
@@ -0,0 +1,92 @@
+/*
+ * sharedserver C API: attach to, release, and inspect shared servers from
+ * C and C++ without running the `sharedserver` CLI for each call.
+ *
+ * Link against libsharedserver_ffi (built with `cargo build -p
+ * sharedserver-ffi --release` in rust/). Starting a server still runs the
+ * `sharedserver` binary (the one named by $SHAREDSERVER_BIN, else the one on
+ * PATH), since a server's watcher is part of it; everything else happens
+ * in-process.
+ *
+ * Every function returns SHAREDSERVER_OK or an error code; the error codes
+ * are the CLI's exit codes. After an error, sharedserver_last_error() says
+ * what went wrong. Functions are safe to call from any thread.
+ */
+
+#ifndef SHAREDSERVER_H
+#define SHAREDSERVER_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Return codes */
+#define SHAREDSERVER_OK 0
+#define SHAREDSERVER_ERROR 1            /* any other failure */
+#define SHAREDSERVER_INVALID_ARGS 2     /* e.g. a NULL or non-UTF-8 name */
+#define SHAREDSERVER_NOT_RUNNING 10
+#define SHAREDSERVER_SHUTTING_DOWN 11   /* retry shortly */
+#define SHAREDSERVER_ALREADY_RUNNING 12
+#define SHAREDSERVER_NOT_ATTACHED 13
+#define SHAREDSERVER_LOCK_TIMEOUT 14
+#define SHAREDSERVER_START_TIMEOUT 15
+#define SHAREDSERVER_ISSUES_REMAIN 16
+#define SHAREDSERVER_AT_CAPACITY 17     /* max_running reached */
+
+/* Server states reported by sharedserver_check (`sharedserver check`'s exit
+ * codes) */
+#define SHAREDSERVER_STATE_ACTIVE 0
+#define SHAREDSERVER_STATE_GRACE 1
+#define SHAREDSERVER_STATE_STOPPED 2
+#define SHAREDSERVER_STATE_DEFUNCT 3
+#define SHAREDSERVER_STATE_UNHEALTHY 4  /* running, but failing its health check */
+
+/* How sharedserver_use starts a server it finds stopped. Every field may be
+ * NULL/0. */
+typedef struct sharedserver_use_options {
+    /* The command to start the server with, as a NULL-terminated argv.
+     * Without one, the server must already be running. */
+    const char *const *command;
+    /* e.g. "10m" (default: the CLI's, 5m) */
+    const char *grace_period;
+    /* Shown next to the client in `sharedserver info`. */
+    const char *metadata;
+    /* The client process the reference stands for (0: the calling
+     * process). The server's watcher drops it if that process dies. */
+    int32_t pid;
+} sharedserver_use_options;
+
+/* Take a reference on `name`, starting it with opts->command if it isn't
+ * running (`sharedserver use`). `opts` may be NULL. The reference lasts until
+ * sharedserver_unuse releases it or the client dies. On success, stores the
+ * new refcount in `*refcount` unless it is NULL. */
+int sharedserver_use(const char *name, const sharedserver_use_options *opts,
+                     uint32_t *refcount);
+
+/* Release client `pid`'s reference on `name` (0: the calling process's). On
+ * success, stores the refcount left in `*refcount` unless it is NULL; zero
+ * starts the server's grace period. */
+int sharedserver_unuse(const char *name, int32_t pid, uint32_t *refcount);
+
+/* Store `name`'s state, one of SHAREDSERVER_STATE_*, in `*state`. */
+int sharedserver_check(const char *name, int *state);
+
+/* Store `name`'s details (state, refcount, clients, and everything recorded
+ * about the server) as a JSON object in `*json`, to be freed with
+ * sharedserver_free_string. SHAREDSERVER_NOT_RUNNING if it is stopped. */
+int sharedserver_info(const char *name, char **json);
+
+/* Free a string returned by this library. NULL is ignored. */
+void sharedserver_free_string(char *string);
+
+/* The message for the last error on this thread, or NULL if the last call
+ * succeeded. Valid until the thread's next call into this library. */
+const char *sharedserver_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* SHAREDSERVER_H */
@@ -0,0 +1,263 @@
+//! C bindings for sharedserver, declared in `include/sharedserver.h`.
+//!
+//! Each function wraps the library API ([`ServerManager`] and friends),
+//! turning its `anyhow` error into the CLI's exit code for the return value
+//! and keeping the message for `sharedserver_last_error`. Panics are caught
+//! at the boundary and reported the same way.
+
+use anyhow::{Context, Result};
+use sharedserver::core::error::exit_code;
+use sharedserver::{get_server_state, ErrorKind, ServerManager, ServerState, UseOptions};
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+/// `SHAREDSERVER_OK`
+pub const SHAREDSERVER_OK: c_int = 0;
+
+/// `sharedserver check`'s code for a running server failing its health check.
+const STATE_UNHEALTHY: c_int = 4;
+
+/// Mirrors `sharedserver_use_options` in the header.
+#[repr(C)]
+pub struct SharedserverUseOptions {
+    pub command: *const *const c_char,
+    pub grace_period: *const c_char,
+    pub metadata: *const c_char,
+    pub pid: i32,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// # Safety
+///
+/// `name` must be a NUL-terminated string. `opts`, if not NULL, must point to
+/// a valid `sharedserver_use_options` whose strings are NUL-terminated and
+/// whose `command`, if not NULL, is a NULL-terminated array of them.
+/// `refcount` must be NULL or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn sharedserver_use(
+    name: *const c_char,
+    opts: *const SharedserverUseOptions,
+    refcount: *mut u32,
+) -> c_int {
+    call(|| {
+        let name = required(name, "name")?;
+        let mut use_opts = UseOptions::default();
+        if let Some(opts) = opts.as_ref() {
+            use_opts.command = argv(opts.command)?;
+            use_opts.grace_period = optional(opts.grace_period, "grace_period")?;
+            use_opts.metadata = optional(opts.metadata, "metadata")?;
+            use_opts.pid = (opts.pid != 0).then_some(opts.pid);
+        }
+        let count = ServerManager::new().use_pid(&name, &use_opts)?;
+        if let Some(refcount) = refcount.as_mut() {
+            *refcount = count;
+        }
+        Ok(())
+    })
+}
+
+/// # Safety
+///
+/// `name` must be a NUL-terminated string; `refcount` NULL or valid for
+/// writes.
+#[no_mangle]
+pub unsafe extern "C" fn sharedserver_unuse(
+    name: *const c_char,
+    pid: i32,
+    refcount: *mut u32,
+) -> c_int {
+    call(|| {
+        let name = required(name, "name")?;
+        let count = ServerManager::new().unuse(&name, (pid != 0).then_some(pid))?;
+        if let Some(refcount) = refcount.as_mut() {
+            *refcount = count;
+        }
+        Ok(())
+    })
+}
+
+/// # Safety
+///
+/// `name` must be a NUL-terminated string; `state` valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn sharedserver_check(name: *const c_char, state: *mut c_int) -> c_int {
+    call(|| {
+        let name = required(name, "name")?;
+        let out = state
+            .as_mut()
+            .ok_or_else(|| ErrorKind::InvalidArgs.error("state is NULL"))?;
+        let current = get_server_state(&name)?;
+        *out = match current {
+            ServerState::Active | ServerState::Grace
+                if sharedserver::read_server_lock(&name).is_ok_and(|lock| lock.is_unhealthy()) =>
+            {
+                STATE_UNHEALTHY
+            }
+            _ => current.exit_code(),
+        };
+        Ok(())
+    })
+}
+
+/// # Safety
+///
+/// `name` must be a NUL-terminated string; `json` valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn sharedserver_info(name: *const c_char, json: *mut *mut c_char) -> c_int {
+    call(|| {
+        let name = required(name, "name")?;
+        let out = json
+            .as_mut()
+            .ok_or_else(|| ErrorKind::InvalidArgs.error("json is NULL"))?;
+        let info = ServerManager::new().info(&name)?;
+        let encoded = serde_json::to_string(&info).context("Failed to encode server info")?;
+        *out = CString::new(encoded)
+            .context("Server info contains a NUL byte")?
+            .into_raw();
+        Ok(())
+    })
+}
+
+/// # Safety
+///
+/// `string` must be NULL or a string returned by this library, not already
+/// freed.
+#[no_mangle]
+pub unsafe extern "C" fn sharedserver_free_string(string: *mut c_char) {
+    if !string.is_null() {
+        drop(CString::from_raw(string));
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn sharedserver_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(std::ptr::null(), |message| message.as_ptr())
+    })
+}
+
+/// Run `f`, recording how it went for `sharedserver_last_error` and
+/// returning its status code.
+fn call(f: impl FnOnce() -> Result<()>) -> c_int {
+    let (code, message) = match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => (SHAREDSERVER_OK, None),
+        Ok(Err(e)) => (exit_code(&e), Some(format!("{:#}", e))),
+        Err(_) => (
+            ErrorKind::GENERAL_EXIT_CODE,
+            Some("internal error: sharedserver panicked".to_string()),
+        ),
+    };
+    let message = message
+        .map(|message| CString::new(message.replace('\0', " ")).expect("NUL bytes were replaced"));
+    LAST_ERROR.with(|last| *last.borrow_mut() = message);
+    code
+}
+
+/// A string argument that must be given.
+unsafe fn required(ptr: *const c_char, what: &str) -> Result<String> {
+    optional(ptr, what)?.ok_or_else(|| ErrorKind::InvalidArgs.error(format!("{} is NULL", what)))
+}
+
+unsafe fn optional(ptr: *const c_char, what: &str) -> Result<Option<String>> {
+    if ptr.is_null() {
+        return Ok(None);
+    }
+    CStr::from_ptr(ptr)
+        .to_str()
+        .map(|s| Some(s.to_string()))
+        .map_err(|_| ErrorKind::InvalidArgs.error(format!("{} is not valid UTF-8", what)))
+}
+
+/// A NULL-terminated argv, or none.
+unsafe fn argv(mut ptr: *const *const c_char) -> Result<Vec<String>> {
+    let mut args = Vec::new();
+    if ptr.is_null() {
+        return Ok(args);
+    }
+    while !(*ptr).is_null() {
+        args.push(required(*ptr, "command")?);
+        ptr = ptr.add(1);
+    }
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn last_error() -> Option<String> {
+        let message = sharedserver_last_error();
+        (!message.is_null()).then(|| {
+            unsafe { CStr::from_ptr(message) }
+                .to_string_lossy()
+                .into_owned()
+        })
+    }
+
+    #[test]
+    fn test_header_codes_match_the_cli() {
+        let header = include_str!("../include/sharedserver.h");
+        let defined = |name: &str| -> i32 {
+            let line = header
+                .lines()
+                .find(|line| line.starts_with(&format!("#define {} ", name)))
+                .unwrap_or_else(|| panic!("{} is not defined", name));
+            line.split_whitespace().nth(2).unwrap().parse().unwrap()
+        };
+        assert_eq!(defined("SHAREDSERVER_OK"), SHAREDSERVER_OK);
+        assert_eq!(defined("SHAREDSERVER_ERROR"), ErrorKind::GENERAL_EXIT_CODE);
+        for kind in ErrorKind::ALL {
+            let name = format!(
+                "SHAREDSERVER_{}",
+                kind.as_str().replace('-', "_").to_uppercase()
+            );
+            assert_eq!(defined(&name), kind.exit_code(), "{}", name);
+        }
+        for state in [
+            ServerState::Active,
+            ServerState::Grace,
+            ServerState::Stopped,
+            ServerState::Defunct,
+        ] {
+            let name = format!("SHAREDSERVER_STATE_{}", state.as_str().to_uppercase());
+            assert_eq!(defined(&name), state.exit_code(), "{}", name);
+        }
+        assert_eq!(defined("SHAREDSERVER_STATE_UNHEALTHY"), STATE_UNHEALTHY);
+    }
+
+    #[test]
+    fn test_errors_are_coded_and_kept() {
+        let mut refcount = 0;
+        let code = unsafe { sharedserver_unuse(std::ptr::null(), 0, &mut refcount) };
+        assert_eq!(code, ErrorKind::InvalidArgs.exit_code());
+        assert_eq!(last_error().as_deref(), Some("name is NULL"));
+
+        let dir = std::env::temp_dir().join(format!("sharedserver-ffi-{}", std::process::id()));
+        std::env::set_var("SHAREDSERVER_LOCKDIR", &dir);
+        let name = CString::new("ffi-missing").unwrap();
+        let mut state = -1;
+        assert_eq!(
+            unsafe { sharedserver_check(name.as_ptr(), &mut state) },
+            SHAREDSERVER_OK
+        );
+        assert_eq!(state, ServerState::Stopped.exit_code());
+        assert_eq!(last_error(), None);
+
+        let mut json = std::ptr::null_mut();
+        let code = unsafe { sharedserver_info(name.as_ptr(), &mut json) };
+        assert_eq!(code, ErrorKind::NotRunning.exit_code());
+        assert!(json.is_null());
+        assert!(last_error().unwrap().contains("not running"));
+
+        // Not running, and nothing to start it with.
+        let code = unsafe { sharedserver_use(name.as_ptr(), std::ptr::null(), &mut refcount) };
+        assert_eq!(code, ErrorKind::NotRunning.exit_code());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
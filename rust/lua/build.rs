@@ -0,0 +1,8 @@
+fn main() {
+    // Lua modules leave the Lua API for the host to resolve at load time;
+    // macOS's linker refuses undefined symbols unless told to.
+    if std::env::var("CARGO_CFG_TARGET_OS").as_deref() == Ok("macos") {
+        println!("cargo:rustc-cdylib-link-arg=-undefined");
+        println!("cargo:rustc-cdylib-link-arg=dynamic_lookup");
+    }
+}
@@ -0,0 +1,463 @@
+//! Native Lua module for sharedserver: attach to, release, and inspect
+//! servers from Neovim (or any LuaJIT host) without running the CLI for each
+//! call, and get called back as a server changes.
+//!
+//! This is a loadable module, not an embedding: the Lua API comes from the
+//! host through mlua's raw LuaJIT bindings (`mlua-sys`, unlinked).
+//! [`luaopen_sharedserver_native`] builds the native functions below and
+//! hands them to `native.lua`, which adds event-loop integration and is what
+//! `require("sharedserver.native")` returns.
+//!
+//! Failures are returned Lua style, as `nil, message, kind`, where `kind` is
+//! the CLI's name for the failure (e.g. `"not-running"`) or nil. Nothing here
+//! raises a Lua error, so no error ever unwinds through Rust frames, and
+//! panics are caught at the boundary and reported the same way.
+
+use anyhow::{Context, Result};
+use mlua_sys::*;
+use sharedserver::core::events::{self, StateEvent};
+use sharedserver::{ErrorKind, ServerManager, ServerState, UseOptions};
+use std::collections::VecDeque;
+use std::ffi::{c_int, CStr};
+use std::io::{Read, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The Lua half of the module, run with the native functions.
+const WRAPPER: &str = include_str!("native.lua");
+
+/// Registry name of the subscription metatable.
+const SUBSCRIPTION: &CStr = c"sharedserver.subscription";
+
+/// How often a subscription's thread checks whether it has been closed.
+const STOP_CHECK: Duration = Duration::from_millis(500);
+
+/// Lua's entry point for `require("sharedserver.native")`.
+///
+/// # Safety
+///
+/// `l` must be a live LuaJIT state, and this called by Lua.
+#[no_mangle]
+pub unsafe extern "C-unwind" fn luaopen_sharedserver_native(l: *mut lua_State) -> c_int {
+    if luaL_loadbuffer(
+        l,
+        WRAPPER.as_ptr().cast(),
+        WRAPPER.len(),
+        c"=sharedserver/native.lua".as_ptr(),
+    ) != 0
+    {
+        // The wrapper is part of this library, so it always compiles.
+        lua_error(l);
+    }
+
+    lua_createtable(l, 0, 5);
+    for (name, function) in [
+        (c"attach", attach as lua_CFunction),
+        (c"detach", detach),
+        (c"info", info),
+        (c"list", list),
+        (c"subscribe", subscribe),
+    ] {
+        lua_pushcfunction(l, function);
+        lua_setfield(l, -2, name.as_ptr());
+    }
+
+    luaL_newmetatable(l, SUBSCRIPTION.as_ptr());
+    lua_createtable(l, 0, 4);
+    for (name, function) in [
+        (c"fd", subscription_fd as lua_CFunction),
+        (c"events", subscription_events),
+        (c"state", subscription_state),
+        (c"close", subscription_close),
+    ] {
+        lua_pushcfunction(l, function);
+        lua_setfield(l, -2, name.as_ptr());
+    }
+    lua_setfield(l, -2, c"__index".as_ptr());
+    lua_pushcfunction(l, subscription_close);
+    lua_setfield(l, -2, c"__gc".as_ptr());
+    lua_pop(l, 1);
+
+    lua_call(l, 1, 1);
+    1
+}
+
+/// `attach(name, opts)`: take a reference on `name` for `opts.pid` (default:
+/// this process), starting it with `opts.command` if it isn't running, as
+/// `sharedserver use --pid` does. Returns the new refcount.
+unsafe extern "C-unwind" fn attach(l: *mut lua_State) -> c_int {
+    call(l, || {
+        let name = required_string(l, 1, "name")?;
+        let mut opts = UseOptions::default();
+        match lua_type(l, 2) {
+            LUA_TNONE | LUA_TNIL => {}
+            LUA_TTABLE => {
+                opts.command = field(l, 2, c"command", strings)?.unwrap_or_default();
+                opts.grace_period = field(l, 2, c"grace_period", string)?;
+                opts.env = field(l, 2, c"env", env)?.unwrap_or_default();
+                opts.log_file = field(l, 2, c"log_file", string)?;
+                opts.args = field(l, 2, c"args", strings)?.unwrap_or_default();
+                opts.pid = field(l, 2, c"pid", pid)?;
+                opts.metadata = field(l, 2, c"metadata", string)?;
+            }
+            _ => return Err(ErrorKind::InvalidArgs.error("opts must be a table")),
+        }
+        let refcount = ServerManager::new().use_pid(&name, &opts)?;
+        lua_pushinteger(l, refcount.into());
+        Ok(1)
+    })
+}
+
+/// `detach(name, pid)`: release client `pid`'s reference on `name` (default:
+/// this process's). Returns the refcount left.
+unsafe extern "C-unwind" fn detach(l: *mut lua_State) -> c_int {
+    call(l, || {
+        let name = required_string(l, 1, "name")?;
+        let client = optional(l, 2, "pid", pid)?;
+        let refcount = ServerManager::new().unuse(&name, client)?;
+        lua_pushinteger(l, refcount.into());
+        Ok(1)
+    })
+}
+
+/// `info(name)`: `name`'s state, refcount, clients (keyed by PID, as
+/// strings), and recorded details, as a table.
+unsafe extern "C-unwind" fn info(l: *mut lua_State) -> c_int {
+    call(l, || {
+        let name = required_string(l, 1, "name")?;
+        let info = ServerManager::new().info(&name)?;
+        push_json(
+            l,
+            &serde_json::to_value(info).context("Failed to encode server info")?,
+        );
+        Ok(1)
+    })
+}
+
+/// `list()`: every running server, as `info` describes it.
+unsafe extern "C-unwind" fn list(l: *mut lua_State) -> c_int {
+    call(l, || {
+        let servers = ServerManager::new().list()?;
+        push_json(
+            l,
+            &serde_json::to_value(servers).context("Failed to encode server list")?,
+        );
+        Ok(1)
+    })
+}
+
+/// A subscription's events, handed over by its thread.
+struct Shared {
+    stop: AtomicBool,
+    /// Each event, with the server's state after it.
+    events: Mutex<VecDeque<(StateEvent, ServerState)>>,
+    /// Readable whenever there may be events: one byte is written per event.
+    reader: UnixStream,
+    writer: UnixStream,
+}
+
+/// What a subscription userdata points to.
+struct Subscription {
+    shared: Arc<Shared>,
+    /// The state as of the last event handed to Lua.
+    state: ServerState,
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        // The thread notices within STOP_CHECK and drops the rest; joining
+        // here would stall the editor for as long.
+        self.shared.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// `subscribe(name)`: observe `name`'s changes on a background thread. The
+/// returned subscription's `fd()` becomes readable when `events()` has
+/// something; `native.lua` wraps this up with a callback.
+unsafe extern "C-unwind" fn subscribe(l: *mut lua_State) -> c_int {
+    call(l, || {
+        let name = required_string(l, 1, "name")?;
+        let mut stream = events::subscribe(&name)?;
+        let (reader, writer) = UnixStream::pair().context("Failed to create event socket")?;
+        reader.set_nonblocking(true)?;
+        writer.set_nonblocking(true)?;
+        let shared = Arc::new(Shared {
+            stop: AtomicBool::new(false),
+            events: Mutex::new(VecDeque::new()),
+            reader,
+            writer,
+        });
+        let subscription = Subscription {
+            shared: Arc::clone(&shared),
+            state: stream.state(),
+        };
+
+        std::thread::Builder::new()
+            .name(format!("sharedserver-events-{}", name))
+            .spawn(move || {
+                // Both ends of the socket live until this thread is done, so
+                // a write never hits a closed peer.
+                while !shared.stop.load(Ordering::Relaxed) {
+                    if let Some(event) = stream.next_timeout(STOP_CHECK) {
+                        let state = stream.state();
+                        lock(&shared.events).push_back((event, state));
+                        // A full socket is already readable, so a byte lost
+                        // to that costs nothing.
+                        let _ = (&shared.writer).write(&[1]);
+                    }
+                }
+            })
+            .context("Failed to start event thread")?;
+
+        let userdata = lua_newuserdata(l, std::mem::size_of::<*mut Subscription>());
+        userdata
+            .cast::<*mut Subscription>()
+            .write(Box::into_raw(Box::new(subscription)));
+        luaL_getmetatable(l, SUBSCRIPTION.as_ptr());
+        lua_setmetatable(l, -2);
+        Ok(1)
+    })
+}
+
+/// `subscription:fd()`: a descriptor that is readable while there are
+/// events to collect.
+unsafe extern "C-unwind" fn subscription_fd(l: *mut lua_State) -> c_int {
+    call(l, || {
+        let subscription = open_subscription(l)?;
+        lua_pushinteger(l, subscription.shared.reader.as_raw_fd().into());
+        Ok(1)
+    })
+}
+
+/// `subscription:events()`: the events since the last call, oldest first,
+/// as tables like `events --json` prints (e.g. `{ event = "state", from =
+/// "active", to = "grace" }`). Empty if there are none.
+unsafe extern "C-unwind" fn subscription_events(l: *mut lua_State) -> c_int {
+    call(l, || {
+        let subscription = open_subscription(l)?;
+        // Drain the wakeups first: anything queued after this is announced
+        // by a fresh one.
+        let mut buf = [0u8; 256];
+        while matches!((&subscription.shared.reader).read(&mut buf), Ok(n) if n > 0) {}
+        let pending: Vec<_> = lock(&subscription.shared.events).drain(..).collect();
+
+        lua_createtable(l, pending.len() as c_int, 0);
+        for (index, (event, state)) in pending.into_iter().enumerate() {
+            push_json(
+                l,
+                &serde_json::to_value(event).context("Failed to encode event")?,
+            );
+            lua_rawseti(l, -2, index as lua_Integer + 1);
+            subscription.state = state;
+        }
+        Ok(1)
+    })
+}
+
+/// `subscription:state()`: the server's state as of the last event
+/// collected (or the subscription), e.g. `"active"`.
+unsafe extern "C-unwind" fn subscription_state(l: *mut lua_State) -> c_int {
+    call(l, || {
+        let subscription = open_subscription(l)?;
+        push_str(l, subscription.state.as_str());
+        Ok(1)
+    })
+}
+
+/// `subscription:close()`, and the garbage collector's: stop observing.
+/// Closing twice is harmless.
+unsafe extern "C-unwind" fn subscription_close(l: *mut lua_State) -> c_int {
+    call(l, || {
+        let slot = subscription_slot(l)?;
+        let subscription = std::mem::replace(&mut *slot, std::ptr::null_mut());
+        if !subscription.is_null() {
+            drop(Box::from_raw(subscription));
+        }
+        Ok(0)
+    })
+}
+
+/// Where the subscription userdata at index 1 keeps its pointer (NULL once
+/// closed).
+unsafe fn subscription_slot(l: *mut lua_State) -> Result<*mut *mut Subscription> {
+    let userdata = lua_touserdata(l, 1);
+    let mut ours = false;
+    if !userdata.is_null() && lua_getmetatable(l, 1) != 0 {
+        luaL_getmetatable(l, SUBSCRIPTION.as_ptr());
+        ours = lua_rawequal(l, -1, -2) != 0;
+        lua_pop(l, 2);
+    }
+    if !ours {
+        return Err(ErrorKind::InvalidArgs.error("expected a subscription (use `sub:method()`)"));
+    }
+    Ok(userdata.cast())
+}
+
+unsafe fn open_subscription<'a>(l: *mut lua_State) -> Result<&'a mut Subscription> {
+    (*subscription_slot(l)?)
+        .as_mut()
+        .ok_or_else(|| ErrorKind::InvalidArgs.error("subscription is closed"))
+}
+
+/// Run `f`, which pushes its results and returns how many. If it fails (or
+/// panics), return `nil, message, kind` instead.
+unsafe fn call(l: *mut lua_State, f: impl FnOnce() -> Result<c_int>) -> c_int {
+    let (message, kind) = match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(results)) => return results,
+        Ok(Err(e)) => (format!("{:#}", e), ErrorKind::of(&e)),
+        Err(_) => ("internal error: sharedserver panicked".to_string(), None),
+    };
+    lua_pushnil(l);
+    push_str(l, &message);
+    match kind {
+        Some(kind) => push_str(l, kind.as_str()),
+        None => lua_pushnil(l),
+    }
+    3
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    // The queue is left consistent at every point a holder could panic.
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+unsafe fn push_str(l: *mut lua_State, s: &str) {
+    lua_pushlstring(l, s.as_ptr().cast(), s.len());
+}
+
+/// Push `value` as the equivalent Lua value. JSON nulls become nil, so they
+/// vanish from tables.
+unsafe fn push_json(l: *mut lua_State, value: &serde_json::Value) {
+    use serde_json::Value;
+    match value {
+        Value::Null => lua_pushnil(l),
+        Value::Bool(b) => lua_pushboolean(l, *b as c_int),
+        Value::Number(n) => match n.as_i64().and_then(|n| lua_Integer::try_from(n).ok()) {
+            Some(n) => lua_pushinteger(l, n),
+            None => lua_pushnumber(l, n.as_f64().unwrap_or(f64::NAN)),
+        },
+        Value::String(s) => push_str(l, s),
+        Value::Array(items) => {
+            lua_createtable(l, items.len() as c_int, 0);
+            for (index, item) in items.iter().enumerate() {
+                push_json(l, item);
+                lua_rawseti(l, -2, index as lua_Integer + 1);
+            }
+        }
+        Value::Object(fields) => {
+            lua_createtable(l, 0, fields.len() as c_int);
+            for (key, item) in fields {
+                push_str(l, key);
+                push_json(l, item);
+                lua_rawset(l, -3);
+            }
+        }
+    }
+}
+
+/// The string argument at `index`, which must be given.
+unsafe fn required_string(l: *mut lua_State, index: c_int, what: &str) -> Result<String> {
+    optional(l, index, what, string)?
+        .ok_or_else(|| ErrorKind::InvalidArgs.error(format!("{} is required", what)))
+}
+
+/// The argument at `index` read with `read`, or None if it is nil or absent.
+unsafe fn optional<T>(
+    l: *mut lua_State,
+    index: c_int,
+    what: &str,
+    read: unsafe fn(*mut lua_State, c_int, &str) -> Result<T>,
+) -> Result<Option<T>> {
+    if lua_type(l, index) <= LUA_TNIL {
+        return Ok(None);
+    }
+    read(l, index, what).map(Some)
+}
+
+/// Field `key` of the table at `index`, read with `read`, or None if it is
+/// nil.
+unsafe fn field<T>(
+    l: *mut lua_State,
+    index: c_int,
+    key: &CStr,
+    read: unsafe fn(*mut lua_State, c_int, &str) -> Result<T>,
+) -> Result<Option<T>> {
+    lua_getfield(l, index, key.as_ptr());
+    let what = format!("opts.{}", key.to_string_lossy());
+    let value = optional(l, -1, &what, read);
+    lua_pop(l, 1);
+    value
+}
+
+unsafe fn string(l: *mut lua_State, index: c_int, what: &str) -> Result<String> {
+    // Strings only: lua_tolstring would also convert a number in place,
+    // which upsets table traversal.
+    if lua_type(l, index) != LUA_TSTRING {
+        return Err(ErrorKind::InvalidArgs.error(format!("{} must be a string", what)));
+    }
+    let mut len = 0;
+    let ptr = lua_tolstring(l, index, &mut len);
+    let bytes = std::slice::from_raw_parts(ptr.cast::<u8>(), len);
+    String::from_utf8(bytes.to_vec())
+        .map_err(|_| ErrorKind::InvalidArgs.error(format!("{} is not valid UTF-8", what)))
+}
+
+unsafe fn pid(l: *mut lua_State, index: c_int, what: &str) -> Result<i32> {
+    let number = lua_tonumber(l, index);
+    if lua_type(l, index) != LUA_TNUMBER || number.fract() != 0.0 || number < 1.0 {
+        return Err(ErrorKind::InvalidArgs.error(format!("{} must be a PID", what)));
+    }
+    i32::try_from(number as i64)
+        .map_err(|_| ErrorKind::InvalidArgs.error(format!("{} must be a PID", what)))
+}
+
+/// A list of strings, e.g. `{ "chroma", "run" }`.
+unsafe fn strings(l: *mut lua_State, index: c_int, what: &str) -> Result<Vec<String>> {
+    if lua_type(l, index) != LUA_TTABLE {
+        return Err(ErrorKind::InvalidArgs.error(format!("{} must be a list of strings", what)));
+    }
+    let index = lua_absindex(l, index);
+    (1..=lua_rawlen(l, index) as lua_Integer)
+        .map(|n| {
+            lua_rawgeti(l, index, n);
+            let item = string(l, -1, &format!("{}[{}]", what, n));
+            lua_pop(l, 1);
+            item
+        })
+        .collect()
+}
+
+/// A table of environment variables, `{ KEY = "value" }`, as `KEY=VALUE`.
+unsafe fn env(l: *mut lua_State, index: c_int, what: &str) -> Result<Vec<String>> {
+    if lua_type(l, index) != LUA_TTABLE {
+        return Err(ErrorKind::InvalidArgs.error(format!("{} must be a table", what)));
+    }
+    let index = lua_absindex(l, index);
+    let mut vars = Vec::new();
+    lua_pushnil(l);
+    while lua_next(l, index) != 0 {
+        let pair = string(l, -2, &format!("{} key", what)).and_then(|key| {
+            Ok(format!(
+                "{}={}",
+                key,
+                string(l, -1, &format!("{}.{}", what, key))?
+            ))
+        });
+        match pair {
+            Ok(pair) => vars.push(pair),
+            Err(e) => {
+                lua_pop(l, 2);
+                return Err(e);
+            }
+        }
+        lua_pop(l, 1);
+    }
+    vars.sort();
+    Ok(vars)
+}
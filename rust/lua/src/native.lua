@@ -0,0 +1,85 @@
+-- The Lua half of `sharedserver.native`: the native functions, plus
+-- subscriptions that call back on the host's event loop.
+--
+-- Compiled into the library and run by luaopen_sharedserver_native with the
+-- native functions' table; what it returns is the module.
+
+local native = ...
+
+local M = {
+    attach = native.attach,
+    detach = native.detach,
+    info = native.info,
+    list = native.list,
+}
+
+local uv = vim and (vim.uv or vim.loop)
+
+local Subscription = {}
+Subscription.__index = Subscription
+
+--- Call back with every event collected so far. Returns how many there were.
+--- Under Neovim this happens on its own; elsewhere, call it when `fd()` is
+--- readable (or on a timer).
+function Subscription:poll()
+    local events = self._native:events()
+    if events then
+        for _, event in ipairs(events) do
+            self._callback(event)
+        end
+    end
+    return events and #events or 0
+end
+
+--- The server's state as of the last event delivered, e.g. "active".
+function Subscription:state()
+    return self._native:state()
+end
+
+--- A descriptor that is readable while there are events to collect.
+function Subscription:fd()
+    return self._native:fd()
+end
+
+--- Stop observing. Closing twice is harmless.
+function Subscription:close()
+    if self._poll then
+        self._poll:stop()
+        self._poll:close()
+        self._poll = nil
+    end
+    self._native:close()
+end
+
+--- Observe `name`, calling `callback(event)` for each change: tables like
+--- `{ event = "state", from = "active", to = "grace" }` and
+--- `{ event = "client-attached", pid = 1234 }`. The server needn't be
+--- running. Under Neovim, callbacks run on the main loop via vim.schedule,
+--- so they may call any API.
+function M.subscribe(name, callback)
+    if type(callback) ~= "function" then
+        return nil, "callback must be a function", "invalid-args"
+    end
+    local sub, err, kind = native.subscribe(name)
+    if not sub then
+        return nil, err, kind
+    end
+    local self = setmetatable({ _native = sub, _callback = callback }, Subscription)
+    if uv then
+        self._poll = uv.new_poll(sub:fd())
+        self._poll:start("r", function()
+            -- Collect now (this is safe in a fast callback), deliver later.
+            local events = sub:events()
+            if events and #events > 0 then
+                vim.schedule(function()
+                    for _, event in ipairs(events) do
+                        callback(event)
+                    end
+                end)
+            end
+        end)
+    end
+    return self
+end
+
+return M
@@ -0,0 +1,67 @@
+import { EventEmitter } from 'node:events'
+
+/** How `useAsync` starts a server it finds stopped. */
+export interface UseOptions {
+  /** The command to start the server with. Without one, it must be running. */
+  command?: string[]
+  /** e.g. `'10m'` (default: the CLI's, 5m) */
+  gracePeriod?: string
+  env?: Record<string, string>
+  logFile?: string
+  /** Any further `sharedserver use` flags. */
+  args?: string[]
+  /** The client process the reference stands for (default: this one). */
+  pid?: number
+  /** Shown next to the client in `info`. */
+  metadata?: string
+}
+
+export type ServerState = 'active' | 'grace' | 'stopped' | 'defunct'
+
+export type StateEvent =
+  | { event: 'state'; from: ServerState; to: ServerState }
+  | { event: 'client-attached'; pid: number }
+  | { event: 'client-detached'; pid: number }
+
+/** A running server, as `sharedserver info --json` reports it. */
+export interface ServerInfo {
+  name: string
+  state: ServerState
+  refcount: number
+  /** Keyed by client PID. */
+  clients: Record<string, { attached_at: string; metadata: string | null }>
+  /** Everything recorded about the server (pid, command, address, ...). */
+  server: Record<string, unknown> & { pid: number; command: string[] }
+}
+
+/** Thrown (or rejected with) on failure. */
+export interface SharedServerError extends Error {
+  /** e.g. `'not-running'`, `'shutting-down'`; absent for other failures. */
+  code?: string
+  /** The CLI's exit code for the failure. */
+  exitCode: number
+}
+
+/** Take a reference on `name`, starting it if needed. Resolves to the refcount. */
+export function useAsync(name: string, opts?: UseOptions): Promise<number>
+/** Release client `pid`'s reference (default: this process's). */
+export function unuseAsync(name: string, pid?: number): Promise<number>
+export function list(): ServerInfo[]
+export function info(name: string): ServerInfo
+
+export class ServerWatcher extends EventEmitter {
+  readonly name: string
+  readonly state: ServerState
+  close(): void
+  ref(): this
+  unref(): this
+  on(event: 'change', listener: (event: StateEvent) => void): this
+  on(event: 'state', listener: (event: Extract<StateEvent, { event: 'state' }>) => void): this
+  on(
+    event: 'client-attached' | 'client-detached',
+    listener: (event: { event: string; pid: number }) => void,
+  ): this
+}
+
+/** Observe `name`'s changes. Keeps the process alive until closed or unref()ed. */
+export function watch(name: string): ServerWatcher
@@ -0,0 +1,56 @@
+'use strict'
+
+// The `sharedserver` package: the native bindings (src/lib.rs), plus
+// `watch`, which puts a server's changes on an EventEmitter.
+
+const { EventEmitter } = require('node:events')
+const native = require('./sharedserver.node')
+
+/**
+ * Emits a server's changes: `'change'` for every event, then the event
+ * under its own name (`'state'`, `'client-attached'`, `'client-detached'`).
+ */
+class ServerWatcher extends EventEmitter {
+  constructor(name) {
+    super()
+    this.name = name
+    this._subscription = native.subscribe(name, (event) => {
+      this.emit('change', event)
+      this.emit(event.event, event)
+    })
+  }
+
+  /** The server's state as of the last change, e.g. `'active'`. */
+  get state() {
+    return this._subscription.state
+  }
+
+  close() {
+    this._subscription.close()
+    // Stop holding the process open now, not when the watcher thread notices.
+    this._subscription.unref()
+  }
+
+  ref() {
+    this._subscription.ref()
+    return this
+  }
+
+  unref() {
+    this._subscription.unref()
+    return this
+  }
+}
+
+function watch(name) {
+  return new ServerWatcher(name)
+}
+
+module.exports = {
+  useAsync: native.useAsync,
+  unuseAsync: native.unuseAsync,
+  list: native.list,
+  info: native.info,
+  watch,
+  ServerWatcher,
+}
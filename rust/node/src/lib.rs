@@ -0,0 +1,240 @@
+//! Node.js bindings for sharedserver, built with napi-rs: the native half of
+//! the `sharedserver` package, which `index.js` wraps with an `EventEmitter`
+//! (see `index.d.ts` for the API).
+//!
+//! Taking and releasing references can wait on the CLI or on other
+//! processes' locks, so `useAsync` and `unuseAsync` run on libuv's thread
+//! pool and return promises; `list` and `info` only read the locks. Failures
+//! are `Error`s whose `code` names the failure as the CLI does (e.g.
+//! `"not-running"`) and whose `exitCode` is its exit code.
+
+use anyhow::Context;
+use napi::bindgen_prelude::AsyncTask;
+use napi::threadsafe_function::{
+    ErrorStrategy, ThreadSafeCallContext, ThreadsafeFunction, ThreadsafeFunctionCallMode,
+};
+use napi::{Env, JsFunction, Task};
+use napi_derive::napi;
+use sharedserver::core::error::exit_code;
+use sharedserver::core::events;
+use sharedserver::{ErrorKind, ServerManager, ServerState};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How often a subscription's thread checks whether it has been closed.
+const STOP_CHECK: Duration = Duration::from_millis(500);
+
+/// `err` as a JS `Error` carrying `code` and `exitCode`.
+fn js_error(env: &Env, err: anyhow::Error) -> napi::Error {
+    let message = format!("{:#}", err);
+    let build = || -> napi::Result<napi::Error> {
+        let mut error = env.create_error(napi::Error::from_reason(message.clone()))?;
+        match ErrorKind::of(&err) {
+            Some(kind) => error.set_named_property("code", kind.as_str())?,
+            // Not napi's "GenericFailure": nothing more specific to say.
+            None => {
+                error.delete_named_property("code")?;
+            }
+        }
+        error.set_named_property("exitCode", exit_code(&err))?;
+        Ok(napi::Error::from(error.into_unknown()))
+    };
+    build().unwrap_or_else(|_| napi::Error::from_reason(message))
+}
+
+fn to_js<T: serde::Serialize>(env: &Env, value: &T) -> napi::Result<serde_json::Value> {
+    serde_json::to_value(value)
+        .context("Failed to encode result")
+        .map_err(|e| js_error(env, e))
+}
+
+/// How `useAsync` starts a server it finds stopped. Ignored if it is
+/// already running.
+#[napi(object)]
+pub struct UseOptions {
+    /// The command to start the server with. Without one, the server must
+    /// already be running.
+    pub command: Option<Vec<String>>,
+    /// e.g. `"10m"` [default: the CLI's, 5m]
+    pub grace_period: Option<String>,
+    pub env: Option<HashMap<String, String>>,
+    pub log_file: Option<String>,
+    /// Any further `sharedserver use` flags.
+    pub args: Option<Vec<String>>,
+    /// The client process the reference stands for. Defaults to this one.
+    pub pid: Option<i32>,
+    /// Shown next to the client in `info`.
+    pub metadata: Option<String>,
+}
+
+impl From<UseOptions> for sharedserver::UseOptions {
+    fn from(opts: UseOptions) -> Self {
+        let mut env: Vec<String> = opts
+            .env
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        env.sort();
+        Self {
+            command: opts.command.unwrap_or_default(),
+            grace_period: opts.grace_period,
+            env,
+            log_file: opts.log_file,
+            args: opts.args.unwrap_or_default(),
+            pid: opts.pid,
+            metadata: opts.metadata,
+        }
+    }
+}
+
+/// A reference taken or released on the thread pool.
+pub enum RefcountTask {
+    Use {
+        name: String,
+        opts: sharedserver::UseOptions,
+    },
+    Unuse {
+        name: String,
+        pid: Option<i32>,
+    },
+}
+
+impl Task for RefcountTask {
+    // Failures travel as `anyhow` errors, to become JS errors on the main
+    // thread, where their `code` can be set.
+    type Output = anyhow::Result<u32>;
+    type JsValue = u32;
+
+    fn compute(&mut self) -> napi::Result<Self::Output> {
+        let manager = ServerManager::new();
+        Ok(match self {
+            RefcountTask::Use { name, opts } => manager.use_pid(name, opts),
+            RefcountTask::Unuse { name, pid } => manager.unuse(name, *pid),
+        })
+    }
+
+    fn resolve(&mut self, env: Env, output: Self::Output) -> napi::Result<u32> {
+        output.map_err(|e| js_error(&env, e))
+    }
+}
+
+/// Take a reference on `name` for `opts.pid` (default: this process),
+/// starting it with `opts.command` if it isn't running, as `sharedserver use
+/// --pid` does. Resolves to the new refcount.
+#[napi(ts_return_type = "Promise<number>")]
+pub fn use_async(name: String, opts: Option<UseOptions>) -> AsyncTask<RefcountTask> {
+    AsyncTask::new(RefcountTask::Use {
+        name,
+        opts: opts.map(Into::into).unwrap_or_default(),
+    })
+}
+
+/// Release client `pid`'s reference on `name` (default: this process's).
+/// Resolves to the refcount left.
+#[napi(ts_return_type = "Promise<number>")]
+pub fn unuse_async(name: String, pid: Option<i32>) -> AsyncTask<RefcountTask> {
+    AsyncTask::new(RefcountTask::Unuse { name, pid })
+}
+
+/// Every running server, as `info` describes it.
+#[napi]
+pub fn list(env: Env) -> napi::Result<serde_json::Value> {
+    let servers = ServerManager::new().list().map_err(|e| js_error(&env, e))?;
+    to_js(&env, &servers)
+}
+
+/// `name`'s state, refcount, clients (keyed by PID), and recorded details.
+#[napi]
+pub fn info(env: Env, name: String) -> napi::Result<serde_json::Value> {
+    let info = ServerManager::new()
+        .info(&name)
+        .map_err(|e| js_error(&env, e))?;
+    to_js(&env, &info)
+}
+
+/// A server's changes, observed on a background thread and delivered to a
+/// callback on the main thread. Like a watcher from `fs.watch`, it keeps the
+/// process alive until closed or `unref()`ed.
+#[napi]
+pub struct Subscription {
+    stop: Arc<AtomicBool>,
+    state: Arc<Mutex<ServerState>>,
+    callback: ThreadsafeFunction<serde_json::Value, ErrorStrategy::Fatal>,
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        // The thread notices within STOP_CHECK and drops its reference to the
+        // callback, which lets the process exit.
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+#[napi]
+impl Subscription {
+    /// The server's state as of the last change observed, e.g. `"active"`.
+    #[napi(getter)]
+    pub fn state(&self) -> String {
+        lock(&self.state).as_str().to_string()
+    }
+
+    /// Stop observing. Closing twice is harmless.
+    #[napi]
+    pub fn close(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+
+    #[napi(js_name = "ref")]
+    pub fn refer(&mut self, env: Env) -> napi::Result<()> {
+        self.callback.refer(&env)
+    }
+
+    #[napi]
+    pub fn unref(&mut self, env: Env) -> napi::Result<()> {
+        self.callback.unref(&env)
+    }
+}
+
+/// Observe `name`, calling `callback(event)` for each change, with objects
+/// like `{ event: "state", from: "active", to: "grace" }`. The server needn't
+/// be running.
+#[napi(ts_args_type = "name: string, callback: (event: StateEvent) => void")]
+pub fn subscribe(env: Env, name: String, callback: JsFunction) -> napi::Result<Subscription> {
+    let mut stream = events::subscribe(&name).map_err(|e| js_error(&env, e))?;
+    let callback: ThreadsafeFunction<serde_json::Value, ErrorStrategy::Fatal> = callback
+        .create_threadsafe_function(0, |ctx: ThreadSafeCallContext<serde_json::Value>| {
+            Ok(vec![ctx.env.to_js_value(&ctx.value)?])
+        })?;
+    let subscription = Subscription {
+        stop: Arc::new(AtomicBool::new(false)),
+        state: Arc::new(Mutex::new(stream.state())),
+        callback: callback.clone(),
+    };
+
+    let stop = Arc::clone(&subscription.stop);
+    let state = Arc::clone(&subscription.state);
+    std::thread::Builder::new()
+        .name(format!("sharedserver-events-{}", name))
+        .spawn(move || {
+            while !stop.load(Ordering::Relaxed) {
+                if let Some(event) = stream.next_timeout(STOP_CHECK) {
+                    *lock(&state) = stream.state();
+                    if let Ok(event) = serde_json::to_value(event) {
+                        callback.call(event, ThreadsafeFunctionCallMode::NonBlocking);
+                    }
+                }
+            }
+        })
+        .map_err(|e| napi::Error::from_reason(format!("Failed to start event thread: {}", e)))?;
+    Ok(subscription)
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    // A lone state value is consistent at every point a holder could panic.
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
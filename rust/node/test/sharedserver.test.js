@@ -0,0 +1,51 @@
+'use strict'
+
+// Run with `npm test` after `npm run build` (or copy the cdylib to
+// sharedserver.node). Starting servers goes through the CLI, so
+// SHAREDSERVER_BIN defaults to the workspace's debug build.
+
+const assert = require('node:assert/strict')
+const fs = require('node:fs')
+const os = require('node:os')
+const path = require('node:path')
+const { after, test } = require('node:test')
+
+process.env.SHAREDSERVER_LOCKDIR = fs.mkdtempSync(path.join(os.tmpdir(), 'sharedserver-node-'))
+process.env.SHAREDSERVER_BIN ??= path.join(__dirname, '..', '..', 'target', 'debug', 'sharedserver')
+
+const sharedserver = require('..')
+
+after(() => {
+  sharedserver.unuseAsync('node-test').catch(() => {})
+})
+
+test('failures carry the CLI code', async () => {
+  assert.throws(() => sharedserver.info('node-test'), { code: 'not-running', exitCode: 10 })
+  await assert.rejects(sharedserver.useAsync('node-test'), { code: 'not-running' })
+})
+
+test('watch, use, and unuse', async () => {
+  const watcher = sharedserver.watch('node-test')
+  assert.equal(watcher.state, 'stopped')
+  const attached = new Promise((resolve) => watcher.once('client-attached', resolve))
+
+  const refcount = await sharedserver.useAsync('node-test', {
+    command: ['sleep', '30'],
+    gracePeriod: '1s',
+    metadata: 'node',
+  })
+  assert.equal(refcount, 1)
+  assert.deepEqual(await attached, { event: 'client-attached', pid: process.pid })
+
+  const info = sharedserver.info('node-test')
+  assert.equal(info.state, 'active')
+  assert.equal(info.clients[process.pid].metadata, 'node')
+  assert.ok(sharedserver.list().some((server) => server.name === 'node-test'))
+
+  const grace = new Promise((resolve) =>
+    watcher.on('state', (event) => event.to === 'grace' && resolve(event)),
+  )
+  assert.equal(await sharedserver.unuseAsync('node-test'), 0)
+  assert.equal((await grace).from, 'active')
+  watcher.close()
+})
@@ -0,0 +1,71 @@
+"""Share servers between processes with reference counting.
+
+Starting a server runs the ``sharedserver`` binary (``$SHAREDSERVER_BIN``, or
+the one on ``PATH``), since a server's watcher is part of it.
+"""
+
+from types import TracebackType
+from typing import Any, Dict, List, Mapping, Optional, Sequence, Type
+
+__version__: str
+
+class SharedServerError(Exception):
+    """A sharedserver operation failed."""
+
+    kind: Optional[str]
+    """e.g. ``"not-running"``, ``"shutting-down"``, or None for any other failure."""
+    exit_code: int
+    """The CLI's exit code for the failure."""
+
+class Server:
+    """A reference on a server, from :func:`use_server`.
+
+    Released by :meth:`detach`, at the end of a ``with`` block, or when the
+    object is garbage-collected.
+    """
+
+    @property
+    def name(self) -> str: ...
+    @property
+    def pid(self) -> int:
+        """The client PID the reference stands for."""
+    @property
+    def attached(self) -> bool:
+        """Whether the reference is still held."""
+    def detach(self) -> Optional[int]:
+        """Release the reference; the refcount afterwards, or None if already released."""
+    def info(self) -> Dict[str, Any]:
+        """The server's details, as :func:`info` returns them."""
+    def __enter__(self) -> "Server": ...
+    def __exit__(
+        self,
+        exc_type: Optional[Type[BaseException]],
+        exc_value: Optional[BaseException],
+        traceback: Optional[TracebackType],
+    ) -> bool: ...
+
+def use_server(
+    name: str,
+    command: Optional[Sequence[str]] = None,
+    *,
+    grace_period: Optional[str] = None,
+    env: Optional[Mapping[str, str]] = None,
+    log_file: Optional[str] = None,
+    args: Optional[Sequence[str]] = None,
+    pid: Optional[int] = None,
+    metadata: Optional[str] = None,
+) -> Server:
+    """Attach to ``name``, starting it with ``command`` if it isn't running.
+
+    ``grace_period`` is e.g. ``"10m"``; ``args`` are further ``sharedserver
+    use`` flags, e.g. ``["--restart", "on-failure"]``.
+    """
+
+def unuse(name: str, pid: Optional[int] = None) -> int:
+    """Release client ``pid``'s reference (default: this process's); the refcount left."""
+
+def list() -> List[Dict[str, Any]]:
+    """Every running server, as :func:`info` describes it."""
+
+def info(name: str) -> Dict[str, Any]:
+    """``name``'s state, refcount, clients (by PID), and recorded details."""
@@ -0,0 +1,202 @@
+//! Python bindings for sharedserver: the `sharedserver` module, built with
+//! maturin (see `pyproject.toml`, and `sharedserver.pyi` for the API).
+//!
+//! Everything goes through the library API ([`ServerManager`]), with the GIL
+//! released while it waits on locks or on the CLI. Errors are raised as
+//! `SharedServerError`, carrying the CLI's classification and exit code.
+
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use sharedserver::core::error::exit_code;
+use sharedserver::{ErrorKind, ServerHandle, ServerManager, UseOptions};
+use std::collections::HashMap;
+
+create_exception!(
+    sharedserver,
+    SharedServerError,
+    PyException,
+    "A sharedserver operation failed. `kind` names the failure (e.g. \
+     \"not-running\", \"shutting-down\"), or is None for any other; \
+     `exit_code` is the CLI's exit code for it."
+);
+
+/// `err` as a `SharedServerError`.
+fn raise(py: Python<'_>, err: anyhow::Error) -> PyErr {
+    let pyerr = SharedServerError::new_err(format!("{:#}", err));
+    let value = pyerr.value(py);
+    // Setting attributes on a fresh exception instance can't fail.
+    let _ = value.setattr("kind", ErrorKind::of(&err).map(|kind| kind.as_str()));
+    let _ = value.setattr("exit_code", exit_code(&err));
+    pyerr
+}
+
+/// `value` as the equivalent Python object, via JSON.
+fn to_python<T: serde::Serialize>(py: Python<'_>, value: &T) -> PyResult<Py<PyAny>> {
+    let json = serde_json::to_string(value).map_err(|e| raise(py, e.into()))?;
+    Ok(py.import("json")?.call_method1("loads", (json,))?.unbind())
+}
+
+/// A reference on a server, from `use_server`. Released by `detach()`, at the
+/// end of a `with` block, or when the object is garbage-collected.
+#[pyclass(module = "sharedserver")]
+struct Server {
+    name: String,
+    pid: i32,
+    handle: Option<ServerHandle>,
+}
+
+#[pymethods]
+impl Server {
+    #[getter]
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The client PID the reference stands for.
+    #[getter]
+    fn pid(&self) -> i32 {
+        self.pid
+    }
+
+    /// Whether the reference is still held.
+    #[getter]
+    fn attached(&self) -> bool {
+        self.handle.is_some()
+    }
+
+    /// Release the reference. Returns the server's refcount afterwards, or
+    /// None if it was already released.
+    fn detach(&mut self, py: Python<'_>) -> PyResult<Option<u32>> {
+        let Some(handle) = self.handle.take() else {
+            return Ok(None);
+        };
+        py.detach(move || handle.detach())
+            .map(Some)
+            .map_err(|e| raise(py, e))
+    }
+
+    /// The server's details, as `info()` returns them.
+    fn info(&self, py: Python<'_>) -> PyResult<Py<PyAny>> {
+        info(py, &self.name)
+    }
+
+    fn __enter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __exit__(
+        &mut self,
+        py: Python<'_>,
+        _exc_type: Option<&Bound<'_, PyAny>>,
+        _exc_value: Option<&Bound<'_, PyAny>>,
+        _traceback: Option<&Bound<'_, PyAny>>,
+    ) -> PyResult<bool> {
+        self.detach(py)?;
+        Ok(false)
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "<sharedserver.Server {:?} pid={}{}>",
+            self.name,
+            self.pid,
+            if self.handle.is_some() {
+                ""
+            } else {
+                " detached"
+            }
+        )
+    }
+}
+
+/// Attach to `name`, starting it with `command` if it isn't running, as
+/// `sharedserver use` does. The reference is held by the returned `Server`.
+#[pyfunction]
+#[pyo3(signature = (
+    name,
+    command=None,
+    *,
+    grace_period=None,
+    env=None,
+    log_file=None,
+    args=None,
+    pid=None,
+    metadata=None,
+))]
+// Keyword arguments, one per `UseOptions` field.
+#[allow(clippy::too_many_arguments)]
+fn use_server(
+    py: Python<'_>,
+    name: String,
+    command: Option<Vec<String>>,
+    grace_period: Option<String>,
+    env: Option<HashMap<String, String>>,
+    log_file: Option<String>,
+    args: Option<Vec<String>>,
+    pid: Option<i32>,
+    metadata: Option<String>,
+) -> PyResult<Server> {
+    let opts = UseOptions {
+        command: command.unwrap_or_default(),
+        grace_period,
+        env: env
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect(),
+        log_file,
+        args: args.unwrap_or_default(),
+        pid,
+        metadata,
+    };
+    let handle = py
+        .detach(|| ServerManager::new().use_server(&name, &opts))
+        .map_err(|e| raise(py, e))?;
+    Ok(Server {
+        name,
+        pid: handle.pid(),
+        handle: Some(handle),
+    })
+}
+
+/// Release client `pid`'s reference on `name` (default: this process's),
+/// e.g. one taken by `sharedserver use`. Returns the refcount left.
+#[pyfunction]
+#[pyo3(signature = (name, pid=None))]
+fn unuse(py: Python<'_>, name: String, pid: Option<i32>) -> PyResult<u32> {
+    py.detach(|| ServerManager::new().unuse(&name, pid))
+        .map_err(|e| raise(py, e))
+}
+
+/// Every running server, as `info()` describes it.
+#[pyfunction]
+fn list(py: Python<'_>) -> PyResult<Py<PyAny>> {
+    let servers = py
+        .detach(|| ServerManager::new().list())
+        .map_err(|e| raise(py, e))?;
+    to_python(py, &servers)
+}
+
+/// `name`'s state, refcount, clients (by PID), and recorded details, as a
+/// dict. Raises `SharedServerError` with kind "not-running" if it's stopped.
+#[pyfunction]
+fn info(py: Python<'_>, name: &str) -> PyResult<Py<PyAny>> {
+    let info = py
+        .detach(|| ServerManager::new().info(name))
+        .map_err(|e| raise(py, e))?;
+    to_python(py, &info)
+}
+
+#[pymodule]
+#[pyo3(name = "sharedserver")]
+fn sharedserver_py(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("__version__", env!("CARGO_PKG_VERSION"))?;
+    m.add("SharedServerError", m.py().get_type::<SharedServerError>())?;
+    m.add_class::<Server>()?;
+    m.add_function(wrap_pyfunction!(use_server, m)?)?;
+    m.add_function(wrap_pyfunction!(unuse, m)?)?;
+    m.add_function(wrap_pyfunction!(list, m)?)?;
+    m.add_function(wrap_pyfunction!(info, m)?)?;
+    Ok(())
+}
@@ -0,0 +1,67 @@
+"""End-to-end tests for the Python bindings.
+
+Run after building the module (``maturin develop``) with the CLI it starts
+servers through::
+
+    SHAREDSERVER_BIN=../target/debug/sharedserver python -m unittest discover tests
+"""
+
+import os
+import pathlib
+import shutil
+import subprocess
+import tempfile
+import unittest
+
+import sharedserver
+
+HELPERS = pathlib.Path(__file__).resolve().parents[3] / "tests" / "test_helpers"
+
+
+class SharedServerTest(unittest.TestCase):
+    def setUp(self):
+        self.lockdir = tempfile.mkdtemp(prefix="sharedserver-py-")
+        os.environ["SHAREDSERVER_LOCKDIR"] = self.lockdir
+        self.name = "py-test"
+
+    def tearDown(self):
+        binary = os.environ.get("SHAREDSERVER_BIN", "sharedserver")
+        subprocess.run(
+            [binary, "admin", "stop", self.name], capture_output=True, check=False
+        )
+        shutil.rmtree(self.lockdir, ignore_errors=True)
+
+    def test_not_running(self):
+        with self.assertRaises(sharedserver.SharedServerError) as raised:
+            sharedserver.info(self.name)
+        self.assertEqual(raised.exception.kind, "not-running")
+        self.assertEqual(raised.exception.exit_code, 10)
+        self.assertEqual(sharedserver.list(), [])
+
+    def test_context_manager_detaches(self):
+        command = [str(HELPERS / "long_running.sh")]
+        with sharedserver.use_server(
+            self.name, command, grace_period="30s", metadata="pytest"
+        ) as server:
+            self.assertTrue(server.attached)
+            info = server.info()
+            self.assertEqual(info["state"], "active")
+            self.assertEqual(info["refcount"], 1)
+            self.assertEqual(info["clients"][str(server.pid)]["metadata"], "pytest")
+            self.assertEqual(info["server"]["command"], command)
+            self.assertEqual([s["name"] for s in sharedserver.list()], [self.name])
+
+        self.assertFalse(server.attached)
+        self.assertIsNone(server.detach())
+        self.assertEqual(sharedserver.info(self.name)["state"], "grace")
+
+        # Already running: attaching again needs no command.
+        server = sharedserver.use_server(self.name)
+        self.assertEqual(server.detach(), 0)
+        with self.assertRaises(sharedserver.SharedServerError) as raised:
+            sharedserver.unuse(self.name)
+        self.assertEqual(raised.exception.kind, "not-attached")
+
+
+if __name__ == "__main__":
+    unittest.main()
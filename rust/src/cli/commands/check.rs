@@ -1,74 +1,168 @@
 use anyhow::Result;
 use colored::*;
-use sharedserver::core::{get_server_state, read_server_lock, ServerState};
+use serde::{Serialize, Serializer};
+use serde_json::json;
+use sharedserver::core::{get_server_state, read_server_lock, ServerLock, ServerState};
 
-use crate::output::{format_pid, format_server_name};
+use crate::output::{
+    format_duration, format_pid, format_server_name, is_quiet, print_report, Glyph, OutputFormat,
+    Report,
+};
 
-pub fn execute(name: &str) -> Result<()> {
+/// Exit code for a running server (Active or Grace) whose health probe has
+/// marked it unhealthy. Distinct from every [`ServerState::exit_code`].
+pub(crate) const EXIT_UNHEALTHY: i32 = 4;
+
+/// A server's state for `check`, which also reports it as the exit code.
+struct CheckReport {
+    name: String,
+    state: ServerState,
+    server_lock: Option<ServerLock>,
+}
+
+impl CheckReport {
+    fn is_unhealthy(&self) -> bool {
+        matches!(self.state, ServerState::Active | ServerState::Grace)
+            && self.server_lock.as_ref().is_some_and(|l| l.is_unhealthy())
+    }
+
+    fn exit_code(&self) -> i32 {
+        if self.is_unhealthy() {
+            EXIT_UNHEALTHY
+        } else {
+            self.state.exit_code()
+        }
+    }
+}
+
+pub fn execute(name: &str, format: OutputFormat) -> Result<()> {
     let state = get_server_state(name)?;
+    let server_lock = match state {
+        ServerState::Stopped => None,
+        _ => read_server_lock(name).ok(),
+    };
+    let report = CheckReport {
+        name: name.to_string(),
+        state,
+        server_lock,
+    };
+    if !is_quiet() {
+        print_report(format, &report)?;
+    }
+    std::process::exit(report.exit_code());
+}
+
+impl Serialize for CheckReport {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let lock = self.server_lock.as_ref();
+        json!({
+            "name": self.name,
+            "state": self.state.as_str(),
+            "pid": lock.map(|l| l.pid),
+            "health": lock.and_then(|l| l.health_label()),
+            "health_error": lock
+                .and_then(|l| l.health.as_ref())
+                .and_then(|h| h.last_error.clone()),
+            "grace_remaining_secs": lock
+                .and_then(|l| l.grace_remaining())
+                .map(|left| left.as_secs()),
+            "exit_code": self.exit_code(),
+        })
+        .serialize(serializer)
+    }
+}
+
+impl Report for CheckReport {
+    fn print_table(&self) -> Result<()> {
+        let name = self.name.as_str();
+        let state = self.state;
 
-    match state {
-        ServerState::Active => {
-            if let Ok(server_lock) = read_server_lock(name) {
+        if self.is_unhealthy() {
+            if let Some(server_lock) = &self.server_lock {
+                let reason = server_lock
+                    .health
+                    .as_ref()
+                    .and_then(|h| h.last_error.clone())
+                    .unwrap_or_default();
                 println!(
-                    "{} {} is running (PID: {}, state: {})",
-                    "✓".green().bold(),
+                    "{} {} is unhealthy (PID: {}, state: {}): {}",
+                    Glyph::Unhealthy.as_str().red().bold(),
                     format_server_name(name),
                     format_pid(server_lock.pid),
-                    "active".green()
-                );
-            } else {
-                println!(
-                    "{} {} is {}",
-                    "✓".green().bold(),
-                    format_server_name(name),
-                    "active".green()
+                    state.as_str(),
+                    reason
                 );
+                return Ok(());
             }
         }
-        ServerState::Grace => {
-            if let Ok(server_lock) = read_server_lock(name) {
-                println!(
-                    "{} {} is in grace period (PID: {}, shutting down soon)",
-                    "⚠".yellow().bold(),
-                    format_server_name(name),
-                    format_pid(server_lock.pid)
-                );
-            } else {
-                println!(
-                    "{} {} is in {}",
-                    "⚠".yellow().bold(),
-                    format_server_name(name),
-                    "grace period".yellow()
-                );
+
+        match state {
+            ServerState::Active => {
+                if let Some(server_lock) = &self.server_lock {
+                    println!(
+                        "{} {} is running (PID: {}, state: {})",
+                        Glyph::Check.as_str().green().bold(),
+                        format_server_name(name),
+                        format_pid(server_lock.pid),
+                        "active".green()
+                    );
+                } else {
+                    println!(
+                        "{} {} is {}",
+                        Glyph::Check.as_str().green().bold(),
+                        format_server_name(name),
+                        "active".green()
+                    );
+                }
             }
-        }
-        ServerState::Stopped => {
-            println!(
-                "{} {} is {}",
-                "✗".red().bold(),
-                format_server_name(name),
-                "not running".red()
-            );
-        }
-        ServerState::Defunct => {
-            if let Ok(server_lock) = read_server_lock(name) {
-                println!(
-                    "{} {} is defunct (PID: {} died, cleanup pending)",
-                    "☠".magenta().bold(),
-                    format_server_name(name),
-                    format_pid(server_lock.pid)
-                );
-            } else {
+            ServerState::Grace => {
+                if let Some(server_lock) = &self.server_lock {
+                    let when = server_lock
+                        .grace_remaining()
+                        .map(|left| format!("in {}", format_duration(left)))
+                        .unwrap_or_else(|| "soon".to_string());
+                    println!(
+                        "{} {} is in grace period (PID: {}, shutting down {})",
+                        Glyph::Warning.as_str().yellow().bold(),
+                        format_server_name(name),
+                        format_pid(server_lock.pid),
+                        when
+                    );
+                } else {
+                    println!(
+                        "{} {} is in {}",
+                        Glyph::Warning.as_str().yellow().bold(),
+                        format_server_name(name),
+                        "grace period".yellow()
+                    );
+                }
+            }
+            ServerState::Stopped => {
                 println!(
                     "{} {} is {}",
-                    "☠".magenta().bold(),
+                    Glyph::Cross.as_str().red().bold(),
                     format_server_name(name),
-                    "defunct".magenta()
+                    "not running".red()
                 );
             }
+            ServerState::Defunct => {
+                if let Some(server_lock) = &self.server_lock {
+                    println!(
+                        "{} {} is defunct (PID: {} died, cleanup pending)",
+                        Glyph::Defunct.as_str().magenta().bold(),
+                        format_server_name(name),
+                        format_pid(server_lock.pid)
+                    );
+                } else {
+                    println!(
+                        "{} {} is {}",
+                        Glyph::Defunct.as_str().magenta().bold(),
+                        format_server_name(name),
+                        "defunct".magenta()
+                    );
+                }
+            }
         }
+        Ok(())
     }
-
-    std::process::exit(state.exit_code());
 }
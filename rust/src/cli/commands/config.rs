@@ -0,0 +1,374 @@
+use anyhow::Result;
+use serde::Serialize;
+use sharedserver::core::config::{CommandLine, Config, ServerConfig};
+use sharedserver::core::exe::resolve_executable;
+use sharedserver::core::grace::GracePeriod;
+use sharedserver::core::restart::RestartPolicy;
+use sharedserver::core::ErrorKind;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::output::{print_error, print_report, print_success, OutputFormat, Report};
+
+/// Shell words that aren't programs to look for on `PATH`.
+const SHELL_WORDS: &[&str] = &[
+    "cd", "export", "source", ".", "eval", "set", "ulimit", "umask", "trap", "if", "for", "while",
+    "case", "{", "(",
+];
+
+/// What `config check` found.
+#[derive(Serialize)]
+struct CheckReport {
+    files: Vec<PathBuf>,
+    servers: usize,
+    problems: Vec<Problem>,
+}
+
+/// One thing wrong with the config, and where.
+#[derive(Serialize)]
+struct Problem {
+    file: PathBuf,
+    /// The line of the setting, or of its table, if it could be found.
+    line: Option<usize>,
+    message: String,
+}
+
+impl Report for CheckReport {
+    fn print_table(&self) -> Result<()> {
+        if self.problems.is_empty() {
+            print_success(&format!(
+                "Config OK: {} server(s) in {}",
+                self.servers,
+                self.files
+                    .iter()
+                    .map(|file| file.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+        for problem in &self.problems {
+            let place = match problem.line {
+                Some(line) => format!("{}:{}", problem.file.display(), line),
+                None => problem.file.display().to_string(),
+            };
+            print_error(&format!("{}: {}", place, problem.message));
+        }
+        Ok(())
+    }
+}
+
+/// The problems found so far, with the text of the files they are in, read
+/// as needed to place them.
+struct Checker {
+    problems: Vec<Problem>,
+    texts: HashMap<PathBuf, String>,
+}
+
+impl Checker {
+    /// Record `message` about `key` in `table` of `file`.
+    fn problem(&mut self, file: &Path, table: &str, key: Option<&str>, message: String) {
+        let text = self
+            .texts
+            .entry(file.to_path_buf())
+            .or_insert_with(|| std::fs::read_to_string(file).unwrap_or_default());
+        self.problems.push(Problem {
+            file: file.to_path_buf(),
+            line: locate(text, table, key),
+            message,
+        });
+    }
+
+    /// Check one server's settings: that its durations, restart policy, and
+    /// health check parse, its `cwd` exists, and its program can be found.
+    /// `table` is where it is defined.
+    fn server(&mut self, name: &str, table: &str, server: &ServerConfig) {
+        let file = server.source.as_path();
+        if let Some(grace_period) = &server.grace_period {
+            if let Err(e) = grace_period.parse::<GracePeriod>() {
+                self.problem(
+                    file,
+                    table,
+                    Some("grace_period"),
+                    format!("server '{}': invalid grace_period: {:#}", name, e),
+                );
+            }
+        }
+        if let Some(restart) = &server.restart {
+            if let Err(e) = restart.parse::<RestartPolicy>() {
+                self.problem(
+                    file,
+                    table,
+                    Some("restart"),
+                    format!("server '{}': invalid restart: {:#}", name, e),
+                );
+            }
+        }
+        if let Some(health) = &server.health {
+            if let Err(e) = health.to_check() {
+                self.problem(
+                    file,
+                    table,
+                    Some("health"),
+                    format!("server '{}': invalid health check: {:#}", name, e),
+                );
+            }
+        }
+        let cwd = server.cwd();
+        if let Some(cwd) = cwd.as_ref().filter(|cwd| !cwd.is_dir()) {
+            self.problem(
+                file,
+                table,
+                Some("cwd"),
+                format!(
+                    "server '{}': cwd {} is not a directory",
+                    name,
+                    cwd.display()
+                ),
+            );
+        }
+        if let Some(program) = server.command.as_ref().and_then(program) {
+            let path_var = server
+                .env
+                .get("PATH")
+                .cloned()
+                .or_else(|| std::env::var("PATH").ok());
+            let resolved = match (cwd.as_deref(), Path::new(&program).is_relative()) {
+                (Some(cwd), true) if program.contains('/') => {
+                    resolve_executable(&cwd.join(&program).to_string_lossy(), None)
+                }
+                _ => resolve_executable(&program, path_var.as_deref()),
+            };
+            if resolved.is_none() {
+                self.problem(
+                    file,
+                    table,
+                    Some("command"),
+                    format!(
+                        "server '{}': '{}' is not an executable file or on PATH",
+                        name, program
+                    ),
+                );
+            }
+        }
+    }
+}
+
+/// The program `command` runs, if it can be told without running it: not
+/// when a shell command starts with shell syntax, or the program's name has
+/// variables in it.
+fn program(command: &CommandLine) -> Option<String> {
+    let program = match command {
+        CommandLine::Args(args) => args.first()?.clone(),
+        CommandLine::Shell(command) => {
+            let mut words = command.split_whitespace();
+            let first = words.next()?;
+            let word = if first == "exec" {
+                words.next()?
+            } else {
+                first
+            };
+            if SHELL_WORDS.contains(&word) || word.contains('=') {
+                return None;
+            }
+            word.to_string()
+        }
+    };
+    (!program.contains(['$', '{'])).then_some(program)
+}
+
+/// The 1-based line of `key` in `table` (`servers.db`), or of the table's
+/// header if `key` isn't found in it.
+fn locate(text: &str, table: &str, key: Option<&str>) -> Option<usize> {
+    let header = format!("[{}]", table);
+    let lines: Vec<&str> = text.lines().collect();
+    let start = lines.iter().position(|line| {
+        let line: String = line
+            .split('#')
+            .next()
+            .unwrap_or_default()
+            .chars()
+            .filter(|c| !c.is_whitespace() && *c != '"' && *c != '\'')
+            .collect();
+        line == header
+    })?;
+    let found = key.and_then(|key| {
+        lines[start + 1..]
+            .iter()
+            .take_while(|line| !line.trim_start().starts_with('['))
+            .position(|line| {
+                line.trim_start()
+                    .strip_prefix(key)
+                    .is_some_and(|rest| rest.trim_start().starts_with('='))
+            })
+            .map(|offset| start + 1 + offset)
+    });
+    Some(found.unwrap_or(start) + 1)
+}
+
+/// The env variables of `server` that look like a port it listens on
+/// (`PORT`, `PGPORT`, `HTTP_PORT`), with their values.
+fn ports(server: &ServerConfig) -> impl Iterator<Item = (&str, u16)> {
+    server.env.iter().filter_map(|(key, value)| {
+        let port = value.parse().ok()?;
+        key.to_ascii_uppercase()
+            .ends_with("PORT")
+            .then_some((key.as_str(), port))
+    })
+}
+
+/// `config check`: read the config and check every server it defines (and
+/// every profile's settings) before anything is started, reporting each
+/// problem with the file and line it is on. Fails if there are any.
+///
+/// `builtins` are the subcommands, which an alias can't replace.
+pub fn check(format: OutputFormat, builtins: &[&str]) -> Result<()> {
+    let config = super::r#use::load_config()?;
+    let mut checker = Checker {
+        problems: Vec::new(),
+        texts: HashMap::new(),
+    };
+
+    for (name, server) in &config.servers {
+        checker.server(name, &format!("servers.{}", name), server);
+    }
+    for (profile, overrides) in &config.profiles {
+        for (name, server) in &overrides.servers {
+            let table = format!("profiles.{}.servers.{}", profile, name);
+            checker.server(name, &table, server);
+        }
+    }
+
+    // Dependencies that aren't defined, or that go round in a cycle, stop
+    // `up` and `use`. Each is reported once: undefined ones where they are
+    // named, then cycles at the first server found in one.
+    let mut reported = HashSet::new();
+    for (name, server) in &config.servers {
+        for dependency in &server.depends_on {
+            if !config.servers.contains_key(dependency) {
+                let message = format!(
+                    "Server '{}' depends on '{}', which the config doesn't define",
+                    name, dependency
+                );
+                reported.insert(message.clone());
+                checker.problem(
+                    &server.source,
+                    &format!("servers.{}", name),
+                    Some("depends_on"),
+                    message,
+                );
+            }
+        }
+    }
+    for (name, server) in &config.servers {
+        if let Err(e) = config.start_order(&[name]) {
+            let message = format!("{:#}", e);
+            if reported.insert(message.clone()) {
+                checker.problem(
+                    &server.source,
+                    &format!("servers.{}", name),
+                    Some("depends_on"),
+                    message,
+                );
+            }
+        }
+    }
+
+    // Two servers claiming one port can't both run.
+    let mut claimed: BTreeMap<u16, &str> = BTreeMap::new();
+    for (name, server) in &config.servers {
+        for (key, port) in ports(server) {
+            match claimed.get(&port) {
+                Some(other) => checker.problem(
+                    &server.source,
+                    &format!("servers.{}", name),
+                    Some("env"),
+                    format!(
+                        "server '{}': port {} ({}) is also used by server '{}'",
+                        name, port, key, other
+                    ),
+                ),
+                None => {
+                    claimed.insert(port, name);
+                }
+            }
+        }
+    }
+
+    if let Some(grace_period) = &config.defaults.grace_period {
+        if let Err(e) = grace_period.parse::<GracePeriod>() {
+            if let Some(file) = defined_in(&config, "defaults") {
+                checker.problem(
+                    &file,
+                    "defaults",
+                    Some("grace_period"),
+                    format!("invalid defaults.grace_period: {:#}", e),
+                );
+            }
+        }
+    }
+    for name in config.aliases.keys() {
+        if builtins.contains(&name.as_str()) {
+            if let Some(file) = defined_in(&config, "aliases") {
+                checker.problem(
+                    &file,
+                    "aliases",
+                    Some(name),
+                    format!(
+                        "alias '{}' is never used: '{}' is a built-in subcommand",
+                        name, name
+                    ),
+                );
+            }
+        }
+    }
+
+    let report = CheckReport {
+        files: config.files.clone(),
+        servers: config.servers.len(),
+        problems: checker.problems,
+    };
+    print_report(format, &report)?;
+    match report.problems.len() {
+        0 => Ok(()),
+        count => Err(ErrorKind::InvalidArgs.error(format!("{} problem(s) in the config", count))),
+    }
+}
+
+/// The last file read with a `[table]`, which is the one whose settings
+/// took effect.
+fn defined_in(config: &Config, table: &str) -> Option<PathBuf> {
+    config
+        .files
+        .iter()
+        .rev()
+        .find(|file| {
+            std::fs::read_to_string(file).is_ok_and(|text| locate(&text, table, None).is_some())
+        })
+        .cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locate_finds_keys_in_their_table() {
+        let text = "[servers.web]\ncommand = \"x\"\n\n[servers.\"db\"]  # main\n\
+                    cwd = \"data\"\ngrace_period = \"5x\"\n[servers.cache]\ngrace_period = \"1m\"\n";
+        assert_eq!(locate(text, "servers.db", Some("grace_period")), Some(6));
+        assert_eq!(locate(text, "servers.db", Some("command")), Some(4));
+        assert_eq!(locate(text, "servers.other", None), None);
+    }
+
+    #[test]
+    fn test_program_skips_shell_syntax() {
+        let shell = |command: &str| program(&CommandLine::Shell(command.to_string()));
+        assert_eq!(shell("exec postgres -D data").as_deref(), Some("postgres"));
+        assert_eq!(shell("cd web && npm start"), None);
+        assert_eq!(shell("PORT=1 node app.js"), None);
+        assert_eq!(
+            program(&CommandLine::Args(vec!["{home}/bin/server".to_string()])),
+            None
+        );
+    }
+}
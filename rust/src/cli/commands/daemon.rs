@@ -0,0 +1,99 @@
+use anyhow::{bail, Result};
+use colored::*;
+use serde::Serialize;
+use serde_json::{json, Value};
+use sharedserver::core::ErrorKind;
+use std::time::{Duration, Instant};
+
+use crate::daemon::{call, socket_path};
+use crate::output::{
+    format_pid, format_server_name, print_report, print_success, Glyph, OutputFormat, Progress,
+    Report,
+};
+
+/// How long `daemon stop` waits for the daemon to take its servers down and
+/// exit.
+const STOP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The running daemon, as it describes itself.
+#[derive(Serialize)]
+struct DaemonReport {
+    socket: String,
+    #[serde(flatten)]
+    status: Value,
+}
+
+impl Report for DaemonReport {
+    fn print_table(&self) -> Result<()> {
+        let servers = self.status["servers"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default();
+        println!(
+            "{} Daemon running (PID: {}, version {}) on {}",
+            Glyph::Check.as_str().green().bold(),
+            format_pid(self.status["pid"].as_i64().unwrap_or(0) as i32),
+            self.status["version"].as_str().unwrap_or("?"),
+            self.socket
+        );
+        if servers.is_empty() {
+            println!("  No servers supervised");
+        }
+        for server in servers {
+            println!(
+                "  {} (PID: {})",
+                format_server_name(server["name"].as_str().unwrap_or("?")),
+                format_pid(server["pid"].as_i64().unwrap_or(0) as i32)
+            );
+        }
+        Ok(())
+    }
+}
+
+/// `daemon status`: report the running daemon, or fail with `NotRunning`.
+pub fn status(format: OutputFormat) -> Result<()> {
+    let socket = socket_path()?;
+    let Some(status) = call("ping", Value::Null)? else {
+        return Err(
+            ErrorKind::NotRunning.error(format!("No daemon is listening on {}", socket.display()))
+        );
+    };
+    print_report(
+        format,
+        &DaemonReport {
+            socket: socket.display().to_string(),
+            status,
+        },
+    )
+}
+
+/// `daemon stop`: ask the daemon to shut down, then wait until its socket is
+/// gone, which it removes once it stops listening (its servers go down right
+/// after).
+pub fn stop() -> Result<()> {
+    let socket = socket_path()?;
+    let Some(result) = call("shutdown", json!({}))? else {
+        return Err(
+            ErrorKind::NotRunning.error(format!("No daemon is listening on {}", socket.display()))
+        );
+    };
+    let pid = result["pid"].as_i64().unwrap_or(0) as i32;
+
+    let mut progress = Progress::new(format!("Stopping the daemon (PID: {})", pid));
+    let start = Instant::now();
+    while sharedserver::core::is_process_alive(pid) {
+        if start.elapsed() > STOP_TIMEOUT {
+            progress.finish();
+            bail!(
+                "Daemon (PID: {}) still running after {}s",
+                pid,
+                STOP_TIMEOUT.as_secs()
+            );
+        }
+        progress.tick();
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    progress.finish();
+    print_success(&format!("Stopped the daemon (PID: {})", format_pid(pid)));
+    Ok(())
+}
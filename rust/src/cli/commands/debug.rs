@@ -1,8 +1,127 @@
 use anyhow::Result;
+use chrono::{DateTime, Utc};
+use colored::*;
+use serde::{Serialize, Serializer};
+use serde_json::Value;
+use sharedserver::core::filter::matching_servers;
+use sharedserver::core::log::{InvocationLog, WatcherEvent, LOG_FILES};
 
-pub fn execute(name: &str, count: usize) -> Result<()> {
+use crate::output::{
+    ellipsize, format_pid, format_utc_timestamp, print_report, Glyph, OutputFormat, PerServer,
+    Report, Table,
+};
+
+/// The tail of one of a server's logs.
+enum DebugReport {
+    Invocations {
+        name: String,
+        logs: Vec<InvocationLog>,
+        raw: bool,
+    },
+    WatcherEvents {
+        name: String,
+        events: Vec<WatcherEvent>,
+        raw: bool,
+    },
+}
+
+/// `raw` prints each entry in full with its UTC timestamp, as logged, instead
+/// of one summarized line.
+pub fn execute(name: &str, count: usize, raw: bool, format: OutputFormat) -> Result<()> {
     let logs = sharedserver::core::log::read_recent_invocations(name, count)?;
+    print_report(
+        format,
+        &DebugReport::Invocations {
+            name: name.to_string(),
+            logs,
+            raw,
+        },
+    )
+}
+
+/// The invocation log, or with `watcher` the watcher's event log, of every
+/// server whose name matches `pattern`, keyed by name.
+pub fn execute_matching(
+    pattern: &str,
+    watcher: bool,
+    count: usize,
+    raw: bool,
+    format: OutputFormat,
+) -> Result<()> {
+    let reports = matching_servers(pattern, LOG_FILES)?
+        .into_iter()
+        .map(|name| {
+            let report = if watcher {
+                DebugReport::WatcherEvents {
+                    events: sharedserver::core::log::read_recent_watcher_events(&name, count)?,
+                    name: name.clone(),
+                    raw,
+                }
+            } else {
+                DebugReport::Invocations {
+                    logs: sharedserver::core::log::read_recent_invocations(&name, count)?,
+                    name: name.clone(),
+                    raw,
+                }
+            };
+            Ok((name, report))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    print_report(format, &PerServer(reports))
+}
+
+/// Show the watcher's event log (`<name>.watcher.log`)
+pub fn execute_watcher(name: &str, count: usize, raw: bool, format: OutputFormat) -> Result<()> {
+    let events = sharedserver::core::log::read_recent_watcher_events(name, count)?;
+    print_report(
+        format,
+        &DebugReport::WatcherEvents {
+            name: name.to_string(),
+            events,
+            raw,
+        },
+    )
+}
 
+/// The entries themselves, oldest first, as they appear in the log.
+impl Serialize for DebugReport {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            DebugReport::Invocations { logs, .. } => logs.serialize(serializer),
+            DebugReport::WatcherEvents { events, .. } => events.serialize(serializer),
+        }
+    }
+}
+
+impl Report for DebugReport {
+    fn print_table(&self) -> Result<()> {
+        match self {
+            DebugReport::Invocations {
+                name,
+                logs,
+                raw: true,
+            } => print_invocations(name, logs),
+            DebugReport::Invocations { name, logs, .. } => {
+                summarize_invocations(name, logs);
+                Ok(())
+            }
+            DebugReport::WatcherEvents {
+                name,
+                events,
+                raw: true,
+            } => {
+                print_watcher_events(name, events);
+                Ok(())
+            }
+            DebugReport::WatcherEvents { name, events, .. } => {
+                summarize_watcher_events(name, events);
+                Ok(())
+            }
+        }
+    }
+}
+
+fn print_invocations(name: &str, logs: &[InvocationLog]) -> Result<()> {
     if logs.is_empty() {
         println!("No invocations logged for server '{}'", name);
         return Ok(());
@@ -27,3 +146,168 @@ pub fn execute(name: &str, count: usize) -> Result<()> {
 
     Ok(())
 }
+
+fn print_watcher_events(name: &str, events: &[WatcherEvent]) {
+    if events.is_empty() {
+        println!("No watcher events logged for server '{}'", name);
+        return;
+    }
+
+    println!("Recent watcher events for server '{}':\n", name);
+
+    for event in events {
+        if event.details.is_null() {
+            println!(
+                "[{}] ({}) {}",
+                event.timestamp, event.watcher_pid, event.event
+            );
+        } else {
+            println!(
+                "[{}] ({}) {} {}",
+                event.timestamp, event.watcher_pid, event.event, event.details
+            );
+        }
+    }
+}
+
+/// One line per invocation: when, what, whether it worked, and the gist of
+/// its metadata.
+fn summarize_invocations(name: &str, logs: &[InvocationLog]) {
+    if logs.is_empty() {
+        println!("No invocations logged for server '{}'", name);
+        return;
+    }
+
+    println!("Recent invocations for server '{}':\n", name);
+    let mut table = Table::new(&["WHEN", "COMMAND", "RESULT", "DETAILS"]);
+    for log in logs {
+        let succeeded = log.result == "success";
+        let result = if succeeded {
+            format!("{} ok", Glyph::Check).green()
+        } else {
+            format!("{} {}", Glyph::Cross, log.result).red()
+        };
+        let mut details = Vec::new();
+        if let Some(error) = &log.error {
+            details.push(error.red());
+        }
+        if let Some(metadata) = log
+            .metadata
+            .as_ref()
+            .map(summarize)
+            .filter(|m| !m.is_empty())
+        {
+            details.push(metadata.dimmed());
+        }
+        let details = details
+            .iter()
+            .map(|d| d.to_string())
+            .collect::<Vec<_>>()
+            .join(" ");
+        table.row(vec![
+            ago(log.timestamp).dimmed(),
+            format!("{} {}", log.command, log.args.join(" ")).normal(),
+            result,
+            details.normal(),
+        ]);
+    }
+    table.print();
+}
+
+/// One line per watcher event, with its details flattened to `key=value`.
+fn summarize_watcher_events(name: &str, events: &[WatcherEvent]) {
+    if events.is_empty() {
+        println!("No watcher events logged for server '{}'", name);
+        return;
+    }
+
+    println!("Recent watcher events for server '{}':\n", name);
+    let mut table = Table::new(&["WHEN", "WATCHER", "EVENT", "DETAILS"]);
+    for event in events {
+        let kind = match event.event.as_str() {
+            "error" | "kill-escalation" | "unhealthy" | "limit-exceeded" => event.event.red(),
+            "grace-start" | "grace-expired" | "terminate" | "restart" => event.event.yellow(),
+            _ => event.event.normal(),
+        };
+        table.row(vec![
+            ago(event.timestamp).dimmed(),
+            format_pid(event.watcher_pid),
+            kind,
+            summarize(&event.details).dimmed(),
+        ]);
+    }
+    table.print();
+}
+
+/// How long ago `timestamp` was, to the second under a minute.
+fn ago(timestamp: DateTime<Utc>) -> String {
+    match (Utc::now() - timestamp).num_seconds() {
+        secs @ 0..=59 => format!("{}s ago", secs),
+        _ => format_utc_timestamp(timestamp),
+    }
+}
+
+/// Longest a single value may run in a summary before it is cut short.
+const MAX_VALUE_WIDTH: usize = 60;
+
+/// A JSON value on one line: objects as `key=value` pairs (nulls dropped),
+/// arrays comma-joined, strings unquoted. Nested objects show only their keys
+/// (e.g. the client PIDs of a `clients` map); long values are cut short.
+fn summarize(value: &Value) -> String {
+    match value {
+        Value::Object(fields) => fields
+            .iter()
+            .filter(|(_, v)| !v.is_null())
+            .map(|(k, v)| format!("{}={}", k, summarize_value(v)))
+            .collect::<Vec<_>>()
+            .join(" "),
+        other => summarize_value(other),
+    }
+}
+
+fn summarize_value(value: &Value) -> String {
+    let text = match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        Value::Array(items) => format!(
+            "[{}]",
+            items
+                .iter()
+                .map(summarize_value)
+                .collect::<Vec<_>>()
+                .join(",")
+        ),
+        Value::Object(fields) => format!(
+            "{{{}}}",
+            fields.keys().cloned().collect::<Vec<_>>().join(",")
+        ),
+        other => other.to_string(),
+    };
+    ellipsize(&text, MAX_VALUE_WIDTH)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_summarize_flattens_to_one_line() {
+        let details = json!({
+            "client_pid": 42,
+            "clients": { "1": { "cmdline": ["nvim"] }, "2": {} },
+            "metadata": null,
+            "pids": [1, 2],
+            "reason": "process exited",
+        });
+        assert_eq!(
+            summarize(&details),
+            "client_pid=42 clients={1,2} pids=[1,2] reason=process exited"
+        );
+        assert_eq!(summarize(&Value::Null), "");
+
+        let long = summarize(&json!({ "command": "x".repeat(100) }));
+        assert_eq!(long.chars().count(), "command=".len() + MAX_VALUE_WIDTH);
+        assert!(long.ends_with('…'));
+    }
+}
@@ -1,5 +1,7 @@
-use anyhow::{bail, Context, Result};
-use sharedserver::core::{get_server_state, ClientsLock, ServerState};
+use anyhow::Result;
+use sharedserver::core::grace::GracePeriod;
+use sharedserver::core::handle::detach_client;
+use sharedserver::core::{get_server_state, read_server_lock, ErrorKind, ServerState};
 
 use crate::output::{format_refcount, format_server_name, print_success, print_warning};
 
@@ -8,10 +10,11 @@ pub fn execute(name: &str, client_pid: i32) -> Result<()> {
 
     match state {
         ServerState::Stopped => {
-            bail!("Server '{}' is not running", name);
+            Err(ErrorKind::NotRunning.error(format!("Server '{}' is not running", name)))
         }
         ServerState::Active => {
-            let new_refcount = decrement_refcount(name, client_pid)?;
+            let grace_period = grace_period_of(name);
+            let new_refcount = detach_client(name, client_pid)?;
 
             // Log success
             let _ = sharedserver::core::log::log_invocation(
@@ -26,57 +29,46 @@ pub fn execute(name: &str, client_pid: i32) -> Result<()> {
                 ),
             );
 
-            if new_refcount == 0 {
-                print_warning(&format!(
-                    "Detached from server {} (refcount: {}, entering grace period)",
-                    format_server_name(name),
-                    format_refcount(new_refcount)
-                ));
-            } else {
-                print_success(&format!(
-                    "Detached from server {} (refcount: {})",
-                    format_server_name(name),
-                    format_refcount(new_refcount)
-                ));
-            }
-
+            print_detached(name, new_refcount, grace_period.as_ref());
             Ok(())
         }
-        ServerState::Grace => {
-            bail!("Server '{}' is in grace period (refcount already 0)", name);
-        }
-        ServerState::Defunct => {
-            bail!(
-                "Server '{}' is shutting down (defunct, cleanup pending)",
-                name
-            );
-        }
+        ServerState::Grace => Err(ErrorKind::NotAttached.error(format!(
+            "Server '{}' is in grace period (refcount already 0)",
+            name
+        ))),
+        ServerState::Defunct => Err(ErrorKind::ShuttingDown.error(format!(
+            "Server '{}' is shutting down (defunct, cleanup pending)",
+            name
+        ))),
     }
 }
 
-fn decrement_refcount(name: &str, client_pid: i32) -> Result<u32> {
-    let clients_path = sharedserver::core::lockfile::clients_lockfile_path(name)?;
-
-    // Read-modify-write under a single exclusive lock. The clients lockfile is
-    // never deleted while the server lives (refcount 0 == grace, the file stays
-    // with an empty client map), so the inode is stable and this lock gives real
-    // mutual exclusion. The refcount is derived from the client map, so it can
-    // never drift from the actual set of attached clients.
-    sharedserver::core::lockfile::with_lock(&clients_path, |file| {
-        let mut clients: ClientsLock =
-            sharedserver::core::lockfile::read_json(file).unwrap_or_else(|_| ClientsLock::new());
-
-        if clients.clients.remove(&client_pid).is_none() {
-            bail!(
-                "Client {} was not attached to server '{}'",
-                client_pid,
-                name
-            );
-        }
+/// `name`'s grace period, read before detaching: with `none` the lock may be
+/// gone by the time the detach returns.
+pub(crate) fn grace_period_of(name: &str) -> Option<GracePeriod> {
+    read_server_lock(name).ok()?.grace_period.parse().ok()
+}
 
-        clients.refcount = clients.clients.len() as u32;
-        sharedserver::core::lockfile::write_json(file, &clients)?;
-        Ok(clients.refcount)
-    })
-    .with_context(|| format!("Failed to decrement refcount for '{}'", name))
+/// Report a detach that left `refcount` clients, saying what happens to the
+/// server when that was the last one.
+pub(crate) fn print_detached(name: &str, refcount: u32, grace_period: Option<&GracePeriod>) {
+    if refcount > 0 {
+        print_success(&format!(
+            "Detached from server {} (refcount: {})",
+            format_server_name(name),
+            format_refcount(refcount)
+        ));
+        return;
+    }
+    let next = match grace_period {
+        Some(GracePeriod::None) => "shutting down",
+        Some(GracePeriod::Infinite) => "kept running until stopped",
+        _ => "entering grace period",
+    };
+    print_warning(&format!(
+        "Detached from server {} (refcount: {}, {})",
+        format_server_name(name),
+        format_refcount(refcount),
+        next
+    ));
 }
@@ -0,0 +1,216 @@
+use anyhow::{bail, Context, Result};
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::{dup2, fork, getpid, getppid, setsid, ForkResult, Pid};
+use sharedserver::core::{
+    is_process_alive, parent_pid, process_cwd, process_name, read_clients_lock, read_server_lock,
+};
+use std::io::{Read, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use super::hook::posix_quote;
+use super::r#use::UseFlags;
+use super::start::StartOptions;
+
+/// How often the holder checks whether the shell has left the directory.
+const HOLD_POLL: Duration = Duration::from_secs(1);
+
+/// The interactive shell direnv is loading the `.envrc` for: the parent of
+/// the `direnv` process above us (`shell` → `direnv export` → `bash .envrc`
+/// → us). Run outside direnv, just our parent.
+fn envrc_shell() -> i32 {
+    let parent = getppid().as_raw();
+    let mut pid = parent;
+    // Bounded, in case a PID is recycled mid-walk into a loop.
+    for _ in 0..16 {
+        if process_name(pid).as_deref() == Some("direnv") {
+            return parent_pid(pid).filter(|&shell| shell > 1).unwrap_or(parent);
+        }
+        match parent_pid(pid) {
+            Some(next) if next > 1 && next != pid => pid = next,
+            _ => break,
+        }
+    }
+    parent
+}
+
+/// The client metadata identifying the holder for `shell` in `dir`, so a
+/// reload of the `.envrc` finds the one it already started.
+fn holder_metadata(dir: &Path, shell: i32) -> String {
+    format!("direnv {} (shell {})", dir.display(), shell)
+}
+
+/// Use `name` for as long as the shell that direnv is loading this `.envrc`
+/// for stays in the directory, and print `export` lines for its address.
+///
+/// The reference is held by a small background process rather than the
+/// shell, since direnv has no unload hook: it checks the shell's working
+/// directory every second, and releases the reference once the shell has
+/// left the directory (which is when direnv unloads) or exited.
+pub fn execute(
+    name: &str,
+    opts: &StartOptions,
+    via_daemon: bool,
+    command: &[String],
+) -> Result<()> {
+    let dir = std::env::current_dir()
+        .and_then(|dir| dir.canonicalize())
+        .context("Failed to determine the .envrc's directory")?;
+    let shell = envrc_shell();
+    let metadata = holder_metadata(&dir, shell);
+
+    // Reloaded (the .envrc changed, or `direnv reload`): keep the holder.
+    let held = read_clients_lock(name).is_ok_and(|clients| {
+        clients.clients.iter().any(|(pid, client)| {
+            client.metadata.as_deref() == Some(&metadata) && client.is_alive(*pid)
+        })
+    });
+    if !held {
+        let (holder, mut go) = spawn_holder(name, shell, &dir)?;
+        let used = super::r#use::execute(
+            name,
+            opts,
+            Some(metadata),
+            Some(holder.as_raw()),
+            UseFlags {
+                via_daemon,
+                ..UseFlags::default()
+            },
+            command,
+        );
+        if let Err(e) = used.and_then(|()| {
+            go.write_all(b"1")
+                .context("Failed to start the direnv holder")
+        }) {
+            // It never held anything, so there is nothing to release.
+            let _ = kill(holder, Signal::SIGKILL);
+            return Err(e);
+        }
+    }
+
+    let lock = read_server_lock(name)?;
+    print!("{}", exports(name, lock.pid, lock.address().as_deref()));
+    eprintln!(
+        "sharedserver: using {} (PID {}{}) while in {}",
+        name,
+        lock.pid,
+        lock.address()
+            .map(|address| format!(", {}", address))
+            .unwrap_or_default(),
+        dir.display()
+    );
+    Ok(())
+}
+
+/// `export` lines for `.envrc` to evaluate: the server's PID, and where to
+/// reach it, as `SHAREDSERVER_<NAME>_*`.
+fn exports(name: &str, pid: i32, address: Option<&str>) -> String {
+    let prefix = format!(
+        "SHAREDSERVER_{}",
+        name.chars()
+            .map(|c| if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            })
+            .collect::<String>()
+    );
+    let mut vars = vec![("PID", pid.to_string())];
+    if let Some(address) = address {
+        vars.push(("ADDRESS", address.to_string()));
+        if let Some(path) = address.strip_prefix("unix:") {
+            vars.push(("SOCKET", path.to_string()));
+        } else if let Some((host, port)) = address
+            .strip_prefix("tcp:")
+            .and_then(|host_port| host_port.rsplit_once(':'))
+        {
+            vars.push(("HOST", host.to_string()));
+            vars.push(("PORT", port.to_string()));
+        }
+    }
+    vars.iter()
+        .map(|(var, value)| format!("export {}_{}={}\n", prefix, var, posix_quote(value)))
+        .collect()
+}
+
+/// Fork the process that will hold the reference, detached from direnv's
+/// pipes (direnv waits for them to close) and the terminal. It holds nothing
+/// until sent a byte on the returned socket.
+fn spawn_holder(name: &str, shell: i32, dir: &Path) -> Result<(Pid, UnixStream)> {
+    let (mut ready, go) = UnixStream::pair().context("Failed to create socket pair")?;
+    // SAFETY: nothing has started a thread yet in this short-lived command,
+    // so the child is free to carry on running normal Rust code.
+    match unsafe { fork() } {
+        Ok(ForkResult::Parent { child }) => Ok((child, go)),
+        Ok(ForkResult::Child) => {
+            drop(go);
+            let _ = setsid();
+            if let Ok(null) = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open("/dev/null")
+            {
+                for fd in 0..=2 {
+                    let _ = dup2(null.as_raw_fd(), fd);
+                }
+            }
+            let mut byte = [0u8; 1];
+            if matches!(ready.read(&mut byte), Ok(1)) {
+                hold(name, shell, dir);
+            }
+            std::process::exit(0);
+        }
+        Err(e) => bail!("Failed to fork the direnv holder: {}", e),
+    }
+}
+
+/// Keep the reference until `shell` leaves `dir` or exits, then release it.
+/// Stops early if the reference goes away by other means (`stop`, `unuse`).
+fn hold(name: &str, shell: i32, dir: &Path) {
+    let me = getpid().as_raw();
+    loop {
+        std::thread::sleep(HOLD_POLL);
+        let attached =
+            read_clients_lock(name).is_ok_and(|clients| clients.clients.contains_key(&me));
+        if !attached {
+            return;
+        }
+        let left =
+            !is_process_alive(shell) || process_cwd(shell).is_some_and(|cwd| !in_dir(&cwd, dir));
+        if left {
+            let _ = super::unuse::execute(name, Some(me), false);
+            return;
+        }
+    }
+}
+
+/// Whether `cwd` is `dir` or below it.
+fn in_dir(cwd: &Path, dir: &Path) -> bool {
+    let cwd: PathBuf = cwd.canonicalize().unwrap_or_else(|_| cwd.to_path_buf());
+    cwd.starts_with(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exports() {
+        assert_eq!(
+            exports("my-db", 42, Some("tcp:127.0.0.1:5432")),
+            "export SHAREDSERVER_MY_DB_PID='42'\n\
+             export SHAREDSERVER_MY_DB_ADDRESS='tcp:127.0.0.1:5432'\n\
+             export SHAREDSERVER_MY_DB_HOST='127.0.0.1'\n\
+             export SHAREDSERVER_MY_DB_PORT='5432'\n"
+        );
+        assert_eq!(
+            exports("lsp", 7, Some("unix:/tmp/it's.sock")),
+            "export SHAREDSERVER_LSP_PID='7'\n\
+             export SHAREDSERVER_LSP_ADDRESS='unix:/tmp/it'\\''s.sock'\n\
+             export SHAREDSERVER_LSP_SOCKET='/tmp/it'\\''s.sock'\n"
+        );
+        assert_eq!(exports("x", 1, None), "export SHAREDSERVER_X_PID='1'\n");
+    }
+}
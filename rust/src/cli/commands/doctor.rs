@@ -1,61 +1,418 @@
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
 use colored::*;
+use nix::unistd::gethostname;
+use serde::Serialize;
+use sharedserver::core::address::claimed_by;
+use sharedserver::core::filter::server_tags;
+use sharedserver::core::heartbeat::{delete_heartbeat, heartbeat_age, is_stale};
+use sharedserver::core::lockfile::{
+    read_json, server_lockfile_path, servers_with, with_shared_lock, write_server_lock,
+};
+use sharedserver::core::log::{replay_clients, PrunedLog};
+use sharedserver::core::pool;
+use sharedserver::core::registry;
 use sharedserver::core::{
     clients_lock_exists, delete_clients_lock, delete_server_lock, get_server_state,
-    is_process_alive, process_liveness_checked, read_clients_lock, read_server_lock,
-    server_lock_exists, Liveness, ServerState,
+    read_clients_lock, read_server_lock, server_lock_exists, update_clients_lock, ErrorKind,
+    Liveness, LockUpdate, ServerFilter, ServerLock, ServerState,
+};
+use std::collections::HashMap;
+
+use crate::output::{
+    format_duration, format_pid, format_server_name, print_error, print_report, print_success,
+    print_warning, Glyph, OutputFormat, Report,
 };
-use std::fs;
 
-use crate::output::{format_pid, format_server_name, print_error, print_success, print_warning};
+/// Everything doctor found and did, for `--format json`/`yaml`.
+#[derive(Serialize)]
+struct DoctorReport {
+    /// Where and when, so reports gathered from many machines can be told
+    /// apart.
+    host: String,
+    checked_at: DateTime<Utc>,
+    version: &'static str,
+    /// Logs pruned before an all-servers sweep.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pruned: Vec<PrunedLog>,
+    servers: Vec<Checkup>,
+    summary: Summary,
+    /// Entries in the registry rebuilt after an all-servers sweep.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    registry_entries: Option<usize>,
+}
+
+impl DoctorReport {
+    fn new() -> Self {
+        Self {
+            host: gethostname()
+                .ok()
+                .and_then(|host| host.into_string().ok())
+                .unwrap_or_default(),
+            checked_at: Utc::now(),
+            version: env!("CARGO_PKG_VERSION"),
+            pruned: Vec::new(),
+            servers: Vec::new(),
+            summary: Summary::default(),
+            registry_entries: None,
+        }
+    }
+}
+
+/// Totals across every server checked.
+#[derive(Default, Serialize)]
+struct Summary {
+    servers_checked: usize,
+    /// Servers with no issues and no failed checks.
+    healthy: usize,
+    issues_found: u32,
+    issues_fixed: u32,
+    /// Issues still present: left alone, or whose repair failed. Any make
+    /// doctor exit non-zero.
+    issues_unfixed: u32,
+}
+
+impl Summary {
+    fn new(servers: &[Checkup]) -> Self {
+        let mut summary = Summary {
+            servers_checked: servers.len(),
+            ..Default::default()
+        };
+        for checkup in servers {
+            if checkup.is_healthy() {
+                summary.healthy += 1;
+            }
+            summary.issues_found += checkup.issues_found;
+            summary.issues_fixed += checkup.issues_fixed;
+            summary.issues_unfixed += checkup.issues_unfixed;
+        }
+        summary
+    }
+
+    fn print(&self) {
+        let symbol = if self.issues_unfixed > 0 {
+            Glyph::Warning.as_str().yellow().bold()
+        } else {
+            Glyph::Check.as_str().green().bold()
+        };
+        println!(
+            "  {} {} server(s) checked, {} healthy; {} issue(s) found, {} fixed, {} unfixed",
+            symbol,
+            self.servers_checked,
+            self.healthy,
+            self.issues_found,
+            self.issues_fixed,
+            self.issues_unfixed
+        );
+    }
+}
+
+/// One server's checks. In table mode each finding is printed as it's made,
+/// so a long sweep shows progress, or held in `held` while servers are
+/// checked side by side; otherwise they're only collected.
+#[derive(Serialize)]
+struct Checkup {
+    name: String,
+    state: Option<&'static str>,
+    status: Status,
+    issues_found: u32,
+    /// Issues whose repairs all succeeded.
+    issues_fixed: u32,
+    /// Issues left in place, plus failed checks and repairs.
+    issues_unfixed: u32,
+    findings: Vec<Finding>,
+    /// The check findings are being recorded for.
+    #[serde(skip)]
+    check: &'static str,
+    #[serde(skip)]
+    print: bool,
+    /// Lines waiting to be printed, when the checkup runs alongside others.
+    #[serde(skip)]
+    held: Option<Vec<Line>>,
+}
+
+/// A line of a checkup's table output.
+enum Line {
+    Plain(String),
+    Success(String),
+    Warning(String),
+    Error(String),
+}
+
+impl Line {
+    fn print(&self) {
+        match self {
+            Line::Plain(text) => println!("{}", text),
+            Line::Success(text) => print_success(text),
+            Line::Warning(text) => print_warning(text),
+            Line::Error(text) => print_error(text),
+        }
+    }
+}
+
+/// A server's outcome, once its findings are tallied.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Status {
+    /// No issues.
+    Healthy,
+    /// Issues, all of them fixed.
+    Fixed,
+    /// Issues (or failures) remain.
+    Unhealthy,
+}
+
+#[derive(Serialize)]
+struct Finding {
+    /// Which check this came from: `lockfiles`, `server_lock`,
+    /// `server_process`, `watcher`, `clients`, `address`, `restore`, or
+    /// `doctor` for a check that failed outright.
+    check: &'static str,
+    kind: FindingKind,
+    severity: Severity,
+    /// For a repair (`fixed`, or an `error` from a failed one), what doctor
+    /// did, e.g. `remove_server_lock`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    action: Option<&'static str>,
+    message: String,
+}
+
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum FindingKind {
+    /// A check that passed.
+    Ok,
+    /// Something wrong.
+    Issue,
+    /// Something doctor repaired.
+    Fixed,
+    /// A repair (or check) that failed.
+    Error,
+    Note,
+}
+
+/// How much a finding matters, for aggregating reports.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Severity {
+    Ok,
+    Info,
+    /// Untidy but harmless, or repairable: stale lockfiles, dead clients.
+    Warning,
+    /// The server isn't being looked after properly (no working watcher, no
+    /// clients lock), or doctor couldn't do its job.
+    Error,
+}
+
+impl Checkup {
+    fn new(name: &str, print: bool) -> Self {
+        Self {
+            name: name.to_string(),
+            state: None,
+            status: Status::Healthy,
+            issues_found: 0,
+            issues_fixed: 0,
+            issues_unfixed: 0,
+            findings: Vec::new(),
+            check: "doctor",
+            print,
+            held: None,
+        }
+    }
+
+    /// Print `line`, or hold it for [`Checkup::flush`] if the checkup is
+    /// being held.
+    fn say(&mut self, line: Line) {
+        if !self.print {
+            return;
+        }
+        match &mut self.held {
+            Some(held) => held.push(line),
+            None => line.print(),
+        }
+    }
+
+    /// Print the lines held back so far.
+    fn flush(&mut self) {
+        for line in self.held.iter_mut().flat_map(std::mem::take) {
+            line.print();
+        }
+    }
+
+    /// Record what follows as findings of `check`.
+    fn checking(&mut self, check: &'static str) {
+        self.check = check;
+    }
+
+    fn record(&mut self, kind: FindingKind, message: String) {
+        let severity = match kind {
+            FindingKind::Ok => Severity::Ok,
+            FindingKind::Note | FindingKind::Fixed => Severity::Info,
+            FindingKind::Issue => Severity::Warning,
+            FindingKind::Error => Severity::Error,
+        };
+        self.findings.push(Finding {
+            check: self.check,
+            kind,
+            severity,
+            action: None,
+            message,
+        });
+    }
+
+    fn pass(&mut self, message: String) {
+        self.say(Line::Plain(format!(
+            "  {} {}",
+            Glyph::Check.as_str().green(),
+            message
+        )));
+        self.record(FindingKind::Ok, message);
+    }
+
+    fn issue(&mut self, severity: Severity, message: String) {
+        self.say(Line::Warning(format!("  {}", message)));
+        self.issues_found += 1;
+        self.record(FindingKind::Issue, message);
+        self.last().severity = severity;
+    }
+
+    fn fixed(&mut self, message: String) {
+        self.say(Line::Success(format!("    {}", message)));
+        self.record(FindingKind::Fixed, message);
+    }
+
+    fn failed(&mut self, message: String) {
+        self.say(Line::Error(format!("    {}", message)));
+        self.record(FindingKind::Error, message);
+    }
+
+    /// Record the outcome of a repair: `done` if it worked, otherwise a
+    /// failure to `attempt`.
+    fn repair(&mut self, action: &'static str, result: Result<()>, done: &str, attempt: &str) {
+        match result {
+            Ok(()) => self.fixed(done.to_string()),
+            Err(e) => self.failed(format!("Failed to {}: {}", attempt, e)),
+        }
+        self.last().action = Some(action);
+    }
+
+    fn last(&mut self) -> &mut Finding {
+        self.findings
+            .last_mut()
+            .expect("a finding was just recorded")
+    }
+
+    fn note(&mut self, message: String) {
+        self.say(Line::Plain(format!("    {}", message.dimmed())));
+        self.record(FindingKind::Note, message);
+    }
+
+    fn is_healthy(&self) -> bool {
+        self.status == Status::Healthy
+    }
+
+    /// Tally the findings once the checks are done. An issue counts as fixed
+    /// if a repair follows it (before the next issue) and none of its repairs
+    /// failed; a failure outside any issue, such as a check that couldn't
+    /// run, counts as unfixed on its own.
+    fn finish(&mut self) {
+        // Per issue: (repaired, a repair failed).
+        let mut issues: Vec<(bool, bool)> = Vec::new();
+        let mut stray_failures = 0;
+        for finding in &self.findings {
+            match (finding.kind, issues.last_mut()) {
+                (FindingKind::Issue, _) => issues.push((false, false)),
+                (FindingKind::Fixed, Some(issue)) => issue.0 = true,
+                (FindingKind::Error, Some(issue)) => issue.1 = true,
+                (FindingKind::Error, None) => stray_failures += 1,
+                _ => {}
+            }
+        }
+        let fixed = issues
+            .iter()
+            .filter(|&&issue| issue == (true, false))
+            .count() as u32;
+        self.issues_fixed = fixed;
+        self.issues_unfixed = issues.len() as u32 - fixed + stray_failures;
+        self.status = if self.issues_unfixed > 0 {
+            Status::Unhealthy
+        } else if self.issues_found > 0 {
+            Status::Fixed
+        } else {
+            Status::Healthy
+        };
+
+        self.say(Line::Plain(String::new()));
+        let verdict = if self.is_healthy() {
+            format!("  {} No issues found", Glyph::Check.as_str().green().bold())
+        } else if self.issues_fixed > 0 {
+            format!(
+                "  {} Found {} issue(s), fixed {}",
+                Glyph::Warning.as_str().yellow().bold(),
+                self.issues_found,
+                self.issues_fixed
+            )
+        } else if self.issues_found > 0 {
+            format!(
+                "  {} Found {} issue(s)",
+                Glyph::Warning.as_str().yellow().bold(),
+                self.issues_found
+            )
+        } else {
+            format!("  {} Checks failed", Glyph::Cross.as_str().red().bold())
+        };
+        self.say(Line::Plain(verdict));
+    }
+}
 
 /// Validate a single server's state and fix issues
-fn check_server(name: &str) -> Result<()> {
-    println!("\n{} {}...", "Checking".cyan(), format_server_name(name));
+fn check_server(checkup: &mut Checkup) -> Result<()> {
+    let name = checkup.name.clone();
+    let name = name.as_str();
+    checkup.say(Line::Plain(format!(
+        "\n{} {}...",
+        "Checking".cyan(),
+        format_server_name(name)
+    )));
 
     let state = get_server_state(name)?;
-    let mut issues_found = 0;
-    let mut issues_fixed = 0;
+    checkup.state = Some(state.as_str());
 
     // Check 1: If server is stopped but lockfiles exist
     if state == ServerState::Stopped {
+        checkup.checking("lockfiles");
         let has_server_lock = server_lock_exists(name);
         let has_clients_lock = clients_lock_exists(name);
 
         if has_server_lock || has_clients_lock {
-            issues_found += 1;
-            print_warning(&format!(
-                "  Server is stopped but lockfiles exist (server: {}, clients: {})",
-                has_server_lock, has_clients_lock
-            ));
+            checkup.issue(
+                Severity::Warning,
+                format!(
+                    "Server is stopped but lockfiles exist (server: {}, clients: {})",
+                    has_server_lock, has_clients_lock
+                ),
+            );
+            if read_server_lock(name).is_ok_and(|lock| lock.from_previous_boot()) {
+                checkup.note("Note: the server lockfile is from a previous boot".to_string());
+            }
 
             // Clean up lockfiles
             if has_server_lock {
-                match delete_server_lock(name) {
-                    Ok(_) => {
-                        print_success("    Removed stale server lockfile");
-                    }
-                    Err(e) => print_error(&format!("    Failed to remove server lockfile: {}", e)),
-                }
+                remove_server_lock(checkup, name);
             }
 
             if has_clients_lock {
-                match delete_clients_lock(name) {
-                    Ok(_) => {
-                        print_success("    Removed stale clients lockfile");
-                    }
-                    Err(e) => print_error(&format!("    Failed to remove clients lockfile: {}", e)),
-                }
+                remove_clients_lock(checkup, name);
             }
         } else {
-            println!(
-                "  {} No lockfiles (expected for stopped server)",
-                "✓".green()
-            );
+            checkup.pass("No lockfiles (expected for stopped server)".to_string());
         }
 
-        if issues_found == 0 {
-            println!("  {} Server state is clean", "✓".green());
+        // A leftover heartbeat is meaningless without a server.
+        let _ = delete_heartbeat(name);
+
+        if checkup.issues_found == 0 {
+            checkup.pass("Server state is clean".to_string());
         }
 
         return Ok(());
@@ -63,10 +420,11 @@ fn check_server(name: &str) -> Result<()> {
 
     // Server is running (Active or Grace) - perform deeper checks
     // Read both locks once at the start to minimize lock contention
+    checkup.checking("server_lock");
     let server_lock = match read_server_lock(name) {
         Ok(lock) => lock,
         Err(e) => {
-            print_error(&format!("  Failed to read server lock: {}", e));
+            checkup.failed(format!("Failed to read server lock: {}", e));
             return Ok(());
         }
     };
@@ -84,7 +442,8 @@ fn check_server(name: &str) -> Result<()> {
     // the process is dead (gone or zombie) we only clean up ourselves if there
     // is no live watcher to do it. Deleting locks out from under a live watcher
     // would race its cleanup and could clobber a freshly-restarted instance.
-    let server_liveness = process_liveness_checked(server_lock.pid, server_lock.start_time);
+    checkup.checking("server_process");
+    let server_liveness = server_lock.server_liveness();
     if server_liveness != Liveness::Alive {
         let watcher_alive = sharedserver::core::watcher_alive(&server_lock);
         let descr = match server_liveness {
@@ -92,127 +451,139 @@ fn check_server(name: &str) -> Result<()> {
             _ => "is not running",
         };
 
-        issues_found += 1;
-
         if watcher_alive {
             // Defer to the watcher; it will reap and remove the lockfiles.
-            print_warning(&format!(
-                "  Server process {} {} — watcher is alive, cleanup pending",
-                format_pid(server_lock.pid),
-                descr
-            ));
-            println!(
-                "    {}",
-                "Note: the watcher will reap it and remove the lockfiles shortly".dimmed()
+            checkup.issue(
+                Severity::Warning,
+                format!(
+                    "Server process {} {}; watcher is alive, cleanup pending",
+                    format_pid(server_lock.pid),
+                    descr
+                ),
+            );
+            checkup.note(
+                "Note: the watcher will reap it and remove the lockfiles shortly".to_string(),
             );
         } else {
             // No live watcher to clean up: this state is genuinely stale.
-            print_warning(&format!(
-                "  Server process {} {} and no watcher is running, but lockfile exists",
-                format_pid(server_lock.pid),
-                descr
-            ));
-
-            match delete_server_lock(name) {
-                Ok(_) => {
-                    print_success("    Removed stale server lockfile");
-                    issues_fixed += 1;
-                }
-                Err(e) => print_error(&format!("    Failed to remove server lockfile: {}", e)),
-            }
+            checkup.issue(
+                Severity::Warning,
+                format!(
+                    "Server process {} {} and no watcher is running, but lockfile exists",
+                    format_pid(server_lock.pid),
+                    descr
+                ),
+            );
 
-            match delete_clients_lock(name) {
-                Ok(_) => {
-                    print_success("    Removed stale clients lockfile");
-                    issues_fixed += 1;
-                }
-                Err(e) => print_error(&format!("    Failed to remove clients lockfile: {}", e)),
-            }
+            remove_server_lock(checkup, name);
+            remove_clients_lock(checkup, name);
+            let _ = delete_heartbeat(name);
+            // The remaining checks are of the lockfiles just removed; the
+            // missing watcher is part of the same stale state, now fixed.
+            return Ok(());
         }
     } else {
-        println!(
-            "  {} Server process {} is alive",
-            "✓".green(),
+        checkup.pass(format!(
+            "Server process {} is alive",
             format_pid(server_lock.pid)
-        );
+        ));
     }
 
     // Check 3: Validate watcher process if it exists
     if let Some(watcher_pid) = server_lock.watcher_pid {
+        checkup.checking("watcher");
         if !sharedserver::core::watcher_alive(&server_lock) {
-            issues_found += 1;
-            print_warning(&format!(
-                "  Watcher process {} is not running",
-                format_pid(watcher_pid)
-            ));
+            checkup.issue(
+                Severity::Error,
+                format!("Watcher process {} is not running", format_pid(watcher_pid)),
+            );
             // Note: We don't fix this - watcher may have exited normally
+        } else if let Some(age) = heartbeat_age(name).filter(|age| is_stale(*age)) {
+            // Alive but not looping: it won't reap, clean up, or end grace.
+            checkup.issue(
+                Severity::Error,
+                format!(
+                    "Watcher process {} is alive but hasn't heartbeat for {}",
+                    format_pid(watcher_pid),
+                    format_duration(age)
+                ),
+            );
+            checkup
+                .note("Note: the watcher appears wedged; 'admin kill' will clean up".to_string());
         } else {
-            println!(
-                "  {} Watcher process {} is alive",
-                "✓".green(),
+            checkup.pass(format!(
+                "Watcher process {} is alive",
                 format_pid(watcher_pid)
-            );
+            ));
         }
     }
 
     // Check 4: Validate clients if server is Active
     if state == ServerState::Active {
+        checkup.checking("clients");
         if clients_lock_snapshot.is_none() {
-            issues_found += 1;
-            print_warning("  Server is Active but no clients lockfile exists");
+            checkup.issue(
+                Severity::Error,
+                "Server is Active but no clients lockfile exists".to_string(),
+            );
         } else if let Some(clients_lock) = clients_lock_snapshot {
             let mut dead_clients = Vec::new();
-
-            // Check each client PID
-            for (pid, _info) in &clients_lock.clients {
-                if !is_process_alive(*pid) {
+            let mut recycled_clients = Vec::new();
+
+            // Check each client PID, and that it still belongs to the process
+            // that attached
+            for (pid, client) in &clients_lock.clients {
+                if client.pid_reused(*pid) {
+                    recycled_clients.push(*pid);
+                } else if !client.is_alive(*pid) {
                     dead_clients.push(*pid);
                 }
             }
+            let pid_list = |pids: &[i32]| {
+                pids.iter()
+                    .map(|p| format_pid(*p).to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            };
 
             if !dead_clients.is_empty() {
-                issues_found += 1;
-                print_warning(&format!(
-                    "  Found {} dead client(s): {}",
-                    dead_clients.len(),
-                    dead_clients
-                        .iter()
-                        .map(|p| format_pid(*p).to_string())
-                        .collect::<Vec<_>>()
-                        .join(", ")
-                ));
-                println!(
-                    "    {}",
-                    "Note: Dead clients should be removed via 'admin decref' or will timeout naturally".dimmed()
+                checkup.issue(
+                    Severity::Warning,
+                    format!(
+                        "Found {} dead client(s): {}",
+                        dead_clients.len(),
+                        pid_list(&dead_clients)
+                    ),
                 );
-            } else if !clients_lock.clients.is_empty() {
-                println!(
-                    "  {} All {} client(s) are alive",
-                    "✓".green(),
-                    clients_lock.clients.len()
+            }
+            if !recycled_clients.is_empty() {
+                checkup.issue(
+                    Severity::Warning,
+                    format!(
+                        "Found {} client(s) whose PID now belongs to a different process: {}",
+                        recycled_clients.len(),
+                        pid_list(&recycled_clients)
+                    ),
                 );
             }
-
-            // Validate refcount matches client count
-            if clients_lock.refcount != clients_lock.clients.len() as u32 {
-                issues_found += 1;
-                print_warning(&format!(
-                    "  Refcount mismatch: refcount={}, actual clients={}",
-                    clients_lock.refcount,
+            if !dead_clients.is_empty() || !recycled_clients.is_empty() {
+                checkup.note(
+                    "Note: Dead clients should be removed via 'admin decref' or will timeout naturally"
+                        .to_string(),
+                );
+            } else if !clients_lock.clients.is_empty() {
+                checkup.pass(format!(
+                    "All {} client(s) are alive",
                     clients_lock.clients.len()
                 ));
-            } else {
-                println!(
-                    "  {} Refcount ({}) matches client count",
-                    "✓".green(),
-                    clients_lock.refcount
-                );
             }
 
             // Check if server is Active with no clients
-            if clients_lock.refcount == 0 && clients_lock.clients.is_empty() {
-                issues_found += 1;
-                print_warning("  Server is Active but has no clients (should be in Grace)");
+            if clients_lock.clients.is_empty() {
+                checkup.issue(
+                    Severity::Warning,
+                    "Server is Active but has no clients (should be in Grace)".to_string(),
+                );
             }
         }
     }
@@ -220,91 +591,331 @@ fn check_server(name: &str) -> Result<()> {
     // Check 5: If server is in Grace, ensure no clients
     // Note: We already checked for clients.json existence earlier, so this should be rare
     if state == ServerState::Grace {
+        checkup.checking("clients");
         // Grace state means clients.json shouldn't exist, but double-check
         if clients_lock_exists(name) {
             if let Ok(clients_lock) = read_clients_lock(name) {
-                if clients_lock.refcount > 0 || !clients_lock.clients.is_empty() {
-                    issues_found += 1;
-                    print_warning(&format!(
-                        "  Server in Grace period but has clients (refcount={}, clients={})",
-                        clients_lock.refcount,
-                        clients_lock.clients.len()
-                    ));
+                if clients_lock.refcount() > 0 {
+                    checkup.issue(
+                        Severity::Warning,
+                        format!(
+                            "Server in Grace period but has clients (refcount={})",
+                            clients_lock.refcount()
+                        ),
+                    );
                 }
             }
         } else {
-            println!("  {} No clients (expected for Grace state)", "✓".green());
+            checkup.pass("No clients (expected for Grace state)".to_string());
         }
     }
 
-    // Summary
-    println!();
-    if issues_found == 0 {
-        println!("  {} No issues found", "✓".green().bold());
-    } else if issues_fixed > 0 {
-        println!(
-            "  {} Found {} issue(s), fixed {}",
-            "⚠".yellow().bold(),
-            issues_found,
-            issues_fixed
+    // Check 6: No other running server claims the same address. Only one of
+    // them can really be listening there; doctor can't tell which clients
+    // meant which, so it leaves the choice to the user.
+    let addresses = server_lock.claimed_addresses();
+    if !addresses.is_empty() {
+        checkup.checking("address");
+        let mut shared = false;
+        for address in &addresses {
+            if let Some(other) = claimed_by(address, name) {
+                shared = true;
+                checkup.issue(
+                    Severity::Warning,
+                    format!(
+                        "Address {} is also claimed by running server '{}'",
+                        address, other
+                    ),
+                );
+            }
+        }
+        if !shared {
+            checkup.pass(format!("No other server uses {}", addresses.join(", ")));
+        }
+    }
+
+    Ok(())
+}
+
+fn remove_server_lock(checkup: &mut Checkup, name: &str) {
+    checkup.repair(
+        "remove_server_lock",
+        delete_server_lock(name),
+        "Removed stale server lockfile",
+        "remove server lockfile",
+    );
+}
+
+fn remove_clients_lock(checkup: &mut Checkup, name: &str) {
+    checkup.repair(
+        "remove_clients_lock",
+        delete_clients_lock(name),
+        "Removed stale clients lockfile",
+        "remove clients lockfile",
+    );
+}
+
+/// Repair a running server's lockfiles: put back a corrupt server lock from
+/// its `.bak`, and rebuild the client set by replaying the invocation log.
+fn restore_server(checkup: &mut Checkup) -> Result<()> {
+    let name = checkup.name.clone();
+    let name = name.as_str();
+    checkup.say(Line::Plain(format!(
+        "\n{} {}...",
+        "Restoring".cyan(),
+        format_server_name(name)
+    )));
+    checkup.checking("restore");
+
+    // Reads already fall back to the backup; writing the result back replaces
+    // a corrupt primary.
+    let server_path = server_lockfile_path(name)?;
+    let server_lock = read_server_lock(name)
+        .with_context(|| format!("No usable server lock (or backup) for '{}'", name))?;
+    if with_shared_lock(&server_path, read_json::<ServerLock>).is_err() {
+        write_server_lock(name, &server_lock)?;
+        checkup.say(Line::Success(
+            "  Restored server lock from its backup".to_string(),
+        ));
+        checkup.record(
+            FindingKind::Fixed,
+            "Restored server lock from its backup".to_string(),
         );
-    } else {
-        println!("  {} Found {} issue(s)", "⚠".yellow().bold(), issues_found);
+        checkup.last().action = Some("restore_server_lock");
     }
 
+    if server_lock.server_liveness() != Liveness::Alive {
+        checkup.say(Line::Warning(
+            "  Server is not running; nothing to restore".to_string(),
+        ));
+        checkup.record(
+            FindingKind::Note,
+            "Server is not running; nothing to restore".to_string(),
+        );
+        return Ok(());
+    }
+
+    let Some(replayed) = replay_clients(name)? else {
+        bail!("Invocation log for '{}' has no start to replay from", name);
+    };
+    let clients: HashMap<_, _> = replayed
+        .into_iter()
+        .filter(|(pid, client)| client.is_alive(*pid))
+        .collect();
+
+    // Keeps the shutdown fence if the current lock (or its backup) is readable.
+    let restored = update_clients_lock(name, |lock| {
+        lock.clients = clients;
+        Ok(LockUpdate::Write(lock.clone()))
+    })?;
+
+    let mut pids: Vec<_> = restored.clients.keys().copied().collect();
+    pids.sort_unstable();
+    checkup.say(Line::Success(format!(
+        "  Rebuilt clients from the invocation log (refcount: {})",
+        restored.refcount()
+    )));
+    for pid in &pids {
+        checkup.say(Line::Plain(format!("    {}", format_pid(*pid))));
+    }
+    checkup.record(
+        FindingKind::Fixed,
+        format!(
+            "Rebuilt clients from the invocation log (refcount: {}, PIDs: {:?})",
+            restored.refcount(),
+            pids
+        ),
+    );
+    checkup.last().action = Some("rebuild_clients");
     Ok(())
 }
 
 /// Execute doctor command for one or all servers
-pub fn execute(server_name: Option<String>) -> Result<()> {
+pub fn execute(
+    server_name: Option<String>,
+    filter: ServerFilter,
+    restore: bool,
+    jobs: usize,
+    format: OutputFormat,
+) -> Result<()> {
+    let print = format == OutputFormat::Table;
+    let mut report = DoctorReport::new();
+
     if let Some(name) = server_name {
+        let mut checkup = Checkup::new(&name, print);
+        if restore {
+            restore_server(&mut checkup)?;
+        }
         // Check single server
-        check_server(&name)?;
+        check_server(&mut checkup)?;
+        checkup.finish();
+        report.servers.push(checkup);
     } else {
         // Check all servers
-        println!("{}", "Running health check on all servers...".bold());
+        if print {
+            let scope = if filter.is_empty() {
+                "all servers"
+            } else {
+                "matching servers"
+            };
+            println!("{}", format!("Running health check on {}...", scope).bold());
+        }
 
         let lockdir = sharedserver::core::lockfile::lockfile_dir()?;
 
         if !lockdir.exists() {
-            println!("{}", "No servers found".dimmed());
-            return Ok(());
+            if print {
+                println!("{}", "No servers found".dimmed());
+            }
+            return print_report(format, &report);
         }
 
-        let entries = fs::read_dir(&lockdir)?;
-        let mut server_names = std::collections::BTreeSet::new();
-
-        // Discover by EITHER lockfile, so an orphaned `<name>.clients.json` with
-        // no matching `<name>.server.json` (e.g. from a partial teardown) is
-        // still found and cleaned up rather than lingering invisibly.
-        for entry in entries {
-            let entry = entry?;
-            let filename = entry.file_name();
-            let filename = filename.to_string_lossy();
-
-            if let Some(name) = filename
-                .strip_suffix(".server.json")
-                .or_else(|| filename.strip_suffix(".clients.json"))
-            {
-                server_names.insert(name.to_string());
+        // Logs of servers long gone would otherwise pile up forever. A
+        // filtered sweep leaves servers outside the filter alone.
+        if filter.is_empty() {
+            match sharedserver::core::log::prune_logs(false, &filter) {
+                Ok(pruned) if pruned.is_empty() => {}
+                Ok(pruned) => {
+                    if print {
+                        println!("{}", "Pruning logs...".cyan());
+                        super::prune::print_pruned(&pruned, false, "  ");
+                    }
+                    report.pruned = pruned;
+                }
+                Err(e) => print_error(&format!("  Failed to prune logs: {:#}", e)),
             }
         }
 
+        // Discover by ANY per-server file, so an orphaned `clients.json` (or
+        // heartbeat) with no matching `server.json` (e.g. from a partial
+        // teardown) is still found and cleaned up rather than lingering
+        // invisibly.
+        let server_names: Vec<String> =
+            servers_with(&["server.json", "clients.json", "watcher.heartbeat"])?
+                .into_iter()
+                .filter(|name| filter.matches_name(name))
+                .filter(|name| {
+                    filter.states.is_empty()
+                        || get_server_state(name).is_ok_and(|state| filter.matches_state(state))
+                })
+                .filter(|name| filter.tags.is_empty() || filter.matches_tags(&server_tags(name)))
+                .collect();
+
         if server_names.is_empty() {
-            println!("{}", "No servers found".dimmed());
-            return Ok(());
+            if print {
+                println!("{}", "No servers found".dimmed());
+            }
+            return print_report(format, &report);
         }
 
         // One bad server must not abort the whole sweep — doctor exists to clean
-        // up messes, so keep going and report any per-server failure.
-        for name in server_names {
-            if let Err(e) = check_server(&name) {
-                print_error(&format!("  Failed to check '{}': {:#}", name, e));
+        // up messes, so keep going and report any per-server failure. Servers
+        // are checked `jobs` at a time, each one's output held until those
+        // before it have printed.
+        let parallel = jobs > 1 && server_names.len() > 1;
+        pool::for_each_ordered(
+            &server_names,
+            jobs,
+            |name| {
+                let mut checkup = Checkup::new(name, print);
+                if parallel {
+                    checkup.held = Some(Vec::new());
+                }
+                if let Err(e) = check_server(&mut checkup) {
+                    checkup.checking("doctor");
+                    checkup.failed(format!("Failed to check: {:#}", e));
+                }
+                checkup.finish();
+                checkup
+            },
+            |mut checkup| {
+                checkup.flush();
+                report.servers.push(checkup);
+            },
+        );
+
+        // A filtered sweep only saw some servers, so it can't rebuild the
+        // registry wholesale; bring just the ones it checked up to date.
+        if !filter.is_empty() {
+            for name in &server_names {
+                registry::refresh(name);
+            }
+        } else {
+            // Re-derive the list index from what survived the sweep,
+            // dropping entries for servers that are gone and adding any it
+            // was missing.
+            match registry::rebuild(server_names.iter().map(String::as_str)) {
+                Ok(count) => {
+                    if print {
+                        println!(
+                            "\n  {} Rebuilt server registry ({} server(s))",
+                            Glyph::Check.as_str().green(),
+                            count
+                        );
+                    }
+                    report.registry_entries = Some(count);
+                }
+                Err(e) => print_error(&format!("  Failed to rebuild server registry: {:#}", e)),
             }
         }
 
-        println!("\n{}", "Health check complete".bold());
+        if print {
+            println!("\n{}", "Health check complete".bold());
+        }
     }
 
+    report.summary = Summary::new(&report.servers);
+    if print && report.servers.len() > 1 {
+        report.summary.print();
+    }
+    print_report(format, &report)?;
+
+    // So a cron job or CI step can alert on what doctor couldn't repair.
+    let unfixed = report.summary.issues_unfixed;
+    if unfixed > 0 {
+        return Err(ErrorKind::IssuesRemain.error(format!(
+            "{} issue(s) remain that doctor could not fix",
+            unfixed
+        )));
+    }
     Ok(())
 }
+
+/// Table mode prints as it goes, so there's nothing left to show at the end.
+impl Report for DoctorReport {
+    fn print_table(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_finish_tallies_issues() {
+        let mut checkup = Checkup::new("srv", false);
+        checkup.issue(Severity::Warning, "stale lockfiles".to_string());
+        checkup.fixed("removed server lockfile".to_string());
+        checkup.fixed("removed clients lockfile".to_string());
+        checkup.issue(Severity::Warning, "watcher not running".to_string());
+        checkup.issue(Severity::Warning, "stale lockfile".to_string());
+        checkup.fixed("removed server lockfile".to_string());
+        checkup.failed("failed to remove clients lockfile".to_string());
+        checkup.finish();
+        assert_eq!(checkup.issues_found, 3);
+        assert_eq!(checkup.issues_fixed, 1);
+        assert_eq!(checkup.issues_unfixed, 2);
+        assert!(!checkup.is_healthy());
+
+        // A check that couldn't run is unfixed without being an issue.
+        let mut checkup = Checkup::new("srv", false);
+        checkup.failed("Failed to check: permission denied".to_string());
+        checkup.finish();
+        assert_eq!((checkup.issues_found, checkup.issues_unfixed), (0, 1));
+
+        let summary = Summary::new(&[checkup, Checkup::new("ok", false)]);
+        assert_eq!((summary.servers_checked, summary.healthy), (2, 1));
+        assert_eq!(summary.issues_unfixed, 1);
+    }
+}
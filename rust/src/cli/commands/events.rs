@@ -0,0 +1,54 @@
+use anyhow::Result;
+use colored::*;
+use sharedserver::core::events::{subscribe, StateEvent};
+
+use crate::output::{format_pid, format_server_name, format_server_state, Glyph, OutputFormat};
+
+/// Print `name`'s state changes as they happen, until interrupted or (with
+/// `count`) after that many events. JSON is one object per line; YAML is one
+/// document per event.
+pub fn execute(name: &str, format: OutputFormat, count: Option<usize>) -> Result<()> {
+    let subscription = subscribe(name)?;
+    if format == OutputFormat::Table {
+        println!(
+            "{} {} ({}), waiting for changes...",
+            "Watching".cyan(),
+            format_server_name(name),
+            format_server_state(&subscription.state())
+        );
+    }
+
+    for event in subscription.take(count.unwrap_or(usize::MAX)) {
+        let now = chrono::Local::now();
+        if format != OutputFormat::Table {
+            let mut record = serde_json::to_value(&event)?;
+            record["name"] = name.into();
+            record["timestamp"] = chrono::Utc::now().to_rfc3339().into();
+            if format == OutputFormat::Yaml {
+                print!("---\n{}", serde_yaml::to_string(&record)?);
+            } else {
+                println!("{}", serde_json::to_string(&record)?);
+            }
+            continue;
+        }
+
+        let description = match event {
+            StateEvent::State { from, to } => format!(
+                "{} {} {}",
+                format_server_state(&from),
+                Glyph::Arrow,
+                format_server_state(&to)
+            ),
+            StateEvent::ClientAttached { pid } => format!("client {} attached", format_pid(pid)),
+            StateEvent::ClientDetached { pid } => format!("client {} detached", format_pid(pid)),
+        };
+        println!(
+            "{} {}: {}",
+            now.format("%H:%M:%S").to_string().dimmed(),
+            format_server_name(name),
+            description
+        );
+    }
+
+    Ok(())
+}
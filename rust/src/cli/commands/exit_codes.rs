@@ -0,0 +1,84 @@
+use anyhow::Result;
+use colored::*;
+use serde::Serialize;
+use sharedserver::core::{ErrorKind, ServerState};
+
+use crate::output::{print_report, OutputFormat, Report, Table};
+
+use super::check::EXIT_UNHEALTHY;
+
+/// Print the exit-code contract (`--print-exit-codes`).
+pub fn execute(format: OutputFormat) -> Result<()> {
+    print_report(format, &exit_codes())
+}
+
+#[derive(Serialize)]
+#[serde(transparent)]
+struct ExitCodes(Vec<ExitCode>);
+
+#[derive(Serialize)]
+struct ExitCode {
+    code: i32,
+    name: &'static str,
+    /// `all`, or the one command that uses the code this way.
+    scope: &'static str,
+    description: &'static str,
+}
+
+fn exit_codes() -> ExitCodes {
+    let mut codes = vec![
+        ExitCode {
+            code: 0,
+            name: "ok",
+            scope: "all",
+            description: "The command succeeded",
+        },
+        ExitCode {
+            code: ErrorKind::GENERAL_EXIT_CODE,
+            name: "error",
+            scope: "all",
+            description: "Any other error (the message says what)",
+        },
+    ];
+    codes.extend(ErrorKind::ALL.iter().map(|kind| ExitCode {
+        code: kind.exit_code(),
+        name: kind.as_str(),
+        scope: "all",
+        description: kind.description(),
+    }));
+    let check_states = [
+        ServerState::Active,
+        ServerState::Grace,
+        ServerState::Stopped,
+        ServerState::Defunct,
+    ];
+    codes.extend(check_states.iter().map(|state| ExitCode {
+        code: state.exit_code(),
+        name: state.as_str(),
+        scope: "check",
+        description: "The server is in this state",
+    }));
+    codes.push(ExitCode {
+        code: EXIT_UNHEALTHY,
+        name: "unhealthy",
+        scope: "check",
+        description: "The server is running but its health probe is failing",
+    });
+    ExitCodes(codes)
+}
+
+impl Report for ExitCodes {
+    fn print_table(&self) -> Result<()> {
+        let mut table = Table::new(&["CODE", "NAME", "SCOPE", "DESCRIPTION"]);
+        for code in &self.0 {
+            table.row(vec![
+                code.code.to_string().bold(),
+                code.name.normal(),
+                code.scope.dimmed(),
+                code.description.normal(),
+            ]);
+        }
+        table.print();
+        Ok(())
+    }
+}
@@ -0,0 +1,377 @@
+use anyhow::{Context, Result};
+use sharedserver::core::grace::GracePeriod;
+use sharedserver::core::heartbeat::HEARTBEAT_STALE_AFTER;
+use sharedserver::core::restart::RestartPolicy;
+use sharedserver::core::{get_server_state, read_server_lock, ErrorKind, ServerState};
+use std::path::PathBuf;
+
+use super::r#use::{apply_server, load_config};
+use super::start::StartOptions;
+
+/// Flags for `export`, overriding what is exported.
+pub struct ExportOptions {
+    pub grace_period: Option<String>,
+    pub env_vars: Vec<String>,
+    pub log_file: Option<String>,
+    pub restart: Option<String>,
+    /// Describe `[servers.<name>]` in the config, not the running server.
+    pub defined: bool,
+}
+
+/// What a generated service runs: enough to re-create the server with `use`.
+struct ServiceSpec {
+    name: String,
+    command: Vec<String>,
+    /// `KEY=VALUE` assignments.
+    env: Vec<String>,
+    cwd: Option<PathBuf>,
+    grace_period: String,
+    log_file: Option<String>,
+    restart: RestartPolicy,
+}
+
+impl ServiceSpec {
+    /// The server described by `command` and `opts`, or, with no command,
+    /// the running server `name` as it was started, or with `--defined`, as
+    /// the config defines it (with `opts` applied).
+    fn resolve(name: &str, opts: &ExportOptions, command: &[String]) -> Result<Self> {
+        let mut spec = if opts.defined {
+            Self::defined(name, command)?
+        } else if command.is_empty() {
+            if get_server_state(name)? == ServerState::Stopped {
+                return Err(ErrorKind::NotRunning.error(format!(
+                    "Server '{}' is not running; give its command after --",
+                    name
+                )));
+            }
+            let lock = read_server_lock(name)?;
+            Self {
+                name: name.to_string(),
+                command: lock.command,
+                env: lock.env,
+                cwd: lock.cwd,
+                grace_period: lock.grace_period,
+                log_file: lock.log_file,
+                restart: lock.restart,
+            }
+        } else {
+            Self {
+                name: name.to_string(),
+                command: command.to_vec(),
+                env: Vec::new(),
+                cwd: std::env::current_dir().ok(),
+                grace_period: "5m".to_string(),
+                log_file: None,
+                restart: RestartPolicy::Never,
+            }
+        };
+
+        let invalid = |e: anyhow::Error| ErrorKind::InvalidArgs.wrap(e, "Invalid export options");
+        if let Some(grace_period) = &opts.grace_period {
+            grace_period.parse::<GracePeriod>().map_err(invalid)?;
+            spec.grace_period = grace_period.clone();
+        }
+        if let Some(restart) = &opts.restart {
+            spec.restart = restart.parse().map_err(invalid)?;
+        }
+        if let Some(var) = opts.env_vars.iter().find(|var| !var.contains('=')) {
+            return Err(ErrorKind::InvalidArgs
+                .error(format!("Invalid --env '{}': expected KEY=VALUE", var)));
+        }
+        spec.env.extend(opts.env_vars.iter().cloned());
+        if opts.log_file.is_some() {
+            spec.log_file = opts.log_file.clone();
+        }
+        Ok(spec)
+    }
+
+    /// `name` as `[servers.<name>]` in the config defines it, filled in as
+    /// `use <name>` would; a `command` replaces the config's.
+    fn defined(name: &str, command: &[String]) -> Result<Self> {
+        let config = load_config()?;
+        if config.server(name).is_none() {
+            return Err(ErrorKind::InvalidArgs
+                .error(format!("The config doesn't define a server '{}'", name)));
+        }
+        let mut opts = StartOptions::default();
+        let mut command = command.to_vec();
+        // This moves to the server's `cwd`, which the service should run in.
+        apply_server(name, &config, None, None, &mut opts, &mut command)?;
+        if command.is_empty() {
+            return Err(ErrorKind::InvalidArgs.error(format!(
+                "The config gives '{}' no command; give it after --",
+                name
+            )));
+        }
+        Ok(Self {
+            name: name.to_string(),
+            command,
+            env: opts.env_vars,
+            cwd: std::env::current_dir().ok(),
+            grace_period: opts.grace_period,
+            log_file: opts.log_file,
+            restart: opts.restart.parse().map_err(|e: anyhow::Error| {
+                ErrorKind::InvalidArgs.wrap(e, format!("Invalid restart policy for '{}'", name))
+            })?,
+        })
+    }
+
+    /// The `sharedserver use …` invocation that starts the server. Its client
+    /// is the service manager, so the server stays up for as long as the
+    /// service does.
+    fn use_args(&self, exe: &str) -> Vec<String> {
+        let mut args = vec![
+            exe.to_string(),
+            "use".to_string(),
+            "--grace-period".to_string(),
+            self.grace_period.clone(),
+        ];
+        if let Some(log_file) = &self.log_file {
+            args.extend(["--log-file".to_string(), log_file.clone()]);
+        }
+        // The watcher relaunches a crashed server itself, exiting cleanly;
+        // the service manager's restart setting covers the watcher.
+        if self.restart != RestartPolicy::Never {
+            args.extend(["--restart".to_string(), self.restart.as_str().to_string()]);
+        }
+        args.push(self.name.clone());
+        args.push("--".to_string());
+        args.extend(self.command.iter().cloned());
+        args
+    }
+}
+
+/// The path of this binary, for the service to run.
+fn current_exe() -> Result<String> {
+    let exe = std::env::current_exe().context("Failed to locate the sharedserver binary")?;
+    Ok(exe.display().to_string())
+}
+
+/// `export systemd`: print a user-level systemd service unit that runs the
+/// server under `use` and stops it with `admin stop`.
+pub fn systemd(name: &str, opts: &ExportOptions, command: &[String]) -> Result<()> {
+    let spec = ServiceSpec::resolve(name, opts, command)?;
+    print!("{}", systemd_unit(&spec, &current_exe()?));
+    Ok(())
+}
+
+fn systemd_unit(spec: &ServiceSpec, exe: &str) -> String {
+    let exec = |args: &[String]| {
+        args.iter()
+            .map(|arg| systemd_quote(&arg.replace('$', "$$")))
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    let mut unit = format!(
+        "# Generated by `sharedserver export systemd {name}`. Install with:\n\
+         #   cp this ~/.config/systemd/user/sharedserver-{name}.service\n\
+         #   systemctl --user daemon-reload\n\
+         #   systemctl --user enable --now sharedserver-{name}\n\
+         [Unit]\n\
+         Description=sharedserver: {name}\n\
+         \n\
+         [Service]\n\
+         # The watcher reports READY=1 and takes over as the main process.\n\
+         Type=notify\n\
+         NotifyAccess=all\n\
+         WatchdogSec={watchdog}\n",
+        name = spec.name,
+        watchdog = HEARTBEAT_STALE_AFTER.as_secs(),
+    );
+    if let Ok(lockdir) = std::env::var("SHAREDSERVER_LOCKDIR") {
+        unit.push_str(&format!(
+            "Environment={}\n",
+            systemd_quote(&format!("SHAREDSERVER_LOCKDIR={}", lockdir))
+        ));
+    }
+    for var in &spec.env {
+        unit.push_str(&format!("Environment={}\n", systemd_quote(var)));
+    }
+    if let Some(cwd) = &spec.cwd {
+        unit.push_str(&format!(
+            "WorkingDirectory={}\n",
+            cwd.display().to_string().replace('%', "%%")
+        ));
+    }
+    unit.push_str(&format!("ExecStart={}\n", exec(&spec.use_args(exe))));
+    // `-`: the server may already be gone (e.g. stopped by hand).
+    unit.push_str(&format!(
+        "ExecStop=-{}\n",
+        exec(&[
+            exe.to_string(),
+            "admin".to_string(),
+            "stop".to_string(),
+            spec.name.clone()
+        ])
+    ));
+    unit.push_str(&format!(
+        "Restart={}\n\n[Install]\nWantedBy=default.target\n",
+        match spec.restart {
+            RestartPolicy::Never => "no",
+            RestartPolicy::OnFailure => "on-failure",
+            RestartPolicy::Always => "always",
+        }
+    ));
+    unit
+}
+
+/// `export launchd`: print a launchd agent plist that starts the server with
+/// `use` at login.
+pub fn launchd(name: &str, opts: &ExportOptions, command: &[String]) -> Result<()> {
+    let spec = ServiceSpec::resolve(name, opts, command)?;
+    print!("{}", launchd_plist(&spec, &current_exe()?));
+    Ok(())
+}
+
+fn launchd_plist(spec: &ServiceSpec, exe: &str) -> String {
+    let label = format!("sharedserver.{}", spec.name);
+    let mut plist = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \
+         \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n\
+         <!-- Generated by sharedserver export launchd. Install with:\n\
+         \x20    cp this ~/Library/LaunchAgents/{label}.plist\n\
+         \x20    launchctl bootstrap gui/$(id -u) ~/Library/LaunchAgents/{label}.plist -->\n\
+         <plist version=\"1.0\">\n\
+         <dict>\n\
+         \x20 <key>Label</key>\n\
+         \x20 <string>{label}</string>\n\
+         \x20 <key>ProgramArguments</key>\n\
+         \x20 <array>\n",
+        label = xml_escape(&label),
+    );
+    for arg in spec.use_args(exe) {
+        plist.push_str(&format!("    <string>{}</string>\n", xml_escape(&arg)));
+    }
+    plist.push_str("  </array>\n");
+
+    // launchd starts jobs with a minimal PATH; keep the one the server would
+    // have had from a shell.
+    let mut env: Vec<(String, String)> = std::env::var("PATH")
+        .map(|path| vec![("PATH".to_string(), path)])
+        .unwrap_or_default();
+    if let Ok(lockdir) = std::env::var("SHAREDSERVER_LOCKDIR") {
+        env.push(("SHAREDSERVER_LOCKDIR".to_string(), lockdir));
+    }
+    for var in &spec.env {
+        if let Some((key, value)) = var.split_once('=') {
+            env.retain(|(k, _)| k != key);
+            env.push((key.to_string(), value.to_string()));
+        }
+    }
+    if !env.is_empty() {
+        plist.push_str("  <key>EnvironmentVariables</key>\n  <dict>\n");
+        for (key, value) in &env {
+            plist.push_str(&format!(
+                "    <key>{}</key>\n    <string>{}</string>\n",
+                xml_escape(key),
+                xml_escape(value)
+            ));
+        }
+        plist.push_str("  </dict>\n");
+    }
+    if let Some(cwd) = &spec.cwd {
+        plist.push_str(&format!(
+            "  <key>WorkingDirectory</key>\n  <string>{}</string>\n",
+            xml_escape(&cwd.display().to_string())
+        ));
+    }
+    // `use` returns once the watcher is up, and the watcher (in a session of
+    // its own) supervises from there: keep it when the job exits, and rerun
+    // the job only if the start itself failed.
+    plist.push_str(&format!(
+        "  <key>RunAtLoad</key>\n  <true/>\n\
+         \x20 <key>AbandonProcessGroup</key>\n  <true/>\n\
+         \x20 <key>KeepAlive</key>\n{}\
+         </dict>\n\
+         </plist>\n",
+        if spec.restart == RestartPolicy::Never {
+            "  <false/>\n".to_string()
+        } else {
+            "  <dict>\n    <key>SuccessfulExit</key>\n    <false/>\n  </dict>\n".to_string()
+        }
+    ));
+    plist
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// `arg` as one word of a systemd command line or assignment: `%` escaped
+/// (specifiers), and quoted unless it is plainly safe.
+fn systemd_quote(arg: &str) -> String {
+    let arg = arg.replace('%', "%%");
+    let plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=@+,$".contains(c));
+    if plain {
+        arg
+    } else {
+        format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec() -> ServiceSpec {
+        ServiceSpec {
+            name: "db".to_string(),
+            command: vec![
+                "postgres".to_string(),
+                "-D".to_string(),
+                "my data".to_string(),
+            ],
+            env: vec!["PGPORT=5433".to_string()],
+            cwd: Some(PathBuf::from("/srv/db")),
+            grace_period: "10m".to_string(),
+            log_file: None,
+            restart: RestartPolicy::OnFailure,
+        }
+    }
+
+    #[test]
+    fn test_launchd_plist() {
+        let plist = launchd_plist(&spec(), "/bin/sharedserver");
+        assert!(plist.contains("<string>sharedserver.db</string>"));
+        assert!(plist.contains(
+            "    <string>use</string>\n    <string>--grace-period</string>\n    <string>10m</string>\n"
+        ));
+        assert!(plist.contains("    <string>my data</string>\n"));
+        assert!(plist.contains("    <key>PGPORT</key>\n    <string>5433</string>\n"));
+        assert!(plist.contains("  <key>WorkingDirectory</key>\n  <string>/srv/db</string>\n"));
+        assert!(plist.contains("<key>SuccessfulExit</key>"));
+        assert!(plist.ends_with("</dict>\n</plist>\n"));
+        assert_eq!(xml_escape("a<b & \"c\""), "a&lt;b &amp; &quot;c&quot;");
+    }
+
+    #[test]
+    fn test_systemd_quote() {
+        assert_eq!(systemd_quote("/usr/bin/env"), "/usr/bin/env");
+        assert_eq!(systemd_quote("my data"), "\"my data\"");
+        assert_eq!(systemd_quote("say \"hi\""), "\"say \\\"hi\\\"\"");
+        assert_eq!(systemd_quote("100%"), "\"100%%\"");
+        assert_eq!(systemd_quote(""), "\"\"");
+    }
+
+    #[test]
+    fn test_systemd_unit() {
+        let unit = systemd_unit(&spec(), "/bin/sharedserver");
+        assert!(unit.contains("Type=notify\n"));
+        assert!(unit.contains("Environment=PGPORT=5433\n"));
+        assert!(unit.contains("WorkingDirectory=/srv/db\n"));
+        assert!(unit.contains(
+            "ExecStart=/bin/sharedserver use --grace-period 10m --restart on-failure db -- \
+             postgres -D \"my data\"\n"
+        ));
+        assert!(unit.contains("ExecStop=-/bin/sharedserver admin stop db\n"));
+        assert!(unit.contains("Restart=on-failure\n"));
+        assert!(unit.ends_with("WantedBy=default.target\n"));
+    }
+}
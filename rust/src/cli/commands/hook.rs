@@ -0,0 +1,193 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use sharedserver::core::lockfile::servers_with;
+use sharedserver::core::{process_name, read_clients_lock};
+
+use crate::output::print_info;
+
+/// A shell `hook` can print code for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum HookShell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+/// The variable the hook sets to the PID of the shell it was installed in,
+/// so `use` knows not to suggest it there again. Exported, so a shell
+/// started from a hooked one sees its parent's PID, not its own.
+const HOOK_VAR: &str = "SHAREDSERVER_HOOK";
+
+// `@EXE@` is replaced by the quoted path of this binary, so the hook keeps
+// working if PATH changes before the shell exits. Each script guards against
+// being sourced twice in one shell.
+
+const BASH: &str = r#"# sharedserver: release this shell's server references when it exits.
+if [ -z "${__sharedserver_hooked:-}" ]; then
+    __sharedserver_hooked=1
+    export SHAREDSERVER_HOOK=$$
+    __sharedserver_detach() {
+        command @EXE@ unuse --all --pid "$$" --quiet >/dev/null 2>&1
+    }
+    # Keep any EXIT trap already set, running it after ours.
+    __sharedserver_prior_trap() { __sharedserver_prior_exit=${3:-}; }
+    eval "__sharedserver_prior_trap $(trap -p EXIT)"
+    unset -f __sharedserver_prior_trap
+    trap '__sharedserver_detach; eval "$__sharedserver_prior_exit"' EXIT
+fi
+"#;
+
+const ZSH: &str = r#"# sharedserver: release this shell's server references when it exits.
+if [[ -z "${__sharedserver_hooked:-}" ]]; then
+    __sharedserver_hooked=1
+    export SHAREDSERVER_HOOK=$$
+    __sharedserver_detach() {
+        command @EXE@ unuse --all --pid "$$" --quiet >/dev/null 2>&1
+    }
+    autoload -Uz add-zsh-hook
+    add-zsh-hook zshexit __sharedserver_detach
+fi
+"#;
+
+const FISH: &str = r#"# sharedserver: release this shell's server references when it exits.
+if not set -q __sharedserver_hooked
+    set -g __sharedserver_hooked 1
+    set -gx SHAREDSERVER_HOOK $fish_pid
+    function __sharedserver_detach --on-event fish_exit
+        command @EXE@ unuse --all --pid $fish_pid --quiet >/dev/null 2>&1
+    end
+end
+"#;
+
+impl HookShell {
+    /// The shell `pid` is running, if it is one `hook` supports. Login shells
+    /// are named with a leading `-`.
+    fn of(pid: i32) -> Option<Self> {
+        match process_name(pid)?.trim_start_matches('-') {
+            "bash" => Some(HookShell::Bash),
+            "zsh" => Some(HookShell::Zsh),
+            "fish" => Some(HookShell::Fish),
+            _ => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            HookShell::Bash => "bash",
+            HookShell::Zsh => "zsh",
+            HookShell::Fish => "fish",
+        }
+    }
+
+    /// The hook, calling `exe`.
+    fn script(self, exe: &str) -> String {
+        let (template, exe) = match self {
+            HookShell::Bash => (BASH, posix_quote(exe)),
+            HookShell::Zsh => (ZSH, posix_quote(exe)),
+            HookShell::Fish => (FISH, fish_quote(exe)),
+        };
+        template.replace("@EXE@", &exe)
+    }
+
+    /// The line that installs the hook, and where it goes.
+    fn install(self) -> (String, &'static str) {
+        match self {
+            HookShell::Bash => (
+                "eval \"$(sharedserver hook bash)\"".to_string(),
+                "~/.bashrc",
+            ),
+            HookShell::Zsh => ("eval \"$(sharedserver hook zsh)\"".to_string(), "~/.zshrc"),
+            HookShell::Fish => (
+                "sharedserver hook fish | source".to_string(),
+                "~/.config/fish/config.fish",
+            ),
+        }
+    }
+}
+
+/// `s` as one word in a POSIX shell.
+pub(super) fn posix_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// `s` as one word in fish, where `\` escapes `'` and itself inside quotes.
+fn fish_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\\', r"\\").replace('\'', r"\'"))
+}
+
+/// Print the code that makes `shell` release its references on exit (an
+/// EXIT trap running `unuse --all --pid $$`), for its rc file to evaluate.
+pub fn execute(shell: HookShell) -> Result<()> {
+    let exe = std::env::current_exe()
+        .ok()
+        .and_then(|path| path.to_str().map(str::to_string))
+        .unwrap_or_else(|| "sharedserver".to_string());
+    print!("{}", shell.script(&exe));
+    Ok(())
+}
+
+/// After `client_pid` has attached to a server: if it is an interactive
+/// shell without the hook, and this is its first server, say how to install
+/// the hook, since nothing else will release the reference when the terminal
+/// closes.
+pub fn suggest(client_pid: i32) {
+    let Some(shell) = HookShell::of(client_pid) else {
+        return;
+    };
+    if std::env::var(HOOK_VAR).is_ok_and(|pid| pid == client_pid.to_string()) {
+        return;
+    }
+    let Ok(names) = servers_with(&["clients.json"]) else {
+        return;
+    };
+    let attached = names
+        .iter()
+        .filter(|name| {
+            read_clients_lock(name).is_ok_and(|clients| clients.clients.contains_key(&client_pid))
+        })
+        .count();
+    if attached != 1 {
+        return;
+    }
+    let (line, rc_file) = shell.install();
+    print_info(&format!(
+        "This {} shell holds the reference until it runs 'sharedserver unuse'. \
+         To release it when the shell exits, add to {}: {}",
+        shell.as_str(),
+        rc_file,
+        line
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quoting() {
+        assert_eq!(posix_quote("/opt/my bin/ss"), "'/opt/my bin/ss'");
+        assert_eq!(posix_quote("it's"), r"'it'\''s'");
+        assert_eq!(fish_quote(r"it's a\b"), r"'it\'s a\\b'");
+    }
+
+    #[test]
+    fn test_scripts_detach_the_shell() {
+        for (shell, pid) in [
+            (HookShell::Bash, "\"$$\""),
+            (HookShell::Zsh, "\"$$\""),
+            (HookShell::Fish, "$fish_pid"),
+        ] {
+            let script = shell.script("/usr/bin/sharedserver");
+            assert!(
+                script.contains(&format!(
+                    "command '/usr/bin/sharedserver' unuse --all --pid {} --quiet",
+                    pid
+                )),
+                "{}",
+                script
+            );
+            assert!(script.contains(HOOK_VAR));
+            assert!(!script.contains("@EXE@"));
+        }
+    }
+}
@@ -1,5 +1,6 @@
-use anyhow::{bail, Context, Result};
-use sharedserver::core::{get_server_state, ClientInfo, ClientsLock, ServerState};
+use anyhow::Result;
+use sharedserver::core::handle::attach_client;
+use sharedserver::core::{get_server_state, ErrorKind, ServerState};
 
 use crate::output::{format_refcount, format_server_name, print_success};
 
@@ -8,19 +9,19 @@ pub fn execute(name: &str, metadata: Option<String>, client_pid: i32) -> Result<
 
     match state {
         ServerState::Stopped => {
-            bail!(
+            Err(ErrorKind::NotRunning.error(format!(
                 "Server '{}' is not running. Start it first with 'sharedserver use' or 'sharedserver admin start'",
                 name
-            );
+            )))
         }
         ServerState::Defunct => {
-            bail!(
+            Err(ErrorKind::ShuttingDown.error(format!(
                 "Server '{}' is shutting down (defunct, cleanup pending). Retry shortly.",
                 name
-            );
+            )))
         }
         ServerState::Active | ServerState::Grace => {
-            let new_refcount = increment_refcount(name, metadata, client_pid)?;
+            let new_refcount = attach_client(name, client_pid, metadata.clone())?;
 
             // Log success
             let _ = sharedserver::core::log::log_invocation(
@@ -31,6 +32,9 @@ pub fn execute(name: &str, metadata: Option<String>, client_pid: i32) -> Result<
                     Some(serde_json::json!({
                         "new_refcount": new_refcount,
                         "state": state.as_str(),
+                        "client_pid": client_pid,
+                        "start_time": sharedserver::core::process_start_stamp(client_pid),
+                        "metadata": metadata,
                     })),
                 ),
             );
@@ -44,26 +48,3 @@ pub fn execute(name: &str, metadata: Option<String>, client_pid: i32) -> Result<
         }
     }
 }
-
-fn increment_refcount(name: &str, metadata: Option<String>, client_pid: i32) -> Result<u32> {
-    let clients_path = sharedserver::core::lockfile::clients_lockfile_path(name)?;
-
-    // Read-modify-write the whole clients lock under a single exclusive lock.
-    // The clients lockfile is created at server start and kept for the server's
-    // whole life (never deleted on grace), so the inode is stable and this lock
-    // provides real mutual exclusion. The refcount is *derived* from the number
-    // of distinct client PIDs, so a repeat attach from the same PID is
-    // idempotent: a HashMap insert that replaces an existing key must not bump
-    // the count.
-    sharedserver::core::lockfile::with_lock(&clients_path, |file| {
-        let mut clients: ClientsLock =
-            sharedserver::core::lockfile::read_json(file).unwrap_or_else(|_| ClientsLock::new());
-        clients
-            .clients
-            .insert(client_pid, ClientInfo::new(metadata));
-        clients.refcount = clients.clients.len() as u32;
-        sharedserver::core::lockfile::write_json(file, &clients)?;
-        Ok(clients.refcount)
-    })
-    .context("Failed to increment refcount")
-}
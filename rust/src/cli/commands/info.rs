@@ -1,33 +1,111 @@
 use anyhow::Result;
 use colored::*;
+use serde::{Serialize, Serializer};
 use serde_json::json;
-use sharedserver::core::{get_server_state, read_clients_lock, read_server_lock, ServerState};
+use sharedserver::core::filter::matching_servers;
+use sharedserver::core::grace::GracePeriod;
+use sharedserver::core::heartbeat::{heartbeat_age, is_stale, read_heartbeat};
+use sharedserver::core::tombstone::{read_tombstone, Tombstone};
+use sharedserver::core::{
+    get_server_state, parse_duration, read_clients_lock, read_server_lock, watcher_alive,
+    ErrorKind, RestartPolicy, ServerLock, ServerState,
+};
+use std::io::{IsTerminal, Write};
+use std::time::Duration;
 
 use crate::output::{
-    format_duration, format_pid, format_refcount, format_server_name, format_server_state,
-    format_timestamp,
+    format_bytes, format_duration, format_last_exit, format_pid, format_refcount,
+    format_server_name, format_server_state, format_timestamp, format_utc_timestamp, print_report,
+    Glyph, OutputFormat, PerServer, Report,
 };
 
-pub fn execute(name: &str, json_output: bool) -> Result<()> {
+/// Everything `info` shows about one server.
+struct InfoReport {
+    name: String,
+    state: ServerState,
+    last_exit: Option<Tombstone>,
+    /// Absent once the server has stopped.
+    running: Option<Running>,
+}
+
+struct Running {
+    server_lock: ServerLock,
+    refcount: u32,
+    /// Only read while Active; a server in grace has none by definition.
+    clients_info: Option<Vec<serde_json::Value>>,
+    heartbeat_age: Option<Duration>,
+    watcher_stale: bool,
+}
+
+/// Print `name`'s details, or with `watch` (an interval like "2s") keep
+/// re-printing them until interrupted: redrawing the screen on a terminal,
+/// otherwise one table, JSON line, or YAML document per refresh.
+pub fn execute(name: &str, format: OutputFormat, watch: Option<&str>) -> Result<()> {
+    let Some(interval) = watch else {
+        return print_report(format, &gather(name)?);
+    };
+    let interval = parse_duration(interval).map_err(|e| {
+        ErrorKind::InvalidArgs.wrap(e, format!("Invalid --watch interval '{}'", interval))
+    })?;
+    let redraw = format == OutputFormat::Table && std::io::stdout().is_terminal();
+
+    loop {
+        let report = gather(name)?;
+        match format {
+            OutputFormat::Json => println!("{}", serde_json::to_string(&report)?),
+            OutputFormat::Yaml => print!("---\n{}", serde_yaml::to_string(&report)?),
+            _ => {
+                if redraw {
+                    // Clear the screen and home the cursor.
+                    print!("\x1b[2J\x1b[H");
+                }
+                println!(
+                    "{}\n",
+                    format!(
+                        "Every {}: sharedserver info {}    {}",
+                        format_duration(interval),
+                        name,
+                        chrono::Local::now().format("%H:%M:%S")
+                    )
+                    .dimmed()
+                );
+                report.print_table()?;
+                if !redraw {
+                    println!();
+                }
+            }
+        }
+        std::io::stdout().flush()?;
+        std::thread::sleep(interval);
+    }
+}
+
+/// The details of every server whose name matches `pattern`, keyed by name.
+pub fn execute_matching(pattern: &str, format: OutputFormat) -> Result<()> {
+    let reports = matching_servers(pattern, &["server.json"])?
+        .into_iter()
+        .map(|name| Ok((name.clone(), gather(&name)?)))
+        .collect::<Result<Vec<_>>>()?;
+    print_report(format, &PerServer(reports))
+}
+
+/// `name` as `info --json` shows it, for the daemon's HTTP API.
+pub(crate) fn snapshot(name: &str) -> Result<serde_json::Value> {
+    Ok(serde_json::to_value(gather(name)?)?)
+}
+
+fn gather(name: &str) -> Result<InfoReport> {
     let state = get_server_state(name)?;
 
+    let last_exit = read_tombstone(name);
+
     if state == ServerState::Stopped {
-        if json_output {
-            println!(
-                "{}",
-                json!({
-                    "state": "stopped",
-                    "name": name,
-                })
-            );
-        } else {
-            println!(
-                "Server: {}\nStatus: {}",
-                format_server_name(name),
-                format_server_state(&state)
-            );
-        }
-        return Ok(());
+        return Ok(InfoReport {
+            name: name.to_string(),
+            state,
+            last_exit,
+            running: None,
+        });
     }
 
     let server_lock = read_server_lock(name)?;
@@ -43,10 +121,12 @@ pub fn execute(name: &str, json_output: bool) -> Result<()> {
                             "pid": pid,
                             "attached_at": info.attached_at,
                             "metadata": info.metadata,
+                            "command": info.command,
+                            "cmdline": info.cmdline,
                         })
                     })
                     .collect();
-                (clients.refcount, Some(clients_info))
+                (clients.refcount(), Some(clients_info))
             }
             Err(_) => (0, None),
         }
@@ -54,24 +134,117 @@ pub fn execute(name: &str, json_output: bool) -> Result<()> {
         (0, None)
     };
 
-    if json_output {
-        let info = json!({
-            "state": state.as_str(),
+    // A live watcher that has stopped heartbeating is wedged; a missing
+    // heartbeat (older watcher) is simply unknown.
+    let heartbeat_age = heartbeat_age(name);
+    let watcher_stale = watcher_alive(&server_lock) && heartbeat_age.is_some_and(is_stale);
+
+    Ok(InfoReport {
+        name: name.to_string(),
+        state,
+        last_exit,
+        running: Some(Running {
+            server_lock,
+            refcount,
+            clients_info,
+            heartbeat_age,
+            watcher_stale,
+        }),
+    })
+}
+
+impl Serialize for InfoReport {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let name = &self.name;
+        let last_exit = &self.last_exit;
+        let Some(running) = &self.running else {
+            return json!({
+                "state": "stopped",
+                "name": name,
+                "last_exit": last_exit,
+            })
+            .serialize(serializer);
+        };
+        let Running {
+            server_lock,
+            refcount,
+            clients_info,
+            heartbeat_age,
+            watcher_stale,
+        } = running;
+
+        json!({
+            "state": self.state.as_str(),
             "name": name,
             "pid": server_lock.pid,
             "command": server_lock.command,
             "grace_period": server_lock.grace_period,
+            "grace_clock": server_lock.grace_clock.as_str(),
+            "grace_deadline": server_lock.grace_deadline,
+            "grace_remaining_secs": server_lock.grace_remaining().map(|left| left.as_secs()),
             "watcher_pid": server_lock.watcher_pid,
             "started_at": server_lock.started_at.timestamp(),
+            "uptime_secs": server_lock.uptime().as_secs(),
+            "address": server_lock.address(),
+            "server_address": server_lock.server_address(),
+            "container": server_lock.container,
             "start_time": server_lock.start_time,
             "watcher_start_time": server_lock.watcher_start_time,
+            "watcher_heartbeat": read_heartbeat(name).map(|t| t.to_rfc3339()),
+            "watcher_heartbeat_age_secs": heartbeat_age.map(|age| age.as_secs()),
+            "watcher_stale": watcher_stale,
+            "tags": server_lock.tags,
+            "restart": server_lock.restart.as_str(),
+            "restart_count": server_lock.restart_count,
+            "health": server_lock.health_label(),
+            "health_check": server_lock.health_check,
+            "health_status": server_lock.health,
+            "resource_limits": server_lock.limits,
+            "resources": server_lock.resources,
+            "notify_signal": server_lock.notify_signal,
+            "executable": server_lock.executable,
+            "executable_changed": server_lock.executable.as_ref().map(|exe| exe.changed_on_disk()),
+            "cwd": server_lock.cwd,
+            "env": server_lock.env,
+            "owner_uid": server_lock.owner_uid,
+            "shared_group": server_lock.shared_group,
+            "last_exit": last_exit,
             "refcount": refcount,
             "clients": clients_info,
-        });
+        })
+        .serialize(serializer)
+    }
+}
+
+impl Report for InfoReport {
+    fn print_table(&self) -> Result<()> {
+        let name = self.name.as_str();
+        let state = self.state;
+        let last_exit = &self.last_exit;
+        let Some(running) = &self.running else {
+            println!(
+                "Server: {}\nStatus: {}",
+                format_server_name(name),
+                format_server_state(&state)
+            );
+            if let Some(tombstone) = last_exit {
+                println!(
+                    "Last exit: {} {}",
+                    format_last_exit(tombstone),
+                    format_utc_timestamp(tombstone.exited_at).dimmed()
+                );
+            }
+            return Ok(());
+        };
+        let Running {
+            server_lock,
+            refcount,
+            clients_info,
+            heartbeat_age,
+            watcher_stale,
+        } = running;
+        let (refcount, heartbeat_age, watcher_stale) = (*refcount, *heartbeat_age, *watcher_stale);
 
-        println!("{}", serde_json::to_string_pretty(&info)?);
-    } else {
-        // Formatted output
         println!(
             "Server: {} (PID: {})",
             format_server_name(name),
@@ -82,25 +255,192 @@ pub fn execute(name: &str, json_output: bool) -> Result<()> {
             format_server_state(&state),
             format_refcount(refcount)
         );
-        println!("Command: {}", server_lock.command.join(" ").bright_white());
+        if server_lock.command.is_empty() && server_lock.container.is_some() {
+            println!("Command: {}", "(the image's default)".dimmed());
+        } else {
+            println!("Command: {}", server_lock.command.join(" ").bright_white());
+        }
+        match (
+            &server_lock.listen,
+            &server_lock.address,
+            server_lock.server_address(),
+        ) {
+            (Some(listen), _, Some(upstream)) => println!(
+                "Address: {} {}",
+                listen,
+                format!("(forwarded to {})", upstream).dimmed()
+            ),
+            (_, Some(advertised), _) => println!("Address: {}", advertised),
+            (_, None, Some(probed)) => {
+                println!("Address: {} {}", probed, "(from the health probe)".dimmed())
+            }
+            (_, None, None) => {}
+        }
+        if !server_lock.tags.is_empty() {
+            let tags: Vec<String> = server_lock
+                .tags
+                .iter()
+                .map(|(key, value)| format!("{}={}", key, value))
+                .collect();
+            println!("Tags: {}", tags.join(", "));
+        }
+        if let Some(container) = &server_lock.container {
+            let id = match &container.id {
+                Some(id) => id.chars().take(12).collect(),
+                None => "not yet created".to_string(),
+            };
+            println!(
+                "Container: {} {} {}",
+                container.image,
+                format!("({})", container.backend).dimmed(),
+                format!("{} {}", container.name, id).dimmed()
+            );
+        }
+        if let Some(exe) = &server_lock.executable {
+            if exe.changed_on_disk() {
+                println!(
+                    "Executable: {} {}",
+                    exe.path.display(),
+                    "(changed on disk since start; `use --replace` restarts it)".yellow()
+                );
+            } else {
+                println!("Executable: {}", exe.path.display().to_string().dimmed());
+            }
+        }
+        if let Some(cwd) = &server_lock.cwd {
+            println!("Working Dir: {}", cwd.display().to_string().dimmed());
+        }
+        if !server_lock.env.is_empty() {
+            println!("Environment: {}", server_lock.env.join(" ").dimmed());
+        }
+        if let Some(group) = &server_lock.shared_group {
+            let owner = server_lock
+                .owner_uid
+                .map(|uid| format!(", owner UID {}", uid))
+                .unwrap_or_default();
+            println!("Shared: with group '{}'{}", group.cyan(), owner);
+        }
 
         // Parse grace period string and format duration
-        if let Ok(grace_duration) = sharedserver::core::parse_duration(&server_lock.grace_period) {
-            println!("Grace Period: {}", format_duration(grace_duration));
-        } else {
-            println!("Grace Period: {}", server_lock.grace_period);
+        match server_lock.grace_period.parse::<GracePeriod>() {
+            Ok(GracePeriod::After(grace_duration)) => println!(
+                "Grace Period: {} ({} clock)",
+                format_duration(grace_duration),
+                server_lock.grace_clock.as_str()
+            ),
+            Ok(GracePeriod::None) => println!(
+                "Grace Period: none {}",
+                "(stops when the last client detaches)".dimmed()
+            ),
+            Ok(GracePeriod::Infinite) => {
+                println!("Grace Period: infinite {}", "(runs until stopped)".dimmed())
+            }
+            Err(_) => println!("Grace Period: {}", server_lock.grace_period),
+        }
+        if let Some(remaining) = server_lock.grace_remaining() {
+            println!(
+                "Shutting down in: {} {}",
+                format_duration(remaining).yellow(),
+                "(unless a client attaches)".dimmed()
+            );
         }
 
         // Convert chrono::DateTime to SystemTime for formatting
         let started_system_time = std::time::SystemTime::UNIX_EPOCH
             + std::time::Duration::from_secs(server_lock.started_at.timestamp() as u64);
         println!(
-            "Started: {}",
-            format_timestamp(started_system_time).dimmed()
+            "Started: {} {}",
+            format_timestamp(started_system_time).dimmed(),
+            format!("(up {})", format_duration(server_lock.uptime())).dimmed()
         );
 
         if let Some(watcher_pid) = server_lock.watcher_pid {
-            println!("Watcher: {}", format_pid(watcher_pid));
+            match heartbeat_age {
+                Some(age) if watcher_stale => println!(
+                    "Watcher: {} {}",
+                    format_pid(watcher_pid),
+                    format!(
+                        "(no heartbeat for {}; watcher may be wedged)",
+                        format_duration(age)
+                    )
+                    .red()
+                ),
+                Some(age) => println!(
+                    "Watcher: {} {}",
+                    format_pid(watcher_pid),
+                    format!("(last heartbeat {} ago)", format_duration(age)).dimmed()
+                ),
+                None => println!("Watcher: {}", format_pid(watcher_pid)),
+            }
+        } else {
+            // Its watcher handed it off (--linger): nothing enforces grace.
+            println!("Watcher: {}", "none (left running unsupervised)".yellow());
+        }
+
+        if let (Some(label), Some(check)) = (server_lock.health_label(), &server_lock.health_check)
+        {
+            let label = if server_lock.is_unhealthy() {
+                label.red()
+            } else {
+                label.green()
+            };
+            println!(
+                "Health: {} ({}, every {})",
+                label,
+                check.probe.describe(),
+                check.interval
+            );
+            if let Some(status) = &server_lock.health {
+                if let Some(error) = &status.last_error {
+                    println!(
+                        "  Last failure: {} ({} consecutive)",
+                        error, status.consecutive_failures
+                    );
+                }
+            }
+        }
+
+        if let Some(usage) = &server_lock.resources {
+            let mut line = format!("Memory: {}", format_bytes(usage.rss_bytes));
+            if let Some(limits) = &server_lock.limits {
+                if let Some(limit) = &limits.memory_limit {
+                    line.push_str(&format!(
+                        " (limit {}, {})",
+                        limit,
+                        limits.memory_action.as_str()
+                    ));
+                }
+            }
+            println!("{}", line);
+            if let Some(cpu) = usage.cpu_percent {
+                let mut line = format!("CPU: {:.0}%", cpu);
+                if let Some(limits) = &server_lock.limits {
+                    if let Some(limit) = &limits.cpu_limit {
+                        line.push_str(&format!(
+                            " (limit {}, {})",
+                            limit,
+                            limits.cpu_action.as_str()
+                        ));
+                    }
+                }
+                println!("{}", line);
+            }
+        }
+
+        if server_lock.restart != RestartPolicy::Never || server_lock.restart_count > 0 {
+            println!(
+                "Restarts: {} (policy: {})",
+                server_lock.restart_count,
+                server_lock.restart.as_str()
+            );
+        }
+
+        if let Some(tombstone) = last_exit {
+            println!(
+                "Last exit: {} {}",
+                format_last_exit(tombstone),
+                format_utc_timestamp(tombstone.exited_at).dimmed()
+            );
         }
 
         // Print clients
@@ -111,10 +451,22 @@ pub fn execute(name: &str, json_output: bool) -> Result<()> {
             } else {
                 for client in clients {
                     let pid = client["pid"].as_i64().unwrap_or(0) as i32;
-                    let metadata = client["metadata"]
-                        .as_str()
-                        .map(|m| format!(" ({})", m))
-                        .unwrap_or_default();
+                    let metadata = client["metadata"].as_str();
+                    // "nvim (PID: 1234, project foo)", or "PID: 1234 (project
+                    // foo)" for clients attached before names were recorded.
+                    let label = match client["command"].as_str() {
+                        Some(command) => format!(
+                            "{} (PID: {}{})",
+                            command.bold(),
+                            format_pid(pid),
+                            metadata.map(|m| format!(", {}", m)).unwrap_or_default()
+                        ),
+                        None => format!(
+                            "PID: {}{}",
+                            format_pid(pid),
+                            metadata.map(|m| format!(" ({})", m)).unwrap_or_default()
+                        ),
+                    };
 
                     if let Some(attached_at_str) = client["attached_at"].as_str() {
                         // Parse chrono DateTime from JSON string
@@ -124,22 +476,20 @@ pub fn execute(name: &str, json_output: bool) -> Result<()> {
                             let attached_system_time = std::time::SystemTime::UNIX_EPOCH
                                 + std::time::Duration::from_secs(attached_at.timestamp() as u64);
                             println!(
-                                "  {} PID: {}{} - attached {}",
-                                "•".cyan(),
-                                format_pid(pid),
-                                metadata,
+                                "  {} {} - attached {}",
+                                Glyph::Bullet.as_str().cyan(),
+                                label,
                                 format_timestamp(attached_system_time).dimmed()
                             );
                         } else {
-                            println!("  {} PID: {}{}", "•".cyan(), format_pid(pid), metadata);
+                            println!("  {} {}", Glyph::Bullet.as_str().cyan(), label);
                         }
                     } else {
-                        println!("  {} PID: {}{}", "•".cyan(), format_pid(pid), metadata);
+                        println!("  {} {}", Glyph::Bullet.as_str().cyan(), label);
                     }
                 }
             }
         }
+        Ok(())
     }
-
-    Ok(())
 }
@@ -1,14 +1,43 @@
 use anyhow::{bail, Result};
 use nix::sys::signal::{kill, killpg, Signal};
 use nix::unistd::Pid;
+use sharedserver::core::filter::matching_servers;
+use sharedserver::core::tombstone::{write_tombstone, DeathReason, Tombstone};
 use sharedserver::core::{
-    delete_locks_owned_by, get_server_state, process_liveness_checked, read_server_lock, Liveness,
-    ServerState,
+    delete_locks_owned_by, get_server_state, process_liveness_checked, read_clients_lock,
+    read_server_lock, ErrorKind, Liveness, ServerExit, ServerState,
 };
 use std::thread;
 use std::time::{Duration, Instant};
 
-use crate::output::{format_pid, format_server_name, print_error, print_success, print_warning};
+use crate::output::{
+    confirm, format_pid, format_server_name, print_error, print_success, print_warning,
+};
+
+/// Kill every running server whose name matches `pattern`, once
+/// [`confirm`]ed. The ones that fail are reported once the rest have been
+/// tried.
+pub fn execute_matching(pattern: &str, yes: bool) -> Result<()> {
+    let names: Vec<String> = matching_servers(pattern, &["server.json"])?
+        .into_iter()
+        .filter(|name| get_server_state(name).is_ok_and(|state| state != ServerState::Stopped))
+        .collect();
+    if names.is_empty() {
+        return Err(ErrorKind::NotRunning.error(format!("No running server matches '{}'", pattern)));
+    }
+    confirm("Kill", &names, yes)?;
+    let mut failed = 0;
+    for name in &names {
+        if let Err(e) = execute(name) {
+            print_error(&format!("{}: {:#}", name, e));
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        bail!("Failed to kill {} of {} servers", failed, names.len());
+    }
+    Ok(())
+}
 
 /// Forcibly kill a server and clean up its state.
 ///
@@ -21,7 +50,7 @@ pub fn execute(name: &str) -> Result<()> {
     let state = get_server_state(name)?;
 
     if state == ServerState::Stopped {
-        bail!("Server '{}' is not running", name);
+        return Err(ErrorKind::NotRunning.error(format!("Server '{}' is not running", name)));
     }
 
     let server = read_server_lock(name)?;
@@ -35,7 +64,11 @@ pub fn execute(name: &str) -> Result<()> {
 
     // 1. Kill the watcher first so it can't race our lockfile cleanup or linger
     //    after we've removed them. kill is watcher-independent by design.
-    if let Some(watcher_pid) = server.watcher_pid {
+    //    A daemon's server is the exception: the daemon supervises others
+    //    too, so it is only told not to restart this one.
+    if server.daemon {
+        sharedserver::core::stop::mark_stop_requested(name, server.pid);
+    } else if let Some(watcher_pid) = server.watcher_pid {
         // Identity-checked so we never SIGKILL an unrelated process that reused
         // the watcher's PID after it died.
         if sharedserver::core::watcher_alive(&server) {
@@ -52,12 +85,20 @@ pub fn execute(name: &str) -> Result<()> {
 
     // 2. SIGKILL the server's whole process group (server + children like
     //    uv→python). Fall back to a single-PID kill if it isn't a group leader.
+    //    A container goes first: killing its client alone would leave it
+    //    running.
+    if let Some(container) = &server.container {
+        match container.kill() {
+            Ok(()) => print_success(&format!("Container {} killed", container.name)),
+            Err(e) => print_warning(&format!("Failed to kill container: {:#}", e)),
+        }
+    }
     match killpg(pid, Signal::SIGKILL) {
         Ok(_) => print_success("SIGKILL sent to process group"),
         Err(_) => match kill(pid, Signal::SIGKILL) {
             Ok(_) => print_success("SIGKILL sent"),
             Err(e) => {
-                if process_liveness_checked(server.pid, server.start_time) == Liveness::Gone {
+                if server.server_liveness() == Liveness::Gone {
                     print_warning("Process already dead");
                 } else {
                     print_error(&format!("Failed to send SIGKILL: {}", e));
@@ -70,7 +111,7 @@ pub fn execute(name: &str) -> Result<()> {
     // 3. Confirm termination. With the watcher dead, init reaps the zombie;
     //    poll briefly for it to fully disappear.
     wait_until_not_alive(server.pid, server.start_time, Duration::from_secs(2));
-    match process_liveness_checked(server.pid, server.start_time) {
+    match server.server_liveness() {
         Liveness::Gone => print_success(&format!(
             "Server {} forcefully terminated",
             format_server_name(name)
@@ -80,12 +121,31 @@ pub fn execute(name: &str) -> Result<()> {
             format_server_name(name)
         )),
         Liveness::Alive => print_error(&format!(
-            "Server process {} may still be alive (SIGKILL not deliverable — \
+            "Server process {} may still be alive (SIGKILL not deliverable; \
              possibly stuck in uninterruptible sleep)",
             format_pid(server.pid)
         )),
     }
 
+    // Nobody is left to waitpid the server, so record the death ourselves.
+    let last_refcount = read_clients_lock(name).map_or(0, |c| c.refcount());
+    let _ = write_tombstone(
+        name,
+        &Tombstone {
+            pid: server.pid,
+            command: server.command.clone(),
+            reason: DeathReason::Killed,
+            exit: ServerExit::Signaled {
+                signal: Signal::SIGKILL as i32,
+            },
+            started_at: server.started_at,
+            exited_at: chrono::Utc::now(),
+            restart_count: server.restart_count,
+            last_refcount,
+            tags: server.tags.clone(),
+        },
+    );
+
     // 4. Clean up lockfiles. kill is the only command that deletes them itself
     //    (the watcher it would otherwise rely on is now dead). Pid-guarded so a
     //    concurrently-restarted instance is never clobbered.
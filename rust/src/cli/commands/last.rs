@@ -0,0 +1,65 @@
+use anyhow::{bail, Result};
+use colored::*;
+use serde::Serialize;
+use sharedserver::core::tombstone::{read_tombstone, Tombstone};
+
+use crate::output::{
+    format_duration, format_last_exit, format_pid, format_refcount, format_server_name,
+    format_utc_timestamp, print_report, OutputFormat, Report,
+};
+
+/// Show how the server last went down, from its `<name>.exit.json` record.
+pub fn execute(name: &str, format: OutputFormat) -> Result<()> {
+    let Some(tombstone) = read_tombstone(name) else {
+        bail!("No exit recorded for server '{}'", name);
+    };
+    print_report(
+        format,
+        &LastReport {
+            name: name.to_string(),
+            tombstone,
+        },
+    )
+}
+
+#[derive(Serialize)]
+struct LastReport {
+    name: String,
+    #[serde(flatten)]
+    tombstone: Tombstone,
+}
+
+impl Report for LastReport {
+    fn print_table(&self) -> Result<()> {
+        print_tombstone(&self.name, &self.tombstone);
+        Ok(())
+    }
+}
+
+fn print_tombstone(name: &str, tombstone: &Tombstone) {
+    println!(
+        "Server: {} (PID: {})",
+        format_server_name(name),
+        format_pid(tombstone.pid)
+    );
+    println!("Last exit: {}", format_last_exit(tombstone));
+    println!("Command: {}", tombstone.command.join(" ").bright_white());
+    println!(
+        "Started: {}",
+        format_utc_timestamp(tombstone.started_at).dimmed()
+    );
+    println!(
+        "Exited: {}",
+        format_utc_timestamp(tombstone.exited_at).dimmed()
+    );
+    if let Ok(ran_for) = (tombstone.exited_at - tombstone.started_at).to_std() {
+        println!("Ran for: {}", format_duration(ran_for));
+    }
+    println!(
+        "Clients at exit: {}",
+        format_refcount(tombstone.last_refcount)
+    );
+    if tombstone.restart_count > 0 {
+        println!("Restarts: {}", tombstone.restart_count);
+    }
+}
@@ -1,95 +1,285 @@
 use anyhow::Result;
+use clap::ValueEnum;
 use colored::*;
+use serde::{Serialize, Serializer};
 use serde_json::json;
-use sharedserver::core::{get_server_state, read_clients_lock, read_server_lock};
-use std::fs;
+use sharedserver::core::limits::sample_usage;
+use sharedserver::core::lockfile::servers_with;
+use sharedserver::core::pool;
+use sharedserver::core::registry::{read_registry, Registry};
+use sharedserver::core::tombstone::{recent_tombstones, Tombstone};
+use sharedserver::core::{
+    child_pids, get_server_state, process_name, read_clients_lock, read_server_lock,
+    state_from_locks, ClientInfo, ClientsLock, ServerFilter, ServerLock, ServerState,
+};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashSet};
+use std::time::Duration;
 
 use crate::output::{
-    format_clients, format_pid, format_refcount, format_server_name, format_server_state,
+    format_bytes, format_clients, format_duration, format_grace_state, format_last_exit,
+    format_pid, format_refcount, format_server_name, format_server_state, format_unhealthy_state,
+    print_report, OutputFormat, Records, Report, Table, TreeNode,
 };
 
-pub fn execute(json_output: bool) -> Result<()> {
+/// A server as listed: its state, plus its locks when it is running.
+type Listed = (String, ServerState, Option<ServerLock>, Option<ClientsLock>);
+
+/// Order of the running servers in `list`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum ListSort {
+    #[default]
+    Name,
+    /// Longest-running first.
+    Uptime,
+    /// Most clients first.
+    Refcount,
+    /// Active, then grace, then defunct, then stopped.
+    State,
+}
+
+/// How long `list --resources` measures CPU use over.
+const RESOURCE_SAMPLE_WINDOW: Duration = Duration::from_millis(500);
+
+/// How many levels of the server's own children `list --tree` follows.
+const MAX_TREE_DEPTH: usize = 6;
+
+/// A process in `list --tree`, with the processes it started.
+#[derive(Serialize)]
+struct ProcessNode {
+    pid: i32,
+    name: Option<String>,
+    children: Vec<ProcessNode>,
+}
+
+/// What `list` shows: the running servers, and (with `--recent`) the ones
+/// that went down lately.
+struct ListReport {
+    servers: Vec<Listed>,
+    stopped: Vec<(String, Tombstone)>,
+    /// Show memory and CPU columns (`--resources`).
+    resources: bool,
+    /// Each running server's process tree, rooted at its watcher
+    /// (`--tree`).
+    trees: Option<BTreeMap<String, ProcessNode>>,
+}
+
+pub fn execute(
+    format: OutputFormat,
+    recent: bool,
+    filter: ServerFilter,
+    sort: ListSort,
+    resources: bool,
+    tree: bool,
+    jobs: usize,
+) -> Result<()> {
+    // Asking for stopped servers means the recently stopped ones: those are
+    // the only stopped servers there is anything to show for.
+    let recent = recent || filter.states.contains(&ServerState::Stopped);
+    let mut report = gather(recent, &filter, jobs)?;
+    if resources {
+        sample_resources(&mut report.servers);
+        report.resources = true;
+    }
+    if tree {
+        report.trees = Some(process_trees(&report.servers, jobs));
+    }
+    sort_servers(&mut report.servers, sort);
+    print_report(format, &report)
+}
+
+/// The running servers as `list --json` shows them, for the daemon's HTTP
+/// API.
+pub(crate) fn snapshot() -> Result<serde_json::Value> {
+    Ok(serde_json::to_value(gather(
+        false,
+        &ServerFilter::default(),
+        pool::default_jobs(),
+    )?)?)
+}
+
+fn gather(recent: bool, filter: &ServerFilter, jobs: usize) -> Result<ListReport> {
     let lockdir = sharedserver::core::lockfile::lockfile_dir()?;
 
     if !lockdir.exists() {
-        if json_output {
-            println!("[]");
-        } else {
-            println!("{}", "No servers found".dimmed());
-        }
-        return Ok(());
+        return Ok(ListReport {
+            servers: Vec::new(),
+            stopped: Vec::new(),
+            resources: false,
+            trees: None,
+        });
     }
 
-    let entries = fs::read_dir(&lockdir)?;
-
-    let mut servers = Vec::new();
+    let mut servers = match read_registry() {
+        Ok(registry) => from_registry(&registry),
+        Err(_) => scan_lockdir(jobs)?,
+    };
 
-    for entry in entries {
-        let entry = entry?;
-        let path = entry.path();
+    // Servers that have gone down within the retention period and haven't
+    // come back.
+    let stopped: Vec<(String, Tombstone)> = if recent {
+        recent_tombstones()?
+            .into_iter()
+            .filter(|(name, _)| filter.matches(name, ServerState::Stopped))
+            .filter(|(_, tombstone)| filter.matches_tags(&tombstone.tags))
+            .filter(|(name, _)| {
+                !servers
+                    .iter()
+                    .any(|(running, state, ..)| running == name && *state != ServerState::Stopped)
+            })
+            .collect()
+    } else {
+        Vec::new()
+    };
 
-        if let Some(filename) = path.file_name() {
-            let filename = filename.to_string_lossy();
+    // Filtered only now, so a running server outside the filter still hides
+    // its tombstone above.
+    servers.retain(|(name, state, server_info, _)| {
+        filter.matches(name, *state)
+            && (filter.tags.is_empty()
+                || server_info
+                    .as_ref()
+                    .is_some_and(|s| filter.matches_tags(&s.tags)))
+    });
 
-            if filename.ends_with(".server.json") {
-                let name = filename
-                    .strip_suffix(".server.json")
-                    .unwrap_or(&filename)
-                    .to_string();
+    Ok(ListReport {
+        servers,
+        stopped,
+        resources: false,
+        trees: None,
+    })
+}
 
-                if let Ok(state) = get_server_state(&name) {
-                    let server_info = if state != sharedserver::core::ServerState::Stopped {
-                        read_server_lock(&name).ok()
-                    } else {
-                        None
-                    };
+/// Replace each running server's recorded usage with a fresh sample of its
+/// process group. The watcher only samples servers that have limits, so this
+/// is what makes the columns work for every server; one that can't be
+/// sampled keeps whatever the watcher last recorded.
+fn sample_resources(servers: &mut [Listed]) {
+    let pids: Vec<i32> = servers
+        .iter()
+        .filter_map(|(_, _, server_info, _)| server_info.as_ref().map(|s| s.pid))
+        .collect();
+    let mut usage = sample_usage(&pids, RESOURCE_SAMPLE_WINDOW);
+    for server_info in servers.iter_mut().filter_map(|(_, _, s, _)| s.as_mut()) {
+        if let Some(sample) = usage.remove(&server_info.pid) {
+            server_info.resources = Some(sample);
+        }
+    }
+}
 
-                    servers.push((name, state, server_info));
+/// Each running server's processes: its watcher, the server under it, and
+/// whatever the server started in turn. Up to `jobs` servers are walked at
+/// once.
+fn process_trees(servers: &[Listed], jobs: usize) -> BTreeMap<String, ProcessNode> {
+    pool::map(servers, jobs, |(name, _, server_info, _)| {
+        let server_info = server_info.as_ref()?;
+        let mut seen = HashSet::new();
+        let server = process_node(server_info.pid, 0, &mut seen);
+        let root = match server_info.watcher_pid {
+            Some(watcher) if watcher != server_info.pid => {
+                seen.insert(watcher);
+                // The server first, then anything else the watcher has
+                // running (a health probe, say).
+                let mut children = vec![server];
+                for pid in child_pids(watcher) {
+                    if !seen.contains(&pid) {
+                        children.push(process_node(pid, 1, &mut seen));
+                    }
+                }
+                ProcessNode {
+                    pid: watcher,
+                    name: process_name(watcher),
+                    children,
                 }
             }
-        }
-    }
+            _ => server,
+        };
+        Some((name.clone(), root))
+    })
+    .into_iter()
+    .flatten()
+    .collect()
+}
 
-    if servers.is_empty() {
-        if json_output {
-            println!("[]");
-        } else {
-            println!("{}", "No servers found".dimmed());
+/// `pid` and its descendants, down to [`MAX_TREE_DEPTH`]. `seen` guards
+/// against a PID reused mid-walk leading back up the tree.
+fn process_node(pid: i32, depth: usize, seen: &mut HashSet<i32>) -> ProcessNode {
+    seen.insert(pid);
+    let mut children = Vec::new();
+    if depth < MAX_TREE_DEPTH {
+        for child in child_pids(pid) {
+            if !seen.contains(&child) {
+                children.push(process_node(child, depth + 1, seen));
+            }
         }
-        return Ok(());
     }
+    ProcessNode {
+        pid,
+        name: process_name(pid),
+        children,
+    }
+}
+
+/// Who a client is: the process name recorded when it attached, or else its
+/// current one.
+fn client_name(pid: i32, info: &ClientInfo) -> Option<String> {
+    info.command.clone().or_else(|| process_name(pid))
+}
 
-    // Sort by name
+/// Sort by `sort`, then by name.
+fn sort_servers(servers: &mut [Listed], sort: ListSort) {
     servers.sort_by(|a, b| a.0.cmp(&b.0));
+    match sort {
+        ListSort::Name => {}
+        // Servers with no start time (stopped) sort last.
+        ListSort::Uptime => servers.sort_by_key(|(_, _, server_info, _)| {
+            Reverse(server_info.as_ref().map(ServerLock::uptime))
+        }),
+        ListSort::Refcount => servers.sort_by_key(|(_, _, _, clients_lock)| {
+            Reverse(clients_lock.as_ref().map_or(0, |c| c.refcount()))
+        }),
+        ListSort::State => servers.sort_by_key(|(_, state, ..)| match state {
+            ServerState::Active => 0,
+            ServerState::Grace => 1,
+            ServerState::Defunct => 2,
+            ServerState::Stopped => 3,
+        }),
+    }
+}
 
-    if json_output {
-        let items: Vec<_> = servers
+/// One array entry per server, running ones first.
+impl Serialize for ListReport {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let running = self
+            .servers
             .iter()
-            .map(|(name, state, server_info)| {
-                let (refcount, clients_info) = if state == &sharedserver::core::ServerState::Active
-                {
-                    if let Ok(clients_lock) = read_clients_lock(name) {
+            .map(|(name, state, server_info, clients_lock)| {
+                let (refcount, clients_info) = match clients_lock {
+                    Some(clients_lock) => {
                         let clients_info: Vec<_> = clients_lock
                             .clients
                             .iter()
                             .map(|(pid, info)| {
-                                json!({
+                                let mut client = json!({
                                     "pid": pid,
                                     "attached_at": info.attached_at,
                                     "metadata": info.metadata,
-                                })
+                                    "command": info.command,
+                                    "cmdline": info.cmdline,
+                                });
+                                if self.trees.is_some() {
+                                    client["name"] = json!(client_name(*pid, info));
+                                }
+                                client
                             })
                             .collect();
-                        (clients_lock.refcount, Some(clients_info))
-                    } else {
-                        (0, None)
+                        (clients_lock.refcount(), Some(clients_info))
                     }
-                } else {
-                    (0, None)
+                    None => (0, None),
                 };
 
                 if let Some(srv) = server_info {
-                    json!({
+                    let mut entry = json!({
                         "name": name,
                         "state": state.as_str(),
                         "pid": srv.pid,
@@ -97,9 +287,20 @@ pub fn execute(json_output: bool) -> Result<()> {
                         "grace_period": srv.grace_period,
                         "watcher_pid": srv.watcher_pid,
                         "started_at": srv.started_at.timestamp(),
+                        "uptime_secs": srv.uptime().as_secs(),
+                        "address": srv.address(),
+                        "resources": srv.resources,
+                        "health": srv.health_label(),
+                        "grace_deadline": srv.grace_deadline,
+                        "grace_remaining_secs": srv.grace_remaining().map(|left| left.as_secs()),
+                        "tags": srv.tags,
                         "refcount": refcount,
                         "clients": clients_info,
-                    })
+                    });
+                    if let Some(trees) = &self.trees {
+                        entry["processes"] = json!(trees.get(name));
+                    }
+                    entry
                 } else {
                     json!({
                         "name": name,
@@ -109,52 +310,347 @@ pub fn execute(json_output: bool) -> Result<()> {
                         "clients": null,
                     })
                 }
+            });
+        let stopped = self.stopped.iter().map(|(name, tombstone)| {
+            json!({
+                "name": name,
+                "state": "stopped",
+                "pid": null,
+                "refcount": 0,
+                "clients": null,
+                "last_exit": tombstone,
             })
-            .collect();
+        });
+        serializer.collect_seq(running.chain(stopped))
+    }
+}
+
+impl Report for ListReport {
+    fn print_table(&self) -> Result<()> {
+        if self.servers.is_empty() && self.stopped.is_empty() {
+            println!("{}", "No servers found".dimmed());
+            return Ok(());
+        }
+
+        let any_running = !self.servers.is_empty();
+        if let Some(trees) = &self.trees {
+            print_trees(&self.servers, trees);
+        } else if any_running {
+            print_servers(
+                &self.servers,
+                self.resources,
+                any_address(&self.servers),
+                any_tags(&self.servers),
+            );
+        }
+        if !self.stopped.is_empty() {
+            if any_running {
+                println!();
+            }
+            print_stopped(&self.stopped);
+        }
+        Ok(())
+    }
+
+    /// Plain values named like the JSON fields: seconds rather than "4m",
+    /// client PIDs joined with commas, and empty fields for what a server
+    /// doesn't have.
+    fn records(&self) -> Option<Records> {
+        let addresses = any_address(&self.servers);
+        let tags = any_tags(&self.servers);
+        let mut headers = vec!["name", "state", "pid", "uptime_secs"];
+        if addresses {
+            headers.push("address");
+        }
+        if tags {
+            headers.push("tags");
+        }
+        if self.resources {
+            headers.extend(["rss_bytes", "cpu_percent"]);
+        }
+        headers.extend(["refcount", "clients"]);
 
-        println!("{}", serde_json::to_string_pretty(&items)?);
-        return Ok(());
+        let mut rows = Vec::new();
+        for (name, state, server_info, clients_lock) in &self.servers {
+            let mut row = vec![
+                name.clone(),
+                state.as_str().to_string(),
+                field(server_info.as_ref().map(|s| s.pid)),
+                field(server_info.as_ref().map(|s| s.uptime().as_secs())),
+            ];
+            if addresses {
+                row.push(field(server_info.as_ref().and_then(|s| s.address())));
+            }
+            if tags {
+                row.push(server_info.as_ref().map(format_tags).unwrap_or_default());
+            }
+            if self.resources {
+                let usage = server_info.as_ref().and_then(|s| s.resources.as_ref());
+                row.push(field(usage.map(|u| u.rss_bytes)));
+                row.push(field(
+                    usage
+                        .and_then(|u| u.cpu_percent)
+                        .map(|cpu| format!("{:.1}", cpu)),
+                ));
+            }
+            let clients: Vec<String> = clients_lock
+                .iter()
+                .flat_map(|c| c.clients.keys().map(|pid| pid.to_string()))
+                .collect();
+            row.push(
+                clients_lock
+                    .as_ref()
+                    .map_or(0, |c| c.refcount())
+                    .to_string(),
+            );
+            row.push(clients.join(","));
+            rows.push(row);
+        }
+        for (name, _) in &self.stopped {
+            let mut row = vec![name.clone(), "stopped".to_string()];
+            row.resize(headers.len() - 2, String::new());
+            row.extend(["0".to_string(), String::new()]);
+            rows.push(row);
+        }
+        Some(Records { headers, rows })
     }
+}
+
+/// Whether any server advertises an address, so the ADDRESS column is worth
+/// its width.
+fn any_address(servers: &[Listed]) -> bool {
+    servers
+        .iter()
+        .any(|(_, _, server_info, _)| server_info.as_ref().is_some_and(|s| s.address().is_some()))
+}
+
+/// Whether any server has tags, so the TAGS column is worth its width.
+fn any_tags(servers: &[Listed]) -> bool {
+    servers
+        .iter()
+        .any(|(_, _, server_info, _)| server_info.as_ref().is_some_and(|s| !s.tags.is_empty()))
+}
+
+/// A server's tags as `key=value`, comma-separated.
+fn format_tags(server: &ServerLock) -> String {
+    server
+        .tags
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect::<Vec<_>>()
+        .join(",")
+}
 
-    // Print header
-    println!(
-        "{:<20} {:<15} {:<10} {:<10} {}",
-        "NAME".bold(),
-        "STATE".bold(),
-        "PID".bold(),
-        "REFCOUNT".bold(),
-        "CLIENTS".bold()
-    );
-    println!("{}", "─".repeat(80).dimmed());
+/// `value`, or an empty field.
+fn field(value: Option<impl ToString>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_default()
+}
 
-    for (name, state, server_info) in servers {
-        let pid_str = server_info
+fn print_servers(servers: &[Listed], resources: bool, addresses: bool, tags: bool) {
+    let mut headers = vec!["NAME", "STATE", "PID", "UPTIME"];
+    if addresses {
+        headers.push("ADDRESS");
+    }
+    if tags {
+        headers.push("TAGS");
+    }
+    if resources {
+        headers.extend(["MEM", "CPU"]);
+    }
+    headers.extend(["REFCOUNT", "CLIENTS"]);
+    let mut table = Table::new(&headers);
+
+    for (name, state, server_info, clients_lock) in servers {
+        let pid = server_info
+            .as_ref()
+            .map(|s| format_pid(s.pid))
+            .unwrap_or_else(|| "-".dimmed());
+        let uptime = server_info
             .as_ref()
-            .map(|s| format_pid(s.pid).to_string())
-            .unwrap_or_else(|| "-".dimmed().to_string());
+            .map(|s| format_duration(s.uptime()))
+            .unwrap_or_else(|| "-".to_string());
 
-        // Read refcount and clients from ClientsLock if the server is active
-        let (refcount, clients) = if state == sharedserver::core::ServerState::Active {
-            if let Ok(clients_lock) = read_clients_lock(&name) {
+        let (refcount, clients) = match clients_lock {
+            Some(clients_lock) => {
                 let client_list: Vec<String> =
                     clients_lock.clients.keys().map(|k| k.to_string()).collect();
-                (clients_lock.refcount, client_list)
-            } else {
-                (0, vec![])
+                (clients_lock.refcount(), client_list)
             }
+            None => (0, vec![]),
+        };
+
+        let grace_remaining = server_info
+            .as_ref()
+            .filter(|_| *state == ServerState::Grace)
+            .and_then(|s| s.grace_remaining());
+        let state = if server_info.as_ref().is_some_and(|s| s.is_unhealthy()) {
+            format_unhealthy_state()
+        } else if let Some(remaining) = grace_remaining {
+            format_grace_state(remaining)
         } else {
-            (0, vec![])
+            format_server_state(state)
         };
 
-        println!(
-            "{:<20} {:<24} {:<10} {:<10} {}",
-            format_server_name(&name),
-            format_server_state(&state),
-            pid_str,
+        let mut row = vec![format_server_name(name), state, pid, uptime.into()];
+        if addresses {
+            let address = server_info.as_ref().and_then(|s| s.address());
+            row.push(address.map_or_else(|| "-".dimmed(), |a| a.normal()));
+        }
+        if tags {
+            let tags = server_info.as_ref().map(format_tags).unwrap_or_default();
+            row.push(if tags.is_empty() {
+                "-".dimmed()
+            } else {
+                tags.normal()
+            });
+        }
+        if resources {
+            let usage = server_info.as_ref().and_then(|s| s.resources.as_ref());
+            let memory = usage
+                .map(|u| format_bytes(u.rss_bytes))
+                .unwrap_or_else(|| "-".to_string());
+            let cpu = usage
+                .and_then(|u| u.cpu_percent)
+                .map(|cpu| format!("{:.0}%", cpu))
+                .unwrap_or_else(|| "-".to_string());
+            row.extend([memory.into(), cpu.into()]);
+        }
+        row.extend([
             format_refcount(refcount),
-            format_clients(&clients, 3)
+            format_clients(&clients, 3).into(),
+        ]);
+        table.row(row);
+    }
+    table.print();
+}
+
+/// `--tree`: each running server as a heading, with its processes and its
+/// attached clients drawn beneath it.
+fn print_trees(servers: &[Listed], trees: &BTreeMap<String, ProcessNode>) {
+    for (i, (name, state, server_info, clients_lock)) in servers.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        let uptime = server_info
+            .as_ref()
+            .map(|s| format!("  up {}", format_duration(s.uptime())))
+            .unwrap_or_default();
+        println!(
+            "{}  {}{}",
+            format_server_name(name),
+            format_server_state(state),
+            uptime.dimmed()
         );
+
+        let mut nodes = Vec::new();
+        if let (Some(server_info), Some(root)) = (server_info, trees.get(name)) {
+            nodes.push(process_tree_node(root, server_info));
+        }
+        if let Some(clients_lock) = clients_lock {
+            let mut clients: Vec<_> = clients_lock.clients.iter().collect();
+            clients.sort_by_key(|(pid, _)| **pid);
+            let clients = clients
+                .into_iter()
+                .map(|(pid, info)| {
+                    let mut label = format!(
+                        "{} {}",
+                        format_pid(*pid),
+                        client_name(*pid, info).unwrap_or_else(|| "?".to_string())
+                    );
+                    if let Some(metadata) = &info.metadata {
+                        label.push_str(&format!("  {}", metadata.dimmed()));
+                    }
+                    TreeNode::new(label, Vec::new())
+                })
+                .collect();
+            nodes.push(TreeNode::new("clients".bold().to_string(), clients));
+        }
+        print!("{}", TreeNode::render_all(&nodes));
     }
+}
+
+/// A process as a tree line, the watcher and the server labeled as such.
+fn process_tree_node(node: &ProcessNode, server_info: &ServerLock) -> TreeNode {
+    let role = if Some(node.pid) == server_info.watcher_pid && node.pid != server_info.pid {
+        "watcher "
+    } else if node.pid == server_info.pid {
+        "server "
+    } else {
+        ""
+    };
+    let name = node
+        .name
+        .as_deref()
+        .map(|name| format!(" ({})", name))
+        .unwrap_or_default();
+    TreeNode::new(
+        format!("{}{}{}", role.bold(), format_pid(node.pid), name),
+        node.children
+            .iter()
+            .map(|child| process_tree_node(child, server_info))
+            .collect(),
+    )
+}
+
+/// The `--recent` section: servers that went down within the retention
+/// period, most recent first.
+fn print_stopped(stopped: &[(String, Tombstone)]) {
+    println!("{}", "Recently stopped:".bold());
+    let mut table = Table::new(&["NAME", "STOPPED", "LAST EXIT", "CLIENTS"]);
+
+    for (name, tombstone) in stopped {
+        let ago = (chrono::Utc::now() - tombstone.exited_at)
+            .to_std()
+            .map(|ago| format!("{} ago", format_duration(ago)))
+            .unwrap_or_else(|_| "just now".to_string());
+        table.row(vec![
+            format_server_name(name),
+            ago.dimmed(),
+            format_last_exit(tombstone),
+            format_refcount(tombstone.last_refcount),
+        ]);
+    }
+    table.print();
+}
+
+/// One read: every live registry entry, with its state derived from the
+/// snapshot (liveness checks only, no per-server locking).
+fn from_registry(registry: &Registry) -> Vec<Listed> {
+    registry
+        .live_entries()
+        .map(|(name, entry)| {
+            let state = state_from_locks(&entry.server, || {
+                entry.clients.as_ref().map_or(0, |c| c.refcount())
+            });
+            let server_info = (state != ServerState::Stopped).then(|| entry.server.clone());
+            let clients = entry
+                .clients
+                .clone()
+                .filter(|_| state == ServerState::Active);
+            (name.clone(), state, server_info, clients)
+        })
+        .collect()
+}
+
+/// Fallback when there is no usable registry (e.g. locks written before it
+/// existed): find servers by their lockfiles and read each one, up to `jobs`
+/// at a time.
+fn scan_lockdir(jobs: usize) -> Result<Vec<Listed>> {
+    let names: Vec<String> = servers_with(&["server.json"])?.into_iter().collect();
+    let servers = pool::map(&names, jobs, |name| {
+        let state = get_server_state(name).ok()?;
+        let server_info = if state != ServerState::Stopped {
+            read_server_lock(name).ok()
+        } else {
+            None
+        };
+        let clients = if state == ServerState::Active {
+            read_clients_lock(name).ok()
+        } else {
+            None
+        };
+        Some((name.clone(), state, server_info, clients))
+    });
 
-    Ok(())
+    Ok(servers.into_iter().flatten().collect())
 }
@@ -0,0 +1,135 @@
+use anyhow::{Context, Result};
+use sharedserver::core::{is_process_alive, read_server_lock, ErrorKind};
+use std::net::{TcpListener, TcpStream};
+use std::time::{Duration, Instant};
+
+use super::r#use::UseFlags;
+use super::start::StartOptions;
+use crate::output::{self, format_server_name};
+
+/// How often to retry connecting while the server starts listening.
+const CONNECT_RETRY: Duration = Duration::from_millis(100);
+
+/// Share a language server over TCP with editors that only speak stdio LSP:
+/// attach to `name` (starting it with `command` on a free port, passed to it
+/// in `port_env`, if it isn't running), then relay this process's stdin and
+/// stdout to a connection to it until either side closes.
+///
+/// Unlike `proxy`, the server multiplexes its own sessions: each connection
+/// is a separate LSP session. This process is the client, so the reference
+/// lasts exactly as long as the editor's session.
+pub fn execute(
+    name: &str,
+    port_env: &str,
+    opts: &StartOptions,
+    connect_timeout: Duration,
+    metadata: Option<String>,
+    command: &[String],
+) -> Result<()> {
+    output::set_quiet(true);
+    if port_env.is_empty() || port_env.contains('=') {
+        return Err(ErrorKind::InvalidArgs.error(format!(
+            "Invalid --port-env '{}': expected a variable name",
+            port_env
+        )));
+    }
+    // Only used if the server is started here: one already running keeps
+    // the port it was started on.
+    let port = free_port()?;
+    let mut env_vars = opts.env_vars.clone();
+    env_vars.push(format!("{}={}", port_env, port));
+    let opts = StartOptions {
+        env_vars,
+        address: Some(format!("tcp:127.0.0.1:{}", port)),
+        ..opts.clone()
+    };
+
+    let client_pid = std::process::id() as i32;
+    super::r#use::execute(
+        name,
+        &opts,
+        metadata,
+        Some(client_pid),
+        UseFlags::default(),
+        command,
+    )?;
+    let relayed = connect(name, connect_timeout).and_then(super::proxy::relay);
+    let _ = super::unuse::execute(name, Some(client_pid), false);
+    relayed
+}
+
+/// A port nothing is listening on, for the server to take. Another process
+/// could take it first, but the server would then fail to start and say so.
+pub fn free_port() -> Result<u16> {
+    let listener =
+        TcpListener::bind(("127.0.0.1", 0)).context("Failed to find a free port for the server")?;
+    Ok(listener.local_addr()?.port())
+}
+
+/// The `HOST:PORT` of a `tcp:` address.
+fn tcp_target(address: &str) -> Option<&str> {
+    address.strip_prefix("tcp:")
+}
+
+/// Connect to `name`'s TCP address, retrying until it accepts: a server just
+/// started needs a moment before it listens.
+fn connect(name: &str, timeout: Duration) -> Result<TcpStream> {
+    let started = Instant::now();
+    loop {
+        let lock = read_server_lock(name)?;
+        let Some(address) = lock.address() else {
+            return Err(ErrorKind::InvalidArgs.error(format!(
+                "Server {} has no address to connect to: it was not started by `lsp` or with --address",
+                format_server_name(name)
+            )));
+        };
+        let Some(target) = tcp_target(&address) else {
+            return Err(ErrorKind::InvalidArgs.error(format!(
+                "Server {} listens on {}, not a TCP port",
+                format_server_name(name),
+                address
+            )));
+        };
+        match TcpStream::connect(target) {
+            Ok(stream) => {
+                // LSP traffic is small request/response messages.
+                let _ = stream.set_nodelay(true);
+                return Ok(stream);
+            }
+            Err(e) if !is_process_alive(lock.pid) => {
+                return Err(ErrorKind::NotRunning.wrap(
+                    anyhow::Error::new(e),
+                    format!(
+                        "Server {} exited before accepting",
+                        format_server_name(name)
+                    ),
+                ));
+            }
+            Err(e) if started.elapsed() >= timeout => {
+                return Err(ErrorKind::StartTimeout.wrap(
+                    anyhow::Error::new(e),
+                    format!(
+                        "Server {} did not accept connections on {} within {:?}",
+                        format_server_name(name),
+                        target,
+                        timeout
+                    ),
+                ));
+            }
+            Err(_) => std::thread::sleep(CONNECT_RETRY),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_free_port_and_tcp_target() {
+        let port = free_port().unwrap();
+        assert!(TcpListener::bind(("127.0.0.1", port)).is_ok());
+        assert_eq!(tcp_target("tcp:127.0.0.1:8080"), Some("127.0.0.1:8080"));
+        assert_eq!(tcp_target("unix:/tmp/lsp.sock"), None);
+    }
+}
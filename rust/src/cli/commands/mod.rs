@@ -1,12 +1,26 @@
 pub mod check;
+pub mod config;
+pub mod daemon;
 pub mod debug;
 pub mod decref;
+pub mod direnv;
 pub mod doctor;
+pub mod events;
+pub mod exit_codes;
+pub mod export;
+pub mod hook;
 pub mod incref;
 pub mod info;
 pub mod kill;
+pub mod last;
 pub mod list;
+pub mod lsp;
+pub mod prompt;
+pub mod proxy;
+pub mod prune;
 pub mod start;
 pub mod stop;
+pub mod tmux;
 pub mod unuse;
+pub mod up;
 pub mod r#use;
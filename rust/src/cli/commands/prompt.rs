@@ -0,0 +1,98 @@
+use anyhow::Result;
+use colored::*;
+use serde::Serialize;
+use sharedserver::core::registry::{peek_registry, read_registry};
+use sharedserver::core::{is_descendant, state_from_locks, ServerState};
+
+use crate::output::{print_report, Glyph, OutputFormat, Report};
+
+/// The servers a shell (or anything started from it) is attached to, for
+/// embedding in a prompt.
+#[derive(Serialize)]
+#[serde(transparent)]
+struct PromptReport(Vec<Attached>);
+
+#[derive(Serialize)]
+struct Attached {
+    name: String,
+    state: &'static str,
+    unhealthy: bool,
+    /// The attached clients in the shell's process tree.
+    clients: Vec<i32>,
+}
+
+/// Whether `client` is in `shell`'s process tree: the shell itself, something
+/// started from it (an editor), or a process it runs under (an outer shell
+/// or a wrapper's parent). PID 1 is everyone's ancestor, so never counts.
+fn in_tree(client: i32, shell: i32) -> bool {
+    client > 1 && (is_descendant(client, shell) || is_descendant(shell, client))
+}
+
+/// Print a compact summary of the servers `pid`'s process tree is attached
+/// to. Runs on every prompt, so it reads only the registry, without taking
+/// its lock, and prints nothing rather than fail.
+pub fn execute(pid: Option<i32>, format: OutputFormat) -> Result<()> {
+    // The shell that runs us for its prompt is our parent.
+    let shell = pid.unwrap_or_else(|| nix::unistd::getppid().as_raw());
+    let Ok(registry) = peek_registry().or_else(|_| read_registry()) else {
+        return print_report(format, &PromptReport(Vec::new()));
+    };
+
+    let mut attached = Vec::new();
+    for (name, entry) in registry.live_entries() {
+        let Some(clients_lock) = &entry.clients else {
+            continue;
+        };
+        let state = state_from_locks(&entry.server, || clients_lock.refcount());
+        if state == ServerState::Stopped {
+            continue;
+        }
+        let mut clients: Vec<i32> = clients_lock
+            .clients
+            .iter()
+            .filter(|(pid, client)| client.is_alive(**pid) && in_tree(**pid, shell))
+            .map(|(pid, _)| *pid)
+            .collect();
+        if clients.is_empty() {
+            continue;
+        }
+        clients.sort_unstable();
+        attached.push(Attached {
+            name: name.clone(),
+            state: state.as_str(),
+            unhealthy: entry.server.is_unhealthy(),
+            clients,
+        });
+    }
+    print_report(format, &PromptReport(attached))
+}
+
+impl Attached {
+    /// ✓ if all is well, ⚠ if the health probe is failing, ✗ if the server
+    /// is going away.
+    fn symbol(&self) -> ColoredString {
+        match (self.state, self.unhealthy) {
+            ("defunct", _) => Glyph::Cross.as_str().red(),
+            (_, true) => Glyph::Warning.as_str().yellow(),
+            ("active", false) => Glyph::Check.as_str().green(),
+            _ => Glyph::Warning.as_str().yellow(),
+        }
+    }
+}
+
+/// `[name✓ other⚠]`, or nothing at all when the shell isn't attached to
+/// anything, so the prompt stays clean.
+impl Report for PromptReport {
+    fn print_table(&self) -> Result<()> {
+        if self.0.is_empty() {
+            return Ok(());
+        }
+        let servers: Vec<String> = self
+            .0
+            .iter()
+            .map(|server| format!("{}{}", server.name, server.symbol()))
+            .collect();
+        println!("[{}]", servers.join(" "));
+        Ok(())
+    }
+}
@@ -0,0 +1,118 @@
+use anyhow::{Context, Result};
+use sharedserver::core::stdio_mux::SOCKET_FILE;
+use sharedserver::core::{read_server_lock, ErrorKind};
+use std::io::{Read, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::net::UnixStream;
+
+use super::start::StartOptions;
+use crate::output;
+
+/// Share a stdio server: attach to `name` (starting it with `command` behind
+/// a stdio socket if it isn't running), then relay this process's stdin and
+/// stdout to it until either side closes.
+///
+/// This process is the client, so the reference lasts exactly as long as the
+/// session. stdout carries the protocol, so nothing else is printed there.
+pub fn execute(
+    name: &str,
+    opts: &StartOptions,
+    metadata: Option<String>,
+    command: &[String],
+) -> Result<()> {
+    output::set_quiet(true);
+    let client_pid = std::process::id() as i32;
+    let opts = StartOptions {
+        stdio: true,
+        ..opts.clone()
+    };
+    super::r#use::execute(
+        name,
+        &opts,
+        metadata,
+        Some(client_pid),
+        super::r#use::UseFlags::default(),
+        command,
+    )?;
+    let relayed = connect(name).and_then(relay);
+    let _ = super::unuse::execute(name, Some(client_pid), false);
+    relayed
+}
+
+fn connect(name: &str) -> Result<UnixStream> {
+    let lock = read_server_lock(name)?;
+    if !lock.stdio {
+        return Err(ErrorKind::InvalidArgs.error(format!(
+            "Server '{}' was not started by `proxy`, so its stdin and stdout can't be shared",
+            name
+        )));
+    }
+    let socket = sharedserver::core::lockfile::server_file(name, SOCKET_FILE)?;
+    UnixStream::connect(&socket)
+        .with_context(|| format!("Failed to connect to {}", socket.display()))
+}
+
+/// Copy stdin to `stream` and `stream` to stdout until either reaches EOF.
+pub(super) fn relay<S: Read + Write + AsRawFd>(mut stream: S) -> Result<()> {
+    let mut stdout = std::io::stdout().lock();
+    let mut buf = [0u8; 65536];
+    let mut fds = [
+        libc::pollfd {
+            fd: libc::STDIN_FILENO,
+            events: libc::POLLIN,
+            revents: 0,
+        },
+        libc::pollfd {
+            fd: stream.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        },
+    ];
+    loop {
+        // SAFETY: `fds` is a valid, correctly-sized array of pollfd.
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } < 0 {
+            let err = std::io::Error::last_os_error();
+            if err.kind() == std::io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err).context("poll failed");
+        }
+        if fds[0].revents != 0 {
+            // Read the descriptor directly: std's stdin buffers, which poll
+            // can't see.
+            // SAFETY: reading into a local buffer of the given length.
+            let n = unsafe {
+                libc::read(
+                    libc::STDIN_FILENO,
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                )
+            };
+            if n <= 0 {
+                let err = std::io::Error::last_os_error();
+                if n < 0 && err.kind() == std::io::ErrorKind::Interrupted {
+                    continue;
+                }
+                // The client hung up: the session is over.
+                return Ok(());
+            }
+            stream
+                .write_all(&buf[..n as usize])
+                .context("The server's socket closed")?;
+        }
+        if fds[1].revents != 0 {
+            let n = match stream.read(&mut buf) {
+                Ok(n) => n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e).context("Failed to read from the server's socket"),
+            };
+            if n == 0 {
+                // The server went away (or was restarted, which ends every
+                // session with it).
+                return Ok(());
+            }
+            stdout.write_all(&buf[..n])?;
+            stdout.flush()?;
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use anyhow::Result;
+use colored::*;
+use sharedserver::core::log::{prune_logs, PruneAction, PrunedLog};
+use sharedserver::core::ServerFilter;
+
+use crate::output::{format_bytes, format_server_name, print_success, Glyph};
+
+/// Garbage-collect server logs: drop those of long-gone servers and trim
+/// oversized ones. With `filter`, only the logs of the servers it selects.
+pub fn execute(dry_run: bool, filter: &ServerFilter) -> Result<()> {
+    let pruned = prune_logs(dry_run, filter)?;
+    if pruned.is_empty() {
+        println!("{}", "No logs to prune".dimmed());
+        return Ok(());
+    }
+    print_pruned(&pruned, dry_run, "");
+    if !dry_run {
+        print_success(&format!("Pruned {} log(s)", pruned.len()));
+    }
+    Ok(())
+}
+
+/// One line per pruned log, each prefixed with `indent`.
+pub fn print_pruned(pruned: &[PrunedLog], dry_run: bool, indent: &str) {
+    for log in pruned {
+        let action = match (&log.action, dry_run) {
+            (PruneAction::Removed { size }, false) => format!("removed ({})", format_bytes(*size)),
+            (PruneAction::Removed { size }, true) => {
+                format!("would remove ({})", format_bytes(*size))
+            }
+            (PruneAction::Trimmed { from, to }, false) => {
+                format!(
+                    "trimmed {} {} {}",
+                    format_bytes(*from),
+                    Glyph::Arrow,
+                    format_bytes(*to)
+                )
+            }
+            (PruneAction::Trimmed { from, to }, true) => {
+                format!(
+                    "would trim {} {} {}",
+                    format_bytes(*from),
+                    Glyph::Arrow,
+                    format_bytes(*to)
+                )
+            }
+        };
+        println!(
+            "{}{} {}/{}: {}",
+            indent,
+            Glyph::Bullet.as_str().cyan(),
+            format_server_name(&log.name),
+            log.file,
+            action
+        );
+    }
+}
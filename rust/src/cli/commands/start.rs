@@ -2,56 +2,281 @@ use anyhow::{anyhow, bail, Context, Result};
 use nix::sys::signal::{kill, killpg, Signal};
 use nix::sys::wait::waitpid;
 use nix::unistd::{fork, setpgid, setsid, ForkResult, Pid};
+use serde::{Deserialize, Serialize};
+use sharedserver::core::address::{claimed_by, parse_address};
+use sharedserver::core::capacity::{self, Capacity};
+use sharedserver::core::config::Config;
+use sharedserver::core::container::{self, Container};
+use sharedserver::core::exe::ExeSnapshot;
+use sharedserver::core::front::{self, Front};
+use sharedserver::core::grace::{GraceClock, GracePeriod};
+use sharedserver::core::lockfile::current_uid;
+use sharedserver::core::log_capture::LogCapture;
+use sharedserver::core::notify::parse_signal;
+use sharedserver::core::sd_notify;
+use sharedserver::core::shared::shared_group;
+use sharedserver::core::stdio_mux::{self, StdioMux};
+use sharedserver::core::telemetry;
+use sharedserver::core::webhook::{self, WEBHOOK_ENV};
 use sharedserver::core::{
-    delete_clients_lock, delete_server_lock, get_server_state, is_process_alive, parse_duration,
-    process_start_stamp, read_server_lock, server_lock_exists, write_clients_lock,
-    write_server_lock, ClientInfo, ClientsLock, ServerLock, ServerState,
+    boot_id, delete_clients_lock, delete_server_lock, get_server_state, is_process_alive,
+    process_start_stamp, read_server_lock, server_lock_exists, watcher_alive, with_lock,
+    write_clients_lock, write_server_lock, ClientInfo, ClientsLock, ErrorKind, HealthCheck,
+    ResourceLimits, RestartPolicy, ServerLock, ServerState,
 };
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::Duration;
 
-/// Start a server with no initial clients (refcount=0)
+use crate::output::{print_info, Progress};
+use crate::watcher::Relaunch;
+
+/// How often `use --queue` checks whether a server has stopped to make room.
+const QUEUE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The config's caps on running servers (see [`set_capacity`]).
+static CAPACITY: OnceLock<Capacity> = OnceLock::new();
+
+/// Cap the servers this process starts (or the daemon starts for others) at
+/// `capacity`. Only the first call counts.
+pub fn set_capacity(capacity: Capacity) {
+    let _ = CAPACITY.set(capacity);
+}
+
+/// Launch settings shared by `admin start`, `use`, and `use --replace`.
+///
+/// Values are kept as the user typed them and validated when the server is
+/// started, matching how the CLI passes them through.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StartOptions {
+    /// Grace period before shutdown when refcount reaches 0 (e.g. "5m")
+    pub grace_period: String,
+    /// Clock the grace period is measured on: "monotonic" or "wall"
+    pub grace_clock: String,
+    /// Environment overrides in `KEY=VALUE` form
+    pub env_vars: Vec<String>,
+    /// Optional log file for server stdout/stderr
+    pub log_file: Option<String>,
+    /// Prefix each line of server output in `log_file` with a timestamp and
+    /// stream tag
+    pub log_timestamps: bool,
+    /// Restart policy: "never", "on-failure", or "always"
+    pub restart: String,
+    /// Optional health probe run periodically by the watcher
+    pub health_check: Option<HealthCheck>,
+    /// Optional memory/CPU limits enforced by the watcher
+    pub limits: Option<ResourceLimits>,
+    /// Signal to send attached clients before the server is shut down
+    pub notify_clients: Option<String>,
+    /// Leave the server running if the watcher is sent SIGTERM
+    pub linger: bool,
+    /// Where the server can be reached, shown by `list` and `info`
+    pub address: Option<String>,
+    /// A stable address to forward to `address` from, across relaunches
+    pub listen: Option<String>,
+    /// URL to POST lifecycle events to [default: `SHAREDSERVER_WEBHOOK`]
+    pub on_event_webhook: Option<String>,
+    /// Show a desktop notification on grace entry and shutdown
+    pub notify_desktop: bool,
+    /// What the server runs as: "process", "docker", or "podman"
+    pub backend: String,
+    /// Image to run with a container backend
+    pub image: Option<String>,
+    /// `key=value` labels to select the server by
+    pub tags: BTreeMap<String, String>,
+    /// The config files as found from the client's directory, before any
+    /// move to the server's `cwd`; empty to find them from its `cwd`
+    pub config_files: Vec<PathBuf>,
+    /// Share the server's stdin/stdout through a socket (`proxy`). Never set
+    /// by the daemon's callers: only a watcher can run the mux.
+    #[serde(skip)]
+    pub stdio: bool,
+    /// If starting would go over a cap on running servers, wait for a slot
+    /// rather than failing (`use --queue`). Never sent to the daemon, which
+    /// mustn't block on one client's behalf.
+    #[serde(skip)]
+    pub queue: bool,
+}
+
+/// The CLI's defaults, for callers of the daemon's HTTP API that leave
+/// options out.
+impl Default for StartOptions {
+    fn default() -> Self {
+        Self {
+            grace_period: super::r#use::DEFAULT_GRACE_PERIOD.to_string(),
+            grace_clock: "monotonic".to_string(),
+            env_vars: Vec::new(),
+            log_file: None,
+            log_timestamps: false,
+            restart: "never".to_string(),
+            health_check: None,
+            limits: None,
+            notify_clients: None,
+            linger: false,
+            address: None,
+            listen: None,
+            on_event_webhook: None,
+            notify_desktop: false,
+            backend: "process".to_string(),
+            image: None,
+            tags: BTreeMap::new(),
+            config_files: Vec::new(),
+            stdio: false,
+            queue: false,
+        }
+    }
+}
+
+impl StartOptions {
+    /// The container `name` runs in, or `None` for a local process.
+    pub fn container(&self, name: &str) -> Result<Option<Container>> {
+        Container::new(name, self.backend.parse()?, self.image.as_deref())
+    }
+}
+
+/// The working directory and environment of the client a server is started
+/// for. A watcher forked from the client inherits both; the daemon launches
+/// servers from its own process, so it is handed them instead.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientContext {
+    pub cwd: PathBuf,
+    pub env: Vec<(String, String)>,
+}
+
+impl ClientContext {
+    /// This process's.
+    pub fn current() -> Result<Self> {
+        Ok(Self {
+            cwd: std::env::current_dir().context("Failed to read the working directory")?,
+            env: std::env::vars().collect(),
+        })
+    }
+}
+
+/// Start a server with no initial clients (refcount=0). With `via_daemon`,
+/// a running daemon launches and supervises it instead.
 pub fn execute(
     name: &str,
-    grace_period: &str,
-    env_vars: &[String],
+    opts: &StartOptions,
     command: &[String],
-    log_file: Option<&str>,
+    via_daemon: bool,
 ) -> Result<()> {
-    execute_internal(name, grace_period, env_vars, command, None, log_file)
+    if via_daemon {
+        let params = crate::daemon::StartParams {
+            name: name.to_string(),
+            command: command.to_vec(),
+            options: opts.clone(),
+            context: ClientContext::current()?,
+        };
+        if crate::daemon::call("start", serde_json::to_value(params)?)?.is_some() {
+            return Ok(());
+        }
+        log::debug!("no daemon running; acting directly");
+    }
+    execute_internal(name, opts, command, HashMap::new())
 }
 
 /// Start a server with an initial client atomically (refcount=1)
 /// This is used by the `use` command to avoid the refcount=0 window
 pub fn execute_with_client(
     name: &str,
-    grace_period: &str,
-    env_vars: &[String],
+    opts: &StartOptions,
     command: &[String],
     client_pid: i32,
     metadata: Option<String>,
-    log_file: Option<&str>,
 ) -> Result<()> {
-    execute_internal(
-        name,
-        grace_period,
-        env_vars,
-        command,
-        Some((client_pid, metadata)),
-        log_file,
-    )
+    let mut clients = HashMap::new();
+    clients.insert(client_pid, ClientInfo::new(client_pid, metadata));
+    execute_internal(name, opts, command, clients)
 }
 
-fn execute_internal(
+/// Start a server seeded with an existing set of clients.
+///
+/// Used by `use --replace` to carry the previous instance's clients over to its
+/// replacement, so nobody who was attached loses their reference.
+pub fn execute_with_clients(
     name: &str,
-    grace_period: &str,
-    env_vars: &[String],
+    opts: &StartOptions,
     command: &[String],
-    initial_client: Option<(i32, Option<String>)>,
-    log_file: Option<&str>,
+    clients: HashMap<i32, ClientInfo>,
 ) -> Result<()> {
+    execute_internal(name, opts, command, clients)
+}
+
+/// Validate the options, check `name` isn't already running, and write its
+/// lockfiles with `initial_clients` attached. The server lock names this
+/// process (and, for the daemon, `cwd`) until the launch publishes the real
+/// PIDs.
+pub(crate) fn prepare_launch(
+    name: &str,
+    opts: &StartOptions,
+    command: &[String],
+    initial_clients: HashMap<i32, ClientInfo>,
+    cwd: Option<PathBuf>,
+) -> Result<ClientsLock> {
+    let grace_period = opts.grace_period.as_str();
+    let env_vars = opts.env_vars.as_slice();
+    let log_file = opts.log_file.as_deref();
+
     // Validate grace period
-    let _grace_duration = parse_duration(grace_period)
-        .with_context(|| format!("Invalid grace period: {}", grace_period))?;
+    let _grace: GracePeriod = grace_period.parse().map_err(|e| {
+        ErrorKind::InvalidArgs.wrap(e, format!("Invalid grace period: {}", grace_period))
+    })?;
+    let invalid = |e: anyhow::Error| ErrorKind::InvalidArgs.wrap(e, "Invalid server options");
+    let grace_clock: GraceClock = opts.grace_clock.parse().map_err(invalid)?;
+    let restart: RestartPolicy = opts.restart.parse().map_err(invalid)?;
+    if let Some(check) = &opts.health_check {
+        check.validate().map_err(invalid)?;
+    }
+    if let Some(limits) = &opts.limits {
+        limits.validate().map_err(invalid)?;
+    }
+    if let Some(signal) = &opts.notify_clients {
+        parse_signal(signal).map_err(invalid)?;
+    }
+    let address = opts
+        .address
+        .as_deref()
+        .map(parse_address)
+        .transpose()
+        .map_err(invalid)?;
+    let listen = opts
+        .listen
+        .as_deref()
+        .map(parse_address)
+        .transpose()
+        .map_err(invalid)?;
+    if let Some(listen) = &listen {
+        let upstream = address.clone().or_else(|| {
+            opts.health_check
+                .as_ref()
+                .and_then(|check| check.probe.address())
+        });
+        match upstream {
+            None => {
+                return Err(ErrorKind::InvalidArgs
+                    .error("--listen needs the server's own address to forward to: add --address"))
+            }
+            Some(upstream) if upstream == *listen => {
+                return Err(ErrorKind::InvalidArgs.error(format!(
+                    "--listen {} is the server's own address: the front needs one of its own",
+                    listen
+                )))
+            }
+            Some(_) => {}
+        }
+    }
+    let webhook = opts.on_event_webhook.clone().or_else(|| {
+        std::env::var(WEBHOOK_ENV)
+            .ok()
+            .filter(|url| !url.is_empty())
+    });
+    if let Some(url) = &webhook {
+        webhook::validate(url).map_err(invalid)?;
+    }
+    let container = opts.container(name).map_err(invalid)?;
 
     // Check current state
     let state = get_server_state(name)?;
@@ -59,27 +284,35 @@ fn execute_internal(
     match state {
         ServerState::Active | ServerState::Grace => {
             let server = read_server_lock(name)?;
-            bail!(
+            return Err(ErrorKind::AlreadyRunning.error(format!(
                 "Server '{}' is already running (PID: {}, state: {})",
                 name,
                 server.pid,
                 state.as_str()
-            );
+            )));
         }
         ServerState::Defunct => {
             // Previous instance died but its watcher hasn't finished reaping and
             // removing the lockfiles yet. Don't race the watcher's cleanup.
-            bail!(
+            return Err(ErrorKind::ShuttingDown.error(format!(
                 "Server '{}' is shutting down (defunct, cleanup pending). Retry shortly, \
                  or run 'sharedserver admin kill {}' if it is stuck.",
-                name,
-                name
-            );
+                name, name
+            )));
         }
         ServerState::Stopped => {
             // Clean up any stale locks
             if server_lock_exists(name) {
                 let server = read_server_lock(name)?;
+                // A live watcher with a dead server is mid-restart (restart
+                // policy): the lock is about to name the relaunched server.
+                if watcher_alive(&server) {
+                    return Err(ErrorKind::ShuttingDown.error(format!(
+                        "Server '{}' is restarting (watcher PID {} is relaunching it). Retry shortly.",
+                        name,
+                        server.watcher_pid.unwrap_or_default()
+                    )));
+                }
                 if !is_process_alive(server.pid) {
                     eprintln!("Warning: Cleaning up stale lock for server '{}'", name);
                     let _ = delete_server_lock(name);
@@ -90,32 +323,147 @@ fn execute_internal(
     }
 
     // Create initial lockfiles (with placeholder PID)
+    // The server's PATH: an override, else the one it inherits from us.
+    let server_path = env_vars
+        .iter()
+        .rev()
+        .find_map(|var| var.strip_prefix("PATH=").map(str::to_string))
+        .or_else(|| std::env::var("PATH").ok());
+    let cwd = cwd.or_else(|| std::env::current_dir().ok());
     let server_lock = ServerLock {
         pid: std::process::id() as i32,
         command: command.to_vec(),
         grace_period: grace_period.to_string(),
+        grace_clock,
         watcher_pid: None,
         started_at: chrono::Utc::now(),
         // Filled in by the watcher once it knows the real server PID.
         start_time: None,
         watcher_start_time: None,
+        boot_id: boot_id(),
+        env: env_vars.to_vec(),
+        log_file: log_file.map(str::to_string),
+        log_timestamps: opts.log_timestamps && log_file.is_some(),
+        restart,
+        health_check: opts.health_check.clone(),
+        limits: opts.limits.clone(),
+        notify_signal: opts.notify_clients.clone(),
+        linger: opts.linger,
+        webhook,
+        notify_desktop: opts.notify_desktop,
+        owner_uid: Some(current_uid()),
+        shared_group: shared_group(),
+        // A container's command is resolved inside the image, not here.
+        executable: command
+            .first()
+            .filter(|_| container.is_none())
+            .and_then(|program| ExeSnapshot::capture(program, server_path.as_deref())),
+        config_files: if opts.config_files.is_empty() {
+            cwd.as_deref().map(Config::paths_from).unwrap_or_default()
+        } else {
+            opts.config_files.clone()
+        },
+        cwd,
+        address,
+        listen,
+        stdio: opts.stdio,
+        container,
+        tags: opts.tags.clone(),
+        ..Default::default()
     };
 
-    write_server_lock(name, &server_lock).context("Failed to create server lockfile")?;
+    claim_slot(name, opts, || {
+        // Only one of them could bind it; the other would fail, or worse,
+        // reach the wrong server's clients.
+        for address in server_lock.claimed_addresses() {
+            if let Some(other) = claimed_by(&address, name) {
+                return Err(ErrorKind::InvalidArgs.error(format!(
+                    "Server '{}' would listen on {}, which server '{}' is already using",
+                    name, address, other
+                )));
+            }
+        }
+        write_server_lock(name, &server_lock).context("Failed to create server lockfile")
+    })?;
 
     // Always create the clients lockfile. It lives for the whole life of the
     // server and is the single mutual-exclusion point for refcount changes; it
     // is no longer deleted when the refcount hits zero (refcount 0 == grace).
     // `use` seeds it with one client (Active); a bare `admin start` seeds it
-    // empty (refcount 0 == grace immediately, as before).
+    // empty (refcount 0 == grace immediately, as before); `use --replace`
+    // seeds it with the clients of the instance being replaced.
     let mut clients = ClientsLock::new();
-    if let Some((client_pid, metadata)) = initial_client {
-        clients
-            .clients
-            .insert(client_pid, ClientInfo::new(metadata));
-    }
-    clients.refcount = clients.clients.len() as u32;
+    clients.clients = initial_clients;
     write_clients_lock(name, &clients).context("Failed to create clients lockfile")?;
+    Ok(clients)
+}
+
+/// Run `claim`, which checks `name`'s addresses are free and writes its
+/// server lock, once starting it leaves the servers running within the caps
+/// (see [`set_capacity`]). Over a cap, fail, or with `opts.queue` wait until
+/// a server stops. The claim (and the count) always happen under the start
+/// lock, so racing starts can't both take the last slot or the same address.
+fn claim_slot(name: &str, opts: &StartOptions, claim: impl FnOnce() -> Result<()>) -> Result<()> {
+    let unlimited = Capacity::default();
+    let capacity = CAPACITY.get().unwrap_or(&unlimited);
+    let path = capacity::start_lock_path()?;
+    let mut claim = Some(claim);
+    let mut waiting = false;
+    loop {
+        let full = with_lock(&path, |_| {
+            let running = if capacity.is_unlimited() {
+                Vec::new()
+            } else {
+                capacity::running_servers(name)?
+            };
+            match capacity.exceeded(&opts.tags, &running) {
+                Some(full) => Ok(Some(full)),
+                None => (claim.take().expect("claimed only once"))().map(|()| None),
+            }
+        })?;
+        let Some(full) = full else {
+            return Ok(());
+        };
+        if !opts.queue {
+            return Err(ErrorKind::AtCapacity.error(format!(
+                "Can't start '{}': {}. Pass --queue to wait for one to stop",
+                name, full
+            )));
+        }
+        if !waiting {
+            print_info(&format!("Waiting to start '{}': {}", name, full));
+            waiting = true;
+        }
+        std::thread::sleep(QUEUE_POLL_INTERVAL);
+    }
+}
+
+fn execute_internal(
+    name: &str,
+    opts: &StartOptions,
+    command: &[String],
+    initial_clients: HashMap<i32, ClientInfo>,
+) -> Result<()> {
+    let clients = prepare_launch(name, opts, command, initial_clients, None)?;
+    let container = opts.container(name)?;
+    // A `proxy` server's socket and a `--listen` front's are bound here
+    // rather than in the watcher, so a failure reaches the user.
+    let listeners = opts
+        .stdio
+        .then(|| listen_stdio(name))
+        .transpose()
+        .and_then(|stdio| Ok((stdio, listen_front(name)?)));
+    let (listener, front) = match listeners {
+        Ok(listeners) => listeners,
+        Err(e) => {
+            let _ = delete_server_lock(name);
+            let _ = delete_clients_lock(name);
+            return Err(e);
+        }
+    };
+    let grace_period = opts.grace_period.as_str();
+    let env_vars = opts.env_vars.as_slice();
+    let log_file = opts.log_file.as_deref();
 
     // Double fork strategy:
     // 1. First fork: Parent = sharedserver (returns), Child = watcher
@@ -131,6 +479,7 @@ fn execute_internal(
         Ok(ForkResult::Child) => {
             // First child: become the watcher process
             setsid().context("Failed to create new session for watcher")?;
+            crate::logging::route_to_watcher_log(name);
 
             // CRITICAL: Redirect watcher's stdout/stderr immediately to prevent blocking
             // on inherited pipes from parent process when writing errors/logs
@@ -150,127 +499,69 @@ fn execute_internal(
                 }
             }
 
-            let watcher_pid = std::process::id() as i32;
+            // With --log-timestamps the server writes into pipes relayed to the
+            // log file. If the relay can't be set up, fall back to plain
+            // redirection rather than fail the start.
+            let capture = log_file
+                .filter(|_| opts.log_timestamps)
+                .and_then(|path| LogCapture::spawn(path).ok());
+            let stdio = match listener
+                .map(|(listener, socket)| StdioMux::spawn(listener, &socket))
+                .transpose()
+            {
+                Ok(stdio) => stdio,
+                Err(e) => {
+                    eprintln!("Watcher: {:#}", e);
+                    std::process::exit(1);
+                }
+            };
+            let front = match front
+                .map(|(listener, upstream)| Front::spawn(listener, &upstream))
+                .transpose()
+            {
+                Ok(front) => front,
+                Err(e) => {
+                    eprintln!("Watcher: {:#}", e);
+                    std::process::exit(1);
+                }
+            };
+            let relaunch = Relaunch {
+                capture,
+                context: None,
+                stdio,
+                front,
+            };
 
             // Fork again to create the actual server process
-            match unsafe { fork() } {
-                Ok(ForkResult::Parent {
-                    child: server_child,
-                }) => {
-                    // Watcher process: update locks with real PIDs
-                    let mut server_lock = match read_server_lock(name) {
-                        Ok(lock) => lock,
-                        Err(e) => {
-                            eprintln!("Watcher: Failed to read server lock ({}), cleaning up", e);
-                            let _ = delete_server_lock(name);
-                            let _ = delete_clients_lock(name);
-                            std::process::exit(1);
-                        }
-                    };
-                    server_lock.pid = server_child.as_raw();
-                    server_lock.watcher_pid = Some(watcher_pid);
-                    // Capture start stamps now so later liveness checks can
-                    // detect PID reuse (see process_liveness_checked).
-                    server_lock.start_time = process_start_stamp(server_child.as_raw());
-                    server_lock.watcher_start_time = process_start_stamp(watcher_pid);
-
-                    if let Err(e) = write_server_lock(name, &server_lock) {
-                        eprintln!("Watcher: Failed to update server lock ({}), cleaning up", e);
-                        let _ = delete_server_lock(name);
-                        let _ = delete_clients_lock(name);
+            match spawn_server(
+                name,
+                command,
+                env_vars,
+                log_file,
+                &relaunch,
+                container.as_ref(),
+            ) {
+                Ok(server_child) => {
+                    // Under systemd, hand supervision to the watcher before the
+                    // CLI (the unit's original main process) sees the publish
+                    // below and exits.
+                    sd_notify::notify_ready();
+
+                    if let Err(e) = publish_launch(name, server_child.as_raw(), false) {
+                        eprintln!("Watcher: {:#}", e);
                         std::process::exit(1);
                     }
 
                     // Run watcher (never returns unless server dies)
-                    if let Err(e) = crate::watcher::run_watcher(name, grace_period) {
+                    if let Err(e) = crate::watcher::run_watcher(name, grace_period, relaunch) {
                         eprintln!("Watcher error: {:#}", e);
                         std::process::exit(1);
                     }
 
                     std::process::exit(0);
                 }
-                Ok(ForkResult::Child) => {
-                    // Grandchild: become the actual server process
-
-                    // Put the server in its own process group so we can kill the
-                    // entire tree (including children like uv→python) with killpg().
-                    // The watcher is in a separate session (setsid above) so it
-                    // won't be affected.
-                    let _ = setpgid(Pid::from_raw(0), Pid::from_raw(0));
-
-                    // Redirect stdin to /dev/null (required for servers like workspace-mcp)
-                    // stdout/stderr go to log_file if provided, otherwise /dev/null
-                    use std::fs::OpenOptions;
-                    use std::os::unix::io::IntoRawFd;
-
-                    // stdin always goes to /dev/null. into_raw_fd() transfers
-                    // ownership out of the File so the explicit libc::close is the
-                    // only close — a double close aborts under std's debug-mode
-                    // I/O-safety guard (release tolerates it).
-                    if let Ok(devnull) = OpenOptions::new().read(true).open("/dev/null") {
-                        let fd = devnull.into_raw_fd();
-                        unsafe {
-                            let flags = libc::fcntl(fd, libc::F_GETFD);
-                            libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC);
-                            libc::dup2(fd, 0); // stdin
-                            libc::close(fd);
-                        }
-                    }
-
-                    // stdout/stderr: log_file or /dev/null
-                    if let Some(log_path) = log_file {
-                        // Redirect to log file
-                        if let Ok(logfile) =
-                            OpenOptions::new().create(true).append(true).open(log_path)
-                        {
-                            let fd = logfile.into_raw_fd();
-                            unsafe {
-                                let flags = libc::fcntl(fd, libc::F_GETFD);
-                                libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC);
-                                libc::dup2(fd, 1); // stdout
-                                libc::dup2(fd, 2); // stderr
-                                libc::close(fd);
-                            }
-                        }
-                    } else {
-                        // Redirect to /dev/null
-                        if let Ok(devnull) = OpenOptions::new().write(true).open("/dev/null") {
-                            let fd = devnull.into_raw_fd();
-                            unsafe {
-                                let flags = libc::fcntl(fd, libc::F_GETFD);
-                                libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC);
-                                libc::dup2(fd, 1); // stdout
-                                libc::dup2(fd, 2); // stderr
-                                libc::close(fd);
-                            }
-                        }
-                    }
-
-                    // Exec into server command (never returns)
-                    if let Err(e) = exec_server(command, env_vars) {
-                        // Log error to server-specific log file if available
-                        if let Some(error_log) = log_file {
-                            if let Ok(mut log) = std::fs::OpenOptions::new()
-                                .create(true)
-                                .append(true)
-                                .open(error_log)
-                            {
-                                use std::io::Write;
-                                let _ = writeln!(
-                                    log,
-                                    "[{}] ERROR: Failed to exec server '{}': {:#}",
-                                    chrono::Utc::now().format("%Y-%m-%d %H:%M:%S"),
-                                    name,
-                                    e
-                                );
-                            }
-                        }
-                        std::process::exit(1);
-                    }
-                    unreachable!("exec should never return");
-                }
                 Err(e) => {
-                    eprintln!("Failed to fork server: {}", e);
+                    eprintln!("{:#}", e);
                     std::process::exit(1);
                 }
             }
@@ -278,6 +569,10 @@ fn execute_internal(
         Ok(ForkResult::Parent {
             child: watcher_child,
         }) => {
+            // The watcher's mux and front serve the sockets now.
+            drop(listener);
+            drop(front);
+
             // Original sharedserver process: wait briefly for watcher to set up,
             // then return to caller
 
@@ -294,9 +589,16 @@ fn execute_internal(
             // We only give up early when the watcher has *died* without
             // publishing — a real launch failure.
             let self_pid = std::process::id() as i32;
+            let mut span = telemetry::span("launch");
+            span.attr("server.name", name)
+                .attr("watcher.pid", watcher_child.as_raw());
             let start = std::time::Instant::now();
             let hard_cap = std::time::Duration::from_secs(10);
 
+            let mut progress = Progress::new(format!(
+                "Starting {}: waiting for the watcher to launch it",
+                name
+            ));
             let mut published: Option<ServerLock> = None;
             loop {
                 if let Ok(lock) = read_server_lock(name) {
@@ -320,10 +622,13 @@ fn execute_internal(
                 if start.elapsed() > hard_cap {
                     break;
                 }
+                progress.tick();
                 std::thread::sleep(std::time::Duration::from_millis(50));
             }
+            progress.finish();
 
             if let Some(lock) = published {
+                span.attr("server.pid", lock.pid);
                 let _ = sharedserver::core::log::log_invocation(
                     name,
                     &sharedserver::core::log::InvocationLog::success(
@@ -334,6 +639,7 @@ fn execute_internal(
                             "watcher_pid": watcher_child.as_raw(),
                             "command": command,
                             "grace_period": grace_period,
+                            "clients": clients.clients,
                         })),
                     ),
                 );
@@ -349,6 +655,9 @@ fn execute_internal(
             let _ = kill(watcher_child, Signal::SIGKILL);
             if let Ok(lock) = read_server_lock(name) {
                 if lock.pid != self_pid {
+                    if let Some(container) = &lock.container {
+                        let _ = container.kill();
+                    }
                     let server_pid = Pid::from_raw(lock.pid);
                     if killpg(server_pid, Signal::SIGKILL).is_err() {
                         let _ = kill(server_pid, Signal::SIGKILL);
@@ -358,7 +667,9 @@ fn execute_internal(
             let _ = waitpid(watcher_child, None);
             let _ = delete_server_lock(name);
             let _ = delete_clients_lock(name);
-            bail!("Timeout waiting for server to start (cleaned up partial state)");
+            span.fail("Timed out waiting for the watcher to publish");
+            Err(ErrorKind::StartTimeout
+                .error("Timeout waiting for server to start (cleaned up partial state)"))
         }
         Err(e) => {
             // Fork failed, clean up
@@ -369,6 +680,181 @@ fn execute_internal(
     }
 }
 
+/// Bind `name`'s stdio socket (see `stdio_mux`), returning it with its path.
+fn listen_stdio(name: &str) -> Result<(std::os::unix::net::UnixListener, PathBuf)> {
+    let socket = sharedserver::core::lockfile::server_file(name, stdio_mux::SOCKET_FILE)?;
+    Ok((stdio_mux::listen(&socket)?, socket))
+}
+
+/// Bind `name`'s `--listen` front, if it has one, returning it with the
+/// address it forwards to.
+pub(crate) fn listen_front(name: &str) -> Result<Option<(front::Listener, String)>> {
+    let lock = read_server_lock(name)?;
+    let (Some(listen), Some(upstream)) = (&lock.listen, lock.server_address()) else {
+        return Ok(None);
+    };
+    Ok(Some((front::bind(listen)?, upstream)))
+}
+
+/// Record the launched server's PID, and this process as its watcher, in the
+/// lock `prepare_launch` wrote, with start stamps so later liveness checks can
+/// detect PID reuse (see `process_liveness_checked`). `daemon` marks this
+/// process as the daemon rather than a watcher of the server's own. On failure
+/// both lockfiles are removed.
+pub(crate) fn publish_launch(name: &str, server_pid: i32, daemon: bool) -> Result<()> {
+    let watcher_pid = std::process::id() as i32;
+    let published = read_server_lock(name)
+        .context("Failed to read server lock")
+        .and_then(|mut server_lock| {
+            server_lock.pid = server_pid;
+            server_lock.watcher_pid = Some(watcher_pid);
+            server_lock.start_time = process_start_stamp(server_pid);
+            server_lock.watcher_start_time = process_start_stamp(watcher_pid);
+            server_lock.daemon = daemon;
+            write_server_lock(name, &server_lock).context("Failed to update server lock")
+        });
+    if published.is_err() {
+        let _ = delete_server_lock(name);
+        let _ = delete_clients_lock(name);
+    }
+    published
+}
+
+/// Fork the server process and exec `command` in it, returning its PID.
+///
+/// Called from the watcher (initial launch and restart-policy relaunches), so
+/// the watcher is the server's parent and is responsible for reaping it. The
+/// child gets its own process group, stdin from /dev/null, and stdout/stderr
+/// to the `relaunch` capture pipes if any, else `log_file` (or /dev/null).
+/// With stdio pipes (`proxy`), its stdin and stdout are those instead. With a
+/// context (the daemon), it runs in the client's directory and environment
+/// instead of this process's. With a `container`, the server is a foreground
+/// `docker run` (or `podman run`) of it, and `env_vars` are set inside the
+/// container rather than on the runtime's client.
+///
+/// SAFETY: see the note in `execute_internal` — the child runs non-async-
+/// signal-safe code before exec, which is only sound because the watcher (and
+/// the daemon) is single-threaded.
+pub(crate) fn spawn_server(
+    name: &str,
+    command: &[String],
+    env_vars: &[String],
+    log_file: Option<&str>,
+    relaunch: &Relaunch,
+    container: Option<&Container>,
+) -> Result<Pid> {
+    let capture = relaunch.capture.as_ref();
+    let context = relaunch.context.as_ref();
+    match unsafe { fork() } {
+        Ok(ForkResult::Parent { child }) => Ok(child),
+        Ok(ForkResult::Child) => {
+            // Child: become the actual server process
+
+            // Put the server in its own process group so we can kill the
+            // entire tree (including children like uv→python) with killpg().
+            // The watcher is in a separate session (setsid above) so it
+            // won't be affected.
+            let _ = setpgid(Pid::from_raw(0), Pid::from_raw(0));
+
+            // Redirect stdin to /dev/null (required for servers like workspace-mcp)
+            // stdout/stderr go to log_file if provided, otherwise /dev/null
+            use std::fs::OpenOptions;
+            use std::os::unix::io::IntoRawFd;
+
+            // stdin always goes to /dev/null. into_raw_fd() transfers
+            // ownership out of the File so the explicit libc::close is the
+            // only close — a double close aborts under std's debug-mode
+            // I/O-safety guard (release tolerates it).
+            if let Ok(devnull) = OpenOptions::new().read(true).open("/dev/null") {
+                let fd = devnull.into_raw_fd();
+                unsafe {
+                    let flags = libc::fcntl(fd, libc::F_GETFD);
+                    libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC);
+                    libc::dup2(fd, 0); // stdin
+                    libc::close(fd);
+                }
+            }
+
+            // stdout/stderr: capture pipes, log_file, or /dev/null
+            if let Some(capture) = capture {
+                // dup2 leaves the copies on 1/2 without close-on-exec; the
+                // originals are closed by exec.
+                unsafe {
+                    libc::dup2(capture.stdout_fd(), 1);
+                    libc::dup2(capture.stderr_fd(), 2);
+                }
+            } else if let Some(log_path) = log_file {
+                // Redirect to log file
+                if let Ok(logfile) = OpenOptions::new().create(true).append(true).open(log_path) {
+                    let fd = logfile.into_raw_fd();
+                    unsafe {
+                        let flags = libc::fcntl(fd, libc::F_GETFD);
+                        libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC);
+                        libc::dup2(fd, 1); // stdout
+                        libc::dup2(fd, 2); // stderr
+                        libc::close(fd);
+                    }
+                }
+            } else {
+                // Redirect to /dev/null
+                if let Ok(devnull) = OpenOptions::new().write(true).open("/dev/null") {
+                    let fd = devnull.into_raw_fd();
+                    unsafe {
+                        let flags = libc::fcntl(fd, libc::F_GETFD);
+                        libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC);
+                        libc::dup2(fd, 1); // stdout
+                        libc::dup2(fd, 2); // stderr
+                        libc::close(fd);
+                    }
+                }
+            }
+
+            // Exec into server command (never returns)
+            // A `proxy` server talks over the mux's pipes.
+            if let Some(stdio) = &relaunch.stdio {
+                unsafe {
+                    libc::dup2(stdio.stdin_fd(), 0);
+                    libc::dup2(stdio.stdout_fd(), 1);
+                }
+            }
+
+            let exec = match container {
+                Some(container) => exec_container(
+                    name,
+                    container,
+                    command,
+                    env_vars,
+                    context,
+                    relaunch.stdio.is_some(),
+                ),
+                None => exec_server(command, env_vars, context),
+            };
+            if let Err(e) = exec {
+                // Log error to server-specific log file if available
+                if let Some(error_log) = log_file {
+                    if let Ok(mut log) = std::fs::OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(error_log)
+                    {
+                        use std::io::Write;
+                        let _ = writeln!(
+                            log,
+                            "[{}] ERROR: Failed to exec server '{}': {:#}",
+                            chrono::Utc::now().format("%Y-%m-%d %H:%M:%S"),
+                            name,
+                            e
+                        );
+                    }
+                }
+                std::process::exit(1);
+            }
+            unreachable!("exec should never return");
+        }
+        Err(e) => bail!("Failed to fork server: {}", e),
+    }
+}
+
 fn parse_env_vars(env_vars: &[String]) -> Result<HashMap<String, String>> {
     let mut map = HashMap::new();
     for env_str in env_vars {
@@ -384,7 +870,11 @@ fn parse_env_vars(env_vars: &[String]) -> Result<HashMap<String, String>> {
     Ok(map)
 }
 
-fn exec_server(command: &[String], env_vars: &[String]) -> Result<()> {
+fn exec_server(
+    command: &[String],
+    env_vars: &[String],
+    context: Option<&ClientContext>,
+) -> Result<()> {
     if command.is_empty() {
         bail!("Server command cannot be empty");
     }
@@ -400,6 +890,19 @@ fn exec_server(command: &[String], env_vars: &[String]) -> Result<()> {
     cmd.arg("-c");
     cmd.arg(&cmd_string);
 
+    // Launched by the daemon: run where, and with the environment, the
+    // client would have.
+    if let Some(context) = context {
+        cmd.current_dir(&context.cwd);
+        cmd.env_clear();
+        cmd.envs(context.env.iter().map(|(key, value)| (key, value)));
+    }
+
+    // The systemd notify socket belongs to the watcher, not the server.
+    for var in sd_notify::NOTIFY_ENV_VARS {
+        cmd.env_remove(var);
+    }
+
     // Add custom environment variables on top of inherited ones
     if !env_map.is_empty() {
         cmd.envs(&env_map);
@@ -412,6 +915,41 @@ fn exec_server(command: &[String], env_vars: &[String]) -> Result<()> {
     Err(anyhow!("Failed to exec into server: {}", err))
 }
 
+/// Exec into the container runtime's client, running `container` in the
+/// foreground (with stdin attached if `interactive`). Its signal proxy passes
+/// signals sent to the client on to the container, and it exits with the
+/// container's status.
+fn exec_container(
+    name: &str,
+    container: &Container,
+    command: &[String],
+    env_vars: &[String],
+    context: Option<&ClientContext>,
+    interactive: bool,
+) -> Result<()> {
+    parse_env_vars(env_vars)?;
+    let cidfile = sharedserver::core::lockfile::server_file(name, container::CIDFILE)?;
+    // The runtime refuses to overwrite a cidfile, and a container left over
+    // by a killed run would hold on to the name.
+    let _ = std::fs::remove_file(&cidfile);
+    container.remove_stale();
+
+    use std::os::unix::process::CommandExt;
+    let mut cmd = std::process::Command::new(container.program());
+    cmd.args(container.run_args(name, command, env_vars, &cidfile, interactive));
+    if let Some(context) = context {
+        cmd.current_dir(&context.cwd);
+        cmd.env_clear();
+        cmd.envs(context.env.iter().map(|(key, value)| (key, value)));
+    }
+    for var in sd_notify::NOTIFY_ENV_VARS {
+        cmd.env_remove(var);
+    }
+
+    let err = cmd.exec();
+    Err(anyhow!("Failed to exec {}: {}", container.program(), err))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1,19 +1,26 @@
 use anyhow::{bail, Context, Result};
 use nix::sys::signal::{kill, killpg, Signal};
 use nix::unistd::Pid;
+use serde_json::json;
+use sharedserver::core::lockfile::servers_with;
+use sharedserver::core::stop::{request_stop, torn_down};
+use sharedserver::core::telemetry;
 use sharedserver::core::{
-    clients_lock_exists, delete_locks_owned_by, get_server_state, parse_duration,
-    process_liveness_checked, read_server_lock, server_lock_exists, Liveness, ServerLock,
-    ServerState,
+    clients_lock_exists, get_server_state, parse_duration, read_server_lock, server_lock_exists,
+    ErrorKind, Liveness, ServerFilter, ServerLock, ServerState,
 };
 use std::thread;
 use std::time::{Duration, Instant};
 
 use crate::output::{
-    format_duration, format_pid, format_server_name, print_error, print_info, print_success,
-    print_warning,
+    confirm, format_duration, format_pid, format_server_name, print_error, print_info,
+    print_success, print_warning, Progress,
 };
 
+/// How long `stop` waits for teardown when neither `--timeout` nor the
+/// config's `shutdown_timeout` says.
+pub const DEFAULT_TIMEOUT: &str = "10s";
+
 /// Stop a server.
 ///
 /// `stop` is a *signaller*: it asks the server to exit, then waits for the
@@ -28,12 +35,12 @@ use crate::output::{
 ///   then wait again. Errors with a diagnostic if it still can't converge —
 ///   at which point `admin kill` is the watcher-independent escape hatch.
 pub fn execute(name: &str, force: bool, timeout: &str) -> Result<()> {
-    let timeout =
-        parse_duration(timeout).with_context(|| format!("Invalid timeout: {}", timeout))?;
+    let timeout = parse_duration(timeout)
+        .map_err(|e| ErrorKind::InvalidArgs.wrap(e, format!("Invalid timeout: {}", timeout)))?;
 
     let state = get_server_state(name)?;
     if state == ServerState::Stopped {
-        bail!("Server '{}' is not running", name);
+        return Err(ErrorKind::NotRunning.error(format!("Server '{}' is not running", name)));
     }
 
     let server = read_server_lock(name)?;
@@ -45,11 +52,9 @@ pub fn execute(name: &str, force: bool, timeout: &str) -> Result<()> {
         format_pid(server.pid)
     ));
 
-    // Ask the server to exit. It runs in its own process group, so signal the
-    // whole group; fall back to a single-PID kill for servers started before
-    // the setpgid change.
-    if killpg(pid, Signal::SIGTERM).is_err() {
-        kill(pid, Signal::SIGTERM).context("Failed to send SIGTERM")?;
+    let notified = request_stop(name, &server)?;
+    if let Some(signal) = server.notify_signal.as_ref().filter(|_| notified > 0) {
+        print_info(&format!("Sent {} to {} client(s)", signal, notified));
     }
 
     if wait_for_teardown(name, &server, timeout) {
@@ -76,6 +81,10 @@ pub fn execute(name: &str, force: bool, timeout: &str) -> Result<()> {
 
     // --force: escalate to SIGKILL and wait for the watcher to converge again.
     print_warning("Server did not stop gracefully, sending SIGKILL...");
+    telemetry::mark("stop.escalate", &json!({ "server.pid": server.pid }));
+    if let Some(container) = &server.container {
+        let _ = container.kill();
+    }
     if killpg(pid, Signal::SIGKILL).is_err() {
         kill(pid, Signal::SIGKILL).context("Failed to send SIGKILL")?;
     }
@@ -94,34 +103,57 @@ pub fn execute(name: &str, force: bool, timeout: &str) -> Result<()> {
     bail!("{}", diagnostic);
 }
 
-/// Wait until the server has been fully torn down: the watcher has exited and
-/// both lockfiles are gone. Returns `false` on timeout.
-///
-/// While a live watcher exists we leave cleanup entirely to it. If there is no
-/// live watcher (it already exited, or was never recorded) and the server is
-/// dead, we remove the lockfiles ourselves — pid-guarded so a restarted
-/// instance is never touched — because nothing else will.
+/// Stop every running server `filter` selects (a name pattern or `--tag`),
+/// one after another, once [`confirm`]ed. The ones that fail are reported
+/// once the rest have been tried.
+pub fn execute_matching(
+    filter: &ServerFilter,
+    force: bool,
+    timeout: &str,
+    yes: bool,
+) -> Result<()> {
+    let names: Vec<String> = servers_with(&["server.json"])?
+        .into_iter()
+        .filter(|name| filter.matches_name(name))
+        .filter(|name| read_server_lock(name).is_ok_and(|lock| filter.matches_tags(&lock.tags)))
+        .filter(|name| get_server_state(name).is_ok_and(|state| state != ServerState::Stopped))
+        .collect();
+    if names.is_empty() {
+        return Err(ErrorKind::NotRunning.error("No running server matches"));
+    }
+    confirm("Stop", &names, yes)?;
+    let mut failed = 0;
+    for name in &names {
+        if let Err(e) = execute(name, force, timeout) {
+            print_error(&format!("{}: {:#}", name, e));
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        bail!("Failed to stop {} of {} servers", failed, names.len());
+    }
+    Ok(())
+}
+
+/// Wait until the server has been fully torn down (see [`torn_down`]).
+/// Returns `false` on timeout.
 fn wait_for_teardown(name: &str, server: &ServerLock, timeout: Duration) -> bool {
+    let mut span = telemetry::span("teardown");
+    span.attr("server.name", name)
+        .attr("server.pid", server.pid);
     let start = Instant::now();
+    let mut progress = Progress::new(format!("Waiting for {} to shut down", name));
     loop {
-        let watcher_alive = sharedserver::core::watcher_alive(server);
-
-        if !watcher_alive
-            && process_liveness_checked(server.pid, server.start_time) != Liveness::Alive
-        {
-            // No watcher to reap/clean and the server is dead: clean up the
-            // orphaned lockfiles ourselves (guarded against a newer instance).
-            delete_locks_owned_by(name, server.pid);
-        }
-
-        if !watcher_alive && !server_lock_exists(name) && !clients_lock_exists(name) {
+        if torn_down(name, server) {
             return true;
         }
 
         if start.elapsed() >= timeout {
+            span.fail(format!("Not torn down within {}", format_duration(timeout)));
             return false;
         }
 
+        progress.tick();
         thread::sleep(Duration::from_millis(100));
     }
 }
@@ -131,7 +163,7 @@ fn wait_for_teardown(name: &str, server: &ServerLock, timeout: Duration) -> bool
 fn teardown_failure_diagnostic(name: &str, server: &ServerLock) -> String {
     let mut parts = Vec::new();
 
-    match process_liveness_checked(server.pid, server.start_time) {
+    match server.server_liveness() {
         Liveness::Alive => parts.push(format!("server process {} still alive", server.pid)),
         Liveness::Zombie => parts.push(format!(
             "server process {} is defunct (awaiting reap)",
@@ -0,0 +1,175 @@
+use anyhow::{bail, Context, Result};
+use sharedserver::core::{get_server_state, read_server_lock, ErrorKind, ServerState};
+use std::path::PathBuf;
+use std::process::Command;
+
+use super::hook::posix_quote;
+use super::r#use::UseFlags;
+use super::start::StartOptions;
+use crate::output::{format_server_name, print_success};
+
+/// The window option marking a window as `tmux-attach`'s for a server, so it
+/// is found again even if renamed.
+const WINDOW_OPTION: &str = "@sharedserver";
+
+/// How much of the log the new window starts with.
+const TAIL_LINES: u32 = 200;
+
+/// A window `tmux-attach` opened earlier.
+#[derive(Debug, PartialEq, Eq)]
+struct Window {
+    id: String,
+    /// The process in its pane: `tail`.
+    pane_pid: i32,
+}
+
+/// Run `tmux args...`, returning its stdout.
+fn tmux(args: &[&str]) -> Result<String> {
+    let out = Command::new("tmux").args(args).output().map_err(|e| {
+        if e.kind() == std::io::ErrorKind::NotFound {
+            anyhow::anyhow!("tmux is not installed")
+        } else {
+            anyhow::Error::new(e).context("Failed to run tmux")
+        }
+    })?;
+    if !out.status.success() {
+        bail!(
+            "tmux {} failed: {}",
+            args.first().copied().unwrap_or_default(),
+            String::from_utf8_lossy(&out.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+/// A line of `tmux` output formatted as `WINDOW_ID PANE_PID MARK`, with the
+/// window and its mark (empty if unmarked). Separated by spaces, not tabs,
+/// since tmux can print those as `_`, so the mark, which may contain
+/// spaces, comes last.
+fn parse_window(line: &str) -> Option<(Window, &str)> {
+    let mut fields = line.splitn(3, ' ');
+    let id = fields.next()?;
+    let pane_pid = fields.next()?.trim().parse().ok()?;
+    let window = Window {
+        id: id.to_string(),
+        pane_pid,
+    };
+    Some((window, fields.next().unwrap_or_default().trim_end()))
+}
+
+/// The window marked for `name` in a `list-windows` listing.
+fn find_window(listing: &str, name: &str) -> Option<Window> {
+    listing
+        .lines()
+        .filter_map(parse_window)
+        .find_map(|(window, mark)| (mark == name).then_some(window))
+}
+
+/// Where `name`'s output goes: its `--log-file`, relative to where it was
+/// started.
+fn log_path(name: &str) -> Result<PathBuf> {
+    let lock = read_server_lock(name)?;
+    let Some(log_file) = lock.log_file else {
+        bail!(
+            "Server {} has no log file to tail (start it with --log-file)",
+            format_server_name(name)
+        );
+    };
+    let log_file = PathBuf::from(log_file);
+    Ok(match lock.cwd {
+        Some(cwd) if log_file.is_relative() => cwd.join(log_file),
+        _ => log_file,
+    })
+}
+
+/// Open a tmux window named after `name` tailing its log file, or switch to
+/// the one opened before. With `hold`, the pane's process also holds a
+/// reference on the server, released when the pane is closed.
+pub fn execute(name: &str, hold: bool) -> Result<()> {
+    if get_server_state(name)? == ServerState::Stopped {
+        return Err(ErrorKind::NotRunning.error(format!(
+            "Server {} is not running",
+            format_server_name(name)
+        )));
+    }
+    let log = log_path(name)?;
+
+    // Fails only when there is no tmux server yet, so no windows either.
+    let format = format!("#{{window_id}} #{{pane_pid}} #{{{}}}", WINDOW_OPTION);
+    let listing = tmux(&["list-windows", "-a", "-F", &format]).unwrap_or_default();
+    let window = match find_window(&listing, name) {
+        Some(window) => {
+            tmux(&["select-window", "-t", &window.id])?;
+            if std::env::var_os("TMUX").is_some() {
+                // From another session: bring this client to the window.
+                let _ = tmux(&["switch-client", "-t", &window.id]);
+            }
+            print_success(&format!(
+                "Switched to the tmux window for {}",
+                format_server_name(name)
+            ));
+            window
+        }
+        None => {
+            let tail = format!(
+                "exec tail -n {} -F {}",
+                TAIL_LINES,
+                posix_quote(&log.to_string_lossy())
+            );
+            let created = tmux(&[
+                "new-window",
+                "-n",
+                name,
+                "-P",
+                "-F",
+                "#{window_id} #{pane_pid}",
+                &tail,
+            ])
+            .context("Failed to open a tmux window (is tmux running?)")?;
+            let (window, _) = parse_window(created.trim_end())
+                .with_context(|| format!("tmux did not report the new window: {:?}", created))?;
+            tmux(&["set-option", "-w", "-t", &window.id, WINDOW_OPTION, name])?;
+            print_success(&format!(
+                "Opened tmux window {} tailing {}",
+                format_server_name(name),
+                log.display()
+            ));
+            window
+        }
+    };
+
+    if hold {
+        super::r#use::execute(
+            name,
+            &StartOptions::default(),
+            Some(format!("tmux window {}", window.id)),
+            Some(window.pane_pid),
+            UseFlags::default(),
+            &[],
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_window() {
+        let listing = "@1 100 \n@4 205 api\n@7 311 my db\n";
+        assert_eq!(
+            find_window(listing, "my db"),
+            Some(Window {
+                id: "@7".to_string(),
+                pane_pid: 311
+            })
+        );
+        assert_eq!(find_window(listing, "web"), None);
+        // `new-window -P` output: no mark yet.
+        assert_eq!(
+            parse_window("@9 412").map(|(w, mark)| (w.pane_pid, mark)),
+            Some((412, ""))
+        );
+    }
+}
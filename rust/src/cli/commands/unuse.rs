@@ -1,6 +1,8 @@
-use crate::output::{format_server_name, print_warning};
-use anyhow::{bail, Result};
-use sharedserver::core::{get_server_state, ServerState};
+use crate::daemon::{self, UnuseParams};
+use crate::output::{format_pid, format_server_name, print_error, print_info, print_warning};
+use anyhow::{anyhow, Result};
+use sharedserver::core::lockfile::servers_with;
+use sharedserver::core::{get_server_state, read_clients_lock, ErrorKind, ServerState};
 
 /// Get the client PID: use provided PID, or default to parent process PID
 fn get_client_pid(pid: Option<i32>) -> i32 {
@@ -14,16 +16,31 @@ fn get_client_pid(pid: Option<i32>) -> i32 {
 ///
 /// This is a user-friendly wrapper around the 'admin decref' command.
 /// It checks the server state and provides clear feedback about what's happening.
-pub fn execute(name: &str, pid: Option<i32>) -> Result<()> {
+pub fn execute(name: &str, pid: Option<i32>, via_daemon: bool) -> Result<()> {
     let client_pid = get_client_pid(pid);
 
+    if via_daemon {
+        let params = UnuseParams {
+            name: name.to_string(),
+            client_pid,
+        };
+        let grace_period = super::decref::grace_period_of(name);
+        if let Some(result) = daemon::call("unuse", serde_json::to_value(params)?)? {
+            let refcount = result["refcount"].as_u64().unwrap_or(0) as u32;
+            super::decref::print_detached(name, refcount, grace_period.as_ref());
+            return Ok(());
+        }
+        log::debug!("no daemon running; acting directly");
+    }
+
     // Check current server state
     let state = get_server_state(name)?;
 
     match state {
-        ServerState::Stopped => {
-            bail!("Server {} is not running", format_server_name(name));
-        }
+        ServerState::Stopped => Err(ErrorKind::NotRunning.error(format!(
+            "Server {} is not running",
+            format_server_name(name)
+        ))),
         ServerState::Grace => {
             // Server is already in grace period, but we can still decref
             // This handles the case where a client might be trying to clean up
@@ -39,10 +56,47 @@ pub fn execute(name: &str, pid: Option<i32>) -> Result<()> {
         }
         ServerState::Defunct => {
             // Server already died and is being torn down; nothing to detach from.
-            bail!(
+            Err(ErrorKind::ShuttingDown.error(format!(
                 "Server {} is shutting down (defunct, cleanup pending)",
                 format_server_name(name)
-            );
+            )))
+        }
+    }
+}
+
+/// Detach the client from every server it is attached to (`unuse --all`),
+/// e.g. from a shell's EXIT trap (see `hook`). Carries on past a server it
+/// fails to detach from, and fails at the end if there were any.
+pub fn execute_all(pid: Option<i32>, via_daemon: bool) -> Result<()> {
+    let client_pid = get_client_pid(pid);
+    let names: Vec<String> = servers_with(&["clients.json"])?
+        .into_iter()
+        .filter(|name| {
+            read_clients_lock(name).is_ok_and(|clients| clients.clients.contains_key(&client_pid))
+        })
+        .collect();
+    if names.is_empty() {
+        print_info(&format!(
+            "PID {} is not attached to any server",
+            format_pid(client_pid)
+        ));
+        return Ok(());
+    }
+
+    let mut failed = 0;
+    for name in &names {
+        if let Err(e) = execute(name, Some(client_pid), via_daemon) {
+            print_error(&format!("{}: {:#}", format_server_name(name), e));
+            failed += 1;
         }
     }
+    if failed > 0 {
+        return Err(anyhow!(
+            "Failed to detach PID {} from {} of {} servers",
+            client_pid,
+            failed,
+            names.len()
+        ));
+    }
+    Ok(())
 }
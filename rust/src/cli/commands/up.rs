@@ -0,0 +1,178 @@
+use anyhow::{anyhow, Context, Result};
+use colored::{ColoredString, Colorize};
+use sharedserver::core::{get_server_state, read_server_lock, ServerState};
+
+use super::r#use::{apply_server, load_config, UseFlags};
+use super::start::StartOptions;
+use crate::output::{self, print_error, Table};
+
+/// The client metadata `up` attaches with, shown by `info`.
+const UP_METADATA: &str = "sharedserver up";
+
+/// One row of the table `up` and `down` print.
+fn row(name: &str, status: ColoredString) -> Vec<ColoredString> {
+    let lock = read_server_lock(name).ok();
+    vec![
+        name.into(),
+        status,
+        lock.as_ref()
+            .map(|lock| lock.pid.to_string())
+            .unwrap_or_else(|| "-".to_string())
+            .into(),
+        lock.and_then(|lock| lock.address())
+            .unwrap_or_default()
+            .into(),
+    ]
+}
+
+/// Use every server the config defines (or those in `group`), starting the
+/// ones that aren't running, and print a table of what became of each.
+///
+/// Unless `no_deps`, the servers they depend on are used too, and each
+/// server is only started once its dependencies are ready; one whose
+/// dependency failed is skipped.
+///
+/// Like `use`, the references are held by the caller (or `pid`), so they last
+/// until it exits or runs `down` or `unuse`. A server that fails doesn't stop
+/// the rest.
+pub fn execute(
+    group: Option<&str>,
+    pid: Option<i32>,
+    no_deps: bool,
+    via_daemon: bool,
+) -> Result<()> {
+    let config = load_config()?;
+    let selected: Vec<&str> = config
+        .select(group)?
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect();
+    let servers = if no_deps {
+        selected
+    } else {
+        config.start_order(&selected)?
+    };
+    let client_pid = pid.unwrap_or_else(|| nix::unistd::getppid().as_raw());
+    let home = std::env::current_dir().context("Failed to read the working directory")?;
+
+    // The table says it all.
+    output::set_quiet(true);
+    let mut table = Table::new(&["NAME", "STATUS", "PID", "ADDRESS"]);
+    let mut failures = Vec::new();
+    for &name in &servers {
+        let needs = config
+            .server(name)
+            .map_or(&[][..], |server| &server.depends_on);
+        if let Some(failed) = needs
+            .iter()
+            .find(|&dependency| !no_deps && failures.iter().any(|(f, _)| f == dependency))
+        {
+            table.row(row(name, "skipped".yellow()));
+            failures.push((name, anyhow!("its dependency '{}' failed", failed)));
+            continue;
+        }
+        let was_running = get_server_state(name).is_ok_and(|state| state != ServerState::Stopped);
+        let mut opts = StartOptions::default();
+        let mut command = Vec::new();
+        let used =
+            apply_server(name, &config, None, None, &mut opts, &mut command).and_then(|()| {
+                super::r#use::execute(
+                    name,
+                    &opts,
+                    Some(UP_METADATA.to_string()),
+                    Some(client_pid),
+                    UseFlags {
+                        via_daemon,
+                        ..UseFlags::default()
+                    },
+                    &command,
+                )
+            });
+        // Each server's `cwd` is relative to where `up` was run, not to the
+        // last one's.
+        std::env::set_current_dir(&home)
+            .with_context(|| format!("Failed to return to {}", home.display()))?;
+        // Dependents wait for this one to be ready.
+        let is_needed = !no_deps
+            && servers.iter().any(|&other| {
+                config
+                    .server(other)
+                    .is_some_and(|server| server.depends_on.iter().any(|d| d == name))
+            });
+        let used = used.and_then(|()| {
+            if is_needed {
+                super::r#use::wait_until_ready(name)?;
+            }
+            Ok(())
+        });
+        match used {
+            Ok(()) if was_running => table.row(row(name, "running".green())),
+            Ok(()) => table.row(row(name, "started".green())),
+            Err(e) => {
+                table.row(row(name, "failed".red()));
+                failures.push((name, e));
+            }
+        }
+    }
+    table.print();
+    report_failures(failures, servers.len(), "start")
+}
+
+/// Stop every server the config defines (or those in `group`) that is
+/// running, in the reverse of the order `up` starts them (so dependents go
+/// before what they depend on), and print a table of what became of each.
+/// Dependencies outside `group` are left running.
+pub fn down(group: Option<&str>, force: bool, timeout: &str) -> Result<()> {
+    let config = load_config()?;
+    let selected: Vec<&str> = config
+        .select(group)?
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect();
+    // A broken dependency list shouldn't keep anything running.
+    let servers: Vec<&str> = match config.start_order(&selected) {
+        Ok(order) => order
+            .into_iter()
+            .filter(|name| selected.contains(name))
+            .collect(),
+        Err(_) => selected,
+    };
+
+    output::set_quiet(true);
+    let mut table = Table::new(&["NAME", "STATUS", "PID", "ADDRESS"]);
+    let mut failures = Vec::new();
+    for &name in servers.iter().rev() {
+        if get_server_state(name).is_ok_and(|state| state == ServerState::Stopped) {
+            table.row(row(name, "not running".dimmed()));
+            continue;
+        }
+        // Read before stopping, since the locks go with the server.
+        let running = row(name, "".into());
+        match super::stop::execute(name, force, timeout) {
+            Ok(()) => {
+                let mut stopped = running;
+                stopped[1] = "stopped".green();
+                table.row(stopped);
+            }
+            Err(e) => {
+                table.row(row(name, "failed".red()));
+                failures.push((name, e));
+            }
+        }
+    }
+    table.print();
+    report_failures(failures, servers.len(), "stop")
+}
+
+/// Print each failure under the table, then fail with a count if there were
+/// any.
+fn report_failures(failures: Vec<(&str, anyhow::Error)>, total: usize, verb: &str) -> Result<()> {
+    if failures.is_empty() {
+        return Ok(());
+    }
+    let count = failures.len();
+    for (name, e) in failures {
+        print_error(&format!("{}: {:#}", name, e));
+    }
+    Err(anyhow!("Failed to {} {} of {} servers", verb, count, total))
+}
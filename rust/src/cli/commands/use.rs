@@ -1,10 +1,110 @@
-use anyhow::{bail, Result};
-use sharedserver::core::{get_server_state, read_clients_lock, read_server_lock, ServerState};
+use anyhow::{Context, Result};
+use serde_json::json;
+use sharedserver::core::address::claimed_by;
+use sharedserver::core::config::{Config, ServerConfig};
+use sharedserver::core::error::exit_code;
+use sharedserver::core::exe::ExeSnapshot;
+use sharedserver::core::lockfile::lockfile_dir;
+use sharedserver::core::telemetry;
+use sharedserver::core::{
+    get_server_state, is_process_alive, read_clients_lock, read_server_lock, ClientInfo, ErrorKind,
+    HealthCheck, ServerLock, ServerState,
+};
+use std::time::{Duration, Instant};
 
+use super::start::{ClientContext, StartOptions};
+use crate::daemon::{self, UseParams};
 use crate::output::{
-    format_pid, format_refcount, format_server_name, print_success, print_warning,
+    format_pid, format_refcount, format_server_name, print_info, print_success, print_warning,
 };
 
+/// How long `use --replace` waits for the old instance to drain (SIGTERM, then
+/// SIGKILL) before giving up.
+const REPLACE_DRAIN_TIMEOUT: &str = "10s";
+
+/// How often `use --events-json` probes a server waiting for its health check
+/// to pass.
+const READY_PROBE_INTERVAL: Duration = Duration::from_millis(200);
+
+/// How many free ports to try for `{port}` before settling for one another
+/// server has claimed (and failing to start on it).
+const PORT_ATTEMPTS: usize = 8;
+
+/// The grace period when neither the command line nor the config (the
+/// server's definition or `[defaults]`) sets one.
+pub const DEFAULT_GRACE_PERIOD: &str = "5m";
+
+/// The restart policy when neither the command line nor the config sets one.
+pub const DEFAULT_RESTART: &str = "never";
+
+/// How `use` goes about attaching, beyond what to start and for whom.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UseFlags {
+    /// Drain and restart a server launched differently (`--replace`)
+    pub replace: bool,
+    /// Let a running daemon act (`--via-daemon`)
+    pub via_daemon: bool,
+    /// Report progress as JSON lines instead of messages (`--events-json`)
+    pub events_json: bool,
+}
+
+/// What `use` did to attach.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AttachAction {
+    Started,
+    Attached,
+    Rescued,
+    Replaced,
+}
+
+impl AttachAction {
+    fn as_str(self) -> &'static str {
+        match self {
+            AttachAction::Started => "started",
+            AttachAction::Attached => "attached",
+            AttachAction::Rescued => "rescued",
+            AttachAction::Replaced => "replaced",
+        }
+    }
+}
+
+/// `use --events-json`'s progress: one JSON object per line on stdout, with
+/// the `name` and `timestamp` fields of `events --json`'s records. Does
+/// nothing without the flag.
+#[derive(Debug, Clone, Copy)]
+struct Report<'a> {
+    name: &'a str,
+    enabled: bool,
+}
+
+impl Report<'_> {
+    fn emit(&self, event: &str, fields: serde_json::Value) {
+        if !self.enabled {
+            return;
+        }
+        let mut record = json!({
+            "event": event,
+            "name": self.name,
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+        });
+        if let (Some(record), serde_json::Value::Object(fields)) = (record.as_object_mut(), fields)
+        {
+            record.extend(fields);
+        }
+        println!("{}", record);
+    }
+
+    /// `pid-assigned`, for the server `name` was just started as.
+    fn pid_assigned(&self) {
+        if let Ok(lock) = read_server_lock(self.name) {
+            self.emit(
+                "pid-assigned",
+                json!({ "pid": lock.pid, "watcher_pid": lock.watcher_pid }),
+            );
+        }
+    }
+}
+
 /// Get the client PID: use provided PID, or default to parent process PID
 fn get_client_pid(pid: Option<i32>) -> i32 {
     pid.unwrap_or_else(|| {
@@ -13,49 +113,408 @@ fn get_client_pid(pid: Option<i32>) -> i32 {
     })
 }
 
+/// Whether a running server was launched differently from what is being
+/// requested now: a different argv, or a different set of env overrides
+/// (order-insensitive).
+fn differs_from_request(lock: &ServerLock, command: &[String], env_vars: &[String]) -> bool {
+    let mut running_env = lock.env.clone();
+    let mut requested_env = env_vars.to_vec();
+    running_env.sort();
+    requested_env.sort();
+    lock.command != command || running_env != requested_env
+}
+
+/// Why a running server should be replaced to satisfy this request, if it
+/// should: it was launched differently, or its executable has changed on disk
+/// since.
+fn replace_reason(
+    lock: &ServerLock,
+    command: &[String],
+    env_vars: &[String],
+) -> Option<&'static str> {
+    if differs_from_request(lock, command, env_vars) {
+        Some("command or environment changed")
+    } else if lock
+        .executable
+        .as_ref()
+        .is_some_and(ExeSnapshot::changed_on_disk)
+    {
+        Some("executable changed on disk")
+    } else {
+        None
+    }
+}
+
+/// Fill in what the command line left out from `[servers.<name>]` in the
+/// config, if it defines `name` (see [`apply_server`]).
+///
+/// A config that can't be read only fails a `use` that needed it for the
+/// command.
+pub fn apply_config(
+    name: &str,
+    grace_period: Option<String>,
+    restart: Option<String>,
+    opts: &mut StartOptions,
+    command: &mut Vec<String>,
+) -> Result<()> {
+    let config = match load_config() {
+        Ok(config) => config,
+        Err(e) if !command.is_empty() => {
+            print_warning(&format!("Ignoring the config: {:#}", e));
+            Config::default()
+        }
+        Err(e) => return Err(e),
+    };
+    apply_server(name, &config, grace_period, restart, opts, command)
+}
+
+/// `name`'s grace period: `grace_period` from the command line, or else the
+/// config's (see [`grace_period`]). For commands that take nothing else from
+/// the config; one that can't be read is ignored with a warning.
+pub fn config_grace_period(name: &str, grace_period: Option<String>) -> String {
+    self::grace_period(name, &config_or_default(), grace_period)
+}
+
+/// `name`'s grace period and restart policy: `grace_period` and `restart`
+/// from the command line, or else the config's, as [`apply_server`] would
+/// set them. For `admin start`, which takes nothing else from the config.
+pub fn config_start_settings(
+    name: &str,
+    grace_period: Option<String>,
+    restart: Option<String>,
+) -> (String, String) {
+    let config = config_or_default();
+    let restart = restart
+        .or_else(|| config.server(name)?.restart.clone())
+        .unwrap_or_else(|| DEFAULT_RESTART.to_string());
+    (self::grace_period(name, &config, grace_period), restart)
+}
+
+/// The config, or none if it can't be read, with a warning.
+fn config_or_default() -> Config {
+    load_config().unwrap_or_else(|e| {
+        print_warning(&format!("Ignoring the config: {:#}", e));
+        Config::default()
+    })
+}
+
+/// `grace_period`, or else `name`'s in `config`, `config`'s default, or
+/// [`DEFAULT_GRACE_PERIOD`].
+fn grace_period(name: &str, config: &Config, grace_period: Option<String>) -> String {
+    grace_period
+        .or_else(|| config.server(name)?.grace_period.clone())
+        .or_else(|| config.defaults.grace_period.clone())
+        .unwrap_or_else(|| DEFAULT_GRACE_PERIOD.to_string())
+}
+
+/// The config, with a failure to read it classed as invalid arguments.
+pub fn load_config() -> Result<Config> {
+    Config::load().map_err(|e| ErrorKind::InvalidArgs.wrap(e, "Failed to load the config"))
+}
+
+/// Fill in what the command line left out from `name`'s definition in
+/// `config`, with its variables expanded: the command, when none was given
+/// after `--`, and the grace period and restart policy (unless given as
+/// `grace_period` and `restart`), environment (overridden by `--env`), tags
+/// (overridden by `--tag`), log settings, and health check. Moves to the
+/// server's `cwd`, if it has one, so a server started from here runs there.
+pub fn apply_server(
+    name: &str,
+    config: &Config,
+    grace_period: Option<String>,
+    restart: Option<String>,
+    opts: &mut StartOptions,
+    command: &mut Vec<String>,
+) -> Result<()> {
+    opts.grace_period = self::grace_period(name, config, grace_period);
+    // Found from here, before moving to the server's `cwd`, for its watcher.
+    opts.config_files = Config::paths();
+    let Some(server) = config.server(name) else {
+        opts.restart = restart.unwrap_or_else(|| DEFAULT_RESTART.to_string());
+        return Ok(());
+    };
+    let server = expand_server(name, server, opts)?;
+    opts.restart = restart
+        .or_else(|| server.restart.clone())
+        .unwrap_or_else(|| DEFAULT_RESTART.to_string());
+    if opts.health_check.is_none() {
+        if let Some(health) = &server.health {
+            opts.health_check = Some(health.to_check().map_err(|e| {
+                ErrorKind::InvalidArgs.wrap(e, format!("Invalid health check for '{}'", name))
+            })?);
+        }
+    }
+
+    if command.is_empty() {
+        if let Some(configured) = &server.command {
+            *command = configured.to_vec();
+        }
+    }
+    // Later assignments win, so `--env` goes last.
+    opts.env_vars = server
+        .env_vars()
+        .into_iter()
+        .chain(std::mem::take(&mut opts.env_vars))
+        .collect();
+    if opts.log_file.is_none() {
+        opts.log_file = server.log_file.clone();
+    }
+    // Likewise `--tag`.
+    opts.tags = server
+        .tags
+        .clone()
+        .into_iter()
+        .chain(std::mem::take(&mut opts.tags))
+        .collect();
+    opts.log_timestamps |= server.log_timestamps.unwrap_or(false);
+    if let Some(cwd) = server.cwd() {
+        std::env::set_current_dir(&cwd).with_context(|| {
+            format!(
+                "Failed to change to {}'s directory {}",
+                format_server_name(name),
+                cwd.display()
+            )
+        })?;
+    }
+    Ok(())
+}
+
+/// The settings a running server's watcher picks up from the config when it
+/// changes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerSettings {
+    pub grace_period: String,
+    pub restart: String,
+    pub health_check: Option<HealthCheck>,
+}
+
+/// The grace period, restart policy, and health check `config` gives `name`,
+/// as [`apply_server`] would set them with no flags.
+pub fn configured_settings(name: &str, config: &Config) -> Result<ServerSettings> {
+    let server = config
+        .server(name)
+        .map(|server| expand_server(name, server, &mut StartOptions::default()))
+        .transpose()?;
+    let server = server.as_ref();
+    Ok(ServerSettings {
+        grace_period: grace_period(name, config, None),
+        restart: server
+            .and_then(|server| server.restart.clone())
+            .unwrap_or_else(|| DEFAULT_RESTART.to_string()),
+        health_check: server
+            .and_then(|server| server.health.as_ref())
+            .map(|health| health.to_check())
+            .transpose()?,
+    })
+}
+
+/// `server` with its variables expanded for `name`. `{port}` is the port
+/// the server is running on, if it is running on a local one, or else a free
+/// one, which becomes its address unless `--address` gives one.
+fn expand_server(
+    name: &str,
+    server: &ServerConfig,
+    opts: &mut StartOptions,
+) -> Result<ServerConfig> {
+    let mut port = None;
+    let expanded = server.expand(&mut |var| {
+        Ok(match var {
+            "name" => Some(name.to_string()),
+            "port" => {
+                if port.is_none() {
+                    port = Some(server_port(name)?);
+                }
+                port.map(|port| port.to_string())
+            }
+            "lockdir" => Some(lockfile_dir()?.display().to_string()),
+            "home" => std::env::var("HOME").ok(),
+            _ => None,
+        })
+    })?;
+    if let (Some(port), None) = (port, &opts.address) {
+        opts.address = Some(format!("tcp:127.0.0.1:{}", port));
+    }
+    Ok(expanded)
+}
+
+/// The local port `name` is running on, or else a free one no other server
+/// has claimed (one still starting up may not be listening on its port yet).
+fn server_port(name: &str) -> Result<u16> {
+    let running = read_server_lock(name).ok().and_then(|lock| {
+        lock.address
+            .as_deref()?
+            .strip_prefix("tcp:127.0.0.1:")?
+            .parse()
+            .ok()
+    });
+    if let Some(port) = running {
+        return Ok(port);
+    }
+    let mut port = super::lsp::free_port()?;
+    for _ in 0..PORT_ATTEMPTS {
+        if claimed_by(&format!("tcp:127.0.0.1:{}", port), name).is_none() {
+            break;
+        }
+        port = super::lsp::free_port()?;
+    }
+    Ok(port)
+}
+
 /// Use a server: start it if not running, then always increment refcount.
 /// This is an atomic "start-or-attach" operation that combines start + incref.
+///
+/// With `flags.replace`, a running server whose command or env differs from
+/// the request is drained and restarted with the new command. Its clients are
+/// carried over to the new instance, so nobody loses their reference.
+///
+/// With `flags.events_json`, progress is reported as JSON lines (`starting`,
+/// `pid-assigned`, `ready-probe-passed`, `attached`, or `error`), and a server
+/// with a health check isn't reported attached until it passes.
 pub fn execute(
     name: &str,
-    grace_period: &str,
+    opts: &StartOptions,
     metadata: Option<String>,
     pid: Option<i32>,
-    env_vars: &[String],
-    log_file: Option<&str>,
+    flags: UseFlags,
     command: &[String],
 ) -> Result<()> {
     // Determine the client PID (use provided or default to parent process)
     let client_pid = get_client_pid(pid);
+    let report = Report {
+        name,
+        enabled: flags.events_json,
+    };
+
+    let attached =
+        attach(name, opts, metadata, client_pid, flags, command, report).and_then(|attached| {
+            if report.enabled {
+                report_attached(name, client_pid, attached, report)?;
+            } else {
+                super::hook::suggest(client_pid);
+            }
+            Ok(())
+        });
+    if let Err(e) = &attached {
+        report.emit(
+            "error",
+            json!({
+                "message": format!("{:#}", e),
+                "kind": ErrorKind::of(e).map(|kind| kind.as_str()),
+                "exit_code": exit_code(e),
+            }),
+        );
+    }
+    attached
+}
+
+fn attach(
+    name: &str,
+    opts: &StartOptions,
+    metadata: Option<String>,
+    client_pid: i32,
+    flags: UseFlags,
+    command: &[String],
+    report: Report<'_>,
+) -> Result<AttachAction> {
+    let UseFlags {
+        replace,
+        via_daemon,
+        ..
+    } = flags;
+
+    if via_daemon && (replace || opts.queue) {
+        log::debug!("--replace and --queue are not handled by the daemon; acting directly");
+    } else if via_daemon {
+        let params = UseParams {
+            name: name.to_string(),
+            client_pid,
+            metadata: metadata.clone(),
+            command: command.to_vec(),
+            options: opts.clone(),
+            context: ClientContext::current()?,
+        };
+        if let Some(result) = daemon::call("use", serde_json::to_value(params)?)? {
+            telemetry::mark(
+                "use.daemon",
+                &serde_json::json!({ "action": result["action"], "client.pid": client_pid }),
+            );
+            report_daemon_use(name, &result);
+            return Ok(match result["action"].as_str() {
+                Some("started") => {
+                    // The daemon launched it before answering.
+                    report.emit("starting", json!({ "command": command }));
+                    report.pid_assigned();
+                    AttachAction::Started
+                }
+                Some("rescued") => AttachAction::Rescued,
+                _ => AttachAction::Attached,
+            });
+        }
+        log::debug!("no daemon running; acting directly");
+    }
 
     // Check current state
     let state = get_server_state(name)?;
+    log::debug!(
+        "'{}' is {}; attaching client PID {}",
+        name,
+        state.as_str(),
+        client_pid
+    );
+    telemetry::mark(
+        "use.state",
+        &serde_json::json!({ "server.state": state.as_str(), "client.pid": client_pid }),
+    );
+
+    if replace && !command.is_empty() && matches!(state, ServerState::Active | ServerState::Grace) {
+        if let Ok(server_lock) = read_server_lock(name) {
+            let reason = replace_reason(&server_lock, command, &opts.env_vars);
+            if reason.is_none() {
+                log::debug!("--replace: command, environment, and executable unchanged");
+            }
+            if let Some(reason) = reason {
+                telemetry::mark("use.replace", &serde_json::json!({ "reason": reason }));
+                report.emit(
+                    "starting",
+                    json!({ "command": command, "replacing": server_lock.pid }),
+                );
+                replace_server(
+                    name,
+                    opts,
+                    metadata,
+                    client_pid,
+                    command,
+                    &server_lock,
+                    reason,
+                )?;
+                report.pid_assigned();
+                return Ok(AttachAction::Replaced);
+            }
+        }
+    }
 
     match state {
         ServerState::Stopped => {
-            // Server not running - we need a command to start it
-            if command.is_empty() {
-                bail!(
-                    "Server '{}' is not running and no command provided. \
+            // Server not running - we need a command to start it (a
+            // container can fall back on its image's)
+            if command.is_empty() && opts.image.is_none() {
+                return Err(ErrorKind::NotRunning.error(format!(
+                    "Server '{}' is not running and no command provided (and the config \
+                     doesn't define one). \
                      Usage: sharedserver use [--grace-period DURATION] [--pid PID] <name> -- <command> [args...]",
                     name
-                );
+                )));
             }
 
             // Start the server atomically with this client as the initial client (refcount=1)
             // This avoids the refcount=0 window that would trigger immediate grace period
-            super::start::execute_with_client(
-                name,
-                grace_period,
-                env_vars,
-                command,
-                client_pid,
-                metadata.clone(),
-                log_file,
-            )?;
+            report.emit("starting", json!({ "command": command }));
+            super::start::execute_with_client(name, opts, command, client_pid, metadata.clone())?;
+            report.pid_assigned();
 
             // Read the server and clients info to get PID and refcount for output
             if let Ok(server_lock) = read_server_lock(name) {
-                let refcount = read_clients_lock(name).map(|c| c.refcount).unwrap_or(1);
+                let refcount = read_clients_lock(name).map(|c| c.refcount()).unwrap_or(1);
                 print_success(&format!(
                     "Started server {} (PID: {}, refcount: {})",
                     format_server_name(name),
@@ -64,7 +523,7 @@ pub fn execute(
                 ));
             }
 
-            Ok(())
+            Ok(AttachAction::Started)
         }
         ServerState::Active => {
             // Server exists - just increment refcount
@@ -76,11 +535,11 @@ pub fn execute(
                 print_success(&format!(
                     "Attached to server {} (refcount: {})",
                     format_server_name(name),
-                    format_refcount(clients_lock.refcount)
+                    format_refcount(clients_lock.refcount())
                 ));
             }
 
-            Ok(())
+            Ok(AttachAction::Attached)
         }
         ServerState::Grace => {
             // Server in grace period - rescue it
@@ -91,21 +550,303 @@ pub fn execute(
                 print_warning(&format!(
                     "Rescued server {} from grace period (refcount: {})",
                     format_server_name(name),
-                    format_refcount(clients_lock.refcount)
+                    format_refcount(clients_lock.refcount())
                 ));
             }
 
-            Ok(())
+            Ok(AttachAction::Rescued)
         }
         ServerState::Defunct => {
             // Previous instance died and is still being torn down by its watcher.
             // Don't race the watcher's cleanup; ask the caller to retry.
-            bail!(
+            Err(ErrorKind::ShuttingDown.error(format!(
                 "Server '{}' is shutting down (defunct, cleanup pending). Retry shortly, \
                  or run 'sharedserver admin kill {}' if it is stuck.",
-                name,
-                name
+                name, name
+            )))
+        }
+    }
+}
+
+/// Report `attached` for `client_pid`, once the server is usable: at once,
+/// or, if it has a health check, when the check first passes (or is given up
+/// on).
+fn report_attached(
+    name: &str,
+    client_pid: i32,
+    attached: AttachAction,
+    report: Report<'_>,
+) -> Result<()> {
+    let lock = read_server_lock(name)?;
+    if let Some(check) = &lock.health_check {
+        wait_ready(name, &lock, check, report)?;
+    }
+    let refcount = read_clients_lock(name).map(|c| c.refcount()).ok();
+    report.emit(
+        "attached",
+        json!({
+            "action": attached.as_str(),
+            "client_pid": client_pid,
+            "pid": lock.pid,
+            "refcount": refcount,
+            "address": lock.server_address(),
+        }),
+    );
+    Ok(())
+}
+
+/// Wait for the running server `name` to be ready for dependents: until its
+/// health check passes, if it has one. Fails if the check is given up on or
+/// the server exits first.
+pub fn wait_until_ready(name: &str) -> Result<()> {
+    let lock = read_server_lock(name)?;
+    let Some(check) = &lock.health_check else {
+        return Ok(());
+    };
+    let report = Report {
+        name,
+        enabled: false,
+    };
+    if wait_ready(name, &lock, check, report)? {
+        Ok(())
+    } else {
+        Err(ErrorKind::StartTimeout.error(format!(
+            "Server '{}' didn't pass its health check ({})",
+            name,
+            check.probe.describe()
+        )))
+    }
+}
+
+/// Use the servers `name` depends on in the config, directly or not, for
+/// `pid` as `use` would, each after its own dependencies and once they are
+/// ready (see [`wait_until_ready`]). `name` itself is left to the caller. A
+/// config that can't be read has no dependencies to start.
+pub fn use_dependencies(name: &str, pid: Option<i32>, flags: UseFlags) -> Result<()> {
+    let Ok(config) = load_config() else {
+        return Ok(());
+    };
+    let order = config.start_order(&[name])?;
+    let home = std::env::current_dir().context("Failed to read the working directory")?;
+    for &dependency in order.iter().filter(|&&server| server != name) {
+        let mut opts = StartOptions::default();
+        let mut command = Vec::new();
+        let used =
+            apply_server(dependency, &config, None, None, &mut opts, &mut command).and_then(|()| {
+                execute(
+                    dependency,
+                    &opts,
+                    Some(format!("dependency of {}", name)),
+                    pid,
+                    UseFlags {
+                        replace: false,
+                        ..flags
+                    },
+                    &command,
+                )
+            });
+        // A dependency's `cwd` is its own, not `name`'s.
+        std::env::set_current_dir(&home)
+            .with_context(|| format!("Failed to return to {}", home.display()))?;
+        used.and_then(|()| wait_until_ready(dependency))
+            .with_context(|| format!("Failed to start '{}', which '{}' needs", dependency, name))?;
+    }
+    Ok(())
+}
+
+/// Probe `lock`'s server until its health check passes, and report that
+/// (`ready-probe-passed`). Gives up (`ready-probe-failed`, and `false`) when
+/// the watcher would judge it unhealthy, `retries` intervals in; fails if the
+/// server exits first.
+fn wait_ready(
+    name: &str,
+    lock: &ServerLock,
+    check: &HealthCheck,
+    report: Report<'_>,
+) -> Result<bool> {
+    let timeout = check.timeout()?;
+    let started = Instant::now();
+    let deadline = started + check.interval()? * check.retries.max(1) + timeout;
+    let probe = check.probe.describe();
+    loop {
+        let error = match check.probe.run(timeout) {
+            Ok(()) => {
+                report.emit(
+                    "ready-probe-passed",
+                    json!({ "probe": probe, "elapsed_ms": started.elapsed().as_millis() as u64 }),
+                );
+                return Ok(true);
+            }
+            Err(error) => error,
+        };
+        // Relaunched (restart policy) or gone: the probe was of a server that
+        // no longer exists.
+        let current = read_server_lock(name).ok().filter(|l| l.pid == lock.pid);
+        let Some(current) = current.filter(|_| is_process_alive(lock.pid)) else {
+            return Err(ErrorKind::NotRunning.error(format!(
+                "Server '{}' exited before its health check passed ({})",
+                name, error
+            )));
+        };
+        if current.is_unhealthy() || Instant::now() >= deadline {
+            report.emit(
+                "ready-probe-failed",
+                json!({ "probe": probe, "error": error }),
             );
+            return Ok(false);
         }
+        std::thread::sleep(READY_PROBE_INTERVAL);
+    }
+}
+
+/// Print what the daemon did for a `use`, as the direct path would have.
+fn report_daemon_use(name: &str, result: &serde_json::Value) {
+    let refcount = result["refcount"].as_u64().unwrap_or(1) as u32;
+    match result["action"].as_str() {
+        Some("started") => print_success(&format!(
+            "Started server {} (PID: {}, refcount: {})",
+            format_server_name(name),
+            format_pid(result["pid"].as_i64().unwrap_or(0) as i32),
+            format_refcount(refcount)
+        )),
+        Some("rescued") => print_warning(&format!(
+            "Rescued server {} from grace period (refcount: {})",
+            format_server_name(name),
+            format_refcount(refcount)
+        )),
+        _ => print_success(&format!(
+            "Attached to server {} (refcount: {})",
+            format_server_name(name),
+            format_refcount(refcount)
+        )),
+    }
+}
+
+/// Drain the running instance and start a new one with `command`, seeding it
+/// with the old instance's (still-live) clients plus the caller.
+fn replace_server(
+    name: &str,
+    opts: &StartOptions,
+    metadata: Option<String>,
+    client_pid: i32,
+    command: &[String],
+    old: &ServerLock,
+    reason: &str,
+) -> Result<()> {
+    // Snapshot the clients before teardown removes the clients lockfile.
+    let mut clients = read_clients_lock(name)
+        .map(|c| c.clients)
+        .unwrap_or_default();
+
+    print_info(&format!(
+        "Replacing server {} (PID: {}): {}",
+        format_server_name(name),
+        format_pid(old.pid),
+        reason
+    ));
+
+    super::stop::execute(name, true, REPLACE_DRAIN_TIMEOUT)?;
+
+    // Clients that died while we were draining must not keep the new instance
+    // alive; the caller is (re)attached with its fresh metadata.
+    clients.retain(|pid, client| client.is_alive(*pid));
+    clients.insert(client_pid, ClientInfo::new(client_pid, metadata));
+
+    super::start::execute_with_clients(name, opts, command, clients)?;
+
+    let _ = sharedserver::core::log::log_invocation(
+        name,
+        &sharedserver::core::log::InvocationLog::success(
+            "replace",
+            &[name.to_string()],
+            Some(serde_json::json!({
+                "old_pid": old.pid,
+                "old_command": old.command,
+                "new_command": command,
+                "reason": reason,
+            })),
+        ),
+    );
+
+    if let Ok(server_lock) = read_server_lock(name) {
+        let refcount = read_clients_lock(name).map(|c| c.refcount()).unwrap_or(1);
+        print_success(&format!(
+            "Replaced server {} (PID: {}, refcount: {})",
+            format_server_name(name),
+            format_pid(server_lock.pid),
+            format_refcount(refcount)
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lock(command: &[&str], env: &[&str]) -> ServerLock {
+        ServerLock {
+            pid: 1,
+            command: command.iter().map(|s| s.to_string()).collect(),
+            grace_period: "5m".to_string(),
+            env: env.iter().map(|s| s.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn strings(v: &[&str]) -> Vec<String> {
+        v.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn identical_request_does_not_differ() {
+        let l = lock(&["srv", "--port", "1"], &["A=1", "B=2"]);
+        assert!(!differs_from_request(
+            &l,
+            &strings(&["srv", "--port", "1"]),
+            &strings(&["B=2", "A=1"])
+        ));
+    }
+
+    #[test]
+    fn changed_command_or_env_differs() {
+        let l = lock(&["srv", "--port", "1"], &["A=1"]);
+        assert!(differs_from_request(
+            &l,
+            &strings(&["srv", "--port", "2"]),
+            &strings(&["A=1"])
+        ));
+        assert!(differs_from_request(
+            &l,
+            &strings(&["srv", "--port", "1"]),
+            &strings(&["A=2"])
+        ));
+        assert!(differs_from_request(
+            &l,
+            &strings(&["srv", "--port", "1"]),
+            &[]
+        ));
+    }
+
+    #[test]
+    fn rebuilt_executable_is_a_reason_to_replace() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!("use-replace-exe-{}", std::process::id()));
+        std::fs::write(&path, "#!/bin/sh\nexec sleep 100\n").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let program = path.to_str().unwrap();
+
+        let mut l = lock(&[program], &[]);
+        l.executable = ExeSnapshot::capture(program, None);
+        assert_eq!(replace_reason(&l, &strings(&[program]), &[]), None);
+
+        std::fs::write(&path, "#!/bin/sh\nexec sleep 200\n").unwrap();
+        assert_eq!(
+            replace_reason(&l, &strings(&[program]), &[]),
+            Some("executable changed on disk")
+        );
+        let _ = std::fs::remove_file(&path);
     }
 }
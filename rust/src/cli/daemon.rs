@@ -0,0 +1,826 @@
+//! `sharedserver daemon`: one long-running process that supervises every
+//! server started through it, instead of one watcher process per server.
+//!
+//! The daemon listens on `daemon.sock` in the lock directory and speaks
+//! JSON-RPC 2.0, one request and one response per line. Commands run with
+//! `--via-daemon` send `use`, `unuse`, and `admin start` to it when it is
+//! running, so those changes are made one at a time by a single process.
+//! Servers it launches are its children, run in the client's directory and
+//! environment, and are supervised by a [`Watch`] stepped from the daemon's
+//! own loop. Lockfiles are still written as usual, so every other command
+//! (and a CLI without `--via-daemon`) sees these servers like any other.
+//!
+//! It can be started on demand by systemd socket activation, and with
+//! `--idle-exit` leaves again once it has had nothing to do for a while.
+//!
+//! With `--http ADDR` it also answers a small REST API (see [`Daemon::route`])
+//! for dashboards and remote scripts, guarded by a bearer token. With
+//! `--grpc ADDR` (the `grpc` feature) the same operations are served over
+//! gRPC, by a process it forks (see [`crate::grpc`]), with the same token.
+//!
+//! The daemon is single-threaded, like the watcher, so it can fork servers
+//! safely. The price is that a slow step for one server (waiting out a
+//! server's SIGTERM, or a crash loop's restart backoff) holds up the rest.
+
+use anyhow::{Context, Result};
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::Pid;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sharedserver::core::front::Front;
+use sharedserver::core::handle::{attach_client, detach_client};
+use sharedserver::core::lockfile::{ensure_lockfile_dir, lockfile_dir};
+use sharedserver::core::log::{log_invocation, InvocationLog};
+use sharedserver::core::log_capture::LogCapture;
+use sharedserver::core::sd_notify;
+use sharedserver::core::stop::request_stop;
+use sharedserver::core::webhook::WEBHOOK_ENV;
+use sharedserver::core::{
+    delete_clients_lock, delete_server_lock, get_server_state, read_clients_lock, read_server_lock,
+    ClientInfo, ErrorKind, ServerState,
+};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::SocketAddr;
+use std::os::fd::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::commands::start::{
+    listen_front, prepare_launch, publish_launch, spawn_server, ClientContext, StartOptions,
+};
+use crate::http;
+use crate::watcher::{self, Relaunch, Watch};
+
+/// The longest the daemon sleeps between passes over its servers. Their own
+/// timers usually wake it sooner; this bounds how late it notices a change
+/// made without it (a plain `use`, or a client exiting).
+const TICK: Duration = Duration::from_millis(100);
+
+/// How long a connection may take to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// JSON-RPC error codes: the reserved ones, and the one for a failed call,
+/// whose `data.exit_code` carries the [`ErrorKind`] exit code.
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const CALL_FAILED: i64 = -32000;
+
+/// Where the daemon listens.
+pub fn socket_path() -> Result<PathBuf> {
+    Ok(lockfile_dir()?.join("daemon.sock"))
+}
+
+/// Parameters of the `use` method.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UseParams {
+    pub name: String,
+    pub client_pid: i32,
+    pub metadata: Option<String>,
+    /// Empty to only attach to a running server.
+    pub command: Vec<String>,
+    pub options: StartOptions,
+    pub context: ClientContext,
+}
+
+/// Parameters of the `start` method (`admin start`).
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StartParams {
+    pub name: String,
+    pub command: Vec<String>,
+    pub options: StartOptions,
+    pub context: ClientContext,
+}
+
+/// Parameters of the `unuse` method.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UnuseParams {
+    pub name: String,
+    pub client_pid: i32,
+}
+
+/// A server the daemon supervises, and when its watch is next due a pass.
+struct Supervised {
+    watch: Watch,
+    due: Instant,
+}
+
+struct Daemon {
+    servers: Vec<Supervised>,
+    /// `--log-timestamps` relays and `--listen` fronts of finished watches,
+    /// reaped once they exit.
+    relays: Vec<i32>,
+    started_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Parameters of the `stop` method.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StopParams {
+    pub name: String,
+}
+
+/// The body of `POST /servers/{name}/use`. Only `pid` is required; a server
+/// launched this way runs in `cwd` (default: the daemon's) with the daemon's
+/// environment.
+#[derive(Debug, Deserialize)]
+struct HttpUse {
+    pid: i32,
+    #[serde(default)]
+    metadata: Option<String>,
+    #[serde(default)]
+    command: Vec<String>,
+    #[serde(default)]
+    options: StartOptions,
+    #[serde(default)]
+    cwd: Option<PathBuf>,
+}
+
+/// The body of `POST /servers/{name}/unuse`.
+#[derive(Debug, Deserialize)]
+struct HttpUnuse {
+    pid: i32,
+}
+
+/// Run the daemon in the foreground until SIGTERM, SIGINT, or a `shutdown`
+/// request; its servers go down with it (unless started with `--linger`).
+/// With `http`, also serve the REST API on that address, and with `grpc`, the
+/// gRPC API. With `idle_exit`, exit once that long has passed with no
+/// servers and no connections.
+///
+/// Under systemd socket activation the listening socket is the one passed in
+/// (`LISTEN_FDS`), and is left in place on exit so the next connection starts
+/// the daemon again.
+pub fn run(http: Option<&str>, grpc: Option<&str>, idle_exit: Option<Duration>) -> Result<()> {
+    let path = socket_path()?;
+    let activated = sd_notify::listen_fds().first().copied();
+    let listener = match activated {
+        // SAFETY: systemd passed this descriptor to us alone, and nothing
+        // else in the process takes ownership of it.
+        Some(fd) => unsafe { UnixListener::from_raw_fd(fd) },
+        None => bind(&path)?,
+    };
+    listener.set_nonblocking(true)?;
+
+    // The token both APIs' requests must carry.
+    let token_file = lockfile_dir()?.join("http.token");
+    let token = if http.is_some() || grpc.is_some() {
+        Some(http::load_token(&token_file)?)
+    } else {
+        None
+    };
+    let http = http.map(http::bind).transpose()?;
+    // Forked before the signal handlers go in, so SIGTERM ends it outright.
+    let grpc = match (grpc, &token) {
+        (Some(address), Some(token)) => Some(spawn_grpc(address, token)?),
+        _ => None,
+    };
+
+    watcher::install_sigchld_wakeup();
+    watcher::install_sigterm_handler();
+    watcher::install_sigint_handler();
+    watcher::install_sighup_handler();
+    log::info!(
+        "daemon {} listening on {}",
+        std::process::id(),
+        path.display()
+    );
+    crate::output::print_success(&format!(
+        "Daemon listening on {} (PID: {}{})",
+        path.display(),
+        std::process::id(),
+        if activated.is_some() {
+            ", socket-activated"
+        } else {
+            ""
+        }
+    ));
+    sd_notify::notify_ready();
+    let token_source = if token_file.exists() {
+        token_file.display().to_string()
+    } else {
+        format!("${}", http::TOKEN_ENV)
+    };
+    if let Some(listener) = &http {
+        crate::output::print_info(&format!(
+            "HTTP API on http://{} (token: {})",
+            listener.local_addr()?,
+            token_source
+        ));
+    }
+    if let Some((_, address)) = &grpc {
+        crate::output::print_info(&format!(
+            "gRPC API on {} (token: {})",
+            address, token_source
+        ));
+    }
+
+    let mut daemon = Daemon {
+        servers: Vec::new(),
+        relays: Vec::new(),
+        started_at: chrono::Utc::now(),
+    };
+    let mut last_active = Instant::now();
+    while !watcher::termination_requested() {
+        loop {
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    last_active = Instant::now();
+                    daemon.serve(stream);
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    log::warn!("accept failed: {}", e);
+                    break;
+                }
+            }
+        }
+        if let (Some(listener), Some(token)) = (&http, &token) {
+            while let Ok((stream, _)) = listener.accept() {
+                last_active = Instant::now();
+                http::serve(stream, token, |request| daemon.route(request));
+            }
+        }
+        daemon.step_due();
+        daemon.reap_relays();
+        if !daemon.servers.is_empty() {
+            last_active = Instant::now();
+        } else if idle_exit.is_some_and(|idle| last_active.elapsed() >= idle) {
+            log::info!("daemon idle with no servers; exiting");
+            break;
+        }
+        let mut fds = vec![listener.as_raw_fd()];
+        fds.extend(http.as_ref().map(|listener| listener.as_raw_fd()));
+        wait_readable(&fds, daemon.next_due());
+    }
+
+    // Each watch sees the request on its next pass and takes its server down.
+    // A passed-in socket belongs to systemd, which keeps listening on it.
+    if activated.is_none() {
+        let _ = std::fs::remove_file(&path);
+    }
+    if let Some((pid, _)) = grpc {
+        let pid = Pid::from_raw(pid);
+        let _ = nix::sys::signal::kill(pid, nix::sys::signal::Signal::SIGTERM);
+        let _ = waitpid(pid, None);
+    }
+    if token.is_some() {
+        let _ = std::fs::remove_file(&token_file);
+    }
+    for mut supervised in std::mem::take(&mut daemon.servers) {
+        while supervised.watch.step().is_some() {}
+        supervised.watch.finish();
+    }
+    log::info!("daemon {} exiting", std::process::id());
+    Ok(())
+}
+
+/// Listen for gRPC on `address` and fork the process that serves it. Returns
+/// its PID and the address it took.
+#[cfg(feature = "grpc")]
+fn spawn_grpc(address: &str, token: &str) -> Result<(i32, SocketAddr)> {
+    let listener = crate::grpc::bind(address)?;
+    let local = listener.local_addr()?;
+    Ok((crate::grpc::spawn(listener, token.to_string())?, local))
+}
+
+#[cfg(not(feature = "grpc"))]
+fn spawn_grpc(_: &str, _: &str) -> Result<(i32, SocketAddr)> {
+    Err(ErrorKind::InvalidArgs.error("--grpc needs a sharedserver built with the `grpc` feature"))
+}
+
+/// Listen on `path`, unless another daemon already is.
+fn bind(path: &Path) -> Result<UnixListener> {
+    if UnixStream::connect(path).is_ok() {
+        return Err(ErrorKind::AlreadyRunning.error(format!(
+            "A daemon is already listening on {}",
+            path.display()
+        )));
+    }
+    // Nobody answered, so anything there is left over from a daemon that died.
+    let _ = std::fs::remove_file(path);
+    ensure_lockfile_dir()?;
+    let listener = UnixListener::bind(path)
+        .with_context(|| format!("Failed to listen on {}", path.display()))?;
+    // As private as the lockfiles: a group-shared lock directory shares the
+    // daemon with the group too.
+    let mode = if sharedserver::core::shared::shared_group().is_some() {
+        0o660
+    } else {
+        0o600
+    };
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+        .with_context(|| format!("Failed to restrict {}", path.display()))?;
+    Ok(listener)
+}
+
+/// Sleep until a connection arrives on one of `listeners` or `until`,
+/// whichever is first, but no longer than [`TICK`].
+fn wait_readable(listeners: &[RawFd], until: Option<Instant>) {
+    let timeout = until
+        .map_or(TICK, |due| due.saturating_duration_since(Instant::now()))
+        .min(TICK);
+    let mut fds: Vec<libc::pollfd> = listeners
+        .iter()
+        .map(|&fd| libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        })
+        .collect();
+    // SAFETY: poll reads and writes only the pollfds passed.
+    unsafe {
+        libc::poll(
+            fds.as_mut_ptr(),
+            fds.len() as libc::nfds_t,
+            timeout.as_millis() as libc::c_int,
+        )
+    };
+}
+
+impl Daemon {
+    /// Give every watch that is due its pass, dropping those that are over.
+    fn step_due(&mut self) {
+        let now = Instant::now();
+        let mut index = 0;
+        while index < self.servers.len() {
+            let supervised = &mut self.servers[index];
+            if supervised.due > now {
+                index += 1;
+                continue;
+            }
+            // Drain the watch's wakeups; its pass looks at everything anyway.
+            supervised.watch.wait(Duration::ZERO);
+            match supervised.watch.step() {
+                Some(timeout) => {
+                    supervised.due = Instant::now() + timeout;
+                    index += 1;
+                }
+                None => {
+                    let finished = self.servers.remove(index);
+                    finished.watch.finish();
+                    self.relays.extend(finished.watch.relay_pids());
+                }
+            }
+        }
+    }
+
+    fn next_due(&self) -> Option<Instant> {
+        self.servers.iter().map(|s| s.due).min()
+    }
+
+    /// Have `name`'s watch (if it is ours) look again now, after a change
+    /// made on its behalf.
+    fn poke(&mut self, name: &str) {
+        if let Some(supervised) = self.servers.iter_mut().find(|s| s.watch.name() == name) {
+            supervised.due = Instant::now();
+        }
+    }
+
+    fn reap_relays(&mut self) {
+        self.relays.retain(|&relay| {
+            matches!(
+                waitpid(Pid::from_raw(relay), Some(WaitPidFlag::WNOHANG)),
+                Ok(WaitStatus::StillAlive)
+            )
+        });
+    }
+
+    /// Answer every request on `stream` until the client closes it.
+    fn serve(&mut self, stream: UnixStream) {
+        let _ = stream.set_nonblocking(false);
+        let _ = stream.set_read_timeout(Some(REQUEST_TIMEOUT));
+        let Ok(mut writer) = stream.try_clone() else {
+            return;
+        };
+        for line in BufReader::new(stream).lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            let response = self.respond(&line);
+            if writeln!(writer, "{}", response).is_err() {
+                break;
+            }
+        }
+    }
+
+    /// The JSON-RPC response to one request line.
+    fn respond(&mut self, line: &str) -> Value {
+        let request: Value = match serde_json::from_str(line) {
+            Ok(request) => request,
+            Err(e) => return rpc_error(Value::Null, PARSE_ERROR, &e.to_string(), None),
+        };
+        let id = request.get("id").cloned().unwrap_or(Value::Null);
+        let method = request.get("method").and_then(Value::as_str).unwrap_or("");
+        let params = request.get("params").cloned().unwrap_or(Value::Null);
+        log::debug!("request {}: {}", id, method);
+
+        let result = match method {
+            "ping" => Ok(Ok(self.status())),
+            "use" => params_of(params).map(|p| self.use_server(p)),
+            "unuse" => params_of(params).map(|p| self.unuse_server(p)),
+            "start" => params_of(params).map(|p| self.start_server(p)),
+            "stop" => params_of(params).map(|p: StopParams| self.stop_server(&p.name)),
+            "shutdown" => {
+                watcher::request_termination();
+                Ok(Ok(json!({ "pid": std::process::id() })))
+            }
+            _ => {
+                return rpc_error(
+                    id,
+                    METHOD_NOT_FOUND,
+                    &format!("Unknown method '{}'", method),
+                    None,
+                )
+            }
+        };
+        match result {
+            Ok(Ok(result)) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Ok(Err(e)) => rpc_error(
+                id,
+                CALL_FAILED,
+                &format!("{:#}", e),
+                Some(json!({ "exit_code": sharedserver::core::error::exit_code(&e) })),
+            ),
+            Err(e) => rpc_error(id, INVALID_PARAMS, &format!("{:#}", e), None),
+        }
+    }
+
+    /// `ping`: who we are and what we supervise.
+    fn status(&self) -> Value {
+        let servers: Vec<Value> = self
+            .servers
+            .iter()
+            .map(|s| json!({ "name": s.watch.name(), "pid": s.watch.server_pid() }))
+            .collect();
+        json!({
+            "pid": std::process::id(),
+            "version": env!("CARGO_PKG_VERSION"),
+            "started_at": self.started_at,
+            "servers": servers,
+        })
+    }
+
+    /// `use`: attach to `name`, launching it if it isn't running. The same
+    /// rules as the `use` command, minus `--replace`.
+    fn use_server(&mut self, params: UseParams) -> Result<Value> {
+        let name = params.name.as_str();
+        let state = get_server_state(name)?;
+        match state {
+            ServerState::Stopped => {
+                if params.command.is_empty() && params.options.image.is_none() {
+                    return Err(ErrorKind::NotRunning.error(format!(
+                        "Server '{}' is not running and no command provided",
+                        name
+                    )));
+                }
+                let mut clients = HashMap::new();
+                clients.insert(
+                    params.client_pid,
+                    ClientInfo::new(params.client_pid, params.metadata),
+                );
+                let pid = self.launch(
+                    name,
+                    &params.options,
+                    &params.command,
+                    clients,
+                    params.context,
+                )?;
+                Ok(json!({ "action": "started", "pid": pid, "refcount": 1 }))
+            }
+            ServerState::Active | ServerState::Grace => {
+                let refcount = attach_client(name, params.client_pid, params.metadata.clone())?;
+                let _ = log_invocation(
+                    name,
+                    &InvocationLog::success(
+                        "incref",
+                        &[name.to_string()],
+                        Some(json!({
+                            "new_refcount": refcount,
+                            "state": state.as_str(),
+                            "client_pid": params.client_pid,
+                            "metadata": params.metadata,
+                            "via": "daemon",
+                        })),
+                    ),
+                );
+                self.poke(name);
+                let pid = read_server_lock(name).map(|lock| lock.pid).ok();
+                let action = if state == ServerState::Grace {
+                    "rescued"
+                } else {
+                    "attached"
+                };
+                Ok(json!({ "action": action, "pid": pid, "refcount": refcount }))
+            }
+            ServerState::Defunct => Err(ErrorKind::ShuttingDown.error(format!(
+                "Server '{}' is shutting down (defunct, cleanup pending). Retry shortly.",
+                name
+            ))),
+        }
+    }
+
+    /// `unuse`: detach a client, as the `unuse` command does.
+    fn unuse_server(&mut self, params: UnuseParams) -> Result<Value> {
+        let name = params.name.as_str();
+        let state = get_server_state(name)?;
+        match state {
+            ServerState::Stopped => {
+                Err(ErrorKind::NotRunning.error(format!("Server '{}' is not running", name)))
+            }
+            ServerState::Defunct => Err(ErrorKind::ShuttingDown.error(format!(
+                "Server '{}' is shutting down (defunct, cleanup pending)",
+                name
+            ))),
+            ServerState::Grace => Err(ErrorKind::NotAttached.error(format!(
+                "Server '{}' is in grace period (refcount already 0)",
+                name
+            ))),
+            ServerState::Active => {
+                let refcount = detach_client(name, params.client_pid)?;
+                let _ = log_invocation(
+                    name,
+                    &InvocationLog::success(
+                        "decref",
+                        &[name.to_string()],
+                        Some(json!({
+                            "new_refcount": refcount,
+                            "client_pid": params.client_pid,
+                            "via": "daemon",
+                        })),
+                    ),
+                );
+                self.poke(name);
+                Ok(json!({ "refcount": refcount }))
+            }
+        }
+    }
+
+    /// `stop`: SIGTERM `name` the way `admin stop` does, without waiting for
+    /// it to go: if the daemon supervises it, the daemon itself finishes the
+    /// teardown.
+    fn stop_server(&mut self, name: &str) -> Result<Value> {
+        if get_server_state(name)? == ServerState::Stopped {
+            return Err(ErrorKind::NotRunning.error(format!("Server '{}' is not running", name)));
+        }
+        let server = read_server_lock(name)?;
+        let notified = request_stop(name, &server)?;
+        self.poke(name);
+        Ok(json!({ "pid": server.pid, "notified_clients": notified }))
+    }
+
+    /// Answer a REST request:
+    ///
+    /// - `GET /servers`: the running servers, as `list --json`
+    /// - `GET /servers/{name}`: one server, as `info --json`
+    /// - `GET /servers/{name}/clients`: its attached clients
+    /// - `POST /servers/{name}/use`, `.../unuse`: as the RPC methods, from a
+    ///   body like `{"pid": 1234, "command": [...]}`
+    /// - `POST /servers/{name}/stop`: as `admin stop`, answered with 202 as
+    ///   soon as the server has been signalled
+    fn route(&mut self, request: http::Request) -> http::Response {
+        let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+        let method = request.method.as_str();
+        let result = match (method, segments.as_slice()) {
+            ("GET", ["servers"]) => crate::commands::list::snapshot(),
+            ("GET", ["servers", name]) => crate::commands::info::snapshot(name),
+            ("GET", ["servers", name, "clients"]) => clients_of(name),
+            ("POST", ["servers", name, "use"]) => match params_of::<HttpUse>(request.body) {
+                Ok(body) => {
+                    let mut context = match ClientContext::current() {
+                        Ok(context) => context,
+                        Err(e) => return http::Response::failed(&e),
+                    };
+                    if let Some(cwd) = body.cwd {
+                        context.cwd = cwd;
+                    }
+                    self.use_server(UseParams {
+                        name: name.to_string(),
+                        client_pid: body.pid,
+                        metadata: body.metadata,
+                        command: body.command,
+                        options: body.options,
+                        context,
+                    })
+                }
+                Err(e) => return http::Response::error(400, format!("{:#}", e)),
+            },
+            ("POST", ["servers", name, "unuse"]) => match params_of::<HttpUnuse>(request.body) {
+                Ok(body) => self.unuse_server(UnuseParams {
+                    name: name.to_string(),
+                    client_pid: body.pid,
+                }),
+                Err(e) => return http::Response::error(400, format!("{:#}", e)),
+            },
+            ("POST", ["servers", name, "stop"]) => {
+                return match self.stop_server(name) {
+                    Ok(body) => http::Response { status: 202, body },
+                    Err(e) => http::Response::failed(&e),
+                }
+            }
+            (
+                _,
+                ["servers"] | ["servers", _] | ["servers", _, "clients" | "use" | "unuse" | "stop"],
+            ) => return http::Response::error(405, format!("{} not allowed here", method)),
+            _ => return http::Response::error(404, format!("No route for {}", request.path)),
+        };
+        match result {
+            Ok(body) => http::Response::ok(body),
+            Err(e) => http::Response::failed(&e),
+        }
+    }
+
+    /// `start`: launch `name` with no clients, as `admin start` does.
+    fn start_server(&mut self, params: StartParams) -> Result<Value> {
+        let pid = self.launch(
+            &params.name,
+            &params.options,
+            &params.command,
+            HashMap::new(),
+            params.context,
+        )?;
+        Ok(json!({ "pid": pid }))
+    }
+
+    /// Launch `name` as our own child and start supervising it. Returns the
+    /// server's PID.
+    fn launch(
+        &mut self,
+        name: &str,
+        opts: &StartOptions,
+        command: &[String],
+        clients: HashMap<i32, ClientInfo>,
+        context: ClientContext,
+    ) -> Result<i32> {
+        // The client's default webhook, rather than the daemon's.
+        let mut opts = opts.clone();
+        if opts.on_event_webhook.is_none() {
+            opts.on_event_webhook = context
+                .env
+                .iter()
+                .find(|(key, value)| key == WEBHOOK_ENV && !value.is_empty())
+                .map(|(_, value)| value.clone());
+        }
+        let opts = &opts;
+        let clients = prepare_launch(name, opts, command, clients, Some(context.cwd.clone()))?;
+        let log_file = opts.log_file.as_deref();
+        let front = match listen_front(name).and_then(|front| {
+            front
+                .map(|(listener, upstream)| Front::spawn(listener, &upstream))
+                .transpose()
+        }) {
+            Ok(front) => front,
+            Err(e) => {
+                let _ = delete_server_lock(name);
+                let _ = delete_clients_lock(name);
+                return Err(e);
+            }
+        };
+        let capture = log_file
+            .filter(|_| opts.log_timestamps)
+            .and_then(|path| LogCapture::spawn(path).ok());
+        let relaunch = Relaunch {
+            capture,
+            context: Some(context),
+            stdio: None,
+            front,
+        };
+        let server_pid = match spawn_server(
+            name,
+            command,
+            &opts.env_vars,
+            log_file,
+            &relaunch,
+            opts.container(name)?.as_ref(),
+        ) {
+            Ok(pid) => pid.as_raw(),
+            Err(e) => {
+                if let Some(front) = &relaunch.front {
+                    front.stop();
+                    self.relays.push(front.pid());
+                }
+                let _ = delete_server_lock(name);
+                let _ = delete_clients_lock(name);
+                return Err(e);
+            }
+        };
+        publish_launch(name, server_pid, true)?;
+
+        let watch = Watch::new(name, &opts.grace_period, relaunch, false)?;
+        let _ = log_invocation(
+            name,
+            &InvocationLog::success(
+                "start",
+                &[name.to_string()],
+                Some(json!({
+                    "server_pid": server_pid,
+                    "watcher_pid": std::process::id(),
+                    "command": command,
+                    "grace_period": opts.grace_period,
+                    "clients": clients.clients,
+                    "via": "daemon",
+                })),
+            ),
+        );
+        self.servers.push(Supervised {
+            watch,
+            due: Instant::now(),
+        });
+        Ok(server_pid)
+    }
+}
+
+/// `name`'s attached clients, keyed by PID.
+fn clients_of(name: &str) -> Result<Value> {
+    if get_server_state(name)? == ServerState::Stopped {
+        return Err(ErrorKind::NotRunning.error(format!("Server '{}' is not running", name)));
+    }
+    let clients = read_clients_lock(name)
+        .map(|lock| lock.clients)
+        .unwrap_or_default();
+    Ok(json!({ "refcount": clients.len(), "clients": clients }))
+}
+
+fn params_of<T: serde::de::DeserializeOwned>(params: Value) -> Result<T> {
+    serde_json::from_value(params).context("Invalid params")
+}
+
+fn rpc_error(id: Value, code: i64, message: &str, data: Option<Value>) -> Value {
+    let mut error = json!({ "code": code, "message": message });
+    if let Some(data) = data {
+        error["data"] = data;
+    }
+    json!({ "jsonrpc": "2.0", "id": id, "error": error })
+}
+
+/// Call `method` on the running daemon. `Ok(None)` if no daemon is listening,
+/// so the caller can do the work itself; a failed call comes back as the
+/// error the daemon reported, with its exit code.
+pub fn call(method: &str, params: Value) -> Result<Option<Value>> {
+    let path = socket_path()?;
+    let Ok(mut stream) = UnixStream::connect(&path) else {
+        log::debug!("no daemon on {}", path.display());
+        return Ok(None);
+    };
+    let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+    writeln!(stream, "{}", request).context("Failed to send request to the daemon")?;
+    stream.shutdown(std::net::Shutdown::Write)?;
+    let mut line = String::new();
+    BufReader::new(stream)
+        .read_line(&mut line)
+        .context("Failed to read the daemon's response")?;
+    let response: Value =
+        serde_json::from_str(&line).context("The daemon sent an invalid response")?;
+    if let Some(error) = response.get("error") {
+        let message = error["message"].as_str().unwrap_or("daemon request failed");
+        let exit_code = error["data"]["exit_code"].as_i64();
+        return Err(
+            match ErrorKind::ALL
+                .iter()
+                .find(|kind| Some(kind.exit_code() as i64) == exit_code)
+            {
+                Some(kind) => kind.error(message.to_string()),
+                None => anyhow::anyhow!("{}", message),
+            },
+        );
+    }
+    Ok(Some(response["result"].clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn daemon() -> Daemon {
+        Daemon {
+            servers: Vec::new(),
+            relays: Vec::new(),
+            started_at: chrono::Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_respond_reports_protocol_errors() {
+        let mut daemon = daemon();
+        assert_eq!(daemon.respond("not json")["error"]["code"], PARSE_ERROR);
+
+        let response = daemon.respond(r#"{"jsonrpc":"2.0","id":7,"method":"nope"}"#);
+        assert_eq!(response["id"], 7);
+        assert_eq!(response["error"]["code"], METHOD_NOT_FOUND);
+
+        let response = daemon.respond(r#"{"jsonrpc":"2.0","id":8,"method":"use","params":{}}"#);
+        assert_eq!(response["error"]["code"], INVALID_PARAMS);
+    }
+
+    #[test]
+    fn test_ping_lists_no_servers() {
+        let response = daemon().respond(r#"{"jsonrpc":"2.0","id":1,"method":"ping"}"#);
+        assert_eq!(response["result"]["pid"], std::process::id());
+        assert_eq!(response["result"]["servers"], json!([]));
+    }
+}
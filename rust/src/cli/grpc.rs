@@ -0,0 +1,263 @@
+//! The daemon's optional gRPC listener (`daemon --grpc ADDR`, built with the
+//! `grpc` feature): the API in `proto/sharedserver.proto`.
+//!
+//! It is served by a process the daemon forks at startup, so the daemon
+//! itself stays single-threaded (it forks servers) while this side runs an
+//! async runtime. Reads go straight to the lockfiles; `Use`, `Unuse`, and
+//! `Stop` are handed to the daemon over its socket, so the daemon makes those
+//! changes like any other client's. Calls need the HTTP API's bearer token.
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Serialize;
+use serde_json::Value;
+use sharedserver::core::aio;
+use sharedserver::core::filter::glob_match;
+use sharedserver::core::grpc::{self, proto};
+use sharedserver::core::lockfile::servers_with;
+use std::net::TcpListener;
+use std::os::fd::AsRawFd;
+use std::time::Duration;
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tonic::{Request, Response, Status};
+
+use crate::commands::start::{ClientContext, StartOptions};
+use crate::daemon::{self, StopParams, UnuseParams, UseParams};
+use crate::http;
+
+/// How often the gRPC process checks that the daemon is still there.
+const PARENT_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Bind the listener, in the daemon so a failure reaches the user.
+pub fn bind(address: &str) -> Result<TcpListener> {
+    TcpListener::bind(address).with_context(|| format!("Failed to listen for gRPC on {}", address))
+}
+
+/// Fork the process serving gRPC on `listener` to callers with `token`, and
+/// return its PID. It exits on SIGTERM or once the daemon is gone.
+///
+/// SAFETY: forks; like the rest of the daemon this relies on the process
+/// being single-threaded.
+pub fn spawn(listener: TcpListener, token: String) -> Result<i32> {
+    let parent = std::process::id() as i32;
+    match unsafe { nix::unistd::fork() } {
+        Ok(nix::unistd::ForkResult::Parent { child }) => Ok(child.as_raw()),
+        Ok(nix::unistd::ForkResult::Child) => {
+            // The daemon's socket and HTTP listener stay the daemon's.
+            sharedserver::core::front::close_inherited(listener.as_raw_fd());
+            let code = match serve(listener, token, parent) {
+                Ok(()) => 0,
+                Err(e) => {
+                    log::error!("gRPC listener failed: {:#}", e);
+                    1
+                }
+            };
+            std::process::exit(code);
+        }
+        Err(e) => bail!("Failed to fork the gRPC listener: {}", e),
+    }
+}
+
+/// Answer calls on `listener` until `parent` is gone.
+fn serve(listener: TcpListener, token: String, parent: i32) -> Result<()> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .context("Failed to start the gRPC runtime")?;
+    runtime.block_on(async move {
+        listener.set_nonblocking(true)?;
+        let incoming = TcpListenerStream::new(tokio::net::TcpListener::from_std(listener)?);
+        let check_token = move |request: Request<()>| {
+            let authorization = request
+                .metadata()
+                .get("authorization")
+                .and_then(|value| value.to_str().ok());
+            if http::authorized(authorization, &token) {
+                Ok(request)
+            } else {
+                Err(Status::unauthenticated("Missing or wrong bearer token"))
+            }
+        };
+        let parent_gone = async move {
+            while nix::unistd::getppid().as_raw() == parent {
+                tokio::time::sleep(PARENT_CHECK_INTERVAL).await;
+            }
+        };
+        tonic::transport::Server::builder()
+            .add_service(
+                proto::shared_server_server::SharedServerServer::with_interceptor(Api, check_token),
+            )
+            .serve_with_incoming_shutdown(incoming, parent_gone)
+            .await
+            .context("gRPC server failed")
+    })
+}
+
+/// Run `work`, which blocks (on locks, or on the daemon), off the runtime.
+async fn blocking<T: Send + 'static>(
+    work: impl FnOnce() -> Result<T> + Send + 'static,
+) -> Result<T, Status> {
+    tokio::task::spawn_blocking(work)
+        .await
+        .map_err(|e| Status::internal(e.to_string()))?
+        .map_err(|e| grpc::status(&e))
+}
+
+/// Call `method` on the daemon that forked us.
+fn call(method: &str, params: impl Serialize) -> Result<Value> {
+    daemon::call(method, serde_json::to_value(params)?)?
+        .ok_or_else(|| anyhow!("The daemon is no longer running"))
+}
+
+/// `options` over the CLI's defaults for whatever they leave out.
+fn start_options(options: Option<proto::StartOptions>) -> StartOptions {
+    let defaults = StartOptions::default();
+    let Some(options) = options else {
+        return defaults;
+    };
+    StartOptions {
+        grace_period: options.grace_period.unwrap_or(defaults.grace_period),
+        grace_clock: options.grace_clock.unwrap_or(defaults.grace_clock),
+        env_vars: options.env_vars,
+        log_file: options.log_file,
+        log_timestamps: options.log_timestamps,
+        restart: options.restart.unwrap_or(defaults.restart),
+        notify_clients: options.notify_clients,
+        linger: options.linger,
+        address: options.address,
+        ..defaults
+    }
+}
+
+struct Api;
+
+#[tonic::async_trait]
+impl proto::shared_server_server::SharedServer for Api {
+    async fn list(
+        &self,
+        request: Request<proto::ListRequest>,
+    ) -> Result<Response<proto::ListResponse>, Status> {
+        let request = request.into_inner();
+        let servers = blocking(move || {
+            let mut servers = Vec::new();
+            for name in servers_with(&["server.json"])? {
+                if !request
+                    .name
+                    .as_deref()
+                    .is_none_or(|pattern| glob_match(pattern, &name))
+                {
+                    continue;
+                }
+                // Gone since the scan: it no longer counts.
+                let Ok(server) = grpc::server(&name) else {
+                    continue;
+                };
+                if request.states.is_empty() || request.states.contains(&server.state) {
+                    servers.push(server);
+                }
+            }
+            Ok(servers)
+        })
+        .await?;
+        Ok(Response::new(proto::ListResponse { servers }))
+    }
+
+    async fn info(
+        &self,
+        request: Request<proto::InfoRequest>,
+    ) -> Result<Response<proto::Server>, Status> {
+        let name = request.into_inner().name;
+        Ok(Response::new(blocking(move || grpc::server(&name)).await?))
+    }
+
+    async fn r#use(
+        &self,
+        request: Request<proto::UseRequest>,
+    ) -> Result<Response<proto::UseResponse>, Status> {
+        let request = request.into_inner();
+        let result = blocking(move || {
+            let mut context = ClientContext::current()?;
+            if let Some(cwd) = request.cwd {
+                context.cwd = cwd.into();
+            }
+            call(
+                "use",
+                UseParams {
+                    name: request.name,
+                    client_pid: request.client_pid,
+                    metadata: request.metadata,
+                    command: request.command,
+                    options: start_options(request.options),
+                    context,
+                },
+            )
+        })
+        .await?;
+        let action = match result["action"].as_str() {
+            Some("started") => proto::use_response::Action::Started,
+            Some("attached") => proto::use_response::Action::Attached,
+            Some("rescued") => proto::use_response::Action::Rescued,
+            _ => proto::use_response::Action::Unspecified,
+        };
+        Ok(Response::new(proto::UseResponse {
+            action: action.into(),
+            pid: result["pid"].as_i64().unwrap_or(0) as i32,
+            refcount: result["refcount"].as_u64().unwrap_or(0) as u32,
+        }))
+    }
+
+    async fn unuse(
+        &self,
+        request: Request<proto::UnuseRequest>,
+    ) -> Result<Response<proto::UnuseResponse>, Status> {
+        let request = request.into_inner();
+        let result = blocking(move || {
+            call(
+                "unuse",
+                UnuseParams {
+                    name: request.name,
+                    client_pid: request.client_pid,
+                },
+            )
+        })
+        .await?;
+        Ok(Response::new(proto::UnuseResponse {
+            refcount: result["refcount"].as_u64().unwrap_or(0) as u32,
+        }))
+    }
+
+    async fn stop(
+        &self,
+        request: Request<proto::StopRequest>,
+    ) -> Result<Response<proto::StopResponse>, Status> {
+        let name = request.into_inner().name;
+        let result = blocking(move || call("stop", StopParams { name })).await?;
+        Ok(Response::new(proto::StopResponse {
+            pid: result["pid"].as_i64().unwrap_or(0) as i32,
+            notified_clients: result["notified_clients"].as_u64().unwrap_or(0) as u32,
+        }))
+    }
+
+    type EventsStream = ReceiverStream<Result<proto::Event, Status>>;
+
+    async fn events(
+        &self,
+        request: Request<proto::EventsRequest>,
+    ) -> Result<Response<Self::EventsStream>, Status> {
+        let name = request.into_inner().name;
+        let mut subscription = aio::subscribe(&name).map_err(|e| grpc::status(&e))?;
+        let (sender, receiver) = tokio::sync::mpsc::channel(16);
+        tokio::spawn(async move {
+            loop {
+                let event = tokio::select! {
+                    event = subscription.next() => event,
+                    () = sender.closed() => break,
+                };
+                let event = grpc::event(&event, chrono::Utc::now());
+                if sender.send(Ok(event)).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+}
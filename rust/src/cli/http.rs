@@ -0,0 +1,243 @@
+//! The daemon's optional HTTP listener (`daemon --http ADDR`): just enough
+//! HTTP/1.1 to answer one JSON request per connection.
+//!
+//! Routing lives with the daemon; this module reads requests, checks the
+//! bearer token, and writes responses.
+
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+use sharedserver::core::lockfile::LOCKFILE_MODE;
+use sharedserver::core::ErrorKind;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::time::Duration;
+
+/// How long a client may take to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The largest request body accepted.
+const MAX_BODY: usize = 1 << 20;
+
+/// The environment variable that supplies the API token.
+pub const TOKEN_ENV: &str = "SHAREDSERVER_HTTP_TOKEN";
+
+/// A parsed request.
+#[derive(Debug)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    /// The JSON body, or `Null` if there was none.
+    pub body: Value,
+}
+
+/// A response: a status code and a JSON body.
+pub struct Response {
+    pub status: u16,
+    pub body: Value,
+}
+
+impl Response {
+    pub fn ok(body: Value) -> Self {
+        Self { status: 200, body }
+    }
+
+    pub fn error(status: u16, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            body: json!({ "error": message.into() }),
+        }
+    }
+
+    /// A failed operation, with the status its [`ErrorKind`] maps to and the
+    /// exit code the CLI would have reported.
+    pub fn failed(err: &anyhow::Error) -> Self {
+        let exit_code = sharedserver::core::error::exit_code(err);
+        let status = match ErrorKind::ALL
+            .iter()
+            .find(|kind| kind.exit_code() == exit_code)
+        {
+            Some(ErrorKind::InvalidArgs) => 400,
+            Some(ErrorKind::NotRunning) => 404,
+            Some(ErrorKind::ShuttingDown | ErrorKind::AlreadyRunning | ErrorKind::NotAttached) => {
+                409
+            }
+            Some(ErrorKind::LockTimeout | ErrorKind::StartTimeout | ErrorKind::AtCapacity) => 503,
+            _ => 500,
+        };
+        Self {
+            status,
+            body: json!({ "error": format!("{:#}", err), "exit_code": exit_code }),
+        }
+    }
+}
+
+/// The token clients must send as `Authorization: Bearer <token>`: from
+/// [`TOKEN_ENV`] if set, otherwise a new random one written (owner-only) to
+/// `token_file` for local scripts to read.
+pub fn load_token(token_file: &Path) -> Result<String> {
+    if let Ok(token) = std::env::var(TOKEN_ENV) {
+        if token.is_empty() {
+            bail!("{} is set but empty", TOKEN_ENV);
+        }
+        let _ = std::fs::remove_file(token_file);
+        return Ok(token);
+    }
+    let mut bytes = [0u8; 16];
+    std::fs::File::open("/dev/urandom")
+        .and_then(|mut random| random.read_exact(&mut bytes))
+        .context("Failed to generate an API token")?;
+    let token: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+
+    let _ = std::fs::remove_file(token_file);
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(LOCKFILE_MODE)
+        .open(token_file)
+        .with_context(|| format!("Failed to write {}", token_file.display()))?;
+    writeln!(file, "{}", token)?;
+    Ok(token)
+}
+
+/// Bind the listener, non-blocking so the daemon can poll it with the rest.
+pub fn bind(address: &str) -> Result<TcpListener> {
+    let listener = TcpListener::bind(address)
+        .with_context(|| format!("Failed to listen for HTTP on {}", address))?;
+    listener.set_nonblocking(true)?;
+    Ok(listener)
+}
+
+/// Read one request from `stream`, answer it with `handle` (unless the token
+/// doesn't match), and close the connection.
+pub fn serve(stream: TcpStream, token: &str, handle: impl FnOnce(Request) -> Response) {
+    let _ = stream.set_nonblocking(false);
+    let _ = stream.set_read_timeout(Some(REQUEST_TIMEOUT));
+    let _ = stream.set_write_timeout(Some(REQUEST_TIMEOUT));
+    let Ok(mut writer) = stream.try_clone() else {
+        return;
+    };
+    let response = match read_request(BufReader::new(stream)) {
+        Ok((request, authorization)) => {
+            if authorized(authorization.as_deref(), token) {
+                log::debug!("HTTP {} {}", request.method, request.path);
+                handle(request)
+            } else {
+                Response::error(401, "Missing or wrong bearer token")
+            }
+        }
+        Err(e) => Response::error(400, format!("{:#}", e)),
+    };
+    let _ = write_response(&mut writer, &response);
+}
+
+/// The request, and its `Authorization` header if any.
+fn read_request(mut reader: impl BufRead) -> Result<(Request, Option<String>)> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        bail!("Malformed request line");
+    };
+    let method = method.to_string();
+    // The query string isn't used by any route.
+    let path = target.split('?').next().unwrap_or(target).to_string();
+
+    let mut content_length = 0;
+    let mut authorization = None;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            bail!("Connection closed in the headers");
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            bail!("Malformed header");
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("content-length") {
+            content_length = value.parse().context("Invalid Content-Length")?;
+        } else if name.eq_ignore_ascii_case("authorization") {
+            authorization = Some(value.to_string());
+        }
+    }
+    if content_length > MAX_BODY {
+        bail!("Request body too large");
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+    let body = if body.iter().all(u8::is_ascii_whitespace) {
+        Value::Null
+    } else {
+        serde_json::from_slice(&body).context("Request body is not JSON")?
+    };
+    Ok((Request { method, path, body }, authorization))
+}
+
+/// Whether `authorization` carries `token`, compared in constant time.
+pub fn authorized(authorization: Option<&str>, token: &str) -> bool {
+    let Some(sent) = authorization.and_then(|value| value.strip_prefix("Bearer ")) else {
+        return false;
+    };
+    let sent = sent.trim().as_bytes();
+    let token = token.as_bytes();
+    sent.len() == token.len() && sent.iter().zip(token).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+fn write_response(writer: &mut impl Write, response: &Response) -> std::io::Result<()> {
+    let body = serde_json::to_string(&response.body)?;
+    write!(
+        writer,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        reason(response.status),
+        body.len(),
+        body
+    )
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        202 => "Accepted",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_request_parses_body_and_token() {
+        let raw = "POST /servers/db/use?x=1 HTTP/1.1\r\nHost: x\r\nAuthorization: Bearer abc\r\n\
+                   Content-Length: 10\r\n\r\n{\"pid\": 1}";
+        let (request, authorization) = read_request(raw.as_bytes()).unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/servers/db/use");
+        assert_eq!(request.body["pid"], 1);
+        assert!(authorized(authorization.as_deref(), "abc"));
+        assert!(!authorized(authorization.as_deref(), "abd"));
+        assert!(!authorized(None, "abc"));
+    }
+
+    #[test]
+    fn test_failed_maps_error_kinds_to_statuses() {
+        let err = ErrorKind::NotRunning.error("gone");
+        let response = Response::failed(&err);
+        assert_eq!(response.status, 404);
+        assert_eq!(response.body["exit_code"], 10);
+        assert_eq!(Response::failed(&anyhow::anyhow!("boom")).status, 500);
+    }
+}
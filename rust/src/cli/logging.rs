@@ -0,0 +1,82 @@
+//! Diagnostics: what the CLI and watcher decide and why, enabled with
+//! `-v`/`-vv` or `RUST_LOG`.
+//!
+//! The CLI writes them to stderr. A watcher has no stderr anyone reads, so
+//! once a process becomes one, its diagnostics go to the server's watcher log
+//! as `diagnostic` events instead, beside the events explaining them.
+
+use log::{LevelFilter, Log, Metadata, Record};
+use serde_json::json;
+use std::sync::OnceLock;
+
+/// Set once this process has become `name`'s watcher.
+static WATCHER: OnceLock<String> = OnceLock::new();
+
+struct Logger {
+    /// Filtering and stderr formatting.
+    inner: env_logger::Logger,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.inner.matches(record) {
+            return;
+        }
+        match WATCHER.get() {
+            Some(name) => {
+                let _ = sharedserver::core::log::log_watcher_event(
+                    name,
+                    "diagnostic",
+                    json!({
+                        "level": record.level().as_str().to_lowercase(),
+                        "target": record.target(),
+                        "message": record.args().to_string(),
+                    }),
+                );
+            }
+            None => self.inner.log(record),
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// Install the logger. `RUST_LOG`, if set, takes precedence; otherwise
+/// `verbosity` is the number of `-v` flags: none shows only warnings, one adds
+/// debug diagnostics, two adds trace (every watcher wakeup).
+pub fn init(verbosity: u8) {
+    let level = match verbosity {
+        0 => LevelFilter::Warn,
+        1 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    };
+    let mut builder = env_logger::Builder::new();
+    match std::env::var("RUST_LOG") {
+        Ok(filters) if !filters.is_empty() => {
+            builder.parse_filters(&filters);
+        }
+        // Only our own diagnostics: dependencies stay at warnings.
+        _ => {
+            builder
+                .filter_level(LevelFilter::Warn)
+                .filter_module("sharedserver", level);
+        }
+    }
+    let inner = builder.build();
+    let max_level = inner.filter();
+    if log::set_boxed_logger(Box::new(Logger { inner })).is_ok() {
+        log::set_max_level(max_level);
+    }
+}
+
+/// Send this process's diagnostics to `name`'s watcher log from now on.
+/// Called by a freshly forked watcher.
+pub fn route_to_watcher_log(name: &str) {
+    let _ = WATCHER.set(name.to_string());
+}
@@ -1,3 +1,8 @@
 pub mod commands;
+pub mod daemon;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod http;
+pub mod logging;
 pub mod output;
 pub mod watcher;
@@ -1,32 +1,673 @@
+use anyhow::{anyhow, bail, Result};
+use clap::ValueEnum;
 use colored::*;
-use sharedserver::core::ServerState;
+use serde::Serialize;
+use sharedserver::core::tombstone::{DeathReason, Tombstone};
+use sharedserver::core::{ErrorKind, ServerState};
+use std::fmt;
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::{Duration, SystemTime};
 
+/// Environment variable selecting the output format when `--format` isn't
+/// given.
+pub const FORMAT_ENV: &str = "SHAREDSERVER_FORMAT";
+
+/// How command results are printed: for people (the default), or as a
+/// document for programs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum OutputFormat {
+    #[default]
+    Table,
+    Json,
+    Yaml,
+    /// Tab-separated rows under a header line (`list` only).
+    Tsv,
+    /// Comma-separated rows under a header line (`list` only).
+    Csv,
+}
+
+impl OutputFormat {
+    /// `--format` if given, else `SHAREDSERVER_FORMAT`, else table.
+    pub fn resolve(flag: Option<Self>) -> Result<Self> {
+        if let Some(format) = flag {
+            return Ok(format);
+        }
+        match std::env::var(FORMAT_ENV) {
+            Ok(value) if !value.is_empty() => Self::from_str(&value, true).map_err(|_| {
+                anyhow!(
+                    "Unknown {} '{}' (expected table, json, yaml, tsv, or csv)",
+                    FORMAT_ENV,
+                    value
+                )
+            }),
+            _ => Ok(Self::Table),
+        }
+    }
+
+    /// TSV or CSV: rows of plain values rather than a document.
+    pub fn is_delimited(self) -> bool {
+        matches!(self, Self::Tsv | Self::Csv)
+    }
+
+    /// This format, or JSON if a command's own `--json` flag was given.
+    pub fn or_json(self, json: bool) -> Self {
+        if json {
+            Self::Json
+        } else {
+            self
+        }
+    }
+}
+
+/// A command's result: printed as text for people, or serialized whole for
+/// `--format json`/`yaml`. Commands build one report and hand it to
+/// [`print_report`], so every format shows the same data.
+pub trait Report: Serialize {
+    /// Print the human-readable view.
+    fn print_table(&self) -> Result<()>;
+
+    /// The report as rows of plain values, for `--format tsv`/`csv`. `None`
+    /// (the default) if it doesn't have that shape.
+    fn records(&self) -> Option<Records> {
+        None
+    }
+}
+
+/// One report per server, for a command given a name pattern: printed one
+/// after another, and serialized as an object keyed by server name.
+pub struct PerServer<R>(pub Vec<(String, R)>);
+
+impl<R: Serialize> Serialize for PerServer<R> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.iter().map(|(name, report)| (name, report)))
+    }
+}
+
+impl<R: Report> Report for PerServer<R> {
+    fn print_table(&self) -> Result<()> {
+        for (i, (_, report)) in self.0.iter().enumerate() {
+            if i > 0 {
+                println!();
+            }
+            report.print_table()?;
+        }
+        Ok(())
+    }
+}
+
+/// Ask before `verb`ing every one of `names` (a pattern or tag matched them),
+/// unless `yes` (`--yes`) already said to. Off a terminal there is nobody to
+/// ask, so it fails instead, naming the servers.
+pub fn confirm(verb: &str, names: &[String], yes: bool) -> Result<()> {
+    if yes {
+        return Ok(());
+    }
+    let list = names.join(", ");
+    if !(std::io::stdin().is_terminal() && std::io::stderr().is_terminal()) {
+        return Err(ErrorKind::InvalidArgs.error(format!(
+            "This would {} {} server(s): {}. Pass --yes to go ahead",
+            verb.to_lowercase(),
+            names.len(),
+            list
+        )));
+    }
+    eprint!("{} {} server(s): {}? [y/N] ", verb, names.len(), list);
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    if matches!(answer.trim().to_lowercase().as_str(), "y" | "yes") {
+        Ok(())
+    } else {
+        Err(ErrorKind::InvalidArgs.error("Cancelled"))
+    }
+}
+
+/// Rows for `--format tsv`/`csv`, under a header line of column names.
+pub struct Records {
+    pub headers: Vec<&'static str>,
+    pub rows: Vec<Vec<String>>,
+}
+
+impl Records {
+    /// The header and rows, one line each, with fields joined by
+    /// `delimiter`.
+    pub fn render(&self, delimiter: char) -> String {
+        let headers = self
+            .headers
+            .iter()
+            .map(|h| h.to_string())
+            .collect::<Vec<_>>();
+        std::iter::once(&headers)
+            .chain(&self.rows)
+            .map(|row| {
+                let fields: Vec<String> = row
+                    .iter()
+                    .map(|field| delimited_field(field, delimiter))
+                    .collect();
+                format!("{}\n", fields.join(&delimiter.to_string()))
+            })
+            .collect()
+    }
+}
+
+/// `field` as is, or double-quoted (with inner quotes doubled) if it holds
+/// the delimiter, a quote, or a line break.
+fn delimited_field(field: &str, delimiter: char) -> String {
+    if field.contains([delimiter, '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Print `report` in `format`.
+pub fn print_report<R: Report>(format: OutputFormat, report: &R) -> Result<()> {
+    match format {
+        OutputFormat::Table => report.print_table(),
+        OutputFormat::Json => {
+            println!("{}", serde_json::to_string_pretty(report)?);
+            Ok(())
+        }
+        OutputFormat::Yaml => {
+            print!("{}", serde_yaml::to_string(report)?);
+            Ok(())
+        }
+        OutputFormat::Tsv | OutputFormat::Csv => {
+            let Some(records) = report.records() else {
+                bail!("This command has no tabular output; use --format json or yaml");
+            };
+            let delimiter = if format == OutputFormat::Tsv {
+                '\t'
+            } else {
+                ','
+            };
+            print!("{}", records.render(delimiter));
+            Ok(())
+        }
+    }
+}
+
+/// When to color output (`--color`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum ColorChoice {
+    /// Color when stdout is a terminal and `NO_COLOR` isn't set.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// Decide once, before anything is printed, whether the `format_*` and
+/// `print_*` helpers emit color codes.
+pub fn init_color(choice: ColorChoice) {
+    let color = match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        // https://no-color.org: any non-empty value turns color off.
+        ColorChoice::Auto => {
+            std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+                && std::io::stdout().is_terminal()
+        }
+    };
+    colored::control::set_override(color);
+}
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+static ASCII: AtomicBool = AtomicBool::new(false);
+
+/// Decide once, before anything is printed, whether symbols, rules, and tree
+/// branches are drawn in plain ASCII: with `--ascii`, or when the locale says
+/// the terminal isn't UTF-8.
+pub fn init_ascii(force: bool) {
+    ASCII.store(force || !locale_is_utf8(), Ordering::Relaxed);
+}
+
+pub fn is_ascii() -> bool {
+    ASCII.load(Ordering::Relaxed)
+}
+
+/// The locale the C library would take the character set from: the first of
+/// `LC_ALL`, `LC_CTYPE`, and `LANG` that is set.
+fn locale_is_utf8() -> bool {
+    let locale = ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|value| !value.is_empty());
+    is_utf8_locale(locale.as_deref())
+}
+
+/// Whether `locale` (e.g. `en_US.UTF-8`) names UTF-8. No locale at all says
+/// nothing about the terminal, so counts as UTF-8; an explicit `C` or
+/// `POSIX`, or any other charset, doesn't.
+fn is_utf8_locale(locale: Option<&str>) -> bool {
+    locale.is_none_or(|locale| {
+        let locale = locale.to_ascii_lowercase();
+        locale.contains("utf-8") || locale.contains("utf8")
+    })
+}
+
+/// A symbol the output draws, with the ASCII it falls back to under
+/// [`is_ascii`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Glyph {
+    /// ✓ success, healthy
+    Check,
+    /// ⚠ warning, grace
+    Warning,
+    /// ✗ error, stopped
+    Cross,
+    /// ℹ info
+    Info,
+    /// ● active
+    Active,
+    /// ☠ defunct
+    Defunct,
+    /// ✚ unhealthy
+    Unhealthy,
+    /// • list item
+    Bullet,
+    /// → transition
+    Arrow,
+    /// … text cut short
+    Ellipsis,
+    /// ─ a table's header rule, repeated
+    Rule,
+    /// ├─ a tree branch with more after it
+    Branch,
+    /// └─ a tree's last branch
+    LastBranch,
+    /// │ a tree's continuing trunk
+    Trunk,
+}
+
+impl Glyph {
+    pub fn as_str(self) -> &'static str {
+        let (unicode, ascii) = match self {
+            Glyph::Check => ("✓", "+"),
+            Glyph::Warning => ("⚠", "!"),
+            Glyph::Cross => ("✗", "x"),
+            Glyph::Info => ("ℹ", "i"),
+            Glyph::Active => ("●", "*"),
+            Glyph::Defunct => ("☠", "X"),
+            Glyph::Unhealthy => ("✚", "!"),
+            Glyph::Bullet => ("•", "*"),
+            Glyph::Arrow => ("→", "->"),
+            Glyph::Ellipsis => ("…", "..."),
+            Glyph::Rule => ("─", "-"),
+            Glyph::Branch => ("├─ ", "|- "),
+            Glyph::LastBranch => ("└─ ", "`- "),
+            Glyph::Trunk => ("│  ", "|  "),
+        };
+        if is_ascii() {
+            ascii
+        } else {
+            unicode
+        }
+    }
+}
+
+impl fmt::Display for Glyph {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Suppress normal output (`--quiet`): the success, warning, and info
+/// messages below print nothing, leaving errors on stderr and the exit code.
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
 /// Print a success message with a green checkmark
 pub fn print_success(msg: &str) {
-    println!("{} {}", "✓".green().bold(), msg);
+    if !is_quiet() {
+        println!("{} {}", Glyph::Check.as_str().green().bold(), msg);
+    }
 }
 
 /// Print a warning message with a yellow warning symbol
 pub fn print_warning(msg: &str) {
-    println!("{} {}", "⚠".yellow().bold(), msg);
+    if !is_quiet() {
+        println!("{} {}", Glyph::Warning.as_str().yellow().bold(), msg);
+    }
 }
 
 /// Print an error message with a red X
 pub fn print_error(msg: &str) {
-    eprintln!("{} {}", "✗".red().bold(), msg);
+    eprintln!("{} {}", Glyph::Cross.as_str().red().bold(), msg);
 }
 
 /// Print an info message with a blue info symbol
 pub fn print_info(msg: &str) {
-    println!("{} {}", "ℹ".blue().bold(), msg);
+    if !is_quiet() {
+        println!("{} {}", Glyph::Info.as_str().blue().bold(), msg);
+    }
+}
+
+/// How long a wait runs before its status line appears, so a fast start
+/// doesn't flicker.
+const PROGRESS_DELAY: Duration = Duration::from_millis(250);
+
+const SPINNER: [&str; 10] = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
+const ASCII_SPINNER: [&str; 4] = ["|", "/", "-", "\\"];
+
+/// A spinner and status line on stderr while a command waits. Only drawn when
+/// stderr is a terminal and not `--quiet`; the line is erased when done (or
+/// dropped), so the result printed next stands alone.
+pub struct Progress {
+    message: String,
+    enabled: bool,
+    started: std::time::Instant,
+    frame: usize,
+    shown: bool,
+}
+
+impl Progress {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            enabled: !is_quiet()
+                && std::io::stderr().is_terminal()
+                && std::env::var_os("TERM").is_none_or(|term| term != "dumb"),
+            started: std::time::Instant::now(),
+            frame: 0,
+            shown: false,
+        }
+    }
+
+    /// Redraw the line with the spinner advanced. Call it from the wait loop.
+    pub fn tick(&mut self) {
+        if !self.enabled || self.started.elapsed() < PROGRESS_DELAY {
+            return;
+        }
+        let frames: &[&str] = if is_ascii() { &ASCII_SPINNER } else { &SPINNER };
+        let spinner = frames[self.frame % frames.len()];
+        self.frame += 1;
+        eprint!("\r\x1b[2K{} {}", spinner.cyan(), self.message);
+        let _ = std::io::Write::flush(&mut std::io::stderr());
+        self.shown = true;
+    }
+
+    /// Erase the line.
+    pub fn finish(&mut self) {
+        if self.shown {
+            eprint!("\r\x1b[2K");
+            let _ = std::io::Write::flush(&mut std::io::stderr());
+            self.shown = false;
+        }
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+/// Columns are separated by this many spaces.
+const COLUMN_GAP: usize = 2;
+
+/// Columns are never shrunk narrower than this (or their header) to fit the
+/// terminal.
+const MIN_COLUMN_WIDTH: usize = 8;
+
+/// A table for people: each column is as wide as its widest cell, measured on
+/// the text before it's colored, so escape codes never throw the alignment
+/// off. On a terminal too narrow for it, the widest columns are shrunk and
+/// their long cells cut short with `…`.
+pub struct Table {
+    headers: Vec<&'static str>,
+    rows: Vec<Vec<ColoredString>>,
+}
+
+impl Table {
+    pub fn new(headers: &[&'static str]) -> Self {
+        Self {
+            headers: headers.to_vec(),
+            rows: Vec::new(),
+        }
+    }
+
+    /// Add a row. Plain text converts with `.into()`; cells may be shorter
+    /// than the header, missing ones are left blank.
+    pub fn row(&mut self, cells: Vec<ColoredString>) {
+        self.rows.push(cells);
+    }
+
+    /// Print, fitted to the terminal's width.
+    pub fn print(&self) {
+        print!("{}", self.render(terminal_width()));
+    }
+
+    /// The header, a rule under it, and one line per row, no wider than
+    /// `max_width` columns if it can be helped.
+    pub fn render(&self, max_width: Option<usize>) -> String {
+        let mut widths: Vec<usize> = self.headers.iter().map(|h| display_width(h)).collect();
+        for row in &self.rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(display_width(cell));
+            }
+        }
+        if let Some(max_width) = max_width {
+            self.shrink(&mut widths, max_width);
+        }
+
+        let headers: Vec<ColoredString> = self.headers.iter().map(|h| h.bold()).collect();
+        let rule_width = widths.iter().sum::<usize>() + COLUMN_GAP * (widths.len().max(1) - 1);
+        let mut out = render_line(&headers, &widths);
+        out.push_str(&format!(
+            "{}\n",
+            Glyph::Rule.as_str().repeat(rule_width).dimmed()
+        ));
+        for row in &self.rows {
+            out.push_str(&render_line(row, &widths));
+        }
+        out
+    }
+
+    /// Narrow columns until the table fits in `max_width` or every column is
+    /// down to its minimum: the last column first, since that's where tables
+    /// here keep free text (commands, client lists, details), then the
+    /// widest.
+    fn shrink(&self, widths: &mut [usize], max_width: usize) {
+        let minimums: Vec<usize> = self
+            .headers
+            .iter()
+            .map(|h| display_width(h).max(MIN_COLUMN_WIDTH))
+            .collect();
+        let gaps = COLUMN_GAP * (widths.len().max(1) - 1);
+        let last = widths.len().saturating_sub(1);
+        while widths.iter().sum::<usize>() + gaps > max_width {
+            let shrinkable = |i: usize| widths[i] > minimums[i];
+            let column = if widths.is_empty() || !shrinkable(last) {
+                widths
+                    .iter()
+                    .enumerate()
+                    .filter(|&(i, _)| shrinkable(i))
+                    .max_by_key(|&(_, &width)| width)
+                    .map(|(i, _)| i)
+            } else {
+                Some(last)
+            };
+            match column {
+                Some(i) => widths[i] -= 1,
+                None => break,
+            }
+        }
+    }
+}
+
+fn render_line(cells: &[ColoredString], widths: &[usize]) -> String {
+    let mut line = String::new();
+    for (i, &width) in widths.iter().enumerate() {
+        let cell = cells.get(i).map(|cell| {
+            if display_width(cell) > width {
+                let mut cell = cell.clone();
+                cell.input = ellipsize(&cell.input, width);
+                cell
+            } else {
+                cell.clone()
+            }
+        });
+        if let Some(cell) = &cell {
+            line.push_str(&cell.to_string());
+        }
+        // The last column isn't padded, so lines carry no trailing spaces.
+        if i + 1 < widths.len() {
+            let used = cell.as_ref().map_or(0, |cell| display_width(cell));
+            line.push_str(&" ".repeat(width - used + COLUMN_GAP));
+        }
+    }
+    line.truncate(line.trim_end().len());
+    line.push('\n');
+    line
+}
+
+/// A line in a tree view, with the lines nested under it.
+pub struct TreeNode {
+    pub label: String,
+    pub children: Vec<TreeNode>,
+}
+
+impl TreeNode {
+    pub fn new(label: impl Into<String>, children: Vec<TreeNode>) -> Self {
+        Self {
+            label: label.into(),
+            children,
+        }
+    }
+
+    /// `nodes` drawn beneath a heading, one per line, joined by box-drawing
+    /// branches.
+    pub fn render_all(nodes: &[TreeNode]) -> String {
+        let mut out = String::new();
+        render_branches(nodes, "", &mut out);
+        out
+    }
+}
+
+fn render_branches(nodes: &[TreeNode], prefix: &str, out: &mut String) {
+    for (i, node) in nodes.iter().enumerate() {
+        let last = i + 1 == nodes.len();
+        let (branch, indent) = if last {
+            (Glyph::LastBranch.as_str(), "   ")
+        } else {
+            (Glyph::Branch.as_str(), Glyph::Trunk.as_str())
+        };
+        out.push_str(&format!("{}{}{}\n", prefix, branch, node.label));
+        render_branches(&node.children, &format!("{}{}", prefix, indent), out);
+    }
+}
+
+/// `text` cut to `width` columns, ending in `…` (`...` in ASCII mode, when
+/// there's room for it). Escape sequences are kept (even past the cut), so
+/// colors opened inside the text are still reset.
+pub fn ellipsize(text: &str, width: usize) -> String {
+    if display_width(text) <= width {
+        return text.to_string();
+    }
+    let mark = Some(Glyph::Ellipsis.as_str()).filter(|mark| mark.len() <= width);
+    let budget = width - mark.map_or(0, display_width);
+    let mut out = String::new();
+    let mut used = 0;
+    let mut cut = false;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\u{1b}' {
+            out.push(c);
+            for c in chars.by_ref() {
+                out.push(c);
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+            continue;
+        }
+        if cut {
+            continue;
+        }
+        let w = char_width(c);
+        if used + w > budget {
+            if let Some(mark) = mark {
+                out.push_str(mark);
+            }
+            cut = true;
+            continue;
+        }
+        out.push(c);
+        used += w;
+    }
+    out
+}
+
+/// The width of the terminal stdout goes to: `COLUMNS` if set, else asked of
+/// the terminal. `None` when stdout isn't a terminal, so piped output is
+/// never cut short.
+pub fn terminal_width() -> Option<usize> {
+    if let Some(columns) = std::env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse::<usize>().ok())
+        .filter(|&columns| columns > 0)
+    {
+        return Some(columns);
+    }
+    if !std::io::stdout().is_terminal() {
+        return None;
+    }
+    // SAFETY: TIOCGWINSZ only writes a `winsize` into the struct passed.
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    let result = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+    (result == 0 && size.ws_col > 0).then_some(size.ws_col as usize)
+}
+
+/// How many terminal columns `text` takes: escape sequences take none, wide
+/// (CJK, emoji) characters two, and combining marks none.
+pub fn display_width(text: &str) -> usize {
+    let mut width = 0;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\u{1b}' {
+            // CSI sequence: ESC '[' parameters, ended by a letter.
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+            continue;
+        }
+        width += char_width(c);
+    }
+    width
+}
+
+fn char_width(c: char) -> usize {
+    match c as u32 {
+        0x0300..=0x036F | 0x200B..=0x200F | 0xFE00..=0xFE0F => 0,
+        0x1100..=0x115F
+        | 0x2E80..=0xA4CF
+        | 0xAC00..=0xD7A3
+        | 0xF900..=0xFAFF
+        | 0xFE30..=0xFE4F
+        | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE6
+        | 0x1F300..=0x1FAFF
+        | 0x20000..=0x3FFFD => 2,
+        _ if c.is_control() => 0,
+        _ => 1,
+    }
 }
 
 /// Format a duration in a human-readable way
 pub fn format_duration(duration: Duration) -> String {
     let secs = duration.as_secs();
 
-    if secs < 60 {
+    if secs == 0 && duration.subsec_millis() > 0 {
+        format!("{}ms", duration.subsec_millis())
+    } else if secs < 60 {
         format!("{}s", secs)
     } else if secs < 3600 {
         let mins = secs / 60;
@@ -89,11 +730,60 @@ pub fn format_timestamp(time: SystemTime) -> String {
 /// Format a server state with color and symbol
 pub fn format_server_state(state: &ServerState) -> ColoredString {
     match state {
-        ServerState::Active => "● Active".green(),
-        ServerState::Grace => "⚠ Grace".yellow(),
-        ServerState::Stopped => "✗ Stopped".red(),
-        ServerState::Defunct => "☠ Defunct".magenta(),
+        ServerState::Active => format!("{} Active", Glyph::Active).green(),
+        ServerState::Grace => format!("{} Grace", Glyph::Warning).yellow(),
+        ServerState::Stopped => format!("{} Stopped", Glyph::Cross).red(),
+        ServerState::Defunct => format!("{} Defunct", Glyph::Defunct).magenta(),
+    }
+}
+
+/// Format the Grace state with the time left before shutdown, e.g.
+/// "⚠ Grace (4m 12s)"
+pub fn format_grace_state(remaining: Duration) -> ColoredString {
+    format!("{} Grace ({})", Glyph::Warning, format_duration(remaining)).yellow()
+}
+
+/// Format the state of a running server its health probe has marked unhealthy
+pub fn format_unhealthy_state() -> ColoredString {
+    format!("{} Unhealthy", Glyph::Unhealthy).red()
+}
+
+/// Format how a server last went down, e.g. "crashed (exit code 1)", red for
+/// the reasons that usually need attention
+pub fn format_last_exit(tombstone: &Tombstone) -> ColoredString {
+    let text = format!("{} ({})", tombstone.reason, tombstone.exit);
+    match tombstone.reason {
+        DeathReason::Crashed
+        | DeathReason::Unhealthy
+        | DeathReason::Killed
+        | DeathReason::ResourceLimit => text.red(),
+        DeathReason::Exited
+        | DeathReason::Stopped
+        | DeathReason::GraceExpired
+        | DeathReason::Shutdown => text.normal(),
+    }
+}
+
+/// Format a byte count with binary units (e.g. "1.5 GiB")
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
     }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// Format a UTC timestamp relative to now (e.g. "5m ago")
+pub fn format_utc_timestamp(time: chrono::DateTime<chrono::Utc>) -> String {
+    let system_time = SystemTime::UNIX_EPOCH + Duration::from_secs(time.timestamp().max(0) as u64);
+    format_timestamp(system_time)
 }
 
 /// Format a PID with cyan color
@@ -135,6 +825,146 @@ pub fn format_clients(clients: &[String], max_display: usize) -> String {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_is_utf8_locale() {
+        assert!(is_utf8_locale(None));
+        assert!(is_utf8_locale(Some("en_US.UTF-8")));
+        assert!(is_utf8_locale(Some("C.utf8")));
+        assert!(!is_utf8_locale(Some("C")));
+        assert!(!is_utf8_locale(Some("POSIX")));
+        assert!(!is_utf8_locale(Some("de_DE.ISO-8859-1")));
+    }
+
+    #[test]
+    fn test_tree_render() {
+        let tree = [
+            TreeNode::new(
+                "watcher 10",
+                vec![TreeNode::new(
+                    "server 11",
+                    vec![TreeNode::new("12", vec![])],
+                )],
+            ),
+            TreeNode::new("clients", vec![TreeNode::new("20 nvim", vec![])]),
+        ];
+        assert_eq!(
+            TreeNode::render_all(&tree),
+            "├─ watcher 10\n│  └─ server 11\n│     └─ 12\n└─ clients\n   └─ 20 nvim\n"
+        );
+    }
+
+    #[test]
+    fn test_output_format_resolve() {
+        assert_eq!(
+            OutputFormat::resolve(Some(OutputFormat::Yaml)).unwrap(),
+            OutputFormat::Yaml
+        );
+        assert_eq!(OutputFormat::Table.or_json(true), OutputFormat::Json);
+        assert_eq!(OutputFormat::Yaml.or_json(false), OutputFormat::Yaml);
+    }
+
+    #[test]
+    fn test_records_quote_only_when_needed() {
+        let records = Records {
+            headers: vec!["name", "clients"],
+            rows: vec![
+                vec!["plain".to_string(), "1,2".to_string()],
+                vec!["say \"hi\"".to_string(), "tab\there".to_string()],
+            ],
+        };
+        assert_eq!(
+            records.render('\t'),
+            "name\tclients\nplain\t1,2\n\"say \"\"hi\"\"\"\t\"tab\there\"\n"
+        );
+        assert_eq!(
+            records.render(','),
+            "name,clients\nplain,\"1,2\"\n\"say \"\"hi\"\"\",tab\there\n"
+        );
+    }
+
+    #[test]
+    fn test_display_width() {
+        assert_eq!(display_width("abc"), 3);
+        assert_eq!(display_width("● Active"), 8);
+        assert_eq!(display_width("\u{1b}[1;36mname\u{1b}[0m"), 4);
+        assert_eq!(display_width("日本"), 4);
+        assert_eq!(display_width("e\u{301}"), 1);
+    }
+
+    #[test]
+    fn test_table_aligns_colored_cells() {
+        // Escape codes written into the text itself, so the test doesn't
+        // depend on whether color is enabled.
+        let green = |text: &str| format!("\u{1b}[32m{}\u{1b}[0m", text).normal();
+        let mut table = Table::new(&["NAME", "STATE", "PID"]);
+        table.row(vec!["a".into(), green("● Active"), "12".into()]);
+        table.row(vec![green("longer-name"), "⚠ Grace (4m)".into()]);
+        let rendered = table.render(None);
+
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert_eq!(lines.len(), 4);
+        // Every column starts at the same display offset on every line.
+        let offsets = |line: &str| -> Vec<usize> {
+            let plain = strip(line);
+            ["STATE", "●", "⚠"]
+                .iter()
+                .filter_map(|marker| plain.find(marker))
+                .map(|at| display_width(&plain[..at]))
+                .collect()
+        };
+        assert_eq!(offsets(lines[0]), offsets(lines[2]));
+        assert_eq!(offsets(lines[0]), offsets(lines[3]));
+        assert!(!strip(lines[3]).ends_with(' '));
+    }
+
+    #[test]
+    fn test_ellipsize() {
+        assert_eq!(ellipsize("short", 10), "short");
+        assert_eq!(ellipsize("/usr/bin/server --flag", 10), "/usr/bin/…");
+        assert_eq!(display_width(&ellipsize("日本語のテキスト", 7)), 7);
+        // Escapes survive the cut, so the color is still reset.
+        let colored = "\u{1b}[32mgreen text\u{1b}[0m";
+        assert_eq!(ellipsize(colored, 6), "\u{1b}[32mgreen…\u{1b}[0m");
+    }
+
+    #[test]
+    fn test_table_fits_narrow_terminal() {
+        let mut table = Table::new(&["NAME", "PID", "COMMAND"]);
+        let command = "/opt/very/long/path/to/a/language-server --stdio --log-level=debug";
+        table.row(vec![
+            "rust-analyzer-main".into(),
+            "4242".into(),
+            command.into(),
+        ]);
+        let lines: Vec<String> = table.render(Some(40)).lines().map(strip).collect();
+        assert!(
+            lines.iter().all(|line| display_width(line) <= 40),
+            "{:?}",
+            lines
+        );
+        // The free-text last column gives way before the name does.
+        assert!(lines[2].starts_with("rust-analyzer-main "));
+        assert!(lines[2].contains("4242"));
+        assert!(lines[2].ends_with('…'));
+
+        // Wide enough: nothing is cut.
+        assert!(table.render(Some(200)).contains(command));
+    }
+
+    /// `text` with its escape sequences removed.
+    fn strip(text: &str) -> String {
+        let mut out = String::new();
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            if c == '\u{1b}' {
+                chars.by_ref().find(|c| c.is_ascii_alphabetic());
+            } else {
+                out.push(c);
+            }
+        }
+        out
+    }
+
     #[test]
     fn test_format_duration() {
         assert_eq!(format_duration(Duration::from_secs(30)), "30s");
@@ -144,6 +974,16 @@ mod tests {
         assert_eq!(format_duration(Duration::from_secs(3660)), "1h 1m");
         assert_eq!(format_duration(Duration::from_secs(86400)), "1d");
         assert_eq!(format_duration(Duration::from_secs(90000)), "1d 1h");
+        assert_eq!(format_duration(Duration::from_millis(500)), "500ms");
+        assert_eq!(format_duration(Duration::from_millis(1500)), "1s");
+        assert_eq!(format_duration(Duration::ZERO), "0s");
+    }
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(2 << 30), "2.0 GiB");
     }
 
     #[test]
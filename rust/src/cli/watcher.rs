@@ -3,157 +3,1685 @@ use nix::errno::Errno;
 use nix::sys::signal::{kill, killpg, Signal};
 use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
 use nix::unistd::Pid;
+use serde_json::json;
+use sharedserver::core::config::Config;
+use sharedserver::core::container::{self, Container, ContainerState};
+use sharedserver::core::exit_notify::ExitNotifier;
+use sharedserver::core::front::Front;
+use sharedserver::core::grace::{instant_after, GraceClock, GracePeriod, GraceTimer};
+use sharedserver::core::heartbeat::{write_heartbeat, HEARTBEAT_INTERVAL};
+use sharedserver::core::limits::{sample_process_group, BreachTracker, ProcessSample};
+use sharedserver::core::lockfile::server_lockfile_path;
+use sharedserver::core::log_capture::LogCapture;
+use sharedserver::core::sd_notify;
+use sharedserver::core::stdio_mux::StdioMux;
+use sharedserver::core::telemetry;
+use sharedserver::core::tombstone::{write_tombstone, DeathReason, Tombstone};
+use sharedserver::core::webhook::{self, LifecycleEvent};
 use sharedserver::core::{
     delete_clients_lock, delete_locks_owned_by, delete_server_lock, is_process_alive,
-    parse_duration, read_server_lock, ClientsLock,
+    process_start_stamp, read_clients_lock, read_server_lock, update_clients_lock,
+    update_server_lock, HealthCheck, HealthStatus, LimitAction, LockUpdate, ResourceLimits,
+    ResourceUsage, RestartPolicy, ServerExit,
 };
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::OnceLock;
 use std::thread;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 
-/// How often the watcher polls liveness, clients, and the grace timer.
+use crate::commands::r#use::{configured_settings, ServerSettings};
+use crate::commands::start::ClientContext;
+
+/// How often the watcher polls clients and the grace timer. Server and client
+/// death are event-driven where the platform allows (see [`ExitNotifier`]) and
+/// only fall back to this interval elsewhere.
 const POLL_INTERVAL: Duration = Duration::from_millis(500);
 
 /// How long the watcher waits for the server to exit after SIGTERM (on grace
 /// expiry) before escalating to SIGKILL.
 const GRACE_KILL_TIMEOUT: Duration = Duration::from_secs(5);
 
+/// The config's replacements for [`POLL_INTERVAL`] and [`GRACE_KILL_TIMEOUT`]
+/// (see [`set_timing`]).
+static TIMING: OnceLock<(Option<Duration>, Option<Duration>)> = OnceLock::new();
+
+/// Replace the watcher's poll interval and SIGTERM-to-SIGKILL timeout, for
+/// every watcher this process goes on to run (or fork). Only the first call
+/// counts.
+pub fn set_timing(poll_interval: Option<Duration>, kill_timeout: Option<Duration>) {
+    let _ = TIMING.set((poll_interval, kill_timeout));
+}
+
+fn poll_interval() -> Duration {
+    TIMING.get().and_then(|t| t.0).unwrap_or(POLL_INTERVAL)
+}
+
+fn kill_timeout() -> Duration {
+    TIMING.get().and_then(|t| t.1).unwrap_or(GRACE_KILL_TIMEOUT)
+}
+
+/// How far the published grace deadline may drift from the timer's estimate
+/// before it is re-written (it only moves on the monotonic clock, by however
+/// long the machine slept).
+const GRACE_DRIFT: chrono::Duration = chrono::Duration::seconds(2);
+
+/// Restarts of a server that dies within this long of launching count as a
+/// crash loop and are delayed with exponential backoff.
+const RESTART_STABLE_AFTER: Duration = Duration::from_secs(10);
+
+/// Upper bound on the crash-loop backoff between restarts.
+const RESTART_MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// How often the watcher samples memory/CPU when resource limits are set.
+const RESOURCE_SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often the watcher asks the runtime whether a container server's
+/// container is still running.
+const CONTAINER_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
 /// Try to reap the server child without blocking.
 ///
 /// The watcher is the server's parent, so it is the process responsible for
-/// reaping it — otherwise the server lingers as a zombie. Returns `true` once
-/// the server has exited (and been reaped here) or is no longer our child.
-fn try_reap_server(server_pid: i32) -> bool {
+/// reaping it — otherwise the server lingers as a zombie. Returns the exit
+/// status once the server has exited (and been reaped here) or is no longer
+/// our child, and `None` while it is still running.
+fn try_reap_server(server_pid: i32) -> Option<ServerExit> {
     match waitpid(Pid::from_raw(server_pid), Some(WaitPidFlag::WNOHANG)) {
-        Ok(WaitStatus::StillAlive) => false,
-        Ok(WaitStatus::Exited(_, _)) | Ok(WaitStatus::Signaled(_, _, _)) => true,
+        Ok(WaitStatus::StillAlive) => None,
+        Ok(WaitStatus::Exited(_, code)) => Some(ServerExit::Exited { code }),
+        Ok(WaitStatus::Signaled(_, signal, _)) => Some(ServerExit::Signaled {
+            signal: signal as i32,
+        }),
         // Stopped/Continued (job control): still alive, not gone.
-        Ok(_) => false,
+        Ok(_) => None,
         // No such child: already reaped, or never ours.
-        Err(Errno::ECHILD) => true,
+        Err(Errno::ECHILD) => Some(ServerExit::Unknown),
         // Unexpected error: fall back to a liveness probe.
-        Err(_) => !is_process_alive(server_pid),
+        Err(_) => (!is_process_alive(server_pid)).then_some(ServerExit::Unknown),
+    }
+}
+
+/// Reap any other exited children of the watcher so none linger as zombies,
+/// logging each. Returns the server's exit status if the sweep happened to
+/// collect the server itself (it died between the targeted reap and this).
+///
+/// Sound only because the watcher is single-threaded: nothing else (e.g. a
+/// health probe's `Command::output`) is waiting on a child while this runs.
+fn reap_strays(name: &str, server_pid: i32) -> Option<ServerExit> {
+    loop {
+        let (pid, exit) = match waitpid(Pid::from_raw(-1), Some(WaitPidFlag::WNOHANG)) {
+            Ok(WaitStatus::Exited(pid, code)) => (pid.as_raw(), ServerExit::Exited { code }),
+            Ok(WaitStatus::Signaled(pid, signal, _)) => (
+                pid.as_raw(),
+                ServerExit::Signaled {
+                    signal: signal as i32,
+                },
+            ),
+            // Nothing (more) has exited, or no children at all.
+            _ => return None,
+        };
+        if pid == server_pid {
+            return Some(exit);
+        }
+        event(
+            name,
+            "child-reaped",
+            json!({ "pid": pid, "exit": exit.to_string() }),
+        );
     }
 }
 
-/// Block (polling) until the server has exited and been reaped, or `timeout`
-/// elapses. Returns `true` if it is gone.
-fn wait_for_server_exit(server_pid: i32, timeout: Duration) -> bool {
+extern "C" fn on_sigchld(_: libc::c_int) {}
+
+/// Set by the SIGTERM handler; the loop acts on it at the top of its next pass.
+static TERMINATE_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_sigterm(_: libc::c_int) {
+    TERMINATE_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// Ask every [`Watch`] in this process to take its server down (or hand it
+/// off) on its next pass, as SIGTERM does.
+pub(crate) fn request_termination() {
+    TERMINATE_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+pub(crate) fn termination_requested() -> bool {
+    TERMINATE_REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Catch SIGTERM (logout, system shutdown) so the watcher can hand off
+/// cleanly instead of dying with the lockfiles left behind. The signal also
+/// cuts the loop's sleep short.
+pub(crate) fn install_sigterm_handler() {
+    use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet};
+
+    let action = SigAction::new(
+        SigHandler::Handler(on_sigterm),
+        SaFlags::SA_RESTART,
+        SigSet::empty(),
+    );
+    // SAFETY: the handler only stores to an atomic, which is async-signal-safe.
+    let _ = unsafe { sigaction(Signal::SIGTERM, &action) };
+}
+
+/// Bumped by SIGHUP: a [`Watch`] that last read its settings in an earlier
+/// generation re-reads them on its next pass.
+static RELOAD_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+extern "C" fn on_sighup(_: libc::c_int) {
+    RELOAD_GENERATION.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Catch SIGHUP as a request to re-read the config and the server lock now,
+/// rather than when their mtimes are next seen to change. The signal also
+/// cuts the loop's sleep short.
+pub(crate) fn install_sighup_handler() {
+    use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet};
+
+    let action = SigAction::new(
+        SigHandler::Handler(on_sighup),
+        SaFlags::SA_RESTART,
+        SigSet::empty(),
+    );
+    // SAFETY: the handler only adds to an atomic, which is async-signal-safe.
+    let _ = unsafe { sigaction(Signal::SIGHUP, &action) };
+}
+
+/// Treat SIGINT like SIGTERM. Only the daemon, which runs in the foreground,
+/// wants this: Ctrl-C should shut it down cleanly, not orphan its servers.
+pub(crate) fn install_sigint_handler() {
+    use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet};
+
+    let action = SigAction::new(
+        SigHandler::Handler(on_sigterm),
+        SaFlags::SA_RESTART,
+        SigSet::empty(),
+    );
+    // SAFETY: as for SIGTERM.
+    let _ = unsafe { sigaction(Signal::SIGINT, &action) };
+}
+
+/// Make SIGCHLD interrupt the watcher's sleep, so a child exit is noticed at
+/// once even where [`ExitNotifier`] falls back to plain polling. The handler
+/// does nothing itself: `poll`/`sleep` returning early is the whole point.
+/// `SA_RESTART` keeps it from failing other blocking calls (e.g. `flock`).
+pub(crate) fn install_sigchld_wakeup() {
+    use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet};
+
+    let action = SigAction::new(
+        SigHandler::Handler(on_sigchld),
+        SaFlags::SA_RESTART | SaFlags::SA_NOCLDSTOP,
+        SigSet::empty(),
+    );
+    // SAFETY: the handler is empty, so trivially async-signal-safe.
+    let _ = unsafe { sigaction(Signal::SIGCHLD, &action) };
+}
+
+/// Block until the server has exited and been reaped, or `timeout` elapses.
+/// Returns its exit status if it is gone.
+fn wait_for_server_exit(server_pid: i32, timeout: Duration) -> Option<ServerExit> {
     let start = Instant::now();
+    let mut notifier = ExitNotifier::new();
+    notifier.watch(server_pid);
     loop {
-        if try_reap_server(server_pid) {
-            return true;
+        if let Some(exit) = try_reap_server(server_pid) {
+            return Some(exit);
         }
-        if start.elapsed() >= timeout {
-            return false;
+        let elapsed = start.elapsed();
+        if elapsed >= timeout {
+            return None;
         }
-        thread::sleep(Duration::from_millis(100));
+        notifier.wait((timeout - elapsed).min(Duration::from_millis(100)));
     }
 }
 
-pub fn run_watcher(name: &str, grace_period: &str) -> Result<()> {
-    let grace_duration = parse_duration(grace_period)
-        .with_context(|| format!("Invalid grace period: {}", grace_period))?;
+/// What relaunching a server needs besides its lock: the `--log-timestamps`
+/// pipes and a `proxy` server's stdio pipes, handed to every relaunched server
+/// so its output keeps flowing through the same relay or mux, and for a server
+/// the daemon launched, the directory and environment of the client it was
+/// started for. The `--listen` front rides along too: it forwards to
+/// whichever server is current and only goes when the watch does.
+#[derive(Default)]
+pub struct Relaunch {
+    pub capture: Option<LogCapture>,
+    pub context: Option<ClientContext>,
+    pub stdio: Option<StdioMux>,
+    pub front: Option<Front>,
+}
 
-    // Try to read server lock, but if it fails (e.g., empty/corrupted), clean up and exit
-    let server = match read_server_lock(name) {
-        Ok(s) => s,
-        Err(e) => {
-            eprintln!("Watcher: Failed to read server lock ({}), cleaning up", e);
-            let _ = delete_server_lock(name);
-            let _ = delete_clients_lock(name);
-            return Err(e.context("Failed to read server lock in watcher"));
+/// Run the watcher for `name` in this (watcher) process until the server is
+/// gone for good.
+pub fn run_watcher(name: &str, grace_period: &str, relaunch: Relaunch) -> Result<()> {
+    install_sigchld_wakeup();
+    install_sigterm_handler();
+    install_sighup_handler();
+    let mut watch = Watch::new(name, grace_period, relaunch, true)?;
+    while let Some(timeout) = watch.step() {
+        watch.wait(timeout);
+    }
+    watch.finish();
+    Ok(())
+}
+
+/// One server's supervision: everything the watcher loop carries from one
+/// pass to the next. The watcher process runs a single `Watch`; the daemon
+/// steps one per server from its own loop.
+pub struct Watch {
+    name: String,
+    grace_period: String,
+    grace_length: Option<Duration>,
+    server_pid: i32,
+    linger: bool,
+    relaunch: Relaunch,
+    /// Whether this is the only thing the process supervises, so every
+    /// exited child is ours to reap and systemd (if it launched us) is
+    /// watching this server alone.
+    standalone: bool,
+    launched_at: Instant,
+    rapid_restarts: u32,
+    health_check: Option<HealthCheck>,
+    next_probe: Option<Instant>,
+    limits: Option<LimitMonitor>,
+    /// The server's container, if it runs in one (`--backend`).
+    container: Option<Container>,
+    next_container_check: Instant,
+    /// Whether the last check found the container gone.
+    container_gone: bool,
+    grace_clock: GraceClock,
+    grace: Option<GraceTimer>,
+    /// The deadline last written to the lock.
+    published: Option<chrono::DateTime<chrono::Utc>>,
+    notifier: ExitNotifier,
+    watched_clients: HashSet<i32>,
+    clients_path: Option<std::path::PathBuf>,
+    /// Last client set logged, so a poll is only logged when it changes.
+    last_clients: Option<Vec<i32>>,
+    last_heartbeat: Option<Instant>,
+    heartbeat_every: Duration,
+    /// Where the grace period, restart policy, and health check were last
+    /// read from, to pick up changes to them.
+    sources: SettingsSources,
+}
+
+/// What a [`Watch`]'s settings were last read from: the server lock, and the
+/// config files with the settings they gave the server. When either changes
+/// (or on SIGHUP) the watch reads them again and applies what changed, without
+/// touching the server.
+struct SettingsSources {
+    generation: u64,
+    lock_modified: Option<SystemTime>,
+    /// Every file the config is read from, present or not, with its mtime.
+    config_files: Vec<(PathBuf, Option<SystemTime>)>,
+    /// What the config said, if it could be read.
+    configured: Option<ServerSettings>,
+}
+
+impl SettingsSources {
+    fn new(name: &str) -> Self {
+        let mut sources = Self {
+            generation: RELOAD_GENERATION.load(Ordering::SeqCst),
+            lock_modified: server_lockfile_path(name).ok().and_then(|p| modified(&p)),
+            config_files: Vec::new(),
+            configured: None,
+        };
+        sources.configured = sources.read_config(name).ok();
+        sources
+    }
+
+    /// Read the config again, noting its files' mtimes, for the settings it
+    /// gives `name`. The files are the ones the lock recorded at start, as
+    /// the watcher's own directory (the server's `cwd`) may be outside the
+    /// project.
+    fn read_config(&mut self, name: &str) -> Result<ServerSettings> {
+        let paths = read_server_lock(name)
+            .map(|lock| lock.config_files)
+            .ok()
+            .filter(|paths| !paths.is_empty())
+            .unwrap_or_else(Config::paths);
+        let config = Config::load_from(&paths);
+        self.config_files = paths
+            .into_iter()
+            .map(|path| {
+                let modified = modified(&path);
+                (path, modified)
+            })
+            .collect();
+        configured_settings(name, &config?)
+    }
+
+    fn config_changed(&self) -> bool {
+        self.config_files
+            .iter()
+            .any(|(path, last)| modified(path) != *last)
+    }
+}
+
+/// When `path` was last modified, if it exists.
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+}
+
+impl Watch {
+    pub fn new(
+        name: &str,
+        grace_period: &str,
+        relaunch: Relaunch,
+        standalone: bool,
+    ) -> Result<Self> {
+        let grace_length = grace_period
+            .parse::<GracePeriod>()
+            .with_context(|| format!("Invalid grace period: {}", grace_period))?
+            .length();
+
+        // Try to read server lock, but if it fails (e.g., empty/corrupted), clean up and exit
+        let server = match read_server_lock(name) {
+            Ok(s) => s,
+            Err(e) => {
+                eprintln!("Watcher: Failed to read server lock ({}), cleaning up", e);
+                event(
+                    name,
+                    "error",
+                    json!({ "message": format!("failed to read server lock: {:#}", e) }),
+                );
+                let _ = delete_server_lock(name);
+                let _ = delete_clients_lock(name);
+                return Err(e.context("Failed to read server lock in watcher"));
+            }
+        };
+        event(
+            name,
+            "started",
+            json!({
+                "server_pid": server.pid,
+                "grace_period": grace_period,
+                "restart": server.restart.as_str(),
+            }),
+        );
+        webhook(
+            name,
+            LifecycleEvent::Start,
+            json!({ "server_pid": server.pid, "command": server.command }),
+        );
+
+        // A check with unparseable durations was rejected at start; if one slips
+        // through anyway, run without it rather than kill the watcher.
+        let health_check = server
+            .health_check
+            .clone()
+            .filter(|check| check.validate().is_ok());
+        let next_probe = health_check
+            .as_ref()
+            .and_then(|check| check.interval().ok())
+            .map(|interval| instant_after(Instant::now(), interval));
+
+        // Likewise for resource limits.
+        let limits = server
+            .limits
+            .clone()
+            .filter(|limits| limits.validate().is_ok())
+            .map(LimitMonitor::new);
+
+        // Resume a grace period already recorded in the lock rather than
+        // restarting the countdown from scratch. `published` is the deadline last
+        // written to the lock, re-written if the estimate drifts (after a sleep).
+        let grace_clock = server.grace_clock;
+        let grace: Option<GraceTimer> = server.grace_deadline.map(|deadline| {
+            GraceTimer::resume(grace_clock, deadline, Instant::now(), chrono::Utc::now())
+        });
+
+        // Wakes the poll sleep the moment the server or a client dies, or the
+        // clients lock is rewritten (incref/decref), so lockfiles are cleaned up
+        // and grace entered or cancelled immediately rather than up to a poll
+        // interval later.
+        let mut notifier = ExitNotifier::new();
+        notifier.watch(server.pid);
+
+        Ok(Self {
+            name: name.to_string(),
+            grace_period: grace_period.to_string(),
+            grace_length,
+            server_pid: server.pid,
+            linger: server.linger,
+            relaunch,
+            standalone,
+            launched_at: Instant::now(),
+            rapid_restarts: 0,
+            health_check,
+            next_probe,
+            limits,
+            container: server.container.clone(),
+            next_container_check: Instant::now() + CONTAINER_CHECK_INTERVAL,
+            container_gone: false,
+            grace_clock,
+            grace,
+            published: server.grace_deadline,
+            notifier,
+            watched_clients: HashSet::new(),
+            clients_path: sharedserver::core::lockfile::clients_lockfile_path(name).ok(),
+            last_clients: None,
+            last_heartbeat: None,
+            // Under a systemd watchdog, pet it at least twice per WatchdogSec.
+            heartbeat_every: sd_notify::watchdog_interval()
+                .map_or(HEARTBEAT_INTERVAL, |watchdog| {
+                    (watchdog / 2).min(HEARTBEAT_INTERVAL)
+                }),
+            sources: SettingsSources::new(name),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn server_pid(&self) -> i32 {
+        self.server_pid
+    }
+
+    /// The `--log-timestamps` relay, which outlives the watch until the last
+    /// writer is gone, and the `--listen` front, stopped by [`Watch::finish`]:
+    /// both have to be reaped.
+    pub fn relay_pids(&self) -> Vec<i32> {
+        let capture = self.relaunch.capture.as_ref().map(LogCapture::relay_pid);
+        let front = self.relaunch.front.as_ref().map(Front::pid);
+        capture.into_iter().chain(front).collect()
+    }
+
+    /// Tell systemd the server is going down, if it is watching this one
+    /// server.
+    fn notify_stopping(&self) {
+        if self.standalone {
+            sd_notify::notify_stopping();
         }
-    };
-    let server_pid = server.pid;
+    }
+
+    /// Take the server down because of `reason`, then relaunch it if
+    /// `relaunch_why` is given. Returns `true` if a fresh server is running.
+    fn replace_or_stop(&mut self, reason: DeathReason, relaunch_why: Option<&str>) -> bool {
+        let name = self.name.clone();
+        let exit = terminate_server(&name, self.server_pid);
+        self.notifier.unwatch(self.server_pid);
+        record_exit(&name, self.server_pid, exit, Some(reason));
+        let relaunched = relaunch_why
+            .and_then(|why| relaunch_server(&name, self.server_pid, why, &self.relaunch));
+        self.adopt(relaunched)
+    }
 
-    let mut grace_timer: Option<Instant> = None;
+    /// Pick up changes to the server's grace period, restart policy, and
+    /// health check. A config edit that changes one of them is written to the
+    /// lock, where a changed setting (edited there, or from the config) is
+    /// applied: a new grace period restarts a running countdown with the new
+    /// length, and a new health check is scheduled afresh. The restart policy
+    /// is read from the lock whenever the server exits, so needs nothing more.
+    fn reload_settings(&mut self) {
+        let name = self.name.clone();
+        let generation = RELOAD_GENERATION.load(Ordering::SeqCst);
+        let hangup = generation != self.sources.generation;
+        self.sources.generation = generation;
+
+        if hangup || self.sources.config_changed() {
+            match self.sources.read_config(&name) {
+                // Only what the config changes is written, so a setting given
+                // on the command line stands until the config's is edited.
+                Ok(configured) => {
+                    if let Some(before) = self.sources.configured.replace(configured.clone()) {
+                        if before != configured {
+                            self.write_settings(&before, &configured);
+                        }
+                    }
+                }
+                Err(e) => event(
+                    &name,
+                    "error",
+                    json!({ "message": format!("ignoring the config: {:#}", e) }),
+                ),
+            }
+        }
+
+        let lock_modified = server_lockfile_path(&name).ok().and_then(|p| modified(&p));
+        if !hangup && lock_modified == self.sources.lock_modified {
+            return;
+        }
+        self.sources.lock_modified = lock_modified;
+        let Ok(lock) = read_server_lock(&name) else {
+            return;
+        };
+        if lock.pid != self.server_pid {
+            return;
+        }
+        let mut changed = serde_json::Map::new();
+        if lock.grace_period != self.grace_period {
+            match lock.grace_period.parse::<GracePeriod>() {
+                Ok(grace) => {
+                    self.grace_period = lock.grace_period.clone();
+                    self.grace_length = grace.length();
+                    if self.grace.take().is_some() {
+                        self.published = None;
+                        record_grace_deadline(&name, self.server_pid, None);
+                    }
+                    changed.insert("grace_period".into(), json!(self.grace_period));
+                }
+                Err(e) => event(
+                    &name,
+                    "error",
+                    json!({ "message": format!("ignoring grace period: {:#}", e) }),
+                ),
+            }
+        }
+        if lock.health_check != self.health_check {
+            match lock.health_check.as_ref().map(HealthCheck::validate) {
+                Some(Err(e)) => event(
+                    &name,
+                    "error",
+                    json!({ "message": format!("ignoring health check: {:#}", e) }),
+                ),
+                _ => {
+                    self.health_check = lock.health_check.clone();
+                    self.next_probe = self
+                        .health_check
+                        .as_ref()
+                        .and_then(|check| check.interval().ok())
+                        .map(|interval| instant_after(Instant::now(), interval));
+                    changed.insert("health_check".into(), json!(self.health_check));
+                }
+            }
+        }
+        if !changed.is_empty() {
+            changed.insert("restart".into(), json!(lock.restart.as_str()));
+            event(&name, "reload", serde_json::Value::Object(changed));
+        }
+    }
+
+    /// Write the settings the config changed from `before` to `after` to the
+    /// server's lock.
+    fn write_settings(&self, before: &ServerSettings, after: &ServerSettings) {
+        let restart = match after.restart.parse::<RestartPolicy>() {
+            Ok(restart) => Some(restart),
+            Err(e) => {
+                event(
+                    &self.name,
+                    "error",
+                    json!({ "message": format!("ignoring restart policy: {:#}", e) }),
+                );
+                None
+            }
+        };
+        let result = update_server_lock(&self.name, |lock| {
+            if lock.pid != self.server_pid {
+                return Ok(LockUpdate::Keep(()));
+            }
+            if before.grace_period != after.grace_period {
+                lock.grace_period = after.grace_period.clone();
+            }
+            if before.restart != after.restart {
+                if let Some(restart) = restart {
+                    lock.restart = restart;
+                }
+            }
+            if before.health_check != after.health_check {
+                lock.health_check = after.health_check.clone();
+            }
+            Ok(LockUpdate::Write(()))
+        });
+        if let Err(e) = result {
+            event(
+                &self.name,
+                "error",
+                json!({ "message": format!("failed to update the lock: {:#}", e) }),
+            );
+        }
+    }
+
+    /// Start watching the relaunched server `new_pid`, or clean up if there
+    /// is none. Returns whether there is one.
+    fn adopt(&mut self, new_pid: Option<i32>) -> bool {
+        match new_pid {
+            Some(new_pid) => {
+                self.server_pid = new_pid;
+                self.launched_at = Instant::now();
+                if let Some(container) = &mut self.container {
+                    container.id = None;
+                    self.container_gone = false;
+                }
+                self.notifier.watch(new_pid);
+                true
+            }
+            None => {
+                delete_locks_owned_by(&self.name, self.server_pid);
+                false
+            }
+        }
+    }
+
+    /// One pass of the watcher loop. Returns how long to sleep before the
+    /// next one (cut short by [`Watch::wait`]'s events), or `None` once the
+    /// server is gone and the watch is over.
+    pub fn step(&mut self) -> Option<Duration> {
+        let name = self.name.clone();
+        let name = name.as_str();
+
+        // Prove we are still looping, so `info`/`doctor` (and systemd, if it
+        // launched us) can tell a wedged watcher from a healthy one.
+        if self
+            .last_heartbeat
+            .is_none_or(|t| t.elapsed() >= self.heartbeat_every)
+        {
+            beat(name);
+            self.last_heartbeat = Some(Instant::now());
+        }
+
+        // Sent SIGTERM: take the server down with us, or with --linger leave
+        // it running and record that nobody is supervising it any more.
+        if TERMINATE_REQUESTED.load(Ordering::SeqCst) {
+            event(
+                name,
+                "sigterm",
+                json!({ "server_pid": self.server_pid, "linger": self.linger }),
+            );
+            if self.linger {
+                hand_off_server(name, self.server_pid);
+            } else {
+                self.notify_stopping();
+                notify_before_shutdown(name, "shutdown");
+                let exit = terminate_server(name, self.server_pid);
+                record_exit(name, self.server_pid, exit, Some(DeathReason::Shutdown));
+                delete_locks_owned_by(name, self.server_pid);
+            }
+            return None;
+        }
+
+        // Apply settings changed in the config or the lock since last time.
+        self.reload_settings();
 
-    loop {
         // Reap the server if it has exited (we are its parent). This both
-        // detects death and prevents it lingering as a zombie.
-        if try_reap_server(server_pid) {
-            // Server died, clean up both lock files and exit.
-            delete_locks_owned_by(name, server_pid);
-            break;
+        // detects death and prevents it lingering as a zombie. Any other
+        // exited child is swept up at the same time, unless other servers'
+        // children share the process.
+        let exited = try_reap_server(self.server_pid).or_else(|| {
+            self.standalone
+                .then(|| reap_strays(name, self.server_pid))
+                .flatten()
+        });
+        if let Some(exit) = exited {
+            self.notifier.unwatch(self.server_pid);
+            record_exit(name, self.server_pid, exit, None);
+            // Relaunch per the restart policy if clients are still attached;
+            // otherwise clean up both lock files and exit.
+            if self.launched_at.elapsed() < RESTART_STABLE_AFTER {
+                self.rapid_restarts += 1;
+            } else {
+                self.rapid_restarts = 0;
+            }
+            let relaunched = restart_server(
+                name,
+                self.server_pid,
+                exit,
+                self.rapid_restarts,
+                &self.relaunch,
+            );
+            return self.adopt(relaunched).then_some(Duration::ZERO);
+        }
+
+        // Run the health probe when it is due. An unhealthy server whose check
+        // asks for it is restarted in place (clients stay attached).
+        if let (Some(check), Some(due)) = (&self.health_check, self.next_probe) {
+            if Instant::now() >= due {
+                let restart = run_health_probe(name, self.server_pid, check);
+                self.next_probe = check
+                    .interval()
+                    .ok()
+                    .map(|i| instant_after(Instant::now(), i));
+                if restart {
+                    event(name, "unhealthy", json!({ "server_pid": self.server_pid }));
+                    return self
+                        .replace_or_stop(DeathReason::Unhealthy, Some("unhealthy"))
+                        .then_some(Duration::ZERO);
+                }
+            }
+        }
+
+        // Sample memory/CPU when due. A breach sustained past the limit's
+        // window is logged, or restarts/stops the server, per its action.
+        let breach = self
+            .limits
+            .as_mut()
+            .and_then(|monitor| monitor.check(name, self.server_pid));
+        if let Some((action, why)) = breach {
+            if action != LimitAction::Log {
+                if action == LimitAction::Stop {
+                    self.notify_stopping();
+                    notify_before_shutdown(name, "resource-limit");
+                }
+                let relaunch_why = (action == LimitAction::Restart).then_some(why.as_str());
+                if !self.replace_or_stop(DeathReason::ResourceLimit, relaunch_why) {
+                    return None;
+                }
+                if let Some(monitor) = &mut self.limits {
+                    monitor.reset();
+                }
+                return Some(Duration::ZERO);
+            }
+        }
+
+        // A container server's client can outlive its container (e.g. when
+        // the runtime's daemon restarts): take it down so the exit is
+        // handled like any other.
+        if self.container_gone() {
+            event(
+                name,
+                "container-gone",
+                json!({ "server_pid": self.server_pid }),
+            );
+            let pid = Pid::from_raw(self.server_pid);
+            if killpg(pid, Signal::SIGKILL).is_err() {
+                let _ = kill(pid, Signal::SIGKILL);
+            }
+            return Some(Duration::ZERO);
+        }
+
+        // (Re)arm the clients-lock watch; it can't be armed before the file
+        // exists and drops out if the file is ever replaced.
+        if let Some(path) = &self.clients_path {
+            self.notifier.watch_file(path);
         }
 
         // Check and clean up dead clients
-        let has_clients = check_and_cleanup_dead_clients(name);
+        // If the clients lock can't be taken, carry on with the last known
+        // clients rather than mistaking it for "nobody attached".
+        let mut live_clients = match check_and_cleanup_dead_clients(name) {
+            Ok(live) => live,
+            Err(e) => {
+                event(name, "error", json!({ "message": format!("{:#}", e) }));
+                self.last_clients.clone().unwrap_or_default()
+            }
+        };
+        live_clients.sort_unstable();
+        let last_clients_before = self.last_clients.clone().unwrap_or_default();
+        if self.last_clients.as_ref() != Some(&live_clients) {
+            event(name, "clients", json!({ "clients": live_clients }));
+            self.last_clients = Some(live_clients.clone());
+        }
+        sync_client_watches(
+            &mut self.notifier,
+            &mut self.watched_clients,
+            &live_clients,
+            self.server_pid,
+        );
 
-        if has_clients {
-            // Active state: cancel grace timer if it was set
-            if grace_timer.is_some() {
-                grace_timer = None;
+        if !live_clients.is_empty() {
+            // Active state: cancel grace if it was pending
+            if self.grace.take().is_some() {
+                self.published = None;
+                record_grace_deadline(name, self.server_pid, None);
+                event(name, "grace-cancel", json!({ "clients": live_clients }));
+            }
+        } else if let (None, Some(grace_duration)) = (&self.grace, self.grace_length) {
+            // Grace state: start the countdown and publish it, so info/list/
+            // check can show how long is left. (An `infinite` grace has no
+            // countdown: the server idles until it is stopped.)
+            log::debug!(
+                "no clients attached (last seen: {:?}): starting {} grace period",
+                last_clients_before,
+                self.grace_period
+            );
+            let (now, wall_now) = (Instant::now(), chrono::Utc::now());
+            let timer = GraceTimer::start(self.grace_clock, grace_duration, now, wall_now);
+            let deadline = timer.deadline(now, wall_now);
+            self.grace = Some(timer);
+            self.published = Some(deadline);
+            record_grace_deadline(name, self.server_pid, self.published);
+            event(
+                name,
+                "grace-start",
+                json!({
+                    "grace_period": self.grace_period,
+                    "grace_clock": self.grace_clock.as_str(),
+                    "deadline": deadline,
+                }),
+            );
+            notify_desktop(
+                name,
+                &format!("{} is idle", name),
+                &format!(
+                    "No clients are attached. It shuts down in {} unless one attaches.",
+                    self.grace_period
+                ),
+            );
+            webhook(
+                name,
+                LifecycleEvent::Grace,
+                json!({ "grace_period": self.grace_period, "deadline": deadline }),
+            );
+        } else if let Some(timer) = &self.grace {
+            let (now, wall_now) = (Instant::now(), chrono::Utc::now());
+            // On the monotonic clock a suspend pushes the deadline back; keep
+            // the published one honest.
+            let deadline = timer.deadline(now, wall_now);
+            if self
+                .published
+                .is_none_or(|last| (deadline - last).abs() > GRACE_DRIFT)
+            {
+                self.published = Some(deadline);
+                record_grace_deadline(name, self.server_pid, self.published);
+                event(name, "grace-resync", json!({ "deadline": deadline }));
             }
-        } else if grace_timer.is_none() {
-            // Grace state: start timer
-            grace_timer = Some(Instant::now());
-        } else if let Some(start_time) = grace_timer {
             // Check if grace period expired
-            if start_time.elapsed() >= grace_duration {
-                // Grace period expired, kill server process group.
-                // The server runs in its own process group (setpgid) so
-                // killpg takes down the entire tree (e.g. uv + python child).
-                let pid = Pid::from_raw(server_pid);
-
-                // Try SIGTERM on the whole process group first.
-                // Fall back to single-PID kill for servers started before
-                // the setpgid change.
-                if killpg(pid, Signal::SIGTERM).is_err() {
-                    let _ = kill(pid, Signal::SIGTERM);
+            if timer.expired(now, wall_now) {
+                // A client may have attached since the check above. Only go
+                // ahead if none has, fencing out later ones; otherwise the
+                // next pass sees the rescue and cancels grace (or, if the
+                // clients lock was unavailable, tries again).
+                if !commit_shutdown(name) {
+                    log::debug!("grace expired, but a client attached or the lock was busy");
+                    event(
+                        name,
+                        "grace-deferred",
+                        json!({ "server_pid": self.server_pid }),
+                    );
+                    return Some(Duration::ZERO);
                 }
+                // Grace period expired: take the server down.
+                event(
+                    name,
+                    "grace-expired",
+                    json!({ "server_pid": self.server_pid }),
+                );
+                self.notify_stopping();
+                notify_before_shutdown(name, "grace-expired");
+                notify_desktop(
+                    name,
+                    &format!("Shutting down {}", name),
+                    "Its grace period ran out with no clients attached.",
+                );
+                self.replace_or_stop(DeathReason::GraceExpired, None);
+                return None;
+            }
+        }
 
-                // Wait for graceful exit, reaping the server if it goes.
-                if !wait_for_server_exit(server_pid, GRACE_KILL_TIMEOUT) {
-                    // Force kill the whole process group with SIGKILL.
-                    if killpg(pid, Signal::SIGKILL).is_err() {
-                        let _ = kill(pid, Signal::SIGKILL);
-                    }
-                    // Reap the SIGKILLed server so it doesn't linger as a zombie.
-                    wait_for_server_exit(server_pid, GRACE_KILL_TIMEOUT);
-                }
+        // Sleep until the next poll, the grace deadline or probe, or until the
+        // server or a client exits or the clients lock changes.
+        let now = Instant::now();
+        let mut timeout = poll_interval();
+        if let Some(timer) = &self.grace {
+            timeout = timeout.min(timer.remaining(now, chrono::Utc::now()));
+        }
+        if let Some(due) = self.next_probe {
+            timeout = timeout.min(due.saturating_duration_since(now));
+        }
+        if let Some(monitor) = &self.limits {
+            timeout = timeout.min(monitor.next_sample.saturating_duration_since(now));
+        }
+        Some(timeout)
+    }
+
+    /// For a container server, record the container's ID once the runtime has
+    /// written it, and every [`CONTAINER_CHECK_INTERVAL`] ask whether it is
+    /// still running. Returns `true` once it has been found gone on two
+    /// checks in a row, with the client still hanging on to it.
+    fn container_gone(&mut self) -> bool {
+        let Some(container) = &mut self.container else {
+            return false;
+        };
+        if container.id.is_none() {
+            let id = sharedserver::core::lockfile::server_file(&self.name, container::CIDFILE)
+                .ok()
+                .and_then(|cidfile| container::read_cidfile(&cidfile));
+            if let Some(id) = id {
+                record_container_id(&self.name, self.server_pid, &id);
+                container.id = Some(id);
+            }
+            return false;
+        }
+        if Instant::now() < self.next_container_check {
+            return false;
+        }
+        self.next_container_check = Instant::now() + CONTAINER_CHECK_INTERVAL;
+        let gone = container.running() == ContainerState::Stopped;
+        let confirmed = gone && self.container_gone;
+        self.container_gone = gone;
+        confirmed
+    }
 
-                // Clean up and exit
-                delete_locks_owned_by(name, server_pid);
-                break;
+    /// Sleep up to `timeout`, waking early if the server or a client exits or
+    /// the clients lock changes. An exited client only fires once, so its
+    /// watch is dropped; the next pass removes it from the clients lock.
+    pub fn wait(&mut self, timeout: Duration) {
+        log::trace!("sleeping up to {:?}", timeout);
+        for pid in self.notifier.wait(timeout) {
+            log::trace!("woken by PID {} exiting", pid);
+            if pid != self.server_pid {
+                self.notifier.unwatch(pid);
             }
         }
+    }
 
-        // Sleep before next poll
-        thread::sleep(POLL_INTERVAL);
+    /// Log the end of the watch.
+    pub fn finish(&self) {
+        // Covers the paths where the server went on its own; a repeat after an
+        // earlier STOPPING=1 is harmless.
+        self.notify_stopping();
+        if let Some(front) = &self.relaunch.front {
+            front.stop();
+        }
+        event(&self.name, "exit", json!({ "server_pid": self.server_pid }));
     }
+}
 
-    Ok(())
+/// SIGTERM the server's process group, wait for it to exit (reaping it), and
+/// escalate to SIGKILL if it doesn't go within [`kill_timeout`]. Returns
+/// how it exited ([`ServerExit::Unknown`] if it never could be reaped).
+fn terminate_server(name: &str, server_pid: i32) -> ServerExit {
+    let kill_timeout = kill_timeout();
+    // The server runs in its own process group (setpgid) so killpg takes down
+    // the entire tree (e.g. uv + python child).
+    let pid = Pid::from_raw(server_pid);
+    event(
+        name,
+        "terminate",
+        json!({ "server_pid": server_pid, "signal": "SIGTERM" }),
+    );
+
+    // A container is stopped through its runtime, which escalates to SIGKILL
+    // on the same schedule; its client then exits with it.
+    let container = server_container(name, server_pid);
+    let stopped = match &container {
+        Some(container) => match container.stop(kill_timeout) {
+            Ok(()) => true,
+            Err(e) => {
+                event(name, "error", json!({ "message": format!("{:#}", e) }));
+                false
+            }
+        },
+        None => false,
+    };
+
+    // Try SIGTERM on the whole process group first. Fall back to single-PID
+    // kill for servers started before the setpgid change.
+    if !stopped && killpg(pid, Signal::SIGTERM).is_err() {
+        let _ = kill(pid, Signal::SIGTERM);
+    }
+
+    // Wait for graceful exit, reaping the server if it goes.
+    if let Some(exit) = wait_for_server_exit(server_pid, kill_timeout) {
+        return exit;
+    }
+
+    // Force kill the whole process group with SIGKILL.
+    event(
+        name,
+        "kill-escalation",
+        json!({
+            "server_pid": server_pid,
+            "signal": "SIGKILL",
+            "after_secs": kill_timeout.as_secs(),
+        }),
+    );
+    if let Some(container) = &container {
+        let _ = container.kill();
+    }
+    if killpg(pid, Signal::SIGKILL).is_err() {
+        let _ = kill(pid, Signal::SIGKILL);
+    }
+    // Reap the SIGKILLed server so it doesn't linger as a zombie.
+    wait_for_server_exit(server_pid, kill_timeout).unwrap_or(ServerExit::Unknown)
+}
+
+/// The container `server_pid` runs, if the lock still names it and it runs in
+/// one.
+fn server_container(name: &str, server_pid: i32) -> Option<Container> {
+    read_server_lock(name)
+        .ok()
+        .filter(|lock| lock.pid == server_pid)
+        .and_then(|lock| lock.container)
 }
 
-/// Remove dead client PIDs from the clients lockfile and report whether any
-/// live clients remain (`true` == still has references).
+/// Record the ID of `server_pid`'s container in the lock, if it still names
+/// that server.
+fn record_container_id(name: &str, server_pid: i32, id: &str) {
+    let _ = update_server_lock(name, |lock| {
+        match &mut lock.container {
+            Some(container) if lock.pid == server_pid => container.id = Some(id.to_string()),
+            _ => return Ok(LockUpdate::Keep(())),
+        }
+        Ok(LockUpdate::Write(()))
+    });
+    event(
+        name,
+        "container",
+        json!({ "server_pid": server_pid, "id": id }),
+    );
+}
+
+/// Write the `<name>.exit.json` record for a server instance that just died.
+/// `reason` of `None` means it went on its own (or via `stop`): classify it
+/// from the lock's stop request and the exit status. Skipped if the lock no
+/// longer names `server_pid`.
+fn record_exit(name: &str, server_pid: i32, exit: ServerExit, reason: Option<DeathReason>) {
+    event(
+        name,
+        "server-exit",
+        json!({
+            "server_pid": server_pid,
+            "exit": exit.to_string(),
+            "reason": reason.map(|r| r.as_str()),
+        }),
+    );
+    let Ok(lock) = read_server_lock(name) else {
+        return;
+    };
+    if lock.pid != server_pid {
+        return;
+    }
+    // Its container is gone with it (`--rm`).
+    if lock.container.is_some() {
+        if let Ok(cidfile) = sharedserver::core::lockfile::server_file(name, container::CIDFILE) {
+            let _ = std::fs::remove_file(cidfile);
+        }
+    }
+    let reason = reason.unwrap_or(if lock.stop_requested {
+        DeathReason::Stopped
+    } else {
+        DeathReason::unrequested(&exit)
+    });
+    webhook(
+        name,
+        if reason == DeathReason::Crashed {
+            LifecycleEvent::Crash
+        } else {
+            LifecycleEvent::Shutdown
+        },
+        json!({
+            "server_pid": server_pid,
+            "exit": exit.to_string(),
+            "reason": reason.as_str(),
+        }),
+    );
+    let _ = write_tombstone(
+        name,
+        &Tombstone {
+            pid: server_pid,
+            command: lock.command,
+            reason,
+            exit,
+            started_at: lock.started_at,
+            exited_at: chrono::Utc::now(),
+            restart_count: lock.restart_count,
+            last_refcount: read_clients_lock(name).map_or(0, |c| c.refcount()),
+            tags: lock.tags,
+        },
+    );
+}
+
+/// Relaunch the server after it exited with `exit`, if its restart policy asks
+/// for it, no deliberate stop is in progress, and clients are still attached.
+///
+/// Returns the new server PID (already recorded in the server lock), or `None`
+/// if the server should stay down. Crash loops (`rapid_restarts` > 0) back off
+/// exponentially before relaunching.
+fn restart_server(
+    name: &str,
+    old_pid: i32,
+    exit: ServerExit,
+    rapid_restarts: u32,
+    relaunch: &Relaunch,
+) -> Option<i32> {
+    let lock = read_server_lock(name).ok()?;
+    if lock.pid != old_pid || lock.stop_requested || !lock.restart.should_restart(&exit) {
+        log::debug!(
+            "not restarting after {}: restart policy {}{}",
+            exit,
+            lock.restart.as_str(),
+            if lock.stop_requested {
+                ", stop requested"
+            } else {
+                ""
+            }
+        );
+        return None;
+    }
+    if check_and_cleanup_dead_clients(name)
+        .unwrap_or_default()
+        .is_empty()
+    {
+        log::debug!("not restarting after {}: no clients left", exit);
+        return None;
+    }
+
+    if rapid_restarts > 0 {
+        let backoff = Duration::from_secs(1u64 << rapid_restarts.min(5)).min(RESTART_MAX_BACKOFF);
+        // Keep heartbeating through the backoff so it doesn't read as wedged.
+        let until = Instant::now() + backoff;
+        while Instant::now() < until {
+            if TERMINATE_REQUESTED.load(Ordering::SeqCst) {
+                // Nothing is running to hand off; let the caller clean up.
+                return None;
+            }
+            beat(name);
+            thread::sleep(HEARTBEAT_INTERVAL.min(until.saturating_duration_since(Instant::now())));
+        }
+    }
+
+    relaunch_server(name, old_pid, &exit.to_string(), relaunch)
+}
+
+/// Spawn a fresh server from the command recorded in the lock, publish its PID,
+/// and log the restart with `reason`. Returns the new PID, or `None` if the
+/// lock no longer belongs to `old_pid` or the relaunch failed.
+fn relaunch_server(name: &str, old_pid: i32, reason: &str, relaunch: &Relaunch) -> Option<i32> {
+    let lock = read_server_lock(name).ok()?;
+    if lock.pid != old_pid || lock.stop_requested {
+        return None;
+    }
+
+    let new_pid = match crate::commands::start::spawn_server(
+        name,
+        &lock.command,
+        &lock.env,
+        lock.log_file.as_deref(),
+        relaunch,
+        lock.container.as_ref(),
+    ) {
+        Ok(pid) => pid.as_raw(),
+        Err(e) => {
+            eprintln!("Watcher: Failed to restart server ({:#})", e);
+            event(
+                name,
+                "error",
+                json!({ "message": format!("failed to restart server: {:#}", e) }),
+            );
+            return None;
+        }
+    };
+
+    // Publish the new PID, unless `stop` claimed the server meanwhile.
+    let published = update_server_lock(name, |lock| {
+        if lock.pid != old_pid || lock.stop_requested {
+            return Ok(LockUpdate::Keep(None));
+        }
+        lock.pid = new_pid;
+        lock.start_time = process_start_stamp(new_pid);
+        lock.started_at = chrono::Utc::now();
+        lock.restart_count += 1;
+        // The new process hasn't been probed yet.
+        lock.health = None;
+        // Nor has its container been created.
+        if let Some(container) = &mut lock.container {
+            container.id = None;
+        }
+        Ok(LockUpdate::Write(Some(lock.clone())))
+    });
+    let updated = match published {
+        Ok(Some(updated)) => updated,
+        failed => {
+            // We can't publish the new PID, so nothing could ever find or stop
+            // the relaunched server: take it down again rather than orphan it.
+            let reason = match failed {
+                Err(e) => format!("{:#}", e),
+                _ => "stop requested".to_string(),
+            };
+            eprintln!(
+                "Watcher: Failed to record restarted server ({}), stopping it",
+                reason
+            );
+            event(
+                name,
+                "error",
+                json!({ "message": format!("failed to record restarted server {}: {}", new_pid, reason) }),
+            );
+            let _ = killpg(Pid::from_raw(new_pid), Signal::SIGKILL);
+            wait_for_server_exit(new_pid, kill_timeout());
+            return None;
+        }
+    };
+
+    // The proxies' sessions were with the old server.
+    if let Some(stdio) = &relaunch.stdio {
+        stdio.reset();
+    }
+    event(
+        name,
+        "restart",
+        json!({ "old_pid": old_pid, "new_pid": new_pid, "reason": reason }),
+    );
+    webhook(
+        name,
+        LifecycleEvent::Restart,
+        json!({
+            "old_pid": old_pid,
+            "new_pid": new_pid,
+            "reason": reason,
+            "restart_count": updated.restart_count,
+        }),
+    );
+    let _ = sharedserver::core::log::log_invocation(
+        name,
+        &sharedserver::core::log::InvocationLog::success(
+            "restart",
+            &[name.to_string()],
+            Some(serde_json::json!({
+                "old_pid": old_pid,
+                "new_pid": new_pid,
+                "reason": reason,
+                "restart_count": updated.restart_count,
+                "policy": updated.restart.as_str(),
+            })),
+        ),
+    );
+
+    Some(new_pid)
+}
+
+/// Run one health probe and record the outcome in the server lock (only if the
+/// lock still names `server_pid`). Returns `true` when the server has just
+/// been judged unhealthy and its check asks for a restart.
+fn run_health_probe(name: &str, server_pid: i32, check: &HealthCheck) -> bool {
+    let timeout = check.timeout().unwrap_or(POLL_INTERVAL);
+    let result = check.probe.run(timeout);
+
+    update_server_lock(name, |lock| {
+        if lock.pid != server_pid {
+            return Ok(LockUpdate::Keep(false));
+        }
+        let status = HealthStatus::next(lock.health.as_ref(), result, check.retries);
+        let restart = !status.healthy && check.restart;
+        lock.health = Some(status);
+        Ok(LockUpdate::Write(restart))
+    })
+    .unwrap_or(false)
+}
+
+/// Bring the notifier's client watches in line with the clients lock: watch
+/// newly registered clients and drop those that have left. `watched` records
+/// every client already registered (including ones whose exit has fired), so
+/// a client isn't re-watched on every pass.
+fn sync_client_watches(
+    notifier: &mut ExitNotifier,
+    watched: &mut HashSet<i32>,
+    live: &[i32],
+    server_pid: i32,
+) {
+    watched.retain(|pid| {
+        let keep = live.contains(pid);
+        if !keep && *pid != server_pid {
+            notifier.unwatch(*pid);
+        }
+        keep
+    });
+    for &pid in live {
+        if watched.insert(pid) && pid != server_pid {
+            notifier.watch(pid);
+        }
+    }
+}
+
+/// Watcher-side state for enforcing [`ResourceLimits`]: the parsed limits, one
+/// breach tracker per resource, and the previous sample (for CPU rate).
+struct LimitMonitor {
+    memory_limit: Option<u64>,
+    memory_action: LimitAction,
+    cpu_limit: Option<f64>,
+    cpu_action: LimitAction,
+    sustained: Duration,
+    memory: BreachTracker,
+    cpu: BreachTracker,
+    previous: Option<(Instant, ProcessSample)>,
+    next_sample: Instant,
+}
+
+impl LimitMonitor {
+    /// `limits` must already have passed [`ResourceLimits::validate`].
+    fn new(limits: ResourceLimits) -> Self {
+        Self {
+            memory_limit: limits.memory_limit_bytes().ok().flatten(),
+            memory_action: limits.memory_action,
+            cpu_limit: limits.cpu_limit_percent().ok().flatten(),
+            cpu_action: limits.cpu_action,
+            sustained: limits.sustained().unwrap_or(RESOURCE_SAMPLE_INTERVAL),
+            memory: BreachTracker::default(),
+            cpu: BreachTracker::default(),
+            previous: None,
+            next_sample: Instant::now(),
+        }
+    }
+
+    /// Forget per-process state after the server has been relaunched.
+    fn reset(&mut self) {
+        self.memory = BreachTracker::default();
+        self.cpu = BreachTracker::default();
+        self.previous = None;
+        self.next_sample = Instant::now() + RESOURCE_SAMPLE_INTERVAL;
+    }
+
+    /// Take a sample if one is due, record it in the server lock, and return
+    /// the action to take (with a short reason) if a limit has just been
+    /// exceeded for the whole sustain window. Every such breach is logged.
+    fn check(&mut self, name: &str, server_pid: i32) -> Option<(LimitAction, String)> {
+        let now = Instant::now();
+        if now < self.next_sample {
+            return None;
+        }
+        self.next_sample = now + RESOURCE_SAMPLE_INTERVAL;
+
+        let sample = sample_process_group(server_pid)?;
+        let cpu_percent = self
+            .previous
+            .and_then(|(at, previous)| sample.cpu_percent_since(&previous, now.duration_since(at)));
+        self.previous = Some((now, sample));
+        record_resources(name, server_pid, sample.rss_bytes, cpu_percent);
+
+        let memory_over = self
+            .memory_limit
+            .is_some_and(|limit| sample.rss_bytes > limit);
+        let cpu_over =
+            matches!((self.cpu_limit, cpu_percent), (Some(limit), Some(used)) if used > limit);
+
+        // Feed both trackers every sample so neither misses an observation.
+        let memory_fired = self.memory.observe(memory_over, now, self.sustained);
+        let cpu_fired = self.cpu.observe(cpu_over, now, self.sustained);
+        let breach = if memory_fired {
+            Some((
+                "memory",
+                self.memory_action,
+                sample.rss_bytes as f64,
+                self.memory_limit.unwrap_or_default() as f64,
+            ))
+        } else if cpu_fired {
+            Some((
+                "cpu",
+                self.cpu_action,
+                cpu_percent.unwrap_or_default(),
+                self.cpu_limit.unwrap_or_default(),
+            ))
+        } else {
+            None
+        };
+        let (resource, action, value, limit) = breach?;
+
+        event(
+            name,
+            "limit-exceeded",
+            json!({
+                "server_pid": server_pid,
+                "resource": resource,
+                "value": value,
+                "limit": limit,
+                "action": action.as_str(),
+            }),
+        );
+        let _ = sharedserver::core::log::log_invocation(
+            name,
+            &sharedserver::core::log::InvocationLog::success(
+                "resource-limit",
+                &[name.to_string()],
+                Some(serde_json::json!({
+                    "pid": server_pid,
+                    "resource": resource,
+                    "value": value,
+                    "limit": limit,
+                    "action": action.as_str(),
+                })),
+            ),
+        );
+        Some((action, format!("{} limit exceeded", resource)))
+    }
+}
+
+/// Leave the server running without a watcher (`--linger`): clear the
+/// watcher fields and grace deadline so the lock describes an unsupervised
+/// server, drop the heartbeat, and log the handoff. The server stays visible
+/// to `list`/`use`/`stop`; `stop` cleans up the lockfiles itself once it
+/// exits, since there is no watcher to do it.
+fn hand_off_server(name: &str, server_pid: i32) {
+    let _ = update_server_lock(name, |lock| {
+        if lock.pid != server_pid {
+            return Ok(LockUpdate::Keep(()));
+        }
+        lock.watcher_pid = None;
+        lock.watcher_start_time = None;
+        lock.grace_deadline = None;
+        Ok(LockUpdate::Write(()))
+    });
+    let _ = sharedserver::core::heartbeat::delete_heartbeat(name);
+    event(name, "handoff", json!({ "server_pid": server_pid }));
+    let _ = sharedserver::core::log::log_invocation(
+        name,
+        &sharedserver::core::log::InvocationLog::success(
+            "watcher-handoff",
+            &[name.to_string()],
+            Some(json!({
+                "server_pid": server_pid,
+                "watcher_pid": std::process::id(),
+            })),
+        ),
+    );
+}
+
+/// Append to the watcher event log (`admin debug --watcher`), and export it
+/// as a span when tracing. Best-effort: the watcher never fails over its own
+/// diagnostics.
+fn event(name: &str, kind: &str, details: serde_json::Value) {
+    if telemetry::enabled() {
+        let mut attributes = json!({ "server.name": name });
+        if let (Some(all), Some(extra)) = (attributes.as_object_mut(), details.as_object()) {
+            all.extend(extra.clone());
+        }
+        telemetry::mark(&format!("watcher.{}", kind), &attributes);
+        // The watcher may be killed at any point; don't hold spans back.
+        telemetry::flush();
+    }
+    let _ = sharedserver::core::log::log_watcher_event(name, kind, details);
+}
+
+/// POST `lifecycle` to the server's `--on-event-webhook`, if it has one,
+/// without waiting for it. A failed delivery is logged, never fatal.
+fn webhook(name: &str, lifecycle: LifecycleEvent, details: serde_json::Value) {
+    let Some(url) = read_server_lock(name).ok().and_then(|lock| lock.webhook) else {
+        return;
+    };
+    let payload = webhook::payload(name, lifecycle, &details);
+    webhook::send_detached(&url, &payload, |e| {
+        event(
+            name,
+            "error",
+            json!({ "message": format!("Webhook {} failed: {:#}", url, e) }),
+        )
+    });
+}
+
+/// Show a desktop notification, if the server was started with
+/// `--notify-desktop`. A failure is logged, never fatal.
+fn notify_desktop(name: &str, summary: &str, body: &str) {
+    if !read_server_lock(name).is_ok_and(|lock| lock.notify_desktop) {
+        return;
+    }
+    if let Err(e) = sharedserver::core::desktop::notify(summary, body) {
+        event(
+            name,
+            "error",
+            json!({ "message": format!("Desktop notification failed: {:#}", e) }),
+        );
+    }
+}
+
+/// Refresh the heartbeat file and, under systemd, the watchdog.
+fn beat(name: &str) {
+    let _ = write_heartbeat(name);
+    sd_notify::notify_watchdog();
+}
+
+/// Signal attached clients with the lock's `notify_signal`, if configured,
+/// before the watcher takes the server down.
+fn notify_before_shutdown(name: &str, reason: &str) {
+    if let Some(signal) = read_server_lock(name).ok().and_then(|l| l.notify_signal) {
+        sharedserver::core::notify::notify_clients(name, &signal, reason);
+    }
+}
+
+/// Publish (or clear) the grace deadline in the server lock, if it still
+/// belongs to `server_pid`.
+fn record_grace_deadline(
+    name: &str,
+    server_pid: i32,
+    deadline: Option<chrono::DateTime<chrono::Utc>>,
+) {
+    let _ = update_server_lock(name, |lock| {
+        if lock.pid != server_pid || lock.grace_deadline == deadline {
+            return Ok(LockUpdate::Keep(()));
+        }
+        lock.grace_deadline = deadline;
+        Ok(LockUpdate::Write(()))
+    });
+}
+
+/// Record the latest resource sample in the server lock (only if the lock
+/// still names `server_pid`).
+fn record_resources(name: &str, server_pid: i32, rss_bytes: u64, cpu_percent: Option<f64>) {
+    let _ = update_server_lock(name, |lock| {
+        if lock.pid != server_pid {
+            return Ok(LockUpdate::Keep(()));
+        }
+        lock.resources = Some(ResourceUsage {
+            rss_bytes,
+            cpu_percent,
+            sampled_at: chrono::Utc::now(),
+        });
+        Ok(LockUpdate::Write(()))
+    });
+}
+
+/// The final check before a grace-expiry shutdown: under the clients lock,
+/// drop dead clients and, if none are left, bump `shutdown_generation` so any
+/// `incref` that hasn't landed yet is refused. Returns `false` if a live
+/// client is attached (a rescue won the race), or if the lock can't be taken:
+/// whoever holds it may be attaching, so the next pass tries again. A missing
+/// clients file can't hold a rescue, so the shutdown goes ahead.
+fn commit_shutdown(name: &str) -> bool {
+    let Ok(clients_path) = sharedserver::core::lockfile::clients_lockfile_path(name) else {
+        return true;
+    };
+    if !clients_path.exists() {
+        return true;
+    }
+
+    update_clients_lock(name, |clients| {
+        clients.clients.retain(|pid, client| client.is_alive(*pid));
+        if !clients.clients.is_empty() {
+            return Ok(LockUpdate::Keep(false));
+        }
+        clients.shutdown_generation += 1;
+        Ok(LockUpdate::Write(true))
+    })
+    .unwrap_or_else(|e| {
+        event(name, "error", json!({ "message": format!("{:#}", e) }));
+        false
+    })
+}
+
+/// Remove dead client PIDs from the clients lockfile and return the live ones
+/// that remain (empty == no references). Fails if the lock couldn't be taken
+/// (e.g. a hung process holds it past the lock timeout), which is not the same
+/// as having no clients.
 ///
 /// The clients lockfile is never deleted while the server lives: when the last
 /// client leaves, the file simply holds an empty client map with refcount 0
 /// (which signals grace). The whole read-modify-write happens under one
 /// exclusive lock on a stable inode, so it can't race incref/decref. Liveness
 /// probes are cheap (`/proc` reads), so holding the lock across them is fine.
-fn check_and_cleanup_dead_clients(name: &str) -> bool {
-    let clients_path = match sharedserver::core::lockfile::clients_lockfile_path(name) {
-        Ok(p) => p,
-        Err(_) => return false,
-    };
+/// The file is only rewritten when something changed: the watcher watches it
+/// for writes, so an unconditional rewrite would wake itself forever.
+fn check_and_cleanup_dead_clients(name: &str) -> Result<Vec<i32>> {
+    let clients_path = sharedserver::core::lockfile::clients_lockfile_path(name)?;
 
     // No clients lockfile yet (e.g. the brief window during start) -> no clients.
     if !clients_path.exists() {
-        return false;
+        return Ok(Vec::new());
     }
 
-    sharedserver::core::lockfile::with_lock(&clients_path, |file| {
-        let mut clients: ClientsLock =
-            sharedserver::core::lockfile::read_json(file).unwrap_or_else(|_| ClientsLock::new());
+    update_clients_lock(name, |clients| {
+        let mut removed = Vec::new();
+        clients.clients.retain(|pid, client| {
+            let alive = client.is_alive(*pid);
+            if !alive {
+                log::debug!(
+                    "client {} is no longer attached: {}",
+                    pid,
+                    if client.pid_reused(*pid) {
+                        "its PID now belongs to another process"
+                    } else {
+                        "process exited"
+                    }
+                );
+                removed.push(*pid);
+            }
+            alive
+        });
+        let live = clients.clients.keys().copied().collect();
+        if removed.is_empty() {
+            return Ok(LockUpdate::Keep(live));
+        }
+        removed.sort_unstable();
+        event(name, "client-removed", json!({ "clients": removed }));
+        Ok(LockUpdate::Write(live))
+    })
+}
 
-        clients.clients.retain(|pid, _| is_process_alive(*pid));
-        clients.refcount = clients.clients.len() as u32;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sharedserver::core::ClientsLock;
 
-        sharedserver::core::lockfile::write_json(file, &clients)?;
-        Ok(clients.refcount > 0)
-    })
-    .unwrap_or(false)
+    #[test]
+    fn test_reap_strays_returns_server_status() {
+        // SAFETY: the child only calls _exit, which is async-signal-safe.
+        let pid = match unsafe { nix::unistd::fork() }.expect("fork") {
+            nix::unistd::ForkResult::Child => unsafe { libc::_exit(7) },
+            nix::unistd::ForkResult::Parent { child } => child.as_raw(),
+        };
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let exit = loop {
+            if let Some(exit) = reap_strays("unused", pid) {
+                break exit;
+            }
+            assert!(Instant::now() < deadline, "child never reaped");
+            thread::sleep(Duration::from_millis(10));
+        };
+        assert_eq!(exit, ServerExit::Exited { code: 7 });
+        // Already collected: nothing left to wait for.
+        assert_eq!(
+            waitpid(Pid::from_raw(pid), Some(WaitPidFlag::WNOHANG)),
+            Err(Errno::ECHILD)
+        );
+    }
+
+    #[test]
+    fn test_commit_shutdown_backs_off_for_live_client() {
+        let dir = std::env::temp_dir().join(format!("watcher-commit-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::env::set_var("SHAREDSERVER_LOCKDIR", &dir);
+        let name = "commit";
+
+        // A live client attached at the last moment: the rescue wins.
+        let mut clients = ClientsLock::new();
+        clients.clients.insert(
+            std::process::id() as i32,
+            sharedserver::core::ClientInfo::new(std::process::id() as i32, None),
+        );
+        sharedserver::core::write_clients_lock(name, &clients).unwrap();
+        assert!(!commit_shutdown(name));
+        let after = sharedserver::core::read_clients_lock(name).unwrap();
+        assert!(!after.is_shutting_down());
+
+        // Nobody left: the shutdown is committed and fences out late increfs.
+        sharedserver::core::write_clients_lock(name, &ClientsLock::new()).unwrap();
+        assert!(commit_shutdown(name));
+        let after = sharedserver::core::read_clients_lock(name).unwrap();
+        assert_eq!(after.shutdown_generation, 1);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
 }
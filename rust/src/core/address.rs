@@ -0,0 +1,117 @@
+//! Where a server can be reached, shown by `list` and `info`.
+//!
+//! Addresses are written `tcp:HOST:PORT` or `unix:PATH`. A server advertises
+//! one with `--address`; without that, a TCP or HTTP health probe already
+//! names where the server listens, so its address is used instead.
+//!
+//! Two running servers can't listen in the same place, so a server whose
+//! address another running server already has is refused at start, and
+//! `admin doctor` flags any pair that got there anyway.
+
+use anyhow::{bail, Context, Result};
+
+use super::lockfile::{read_server_lock, servers_with};
+use super::state::{get_server_state, ServerState};
+
+/// Normalize an `--address` value. Accepts `tcp:HOST:PORT`, `unix:PATH`, a
+/// bare `HOST:PORT`, a bare port (meaning `127.0.0.1`), or an absolute socket
+/// path.
+pub fn parse_address(address: &str) -> Result<String> {
+    let address = address.trim();
+    if let Some(path) = address.strip_prefix("unix:") {
+        return unix_address(path, address);
+    }
+    if address.starts_with('/') {
+        return unix_address(address, address);
+    }
+    let host_port = address.strip_prefix("tcp:").unwrap_or(address);
+    if let Ok(port) = host_port.parse::<u16>() {
+        return Ok(format!("tcp:127.0.0.1:{}", port));
+    }
+    let Some((host, port)) = host_port.rsplit_once(':') else {
+        bail!(
+            "Invalid address '{}': expected tcp:HOST:PORT, HOST:PORT, PORT, or unix:PATH",
+            address
+        );
+    };
+    if host.is_empty() {
+        bail!("Invalid address '{}': no host", address);
+    }
+    port.parse::<u16>()
+        .with_context(|| format!("Invalid port in address '{}'", address))?;
+    Ok(format!("tcp:{}:{}", host, port))
+}
+
+fn unix_address(path: &str, address: &str) -> Result<String> {
+    if path.is_empty() {
+        bail!("Invalid address '{}': no socket path", address);
+    }
+    Ok(format!("unix:{}", path))
+}
+
+/// Whether servers at `a` and `b` would listen in the same place: the same
+/// TCP port, whatever the host (one bound to every interface takes the port
+/// on all of them), or the same socket path.
+pub fn same_endpoint(a: &str, b: &str) -> bool {
+    let port = |address: &str| {
+        address
+            .strip_prefix("tcp:")?
+            .rsplit_once(':')?
+            .1
+            .parse::<u16>()
+            .ok()
+    };
+    match (port(a), port(b)) {
+        (Some(a), Some(b)) => a == b,
+        _ => a.starts_with("unix:") && a == b,
+    }
+}
+
+/// The running server, other than `except`, that listens at `address` (or
+/// has a front there), if any.
+pub fn claimed_by(address: &str, except: &str) -> Option<String> {
+    servers_with(&["server.json"])
+        .ok()?
+        .into_iter()
+        .filter(|name| name != except)
+        .find(|name| {
+            get_server_state(name).is_ok_and(|state| state != ServerState::Stopped)
+                && read_server_lock(name).is_ok_and(|lock| {
+                    lock.claimed_addresses()
+                        .iter()
+                        .any(|claimed| same_endpoint(claimed, address))
+                })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_address() {
+        assert_eq!(parse_address("8432").unwrap(), "tcp:127.0.0.1:8432");
+        assert_eq!(parse_address("localhost:80").unwrap(), "tcp:localhost:80");
+        assert_eq!(parse_address("tcp:[::1]:9000").unwrap(), "tcp:[::1]:9000");
+        assert_eq!(
+            parse_address("/run/user/1000/lsp.sock").unwrap(),
+            "unix:/run/user/1000/lsp.sock"
+        );
+        assert_eq!(parse_address("unix:rel.sock").unwrap(), "unix:rel.sock");
+
+        assert!(parse_address("localhost").is_err());
+        assert!(parse_address(":80").is_err());
+        assert!(parse_address("host:99999").is_err());
+        assert!(parse_address("unix:").is_err());
+    }
+
+    #[test]
+    fn test_same_endpoint() {
+        assert!(same_endpoint("tcp:127.0.0.1:8080", "tcp:0.0.0.0:8080"));
+        assert!(same_endpoint("tcp:[::1]:9000", "tcp:localhost:9000"));
+        assert!(!same_endpoint("tcp:127.0.0.1:8080", "tcp:127.0.0.1:8081"));
+        assert!(same_endpoint("unix:/tmp/a.sock", "unix:/tmp/a.sock"));
+        assert!(!same_endpoint("unix:/tmp/a.sock", "unix:/tmp/b.sock"));
+        assert!(!same_endpoint("unix:/tmp/8080", "tcp:127.0.0.1:8080"));
+    }
+}
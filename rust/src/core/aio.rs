@@ -0,0 +1,313 @@
+//! Async versions of the lock, attach, process-wait, and event APIs, for
+//! tokio applications (the `async` feature).
+//!
+//! Waiting is what the blocking API spends its time on: for another
+//! process's flock, for a process to exit, for a server to change. Here each
+//! wait yields to the runtime instead of parking a thread, retrying locks on
+//! a timer and waiting on the same pidfd/inotify (or kqueue) descriptors the
+//! watcher uses through tokio's reactor. Once a lock is held, the
+//! read-modify-write itself is the same few small file operations as the
+//! blocking version, so nothing needs `spawn_blocking`.
+//!
+//! ```no_run
+//! # async fn example() -> anyhow::Result<()> {
+//! use sharedserver::core::aio;
+//!
+//! let refcount = aio::attach_client("chroma", std::process::id() as i32, None).await?;
+//! let mut events = aio::subscribe("chroma")?;
+//! loop {
+//!     println!("{:?}", events.next().await);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Needs a tokio runtime with the time and I/O drivers enabled.
+
+use super::events::{self, StateEvent, POLL_INTERVAL};
+use super::exit_notify::ExitNotifier;
+use super::handle::{self, AttachOptions, ServerHandle};
+use super::lockfile::{
+    self, clients_lockfile_path, lock_timeout, server_lock_exists, server_lockfile_path,
+    ClientsLock, LockUpdate, ServerLock,
+};
+use super::state::{state_from_locks, ServerState};
+use super::{is_process_alive, Liveness};
+use anyhow::{Context, Result};
+use nix::fcntl::FlockArg;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::future::Future;
+use std::os::fd::RawFd;
+use std::path::Path;
+use std::task::Poll;
+use std::time::{Duration, Instant};
+use tokio::io::unix::AsyncFd;
+use tokio::io::Interest;
+
+/// How often [`wait_for_exit`] checks on a process it can't be notified
+/// about.
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// [`lockfile::read_server_lock`], waiting for the lock asynchronously.
+pub async fn read_server_lock(name: &str) -> Result<ServerLock> {
+    let path = server_lockfile_path(name)?;
+    read_shared(&path).await
+}
+
+/// [`lockfile::read_clients_lock`], waiting for the lock asynchronously.
+pub async fn read_clients_lock(name: &str) -> Result<ClientsLock> {
+    let path = clients_lockfile_path(name)?;
+    read_shared(&path).await
+}
+
+/// [`lockfile::update_server_lock`], waiting for the lock asynchronously.
+pub async fn update_server_lock<F, R>(name: &str, update: F) -> Result<R>
+where
+    F: FnOnce(&mut ServerLock) -> Result<LockUpdate<R>>,
+{
+    let path = server_lockfile_path(name)?;
+    let updated = update_lock(&path, false, || None, update).await;
+    lockfile::server_lock_updated(name, updated)
+}
+
+/// [`lockfile::update_clients_lock`], waiting for the lock asynchronously.
+pub async fn update_clients_lock<F, R>(name: &str, update: F) -> Result<R>
+where
+    F: FnOnce(&mut ClientsLock) -> Result<LockUpdate<R>>,
+{
+    let path = clients_lockfile_path(name)?;
+    let updated = update_lock(&path, true, || Some(ClientsLock::new()), update).await;
+    lockfile::clients_lock_updated(name, updated)
+}
+
+/// [`get_server_state`](super::get_server_state), reading the locks
+/// asynchronously.
+pub async fn get_server_state(name: &str) -> Result<ServerState> {
+    if !server_lock_exists(name) {
+        return Ok(ServerState::Stopped);
+    }
+    // As in the blocking version, a lock that vanished or is unreadable means
+    // the server is gone.
+    let Ok(server_lock) = read_server_lock(name).await else {
+        return Ok(ServerState::Stopped);
+    };
+    let refcount = match server_lock.server_liveness() {
+        Liveness::Alive => read_clients_lock(name)
+            .await
+            .map(|clients| clients.refcount())
+            .unwrap_or(0),
+        _ => 0,
+    };
+    Ok(state_from_locks(&server_lock, || refcount))
+}
+
+/// [`handle::attach_client`], waiting for the lock asynchronously.
+pub async fn attach_client(name: &str, pid: i32, metadata: Option<String>) -> Result<u32> {
+    let refcount = update_clients_lock(name, |clients| {
+        Ok(handle::add_client(clients, pid, metadata))
+    })
+    .await
+    .context("Failed to increment refcount")?;
+    refcount.ok_or_else(|| handle::shutting_down(name))
+}
+
+/// [`handle::detach_client`], waiting for the lock asynchronously.
+pub async fn detach_client(name: &str, pid: i32) -> Result<u32> {
+    update_clients_lock(name, |clients| handle::remove_client(name, clients, pid))
+        .await
+        .with_context(|| format!("Failed to decrement refcount for '{}'", name))
+}
+
+/// [`ServerHandle::attach`], waiting for the locks asynchronously.
+///
+/// The handle is the ordinary one, so dropping it releases the reference as
+/// usual, which can briefly block on the clients lock; call
+/// [`detach_client`] with its name and PID first to avoid that.
+pub async fn attach(name: &str, opts: AttachOptions) -> Result<ServerHandle> {
+    handle::attachable(name, get_server_state(name).await?)?;
+    let pid = opts.pid.unwrap_or(std::process::id() as i32);
+    let refcount = attach_client(name, pid, opts.metadata.clone()).await?;
+    Ok(ServerHandle::attached(name, pid, refcount, opts.metadata))
+}
+
+/// Wait for process `pid` to exit. It needn't be a child of this process;
+/// one that has exited but not been reaped counts as exited.
+pub async fn wait_for_exit(pid: i32) {
+    let mut notifier = ExitNotifier::new();
+    let watched = notifier.watch(pid);
+    while is_process_alive(pid) {
+        if watched {
+            notified(&notifier, POLL_INTERVAL).await;
+        } else {
+            tokio::time::sleep(EXIT_POLL_INTERVAL).await;
+        }
+    }
+}
+
+/// An endless stream of `name`'s state changes, like
+/// [`events::subscribe`]'s, that waits asynchronously.
+pub struct Subscription {
+    inner: events::Subscription,
+}
+
+/// Start observing `name`. The server needn't be running: its start is
+/// reported like any other change.
+pub fn subscribe(name: &str) -> Result<Subscription> {
+    Ok(Subscription {
+        inner: events::subscribe(name)?,
+    })
+}
+
+impl Subscription {
+    /// The server's state as of the last event (or the subscription).
+    pub fn state(&self) -> ServerState {
+        self.inner.state()
+    }
+
+    /// The next change, once there is one.
+    pub async fn next(&mut self) -> StateEvent {
+        loop {
+            if let Some(event) = self.inner.pop() {
+                return event;
+            }
+            let notifier = self.inner.arm();
+            notified(notifier, POLL_INTERVAL).await;
+            let exited = notifier.wait(Duration::ZERO);
+            self.inner.refresh(exited);
+        }
+    }
+}
+
+/// Take a flock on `file` without blocking the runtime: retry with the same
+/// backoff and [`lock_timeout`] as the blocking API.
+async fn acquire(file: &File, path: &Path, arg: FlockArg) -> Result<()> {
+    let timeout = lock_timeout();
+    let deadline = Instant::now() + timeout;
+    let mut backoff = Duration::from_millis(1);
+    while !lockfile::try_flock(file, path, arg)? {
+        if Instant::now() >= deadline {
+            return Err(lockfile::lock_timed_out(file, path, timeout));
+        }
+        tokio::time::sleep(backoff.min(deadline.saturating_duration_since(Instant::now()))).await;
+        backoff = lockfile::next_backoff(backoff);
+    }
+    Ok(())
+}
+
+async fn read_shared<T>(path: &Path) -> Result<T>
+where
+    T: for<'de> Deserialize<'de>,
+{
+    let mut file = lockfile::open_shared(path)?;
+    acquire(&file, path, FlockArg::LockSharedNonblock).await?;
+    lockfile::read_json_recovering(&mut file, path)
+}
+
+async fn update_lock<T, F, R>(
+    path: &Path,
+    create: bool,
+    empty: impl FnOnce() -> Option<T>,
+    update: F,
+) -> Result<(R, Option<lockfile::Changed>)>
+where
+    T: Serialize + for<'de> Deserialize<'de>,
+    F: FnOnce(&mut T) -> Result<LockUpdate<R>>,
+{
+    let file = lockfile::open_exclusive(path, create)?;
+    acquire(&file, path, FlockArg::LockExclusiveNonblock).await?;
+    lockfile::journaled(file, path, |file| {
+        lockfile::update_locked(file, path, empty, update)
+    })
+}
+
+/// Wait until `notifier` has something to report (collect it with
+/// `wait(Duration::ZERO)`), or `timeout` elapses.
+async fn notified(notifier: &ExitNotifier, timeout: Duration) {
+    // Registered afresh on each wait, as the notifier's descriptors change
+    // between waits.
+    let fds: Vec<AsyncFd<RawFd>> = notifier
+        .fds()
+        .into_iter()
+        .filter_map(|fd| AsyncFd::with_interest(fd, Interest::READABLE).ok())
+        .collect();
+    let mut sleep = std::pin::pin!(tokio::time::sleep(timeout));
+    std::future::poll_fn(|cx| {
+        if fds.iter().any(|fd| fd.poll_read_ready(cx).is_ready()) {
+            return Poll::Ready(());
+        }
+        sleep.as_mut().poll(cx)
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_wait_for_exit() {
+        let mut child = Command::new("sleep").arg("0.3").spawn().unwrap();
+        let pid = child.id() as i32;
+        let started = Instant::now();
+        runtime().block_on(async {
+            tokio::time::timeout(Duration::from_secs(5), wait_for_exit(pid))
+                .await
+                .expect("exit not noticed");
+        });
+        assert!(started.elapsed() >= Duration::from_millis(250));
+        child.wait().unwrap();
+
+        // Already gone.
+        runtime().block_on(wait_for_exit(pid));
+    }
+
+    #[test]
+    fn test_update_lock_waits_without_blocking_the_runtime() {
+        let dir = std::env::temp_dir().join(format!("aio-lock-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("clients.json");
+        let holder = File::create(&path).unwrap();
+        nix::fcntl::flock(
+            std::os::fd::AsRawFd::as_raw_fd(&holder),
+            FlockArg::LockExclusive,
+        )
+        .unwrap();
+
+        // One thread: the holder only lets go if waiting for the lock leaves
+        // it free to run.
+        let updated = runtime().block_on(async {
+            let release = async {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                drop(holder);
+            };
+            let update = update_lock(
+                &path,
+                true,
+                || Some(ClientsLock::new()),
+                |lock: &mut ClientsLock| {
+                    lock.clients
+                        .insert(1, super::super::ClientInfo::new(1, None));
+                    Ok(LockUpdate::Write(lock.refcount()))
+                },
+            );
+            tokio::join!(release, update).1
+        });
+        assert_eq!(updated.unwrap().0, 1);
+        assert_eq!(
+            lockfile::read_backup::<ClientsLock>(&path)
+                .unwrap()
+                .refcount(),
+            1
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,130 @@
+//! Caps on how many servers run at once, so heavyweight servers sharing a
+//! small machine (a CI runner, say) don't exhaust its memory.
+//!
+//! `[defaults]` sets them: `max_running` for every server, and
+//! `max_running_tags` for the servers with a tag. A start that would go over
+//! a cap fails, or with `use --queue` waits for a server to stop. Starts take
+//! the start lock while they count, so two can't both take the last slot.
+
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use super::lockfile::{ensure_lockfile_dir, read_server_lock, servers_with};
+use super::state::{get_server_state, ServerState};
+
+/// The caps in force; none by default.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Capacity {
+    pub max_running: Option<usize>,
+    /// At most this many running servers with each tag.
+    pub per_tag: Vec<((String, String), usize)>,
+}
+
+impl Capacity {
+    pub fn is_unlimited(&self) -> bool {
+        self.max_running.is_none() && self.per_tag.is_empty()
+    }
+
+    /// Why a server with `tags` can't start while the `running` servers
+    /// (with theirs) are: the first cap it would go over. `None` if there is
+    /// room.
+    pub fn exceeded(
+        &self,
+        tags: &BTreeMap<String, String>,
+        running: &[(String, BTreeMap<String, String>)],
+    ) -> Option<String> {
+        let names = |servers: Vec<&str>| servers.join(", ");
+        if let Some(max) = self.max_running.filter(|&max| running.len() >= max) {
+            return Some(format!(
+                "{} server(s) are running ({}), as many as max_running ({}) allows",
+                running.len(),
+                names(running.iter().map(|(name, _)| name.as_str()).collect()),
+                max
+            ));
+        }
+        for ((key, value), max) in &self.per_tag {
+            if tags.get(key) != Some(value) {
+                continue;
+            }
+            let tagged: Vec<&str> = running
+                .iter()
+                .filter(|(_, tags)| tags.get(key) == Some(value))
+                .map(|(name, _)| name.as_str())
+                .collect();
+            if tagged.len() >= *max {
+                return Some(format!(
+                    "{} server(s) tagged {}={} are running ({}), as many as \
+                     max_running_tags allows ({})",
+                    tagged.len(),
+                    key,
+                    value,
+                    names(tagged),
+                    max
+                ));
+            }
+        }
+        None
+    }
+}
+
+/// The servers running now (in their grace periods too), other than
+/// `except`, with their tags.
+pub fn running_servers(except: &str) -> Result<Vec<(String, BTreeMap<String, String>)>> {
+    Ok(servers_with(&["server.json"])?
+        .into_iter()
+        .filter(|name| name != except)
+        .filter(|name| get_server_state(name).is_ok_and(|state| state != ServerState::Stopped))
+        .map(|name| {
+            let tags = read_server_lock(&name)
+                .map(|lock| lock.tags)
+                .unwrap_or_default();
+            (name, tags)
+        })
+        .collect())
+}
+
+/// The lockfile a start holds while it checks the caps and its addresses
+/// against the running servers and writes its own lock.
+pub fn start_lock_path() -> Result<PathBuf> {
+    Ok(ensure_lockfile_dir()?.join("start.lock"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exceeded_counts_all_and_tagged_servers() {
+        let tags = |pairs: &[(&str, &str)]| -> BTreeMap<String, String> {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+        let running = vec![
+            ("db1".to_string(), tags(&[("kind", "db")])),
+            ("web".to_string(), tags(&[("kind", "web")])),
+        ];
+        let capacity = Capacity {
+            max_running: Some(3),
+            per_tag: vec![(("kind".to_string(), "db".to_string()), 1)],
+        };
+
+        let full = capacity
+            .exceeded(&tags(&[("kind", "db")]), &running)
+            .unwrap();
+        assert!(
+            full.contains("tagged kind=db") && full.contains("(db1)"),
+            "{}",
+            full
+        );
+        assert_eq!(capacity.exceeded(&tags(&[("kind", "web")]), &running), None);
+
+        let mut more = running.clone();
+        more.push(("cache".to_string(), tags(&[])));
+        let full = capacity.exceeded(&tags(&[]), &more).unwrap();
+        assert!(full.contains("max_running (3)"), "{}", full);
+        assert!(Capacity::default().exceeded(&tags(&[]), &more).is_none());
+    }
+}
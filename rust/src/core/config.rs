@@ -0,0 +1,979 @@
+//! The config file: servers defined by name, so `use <name>` needs no
+//! command.
+//!
+//! ```toml
+//! [servers.db]
+//! command = ["postgres", "-D", "data"]
+//! cwd = "services/db"          # relative to this file
+//! env = { PGPORT = "5433" }
+//! grace_period = "30m"
+//! log_file = "db.log"          # relative to cwd
+//! groups = ["backend"]         # for `up backend` / `down backend`
+//! depends_on = ["cache"]       # started (and ready) first
+//! restart = "on-failure"
+//! health = { tcp = "127.0.0.1:5433", interval = "10s" }
+//! ```
+//!
+//! Two files are read and merged: the user's
+//! (`$XDG_CONFIG_HOME/sharedserver/config.toml`, by default under
+//! `~/.config`), then the nearest `sharedserver.toml` in the working
+//! directory or one of its parents, which a project can check in. A project's
+//! definition of a server replaces the user's. `SHAREDSERVER_CONFIG` names a
+//! single file to read instead of both.
+//!
+//! A profile adjusts servers for one setting, such as CI. Selected with
+//! `--profile` or `SHAREDSERVER_PROFILE`, its settings override theirs:
+//!
+//! ```toml
+//! [profiles.ci.servers.db]
+//! command = ["postgres", "-D", "/tmp/ci-data"]
+//! grace_period = "none"
+//! ```
+//!
+//! A server's command, env values, log file, and health probe can use
+//! variables, expanded
+//! when it is started: `{name}`, `{port}` (a free port), `{lockdir}`,
+//! `{home}`, and `${ENVVAR}`. Other braces are left alone.
+//!
+//! `[aliases]` gives common invocations short names, expanded by the CLI in
+//! place of a subcommand: `sharedserver rust-lsp` below runs `sharedserver
+//! use lsp-rust --grace-period 1h`.
+//!
+//! ```toml
+//! [aliases]
+//! rust-lsp = "use lsp-rust --grace-period 1h"
+//! ```
+//!
+//! `[defaults]` sets fleet-wide policy, for every server and command:
+//!
+//! ```toml
+//! [defaults]
+//! lockdir = "/var/tmp/sharedserver"  # when SHAREDSERVER_LOCKDIR isn't set
+//! poll_interval = "1s"
+//! shutdown_timeout = "30s"
+//! color = "never"
+//! grace_period = "15m"
+//! max_running = 4                    # servers running at once
+//! max_running_tags = { "kind=db" = 1 }
+//! ```
+
+use super::capacity::Capacity;
+use super::duration::parse_duration;
+use super::error::ErrorKind;
+use super::filter::parse_tag;
+use super::probe::{HealthCheck, HealthProbe};
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// The file a project checks in, found in the working directory or above.
+pub const PROJECT_FILE: &str = "sharedserver.toml";
+
+/// Names the one config file to read, replacing the user's and the
+/// project's.
+pub const CONFIG_ENV: &str = "SHAREDSERVER_CONFIG";
+
+/// Names the profile to apply (`--profile` sets it).
+pub const PROFILE_ENV: &str = "SHAREDSERVER_PROFILE";
+
+/// Servers defined across the config files read.
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    /// With the selected profile, if any, applied.
+    pub servers: BTreeMap<String, ServerConfig>,
+    pub profiles: BTreeMap<String, Profile>,
+    /// The profile applied to `servers`.
+    pub profile: Option<String>,
+    pub defaults: Defaults,
+    pub aliases: BTreeMap<String, Alias>,
+    /// The files read, in the order they were merged.
+    pub files: Vec<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    #[serde(default)]
+    servers: BTreeMap<String, ServerConfig>,
+    #[serde(default)]
+    profiles: BTreeMap<String, Profile>,
+    #[serde(default)]
+    defaults: Defaults,
+    #[serde(default)]
+    aliases: BTreeMap<String, Alias>,
+}
+
+/// `[aliases]`: what a name stands for on the command line, as one string
+/// split on whitespace or a list of arguments.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum Alias {
+    Words(String),
+    Args(Vec<String>),
+}
+
+impl Alias {
+    /// The arguments to put in place of the alias.
+    pub fn args(&self) -> Vec<String> {
+        match self {
+            Alias::Words(words) => words.split_whitespace().map(str::to_string).collect(),
+            Alias::Args(args) => args.clone(),
+        }
+    }
+}
+
+/// `[defaults]`: settings for every server and command, where neither the
+/// command line nor a server's definition says otherwise.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Defaults {
+    /// The lock directory, when `SHAREDSERVER_LOCKDIR` isn't set; relative to
+    /// the file it is set in.
+    pub lockdir: Option<PathBuf>,
+    /// How often watchers poll their clients and grace timer [default: 500ms].
+    pub poll_interval: Option<String>,
+    /// How long a server has to exit once asked to: before a watcher kills it
+    /// [default: 5s], and `stop`'s and `down`'s `--timeout` [default: 10s].
+    pub shutdown_timeout: Option<String>,
+    /// `auto`, `always`, or `never`, when `--color` isn't given.
+    pub color: Option<String>,
+    /// The grace period of servers that don't set one [default: 5m].
+    pub grace_period: Option<String>,
+    /// How many servers may run at once [default: no limit].
+    pub max_running: Option<usize>,
+    /// How many servers with each `KEY=VALUE` tag may run at once.
+    #[serde(default)]
+    pub max_running_tags: BTreeMap<String, usize>,
+}
+
+impl Defaults {
+    /// The settings that are set in `other` replace this one's.
+    fn overlay(&mut self, other: Defaults) {
+        self.lockdir = other.lockdir.or(self.lockdir.take());
+        self.poll_interval = other.poll_interval.or(self.poll_interval.take());
+        self.shutdown_timeout = other.shutdown_timeout.or(self.shutdown_timeout.take());
+        self.color = other.color.or(self.color.take());
+        self.grace_period = other.grace_period.or(self.grace_period.take());
+        self.max_running = other.max_running.or(self.max_running.take());
+        self.max_running_tags.extend(other.max_running_tags);
+    }
+
+    /// Reject the values that would otherwise only fail once used, which for
+    /// a watcher's is long after the command that read them has exited.
+    fn validate(&self) -> Result<()> {
+        for (key, value) in [
+            ("poll_interval", &self.poll_interval),
+            ("shutdown_timeout", &self.shutdown_timeout),
+        ] {
+            if let Some(value) = value {
+                parse_duration(value)
+                    .with_context(|| format!("Invalid defaults.{} '{}'", key, value))?;
+            }
+        }
+        if let Some(color) = self
+            .color
+            .as_deref()
+            .filter(|color| !matches!(*color, "auto" | "always" | "never"))
+        {
+            bail!(
+                "Invalid defaults.color '{}': expected auto, always, or never",
+                color
+            );
+        }
+        for tag in self.max_running_tags.keys() {
+            parse_tag(tag).context("Invalid defaults.max_running_tags")?;
+        }
+        Ok(())
+    }
+
+    /// The caps on how many servers may run at once.
+    pub fn capacity(&self) -> Capacity {
+        Capacity {
+            max_running: self.max_running,
+            per_tag: self
+                .max_running_tags
+                .iter()
+                .filter_map(|(tag, &max)| Some((parse_tag(tag).ok()?, max)))
+                .collect(),
+        }
+    }
+
+    pub fn poll_interval(&self) -> Option<Duration> {
+        parse_duration(self.poll_interval.as_deref()?).ok()
+    }
+
+    pub fn shutdown_timeout(&self) -> Option<Duration> {
+        parse_duration(self.shutdown_timeout.as_deref()?).ok()
+    }
+}
+
+/// `[profiles.<name>]`: settings overriding the servers' when the profile is
+/// selected. A server only a profile defines exists only in that profile.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    #[serde(default)]
+    pub servers: BTreeMap<String, ServerConfig>,
+}
+
+/// `[servers.<name>]`: how to start the server. Everything is optional; the
+/// command line's flags override what is set here.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServerConfig {
+    pub command: Option<CommandLine>,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    pub grace_period: Option<String>,
+    /// Where the server runs, relative to the file it is defined in
+    /// [default: wherever `use` is run].
+    pub cwd: Option<PathBuf>,
+    pub log_file: Option<String>,
+    pub log_timestamps: Option<bool>,
+    /// Groups `up` and `down` can be limited to.
+    #[serde(default)]
+    pub groups: Vec<String>,
+    /// Servers to start first, and wait for until their health checks pass.
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+    /// Restart policy: never, on-failure, or always.
+    pub restart: Option<String>,
+    pub health: Option<HealthConfig>,
+    /// Tags to start it with, as with `--tag`.
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+    /// The file the server is defined in.
+    #[serde(skip)]
+    pub source: PathBuf,
+}
+
+/// `health`: a health check, like the `--health-*` flags. Exactly one of
+/// `cmd`, `http`, and `tcp` says what to probe.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HealthConfig {
+    pub cmd: Option<String>,
+    pub http: Option<String>,
+    pub tcp: Option<String>,
+    pub expect_status: Option<u16>,
+    /// [default: 30s]
+    pub interval: Option<String>,
+    /// [default: 5s]
+    pub timeout: Option<String>,
+    /// [default: 3]
+    pub retries: Option<u32>,
+    pub restart: Option<bool>,
+}
+
+impl HealthConfig {
+    /// The check this describes, with its durations validated.
+    pub fn to_check(&self) -> Result<HealthCheck> {
+        let probe = match (&self.cmd, &self.http, &self.tcp) {
+            (Some(command), None, None) => HealthProbe::Command {
+                command: command.clone(),
+            },
+            (None, Some(url), None) => HealthProbe::Http {
+                url: url.clone(),
+                expected_status: self.expect_status,
+            },
+            (None, None, Some(address)) => HealthProbe::Tcp {
+                address: address.clone(),
+            },
+            _ => bail!("A health check needs exactly one of cmd, http, and tcp"),
+        };
+        let check = HealthCheck {
+            probe,
+            interval: self.interval.clone().unwrap_or_else(|| "30s".to_string()),
+            timeout: self.timeout.clone().unwrap_or_else(|| "5s".to_string()),
+            retries: self.retries.unwrap_or(3),
+            restart: self.restart.unwrap_or(false),
+        };
+        check.validate()?;
+        Ok(check)
+    }
+}
+
+/// A server command: a list of arguments, or one string run by the shell.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum CommandLine {
+    Shell(String),
+    Args(Vec<String>),
+}
+
+impl CommandLine {
+    /// As the command line's words after `--`.
+    pub fn to_vec(&self) -> Vec<String> {
+        match self {
+            CommandLine::Shell(command) => vec![command.clone()],
+            CommandLine::Args(args) => args.clone(),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// `cwd`, resolved against the directory of the file it was set in.
+    pub fn cwd(&self) -> Option<PathBuf> {
+        let cwd = self.cwd.as_ref()?;
+        Some(match self.source.parent() {
+            Some(dir) if cwd.is_relative() => dir.join(cwd),
+            _ => cwd.clone(),
+        })
+    }
+
+    /// Override this server's settings with those `other` sets: its env
+    /// variables are added, and anything else it sets replaces this one's.
+    pub fn overlay(&mut self, other: &ServerConfig) {
+        if other.command.is_some() {
+            self.command = other.command.clone();
+        }
+        self.env
+            .extend(other.env.iter().map(|(k, v)| (k.clone(), v.clone())));
+        if other.grace_period.is_some() {
+            self.grace_period = other.grace_period.clone();
+        }
+        // Resolved now, since `other` may be from another file.
+        if let Some(cwd) = other.cwd() {
+            self.cwd = Some(cwd);
+        }
+        if other.log_file.is_some() {
+            self.log_file = other.log_file.clone();
+        }
+        if other.log_timestamps.is_some() {
+            self.log_timestamps = other.log_timestamps;
+        }
+        if !other.groups.is_empty() {
+            self.groups = other.groups.clone();
+        }
+        if !other.depends_on.is_empty() {
+            self.depends_on = other.depends_on.clone();
+        }
+        if other.restart.is_some() {
+            self.restart = other.restart.clone();
+        }
+        if other.health.is_some() {
+            self.health = other.health.clone();
+        }
+        self.tags
+            .extend(other.tags.iter().map(|(k, v)| (k.clone(), v.clone())));
+        if self.source.as_os_str().is_empty() {
+            self.source = other.source.clone();
+        }
+    }
+
+    /// This server with the variables in its command, env values, log file,
+    /// and health probe expanded (see [`expand`]).
+    pub fn expand(
+        &self,
+        lookup: &mut dyn FnMut(&str) -> Result<Option<String>>,
+    ) -> Result<ServerConfig> {
+        let mut server = self.clone();
+        server.command = match &self.command {
+            Some(CommandLine::Shell(command)) => Some(CommandLine::Shell(expand(command, lookup)?)),
+            Some(CommandLine::Args(args)) => Some(CommandLine::Args(
+                args.iter()
+                    .map(|arg| expand(arg, lookup))
+                    .collect::<Result<_>>()?,
+            )),
+            None => None,
+        };
+        for value in server.env.values_mut() {
+            *value = expand(value, lookup)?;
+        }
+        if let Some(log_file) = &mut server.log_file {
+            *log_file = expand(log_file, lookup)?;
+        }
+        if let Some(health) = &mut server.health {
+            for probe in [&mut health.cmd, &mut health.http, &mut health.tcp]
+                .into_iter()
+                .flatten()
+            {
+                *probe = expand(probe, lookup)?;
+            }
+        }
+        Ok(server)
+    }
+
+    /// `env` as `KEY=VALUE` strings, like `--env`.
+    pub fn env_vars(&self) -> Vec<String> {
+        self.env
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect()
+    }
+}
+
+impl Config {
+    /// Read the config files that apply here, and apply the selected
+    /// profile. Missing files are no config, but one that can't be read or
+    /// parsed is an error, as is selecting a profile it doesn't define.
+    pub fn load() -> Result<Config> {
+        match std::env::var_os(CONFIG_ENV).filter(|path| !path.is_empty()) {
+            Some(path) => Config::read(Path::new(&path))?.with_profile(),
+            None => Config::load_from(&Config::paths()),
+        }
+    }
+
+    /// Like [`Config::load`], but from `paths` (as [`Config::paths`] gave
+    /// them, perhaps in another process and directory), skipping any that
+    /// don't exist.
+    pub fn load_from(paths: &[PathBuf]) -> Result<Config> {
+        let mut config = Config::default();
+        for path in paths {
+            if path.is_file() {
+                config.merge(Config::read(path)?);
+            }
+        }
+        config.with_profile()
+    }
+
+    /// Apply the profile `SHAREDSERVER_PROFILE` selects, if any.
+    fn with_profile(mut self) -> Result<Config> {
+        let profile = std::env::var(PROFILE_ENV).ok().filter(|p| !p.is_empty());
+        // With no config at all, there is nothing for the profile to adjust.
+        if let Some(profile) = profile.filter(|_| !self.files.is_empty()) {
+            self.apply_profile(&profile)?;
+        }
+        Ok(self)
+    }
+
+    /// The files [`Config::load`] reads here, whether or not they exist yet.
+    pub fn paths() -> Vec<PathBuf> {
+        Config::paths_from(&std::env::current_dir().unwrap_or_default())
+    }
+
+    /// The files [`Config::load`] would read in `dir`.
+    pub fn paths_from(dir: &Path) -> Vec<PathBuf> {
+        if let Some(path) = std::env::var_os(CONFIG_ENV).filter(|path| !path.is_empty()) {
+            return vec![PathBuf::from(path)];
+        }
+        let project = find_project_file(dir);
+        user_config_path().into_iter().chain(project).collect()
+    }
+
+    /// Read and parse one file.
+    pub fn read(path: &Path) -> Result<Config> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        Config::parse(&text, path)
+    }
+
+    /// Parse `text`, read from `path`.
+    pub fn parse(text: &str, path: &Path) -> Result<Config> {
+        let file: ConfigFile = toml::from_str(text)
+            .with_context(|| format!("Invalid config file {}", path.display()))?;
+        let from_file = |servers: BTreeMap<String, ServerConfig>| {
+            servers
+                .into_iter()
+                .map(|(name, server)| {
+                    let server = ServerConfig {
+                        source: path.to_path_buf(),
+                        ..server
+                    };
+                    (name, server)
+                })
+                .collect()
+        };
+        let profiles = file
+            .profiles
+            .into_iter()
+            .map(|(name, profile)| {
+                let profile = Profile {
+                    servers: from_file(profile.servers),
+                };
+                (name, profile)
+            })
+            .collect();
+        let mut defaults = file.defaults;
+        defaults
+            .validate()
+            .with_context(|| format!("Invalid config file {}", path.display()))?;
+        if let (Some(lockdir), Some(dir)) = (&mut defaults.lockdir, path.parent()) {
+            *lockdir = dir.join(&*lockdir);
+        }
+        Ok(Config {
+            servers: from_file(file.servers),
+            profiles,
+            profile: None,
+            defaults,
+            aliases: file.aliases,
+            files: vec![path.to_path_buf()],
+        })
+    }
+
+    /// Layer `other` over this config: its servers replace ones of the same
+    /// name, as do the servers of its profiles within the profile, its
+    /// aliases, and its defaults.
+    pub fn merge(&mut self, other: Config) {
+        self.servers.extend(other.servers);
+        for (name, profile) in other.profiles {
+            self.profiles
+                .entry(name)
+                .or_default()
+                .servers
+                .extend(profile.servers);
+        }
+        self.defaults.overlay(other.defaults);
+        self.aliases.extend(other.aliases);
+        self.files.extend(other.files);
+    }
+
+    /// Override the servers' settings with `profile`'s.
+    pub fn apply_profile(&mut self, profile: &str) -> Result<()> {
+        let Some(overrides) = self.profiles.get(profile) else {
+            return Err(ErrorKind::InvalidArgs.error(format!(
+                "No profile '{}' in the config (profiles: {})",
+                profile,
+                self.profiles
+                    .keys()
+                    .map(String::as_str)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )));
+        };
+        for (name, server) in &overrides.servers {
+            self.servers
+                .entry(name.clone())
+                .or_default()
+                .overlay(server);
+        }
+        self.profile = Some(profile.to_string());
+        Ok(())
+    }
+
+    pub fn server(&self, name: &str) -> Option<&ServerConfig> {
+        self.servers.get(name)
+    }
+
+    /// The servers in `group`, or all of them, by name. Fails if that is
+    /// none.
+    pub fn select(&self, group: Option<&str>) -> Result<Vec<(&str, &ServerConfig)>> {
+        let selected: Vec<_> = self
+            .servers
+            .iter()
+            .filter(|(_, server)| {
+                group.is_none_or(|group| server.groups.iter().any(|g| g == group))
+            })
+            .map(|(name, server)| (name.as_str(), server))
+            .collect();
+        if selected.is_empty() {
+            return Err(ErrorKind::InvalidArgs.error(match group {
+                Some(group) => format!("No servers in group '{}' in the config", group),
+                None => "No servers are defined in the config".to_string(),
+            }));
+        }
+        Ok(selected)
+    }
+
+    /// `names` and the servers they depend on, directly or not, each after
+    /// its dependencies; otherwise in the order given. A name the config
+    /// doesn't define has no dependencies. Fails on a dependency that isn't
+    /// defined, or a cycle.
+    pub fn start_order<'a>(&'a self, names: &[&'a str]) -> Result<Vec<&'a str>> {
+        let mut order = Vec::new();
+        let mut path = Vec::new();
+        for name in names {
+            self.visit(name, &mut path, &mut order)?;
+        }
+        Ok(order)
+    }
+
+    /// Add `name` to `order` after its dependencies. `path` is the chain of
+    /// dependents that led here.
+    fn visit<'a>(
+        &'a self,
+        name: &'a str,
+        path: &mut Vec<&'a str>,
+        order: &mut Vec<&'a str>,
+    ) -> Result<()> {
+        if order.contains(&name) {
+            return Ok(());
+        }
+        if let Some(start) = path.iter().position(|&seen| seen == name) {
+            let mut cycle = path[start..].to_vec();
+            cycle.push(name);
+            return Err(
+                ErrorKind::InvalidArgs.error(format!("Dependency cycle: {}", cycle.join(" -> ")))
+            );
+        }
+        path.push(name);
+        for dependency in self
+            .servers
+            .get(name)
+            .into_iter()
+            .flat_map(|s| &s.depends_on)
+        {
+            if !self.servers.contains_key(dependency) {
+                return Err(ErrorKind::InvalidArgs.error(format!(
+                    "Server '{}' depends on '{}', which the config doesn't define",
+                    name, dependency
+                )));
+            }
+            self.visit(dependency, path, order)?;
+        }
+        path.pop();
+        order.push(name);
+        Ok(())
+    }
+}
+
+/// Expand the variables in `text`: `${VAR}` to the environment variable,
+/// which must be set, and `{var}` to what `lookup` gives for it. A `{...}`
+/// that isn't a variable name, or one `lookup` doesn't know, is left as it
+/// is, so a shell command's braces survive.
+pub fn expand(
+    text: &str,
+    lookup: &mut dyn FnMut(&str) -> Result<Option<String>>,
+) -> Result<String> {
+    let is_name = |name: &str| {
+        name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    };
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(open) = rest.find('{') {
+        let Some(len) = rest[open..].find('}') else {
+            break;
+        };
+        let name = &rest[open + 1..open + len];
+        let env = rest[..open].ends_with('$');
+        let value = if !is_name(name) {
+            None
+        } else if env {
+            Some(std::env::var(name).map_err(|_| {
+                ErrorKind::InvalidArgs.error(format!(
+                    "${{{}}} in the config, but {} isn't set",
+                    name, name
+                ))
+            })?)
+        } else {
+            lookup(name)?
+        };
+        match value {
+            Some(value) => {
+                let start = if env { open - 1 } else { open };
+                expanded.push_str(&rest[..start]);
+                expanded.push_str(&value);
+            }
+            None => expanded.push_str(&rest[..=open + len]),
+        }
+        rest = &rest[open + len + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// `$XDG_CONFIG_HOME/sharedserver/config.toml`, or under `~/.config`.
+fn user_config_path() -> Option<PathBuf> {
+    let dir = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(dir.join("sharedserver").join("config.toml"))
+}
+
+/// The nearest `sharedserver.toml` in `dir` or one of its parents.
+fn find_project_file(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|dir| dir.join(PROJECT_FILE))
+        .find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_servers() {
+        let config = Config::parse(
+            r#"
+            [servers.db]
+            command = ["postgres", "-D", "data"]
+            cwd = "services/db"
+            env = { PGPORT = "5433", LANG = "C" }
+            grace_period = "30m"
+            log_file = "db.log"
+            tags = { project = "app", kind = "db" }
+
+            [servers.web]
+            command = "python -m http.server"
+            cwd = "/srv/www"
+            "#,
+            Path::new("/work/app/sharedserver.toml"),
+        )
+        .unwrap();
+
+        let db = config.server("db").unwrap();
+        assert_eq!(
+            db.command.as_ref().unwrap().to_vec(),
+            ["postgres", "-D", "data"]
+        );
+        assert_eq!(db.cwd(), Some(PathBuf::from("/work/app/services/db")));
+        assert_eq!(db.env_vars(), ["LANG=C", "PGPORT=5433"]);
+        assert_eq!(db.grace_period.as_deref(), Some("30m"));
+        assert_eq!(db.log_timestamps, None);
+        assert_eq!(db.tags.get("kind").map(String::as_str), Some("db"));
+
+        let web = config.server("web").unwrap();
+        assert_eq!(
+            web.command.as_ref().unwrap().to_vec(),
+            ["python -m http.server"]
+        );
+        assert_eq!(web.cwd(), Some(PathBuf::from("/srv/www")));
+        assert!(config.server("cache").is_none());
+    }
+
+    #[test]
+    fn test_select_group() {
+        let config = Config::parse(
+            "[servers.db]\ngroups = [\"backend\"]\n\
+             [servers.api]\ngroups = [\"backend\", \"web\"]\n\
+             [servers.docs]\n",
+            Path::new("/work/sharedserver.toml"),
+        )
+        .unwrap();
+        let names = |group| -> Vec<&str> {
+            config
+                .select(group)
+                .unwrap()
+                .into_iter()
+                .map(|(name, _)| name)
+                .collect()
+        };
+        assert_eq!(names(None), ["api", "db", "docs"]);
+        assert_eq!(names(Some("backend")), ["api", "db"]);
+        assert_eq!(names(Some("web")), ["api"]);
+        assert!(config.select(Some("cache")).is_err());
+        assert!(Config::default().select(None).is_err());
+    }
+
+    #[test]
+    fn test_start_order_follows_dependencies() {
+        let config = Config::parse(
+            "[servers.web]\ndepends_on = [\"api\", \"cache\"]\n\
+             [servers.api]\ndepends_on = [\"db\"]\n\
+             [servers.db]\n[servers.cache]\n\
+             [servers.a]\ndepends_on = [\"b\"]\n[servers.b]\ndepends_on = [\"a\"]\n\
+             [servers.orphan]\ndepends_on = [\"missing\"]\n",
+            Path::new("/work/sharedserver.toml"),
+        )
+        .unwrap();
+        assert_eq!(
+            config.start_order(&["web"]).unwrap(),
+            ["db", "api", "cache", "web"]
+        );
+        assert_eq!(
+            config.start_order(&["cache", "api", "other"]).unwrap(),
+            ["cache", "db", "api", "other"]
+        );
+        let cycle = config.start_order(&["a"]).unwrap_err().to_string();
+        assert!(cycle.contains("a -> b -> a"), "{}", cycle);
+        assert!(config.start_order(&["orphan"]).is_err());
+    }
+
+    #[test]
+    fn test_profile_overrides_servers() {
+        let mut config = Config::parse(
+            r#"
+            [servers.db]
+            command = "postgres"
+            env = { PGPORT = "5433", LANG = "C" }
+            grace_period = "30m"
+
+            [profiles.ci.servers.db]
+            env = { PGPORT = "6543" }
+            grace_period = "none"
+            cwd = "ci"
+
+            [profiles.ci.servers.mock]
+            command = "mock-api"
+            "#,
+            Path::new("/work/sharedserver.toml"),
+        )
+        .unwrap();
+        config.apply_profile("ci").unwrap();
+
+        let db = config.server("db").unwrap();
+        assert_eq!(db.command, Some(CommandLine::Shell("postgres".into())));
+        assert_eq!(db.env_vars(), ["LANG=C", "PGPORT=6543"]);
+        assert_eq!(db.grace_period.as_deref(), Some("none"));
+        assert_eq!(db.cwd(), Some(PathBuf::from("/work/ci")));
+        let mock = config.server("mock").unwrap();
+        assert_eq!(mock.source, Path::new("/work/sharedserver.toml"));
+        assert_eq!(config.profile.as_deref(), Some("ci"));
+
+        let err = config.apply_profile("dev").unwrap_err();
+        assert!(format!("{}", err).contains("profiles: ci"), "{}", err);
+    }
+
+    #[test]
+    fn test_invalid_config_names_the_file() {
+        let err = Config::parse(
+            "[servers.db]\ngrace = \"5m\"\n",
+            Path::new("/work/sharedserver.toml"),
+        )
+        .unwrap_err();
+        let message = format!("{:#}", err);
+        assert!(message.contains("/work/sharedserver.toml"), "{}", message);
+        assert!(message.contains("grace"), "{}", message);
+    }
+
+    #[test]
+    fn test_merge_replaces_servers() {
+        let mut config = Config::parse(
+            "[servers.db]\ngrace_period = \"1h\"\n[servers.cache]\n",
+            Path::new("/home/me/.config/sharedserver/config.toml"),
+        )
+        .unwrap();
+        config.merge(
+            Config::parse(
+                "[servers.db]\ncommand = \"postgres\"\n",
+                Path::new("/work/sharedserver.toml"),
+            )
+            .unwrap(),
+        );
+        let db = config.server("db").unwrap();
+        assert_eq!(db.grace_period, None);
+        assert_eq!(db.source, Path::new("/work/sharedserver.toml"));
+        assert!(config.server("cache").is_some());
+        assert_eq!(config.files.len(), 2);
+    }
+
+    #[test]
+    fn test_expand_variables() {
+        std::env::set_var("SHAREDSERVER_TEST_EXPAND", "value");
+        let mut lookup = |var: &str| {
+            Ok(match var {
+                "name" => Some("db".to_string()),
+                "port" => Some("5433".to_string()),
+                _ => None,
+            })
+        };
+        assert_eq!(
+            expand("{name}-{port}:${SHAREDSERVER_TEST_EXPAND}", &mut lookup).unwrap(),
+            "db-5433:value"
+        );
+        // Braces that aren't variables are left for the shell.
+        assert_eq!(
+            expand(
+                "for i in {1..3}; do echo {other} ${i:-x}; done {",
+                &mut lookup
+            )
+            .unwrap(),
+            "for i in {1..3}; do echo {other} ${i:-x}; done {"
+        );
+        let err = expand("${SHAREDSERVER_TEST_UNSET}", &mut lookup).unwrap_err();
+        assert!(format!("{}", err).contains("isn't set"), "{}", err);
+
+        let config = Config::parse(
+            r#"
+            [servers.db]
+            command = ["postgres", "-p", "{port}"]
+            env = { PGDATA = "/data/{name}" }
+            log_file = "{name}.log"
+            "#,
+            Path::new("/work/sharedserver.toml"),
+        )
+        .unwrap();
+        let db = config.server("db").unwrap().expand(&mut lookup).unwrap();
+        assert_eq!(
+            db.command.as_ref().unwrap().to_vec(),
+            ["postgres", "-p", "5433"]
+        );
+        assert_eq!(db.env_vars(), ["PGDATA=/data/db"]);
+        assert_eq!(db.log_file.as_deref(), Some("db.log"));
+    }
+
+    #[test]
+    fn test_health_config() {
+        let config = Config::parse(
+            r#"
+            [servers.db]
+            restart = "on-failure"
+            health = { tcp = "127.0.0.1:{port}", interval = "10s" }
+
+            [servers.web.health]
+            http = "http://localhost/"
+            cmd = "true"
+            "#,
+            Path::new("/work/sharedserver.toml"),
+        )
+        .unwrap();
+        let db = config.server("db").unwrap();
+        assert_eq!(db.restart.as_deref(), Some("on-failure"));
+        let mut lookup = |_: &str| Ok(Some("5433".to_string()));
+        let check = db.expand(&mut lookup).unwrap().health.unwrap().to_check();
+        let check = check.unwrap();
+        assert_eq!(
+            check.probe,
+            HealthProbe::Tcp {
+                address: "127.0.0.1:5433".to_string()
+            }
+        );
+        assert_eq!((check.interval.as_str(), check.retries), ("10s", 3));
+
+        let web = config.server("web").unwrap().health.as_ref().unwrap();
+        assert!(web.to_check().is_err());
+    }
+
+    #[test]
+    fn test_aliases() {
+        let mut config = Config::parse(
+            "[aliases]\nrust-lsp = \"use lsp-rust --grace-period 1h\"\nweb = \"up web\"\n",
+            Path::new("/home/me/.config/sharedserver/config.toml"),
+        )
+        .unwrap();
+        config.merge(
+            Config::parse(
+                "[aliases]\nweb = [\"up\", \"web frontend\"]\n",
+                Path::new("/work/sharedserver.toml"),
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            config.aliases["rust-lsp"].args(),
+            ["use", "lsp-rust", "--grace-period", "1h"]
+        );
+        assert_eq!(config.aliases["web"].args(), ["up", "web frontend"]);
+    }
+
+    #[test]
+    fn test_merge_defaults() {
+        let mut config = Config::parse(
+            "[defaults]\nlockdir = \"locks\"\ngrace_period = \"1h\"\ncolor = \"never\"\n",
+            Path::new("/home/me/.config/sharedserver/config.toml"),
+        )
+        .unwrap();
+        config.merge(
+            Config::parse(
+                "[defaults]\ngrace_period = \"10m\"\npoll_interval = \"2s\"\n",
+                Path::new("/work/sharedserver.toml"),
+            )
+            .unwrap(),
+        );
+        let defaults = &config.defaults;
+        assert_eq!(
+            defaults.lockdir.as_deref(),
+            Some(Path::new("/home/me/.config/sharedserver/locks"))
+        );
+        assert_eq!(defaults.grace_period.as_deref(), Some("10m"));
+        assert_eq!(defaults.color.as_deref(), Some("never"));
+        assert_eq!(defaults.poll_interval(), Some(Duration::from_secs(2)));
+        assert_eq!(defaults.shutdown_timeout(), None);
+    }
+
+    #[test]
+    fn test_invalid_defaults_are_rejected() {
+        for text in [
+            "[defaults]\npoll_interval = \"often\"\n",
+            "[defaults]\ncolor = \"sometimes\"\n",
+        ] {
+            let err = Config::parse(text, Path::new("/work/sharedserver.toml")).unwrap_err();
+            let message = format!("{:#}", err);
+            assert!(message.contains("/work/sharedserver.toml"), "{}", message);
+            assert!(message.contains("defaults."), "{}", message);
+        }
+    }
+}
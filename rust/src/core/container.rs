@@ -0,0 +1,293 @@
+//! Container backends (`--backend docker|podman --image IMAGE`): "the server"
+//! is a container rather than a local process.
+//!
+//! The server process is a foreground `docker run --rm`, so the watcher
+//! supervises, reaps, and restarts it exactly like any other server, and
+//! refcounting and grace are unchanged. The container's ID is recorded in the
+//! lock once the runtime writes it, and stopping or killing the server goes
+//! through the runtime (`docker stop`/`docker kill`), since signalling the
+//! client alone could leave the container running.
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// The file in the server's directory the runtime writes the container ID
+/// to (`--cidfile`).
+pub const CIDFILE: &str = "container.id";
+
+/// What a server runs as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Backend {
+    /// A local process tree (the historical behaviour).
+    #[default]
+    Process,
+    Docker,
+    Podman,
+}
+
+impl Backend {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Backend::Process => "process",
+            Backend::Docker => "docker",
+            Backend::Podman => "podman",
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Backend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "process" => Ok(Backend::Process),
+            "docker" => Ok(Backend::Docker),
+            "podman" => Ok(Backend::Podman),
+            other => bail!(
+                "Invalid backend '{}': expected process, docker, or podman",
+                other
+            ),
+        }
+    }
+}
+
+/// A server's container, as recorded in its lock.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Container {
+    /// `Docker` or `Podman`.
+    pub backend: Backend,
+    pub image: String,
+    /// The container's name, `sharedserver-<server>`.
+    pub name: String,
+    /// The container ID, once the runtime has created it. Cleared when the
+    /// server is relaunched, as the new run is a new container.
+    #[serde(default)]
+    pub id: Option<String>,
+}
+
+/// The answer to [`Container::running`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContainerState {
+    Running,
+    /// The container exists but isn't running, or is gone altogether.
+    Stopped,
+    /// The runtime couldn't be asked.
+    Unknown,
+}
+
+impl Container {
+    /// The container for server `server` running `image` under `backend`,
+    /// checking the combination makes sense.
+    pub fn new(server: &str, backend: Backend, image: Option<&str>) -> Result<Option<Self>> {
+        match (backend, image) {
+            (Backend::Process, None) => Ok(None),
+            (Backend::Process, Some(_)) => {
+                bail!("--image needs a container backend (--backend docker or podman)")
+            }
+            (_, None) => bail!("--backend {} needs an --image to run", backend),
+            (_, Some(image)) if image.trim().is_empty() => bail!("--image cannot be empty"),
+            (_, Some(image)) => Ok(Some(Self {
+                backend,
+                image: image.to_string(),
+                name: container_name(server),
+                id: None,
+            })),
+        }
+    }
+
+    /// The runtime's CLI.
+    pub fn program(&self) -> &'static str {
+        self.backend.as_str()
+    }
+
+    /// How to refer to the container: its ID once known, else its name.
+    pub fn reference(&self) -> &str {
+        self.id.as_deref().unwrap_or(&self.name)
+    }
+
+    /// The arguments to `program()` that run `command` (the image's default
+    /// if empty) in the foreground with `env` (`KEY=VALUE`) set inside the
+    /// container, writing its ID to `cidfile`. With `interactive`, stdin is
+    /// passed through to the container.
+    pub fn run_args(
+        &self,
+        server: &str,
+        command: &[String],
+        env: &[String],
+        cidfile: &Path,
+        interactive: bool,
+    ) -> Vec<String> {
+        let mut args = vec![
+            "run".to_string(),
+            "--rm".to_string(),
+            "--name".to_string(),
+            self.name.clone(),
+            "--label".to_string(),
+            format!("sharedserver.server={}", server),
+            "--cidfile".to_string(),
+            cidfile.display().to_string(),
+        ];
+        if interactive {
+            args.push("--interactive".to_string());
+        }
+        for var in env {
+            args.push("--env".to_string());
+            args.push(var.clone());
+        }
+        args.push(self.image.clone());
+        args.extend(command.iter().cloned());
+        args
+    }
+
+    /// Remove a container left over under this name (e.g. by a run whose
+    /// client was killed), so a new one can take it. Best-effort.
+    pub fn remove_stale(&self) {
+        let _ = self.runtime(&["rm", "--force", &self.name]);
+    }
+
+    /// Stop the container: SIGTERM, then SIGKILL after `timeout`. Blocks
+    /// until it is down.
+    pub fn stop(&self, timeout: Duration) -> Result<()> {
+        let secs = timeout.as_secs().max(1).to_string();
+        self.runtime(&["stop", "--time", &secs, self.reference()])
+    }
+
+    /// Send `signal` (e.g. "TERM") to the container's main process, without
+    /// waiting for it to exit.
+    pub fn signal(&self, signal: &str) -> Result<()> {
+        self.runtime(&["kill", "--signal", signal, self.reference()])
+    }
+
+    /// SIGKILL the container.
+    pub fn kill(&self) -> Result<()> {
+        self.runtime(&["kill", self.reference()])
+    }
+
+    /// Whether the container is running, per the runtime.
+    pub fn running(&self) -> ContainerState {
+        let output = Command::new(self.program())
+            .args([
+                "inspect",
+                "--format",
+                "{{.State.Running}}",
+                self.reference(),
+            ])
+            .stdin(Stdio::null())
+            .output();
+        match output {
+            Ok(output) if output.status.success() => {
+                match String::from_utf8_lossy(&output.stdout).trim() {
+                    "true" => ContainerState::Running,
+                    "false" => ContainerState::Stopped,
+                    _ => ContainerState::Unknown,
+                }
+            }
+            // The runtime answered, and there is no such container (rather
+            // than, say, its daemon being unreachable).
+            Ok(output)
+                if String::from_utf8_lossy(&output.stderr)
+                    .to_lowercase()
+                    .contains("no such") =>
+            {
+                ContainerState::Stopped
+            }
+            _ => ContainerState::Unknown,
+        }
+    }
+
+    fn runtime(&self, args: &[&str]) -> Result<()> {
+        let output = Command::new(self.program())
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .output()
+            .with_context(|| format!("Failed to run {}", self.program()))?;
+        if !output.status.success() {
+            bail!(
+                "{} {} failed: {}",
+                self.program(),
+                args.first().unwrap_or(&""),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// The container name for server `server`: `sharedserver-<server>`, with
+/// anything a runtime wouldn't accept in a name replaced by `-`.
+pub fn container_name(server: &str) -> String {
+    let server: String = server
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-') {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    format!("sharedserver-{}", server)
+}
+
+/// The container ID in `cidfile`, once the runtime has written it.
+pub fn read_cidfile(cidfile: &Path) -> Option<String> {
+    std::fs::read_to_string(cidfile)
+        .ok()
+        .map(|id| id.trim().to_string())
+        .filter(|id| !id.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_checks_backend_and_image() {
+        assert!(Container::new("db", Backend::Process, None)
+            .unwrap()
+            .is_none());
+        assert!(Container::new("db", Backend::Process, Some("redis")).is_err());
+        assert!(Container::new("db", Backend::Docker, None).is_err());
+        let container = Container::new("db", Backend::Podman, Some("redis:7"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(container.program(), "podman");
+        assert_eq!(container.reference(), "sharedserver-db");
+    }
+
+    #[test]
+    fn test_run_args() {
+        let container = Container::new("my db", Backend::Docker, Some("redis:7"))
+            .unwrap()
+            .unwrap();
+        let args = container.run_args(
+            "my db",
+            &[
+                "redis-server".to_string(),
+                "--port".to_string(),
+                "7000".to_string(),
+            ],
+            &["A=1".to_string()],
+            Path::new("/tmp/x/container.id"),
+            false,
+        );
+        assert_eq!(
+            args.join(" "),
+            "run --rm --name sharedserver-my-db --label sharedserver.server=my db \
+             --cidfile /tmp/x/container.id --env A=1 redis:7 redis-server --port 7000"
+        );
+    }
+}
@@ -0,0 +1,74 @@
+//! Desktop notifications (`--notify-desktop`), so whoever is using a shared
+//! server hears that it is about to go away.
+//!
+//! Shown with `osascript` on macOS and `notify-send` (libnotify) elsewhere.
+//! The watcher inherits the session's environment (`DISPLAY`,
+//! `DBUS_SESSION_BUS_ADDRESS`) from the client that started it, which is what
+//! `notify-send` needs to reach the desktop.
+
+use anyhow::{bail, Context, Result};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// How long the notifier may take before it is abandoned (e.g. `notify-send`
+/// waiting on a session bus that isn't there).
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Show a notification titled `summary`.
+pub fn notify(summary: &str, body: &str) -> Result<()> {
+    let mut command = if cfg!(target_os = "macos") {
+        let mut command = Command::new("osascript");
+        command.arg("-e").arg(format!(
+            "display notification {} with title {}",
+            applescript_string(body),
+            applescript_string(summary)
+        ));
+        command
+    } else {
+        let mut command = Command::new("notify-send");
+        command.args(["--app-name", "sharedserver", summary, body]);
+        command
+    };
+    let program = command.get_program().to_string_lossy().into_owned();
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .with_context(|| format!("Failed to run {}", program))?;
+
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            if !status.success() {
+                bail!("{} failed ({})", program, status);
+            }
+            return Ok(());
+        }
+        if start.elapsed() > TIMEOUT {
+            let _ = child.kill();
+            let _ = child.wait();
+            bail!("{} timed out", program);
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+}
+
+/// `s` as a quoted AppleScript string literal.
+fn applescript_string(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_applescript_string_escapes_quotes() {
+        assert_eq!(applescript_string("db"), "\"db\"");
+        assert_eq!(
+            applescript_string(r#"say "hi" \o/"#),
+            r#""say \"hi\" \\o/""#
+        );
+    }
+}
@@ -1,56 +1,92 @@
 use anyhow::{bail, Result};
 use std::time::Duration;
 
-/// Parse duration string like "5m", "1h", "2h30m", "90s"
+/// Parse duration string like "5m", "1h", "2h30m", "90s", "500ms", "2d",
+/// "1w", or "1.5h". Every number needs a unit (ms, s, m, h, d, w), so a bare
+/// "5" is rejected rather than guessed at.
 pub fn parse_duration(s: &str) -> Result<Duration> {
     let s = s.trim();
     if s.is_empty() {
         bail!("Empty duration string");
     }
 
-    let mut total_secs = 0u64;
-    let mut current_num = String::new();
-
-    // Add `value * unit_secs` to the running total, rejecting overflow instead of
-    // panicking (debug) or silently wrapping to a tiny duration (release).
-    let add = |value: u64, unit_secs: u64, total: &mut u64| -> Result<()> {
-        let contribution = value
-            .checked_mul(unit_secs)
-            .ok_or_else(|| anyhow::anyhow!("Duration too large: {}", s))?;
-        *total = total
-            .checked_add(contribution)
-            .ok_or_else(|| anyhow::anyhow!("Duration too large: {}", s))?;
-        Ok(())
-    };
-
-    for ch in s.chars() {
-        if ch.is_ascii_digit() {
-            current_num.push(ch);
-        } else if ch == 'h' || ch == 'H' {
-            add(current_num.parse()?, 3600, &mut total_secs)?;
-            current_num.clear();
-        } else if ch == 'm' || ch == 'M' {
-            add(current_num.parse()?, 60, &mut total_secs)?;
-            current_num.clear();
-        } else if ch == 's' || ch == 'S' {
-            add(current_num.parse()?, 1, &mut total_secs)?;
-            current_num.clear();
-        } else if ch.is_whitespace() {
+    let mut total_nanos = 0u128;
+    let mut chars = s.chars().peekable();
+    while chars.peek().is_some() {
+        if chars.next_if(|ch| ch.is_whitespace()).is_some() {
             continue;
-        } else {
-            bail!("Invalid character in duration: {}", ch);
         }
-    }
 
-    if !current_num.is_empty() {
-        bail!("Duration must end with unit (h, m, or s): {}", s);
+        let mut number = String::new();
+        while let Some(ch) = chars.next_if(|ch| ch.is_ascii_digit() || *ch == '.') {
+            number.push(ch);
+        }
+        let mut unit = String::new();
+        while let Some(ch) = chars.next_if(|ch| ch.is_ascii_alphabetic()) {
+            unit.push(ch.to_ascii_lowercase());
+        }
+        if number.is_empty() {
+            match chars.peek() {
+                Some(ch) if unit.is_empty() => bail!("Invalid character in duration: {}", ch),
+                _ => bail!("Missing number before '{}' in duration: {}", unit, s),
+            }
+        }
+        if number == "." || number.matches('.').count() > 1 {
+            bail!("Invalid number '{}' in duration: {}", number, s);
+        }
+        if unit.is_empty() {
+            bail!("Duration must end with unit (ms, s, m, h, d, or w): {}", s);
+        }
+
+        let unit_nanos = unit_nanos(&unit)
+            .ok_or_else(|| anyhow::anyhow!("Invalid unit '{}' in duration: {}", unit, s))?;
+        total_nanos = scaled(&number, unit_nanos)
+            .and_then(|nanos| total_nanos.checked_add(nanos))
+            .ok_or_else(|| anyhow::anyhow!("Duration too large: {}", s))?;
     }
 
-    if total_secs == 0 {
+    if total_nanos == 0 {
         bail!("Duration must be greater than zero");
     }
 
-    Ok(Duration::from_secs(total_secs))
+    let secs = u64::try_from(total_nanos / NANOS_PER_SEC)
+        .map_err(|_| anyhow::anyhow!("Duration too large: {}", s))?;
+    Ok(Duration::new(secs, (total_nanos % NANOS_PER_SEC) as u32))
+}
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+fn unit_nanos(unit: &str) -> Option<u128> {
+    let secs = match unit {
+        "ms" => return Some(1_000_000),
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+    Some(secs * NANOS_PER_SEC)
+}
+
+/// `number` (digits, optionally with a fractional part) times `unit_nanos`,
+/// or `None` on overflow. Fractional digits past nanosecond precision are
+/// dropped.
+fn scaled(number: &str, unit_nanos: u128) -> Option<u128> {
+    let (whole, fraction) = number.split_once('.').unwrap_or((number, ""));
+    let whole: u128 = if whole.is_empty() {
+        0
+    } else {
+        whole.parse().ok()?
+    };
+    let mut nanos = whole.checked_mul(unit_nanos)?;
+    let fraction: String = fraction.chars().take(9).collect();
+    if !fraction.is_empty() {
+        let numerator: u128 = fraction.parse().ok()?;
+        let denominator = 10u128.pow(fraction.len() as u32);
+        nanos = nanos.checked_add(numerator * unit_nanos / denominator)?;
+    }
+    Some(nanos)
 }
 
 #[cfg(test)]
@@ -71,6 +107,38 @@ mod tests {
         assert!(parse_duration("0m").is_err());
     }
 
+    #[test]
+    fn test_parse_extended_units() {
+        assert_eq!(parse_duration("500ms").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse_duration("2d").unwrap().as_secs(), 2 * 86400);
+        assert_eq!(parse_duration("1w").unwrap().as_secs(), 7 * 86400);
+        assert_eq!(parse_duration("1w2d").unwrap().as_secs(), 9 * 86400);
+        assert_eq!(
+            parse_duration("1m30s500ms").unwrap(),
+            Duration::from_millis(90_500)
+        );
+        assert_eq!(parse_duration("1h 30m").unwrap().as_secs(), 5400);
+        assert_eq!(parse_duration("5M").unwrap().as_secs(), 300);
+    }
+
+    #[test]
+    fn test_parse_fractional() {
+        assert_eq!(parse_duration("1.5h").unwrap().as_secs(), 5400);
+        assert_eq!(parse_duration("0.5s").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse_duration(".25m").unwrap().as_secs(), 15);
+        assert_eq!(
+            parse_duration("2.5ms").unwrap(),
+            Duration::from_micros(2500)
+        );
+
+        assert!(parse_duration("1.2.3s").is_err());
+        assert!(parse_duration(".s").is_err());
+        assert!(parse_duration("1.5").is_err());
+        assert!(parse_duration("0.0s").is_err());
+        assert!(parse_duration("5y").is_err());
+        assert!(parse_duration("ms").is_err());
+    }
+
     #[test]
     fn test_parse_duration_overflow_is_error() {
         // Parses as u64 but *3600 overflows -> error, not a panic (debug) or a
@@ -0,0 +1,189 @@
+//! Errors callers are expected to tell apart, and the exit codes the CLI
+//! reports them with.
+//!
+//! Everything else stays a plain `anyhow` error and exits 1. A classified
+//! error is raised with [`ErrorKind::error`] and survives `.context(...)`:
+//! [`ErrorKind::of`] finds it anywhere in the chain.
+
+use std::fmt;
+
+/// A failure with its own exit code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// A flag or argument failed validation (the same code clap uses for
+    /// usage errors).
+    InvalidArgs,
+    /// The server isn't running.
+    NotRunning,
+    /// The server is shutting down, defunct, or restarting; retrying shortly
+    /// should succeed.
+    ShuttingDown,
+    /// `admin start` found the server already running; attach to it instead.
+    AlreadyRunning,
+    /// The client isn't attached to the server (or its refcount is already 0).
+    NotAttached,
+    /// Another process held a lockfile for longer than the lock timeout.
+    LockTimeout,
+    /// The server didn't come up in time.
+    StartTimeout,
+    /// `admin doctor` found issues it couldn't fix.
+    IssuesRemain,
+    /// Starting the server would run more servers than the config's
+    /// `max_running` (or `max_running_tags`) allows.
+    AtCapacity,
+}
+
+impl ErrorKind {
+    /// Every kind, in exit-code order.
+    pub const ALL: [ErrorKind; 9] = [
+        ErrorKind::InvalidArgs,
+        ErrorKind::NotRunning,
+        ErrorKind::ShuttingDown,
+        ErrorKind::AlreadyRunning,
+        ErrorKind::NotAttached,
+        ErrorKind::LockTimeout,
+        ErrorKind::StartTimeout,
+        ErrorKind::IssuesRemain,
+        ErrorKind::AtCapacity,
+    ];
+
+    /// Exit code for an error that isn't classified.
+    pub const GENERAL_EXIT_CODE: i32 = 1;
+
+    /// The process exit code for this kind. Codes from 10 up don't collide
+    /// with `check`'s state codes (0-4).
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ErrorKind::InvalidArgs => 2,
+            ErrorKind::NotRunning => 10,
+            ErrorKind::ShuttingDown => 11,
+            ErrorKind::AlreadyRunning => 12,
+            ErrorKind::NotAttached => 13,
+            ErrorKind::LockTimeout => 14,
+            ErrorKind::StartTimeout => 15,
+            ErrorKind::IssuesRemain => 16,
+            ErrorKind::AtCapacity => 17,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorKind::InvalidArgs => "invalid-args",
+            ErrorKind::NotRunning => "not-running",
+            ErrorKind::ShuttingDown => "shutting-down",
+            ErrorKind::AlreadyRunning => "already-running",
+            ErrorKind::NotAttached => "not-attached",
+            ErrorKind::LockTimeout => "lock-timeout",
+            ErrorKind::StartTimeout => "start-timeout",
+            ErrorKind::IssuesRemain => "issues-remain",
+            ErrorKind::AtCapacity => "at-capacity",
+        }
+    }
+
+    /// One line for `--print-exit-codes`.
+    pub fn description(&self) -> &'static str {
+        match self {
+            ErrorKind::InvalidArgs => "A flag or argument is invalid",
+            ErrorKind::NotRunning => "The server is not running",
+            ErrorKind::ShuttingDown => "The server is shutting down or restarting; retry shortly",
+            ErrorKind::AlreadyRunning => "admin start: the server is already running",
+            ErrorKind::NotAttached => "The client is not attached to the server",
+            ErrorKind::LockTimeout => "Timed out waiting for a lockfile held by another process",
+            ErrorKind::StartTimeout => "The server did not start in time",
+            ErrorKind::IssuesRemain => "admin doctor: issues remain that it could not fix",
+            ErrorKind::AtCapacity => {
+                "Too many servers are running to start another (use --queue to wait)"
+            }
+        }
+    }
+
+    /// An error of this kind with `message` as its text.
+    pub fn error(self, message: impl Into<String>) -> anyhow::Error {
+        anyhow::Error::new(Error {
+            kind: self,
+            message: message.into(),
+        })
+    }
+
+    /// `err`, explained by `message` and classified as this kind (like
+    /// `.context(message)`).
+    pub fn wrap(self, err: anyhow::Error, message: impl Into<String>) -> anyhow::Error {
+        err.context(Error {
+            kind: self,
+            message: message.into(),
+        })
+    }
+
+    /// The kind of the outermost classified error in `err`, whether it was
+    /// raised or added as context.
+    pub fn of(err: &anyhow::Error) -> Option<ErrorKind> {
+        err.downcast_ref::<Error>()
+            .or_else(|| err.chain().find_map(|cause| cause.downcast_ref::<Error>()))
+            .map(|e| e.kind)
+    }
+}
+
+/// Exit code for `err`: its kind's, or [`ErrorKind::GENERAL_EXIT_CODE`].
+pub fn exit_code(err: &anyhow::Error) -> i32 {
+    ErrorKind::of(err).map_or(ErrorKind::GENERAL_EXIT_CODE, |kind| kind.exit_code())
+}
+
+/// A classified error; build one with [`ErrorKind::error`].
+#[derive(Debug)]
+pub struct Error {
+    kind: ErrorKind,
+    message: String,
+}
+
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_kind_survives_context() {
+        let err: anyhow::Result<()> =
+            Err(ErrorKind::NotAttached.error("Client 1 was not attached"));
+        let err = err.context("Failed to decrement refcount").unwrap_err();
+        assert_eq!(ErrorKind::of(&err), Some(ErrorKind::NotAttached));
+        assert_eq!(exit_code(&err), 13);
+        assert_eq!(err.root_cause().to_string(), "Client 1 was not attached");
+    }
+
+    #[test]
+    fn test_wrap_classifies_and_keeps_cause() {
+        let err = ErrorKind::InvalidArgs.wrap(anyhow::anyhow!("bad unit"), "Invalid timeout: 5x");
+        let err = err.context("Failed to stop");
+        assert_eq!(ErrorKind::of(&err), Some(ErrorKind::InvalidArgs));
+        let chain: Vec<String> = err.chain().map(|c| c.to_string()).collect();
+        assert_eq!(chain, ["Failed to stop", "Invalid timeout: 5x", "bad unit"]);
+    }
+
+    #[test]
+    fn test_unclassified_is_general() {
+        let err = anyhow::anyhow!("something else");
+        assert_eq!(ErrorKind::of(&err), None);
+        assert_eq!(exit_code(&err), ErrorKind::GENERAL_EXIT_CODE);
+    }
+
+    #[test]
+    fn test_exit_codes_are_distinct() {
+        let codes: Vec<i32> = ErrorKind::ALL.iter().map(|k| k.exit_code()).collect();
+        assert!(codes.windows(2).all(|w| w[0] < w[1]), "{:?}", codes);
+        assert!(!codes.contains(&ErrorKind::GENERAL_EXIT_CODE));
+    }
+}
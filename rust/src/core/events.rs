@@ -0,0 +1,231 @@
+//! Observing a server's state changes as they happen.
+//!
+//! [`subscribe`] is the one implementation of "tell me when this server
+//! changes": the `events` command uses it, and so can anything embedding the
+//! library. It waits on the lock directory with the same [`ExitNotifier`] the
+//! watcher uses (inotify on Linux, kqueue on macOS) plus the server's own exit,
+//! re-reads the server's locks on each wakeup, and reports the differences.
+//! A slow poll backs it up where file notifications aren't available.
+
+use super::exit_notify::ExitNotifier;
+use super::lockfile::{
+    clients_lockfile_path, lockfile_dir, read_clients_lock, read_server_lock, server_lockfile_path,
+};
+use super::state::{get_server_state, ServerState};
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::{BTreeSet, VecDeque};
+use std::time::{Duration, Instant};
+
+/// How long to wait for a notification before re-reading anyway.
+pub(crate) const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// One observed change to a server.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum StateEvent {
+    /// The server moved between states (including to and from `stopped`).
+    State { from: ServerState, to: ServerState },
+    /// A client started holding a reference.
+    ClientAttached { pid: i32 },
+    /// A client stopped holding a reference (detached, or found dead).
+    ClientDetached { pid: i32 },
+}
+
+/// What a server looked like at one read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Snapshot {
+    state: ServerState,
+    clients: BTreeSet<i32>,
+}
+
+impl Snapshot {
+    fn read(name: &str) -> Self {
+        let state = get_server_state(name).unwrap_or(ServerState::Stopped);
+        let clients = match state {
+            ServerState::Stopped => BTreeSet::new(),
+            _ => read_clients_lock(name)
+                .map(|lock| lock.clients.into_keys().collect())
+                .unwrap_or_default(),
+        };
+        Self { state, clients }
+    }
+
+    /// The events that turn `self` into `next`: detaches, then the state
+    /// change, then attaches, so a last client leaving reads before the grace
+    /// it causes, and a rescue before the return to active.
+    fn diff(&self, next: &Snapshot) -> Vec<StateEvent> {
+        let detached = self.clients.difference(&next.clients);
+        let attached = next.clients.difference(&self.clients);
+        let mut events: Vec<StateEvent> = detached
+            .map(|&pid| StateEvent::ClientDetached { pid })
+            .collect();
+        if self.state != next.state {
+            events.push(StateEvent::State {
+                from: self.state,
+                to: next.state,
+            });
+        }
+        events.extend(attached.map(|&pid| StateEvent::ClientAttached { pid }));
+        events
+    }
+}
+
+/// A blocking, endless stream of `name`'s state changes, from the moment of
+/// subscribing. Each call to `next` waits until there is something to report.
+pub struct Subscription {
+    name: String,
+    last: Snapshot,
+    pending: VecDeque<StateEvent>,
+    notifier: ExitNotifier,
+}
+
+/// Start observing `name`. The server needn't be running: its start is
+/// reported like any other change.
+pub fn subscribe(name: &str) -> Result<Subscription> {
+    // Resolve (and vet) the lock directory up front, so a bad one is an error
+    // here rather than a silent stream of nothing.
+    lockfile_dir()?;
+    Ok(Subscription {
+        name: name.to_string(),
+        last: Snapshot::read(name),
+        pending: VecDeque::new(),
+        notifier: ExitNotifier::new(),
+    })
+}
+
+impl Subscription {
+    /// The server's state as of the last event (or the subscription).
+    pub fn state(&self) -> ServerState {
+        self.last.state
+    }
+
+    /// The next change, waiting at most `timeout` for one.
+    pub fn next_timeout(&mut self, timeout: Duration) -> Option<StateEvent> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(event) = self.pop() {
+                return Some(event);
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return None;
+            }
+            let exited = self.arm().wait(remaining.min(POLL_INTERVAL));
+            self.refresh(exited);
+        }
+    }
+
+    /// (Re-)arm the notifier: watches drop out when files are replaced or
+    /// the server changes, so this runs before every wait. Returns it, to
+    /// wait on.
+    pub(crate) fn arm(&mut self) -> &mut ExitNotifier {
+        if let Ok(dir) = lockfile_dir() {
+            self.notifier.watch_dir(&dir);
+        }
+        for path in [
+            server_lockfile_path(&self.name),
+            clients_lockfile_path(&self.name),
+        ]
+        .into_iter()
+        .flatten()
+        {
+            self.notifier.watch_file(&path);
+        }
+        if let Ok(lock) = read_server_lock(&self.name) {
+            self.notifier.watch(lock.pid);
+        }
+        &mut self.notifier
+    }
+
+    /// The next change already seen, if any.
+    pub(crate) fn pop(&mut self) -> Option<StateEvent> {
+        self.pending.pop_front()
+    }
+
+    /// After a wait, during which the processes in `exited` exited: re-read
+    /// the server and queue what changed.
+    pub(crate) fn refresh(&mut self, exited: Vec<i32>) {
+        for pid in exited {
+            self.notifier.unwatch(pid);
+        }
+        let next = Snapshot::read(&self.name);
+        self.pending.extend(self.last.diff(&next));
+        self.last = next;
+    }
+}
+
+impl Iterator for Subscription {
+    type Item = StateEvent;
+
+    fn next(&mut self) -> Option<StateEvent> {
+        loop {
+            if let Some(event) = self.pop() {
+                return Some(event);
+            }
+            let exited = self.arm().wait(POLL_INTERVAL);
+            self.refresh(exited);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(state: ServerState, clients: &[i32]) -> Snapshot {
+        Snapshot {
+            state,
+            clients: clients.iter().copied().collect(),
+        }
+    }
+
+    #[test]
+    fn test_diff_orders_detach_state_attach() {
+        let before = snapshot(ServerState::Active, &[1, 2]);
+        let after = snapshot(ServerState::Active, &[2, 3]);
+        assert_eq!(
+            before.diff(&after),
+            vec![
+                StateEvent::ClientDetached { pid: 1 },
+                StateEvent::ClientAttached { pid: 3 },
+            ]
+        );
+
+        let grace = snapshot(ServerState::Grace, &[]);
+        assert_eq!(
+            after.diff(&grace),
+            vec![
+                StateEvent::ClientDetached { pid: 2 },
+                StateEvent::ClientDetached { pid: 3 },
+                StateEvent::State {
+                    from: ServerState::Active,
+                    to: ServerState::Grace,
+                },
+            ]
+        );
+        assert!(grace.diff(&grace.clone()).is_empty());
+    }
+
+    #[test]
+    fn test_state_event_json() {
+        let event = StateEvent::State {
+            from: ServerState::Stopped,
+            to: ServerState::Active,
+        };
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            serde_json::json!({ "event": "state", "from": "stopped", "to": "active" })
+        );
+    }
+
+    #[test]
+    fn test_next_timeout_gives_up() {
+        let name = format!("events-timeout-{}", std::process::id());
+        let mut subscription = subscribe(&name).unwrap();
+        let started = Instant::now();
+        assert_eq!(subscription.next_timeout(Duration::from_millis(200)), None);
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        assert!(started.elapsed() < POLL_INTERVAL * 2);
+    }
+}
@@ -0,0 +1,131 @@
+//! Identifying the binary a server runs, so a later `use --replace` (or a
+//! human reading `info`) can tell it was rebuilt or upgraded since start.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+/// The server's executable as it was on disk at launch.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExeSnapshot {
+    /// Canonical path (symlinks resolved), so a repointed symlink reads as a
+    /// different binary.
+    pub path: PathBuf,
+    pub size: u64,
+    pub modified: Option<DateTime<Utc>>,
+    /// FNV-1a hash of the contents, hex.
+    pub hash: String,
+}
+
+impl ExeSnapshot {
+    /// Snapshot the executable `program` resolves to, searching `path_var`
+    /// (the server's `PATH`) like a shell would. `None` if it can't be found or
+    /// read, e.g. a shell builtin or a relative path from elsewhere.
+    pub fn capture(program: &str, path_var: Option<&str>) -> Option<Self> {
+        let path = resolve_executable(program, path_var)?;
+        Self::of(&path)
+    }
+
+    fn of(path: &Path) -> Option<Self> {
+        let path = path.canonicalize().ok()?;
+        let metadata = std::fs::metadata(&path).ok()?;
+        Some(Self {
+            hash: hash_file(&path)?,
+            size: metadata.len(),
+            modified: metadata.modified().ok().map(DateTime::<Utc>::from),
+            path,
+        })
+    }
+
+    /// Whether the file at `path` is no longer the one snapshotted. Cheap
+    /// when size and mtime still match; otherwise re-hashes, so a `touch`
+    /// alone doesn't count. A deleted file counts as changed.
+    pub fn changed_on_disk(&self) -> bool {
+        let Ok(metadata) = std::fs::metadata(&self.path) else {
+            return true;
+        };
+        let modified = metadata.modified().ok().map(DateTime::<Utc>::from);
+        if metadata.len() == self.size && modified == self.modified {
+            return false;
+        }
+        hash_file(&self.path).as_deref() != Some(self.hash.as_str())
+    }
+}
+
+/// Find `program` the way `execvp` would: as given if it contains a slash,
+/// else in the first `path_var` entry holding an executable file by that name.
+pub fn resolve_executable(program: &str, path_var: Option<&str>) -> Option<PathBuf> {
+    let is_executable = |path: &Path| {
+        std::fs::metadata(path).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+    };
+    if program.contains('/') {
+        let path = PathBuf::from(program);
+        return is_executable(&path).then_some(path);
+    }
+    path_var?
+        .split(':')
+        .filter(|dir| !dir.is_empty())
+        .map(|dir| Path::new(dir).join(program))
+        .find(|path| is_executable(path))
+}
+
+/// 64-bit FNV-1a of the file's contents. Not cryptographic; it only has to
+/// notice a rebuilt binary, and is stable across toolchains (unlike std's
+/// hasher), so snapshots from an older sharedserver still compare.
+fn hash_file(path: &Path) -> Option<String> {
+    const OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    let mut file = std::fs::File::open(path).ok()?;
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut hash = OFFSET;
+    loop {
+        let n = file.read(&mut buf).ok()?;
+        if n == 0 {
+            break;
+        }
+        for &byte in &buf[..n] {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(PRIME);
+        }
+    }
+    Some(format!("{:016x}", hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_executable_searches_path() {
+        let sh = resolve_executable("sh", Some("/nonexistent:/bin:/usr/bin")).unwrap();
+        assert!(sh.ends_with("sh"));
+        assert!(resolve_executable("sh", Some("/nonexistent")).is_none());
+        assert_eq!(
+            resolve_executable("/bin/sh", None),
+            Some(PathBuf::from("/bin/sh"))
+        );
+    }
+
+    #[test]
+    fn test_snapshot_detects_content_change() {
+        let path = std::env::temp_dir().join(format!("exe-snapshot-{}", std::process::id()));
+        std::fs::write(&path, "#!/bin/sh\necho one\n").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let snapshot = ExeSnapshot::capture(path.to_str().unwrap(), None).unwrap();
+        assert!(!snapshot.changed_on_disk());
+
+        // Same contents, new mtime: not a change.
+        std::fs::write(&path, "#!/bin/sh\necho one\n").unwrap();
+        assert!(!snapshot.changed_on_disk());
+
+        std::fs::write(&path, "#!/bin/sh\necho two\n").unwrap();
+        assert!(snapshot.changed_on_disk());
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(snapshot.changed_on_disk());
+    }
+}
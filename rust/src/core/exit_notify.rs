@@ -0,0 +1,470 @@
+//! Event-driven wakeups for the watcher loop.
+//!
+//! The watcher used to learn about a dead server, a dead client, or a
+//! refcount change only on its next poll tick. [`ExitNotifier`] lets it block
+//! until a watched process exits, a watched file is modified, or a timeout
+//! elapses, so it reacts within milliseconds:
+//!
+//! - Linux: one `pidfd_open(2)` descriptor per process (readable once the
+//!   process exits, including while it is still an unreaped zombie) and one
+//!   inotify instance for files, all waited on with `poll(2)`.
+//! - macOS: a single `kqueue` with `EVFILT_PROC`/`NOTE_EXIT` filters for
+//!   processes and `EVFILT_VNODE` filters for files.
+//! - Elsewhere (or if the kernel refuses, e.g. Linux < 5.3): a plain sleep,
+//!   so callers degrade to the old polling behaviour.
+
+use std::collections::HashMap;
+use std::os::fd::RawFd;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+#[derive(Debug, Default)]
+pub struct ExitNotifier {
+    /// Watched PIDs. On Linux each maps to its pidfd; elsewhere the value is
+    /// unused.
+    #[cfg(target_os = "linux")]
+    pids: HashMap<i32, OwnedFd>,
+    #[cfg(not(target_os = "linux"))]
+    pids: HashMap<i32, ()>,
+    /// Watched files. On Linux each maps to its inotify watch descriptor; on
+    /// macOS to the descriptor the vnode filter is registered against.
+    #[cfg(target_os = "linux")]
+    files: HashMap<PathBuf, i32>,
+    #[cfg(target_os = "macos")]
+    files: HashMap<PathBuf, OwnedFd>,
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    files: HashMap<PathBuf, ()>,
+    #[cfg(target_os = "linux")]
+    inotify: Option<OwnedFd>,
+    #[cfg(target_os = "macos")]
+    kqueue: Option<OwnedFd>,
+}
+
+impl ExitNotifier {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start watching `pid`. Returns `false` if it can't be watched
+    /// event-style (already gone, or unsupported here); the caller's regular
+    /// polling still covers it in that case.
+    pub fn watch(&mut self, pid: i32) -> bool {
+        if self.pids.contains_key(&pid) {
+            return true;
+        }
+        self.watch_impl(pid)
+    }
+
+    /// Stop watching `pid` (e.g. once it has been reaped).
+    pub fn unwatch(&mut self, pid: i32) {
+        // Dropping the pidfd closes it; kqueue NOTE_EXIT registrations are
+        // removed by the kernel when the process exits.
+        self.pids.remove(&pid);
+    }
+
+    /// Whether `pid` is currently watched.
+    pub fn is_watching(&self, pid: i32) -> bool {
+        self.pids.contains_key(&pid)
+    }
+
+    /// Wake [`wait`](Self::wait) whenever `path` is written to. Returns
+    /// `false` if it can't be watched (e.g. it doesn't exist yet). A file
+    /// that is deleted or replaced drops out of the watch set, so callers
+    /// should simply call this again on each pass.
+    pub fn watch_file(&mut self, path: &Path) -> bool {
+        if self.files.contains_key(path) {
+            return true;
+        }
+        self.watch_file_impl(path)
+    }
+
+    /// Wake [`wait`](Self::wait) whenever an entry in the directory `path` is
+    /// created, deleted, or renamed (and, where the platform reports it, when
+    /// a file in it is written). Returns `false` if it can't be watched.
+    pub fn watch_dir(&mut self, path: &Path) -> bool {
+        if self.files.contains_key(path) {
+            return true;
+        }
+        self.watch_dir_impl(path)
+    }
+
+    /// Block until a watched process exits, a watched file changes, or
+    /// `timeout` elapses. Returns the PIDs known to have exited (empty on
+    /// timeout or when only a file changed).
+    pub fn wait(&mut self, timeout: Duration) -> Vec<i32> {
+        if self.pids.is_empty() && self.files.is_empty() {
+            std::thread::sleep(timeout);
+            return Vec::new();
+        }
+        self.wait_impl(timeout)
+    }
+
+    /// The descriptors that turn readable when [`wait`](Self::wait) has
+    /// something to report, for callers running their own event loop: wait
+    /// for any of them, then collect with `wait(Duration::ZERO)`. Empty
+    /// where notifications aren't available.
+    pub fn fds(&self) -> Vec<RawFd> {
+        self.fds_impl()
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl ExitNotifier {
+    fn fds_impl(&self) -> Vec<RawFd> {
+        self.pids
+            .values()
+            .chain(self.inotify.as_ref())
+            .map(|fd| fd.as_raw_fd())
+            .collect()
+    }
+
+    fn watch_impl(&mut self, pid: i32) -> bool {
+        // SAFETY: pidfd_open takes a pid and flags and returns a new fd (or -1).
+        let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) };
+        if fd < 0 {
+            return false;
+        }
+        // SAFETY: the syscall just returned this descriptor and nothing else
+        // owns it.
+        let fd = unsafe { OwnedFd::from_raw_fd(fd as i32) };
+        self.pids.insert(pid, fd);
+        true
+    }
+
+    fn watch_file_impl(&mut self, path: &Path) -> bool {
+        // Only content changes: the lock is opened read-write just to flock
+        // it, so IN_CLOSE_WRITE would fire on every read.
+        self.add_inotify_watch(path, libc::IN_MODIFY)
+    }
+
+    fn watch_dir_impl(&mut self, path: &Path) -> bool {
+        // IN_MODIFY on a directory reports writes to the files in it.
+        let mask = libc::IN_CREATE
+            | libc::IN_DELETE
+            | libc::IN_MOVED_FROM
+            | libc::IN_MOVED_TO
+            | libc::IN_MODIFY;
+        self.add_inotify_watch(path, mask)
+    }
+
+    fn add_inotify_watch(&mut self, path: &Path, mask: u32) -> bool {
+        use std::os::unix::ffi::OsStrExt;
+
+        if self.inotify.is_none() {
+            // SAFETY: inotify_init1 takes flags and returns a new fd or -1.
+            let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+            if fd < 0 {
+                return false;
+            }
+            // SAFETY: freshly created descriptor, owned by nobody else.
+            self.inotify = Some(unsafe { OwnedFd::from_raw_fd(fd) });
+        }
+        let Some(inotify) = &self.inotify else {
+            return false;
+        };
+        let Ok(c_path) = std::ffi::CString::new(path.as_os_str().as_bytes()) else {
+            return false;
+        };
+        // SAFETY: valid inotify fd and NUL-terminated path.
+        let wd = unsafe { libc::inotify_add_watch(inotify.as_raw_fd(), c_path.as_ptr(), mask) };
+        if wd < 0 {
+            return false;
+        }
+        self.files.insert(path.to_path_buf(), wd);
+        true
+    }
+
+    /// Consume pending inotify events, forgetting files whose watch the
+    /// kernel removed (deleted or unmounted).
+    fn drain_inotify(&mut self) {
+        let Some(inotify) = &self.inotify else {
+            return;
+        };
+        let header = std::mem::size_of::<libc::inotify_event>();
+        let mut buf = [0u8; 4096];
+        loop {
+            // SAFETY: reading into a local buffer of the given length.
+            let n = unsafe {
+                libc::read(
+                    inotify.as_raw_fd(),
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                )
+            };
+            if n <= 0 {
+                return;
+            }
+            let mut offset = 0;
+            while offset + header <= n as usize {
+                // SAFETY: the kernel wrote a whole event header at `offset`;
+                // read_unaligned copes with the byte buffer's alignment.
+                let event: libc::inotify_event = unsafe {
+                    std::ptr::read_unaligned(buf.as_ptr().add(offset) as *const libc::inotify_event)
+                };
+                if event.mask & libc::IN_IGNORED != 0 {
+                    self.files.retain(|_, wd| *wd != event.wd);
+                }
+                offset += header + event.len as usize;
+            }
+        }
+    }
+
+    fn wait_impl(&mut self, timeout: Duration) -> Vec<i32> {
+        let watched: Vec<i32> = self.pids.keys().copied().collect();
+        let mut fds: Vec<libc::pollfd> = watched
+            .iter()
+            .map(|pid| libc::pollfd {
+                fd: self.pids[pid].as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            })
+            .collect();
+        if let Some(inotify) = &self.inotify {
+            fds.push(libc::pollfd {
+                fd: inotify.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            });
+        }
+
+        let timeout_ms = timeout.as_millis().min(i32::MAX as u128) as i32;
+        // SAFETY: `fds` is a valid, correctly-sized array of pollfd.
+        let ready = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout_ms) };
+        if ready <= 0 {
+            // Timeout, or EINTR (e.g. a signal): the caller re-checks state.
+            return Vec::new();
+        }
+
+        if fds.len() > watched.len() && fds[watched.len()].revents != 0 {
+            self.drain_inotify();
+        }
+        watched
+            .into_iter()
+            .zip(fds)
+            .filter(|(_, fd)| fd.revents != 0)
+            .map(|(pid, _)| pid)
+            .collect()
+    }
+}
+
+#[cfg(target_os = "macos")]
+impl ExitNotifier {
+    fn fds_impl(&self) -> Vec<RawFd> {
+        // The kqueue itself is readable while it has events pending.
+        self.kqueue.iter().map(|fd| fd.as_raw_fd()).collect()
+    }
+
+    fn kqueue_fd(&mut self) -> Option<i32> {
+        if self.kqueue.is_none() {
+            // SAFETY: kqueue() takes no arguments and returns a new fd or -1.
+            let kq = unsafe { libc::kqueue() };
+            if kq < 0 {
+                return None;
+            }
+            // SAFETY: freshly created descriptor, owned by nobody else.
+            self.kqueue = Some(unsafe { OwnedFd::from_raw_fd(kq) });
+        }
+        self.kqueue.as_ref().map(|fd| fd.as_raw_fd())
+    }
+
+    /// Register one change with the kqueue, retrieving no events.
+    fn register(&mut self, change: libc::kevent) -> bool {
+        let Some(kq) = self.kqueue_fd() else {
+            return false;
+        };
+        // SAFETY: registers one change, retrieves no events.
+        let rc = unsafe { libc::kevent(kq, &change, 1, std::ptr::null_mut(), 0, std::ptr::null()) };
+        rc >= 0
+    }
+
+    fn watch_impl(&mut self, pid: i32) -> bool {
+        let registered = self.register(libc::kevent {
+            ident: pid as libc::uintptr_t,
+            filter: libc::EVFILT_PROC,
+            flags: libc::EV_ADD | libc::EV_ONESHOT,
+            fflags: libc::NOTE_EXIT,
+            data: 0,
+            udata: std::ptr::null_mut(),
+        });
+        if registered {
+            self.pids.insert(pid, ());
+        }
+        registered
+    }
+
+    fn watch_file_impl(&mut self, path: &Path) -> bool {
+        use std::os::unix::ffi::OsStrExt;
+
+        let Ok(c_path) = std::ffi::CString::new(path.as_os_str().as_bytes()) else {
+            return false;
+        };
+        // SAFETY: NUL-terminated path; O_EVTONLY opens for notification only.
+        let fd = unsafe { libc::open(c_path.as_ptr(), libc::O_EVTONLY | libc::O_CLOEXEC) };
+        if fd < 0 {
+            return false;
+        }
+        // SAFETY: freshly opened descriptor, owned by nobody else.
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let registered = self.register(libc::kevent {
+            ident: fd.as_raw_fd() as libc::uintptr_t,
+            filter: libc::EVFILT_VNODE,
+            flags: libc::EV_ADD | libc::EV_CLEAR,
+            fflags: libc::NOTE_WRITE | libc::NOTE_EXTEND | libc::NOTE_DELETE | libc::NOTE_RENAME,
+            data: 0,
+            udata: std::ptr::null_mut(),
+        });
+        if registered {
+            self.files.insert(path.to_path_buf(), fd);
+        }
+        registered
+    }
+
+    fn watch_dir_impl(&mut self, path: &Path) -> bool {
+        // NOTE_WRITE on a directory fires when entries are added or removed;
+        // writes to the files themselves need their own watch_file.
+        self.watch_file_impl(path)
+    }
+
+    fn wait_impl(&mut self, timeout: Duration) -> Vec<i32> {
+        let Some(kq) = self.kqueue_fd() else {
+            std::thread::sleep(timeout);
+            return Vec::new();
+        };
+        let ts = libc::timespec {
+            tv_sec: timeout.as_secs() as libc::time_t,
+            tv_nsec: timeout.subsec_nanos() as libc::c_long,
+        };
+        let capacity = self.pids.len() + self.files.len();
+        let mut events: Vec<libc::kevent> = Vec::with_capacity(capacity);
+        // SAFETY: `events` has room for `capacity` entries; kevent writes at
+        // most that many and returns the count.
+        let n = unsafe {
+            libc::kevent(
+                kq,
+                std::ptr::null(),
+                0,
+                events.as_mut_ptr(),
+                capacity as i32,
+                &ts,
+            )
+        };
+        if n <= 0 {
+            return Vec::new();
+        }
+        // SAFETY: kevent initialised the first `n` entries.
+        unsafe { events.set_len(n as usize) };
+
+        // A deleted or renamed file is no longer the lock we care about;
+        // forget it so the caller re-watches whatever replaces it.
+        for ev in events.iter().filter(|ev| ev.filter == libc::EVFILT_VNODE) {
+            if ev.fflags & (libc::NOTE_DELETE | libc::NOTE_RENAME) != 0 {
+                let fd = ev.ident as i32;
+                self.files.retain(|_, owned| owned.as_raw_fd() != fd);
+            }
+        }
+        events
+            .iter()
+            .filter(|ev| ev.filter == libc::EVFILT_PROC)
+            .map(|ev| ev.ident as i32)
+            .collect()
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+impl ExitNotifier {
+    fn fds_impl(&self) -> Vec<RawFd> {
+        Vec::new()
+    }
+
+    fn watch_impl(&mut self, _pid: i32) -> bool {
+        false
+    }
+
+    fn watch_file_impl(&mut self, _path: &Path) -> bool {
+        false
+    }
+
+    fn watch_dir_impl(&mut self, _path: &Path) -> bool {
+        false
+    }
+
+    fn wait_impl(&mut self, timeout: Duration) -> Vec<i32> {
+        std::thread::sleep(timeout);
+        Vec::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+    use std::time::Instant;
+
+    #[test]
+    fn wait_times_out_with_nothing_watched() {
+        let mut notifier = ExitNotifier::new();
+        let start = Instant::now();
+        assert!(notifier.wait(Duration::from_millis(50)).is_empty());
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    #[test]
+    fn wait_returns_promptly_when_child_exits() {
+        let mut child = Command::new("sleep").arg("0.2").spawn().unwrap();
+        let pid = child.id() as i32;
+
+        let mut notifier = ExitNotifier::new();
+        assert!(notifier.watch(pid));
+
+        let start = Instant::now();
+        let mut exited = Vec::new();
+        while exited.is_empty() && start.elapsed() < Duration::from_secs(5) {
+            exited = notifier.wait(Duration::from_secs(5));
+        }
+        assert_eq!(exited, vec![pid]);
+        assert!(
+            start.elapsed() < Duration::from_secs(2),
+            "exit should be reported as it happens, not at the timeout"
+        );
+
+        child.wait().unwrap();
+        notifier.unwatch(pid);
+        assert!(!notifier.is_watching(pid));
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    #[test]
+    fn wait_wakes_when_watched_file_is_written() {
+        use std::io::Write;
+
+        let path = std::env::temp_dir().join(format!("exit-notify-{}.json", std::process::id()));
+        std::fs::write(&path, "{}").unwrap();
+
+        let mut notifier = ExitNotifier::new();
+        assert!(notifier.watch_file(&path));
+
+        let writer_path = path.clone();
+        let writer = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            let mut file = std::fs::OpenOptions::new()
+                .append(true)
+                .open(writer_path)
+                .unwrap();
+            file.write_all(b"\n").unwrap();
+        });
+
+        let start = Instant::now();
+        notifier.wait(Duration::from_secs(5));
+        assert!(
+            start.elapsed() < Duration::from_secs(2),
+            "a write should wake the wait, not the timeout"
+        );
+
+        writer.join().unwrap();
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,173 @@
+//! Selecting servers by state, name pattern, and tags, shared by `list` and
+//! the commands that act on many servers at once.
+
+use super::error::ErrorKind;
+use super::state::ServerState;
+use anyhow::Result;
+use std::collections::BTreeMap;
+
+/// Which servers a command applies to. An empty filter matches everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ServerFilter {
+    /// Keep servers in any of these states (all states if empty).
+    pub states: Vec<ServerState>,
+    /// Keep servers whose name matches this glob (see [`glob_match`]).
+    pub name: Option<String>,
+    /// Keep servers tagged with every one of these `(key, value)` pairs.
+    pub tags: Vec<(String, String)>,
+}
+
+impl ServerFilter {
+    pub fn is_empty(&self) -> bool {
+        self.states.is_empty() && self.name.is_none() && self.tags.is_empty()
+    }
+
+    /// Whether `name` passes the name pattern. Lets callers skip reading the
+    /// state of servers that can't match anyway.
+    pub fn matches_name(&self, name: &str) -> bool {
+        self.name
+            .as_deref()
+            .is_none_or(|pattern| glob_match(pattern, name))
+    }
+
+    pub fn matches_state(&self, state: ServerState) -> bool {
+        self.states.is_empty() || self.states.contains(&state)
+    }
+
+    pub fn matches(&self, name: &str, state: ServerState) -> bool {
+        self.matches_name(name) && self.matches_state(state)
+    }
+
+    pub fn matches_tags(&self, tags: &BTreeMap<String, String>) -> bool {
+        self.tags
+            .iter()
+            .all(|(key, value)| tags.get(key) == Some(value))
+    }
+}
+
+/// The tags `name` has: those of the running server, or else those it had
+/// when it last went down.
+pub fn server_tags(name: &str) -> BTreeMap<String, String> {
+    match super::lockfile::read_server_lock(name) {
+        Ok(lock) => lock.tags,
+        Err(_) => super::tombstone::read_tombstone(name)
+            .map(|tombstone| tombstone.tags)
+            .unwrap_or_default(),
+    }
+}
+
+/// Whether a name argument is a pattern (with `*` or `?` wildcards) rather
+/// than one server's name.
+pub fn is_pattern(name: &str) -> bool {
+    name.contains(['*', '?'])
+}
+
+/// The servers in the lock directory with any of `files` whose names match
+/// `pattern`. None matching is an error, as naming a missing server would be.
+pub fn matching_servers(pattern: &str, files: &[&str]) -> Result<Vec<String>> {
+    let names: Vec<String> = super::lockfile::servers_with(files)?
+        .into_iter()
+        .filter(|name| glob_match(pattern, name))
+        .collect();
+    if names.is_empty() {
+        return Err(ErrorKind::NotRunning.error(format!("No server matches '{}'", pattern)));
+    }
+    Ok(names)
+}
+
+/// Parse a `--tag` as `KEY=VALUE`.
+pub fn parse_tag(text: &str) -> Result<(String, String)> {
+    match text.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => {
+            Err(ErrorKind::InvalidArgs.error(format!("Invalid tag '{}': expected KEY=VALUE", text)))
+        }
+    }
+}
+
+/// Shell-style wildcard match: `*` matches any run of characters (including
+/// none) and `?` matches exactly one. Everything else matches itself.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Where the last `*` was, and how much of the name it has swallowed so
+    // far; on a mismatch, backtrack by letting it take one more character.
+    let mut star: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("lsp-*", "lsp-rust"));
+        assert!(glob_match("lsp-*", "lsp-"));
+        assert!(!glob_match("lsp-*", "db"));
+        assert!(glob_match("*db*", "my-db-1"));
+        assert!(glob_match("a?c", "abc"));
+        assert!(!glob_match("a?c", "ac"));
+        assert!(glob_match("*-*-x", "a-b-c-x"));
+        assert!(glob_match("exact", "exact"));
+        assert!(!glob_match("exact", "exactly"));
+        assert!(glob_match("*", ""));
+    }
+
+    #[test]
+    fn test_filter_matches() {
+        let filter = ServerFilter {
+            states: vec![ServerState::Active, ServerState::Grace],
+            name: Some("lsp-*".to_string()),
+            tags: Vec::new(),
+        };
+        assert!(filter.matches("lsp-rust", ServerState::Grace));
+        assert!(!filter.matches("lsp-rust", ServerState::Stopped));
+        assert!(!filter.matches("db", ServerState::Active));
+        assert!(ServerFilter::default().matches("db", ServerState::Defunct));
+    }
+
+    #[test]
+    fn test_filter_matches_tags() {
+        let tags: BTreeMap<String, String> = [("project", "foo"), ("kind", "lsp")]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let filter = |pairs: &[&str]| ServerFilter {
+            tags: pairs.iter().map(|tag| parse_tag(tag).unwrap()).collect(),
+            ..Default::default()
+        };
+        assert!(filter(&[]).matches_tags(&tags));
+        assert!(filter(&["project=foo"]).matches_tags(&tags));
+        assert!(filter(&["project=foo", "kind=lsp"]).matches_tags(&tags));
+        assert!(!filter(&["project=foo", "kind=db"]).matches_tags(&tags));
+        assert!(!filter(&["owner=me"]).matches_tags(&tags));
+
+        assert_eq!(parse_tag("a=b=c").unwrap(), ("a".into(), "b=c".into()));
+        assert_eq!(parse_tag("empty=").unwrap(), ("empty".into(), "".into()));
+        assert!(parse_tag("=x").is_err());
+        assert!(parse_tag("novalue").is_err());
+    }
+}
@@ -0,0 +1,365 @@
+//! A stable address in front of a server (`--listen`).
+//!
+//! The front is a small process, forked by the watcher (or the daemon), that
+//! listens on the `--listen` address and forwards each connection to where
+//! the server itself listens (its `--address`, or its health probe's). It
+//! outlives the server it forwards to: while a crashed server is relaunched,
+//! new connections wait for it instead of being refused, so clients can keep
+//! one well-known address. Connections open when the server goes down end
+//! with it.
+//!
+//! Each connection is relayed by a child of the front, so a stalled one holds
+//! up no other. The front exits when the watch ends (SIGTERM) or when the
+//! process that forked it is gone.
+
+use anyhow::{bail, Context, Result};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::fd::{AsRawFd, OwnedFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// How long a new connection waits for the server to accept it (e.g. while
+/// it is being relaunched) before it is dropped.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long binding waits for the address to be let go of, e.g. by the front
+/// of the instance `use --replace` is replacing.
+const BIND_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How often the front checks that the process that forked it is still
+/// there.
+const PARENT_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// An address split into what it names.
+#[derive(Debug, PartialEq, Eq)]
+enum Target {
+    /// `HOST:PORT`, as `ToSocketAddrs` takes it.
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+/// Split a normalized address (see `address::parse_address`).
+fn target(address: &str) -> Result<Target> {
+    if let Some(path) = address.strip_prefix("unix:") {
+        return Ok(Target::Unix(PathBuf::from(path)));
+    }
+    match address.strip_prefix("tcp:") {
+        Some(host_port) => Ok(Target::Tcp(host_port.to_string())),
+        None => bail!("Invalid address '{}'", address),
+    }
+}
+
+/// The front's listening socket, bound by the process starting the server so
+/// a failure reaches the user.
+pub enum Listener {
+    Tcp(TcpListener),
+    /// Removed by the front when it exits.
+    Unix(UnixListener, PathBuf),
+}
+
+impl Listener {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Listener::Tcp(listener) => listener.as_raw_fd(),
+            Listener::Unix(listener, _) => listener.as_raw_fd(),
+        }
+    }
+
+    fn accept(&self) -> std::io::Result<OwnedFd> {
+        Ok(match self {
+            Listener::Tcp(listener) => listener.accept()?.0.into(),
+            Listener::Unix(listener, _) => listener.accept()?.0.into(),
+        })
+    }
+}
+
+/// Listen on `address` (`tcp:HOST:PORT` or `unix:PATH`). Waits up to
+/// [`BIND_TIMEOUT`] for an address that is in use to be let go of.
+pub fn bind(address: &str) -> Result<Listener> {
+    let target = target(address)?;
+    let start = Instant::now();
+    loop {
+        let in_use = match &target {
+            Target::Tcp(host_port) => match TcpListener::bind(host_port.as_str()) {
+                Ok(listener) => return Ok(Listener::Tcp(listener)),
+                Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => e,
+                Err(e) => {
+                    return Err(e).with_context(|| format!("Failed to listen on {}", address))
+                }
+            },
+            // A socket file nobody answers on is left over, and in the way.
+            Target::Unix(path) => match UnixStream::connect(path) {
+                Ok(_) => std::io::Error::from(std::io::ErrorKind::AddrInUse),
+                Err(_) => {
+                    let _ = std::fs::remove_file(path);
+                    let listener = UnixListener::bind(path)
+                        .with_context(|| format!("Failed to listen on {}", address))?;
+                    return Ok(Listener::Unix(listener, path.clone()));
+                }
+            },
+        };
+        if start.elapsed() >= BIND_TIMEOUT {
+            return Err(in_use).with_context(|| format!("Failed to listen on {}", address));
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+/// Connect to `address`, retrying until [`CONNECT_TIMEOUT`] while nothing
+/// accepts there.
+fn connect(address: &str) -> Result<OwnedFd> {
+    let target = target(address)?;
+    let start = Instant::now();
+    loop {
+        let connected: std::io::Result<OwnedFd> = match &target {
+            Target::Tcp(host_port) => TcpStream::connect(host_port.as_str()).map(Into::into),
+            Target::Unix(path) => UnixStream::connect(path).map(Into::into),
+        };
+        match connected {
+            Ok(fd) => return Ok(fd),
+            Err(e) if start.elapsed() >= CONNECT_TIMEOUT => {
+                return Err(e).with_context(|| format!("Failed to connect to {}", address))
+            }
+            Err(_) => std::thread::sleep(Duration::from_millis(100)),
+        }
+    }
+}
+
+/// A running front.
+pub struct Front {
+    pid: i32,
+}
+
+static STOP_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_sigterm(_: libc::c_int) {
+    STOP_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+impl Front {
+    /// Fork the front, forwarding connections on `listener` to `upstream`.
+    ///
+    /// SAFETY: forks; like the rest of the watcher this relies on the process
+    /// being single-threaded.
+    pub fn spawn(listener: Listener, upstream: &str) -> Result<Self> {
+        let parent = std::process::id() as i32;
+        match unsafe { nix::unistd::fork() } {
+            Ok(nix::unistd::ForkResult::Parent { child }) => Ok(Self {
+                pid: child.as_raw(),
+            }),
+            Ok(nix::unistd::ForkResult::Child) => {
+                // Copies of the watcher's (or daemon's) descriptors would keep
+                // its sockets bound and its log pipes open for as long as the
+                // front runs.
+                close_inherited(listener.as_raw_fd());
+                run_front(listener, upstream, parent);
+                std::process::exit(0);
+            }
+            Err(e) => bail!("Failed to fork the front for {}: {}", upstream, e),
+        }
+    }
+
+    /// The front's PID, for reaping it once it has exited.
+    pub fn pid(&self) -> i32 {
+        self.pid
+    }
+
+    /// Tell the front to stop accepting and exit. Connections it is relaying
+    /// carry on until either side closes them.
+    pub fn stop(&self) {
+        let _ = nix::sys::signal::kill(
+            nix::unistd::Pid::from_raw(self.pid),
+            nix::sys::signal::Signal::SIGTERM,
+        );
+    }
+}
+
+/// Close every descriptor but stdin, stdout, stderr and `keep`, in a process
+/// just forked from one whose sockets and pipes it mustn't hold open.
+pub fn close_inherited(keep: RawFd) {
+    let Ok(entries) = std::fs::read_dir("/dev/fd") else {
+        return;
+    };
+    let fds: Vec<RawFd> = entries
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+        .collect();
+    for fd in fds {
+        if fd > 2 && fd != keep {
+            // SAFETY: nothing in this freshly forked process uses these; the
+            // one read_dir opened is already closed (EBADF is harmless).
+            unsafe { libc::close(fd) };
+        }
+    }
+}
+
+/// Accept connections on `listener` and hand each to a child that forwards
+/// it to `upstream`, until told to stop or `parent` is gone.
+fn run_front(listener: Listener, upstream: &str, parent: i32) {
+    // SAFETY: the handler only sets an atomic flag. No SA_RESTART, so the
+    // signal interrupts poll. Ignoring SIGCHLD has the kernel reap the
+    // connection children.
+    unsafe {
+        let action = nix::sys::signal::SigAction::new(
+            nix::sys::signal::SigHandler::Handler(on_sigterm),
+            nix::sys::signal::SaFlags::empty(),
+            nix::sys::signal::SigSet::empty(),
+        );
+        let _ = nix::sys::signal::sigaction(nix::sys::signal::Signal::SIGTERM, &action);
+        let _ = nix::sys::signal::signal(
+            nix::sys::signal::Signal::SIGCHLD,
+            nix::sys::signal::SigHandler::SigIgn,
+        );
+    }
+
+    while !STOP_REQUESTED.load(Ordering::SeqCst) {
+        if nix::unistd::getppid().as_raw() != parent {
+            break;
+        }
+        let mut fds = [libc::pollfd {
+            fd: listener.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        }];
+        // SAFETY: `fds` is a valid, correctly-sized array of pollfd.
+        let ready = unsafe {
+            libc::poll(
+                fds.as_mut_ptr(),
+                1,
+                PARENT_CHECK_INTERVAL.as_millis() as libc::c_int,
+            )
+        };
+        if ready <= 0 {
+            continue;
+        }
+        let Ok(client) = listener.accept() else {
+            continue;
+        };
+        // SAFETY: as for spawn: the front is single-threaded.
+        match unsafe { nix::unistd::fork() } {
+            Ok(nix::unistd::ForkResult::Child) => {
+                // SAFETY: restoring the default disposition.
+                unsafe {
+                    let _ = nix::sys::signal::signal(
+                        nix::sys::signal::Signal::SIGTERM,
+                        nix::sys::signal::SigHandler::SigDfl,
+                    );
+                }
+                // SAFETY: the listener is the front's, not this child's.
+                unsafe { libc::close(listener.as_raw_fd()) };
+                if let Ok(server) = connect(upstream) {
+                    relay(&File::from(client), &File::from(server));
+                }
+                std::process::exit(0);
+            }
+            // The child has its own copy of the connection.
+            Ok(nix::unistd::ForkResult::Parent { .. }) => drop(client),
+            Err(_) => drop(client),
+        }
+    }
+
+    if let Listener::Unix(_, path) = &listener {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+/// Copy each of `a` and `b` to the other until both have closed. When one
+/// side stops sending, the other is told so (shutdown for writing), since a
+/// client may be waiting for EOF before it answers.
+fn relay(a: &File, b: &File) {
+    let mut sending = [true, true];
+    let mut buf = [0u8; 65536];
+    while sending[0] || sending[1] {
+        let mut fds = [a, b].map(|file| libc::pollfd {
+            fd: file.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        });
+        for (fd, sending) in fds.iter_mut().zip(sending) {
+            if !sending {
+                // poll ignores negative descriptors.
+                fd.fd = -1;
+            }
+        }
+        // SAFETY: `fds` is a valid, correctly-sized array of pollfd.
+        if unsafe { libc::poll(fds.as_mut_ptr(), 2, -1) } < 0 {
+            if std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted {
+                continue;
+            }
+            return;
+        }
+        for (side, (mut from, mut to)) in [(a, b), (b, a)].into_iter().enumerate() {
+            if !sending[side] || fds[side].revents == 0 {
+                continue;
+            }
+            match from.read(&mut buf) {
+                Ok(n) if n > 0 => {
+                    if to.write_all(&buf[..n]).is_err() {
+                        return;
+                    }
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                _ => {
+                    sending[side] = false;
+                    // SAFETY: shutdown on a socket we hold open.
+                    unsafe { libc::shutdown(to.as_raw_fd(), libc::SHUT_WR) };
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Shutdown;
+
+    #[test]
+    fn test_target() {
+        assert_eq!(
+            target("tcp:127.0.0.1:8432").unwrap(),
+            Target::Tcp("127.0.0.1:8432".to_string())
+        );
+        assert_eq!(
+            target("tcp:[::1]:9000").unwrap(),
+            Target::Tcp("[::1]:9000".to_string())
+        );
+        assert_eq!(
+            target("unix:/run/db.sock").unwrap(),
+            Target::Unix(PathBuf::from("/run/db.sock"))
+        );
+        assert!(target("8432").is_err());
+    }
+
+    #[test]
+    fn test_relay_copies_both_ways_and_passes_on_half_close() {
+        let (mut client, client_side) = UnixStream::pair().unwrap();
+        let (server_side, mut server) = UnixStream::pair().unwrap();
+        let relay = std::thread::spawn(move || {
+            relay(
+                &File::from(OwnedFd::from(client_side)),
+                &File::from(OwnedFd::from(server_side)),
+            )
+        });
+
+        let mut buf = [0u8; 4];
+        client.write_all(b"ping").unwrap();
+        server.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+
+        // The client is done sending; the server sees EOF and can still
+        // answer.
+        client.shutdown(Shutdown::Write).unwrap();
+        assert_eq!(server.read(&mut buf).unwrap(), 0);
+        server.write_all(b"pong").unwrap();
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"pong");
+
+        drop(server);
+        assert_eq!(client.read(&mut buf).unwrap(), 0);
+        relay.join().unwrap();
+    }
+}
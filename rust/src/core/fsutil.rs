@@ -0,0 +1,72 @@
+//! Durable filesystem metadata operations for the lock directory.
+//!
+//! Writing a file and calling `fsync` on it makes its *contents* durable, but
+//! not its directory entry: after a power loss, a freshly created lock can be
+//! missing, a renamed backup can reappear under its old name, and a deleted
+//! lock can come back, pointing later commands at a server that's long gone.
+//! Every create, rename, and delete in the lock directory goes through here so
+//! the containing directory is synced too.
+
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+/// Flush `dir`'s entries to disk.
+pub fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+/// Flush the directory holding `path`, after creating or removing it.
+pub fn sync_parent(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => sync_dir(dir),
+        _ => sync_dir(Path::new(".")),
+    }
+}
+
+/// [`std::fs::rename`], then sync the directories on both sides.
+pub fn rename(from: &Path, to: &Path) -> io::Result<()> {
+    std::fs::rename(from, to)?;
+    sync_parent(to)?;
+    if from.parent() != to.parent() {
+        sync_parent(from)?;
+    }
+    Ok(())
+}
+
+/// [`std::fs::remove_file`], then sync its directory.
+pub fn remove_file(path: &Path) -> io::Result<()> {
+    std::fs::remove_file(path)?;
+    sync_parent(path)
+}
+
+/// [`std::fs::remove_dir`] (which fails unless `dir` is empty), then sync its
+/// parent.
+pub fn remove_dir(dir: &Path) -> io::Result<()> {
+    std::fs::remove_dir(dir)?;
+    sync_parent(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rename_and_remove_across_dirs() {
+        let dir = std::env::temp_dir().join(format!("fsutil-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        let from = dir.join("a");
+        let to = dir.join("sub").join("b");
+        std::fs::write(&from, "x").unwrap();
+
+        rename(&from, &to).unwrap();
+        assert!(!from.exists());
+        assert_eq!(std::fs::read_to_string(&to).unwrap(), "x");
+
+        remove_file(&to).unwrap();
+        assert!(!to.exists());
+        assert!(remove_file(&to).is_err());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
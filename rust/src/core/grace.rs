@@ -0,0 +1,264 @@
+//! Grace-period timing across laptop sleep.
+//!
+//! The watcher's monotonic clock (`Instant`: `CLOCK_MONOTONIC` on Linux,
+//! `CLOCK_UPTIME_RAW` on macOS) stops while the machine is suspended, whereas
+//! the wall clock keeps running. Which one a grace period is measured on
+//! decides what happens to a 5m grace that started just before an overnight
+//! sleep:
+//!
+//! - [`GraceClock::Monotonic`] (the default) counts only time awake, so the
+//!   grace resumes with whatever was left when the lid opens.
+//! - [`GraceClock::Wall`] holds to the wall-clock deadline, so the server is
+//!   stopped as soon as the machine wakes.
+//!
+//! Either way the deadline is published in the lock as wall-clock time (for
+//! `info`/`list`/`check`); on the monotonic clock the watcher re-publishes it
+//! whenever the estimate drifts, e.g. after a resume.
+//!
+//! A grace period can also be [`GracePeriod::None`] (stop as soon as the last
+//! client detaches) or [`GracePeriod::Infinite`] (never stop on its own).
+
+use super::duration::parse_duration;
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// Which clock a grace period is measured on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum GraceClock {
+    /// Time the machine is awake; suspend pauses the countdown.
+    #[default]
+    Monotonic,
+    /// Wall-clock time; suspend counts toward the grace period.
+    Wall,
+}
+
+impl GraceClock {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GraceClock::Monotonic => "monotonic",
+            GraceClock::Wall => "wall",
+        }
+    }
+}
+
+impl FromStr for GraceClock {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "monotonic" => Ok(GraceClock::Monotonic),
+            "wall" => Ok(GraceClock::Wall),
+            other => bail!(
+                "Invalid grace clock '{}': expected monotonic or wall",
+                other
+            ),
+        }
+    }
+}
+
+/// How long a server outlives its last client: `--grace-period` parsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GracePeriod {
+    /// `none`: shut down as soon as the last client detaches.
+    None,
+    /// A duration such as `5m`.
+    After(Duration),
+    /// `infinite`: never shut down for lack of clients; only an explicit
+    /// `stop` (or `kill`) takes the server down.
+    Infinite,
+}
+
+impl GracePeriod {
+    /// Length of the countdown, or `None` if there isn't one (`infinite`).
+    pub fn length(&self) -> Option<Duration> {
+        match self {
+            GracePeriod::None => Some(Duration::ZERO),
+            GracePeriod::After(length) => Some(*length),
+            GracePeriod::Infinite => None,
+        }
+    }
+}
+
+impl FromStr for GracePeriod {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "none" => Ok(GracePeriod::None),
+            "infinite" => Ok(GracePeriod::Infinite),
+            _ => parse_duration(s).map(GracePeriod::After),
+        }
+    }
+}
+
+/// A running grace countdown, tracked on both clocks.
+#[derive(Debug, Clone)]
+pub struct GraceTimer {
+    clock: GraceClock,
+    mono_deadline: Instant,
+    wall_deadline: DateTime<Utc>,
+}
+
+impl GraceTimer {
+    /// Start a countdown of `length` at (`now`, `wall_now`).
+    pub fn start(
+        clock: GraceClock,
+        length: Duration,
+        now: Instant,
+        wall_now: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            clock,
+            mono_deadline: instant_after(now, length),
+            wall_deadline: wall_after(wall_now, length),
+        }
+    }
+
+    /// Pick up a countdown whose wall-clock deadline was recorded earlier
+    /// (e.g. by a previous watcher); whatever is left of it is what remains.
+    pub fn resume(
+        clock: GraceClock,
+        wall_deadline: DateTime<Utc>,
+        now: Instant,
+        wall_now: DateTime<Utc>,
+    ) -> Self {
+        let remaining = (wall_deadline - wall_now).to_std().unwrap_or_default();
+        Self {
+            clock,
+            mono_deadline: instant_after(now, remaining),
+            wall_deadline,
+        }
+    }
+
+    /// Time left on this timer's clock.
+    pub fn remaining(&self, now: Instant, wall_now: DateTime<Utc>) -> Duration {
+        match self.clock {
+            GraceClock::Monotonic => self.mono_deadline.saturating_duration_since(now),
+            GraceClock::Wall => (self.wall_deadline - wall_now).to_std().unwrap_or_default(),
+        }
+    }
+
+    pub fn expired(&self, now: Instant, wall_now: DateTime<Utc>) -> bool {
+        self.remaining(now, wall_now).is_zero()
+    }
+
+    /// The wall-clock time the grace will end, as of `wall_now`. Fixed on the
+    /// wall clock; on the monotonic clock it moves later by however long the
+    /// machine slept.
+    pub fn deadline(&self, now: Instant, wall_now: DateTime<Utc>) -> DateTime<Utc> {
+        match self.clock {
+            GraceClock::Monotonic => {
+                wall_after(wall_now, self.mono_deadline.saturating_duration_since(now))
+            }
+            GraceClock::Wall => self.wall_deadline,
+        }
+    }
+}
+
+/// How far out [`instant_after`] settles for when `now + length` is past the
+/// end of `Instant`'s range.
+const FAR_FUTURE: Duration = Duration::from_secs(100 * 365 * 24 * 60 * 60);
+
+/// `now + length`, falling back to a far-future instant rather than panicking
+/// on absurd lengths (`--grace-period 20000000000000w`).
+pub fn instant_after(now: Instant, length: Duration) -> Instant {
+    now.checked_add(length)
+        .or_else(|| now.checked_add(FAR_FUTURE))
+        .unwrap_or(now)
+}
+
+/// `wall_now + length`, saturating rather than panicking on absurd lengths.
+fn wall_after(wall_now: DateTime<Utc>, length: Duration) -> DateTime<Utc> {
+    chrono::Duration::from_std(length)
+        .ok()
+        .and_then(|length| wall_now.checked_add_signed(length))
+        .unwrap_or(DateTime::<Utc>::MAX_UTC)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIVE_MIN: Duration = Duration::from_secs(300);
+    const OVERNIGHT: chrono::Duration = chrono::Duration::hours(8);
+
+    #[test]
+    fn test_parse_grace_clock() {
+        assert_eq!(
+            "monotonic".parse::<GraceClock>().unwrap(),
+            GraceClock::Monotonic
+        );
+        assert_eq!("wall".parse::<GraceClock>().unwrap(), GraceClock::Wall);
+        assert!("boottime".parse::<GraceClock>().is_err());
+    }
+
+    #[test]
+    fn test_parse_grace_period() {
+        assert_eq!("none".parse::<GracePeriod>().unwrap(), GracePeriod::None);
+        assert_eq!(
+            "Infinite".parse::<GracePeriod>().unwrap(),
+            GracePeriod::Infinite
+        );
+        assert_eq!(
+            "5m".parse::<GracePeriod>().unwrap(),
+            GracePeriod::After(FIVE_MIN)
+        );
+        assert_eq!(GracePeriod::None.length(), Some(Duration::ZERO));
+        assert_eq!(GracePeriod::Infinite.length(), None);
+        assert!("forever".parse::<GracePeriod>().is_err());
+        assert!("0s".parse::<GracePeriod>().is_err());
+    }
+
+    #[test]
+    fn test_monotonic_grace_survives_sleep() {
+        // Sleep: the wall clock jumps 8h while the monotonic clock barely moves.
+        let (now, wall_now) = (Instant::now(), Utc::now());
+        let timer = GraceTimer::start(GraceClock::Monotonic, FIVE_MIN, now, wall_now);
+
+        let (later, wall_later) = (now + Duration::from_secs(1), wall_now + OVERNIGHT);
+        assert!(!timer.expired(later, wall_later));
+        assert_eq!(timer.remaining(later, wall_later), Duration::from_secs(299));
+        assert_eq!(
+            timer.deadline(later, wall_later),
+            wall_later + chrono::Duration::seconds(299)
+        );
+    }
+
+    #[test]
+    fn test_wall_grace_expires_across_sleep() {
+        let (now, wall_now) = (Instant::now(), Utc::now());
+        let timer = GraceTimer::start(GraceClock::Wall, FIVE_MIN, now, wall_now);
+        assert!(!timer.expired(now, wall_now));
+
+        let (later, wall_later) = (now + Duration::from_secs(1), wall_now + OVERNIGHT);
+        assert!(timer.expired(later, wall_later));
+        assert_eq!(
+            timer.deadline(later, wall_later),
+            wall_now + chrono::Duration::seconds(300)
+        );
+    }
+
+    #[test]
+    fn test_absurd_grace_does_not_overflow() {
+        let (now, wall_now) = (Instant::now(), Utc::now());
+        let length = Duration::from_secs(u64::MAX);
+        let timer = GraceTimer::start(GraceClock::Monotonic, length, now, wall_now);
+        assert!(!timer.expired(now, wall_now));
+        assert!(timer.deadline(now, wall_now) > wall_now + chrono::Duration::days(365 * 50));
+        let timer = GraceTimer::resume(GraceClock::Wall, DateTime::<Utc>::MAX_UTC, now, wall_now);
+        assert!(!timer.expired(now, wall_now));
+    }
+
+    #[test]
+    fn test_resume_keeps_what_was_left() {
+        let (now, wall_now) = (Instant::now(), Utc::now());
+        let deadline = wall_now + chrono::Duration::seconds(90);
+        let timer = GraceTimer::resume(GraceClock::Monotonic, deadline, now, wall_now);
+        assert_eq!(timer.remaining(now, wall_now), Duration::from_secs(90));
+        assert_eq!(timer.deadline(now, wall_now), deadline);
+    }
+}
@@ -0,0 +1,162 @@
+//! The daemon's gRPC API (`daemon --grpc ADDR`): the messages, client, and
+//! service trait generated from `proto/sharedserver.proto`, and conversions
+//! between them and this crate's types.
+//!
+//! A failed call carries the CLI's exit code in an [`proto::ErrorDetail`]
+//! (see [`status`] and [`exit_code`]), so a client can tell failures apart
+//! the way scripts do with the CLI.
+
+use super::error::ErrorKind;
+use super::events::StateEvent;
+use super::lockfile::{read_clients_lock, read_server_lock};
+use super::state::{get_server_state, ServerState};
+use anyhow::Result;
+use prost::Message;
+use std::time::SystemTime;
+
+/// The generated code for package `sharedserver.v1`.
+pub mod proto {
+    tonic::include_proto!("sharedserver.v1");
+}
+
+/// The `type_url` of an [`proto::ErrorDetail`] packed in a status's details.
+const ERROR_DETAIL_TYPE: &str = "type.googleapis.com/sharedserver.v1.ErrorDetail";
+
+/// `google.rpc.Status`, which gRPC status details are encoded as.
+#[derive(Clone, PartialEq, Message)]
+struct RpcStatus {
+    #[prost(int32, tag = "1")]
+    code: i32,
+    #[prost(string, tag = "2")]
+    message: String,
+    #[prost(message, repeated, tag = "3")]
+    details: Vec<prost_types::Any>,
+}
+
+impl From<ServerState> for proto::State {
+    fn from(state: ServerState) -> Self {
+        match state {
+            ServerState::Active => proto::State::Active,
+            ServerState::Grace => proto::State::Grace,
+            ServerState::Stopped => proto::State::Stopped,
+            ServerState::Defunct => proto::State::Defunct,
+        }
+    }
+}
+
+fn timestamp(at: chrono::DateTime<chrono::Utc>) -> prost_types::Timestamp {
+    SystemTime::from(at).into()
+}
+
+/// `name` as `Info` reports it: its state, and while it runs, its lock and
+/// attached clients.
+pub fn server(name: &str) -> Result<proto::Server> {
+    let state = get_server_state(name)?;
+    let mut server = proto::Server {
+        name: name.to_string(),
+        state: proto::State::from(state).into(),
+        ..Default::default()
+    };
+    if state == ServerState::Stopped {
+        return Ok(server);
+    }
+
+    let lock = read_server_lock(name)?;
+    server.pid = Some(lock.pid);
+    server.watcher_pid = lock.watcher_pid;
+    server.address = lock.address();
+    server.health = lock.health_label().map(str::to_string);
+    server.started_at = Some(timestamp(lock.started_at));
+    server.grace_deadline = lock.grace_deadline.map(timestamp);
+    server.command = lock.command;
+    server.grace_period = lock.grace_period;
+    if let Ok(clients) = read_clients_lock(name) {
+        server.refcount = clients.refcount();
+        server.clients = clients
+            .clients
+            .into_iter()
+            .map(|(pid, info)| proto::Client {
+                pid,
+                attached_at: Some(timestamp(info.attached_at)),
+                metadata: info.metadata,
+                command: info.command,
+                cmdline: info.cmdline.unwrap_or_default(),
+            })
+            .collect();
+    }
+    Ok(server)
+}
+
+/// `event` as `Events` streams it, observed `at`.
+pub fn event(event: &StateEvent, at: chrono::DateTime<chrono::Utc>) -> proto::Event {
+    let kind = match *event {
+        StateEvent::State { from, to } => proto::event::Kind::State(proto::StateChange {
+            from: proto::State::from(from).into(),
+            to: proto::State::from(to).into(),
+        }),
+        StateEvent::ClientAttached { pid } => proto::event::Kind::ClientAttached(pid),
+        StateEvent::ClientDetached { pid } => proto::event::Kind::ClientDetached(pid),
+    };
+    proto::Event {
+        at: Some(timestamp(at)),
+        kind: Some(kind),
+    }
+}
+
+/// The status a failed operation is answered with: the gRPC code its
+/// [`ErrorKind`] maps to, carrying the exit code the CLI would have reported.
+pub fn status(err: &anyhow::Error) -> tonic::Status {
+    let exit_code = super::error::exit_code(err);
+    let code = match ErrorKind::ALL
+        .iter()
+        .find(|kind| kind.exit_code() == exit_code)
+    {
+        Some(ErrorKind::InvalidArgs) => tonic::Code::InvalidArgument,
+        Some(ErrorKind::NotRunning) => tonic::Code::NotFound,
+        Some(ErrorKind::ShuttingDown | ErrorKind::NotAttached) => tonic::Code::FailedPrecondition,
+        Some(ErrorKind::AlreadyRunning) => tonic::Code::AlreadyExists,
+        Some(ErrorKind::LockTimeout | ErrorKind::StartTimeout | ErrorKind::AtCapacity) => {
+            tonic::Code::Unavailable
+        }
+        _ => tonic::Code::Internal,
+    };
+    let message = format!("{:#}", err);
+    let details = RpcStatus {
+        code: code.into(),
+        message: message.clone(),
+        details: vec![prost_types::Any {
+            type_url: ERROR_DETAIL_TYPE.to_string(),
+            value: proto::ErrorDetail { exit_code }.encode_to_vec(),
+        }],
+    };
+    tonic::Status::with_details(code, message, details.encode_to_vec().into())
+}
+
+/// The CLI exit code a status from [`status`] carries, if any.
+pub fn exit_code(status: &tonic::Status) -> Option<i32> {
+    RpcStatus::decode(status.details())
+        .ok()?
+        .details
+        .iter()
+        .find(|any| any.type_url == ERROR_DETAIL_TYPE)
+        .and_then(|any| proto::ErrorDetail::decode(any.value.as_slice()).ok())
+        .map(|detail| detail.exit_code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_carries_code_and_exit_code() {
+        let status = status(&ErrorKind::NotRunning.error("gone"));
+        assert_eq!(status.code(), tonic::Code::NotFound);
+        assert_eq!(status.message(), "gone");
+        assert_eq!(exit_code(&status), Some(10));
+
+        let status = super::status(&anyhow::anyhow!("boom"));
+        assert_eq!(status.code(), tonic::Code::Internal);
+        assert_eq!(exit_code(&status), Some(1));
+        assert_eq!(exit_code(&tonic::Status::internal("plain")), None);
+    }
+}
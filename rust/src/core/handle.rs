@@ -0,0 +1,222 @@
+//! Attaching to a server from a Rust program.
+//!
+//! [`ServerHandle::attach`] takes a reference on a running server and
+//! releases it when the handle is dropped, so an early return or a panic
+//! can't leak it. References still held when the process calls `exit` are
+//! released by an exit hook. (A process that dies outright is caught by the
+//! server's watcher, which drops dead clients.)
+
+use super::error::ErrorKind;
+use super::lockfile::{update_clients_lock, LockUpdate};
+use super::log::{log_invocation, InvocationLog};
+use super::state::{get_server_state, ServerState};
+use super::{ClientInfo, ClientsLock};
+use anyhow::{Context, Result};
+use std::sync::{Mutex, Once};
+
+/// Add `pid` to `name`'s clients. Attaching the same PID again only refreshes
+/// its entry. Returns the new refcount.
+///
+/// Refused once the watcher has committed to shutting the server down: it
+/// commits under the same clients lock, so either we attach first and it sees
+/// us, or the shutdown is already underway.
+pub fn attach_client(name: &str, pid: i32, metadata: Option<String>) -> Result<u32> {
+    let refcount = update_clients_lock(name, |clients| Ok(add_client(clients, pid, metadata)))
+        .context("Failed to increment refcount")?;
+    refcount.ok_or_else(|| shutting_down(name))
+}
+
+/// [`attach_client`]'s update: `None`, leaving the lock alone, if the server
+/// is shutting down.
+pub(crate) fn add_client(
+    clients: &mut ClientsLock,
+    pid: i32,
+    metadata: Option<String>,
+) -> LockUpdate<Option<u32>> {
+    if clients.is_shutting_down() {
+        return LockUpdate::Keep(None);
+    }
+    clients.clients.insert(pid, ClientInfo::new(pid, metadata));
+    LockUpdate::Write(Some(clients.refcount()))
+}
+
+pub(crate) fn shutting_down(name: &str) -> anyhow::Error {
+    ErrorKind::ShuttingDown.error(format!("Server '{}' is shutting down, retry", name))
+}
+
+/// Remove `pid` from `name`'s clients. Returns the new refcount; zero starts
+/// the grace period.
+pub fn detach_client(name: &str, pid: i32) -> Result<u32> {
+    update_clients_lock(name, |clients| remove_client(name, clients, pid))
+        .with_context(|| format!("Failed to decrement refcount for '{}'", name))
+}
+
+/// [`detach_client`]'s update.
+pub(crate) fn remove_client(
+    name: &str,
+    clients: &mut ClientsLock,
+    pid: i32,
+) -> Result<LockUpdate<u32>> {
+    if clients.clients.remove(&pid).is_none() {
+        return Err(ErrorKind::NotAttached.error(format!(
+            "Client {} was not attached to server '{}'",
+            pid, name
+        )));
+    }
+    Ok(LockUpdate::Write(clients.refcount()))
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AttachOptions {
+    /// The client process the reference stands for; the watcher drops it if
+    /// that process dies. Defaults to the current process.
+    pub pid: Option<i32>,
+    /// Shown next to the client in `info`.
+    pub metadata: Option<String>,
+}
+
+/// A reference on a running server, released on drop.
+///
+/// Clients are keyed by PID, so handles on the same server for the same PID
+/// share one reference, which is released when the last of them goes.
+#[derive(Debug)]
+pub struct ServerHandle {
+    name: String,
+    pid: i32,
+    released: bool,
+}
+
+/// Every live handle's `(name, pid)`, for sharing references and for the exit
+/// hook.
+static ATTACHED: Mutex<Vec<(String, i32)>> = Mutex::new(Vec::new());
+static EXIT_HOOK: Once = Once::new();
+
+impl ServerHandle {
+    /// Attach to `name`, which must already be running (started with
+    /// `sharedserver use` or `admin start`).
+    pub fn attach(name: &str, opts: AttachOptions) -> Result<Self> {
+        attachable(name, get_server_state(name)?)?;
+        let pid = opts.pid.unwrap_or(std::process::id() as i32);
+        let refcount = attach_client(name, pid, opts.metadata.clone())?;
+        Ok(Self::attached(name, pid, refcount, opts.metadata))
+    }
+
+    /// The handle for a reference just taken by [`attach_client`].
+    pub(crate) fn attached(name: &str, pid: i32, refcount: u32, metadata: Option<String>) -> Self {
+        let _ = log_invocation(
+            name,
+            &InvocationLog::success(
+                "incref",
+                &[name.to_string()],
+                Some(serde_json::json!({
+                    "new_refcount": refcount,
+                    "client_pid": pid,
+                    "start_time": super::process_start_stamp(pid),
+                    "metadata": metadata,
+                    "via": "library",
+                })),
+            ),
+        );
+
+        EXIT_HOOK.call_once(|| {
+            // SAFETY: registering a plain `extern "C"` function; failure only
+            // means the hook won't run.
+            unsafe { libc::atexit(release_at_exit) };
+        });
+        lock_attached().push((name.to_string(), pid));
+
+        Self {
+            name: name.to_string(),
+            pid,
+            released: false,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The client PID the reference stands for.
+    pub fn pid(&self) -> i32 {
+        self.pid
+    }
+
+    /// Release the reference now, reporting failure (which dropping the
+    /// handle would swallow). Returns the server's refcount afterwards.
+    pub fn detach(mut self) -> Result<u32> {
+        self.released = true;
+        self.release()
+    }
+
+    fn release(&self) -> Result<u32> {
+        let shared = {
+            let mut attached = lock_attached();
+            let entry = (self.name.clone(), self.pid);
+            if let Some(index) = attached.iter().position(|e| *e == entry) {
+                attached.remove(index);
+            }
+            attached.contains(&entry)
+        };
+        if shared {
+            return Ok(super::read_clients_lock(&self.name)?.refcount());
+        }
+        let refcount = detach_client(&self.name, self.pid)?;
+        let _ = log_invocation(
+            &self.name,
+            &InvocationLog::success(
+                "decref",
+                std::slice::from_ref(&self.name),
+                Some(serde_json::json!({
+                    "new_refcount": refcount,
+                    "client_pid": self.pid,
+                    "via": "library",
+                })),
+            ),
+        );
+        Ok(refcount)
+    }
+}
+
+impl Drop for ServerHandle {
+    fn drop(&mut self) {
+        if !self.released {
+            let _ = self.release();
+        }
+    }
+}
+
+/// Refuse to attach to a server in `state` that isn't up.
+pub(crate) fn attachable(name: &str, state: ServerState) -> Result<()> {
+    match state {
+        ServerState::Active | ServerState::Grace => Ok(()),
+        ServerState::Stopped => {
+            Err(ErrorKind::NotRunning.error(format!("Server '{}' is not running", name)))
+        }
+        ServerState::Defunct => Err(ErrorKind::ShuttingDown.error(format!(
+            "Server '{}' is shutting down (defunct, cleanup pending). Retry shortly.",
+            name
+        ))),
+    }
+}
+
+/// A panic while the list was held leaves it intact, so keep using it.
+fn lock_attached() -> std::sync::MutexGuard<'static, Vec<(String, i32)>> {
+    ATTACHED
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Release the references of handles still alive at `exit`. Best effort: a
+/// handle held by a thread that's mid-update is left to the watcher.
+extern "C" fn release_at_exit() {
+    let Ok(mut attached) = ATTACHED.try_lock() else {
+        return;
+    };
+    let mut released: Vec<(String, i32)> = Vec::new();
+    for (name, pid) in attached.drain(..) {
+        if !released.contains(&(name.clone(), pid)) {
+            let _ = detach_client(&name, pid);
+            released.push((name, pid));
+        }
+    }
+}
@@ -175,13 +175,301 @@ pub fn process_liveness_checked(pid: i32, expected_stamp: Option<u64>) -> Livene
     let liveness = process_liveness(pid);
     match (liveness, expected_stamp) {
         (Liveness::Alive | Liveness::Zombie, Some(expected)) => match process_start_stamp(pid) {
-            Some(current) if current != expected => Liveness::Gone, // PID reused
+            Some(current) if current != expected => {
+                log::debug!(
+                    "PID {} was reused (start stamp {}, recorded {}): treating as gone",
+                    pid,
+                    current,
+                    expected
+                );
+                Liveness::Gone
+            }
             _ => liveness,
         },
         _ => liveness,
     }
 }
 
+/// An identifier for the current boot of the machine, so state recorded before
+/// a reboot can be told apart from state written since: PIDs (and start
+/// stamps, which count from boot) mean nothing across a reboot.
+///
+/// Linux: `/proc/sys/kernel/random/boot_id`.
+/// macOS: the `kern.bootsessionuuid` sysctl.
+/// Other platforms: always `None` (the check is skipped).
+#[cfg(target_os = "linux")]
+pub fn boot_id() -> Option<String> {
+    let id = std::fs::read_to_string("/proc/sys/kernel/random/boot_id").ok()?;
+    Some(id.trim().to_string()).filter(|id| !id.is_empty())
+}
+
+#[cfg(target_os = "macos")]
+pub fn boot_id() -> Option<String> {
+    let mut buf = [0u8; 64];
+    let mut len = buf.len();
+    // SAFETY: sysctlbyname writes at most `len` bytes into `buf` and updates
+    // `len` to the number written.
+    let result = unsafe {
+        libc::sysctlbyname(
+            c"kern.bootsessionuuid".as_ptr(),
+            buf.as_mut_ptr() as *mut libc::c_void,
+            &mut len,
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+    if result != 0 {
+        return None;
+    }
+    let id = std::ffi::CStr::from_bytes_until_nul(&buf[..len]).ok()?;
+    Some(id.to_string_lossy().into_owned()).filter(|id| !id.is_empty())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn boot_id() -> Option<String> {
+    None
+}
+
+/// The short name of a process (its `comm`, e.g. `nvim`), for telling humans
+/// which program a PID is.
+///
+/// Linux: `/proc/<pid>/comm`.
+/// macOS: `proc_name()`.
+/// Other platforms: always `None`.
+#[cfg(target_os = "linux")]
+pub fn process_name(pid: i32) -> Option<String> {
+    let comm = std::fs::read_to_string(format!("/proc/{}/comm", pid)).ok()?;
+    Some(comm.trim_end().to_string()).filter(|comm| !comm.is_empty())
+}
+
+#[cfg(target_os = "macos")]
+pub fn process_name(pid: i32) -> Option<String> {
+    let mut buf = [0u8; 256];
+    // SAFETY: proc_name writes at most `buf.len()` bytes and returns the count.
+    let len = unsafe { libc::proc_name(pid, buf.as_mut_ptr() as *mut _, buf.len() as u32) };
+    if len <= 0 {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&buf[..len as usize]).into_owned())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn process_name(_pid: i32) -> Option<String> {
+    None
+}
+
+/// A process's argument vector. `None` if it can't be read (gone, another
+/// user's on a hardened system, or a kernel thread with no arguments).
+///
+/// Linux: `/proc/<pid>/cmdline`.
+/// macOS: the `KERN_PROCARGS2` sysctl.
+/// Other platforms: always `None`.
+#[cfg(target_os = "linux")]
+pub fn process_cmdline(pid: i32) -> Option<Vec<String>> {
+    let raw = std::fs::read(format!("/proc/{}/cmdline", pid)).ok()?;
+    let args: Vec<String> = raw
+        .split(|&byte| byte == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(|arg| String::from_utf8_lossy(arg).into_owned())
+        .collect();
+    Some(args).filter(|args| !args.is_empty())
+}
+
+#[cfg(target_os = "macos")]
+pub fn process_cmdline(pid: i32) -> Option<Vec<String>> {
+    let mut mib = [libc::CTL_KERN, libc::KERN_PROCARGS2, pid];
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut len = buf.len();
+    // SAFETY: sysctl writes at most `len` bytes into `buf` and updates `len`.
+    let result = unsafe {
+        libc::sysctl(
+            mib.as_mut_ptr(),
+            mib.len() as u32,
+            buf.as_mut_ptr() as *mut libc::c_void,
+            &mut len,
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+    if result != 0 {
+        return None;
+    }
+    parse_procargs2(&buf[..len])
+}
+
+/// Decode a `KERN_PROCARGS2` buffer: `argc` (a native `i32`), the executable
+/// path, NUL padding, then `argc` NUL-terminated arguments.
+#[cfg(target_os = "macos")]
+fn parse_procargs2(buf: &[u8]) -> Option<Vec<String>> {
+    let argc = i32::from_ne_bytes(buf.get(..4)?.try_into().ok()?);
+    let rest = &buf[4..];
+    let path_end = rest.iter().position(|&byte| byte == 0)?;
+    let args: Vec<String> = rest[path_end..]
+        .split(|&byte| byte == 0)
+        .filter(|arg| !arg.is_empty())
+        .take(argc.max(0) as usize)
+        .map(|arg| String::from_utf8_lossy(arg).into_owned())
+        .collect();
+    Some(args).filter(|args| !args.is_empty())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn process_cmdline(_pid: i32) -> Option<Vec<String>> {
+    None
+}
+
+/// The PID of a process's parent, for walking up the process tree. `None` if
+/// the process is gone.
+///
+/// Linux: field 4 of `/proc/<pid>/stat`.
+/// macOS: `pbi_ppid` from `proc_bsdinfo`.
+/// Other platforms: always `None`.
+#[cfg(target_os = "linux")]
+pub fn parent_pid(pid: i32) -> Option<i32> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    parent_from_proc_stat(&stat)
+}
+
+/// The parent PID from the contents of `/proc/<pid>/stat`: the token after
+/// the state, past `comm`'s closing ')'.
+#[cfg(target_os = "linux")]
+fn parent_from_proc_stat(stat: &str) -> Option<i32> {
+    let rest = stat.rsplit_once(')')?.1;
+    rest.split_whitespace().nth(1)?.parse().ok()
+}
+
+#[cfg(target_os = "macos")]
+pub fn parent_pid(pid: i32) -> Option<i32> {
+    use libc::{c_int, proc_pidinfo, PROC_PIDTBSDINFO};
+    use std::mem;
+
+    unsafe {
+        let mut info: libc::proc_bsdinfo = mem::zeroed();
+        let size = mem::size_of::<libc::proc_bsdinfo>() as c_int;
+        let result = proc_pidinfo(
+            pid,
+            PROC_PIDTBSDINFO,
+            0,
+            &mut info as *mut _ as *mut _,
+            size,
+        );
+        (result > 0).then_some(info.pbi_ppid as i32)
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn parent_pid(_pid: i32) -> Option<i32> {
+    None
+}
+
+/// A process's current working directory. `None` if it is gone or can't be
+/// inspected (another user's).
+///
+/// Linux: the `/proc/<pid>/cwd` link.
+/// macOS: `pvi_cdir` from `proc_vnodepathinfo`.
+/// Other platforms: always `None`.
+#[cfg(target_os = "linux")]
+pub fn process_cwd(pid: i32) -> Option<std::path::PathBuf> {
+    std::fs::read_link(format!("/proc/{}/cwd", pid)).ok()
+}
+
+#[cfg(target_os = "macos")]
+pub fn process_cwd(pid: i32) -> Option<std::path::PathBuf> {
+    use libc::{c_int, proc_pidinfo, PROC_PIDVNODEPATHINFO};
+    use std::ffi::CStr;
+    use std::mem;
+
+    // SAFETY: proc_pidinfo fills at most `size` bytes of `info`, and the
+    // kernel NUL-terminates the path it writes into `vip_path`.
+    unsafe {
+        let mut info: libc::proc_vnodepathinfo = mem::zeroed();
+        let size = mem::size_of::<libc::proc_vnodepathinfo>() as c_int;
+        let result = proc_pidinfo(
+            pid,
+            PROC_PIDVNODEPATHINFO,
+            0,
+            &mut info as *mut _ as *mut _,
+            size,
+        );
+        if result <= 0 {
+            return None;
+        }
+        let path = CStr::from_ptr(info.pvi_cdir.vip_path.as_ptr() as *const libc::c_char);
+        Some(std::path::PathBuf::from(path.to_str().ok()?)).filter(|p| p.is_absolute())
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn process_cwd(_pid: i32) -> Option<std::path::PathBuf> {
+    None
+}
+
+/// The PIDs of a process's children, in ascending order. Empty if it has none
+/// or is gone.
+///
+/// Linux: each thread's `/proc/<pid>/task/<tid>/children`, or a scan of
+/// `/proc` for kernels built without them.
+/// macOS: `proc_listchildpids()`.
+/// Other platforms: always empty.
+#[cfg(target_os = "linux")]
+pub fn child_pids(pid: i32) -> Vec<i32> {
+    let mut children = Vec::new();
+    let tasks = std::fs::read_dir(format!("/proc/{}/task", pid));
+    let mut listed = false;
+    for task in tasks.into_iter().flatten().flatten() {
+        if let Ok(list) = std::fs::read_to_string(task.path().join("children")) {
+            listed = true;
+            children.extend(
+                list.split_whitespace()
+                    .filter_map(|c| c.parse::<i32>().ok()),
+            );
+        }
+    }
+    if !listed {
+        let entries = std::fs::read_dir("/proc").into_iter().flatten().flatten();
+        children = entries
+            .filter_map(|entry| entry.file_name().to_str()?.parse::<i32>().ok())
+            .filter(|&child| parent_pid(child) == Some(pid))
+            .collect();
+    }
+    children.sort_unstable();
+    children.dedup();
+    children
+}
+
+#[cfg(target_os = "macos")]
+pub fn child_pids(pid: i32) -> Vec<i32> {
+    let mut buf = vec![0 as libc::pid_t; 1024];
+    let size = (buf.len() * std::mem::size_of::<libc::pid_t>()) as libc::c_int;
+    // SAFETY: proc_listchildpids writes at most `size` bytes of PIDs into
+    // `buf` and returns how many it wrote.
+    let count = unsafe { libc::proc_listchildpids(pid, buf.as_mut_ptr() as *mut _, size) };
+    buf.truncate(count.max(0) as usize);
+    buf.sort_unstable();
+    buf
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn child_pids(_pid: i32) -> Vec<i32> {
+    Vec::new()
+}
+
+/// Whether `pid` is `ancestor` or one of its descendants.
+pub fn is_descendant(pid: i32, ancestor: i32) -> bool {
+    // Bounded, in case a PID is recycled mid-walk into a loop.
+    let mut pid = pid;
+    for _ in 0..64 {
+        if pid == ancestor {
+            return true;
+        }
+        match parent_pid(pid) {
+            Some(parent) if parent > 0 && parent != pid => pid = parent,
+            _ => return false,
+        }
+    }
+    false
+}
+
 // Platform-specific parsing tests (the raw stat/bsd-status decoders).
 #[cfg(all(test, target_os = "linux"))]
 mod tests_linux {
@@ -194,6 +482,35 @@ mod tests_linux {
         assert_eq!(liveness_from_proc_stat(stat), Liveness::Alive);
     }
 
+    #[test]
+    fn parent_is_read_past_comm() {
+        let stat = "1234 (my proc (x)) S 987 1234 1234 0 -1 4194560 0";
+        assert_eq!(parent_from_proc_stat(stat), Some(987));
+        assert_eq!(parent_from_proc_stat("garbage"), None);
+    }
+
+    #[test]
+    fn this_process_descends_from_its_parent() {
+        let me = std::process::id() as i32;
+        let parent = nix::unistd::getppid().as_raw();
+        assert_eq!(parent_pid(me), Some(parent));
+        assert!(is_descendant(me, parent));
+        assert!(is_descendant(me, me));
+        assert!(!is_descendant(parent, me));
+    }
+
+    #[test]
+    fn children_are_listed() {
+        let mut child = std::process::Command::new("sleep")
+            .arg("5")
+            .spawn()
+            .unwrap();
+        let me = std::process::id() as i32;
+        assert!(child_pids(me).contains(&(child.id() as i32)));
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+
     #[test]
     fn zombie_process_is_zombie() {
         // State Z must be reported as Zombie even though /proc/<pid>/stat exists.
@@ -221,11 +538,50 @@ mod tests_macos {
         assert_eq!(liveness_from_bsd_status(648, 2), Liveness::Alive);
     }
 
+    #[test]
+    fn parent_is_read_past_comm() {
+        let stat = "1234 (my proc (x)) S 987 1234 1234 0 -1 4194560 0";
+        assert_eq!(parent_from_proc_stat(stat), Some(987));
+        assert_eq!(parent_from_proc_stat("garbage"), None);
+    }
+
+    #[test]
+    fn this_process_descends_from_its_parent() {
+        let me = std::process::id() as i32;
+        let parent = nix::unistd::getppid().as_raw();
+        assert_eq!(parent_pid(me), Some(parent));
+        assert!(is_descendant(me, parent));
+        assert!(is_descendant(me, me));
+        assert!(!is_descendant(parent, me));
+    }
+
+    #[test]
+    fn children_are_listed() {
+        let mut child = std::process::Command::new("sleep")
+            .arg("5")
+            .spawn()
+            .unwrap();
+        let me = std::process::id() as i32;
+        assert!(child_pids(me).contains(&(child.id() as i32)));
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+
     #[test]
     fn zombie_process_is_zombie() {
         assert_eq!(liveness_from_bsd_status(648, libc::SZOMB), Liveness::Zombie);
     }
 
+    #[test]
+    fn procargs2_yields_argv() {
+        let mut buf = 2i32.to_ne_bytes().to_vec();
+        buf.extend_from_slice(b"/usr/bin/nvim\0\0\0nvim\0--headless\0PATH=/bin\0");
+        assert_eq!(
+            parse_procargs2(&buf),
+            Some(vec!["nvim".to_string(), "--headless".to_string()])
+        );
+    }
+
     #[test]
     fn no_such_process_is_gone() {
         // proc_pidinfo returns <= 0 when the pid is gone or the call fails.
@@ -252,6 +608,20 @@ mod tests_common {
         assert!(!is_process_alive(0));
     }
 
+    #[test]
+    fn name_and_cmdline_are_readable_for_self() {
+        let pid = std::process::id() as i32;
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        {
+            assert!(process_name(pid).is_some());
+            let cmdline = process_cmdline(pid).unwrap();
+            let exe = std::env::args().next().unwrap();
+            assert_eq!(cmdline[0], exe);
+            assert_eq!(process_cwd(pid), std::env::current_dir().ok());
+        }
+        assert_eq!(process_name(0), None);
+    }
+
     #[test]
     fn start_stamp_is_readable_for_self() {
         assert!(process_start_stamp(std::process::id() as i32).is_some());
@@ -277,6 +647,13 @@ mod tests_common {
         assert_eq!(process_liveness_checked(pid, Some(wrong)), Liveness::Gone);
     }
 
+    #[test]
+    fn boot_id_is_stable() {
+        let id = boot_id();
+        assert!(id.is_some());
+        assert_eq!(boot_id(), id);
+    }
+
     #[test]
     fn checked_liveness_falls_back_without_stamp() {
         // Legacy lock (no recorded stamp) -> plain liveness, no false "gone".
@@ -0,0 +1,67 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// How often the watcher refreshes its heartbeat.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A live watcher whose heartbeat is older than this is considered wedged.
+/// Generous enough to cover the longest thing the watcher legitimately blocks
+/// on (a SIGTERM → SIGKILL escalation, or a slow health probe).
+pub const HEARTBEAT_STALE_AFTER: Duration = Duration::from_secs(30);
+
+/// Get path to the watcher heartbeat file
+pub fn heartbeat_path(name: &str) -> Result<PathBuf> {
+    super::lockfile::server_file(name, "watcher.heartbeat")
+}
+
+/// Record that the watcher for `name` is alive and looping right now.
+pub fn write_heartbeat(name: &str) -> Result<()> {
+    let path = heartbeat_path(name)?;
+    super::lockfile::write_private(&path, Utc::now().to_rfc3339())
+        .with_context(|| format!("Failed to write heartbeat: {:?}", path))
+}
+
+/// When the watcher last heartbeat, or `None` if it never has (e.g. a watcher
+/// from a release that predates heartbeats).
+pub fn read_heartbeat(name: &str) -> Option<DateTime<Utc>> {
+    let contents = std::fs::read_to_string(heartbeat_path(name).ok()?).ok()?;
+    DateTime::parse_from_rfc3339(contents.trim())
+        .ok()
+        .map(|t| t.with_timezone(&Utc))
+}
+
+/// Time since the last heartbeat, if there is one.
+pub fn heartbeat_age(name: &str) -> Option<Duration> {
+    let last = read_heartbeat(name)?;
+    // A heartbeat from the future (clock stepped back) counts as fresh.
+    Some((Utc::now() - last).to_std().unwrap_or_default())
+}
+
+/// Whether a heartbeat of this age means the watcher has stopped looping.
+pub fn is_stale(age: Duration) -> bool {
+    age > HEARTBEAT_STALE_AFTER
+}
+
+/// Delete the heartbeat file
+pub fn delete_heartbeat(name: &str) -> Result<()> {
+    let path = heartbeat_path(name)?;
+    if path.exists() {
+        super::fsutil::remove_file(&path)
+            .with_context(|| format!("Failed to delete heartbeat: {:?}", path))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_staleness_threshold() {
+        assert!(!is_stale(Duration::from_secs(1)));
+        assert!(!is_stale(HEARTBEAT_STALE_AFTER));
+        assert!(is_stale(HEARTBEAT_STALE_AFTER + Duration::from_secs(1)));
+    }
+}
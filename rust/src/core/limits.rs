@@ -0,0 +1,381 @@
+use super::duration::parse_duration;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// What the watcher does once a resource limit has been exceeded for the
+/// configured sustain window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LimitAction {
+    /// Record the breach in the invocation log and leave the server running.
+    #[default]
+    Log,
+    /// Restart the server in place (clients stay attached).
+    Restart,
+    /// Take the server down.
+    Stop,
+}
+
+impl LimitAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LimitAction::Log => "log",
+            LimitAction::Restart => "restart",
+            LimitAction::Stop => "stop",
+        }
+    }
+}
+
+impl FromStr for LimitAction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "log" => Ok(LimitAction::Log),
+            "restart" => Ok(LimitAction::Restart),
+            "stop" => Ok(LimitAction::Stop),
+            other => bail!(
+                "Invalid limit action '{}': expected log, restart, or stop",
+                other
+            ),
+        }
+    }
+}
+
+/// Memory/CPU ceilings the watcher enforces on the server's process group.
+///
+/// Sizes, percentages, and durations are kept as the user wrote them (like
+/// `grace_period`) and parsed when used.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResourceLimits {
+    /// Resident memory ceiling, e.g. "512M" or "2G".
+    #[serde(default)]
+    pub memory_limit: Option<String>,
+    #[serde(default)]
+    pub memory_action: LimitAction,
+    /// CPU ceiling as a percentage of one core, e.g. "90" or "150%".
+    #[serde(default)]
+    pub cpu_limit: Option<String>,
+    #[serde(default)]
+    pub cpu_action: LimitAction,
+    /// How long a limit must stay exceeded before its action fires.
+    pub sustained: String,
+}
+
+impl ResourceLimits {
+    pub fn memory_limit_bytes(&self) -> Result<Option<u64>> {
+        self.memory_limit.as_deref().map(parse_size).transpose()
+    }
+
+    pub fn cpu_limit_percent(&self) -> Result<Option<f64>> {
+        self.cpu_limit.as_deref().map(parse_percent).transpose()
+    }
+
+    pub fn sustained(&self) -> Result<Duration> {
+        parse_duration(&self.sustained)
+            .with_context(|| format!("Invalid limit sustain window: {}", self.sustained))
+    }
+
+    /// Validate up front so a bad value is reported at start rather than
+    /// silently disabling the limit inside the watcher.
+    pub fn validate(&self) -> Result<()> {
+        self.memory_limit_bytes()?;
+        self.cpu_limit_percent()?;
+        self.sustained()?;
+        Ok(())
+    }
+}
+
+/// The latest resource sample, recorded in the server lock by the watcher.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResourceUsage {
+    /// Resident set size summed over the server's process group.
+    pub rss_bytes: u64,
+    /// CPU use since the previous sample, as a percentage of one core. `None`
+    /// for the first sample.
+    pub cpu_percent: Option<f64>,
+    pub sampled_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Parse a size like "512M", "2G", "1.5GiB", or a plain byte count. Units are
+/// binary (K = 1024).
+pub fn parse_size(s: &str) -> Result<u64> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let value: f64 = number
+        .parse()
+        .with_context(|| format!("Invalid size: {}", s))?;
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        _ => bail!("Invalid size unit in '{}': expected K, M, G, or T", s),
+    };
+    let bytes = value * multiplier as f64;
+    if bytes < 1.0 || bytes > u64::MAX as f64 {
+        bail!("Size must be greater than zero: {}", s);
+    }
+    Ok(bytes as u64)
+}
+
+/// Parse a CPU percentage like "90" or "150%".
+pub fn parse_percent(s: &str) -> Result<f64> {
+    let trimmed = s.trim();
+    let value: f64 = trimmed
+        .strip_suffix('%')
+        .unwrap_or(trimmed)
+        .trim()
+        .parse()
+        .with_context(|| format!("Invalid CPU percentage: {}", s))?;
+    if !(value > 0.0 && value.is_finite()) {
+        bail!("CPU percentage must be greater than zero: {}", s);
+    }
+    Ok(value)
+}
+
+/// Raw counters for one sample of a process group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProcessSample {
+    pub rss_bytes: u64,
+    /// Total user + system CPU time consumed so far.
+    pub cpu_time: Duration,
+}
+
+impl ProcessSample {
+    /// CPU use between `earlier` and this sample, taken `elapsed` apart, as a
+    /// percentage of one core.
+    pub fn cpu_percent_since(&self, earlier: &ProcessSample, elapsed: Duration) -> Option<f64> {
+        let wall = elapsed.as_secs_f64();
+        (wall > 0.0)
+            .then(|| self.cpu_time.saturating_sub(earlier.cpu_time).as_secs_f64() / wall * 100.0)
+    }
+}
+
+/// Sample memory and CPU time for the server.
+///
+/// Linux: summed over every process in process group `pgid` (the server runs
+/// as its own group leader, so this covers e.g. `uv` → `python`).
+/// macOS: the group leader only (`proc_pidinfo` has no group query).
+/// Other platforms: `None`.
+#[cfg(target_os = "linux")]
+pub fn sample_process_group(pgid: i32) -> Option<ProcessSample> {
+    // SAFETY: sysconf has no memory-safety preconditions.
+    let (page_size, ticks_per_sec) = unsafe {
+        (
+            libc::sysconf(libc::_SC_PAGESIZE),
+            libc::sysconf(libc::_SC_CLK_TCK),
+        )
+    };
+    if page_size <= 0 || ticks_per_sec <= 0 {
+        return None;
+    }
+
+    let mut found = false;
+    let mut rss_pages = 0u64;
+    let mut ticks = 0u64;
+    for entry in std::fs::read_dir("/proc").ok()?.flatten() {
+        let file_name = entry.file_name();
+        let Some(pid) = file_name
+            .to_str()
+            .filter(|n| n.bytes().all(|b| b.is_ascii_digit()))
+        else {
+            continue;
+        };
+        let Ok(stat) = std::fs::read_to_string(format!("/proc/{}/stat", pid)) else {
+            continue;
+        };
+        if let Some((pgrp, utime, stime, rss)) = parse_proc_stat_usage(&stat) {
+            if pgrp == pgid {
+                found = true;
+                ticks += utime + stime;
+                rss_pages += rss;
+            }
+        }
+    }
+
+    found.then(|| ProcessSample {
+        rss_bytes: rss_pages * page_size as u64,
+        cpu_time: Duration::from_secs_f64(ticks as f64 / ticks_per_sec as f64),
+    })
+}
+
+/// Extract `(pgrp, utime, stime, rss)` from `/proc/<pid>/stat`.
+#[cfg(target_os = "linux")]
+fn parse_proc_stat_usage(stat: &str) -> Option<(i32, u64, u64, u64)> {
+    // Fields after the comm's closing ')' start at `state` (field 3): pgrp is
+    // field 5, utime 14, stime 15, rss 24 (in pages).
+    let fields: Vec<&str> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+    Some((
+        fields.get(2)?.parse().ok()?,
+        fields.get(11)?.parse().ok()?,
+        fields.get(12)?.parse().ok()?,
+        fields.get(21)?.parse().ok()?,
+    ))
+}
+
+#[cfg(target_os = "macos")]
+pub fn sample_process_group(pgid: i32) -> Option<ProcessSample> {
+    use libc::{c_int, proc_pidinfo, PROC_PIDTASKINFO};
+    use std::mem;
+
+    unsafe {
+        let mut info: libc::proc_taskinfo = mem::zeroed();
+        let size = mem::size_of::<libc::proc_taskinfo>() as c_int;
+        let result = proc_pidinfo(
+            pgid,
+            PROC_PIDTASKINFO,
+            0,
+            &mut info as *mut _ as *mut _,
+            size,
+        );
+        if result <= 0 {
+            return None;
+        }
+        Some(ProcessSample {
+            rss_bytes: info.pti_resident_size,
+            cpu_time: Duration::from_nanos(info.pti_total_user + info.pti_total_system),
+        })
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn sample_process_group(_pgid: i32) -> Option<ProcessSample> {
+    None
+}
+
+/// Sample several servers' process groups twice, `window` apart, for their
+/// memory and CPU use over that window. Groups that can't be sampled (gone,
+/// or an unsupported platform) are left out.
+pub fn sample_usage(pgids: &[i32], window: Duration) -> HashMap<i32, ResourceUsage> {
+    let first: Vec<_> = pgids
+        .iter()
+        .filter_map(|&pgid| Some((pgid, sample_process_group(pgid)?, Instant::now())))
+        .collect();
+    if first.is_empty() {
+        return HashMap::new();
+    }
+    std::thread::sleep(window);
+
+    first
+        .into_iter()
+        .filter_map(|(pgid, earlier, at)| {
+            let sample = sample_process_group(pgid)?;
+            let usage = ResourceUsage {
+                rss_bytes: sample.rss_bytes,
+                cpu_percent: sample.cpu_percent_since(&earlier, at.elapsed()),
+                sampled_at: chrono::Utc::now(),
+            };
+            Some((pgid, usage))
+        })
+        .collect()
+}
+
+/// Tracks one limit across samples and decides when its action fires: once
+/// per breach, after the value has stayed over the limit for the whole
+/// sustain window. Dropping back under the limit re-arms it.
+#[derive(Debug, Default)]
+pub struct BreachTracker {
+    over_since: Option<Instant>,
+    fired: bool,
+}
+
+impl BreachTracker {
+    /// Feed one observation. Returns `true` exactly when the action should
+    /// fire.
+    pub fn observe(&mut self, over: bool, now: Instant, sustained: Duration) -> bool {
+        if !over {
+            *self = Self::default();
+            return false;
+        }
+        let since = *self.over_since.get_or_insert(now);
+        if !self.fired && now.duration_since(since) >= sustained {
+            self.fired = true;
+            return true;
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("1024").unwrap(), 1024);
+        assert_eq!(parse_size("512M").unwrap(), 512 << 20);
+        assert_eq!(parse_size("2G").unwrap(), 2 << 30);
+        assert_eq!(parse_size("1.5GiB").unwrap(), 3 << 29);
+        assert_eq!(parse_size("64kb").unwrap(), 64 << 10);
+        assert!(parse_size("").is_err());
+        assert!(parse_size("0").is_err());
+        assert!(parse_size("2X").is_err());
+    }
+
+    #[test]
+    fn test_parse_percent() {
+        assert_eq!(parse_percent("90").unwrap(), 90.0);
+        assert_eq!(parse_percent("150%").unwrap(), 150.0);
+        assert!(parse_percent("0").is_err());
+        assert!(parse_percent("lots").is_err());
+    }
+
+    #[test]
+    fn test_breach_fires_once_after_sustain_window() {
+        let window = Duration::from_secs(10);
+        let t0 = Instant::now();
+        let mut tracker = BreachTracker::default();
+
+        assert!(!tracker.observe(true, t0, window));
+        assert!(!tracker.observe(true, t0 + Duration::from_secs(5), window));
+        assert!(tracker.observe(true, t0 + Duration::from_secs(10), window));
+        // Still over: don't fire again for the same breach.
+        assert!(!tracker.observe(true, t0 + Duration::from_secs(20), window));
+        // Dropping under re-arms it.
+        assert!(!tracker.observe(false, t0 + Duration::from_secs(21), window));
+        assert!(!tracker.observe(true, t0 + Duration::from_secs(22), window));
+        assert!(tracker.observe(true, t0 + Duration::from_secs(32), window));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_sample_own_process_group() {
+        let pgid = nix::unistd::getpgrp().as_raw();
+        let sample = sample_process_group(pgid).expect("own group should be sampled");
+        assert!(sample.rss_bytes > 0);
+    }
+
+    #[test]
+    fn test_cpu_percent_since() {
+        let earlier = ProcessSample {
+            rss_bytes: 0,
+            cpu_time: Duration::from_millis(1000),
+        };
+        let later = ProcessSample {
+            rss_bytes: 0,
+            cpu_time: Duration::from_millis(1500),
+        };
+        let percent = later
+            .cpu_percent_since(&earlier, Duration::from_secs(1))
+            .unwrap();
+        assert!((percent - 50.0).abs() < 1e-9);
+        assert_eq!(later.cpu_percent_since(&earlier, Duration::ZERO), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_sample_usage_skips_missing_groups() {
+        let pgid = nix::unistd::getpgrp().as_raw();
+        let usage = sample_usage(&[pgid, i32::MAX], Duration::from_millis(10));
+        assert!(usage[&pgid].cpu_percent.is_some());
+        assert!(!usage.contains_key(&i32::MAX));
+    }
+}
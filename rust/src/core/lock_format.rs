@@ -0,0 +1,121 @@
+//! How lockfiles are encoded on disk.
+//!
+//! Pretty-printed JSON by default, so the files can be read with `cat` or `jq`.
+//! Builds with the `msgpack` feature can write MessagePack instead, which is
+//! smaller and cheaper to parse when many servers are polled often; select it
+//! with `SHAREDSERVER_LOCK_FORMAT=msgpack`. Reads detect the format from the
+//! content, so locks written under either setting stay readable when it
+//! changes.
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// Environment variable selecting the format new lockfile writes use.
+pub const LOCK_FORMAT_ENV: &str = "SHAREDSERVER_LOCK_FORMAT";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockFormat {
+    Json,
+    MessagePack,
+}
+
+impl LockFormat {
+    /// The format to write, from `SHAREDSERVER_LOCK_FORMAT`: `json` (the
+    /// default) or `msgpack`.
+    pub fn configured() -> Result<Self> {
+        match std::env::var(LOCK_FORMAT_ENV).as_deref() {
+            Err(_) | Ok("") | Ok("json") => Ok(Self::Json),
+            Ok("msgpack") if cfg!(feature = "msgpack") => Ok(Self::MessagePack),
+            Ok("msgpack") => bail!(
+                "{}=msgpack needs a sharedserver built with the 'msgpack' feature",
+                LOCK_FORMAT_ENV
+            ),
+            Ok(other) => bail!(
+                "Unknown {} '{}' (expected 'json' or 'msgpack')",
+                LOCK_FORMAT_ENV,
+                other
+            ),
+        }
+    }
+
+    /// The format `contents` is in. Every lock is a JSON object, so anything
+    /// not starting with `{` must be MessagePack (whose maps never start with
+    /// that byte).
+    pub fn detect(contents: &[u8]) -> Self {
+        match contents.iter().find(|byte| !byte.is_ascii_whitespace()) {
+            Some(b'{') | None => Self::Json,
+            Some(_) => Self::MessagePack,
+        }
+    }
+}
+
+/// Parse a lockfile's contents, in whichever format they were written.
+pub fn decode<T>(contents: &[u8]) -> Result<T>
+where
+    T: for<'de> Deserialize<'de>,
+{
+    if contents.iter().all(|byte| byte.is_ascii_whitespace()) {
+        bail!("Lockfile is empty");
+    }
+    match LockFormat::detect(contents) {
+        LockFormat::Json => serde_json::from_slice(contents).context("Failed to parse JSON"),
+        #[cfg(feature = "msgpack")]
+        LockFormat::MessagePack => {
+            rmp_serde::from_slice(contents).context("Failed to parse MessagePack")
+        }
+        #[cfg(not(feature = "msgpack"))]
+        LockFormat::MessagePack => {
+            bail!("Lockfile is not JSON (MessagePack needs a build with the 'msgpack' feature)")
+        }
+    }
+}
+
+/// Encode a lockfile in the configured format.
+pub fn encode<T: Serialize>(data: &T) -> Result<Vec<u8>> {
+    match LockFormat::configured()? {
+        LockFormat::Json => Ok(serde_json::to_vec_pretty(data)?),
+        // Named fields rather than positional arrays, so `#[serde(default)]`
+        // fields added later still read back from older files.
+        #[cfg(feature = "msgpack")]
+        LockFormat::MessagePack => Ok(rmp_serde::to_vec_named(data)?),
+        #[cfg(not(feature = "msgpack"))]
+        LockFormat::MessagePack => unreachable!("rejected by LockFormat::configured"),
+    }
+}
+
+/// Whether `contents` is a complete, well-formed lock of either format.
+pub fn is_valid(contents: &[u8]) -> bool {
+    decode::<serde::de::IgnoredAny>(contents).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::lockfile::ClientsLock;
+
+    #[test]
+    fn test_detect_and_decode_json() {
+        let json = br#"  {"refcount": 0, "clients": {}}"#;
+        assert_eq!(LockFormat::detect(json), LockFormat::Json);
+        assert!(decode::<ClientsLock>(json).unwrap().clients.is_empty());
+        assert!(decode::<ClientsLock>(b"\n").is_err());
+        assert!(!is_valid(br#"{"refcount": "#));
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn test_msgpack_round_trip() {
+        use crate::core::lockfile::ClientInfo;
+
+        let mut lock = ClientsLock::new();
+        lock.clients
+            .insert(42, ClientInfo::new(42, Some("nvim".into())));
+        let bytes = rmp_serde::to_vec_named(&lock).unwrap();
+        assert_eq!(LockFormat::detect(&bytes), LockFormat::MessagePack);
+        assert!(is_valid(&bytes));
+
+        let back: ClientsLock = decode(&bytes).unwrap();
+        assert_eq!(back.refcount(), 1);
+        assert_eq!(back.clients[&42].metadata.as_deref(), Some("nvim"));
+    }
+}
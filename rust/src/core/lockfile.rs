@@ -1,13 +1,32 @@
+use super::container::Container;
+use super::error::ErrorKind;
+use super::exe::ExeSnapshot;
+use super::fsutil;
+use super::grace::GraceClock;
+use super::health::{
+    process_cmdline, process_liveness, process_liveness_checked, process_name, process_start_stamp,
+    Liveness,
+};
+use super::limits::{ResourceLimits, ResourceUsage};
+use super::lock_format;
+use super::probe::{HealthCheck, HealthStatus};
+use super::restart::RestartPolicy;
+use super::shared::{
+    check_shared_dir, create_shared_dir, shared_group, shared_lockdir, SHARED_FILE_MODE,
+};
 use anyhow::{bail, Context, Result};
+use nix::errno::Errno;
 use nix::fcntl::{flock, FlockArg};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs::{File, OpenOptions};
 use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt, PermissionsExt};
 use std::os::unix::io::AsRawFd;
 use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ServerLock {
     pub pid: i32,
     pub command: Vec<String>,
@@ -27,83 +46,609 @@ pub struct ServerLock {
     /// `kill`). `None` on older locks.
     #[serde(default)]
     pub watcher_start_time: Option<u64>,
+    /// The machine's boot ID when the lock was written (see
+    /// [`boot_id`](super::health::boot_id)). A lock from a different boot
+    /// is stale however its PIDs look: they belong to the previous boot, and
+    /// may since have been handed to unrelated processes. `None` on older
+    /// locks and on platforms without a boot ID.
+    #[serde(default)]
+    pub boot_id: Option<String>,
+    /// `KEY=VALUE` environment overrides the server was launched with (on top
+    /// of the inherited environment). Recorded so `use --replace` can tell
+    /// whether a request differs from the running instance. Empty on older
+    /// locks.
+    #[serde(default)]
+    pub env: Vec<String>,
+    /// Log file the server's stdout/stderr were redirected to, if any. Kept so
+    /// a restarted server writes to the same place.
+    #[serde(default)]
+    pub log_file: Option<String>,
+    /// Whether server output is relayed into `log_file` with per-line
+    /// timestamps and stream tags (see `log_capture`).
+    #[serde(default)]
+    pub log_timestamps: bool,
+    /// What the watcher does when the server exits on its own.
+    #[serde(default)]
+    pub restart: RestartPolicy,
+    /// How many times the watcher has relaunched the server.
+    #[serde(default)]
+    pub restart_count: u32,
+    /// Set by `stop` before it signals the server, so the watcher knows the
+    /// exit is deliberate and must not be answered with a restart.
+    #[serde(default)]
+    pub stop_requested: bool,
+    /// Optional health probe the watcher runs on a schedule.
+    #[serde(default)]
+    pub health_check: Option<HealthCheck>,
+    /// Latest probe outcome, written by the watcher. `None` until the first
+    /// probe has run (or when no health check is configured).
+    #[serde(default)]
+    pub health: Option<HealthStatus>,
+    /// Optional memory/CPU limits the watcher enforces.
+    #[serde(default)]
+    pub limits: Option<ResourceLimits>,
+    /// Latest memory/CPU sample, written by the watcher when limits are set.
+    #[serde(default)]
+    pub resources: Option<ResourceUsage>,
+    /// When the watcher will take the server down, set while it is in grace
+    /// (no clients) and cleared when a client attaches.
+    #[serde(default)]
+    pub grace_deadline: Option<chrono::DateTime<chrono::Utc>>,
+    /// Whether time spent suspended counts toward the grace period.
+    #[serde(default)]
+    pub grace_clock: GraceClock,
+    /// Signal sent to every attached client just before the server is shut
+    /// down (grace expiry, `stop`, or a limit's stop action), e.g. "SIGUSR1".
+    #[serde(default)]
+    pub notify_signal: Option<String>,
+    /// If the watcher is sent SIGTERM, leave the server running (unsupervised)
+    /// instead of taking it down too.
+    #[serde(default)]
+    pub linger: bool,
+    /// URL the watcher POSTs lifecycle events to (`--on-event-webhook`).
+    #[serde(default)]
+    pub webhook: Option<String>,
+    /// Show a desktop notification when the server enters its grace period
+    /// and when it is shut down (`--notify-desktop`).
+    #[serde(default)]
+    pub notify_desktop: bool,
+    /// UID of the user who started the server. `None` on older locks.
+    #[serde(default)]
+    pub owner_uid: Option<u32>,
+    /// The group whose members share the server (`--shared --allow-group`),
+    /// or `None` for a private server.
+    #[serde(default)]
+    pub shared_group: Option<String>,
+    /// The binary `command` ran, as it was on disk at launch, so a rebuild or
+    /// upgrade since can be noticed. `None` on older locks, or if it couldn't
+    /// be resolved.
+    #[serde(default)]
+    pub executable: Option<ExeSnapshot>,
+    /// Working directory the server was launched from.
+    #[serde(default)]
+    pub cwd: Option<PathBuf>,
+    /// Where the server says it can be reached (`--address`), as
+    /// `tcp:HOST:PORT` or `unix:PATH`. See [`ServerLock::address`].
+    #[serde(default)]
+    pub address: Option<String>,
+    /// The stable address a front (see `front`) listens on and forwards to
+    /// the server's own address (`--listen`).
+    #[serde(default)]
+    pub listen: Option<String>,
+    /// Whether the server's stdin/stdout are shared through `proxy`'s socket
+    /// (see `stdio_mux`).
+    #[serde(default)]
+    pub stdio: bool,
+    /// The container the server runs in (`--backend docker|podman`), or
+    /// `None` for a local process.
+    #[serde(default)]
+    pub container: Option<Container>,
+    /// Supervised by `sharedserver daemon` rather than a watcher of its own:
+    /// `watcher_pid` is the daemon, which outlives the server and must never
+    /// be signalled on its behalf.
+    #[serde(default)]
+    pub daemon: bool,
+    /// `key=value` labels given at start (`--tag`), for selecting servers.
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+    /// The config files (see `Config::paths`) as found from the directory
+    /// the server was started from, which the watcher reloads its settings
+    /// from. Empty on older locks.
+    #[serde(default)]
+    pub config_files: Vec<PathBuf>,
+}
+
+impl ServerLock {
+    /// Health as reported to users: `None` when no health check is configured,
+    /// `"starting"` until the first probe has run, then `"healthy"` or
+    /// `"unhealthy"`.
+    pub fn health_label(&self) -> Option<&'static str> {
+        self.health_check.as_ref()?;
+        Some(match &self.health {
+            None => "starting",
+            Some(status) if status.healthy => "healthy",
+            Some(_) => "unhealthy",
+        })
+    }
+
+    /// Whether the lock was written before the machine last booted, e.g. left
+    /// in a persistent `SHAREDSERVER_LOCKDIR` by an unclean shutdown.
+    pub fn from_previous_boot(&self) -> bool {
+        match (&self.boot_id, super::health::boot_id()) {
+            (Some(recorded), Some(current)) => *recorded != current,
+            _ => false,
+        }
+    }
+
+    /// Liveness of the server process, guarded against PID reuse by its start
+    /// stamp and reported [`Liveness::Gone`] outright if the lock is from a
+    /// previous boot.
+    pub fn server_liveness(&self) -> Liveness {
+        if self.from_previous_boot() {
+            return Liveness::Gone;
+        }
+        process_liveness_checked(self.pid, self.start_time)
+    }
+
+    /// Whether the watcher's health probe has marked this server unhealthy.
+    pub fn is_unhealthy(&self) -> bool {
+        self.health_label() == Some("unhealthy")
+    }
+
+    /// Time left before grace expires, if the server is in grace. Zero once
+    /// the deadline has passed (the watcher is about to stop it).
+    pub fn grace_remaining(&self) -> Option<std::time::Duration> {
+        let deadline = self.grace_deadline?;
+        Some((deadline - chrono::Utc::now()).to_std().unwrap_or_default())
+    }
+
+    /// Where clients can reach the server: its front's `listen` address, if
+    /// it has one, or else [`ServerLock::server_address`].
+    pub fn address(&self) -> Option<String> {
+        self.listen.clone().or_else(|| self.server_address())
+    }
+
+    /// Where the server itself listens: the advertised `address`, or else the
+    /// one its TCP or HTTP health probe connects to.
+    pub fn server_address(&self) -> Option<String> {
+        self.address.clone().or_else(|| {
+            self.health_check
+                .as_ref()
+                .and_then(|check| check.probe.address())
+        })
+    }
+
+    /// Everywhere the server or its front listens, which no other running
+    /// server may.
+    pub fn claimed_addresses(&self) -> Vec<String> {
+        self.listen
+            .iter()
+            .cloned()
+            .chain(self.server_address())
+            .collect()
+    }
+
+    /// How long the server has been running, from `started_at`.
+    pub fn uptime(&self) -> std::time::Duration {
+        (chrono::Utc::now() - self.started_at)
+            .to_std()
+            .unwrap_or_default()
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ClientInfo {
     pub attached_at: chrono::DateTime<chrono::Utc>,
     pub metadata: Option<String>,
+    /// Start stamp of the client process when it attached (see
+    /// [`process_start_stamp`]), so a PID recycled by another program doesn't
+    /// keep the server alive. `None` on older locks, which fall back to a
+    /// plain liveness check.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_time: Option<u64>,
+    /// The client's process name (e.g. `nvim`) when it attached, so `info`
+    /// can say who is keeping the server alive. `None` on older locks or if
+    /// it couldn't be read.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    /// The client's full command line when it attached.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cmdline: Option<Vec<String>>,
 }
 
 impl ClientInfo {
-    pub fn new(metadata: Option<String>) -> Self {
+    /// Attach record for the client process `pid`, stamped now.
+    pub fn new(pid: i32, metadata: Option<String>) -> Self {
         Self {
             attached_at: chrono::Utc::now(),
             metadata,
+            start_time: process_start_stamp(pid),
+            command: process_name(pid),
+            cmdline: process_cmdline(pid),
         }
     }
+
+    /// Whether the process that attached as `pid` is still running. A PID now
+    /// held by a different process counts as dead.
+    pub fn is_alive(&self, pid: i32) -> bool {
+        process_liveness_checked(pid, self.start_time) == Liveness::Alive
+    }
+
+    /// Whether `pid` is alive but no longer the process that attached: the
+    /// client died and the OS handed its PID to something else.
+    pub fn pid_reused(&self, pid: i32) -> bool {
+        process_liveness(pid) == Liveness::Alive && !self.is_alive(pid)
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// The attached clients. The refcount is not stored separately but derived
+/// from the map ([`refcount`](Self::refcount)), so the two can't disagree.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(into = "ClientsLockFile", from = "ClientsLockFile")]
 pub struct ClientsLock {
-    pub refcount: u32,
     pub clients: HashMap<i32, ClientInfo>,
+    /// Bumped by the watcher, under this lock, once it has re-checked that no
+    /// client attached and committed to taking the server down. `incref`
+    /// checks it under the same lock, so a last-moment rescue either lands
+    /// before the commit (and the watcher backs off) or is refused. Zero on
+    /// older locks.
+    #[serde(default)]
+    pub shutdown_generation: u64,
 }
 
-impl ClientsLock {
-    pub fn new() -> Self {
+/// On-disk form of [`ClientsLock`]. `refcount` is still written, derived, for
+/// older versions and scripts that read it, and ignored when read back.
+#[derive(Serialize, Deserialize)]
+struct ClientsLockFile {
+    #[serde(default)]
+    refcount: u32,
+    clients: HashMap<i32, ClientInfo>,
+    #[serde(default)]
+    shutdown_generation: u64,
+}
+
+impl From<ClientsLock> for ClientsLockFile {
+    fn from(lock: ClientsLock) -> Self {
+        Self {
+            refcount: lock.refcount(),
+            clients: lock.clients,
+            shutdown_generation: lock.shutdown_generation,
+        }
+    }
+}
+
+impl From<ClientsLockFile> for ClientsLock {
+    fn from(file: ClientsLockFile) -> Self {
         Self {
-            refcount: 0,
-            clients: HashMap::new(),
+            clients: file.clients,
+            shutdown_generation: file.shutdown_generation,
         }
     }
 }
 
+impl ClientsLock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of attached clients; zero means the server is in grace.
+    pub fn refcount(&self) -> u32 {
+        self.clients.len() as u32
+    }
+
+    /// Whether the watcher has committed to shutting the server down, so new
+    /// clients must not attach.
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutdown_generation > 0
+    }
+}
+
 /// Get the lockfile directory
 pub fn lockfile_dir() -> Result<PathBuf> {
-    if let Ok(dir) = std::env::var("SHAREDSERVER_LOCKDIR") {
-        return Ok(PathBuf::from(dir));
+    if let Some(group) = shared_group() {
+        let dir = shared_lockdir(&group);
+        check_shared_dir(&dir, &group)?;
+        return Ok(dir);
     }
 
-    if let Ok(xdg_runtime) = std::env::var("XDG_RUNTIME_DIR") {
-        let path = PathBuf::from(xdg_runtime).join("sharedserver");
-        return Ok(path);
-    }
+    let dir = if let Ok(dir) = std::env::var("SHAREDSERVER_LOCKDIR") {
+        PathBuf::from(dir)
+    } else if let Ok(xdg_runtime) = std::env::var("XDG_RUNTIME_DIR") {
+        PathBuf::from(xdg_runtime).join("sharedserver")
+    } else {
+        // Per-user, so users sharing /tmp can't see or clobber each other's
+        // locks.
+        PathBuf::from(format!("/tmp/sharedserver-{}", current_uid()))
+    };
 
-    Ok(PathBuf::from("/tmp/sharedserver"))
+    check_lockfile_dir_owner(&dir)?;
+    Ok(dir)
 }
 
-/// Ensure lockfile directory exists
+/// Ensure lockfile directory exists (created [`LOCKDIR_MODE`], or as a
+/// group's shared directory in shared mode)
 pub fn ensure_lockfile_dir() -> Result<PathBuf> {
     let dir = lockfile_dir()?;
-    std::fs::create_dir_all(&dir)
-        .with_context(|| format!("Failed to create lockfile directory: {:?}", dir))?;
+    // Re-check: someone else may have created it between the two.
+    if let Some(group) = shared_group() {
+        create_shared_dir(&dir, &group)?;
+        check_shared_dir(&dir, &group)?;
+    } else {
+        create_private_dir(&dir)?;
+        check_lockfile_dir_owner(&dir)?;
+    }
     Ok(dir)
 }
 
+/// Mode of a lock directory sharedserver creates: owner-only.
+pub const LOCKDIR_MODE: u32 = 0o700;
+
+/// Mode of every file sharedserver creates in the lock directory. They record
+/// PIDs, commands, and environments, so only the owner may read them.
+pub const LOCKFILE_MODE: u32 = 0o600;
+
+pub fn current_uid() -> u32 {
+    // SAFETY: geteuid has no preconditions and cannot fail.
+    unsafe { libc::geteuid() }
+}
+
+fn create_private_dir(dir: &Path) -> Result<()> {
+    if dir.is_dir() {
+        return Ok(());
+    }
+    std::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(LOCKDIR_MODE)
+        .create(dir)
+        .and_then(|()| fsutil::sync_parent(dir))
+        .with_context(|| format!("Failed to create lockfile directory: {:?}", dir))
+}
+
+/// Refuse a lock directory owned by another user, or one other users can
+/// write to: whoever can write to it controls the locks, so trusting it would
+/// let them forge server state (and the PIDs that `stop` and `kill` signal).
+/// A directory that doesn't exist yet is fine.
+fn check_lockfile_dir_owner(dir: &Path) -> Result<()> {
+    let Ok(metadata) = std::fs::metadata(dir) else {
+        return Ok(());
+    };
+    let uid = current_uid();
+    if metadata.uid() != uid {
+        bail!(
+            "Lock directory {:?} is owned by UID {}, not the current user (UID {}); \
+             refusing to use it (set SHAREDSERVER_LOCKDIR to a directory you own)",
+            dir,
+            metadata.uid(),
+            uid
+        );
+    }
+    let mode = metadata.mode() & 0o7777;
+    if mode & 0o022 != 0 {
+        bail!(
+            "Lock directory {:?} is writable by other users (mode {:o}); refusing to use it \
+             (chmod {:o} it, or set SHAREDSERVER_LOCKDIR to a private directory)",
+            dir,
+            mode,
+            LOCKDIR_MODE
+        );
+    }
+    Ok(())
+}
+
+/// The mode files in the lock directory are created with: [`LOCKFILE_MODE`],
+/// or group read/write in shared mode.
+pub fn lockfile_mode() -> u32 {
+    if shared_group().is_some() {
+        SHARED_FILE_MODE
+    } else {
+        LOCKFILE_MODE
+    }
+}
+
+/// Open a file in the lock directory with `options`, creating it with
+/// [`lockfile_mode`] (and, for a server's file, its directory) if asked to.
+/// A new file's directory entry is synced before this returns.
+pub fn open_lockfile(options: &mut OpenOptions, path: &Path) -> std::io::Result<File> {
+    let mode = lockfile_mode();
+    let existed = path.exists();
+    let file = match options.mode(mode).open(path) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            // A server's directory is made on its first write; if this isn't
+            // a create, the retry fails just the same.
+            match path.parent() {
+                Some(dir) => create_server_dir(dir)?,
+                None => return Err(e),
+            }
+            options.open(path)?
+        }
+        result => result?,
+    };
+    if !existed {
+        fsutil::sync_parent(path)?;
+    }
+    // The umask can strip the group bits a shared file needs. Only the owner
+    // may fix that, and files others created were fixed by them.
+    if mode != LOCKFILE_MODE {
+        if let Ok(metadata) = file.metadata() {
+            if metadata.uid() == current_uid() && metadata.mode() & 0o777 != mode {
+                let _ = file.set_permissions(std::fs::Permissions::from_mode(mode));
+            }
+        }
+    }
+    Ok(file)
+}
+
+/// Write `contents` to `path`, replacing it; created with [`lockfile_mode`]
+/// if new.
+pub fn write_private(path: &Path, contents: impl AsRef<[u8]>) -> std::io::Result<()> {
+    open_lockfile(
+        OpenOptions::new().write(true).create(true).truncate(true),
+        path,
+    )?
+    .write_all(contents.as_ref())
+}
+
+/// Every per-server file, as named inside the server's directory. Before
+/// per-server directories these lived directly in the lock directory as
+/// `<name>.<file>`.
+pub const SERVER_FILES: &[&str] = &[
+    "server.json",
+    "server.json.bak",
+    "clients.json",
+    "clients.json.bak",
+    "invocations.log",
+    "watcher.log",
+    "watcher.heartbeat",
+    "exit.json",
+    super::container::CIDFILE,
+    super::stdio_mux::SOCKET_FILE,
+];
+
+/// Entries of the lock directory itself, which a server's directory can't
+/// be named after.
+pub const LOCKDIR_FILES: &[&str] = &[
+    "registry.json",
+    "daemon.sock",
+    "http.token",
+    "start.lock",
+    "contention.log",
+];
+
+/// Check `name` can be a server's directory: not empty, `.` or `..`, without
+/// a `/`, and not one of [`LOCKDIR_FILES`].
+pub fn validate_name(name: &str) -> Result<()> {
+    let problem = if name.is_empty() {
+        "it is empty"
+    } else if name == "." || name == ".." {
+        "it names a directory"
+    } else if name.contains('/') {
+        "it contains '/'"
+    } else if name.contains('\0') {
+        "it contains a NUL byte"
+    } else if LOCKDIR_FILES.contains(&name) {
+        "the lock directory uses that name itself"
+    } else {
+        return Ok(());
+    };
+    Err(ErrorKind::InvalidArgs.error(format!("Invalid server name '{}': {}", name, problem)))
+}
+
+/// Path of one of `name`'s state files (see [`SERVER_FILES`]), in its own
+/// directory `<lockdir>/<name>/`. Fails if `name` isn't a valid server name
+/// (see [`validate_name`]).
+///
+/// Servers from before that layout are migrated on first touch by moving
+/// their flat `<name>.<file>` files in, except while a live watcher from the
+/// old layout still owns them: it keeps using the flat paths, so they do too
+/// until that instance is gone.
+pub fn server_file(name: &str, file: &str) -> Result<PathBuf> {
+    validate_name(name)?;
+    let lockdir = ensure_lockfile_dir()?;
+    let dir = lockdir.join(name);
+    if !dir.is_dir() {
+        let legacy = |file: &str| lockdir.join(format!("{}.{}", name, file));
+        let legacy_lock = legacy("server.json");
+        if legacy_lock.exists() {
+            let in_use = with_shared_lock(&legacy_lock, read_json::<ServerLock>)
+                .is_ok_and(|lock| super::state::watcher_alive(&lock));
+            if in_use {
+                return Ok(legacy(file));
+            }
+        }
+        for file in SERVER_FILES {
+            let old = legacy(file);
+            if old.exists() {
+                create_server_dir(&dir)
+                    .with_context(|| format!("Failed to create server directory: {:?}", dir))?;
+                let _ = fsutil::rename(&old, &dir.join(file));
+            }
+        }
+    }
+    // Otherwise the directory is only created once something is written to
+    // it (see `open_lockfile`), so looking up a server doesn't litter.
+    Ok(dir.join(file))
+}
+
+/// Create a server's directory: owner-only, or group-shared like its parent
+/// in shared mode. Losing a creation race is fine.
+fn create_server_dir(dir: &Path) -> std::io::Result<()> {
+    let mode = if shared_group().is_some() {
+        super::shared::SHARED_DIR_MODE
+    } else {
+        LOCKDIR_MODE
+    };
+    match std::fs::DirBuilder::new().mode(mode).create(dir) {
+        Ok(()) => {
+            // The umask may have stripped the group bits.
+            if mode != LOCKDIR_MODE {
+                let _ = std::fs::set_permissions(dir, std::fs::Permissions::from_mode(mode));
+            }
+            fsutil::sync_parent(dir)
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Names of the servers that have any of `files`, in either layout.
+pub fn servers_with(files: &[&str]) -> Result<BTreeSet<String>> {
+    let lockdir = lockfile_dir()?;
+    let mut names = BTreeSet::new();
+    let Ok(entries) = std::fs::read_dir(&lockdir) else {
+        return Ok(names);
+    };
+    for entry in entries.flatten() {
+        let filename = entry.file_name().to_string_lossy().into_owned();
+        let path = entry.path();
+        if path.is_dir() {
+            if files.iter().any(|file| path.join(file).exists()) {
+                names.insert(filename);
+            }
+        } else if let Some(name) = files.iter().find_map(|file| {
+            filename
+                .strip_suffix(file)
+                .and_then(|name| name.strip_suffix('.'))
+        }) {
+            names.insert(name.to_string());
+        }
+    }
+    Ok(names)
+}
+
 /// Get path to server lockfile
 pub fn server_lockfile_path(name: &str) -> Result<PathBuf> {
-    Ok(ensure_lockfile_dir()?.join(format!("{}.server.json", name)))
+    server_file(name, "server.json")
 }
 
 /// Get path to clients lockfile
 pub fn clients_lockfile_path(name: &str) -> Result<PathBuf> {
-    Ok(ensure_lockfile_dir()?.join(format!("{}.clients.json", name)))
+    server_file(name, "clients.json")
+}
+
+/// How long lock acquisition waits before giving up, unless overridden with
+/// `SHAREDSERVER_LOCK_TIMEOUT` (e.g. "30s"). Every critical section is a
+/// short read-modify-write, so a lock held this long means the holder is hung.
+pub const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The lock timeout in effect: `SHAREDSERVER_LOCK_TIMEOUT` if set and valid,
+/// else [`DEFAULT_LOCK_TIMEOUT`].
+pub fn lock_timeout() -> Duration {
+    std::env::var("SHAREDSERVER_LOCK_TIMEOUT")
+        .ok()
+        .and_then(|t| super::duration::parse_duration(&t).ok())
+        .unwrap_or(DEFAULT_LOCK_TIMEOUT)
 }
 
 /// Perform read-only operation with shared lock (allows multiple concurrent readers)
+#[track_caller]
 pub fn with_shared_lock<F, R>(path: &Path, operation: F) -> Result<R>
 where
     F: FnOnce(&mut File) -> Result<R>,
 {
-    let mut file = OpenOptions::new()
-        .read(true)
-        .open(path)
-        .with_context(|| format!("Failed to open lockfile: {:?}", path))?;
+    let mut file = open_shared(path)?;
 
     // Acquire shared lock (multiple readers allowed simultaneously)
-    flock(file.as_raw_fd(), FlockArg::LockShared)
-        .with_context(|| format!("Failed to acquire shared lock on: {:?}", path))?;
+    acquire(&file, path, FlockArg::LockSharedNonblock, lock_timeout())?;
 
     let result = operation(&mut file);
 
@@ -111,88 +656,526 @@ where
     result
 }
 
-/// Perform operation on file with exclusive lock (single writer, no readers)
+/// Open an existing lockfile to read under a shared lock.
+pub(crate) fn open_shared(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .open(path)
+        .with_context(|| format!("Failed to open lockfile: {:?}", path))
+}
+
+/// Open a lockfile to modify under an exclusive lock, creating it if `create`.
+pub(crate) fn open_exclusive(path: &Path, create: bool) -> Result<File> {
+    open_lockfile(
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(create)
+            .truncate(false),
+        path,
+    )
+    .with_context(|| format!("Failed to open lockfile: {:?}", path))
+}
+
+/// Perform operation on file with exclusive lock (single writer, no readers),
+/// waiting at most [`lock_timeout`] for it.
+#[track_caller]
 pub fn with_lock<F, R>(path: &Path, operation: F) -> Result<R>
 where
     F: FnOnce(&mut File) -> Result<R>,
 {
-    let mut file = OpenOptions::new()
-        .read(true)
-        .write(true)
-        .create(true)
-        .open(path)
-        .with_context(|| format!("Failed to open lockfile: {:?}", path))?;
+    with_lock_timeout(path, lock_timeout(), operation)
+}
 
+/// Like [`with_lock`], but fails after `timeout` if another process still
+/// holds the lock, naming the holder where the platform allows.
+#[track_caller]
+pub fn with_lock_timeout<F, R>(path: &Path, timeout: Duration, operation: F) -> Result<R>
+where
+    F: FnOnce(&mut File) -> Result<R>,
+{
+    let file = open_exclusive(path, true)?;
+    locked(file, path, timeout, operation)
+}
+
+/// Take the exclusive lock on an open lockfile and run `operation` under it,
+/// backing up whatever it writes.
+#[track_caller]
+fn locked<F, R>(file: File, path: &Path, timeout: Duration, operation: F) -> Result<R>
+where
+    F: FnOnce(&mut File) -> Result<R>,
+{
     // Acquire exclusive lock
-    flock(file.as_raw_fd(), FlockArg::LockExclusive)
-        .with_context(|| format!("Failed to acquire lock on: {:?}", path))?;
+    acquire(&file, path, FlockArg::LockExclusiveNonblock, timeout)?;
+    journaled(file, path, operation)
+}
+
+/// Run `operation` on a lockfile whose exclusive lock is held, backing up
+/// whatever it writes.
+pub(crate) fn journaled<F, R>(mut file: File, path: &Path, operation: F) -> Result<R>
+where
+    F: FnOnce(&mut File) -> Result<R>,
+{
+    let journaled = path.extension().is_some_and(|ext| ext == "json");
+    let before = if journaled { read_all(&mut file) } else { None };
 
     let result = operation(&mut file);
 
+    // Keep a last-known-good copy of every lock the operation rewrote.
+    if journaled && result.is_ok() {
+        if let Some(after) = read_all(&mut file).filter(|after| Some(after) != before.as_ref()) {
+            if lock_format::is_valid(&after) {
+                let _ = write_backup(path, &after);
+            }
+        }
+    }
+
     // Lock is automatically released when file is dropped
     result
 }
 
-/// Read JSON from file
-pub fn read_json<T>(file: &mut File) -> Result<T>
+/// Where the last-known-good copy of a JSON lockfile is kept.
+pub fn backup_path(path: &Path) -> PathBuf {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(".bak");
+    PathBuf::from(backup)
+}
+
+fn read_all(file: &mut File) -> Option<Vec<u8>> {
+    let mut contents = Vec::new();
+    file.seek(SeekFrom::Start(0)).ok()?;
+    file.read_to_end(&mut contents).ok()?;
+    Some(contents)
+}
+
+/// Replace the backup atomically (write a temp file, then rename), so a crash
+/// mid-write can't corrupt the copy meant to survive corruption.
+fn write_backup(path: &Path, contents: &[u8]) -> Result<()> {
+    let backup = backup_path(path);
+    let mut tmp = backup.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let mut file = open_lockfile(
+        OpenOptions::new().write(true).create(true).truncate(true),
+        &tmp,
+    )?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fsutil::rename(&tmp, &backup)?;
+    Ok(())
+}
+
+/// Parse the backup of `path`, if there is a valid one.
+pub fn read_backup<T>(path: &Path) -> Option<T>
 where
     T: for<'de> Deserialize<'de>,
 {
-    file.seek(SeekFrom::Start(0))?;
-    let mut contents = String::new();
-    file.read_to_string(&mut contents)?;
+    let contents = std::fs::read(backup_path(path)).ok()?;
+    lock_format::decode(&contents).ok()
+}
+
+/// [`read_json`], falling back to the last-known-good backup (with a warning
+/// on stderr) when the lockfile itself is empty or corrupt.
+pub fn read_json_recovering<T>(file: &mut File, path: &Path) -> Result<T>
+where
+    T: for<'de> Deserialize<'de>,
+{
+    match read_json(file) {
+        Ok(data) => Ok(data),
+        Err(e) => match read_backup(path) {
+            Some(data) => {
+                eprintln!(
+                    "Warning: {:?} is unreadable ({:#}); using last good copy {:?}",
+                    path,
+                    e,
+                    backup_path(path)
+                );
+                Ok(data)
+            }
+            None => Err(e),
+        },
+    }
+}
+
+/// Lock waits at least this long are reported (see [`report_lock_wait`]).
+/// Off unless `SHAREDSERVER_LOCK_WARN_MS` is set to a number of milliseconds
+/// (or a duration with a unit, e.g. "1.5s").
+pub fn lock_warn_threshold() -> Option<Duration> {
+    let threshold = std::env::var("SHAREDSERVER_LOCK_WARN_MS").ok()?;
+    match threshold.trim().parse() {
+        Ok(ms) => Some(Duration::from_millis(ms)),
+        Err(_) => super::duration::parse_duration(&threshold).ok(),
+    }
+}
+
+/// Take a non-blocking flock, retrying with backoff until `timeout`. A wait
+/// past [`lock_warn_threshold`] is reported against the caller of the public
+/// lock function (hence `#[track_caller]` all the way up).
+#[track_caller]
+fn acquire(file: &File, path: &Path, arg: FlockArg, timeout: Duration) -> Result<()> {
+    let started = Instant::now();
+    let deadline = started + timeout;
+    let warn_after = lock_warn_threshold();
+    let mut backoff = Duration::from_millis(1);
+    // Whoever held the lock when we first found it taken; by the time we get
+    // it they've let go.
+    let mut holders = None;
+    while !try_flock(file, path, arg)? {
+        if Instant::now() >= deadline {
+            return Err(lock_timed_out(file, path, timeout));
+        }
+        if backoff == Duration::from_millis(1) {
+            log::trace!("{:?} is locked, waiting", path);
+        }
+        if warn_after.is_some() && holders.is_none() {
+            holders = Some(lock_holders(file));
+        }
+        std::thread::sleep(backoff.min(deadline.saturating_duration_since(Instant::now())));
+        backoff = next_backoff(backoff);
+    }
+    let waited = started.elapsed();
+    if warn_after.is_some_and(|warn_after| waited >= warn_after) {
+        report_lock_wait(path, arg, waited, holders.as_deref().unwrap_or_default());
+    }
+    Ok(())
+}
+
+/// One attempt at a non-blocking flock: `false` if another process holds it
+/// (or the attempt was interrupted), so the caller should wait and retry.
+pub(crate) fn try_flock(file: &File, path: &Path, arg: FlockArg) -> Result<bool> {
+    match flock(file.as_raw_fd(), arg) {
+        Ok(()) => Ok(true),
+        Err(Errno::EWOULDBLOCK) | Err(Errno::EINTR) => Ok(false),
+        Err(e) => Err(e).with_context(|| format!("Failed to acquire lock on: {:?}", path)),
+    }
+}
+
+/// The wait before the next [`try_flock`]: doubling from 1ms, up to 50ms.
+pub(crate) fn next_backoff(backoff: Duration) -> Duration {
+    (backoff * 2).min(Duration::from_millis(50))
+}
+
+/// The error for a lock still held by someone else after `timeout`.
+pub(crate) fn lock_timed_out(file: &File, path: &Path, timeout: Duration) -> anyhow::Error {
+    ErrorKind::LockTimeout.error(format!(
+        "Timed out after {:?} waiting for lock on {:?}: lock held by {}",
+        timeout,
+        path,
+        describe_holders(&lock_holders(file))
+    ))
+}
+
+/// Report a slow lock acquisition on stderr and in the lock directory's
+/// `contention.log`, naming the lock, the code that wanted it, and who held it.
+#[track_caller]
+fn report_lock_wait(path: &Path, arg: FlockArg, waited: Duration, holders: &[i32]) {
+    let caller = std::panic::Location::caller();
+    let mode = match arg {
+        FlockArg::LockShared | FlockArg::LockSharedNonblock => "shared",
+        _ => "exclusive",
+    };
+    eprintln!(
+        "Warning: waited {:?} for {} lock on {:?} at {} (held by {})",
+        waited,
+        mode,
+        path,
+        caller,
+        describe_holders(holders)
+    );
+    let _ = super::log::log_lock_wait(&super::log::LockWait {
+        timestamp: chrono::Utc::now(),
+        pid: std::process::id() as i32,
+        path: path.to_path_buf(),
+        mode: mode.to_string(),
+        caller: caller.to_string(),
+        waited_ms: waited.as_millis() as u64,
+        holders: holders.to_vec(),
+    });
+}
 
-    if contents.trim().is_empty() {
-        bail!("Lockfile is empty");
+fn describe_holders(holders: &[i32]) -> String {
+    match holders {
+        [] => "another process".to_string(),
+        [pid] => format!("PID {}", pid),
+        pids => format!(
+            "PIDs {}",
+            pids.iter()
+                .map(|pid| pid.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
     }
+}
+
+/// PIDs holding a flock on `file`, from `/proc/locks` (`F_GETLK` only sees
+/// POSIX record locks, not flocks). Each line reads
+/// `<n>: FLOCK ADVISORY WRITE <pid> <maj>:<min>:<inode> 0 EOF`, with major and
+/// minor in hex; waiters are marked `->` and skipped.
+#[cfg(target_os = "linux")]
+fn lock_holders(file: &File) -> Vec<i32> {
+    use std::os::unix::fs::MetadataExt;
+
+    let Ok(meta) = file.metadata() else {
+        return Vec::new();
+    };
+    let id = format!(
+        "{:02x}:{:02x}:{}",
+        nix::sys::stat::major(meta.dev()),
+        nix::sys::stat::minor(meta.dev()),
+        meta.ino()
+    );
+    let locks = std::fs::read_to_string("/proc/locks").unwrap_or_default();
+    let mut holders: Vec<i32> = locks
+        .lines()
+        .filter(|line| !line.contains("->"))
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.as_slice() {
+                [_, "FLOCK", _, _, pid, dev_ino, ..] if *dev_ino == id => pid.parse().ok(),
+                _ => None,
+            }
+        })
+        .collect();
+    holders.sort_unstable();
+    holders.dedup();
+    holders
+}
 
-    serde_json::from_str(&contents).context("Failed to parse JSON")
+/// Other platforms have no cheap way to name a flock's holder.
+#[cfg(not(target_os = "linux"))]
+fn lock_holders(_file: &File) -> Vec<i32> {
+    Vec::new()
 }
 
-/// Write JSON to file (truncates)
+/// Read a lock from file, in whichever [`lock_format`] it was written
+pub fn read_json<T>(file: &mut File) -> Result<T>
+where
+    T: for<'de> Deserialize<'de>,
+{
+    file.seek(SeekFrom::Start(0))?;
+    let mut contents = Vec::new();
+    file.read_to_end(&mut contents)?;
+    lock_format::decode(&contents)
+}
+
+/// Write a lock to file (truncates), as JSON unless another
+/// [`lock_format`] is configured
 pub fn write_json<T>(file: &mut File, data: &T) -> Result<()>
 where
     T: Serialize,
 {
+    let encoded = lock_format::encode(data)?;
     file.seek(SeekFrom::Start(0))?;
     file.set_len(0)?;
-    let json = serde_json::to_string_pretty(data)?;
-    file.write_all(json.as_bytes())?;
+    file.write_all(&encoded)?;
     file.sync_all()?;
     Ok(())
 }
 
 /// Read server lockfile with shared lock (allows concurrent reads)
+#[track_caller]
 pub fn read_server_lock(name: &str) -> Result<ServerLock> {
     let path = server_lockfile_path(name)?;
-    with_shared_lock(&path, |file| read_json(file))
+    with_shared_lock(&path, |file| read_json_recovering(file, &path))
 }
 
 /// Write server lockfile
+#[track_caller]
 pub fn write_server_lock(name: &str, lock: &ServerLock) -> Result<()> {
     let path = server_lockfile_path(name)?;
-    with_lock(&path, |file| write_json(file, lock))
+    with_lock(&path, |file| write_json(file, lock))?;
+    super::registry::refresh(name);
+    Ok(())
 }
 
 /// Read clients lockfile with shared lock (allows concurrent reads)
+#[track_caller]
 pub fn read_clients_lock(name: &str) -> Result<ClientsLock> {
     let path = clients_lockfile_path(name)?;
-    with_shared_lock(&path, |file| read_json(file))
+    with_shared_lock(&path, |file| read_json_recovering(file, &path))
 }
 
 /// Write clients lockfile
+#[track_caller]
 pub fn write_clients_lock(name: &str, lock: &ClientsLock) -> Result<()> {
     let path = clients_lockfile_path(name)?;
-    with_lock(&path, |file| write_json(file, lock))
+    with_lock(&path, |file| write_json(file, lock))?;
+    super::registry::refresh(name);
+    Ok(())
+}
+
+/// What an [`update_server_lock`] or [`update_clients_lock`] closure decided
+/// to do with the lock it was handed, and the value to return.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockUpdate<R> {
+    /// Write the modified lock back.
+    Write(R),
+    /// Leave the file as it was.
+    Keep(R),
+    /// Delete the lockfile and its backup.
+    Delete(R),
+}
+
+/// Read-modify-write `name`'s server lock under its exclusive lock. The lock
+/// must exist: a missing or unrecoverable one is an error, and `update` isn't
+/// called. Reads fall back to the backup like [`read_json_recovering`] (a
+/// recovered lock is written back even if `update` keeps it), and the
+/// registry is refreshed after a write.
+///
+/// Most callers only touch the lock if it still names the server they're
+/// acting for, and return [`LockUpdate::Keep`] otherwise.
+#[track_caller]
+pub fn update_server_lock<F, R>(name: &str, update: F) -> Result<R>
+where
+    F: FnOnce(&mut ServerLock) -> Result<LockUpdate<R>>,
+{
+    let path = server_lockfile_path(name)?;
+    server_lock_updated(name, update_lock(&path, false, || None, update))
+}
+
+/// Finish an [`update_server_lock`]: bring the registry up to date with what
+/// the update did.
+pub(crate) fn server_lock_updated<R>(
+    name: &str,
+    updated: Result<(R, Option<Changed>)>,
+) -> Result<R> {
+    let (result, changed) =
+        updated.with_context(|| format!("Failed to update server lock for '{}'", name))?;
+    match changed {
+        Some(Changed::Written) => super::registry::refresh(name),
+        Some(Changed::Deleted) => super::registry::remove(name),
+        None => {}
+    }
+    Ok(result)
+}
+
+/// Read-modify-write `name`'s clients lock under its exclusive lock, creating
+/// it if it's missing. A lock that's unreadable and has no good backup starts
+/// over empty. The registry is refreshed after a write.
+///
+/// The clients lock lives as long as the server (grace keeps it with an empty
+/// map), so a closure should only return [`LockUpdate::Delete`] as part of
+/// tearing the server down, never because the last client left.
+#[track_caller]
+pub fn update_clients_lock<F, R>(name: &str, update: F) -> Result<R>
+where
+    F: FnOnce(&mut ClientsLock) -> Result<LockUpdate<R>>,
+{
+    let path = clients_lockfile_path(name)?;
+    clients_lock_updated(
+        name,
+        update_lock(&path, true, || Some(ClientsLock::new()), update),
+    )
+}
+
+/// Finish an [`update_clients_lock`]: bring the registry up to date with what
+/// the update did.
+pub(crate) fn clients_lock_updated<R>(
+    name: &str,
+    updated: Result<(R, Option<Changed>)>,
+) -> Result<R> {
+    let (result, changed) =
+        updated.with_context(|| format!("Failed to update clients lock for '{}'", name))?;
+    if changed.is_some() {
+        super::registry::refresh(name);
+    }
+    Ok(result)
+}
+
+/// What an update did to the lockfile, if anything.
+pub(crate) enum Changed {
+    Written,
+    Deleted,
+}
+
+/// The read-modify-write behind the `update_*_lock` functions. `empty` is what
+/// to start from when the lock (and its backup) can't be read, if anything.
+#[track_caller]
+fn update_lock<T, F, R>(
+    path: &Path,
+    create: bool,
+    empty: impl FnOnce() -> Option<T>,
+    update: F,
+) -> Result<(R, Option<Changed>)>
+where
+    T: Serialize + for<'de> Deserialize<'de>,
+    F: FnOnce(&mut T) -> Result<LockUpdate<R>>,
+{
+    let file = open_exclusive(path, create)?;
+    locked(file, path, lock_timeout(), |file| {
+        update_locked(file, path, empty, update)
+    })
+}
+
+/// The read-modify-write itself, on a lockfile whose exclusive lock is held.
+pub(crate) fn update_locked<T, F, R>(
+    file: &mut File,
+    path: &Path,
+    empty: impl FnOnce() -> Option<T>,
+    update: F,
+) -> Result<(R, Option<Changed>)>
+where
+    T: Serialize + for<'de> Deserialize<'de>,
+    F: FnOnce(&mut T) -> Result<LockUpdate<R>>,
+{
+    // Deleted while we waited for it: a write now would go nowhere.
+    if file.metadata()?.nlink() == 0 {
+        bail!("Lockfile {:?} was removed", path);
+    }
+    let (mut data, recovered) = match read_json(file) {
+        Ok(data) => (data, false),
+        Err(e) => {
+            let backup = read_backup(path);
+            let from_backup = backup.is_some();
+            match backup.or_else(empty) {
+                Some(data) => {
+                    // A freshly created lock is empty; anything else
+                    // unreadable is worth a warning.
+                    if file.metadata()?.len() > 0 {
+                        eprintln!(
+                            "Warning: {:?} is unreadable ({:#}); {}",
+                            path,
+                            e,
+                            if from_backup {
+                                "restoring its last good copy"
+                            } else {
+                                "starting over empty"
+                            }
+                        );
+                    }
+                    (data, true)
+                }
+                None => return Err(e),
+            }
+        }
+    };
+
+    match update(&mut data)? {
+        LockUpdate::Keep(result) if !recovered => Ok((result, None)),
+        LockUpdate::Write(result) | LockUpdate::Keep(result) => {
+            write_json(file, &data)?;
+            Ok((result, Some(Changed::Written)))
+        }
+        LockUpdate::Delete(result) => {
+            // Unlinked while still locked, so nobody can slip a write in
+            // between the decision and the delete.
+            fsutil::remove_file(path)
+                .with_context(|| format!("Failed to delete lockfile: {:?}", path))?;
+            let _ = fsutil::remove_file(&backup_path(path));
+            Ok((result, Some(Changed::Deleted)))
+        }
+    }
 }
 
 /// Delete server lockfile
 pub fn delete_server_lock(name: &str) -> Result<()> {
     let path = server_lockfile_path(name)?;
     if path.exists() {
-        std::fs::remove_file(&path)
+        fsutil::remove_file(&path)
             .with_context(|| format!("Failed to delete server lockfile: {:?}", path))?;
     }
+    let _ = fsutil::remove_file(&backup_path(&path));
+    super::registry::remove(name);
     Ok(())
 }
 
@@ -200,34 +1183,39 @@ pub fn delete_server_lock(name: &str) -> Result<()> {
 pub fn delete_clients_lock(name: &str) -> Result<()> {
     let path = clients_lockfile_path(name)?;
     if path.exists() {
-        std::fs::remove_file(&path)
+        fsutil::remove_file(&path)
             .with_context(|| format!("Failed to delete clients lockfile: {:?}", path))?;
     }
+    let _ = fsutil::remove_file(&backup_path(&path));
     Ok(())
 }
 
-/// Delete both lockfiles for `name`, but only if the server lockfile still
+/// Delete both lockfiles (and the watcher heartbeat) for `name`, but only if the server lockfile still
 /// refers to `pid`.
 ///
 /// This guards against deleting a *newer* instance's lockfiles after a restart
 /// reused the name: if the server lock now names a different pid, we leave both
 /// files alone. If the lock is already gone/unreadable, deletion is a safe
 /// no-op. Used by both the watcher and `stop` so teardown has a single,
-/// race-free cleanup path.
-///
-/// Note: the pid check and the unlink are not one atomic step, so there is a
-/// microsecond-wide window where a brand-new instance could publish between the
-/// check and the unlink and have its `server.json` removed. In practice this is
-/// unreachable: `stop` waits for full teardown before returning, so restarts are
-/// sequential, and the watcher only calls this once its own server is dead.
+/// race-free cleanup path. The pid check and the unlink of `server.json`
+/// happen under its lock, so a new instance can't publish in between.
 pub fn delete_locks_owned_by(name: &str, pid: i32) {
-    if let Ok(lock) = read_server_lock(name) {
-        if lock.pid != pid {
-            return;
+    let owned = update_server_lock(name, |lock| {
+        Ok(if lock.pid == pid {
+            LockUpdate::Delete(true)
+        } else {
+            LockUpdate::Keep(false)
+        })
+    });
+    match owned {
+        Ok(false) => return,
+        Ok(true) => {}
+        Err(_) => {
+            let _ = delete_server_lock(name);
         }
     }
-    let _ = delete_server_lock(name);
     let _ = delete_clients_lock(name);
+    let _ = super::heartbeat::delete_heartbeat(name);
 }
 
 /// Check if server lockfile exists
@@ -243,3 +1231,167 @@ pub fn clients_lock_exists(name: &str) -> bool {
         .map(|p| p.exists())
         .unwrap_or(false)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lock_timeout_names_holder() {
+        let path = std::env::temp_dir().join(format!("lock-timeout-{}.json", std::process::id()));
+        let holder = File::create(&path).unwrap();
+        // flock locks belong to the open file description, so a second open in
+        // this same process contends with the first.
+        flock(holder.as_raw_fd(), FlockArg::LockExclusive).unwrap();
+
+        let started = Instant::now();
+        let err = with_lock_timeout(&path, Duration::from_millis(200), |_| Ok(())).unwrap_err();
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        let message = err.to_string();
+        assert!(message.contains("lock held by"), "{}", message);
+        #[cfg(target_os = "linux")]
+        assert!(
+            message.contains(&format!("PID {}", std::process::id())),
+            "{}",
+            message
+        );
+
+        drop(holder);
+        assert!(with_lock_timeout(&path, Duration::from_millis(200), |_| Ok(())).is_ok());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_lockdir_and_lockfiles_are_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("lockdir-private-{}", std::process::id()));
+        create_private_dir(&dir).unwrap();
+        let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&dir), LOCKDIR_MODE);
+        assert!(check_lockfile_dir_owner(&dir).is_ok());
+
+        let path = dir.join("test.server.json");
+        with_lock(&path, |file| write_json(file, &ClientsLock::new())).unwrap();
+        assert_eq!(mode(&path), LOCKFILE_MODE);
+        assert_eq!(mode(&backup_path(&path)), LOCKFILE_MODE);
+
+        // Nor may anyone else write to it.
+        for loose in [0o777, 0o770, 0o1777] {
+            std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(loose)).unwrap();
+            let err = check_lockfile_dir_owner(&dir).unwrap_err();
+            assert!(err.to_string().contains("writable by other users"), "{}", err);
+        }
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(LOCKDIR_MODE)).unwrap();
+        assert!(create_private_dir(&dir).is_ok());
+
+        // Only root can hand the directory to someone else.
+        if current_uid() == 0 {
+            std::os::unix::fs::chown(&dir, Some(65534), None).unwrap();
+            let err = check_lockfile_dir_owner(&dir).unwrap_err();
+            assert!(err.to_string().contains("refusing"), "{}", err);
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_update_lock_writes_keeps_and_deletes() {
+        let dir = std::env::temp_dir().join(format!("update-lock-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("clients.json");
+        let empty = || Some(ClientsLock::new());
+        let insert = |lock: &mut ClientsLock| {
+            lock.clients.insert(1, ClientInfo::new(1, None));
+            Ok(LockUpdate::Write(lock.refcount()))
+        };
+
+        // Missing locks are only created when asked to be.
+        assert!(update_lock(&path, false, || None, insert).is_err());
+        let (refcount, _) = update_lock(&path, true, empty, insert).unwrap();
+        assert_eq!(refcount, 1);
+
+        let before = std::fs::read(&path).unwrap();
+        let keep = |lock: &mut ClientsLock| Ok(LockUpdate::Keep(lock.refcount()));
+        let (refcount, changed) = update_lock(&path, false, || None, keep).unwrap();
+        assert_eq!(refcount, 1);
+        assert!(changed.is_none());
+        assert_eq!(std::fs::read(&path).unwrap(), before);
+
+        // A corrupt lock is restored from its backup even if nothing changed.
+        std::fs::write(&path, "{").unwrap();
+        let (_, changed) = update_lock(&path, false, || None, keep).unwrap();
+        assert!(matches!(changed, Some(Changed::Written)));
+        assert_eq!(std::fs::read(&path).unwrap(), before);
+
+        let delete = |_: &mut ClientsLock| Ok(LockUpdate::Delete(()));
+        update_lock(&path, false, || None, delete).unwrap();
+        assert!(!path.exists());
+        assert!(!backup_path(&path).exists());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_validate_name_keeps_servers_in_their_directory() {
+        for name in [
+            "",
+            ".",
+            "..",
+            "../escape",
+            "a/b",
+            "registry.json",
+            "start.lock",
+        ] {
+            let e = validate_name(name).unwrap_err();
+            assert_eq!(
+                ErrorKind::of(&e),
+                Some(ErrorKind::InvalidArgs),
+                "{:?}",
+                name
+            );
+        }
+        for name in ["web", "db.primary", "..hidden", "my-server_2"] {
+            assert!(validate_name(name).is_ok(), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn test_refcount_derived_from_clients() {
+        // A stored refcount that disagrees with the client map is ignored.
+        let lock: ClientsLock = serde_json::from_str(
+            r#"{"refcount": 3, "clients": {"42": {"attached_at": "2024-01-01T00:00:00Z"}}}"#,
+        )
+        .unwrap();
+        assert_eq!(lock.refcount(), 1);
+        assert_eq!(lock.shutdown_generation, 0);
+
+        let written = serde_json::to_value(&lock).unwrap();
+        assert_eq!(written["refcount"], 1);
+        assert!(written["clients"]["42"].is_object());
+    }
+
+    #[test]
+    fn test_client_pid_reuse_detected() {
+        let pid = std::process::id() as i32;
+        let client = ClientInfo::new(pid, None);
+        assert!(client.is_alive(pid));
+        assert!(!client.pid_reused(pid));
+
+        // Entries from before start stamps were recorded only check liveness.
+        let legacy = ClientInfo {
+            start_time: None,
+            ..client.clone()
+        };
+        assert!(legacy.is_alive(pid));
+
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        {
+            let recycled = ClientInfo {
+                start_time: client.start_time.map(|stamp| stamp + 1),
+                ..client
+            };
+            assert!(!recycled.is_alive(pid));
+            assert!(recycled.pid_reused(pid));
+        }
+    }
+}
@@ -1,8 +1,13 @@
+use super::filter::{server_tags, ServerFilter};
+use super::lockfile::{open_lockfile, ClientInfo};
 use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs::OpenOptions;
-use std::io::Write;
-use std::path::PathBuf;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct InvocationLog {
@@ -40,53 +45,389 @@ impl InvocationLog {
 
 /// Get path to invocation log
 pub fn invocation_log_path(name: &str) -> Result<PathBuf> {
-    let dir = super::lockfile::ensure_lockfile_dir()?;
-    Ok(dir.join(format!("{}.invocations.log", name)))
+    super::lockfile::server_file(name, "invocations.log")
 }
 
 /// Append invocation to log
 pub fn log_invocation(name: &str, log: &InvocationLog) -> Result<()> {
+    append_record(&invocation_log_path(name)?, log)
+}
+
+/// Read recent invocations (last N lines)
+pub fn read_recent_invocations(name: &str, count: usize) -> Result<Vec<InvocationLog>> {
+    read_recent_records(&invocation_log_path(name)?, count)
+}
+
+/// Rebuild a server's client set by replaying its invocation log: the clients
+/// it was started with (the last successful `start`), plus every `incref`,
+/// minus every `decref` since. `None` if the log has no start to replay from.
+///
+/// Clients that died without detaching aren't in the log (the watcher drops
+/// them), so callers should still filter out dead PIDs.
+pub fn replay_clients(name: &str) -> Result<Option<HashMap<i32, ClientInfo>>> {
+    let entries = read_recent_invocations(name, usize::MAX)?;
+    Ok(replay(&entries))
+}
+
+fn replay(entries: &[InvocationLog]) -> Option<HashMap<i32, ClientInfo>> {
+    let start = entries
+        .iter()
+        .rposition(|entry| entry.command == "start" && entry.result == "success")?;
+
+    let field = |entry: &InvocationLog, key: &str| {
+        entry
+            .metadata
+            .as_ref()
+            .and_then(|metadata| metadata.get(key))
+            .cloned()
+    };
+    let mut clients: HashMap<i32, ClientInfo> = field(&entries[start], "clients")
+        .and_then(|clients| serde_json::from_value(clients).ok())
+        .unwrap_or_default();
+
+    for entry in entries[start + 1..]
+        .iter()
+        .filter(|e| e.result == "success")
+    {
+        let Some(pid) = field(entry, "client_pid").and_then(|pid| pid.as_i64()) else {
+            continue;
+        };
+        match entry.command.as_str() {
+            "incref" => {
+                let metadata = field(entry, "metadata")
+                    .and_then(|metadata| metadata.as_str().map(str::to_string));
+                let start_time = field(entry, "start_time").and_then(|stamp| stamp.as_u64());
+                clients.insert(
+                    pid as i32,
+                    ClientInfo {
+                        attached_at: entry.timestamp,
+                        metadata,
+                        start_time,
+                        command: None,
+                        cmdline: None,
+                    },
+                );
+            }
+            "decref" => {
+                clients.remove(&(pid as i32));
+            }
+            _ => {}
+        }
+    }
+    Some(clients)
+}
+
+/// One entry in the watcher's own event log (`<name>.watcher.log`): what the
+/// watcher saw and decided, as opposed to the commands users ran.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatcherEvent {
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    pub watcher_pid: i32,
+    /// Event kind, e.g. "grace-start" or "kill-escalation".
+    pub event: String,
+    #[serde(default, skip_serializing_if = "serde_json::Value::is_null")]
+    pub details: serde_json::Value,
+}
+
+/// Get path to the watcher event log
+pub fn watcher_log_path(name: &str) -> Result<PathBuf> {
+    super::lockfile::server_file(name, "watcher.log")
+}
+
+/// Append an event to the watcher event log, stamped with the calling
+/// process as the watcher
+pub fn log_watcher_event(name: &str, event: &str, details: serde_json::Value) -> Result<()> {
+    append_record(
+        &watcher_log_path(name)?,
+        &WatcherEvent {
+            timestamp: chrono::Utc::now(),
+            watcher_pid: std::process::id() as i32,
+            event: event.to_string(),
+            details,
+        },
+    )
+}
+
+/// Read recent watcher events (last N lines)
+pub fn read_recent_watcher_events(name: &str, count: usize) -> Result<Vec<WatcherEvent>> {
+    read_recent_records(&watcher_log_path(name)?, count)
+}
+
+/// A server's append-only logs, as named in its directory.
+pub const LOG_FILES: &[&str] = &["invocations.log", "watcher.log"];
+
+/// How long a stopped server's logs outlive their last write, unless
+/// overridden with `SHAREDSERVER_LOG_RETENTION` (e.g. "72h").
+pub const DEFAULT_LOG_RETENTION: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// Size past which a log is trimmed to its newest entries, unless overridden
+/// with `SHAREDSERVER_LOG_MAX_SIZE` (in bytes).
+pub const DEFAULT_LOG_MAX_SIZE: u64 = 1024 * 1024;
+
+/// The log retention in effect: `SHAREDSERVER_LOG_RETENTION` if set and
+/// valid, else [`DEFAULT_LOG_RETENTION`].
+pub fn log_retention() -> Duration {
+    std::env::var("SHAREDSERVER_LOG_RETENTION")
+        .ok()
+        .and_then(|r| super::duration::parse_duration(&r).ok())
+        .unwrap_or(DEFAULT_LOG_RETENTION)
+}
+
+/// The per-log size cap in effect: `SHAREDSERVER_LOG_MAX_SIZE` if set and
+/// valid, else [`DEFAULT_LOG_MAX_SIZE`].
+pub fn log_max_size() -> u64 {
+    std::env::var("SHAREDSERVER_LOG_MAX_SIZE")
+        .ok()
+        .and_then(|size| size.parse().ok())
+        .unwrap_or(DEFAULT_LOG_MAX_SIZE)
+}
+
+/// What [`prune_logs`] did (or, on a dry run, would do) to one log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum PruneAction {
+    /// Deleted: the server is gone and the log saw no writes within the
+    /// retention period.
+    Removed { size: u64 },
+    /// Cut down to its newest entries for exceeding the size cap.
+    Trimmed { from: u64, to: u64 },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PrunedLog {
+    pub name: String,
+    pub file: &'static str,
+    #[serde(flatten)]
+    pub action: PruneAction,
+}
+
+/// Garbage-collect every server's logs. A server with no lock whose logs have
+/// all been idle for longer than [`log_retention`] loses them (and its
+/// directory, once empty); any other log over [`log_max_size`] keeps only its
+/// newest entries, up to half the cap. Only the logs of servers `filter`
+/// selects by name and tags are touched. With `dry_run`, nothing is changed.
+pub fn prune_logs(dry_run: bool, filter: &ServerFilter) -> Result<Vec<PrunedLog>> {
+    let retention = log_retention();
+    let max_size = log_max_size();
+    let mut pruned = Vec::new();
+
+    for name in super::lockfile::servers_with(LOG_FILES)? {
+        if !filter.matches_name(&name)
+            || (!filter.tags.is_empty() && !filter.matches_tags(&server_tags(&name)))
+        {
+            continue;
+        }
+        let mut logs = Vec::new();
+        for file in LOG_FILES {
+            let path = super::lockfile::server_file(&name, file)?;
+            if let Ok(metadata) = std::fs::metadata(&path) {
+                logs.push((*file, path, metadata));
+            }
+        }
+
+        let idle = logs.iter().all(|(_, _, metadata)| {
+            metadata
+                .modified()
+                .ok()
+                .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+                .is_some_and(|age| age > retention)
+        });
+        if idle && !super::lockfile::server_lock_exists(&name) {
+            for (file, path, metadata) in &logs {
+                if !dry_run {
+                    super::fsutil::remove_file(path)
+                        .with_context(|| format!("Failed to remove log: {:?}", path))?;
+                }
+                pruned.push(PrunedLog {
+                    name: name.clone(),
+                    file,
+                    action: PruneAction::Removed {
+                        size: metadata.len(),
+                    },
+                });
+            }
+            if !dry_run {
+                // Only a per-server directory; flat-layout logs sit in the
+                // lock directory itself.
+                let dir = logs.first().and_then(|(_, path, _)| path.parent());
+                if let Some(dir) = dir.filter(|dir| dir.file_name() == Some(name.as_ref())) {
+                    let _ = super::fsutil::remove_dir(dir);
+                }
+            }
+            continue;
+        }
+
+        for (file, path, metadata) in &logs {
+            if metadata.len() <= max_size {
+                continue;
+            }
+            let to = if dry_run {
+                trimmed_len(&std::fs::read(path)?, max_size / 2)
+            } else {
+                trim_log(path, max_size / 2)?
+            };
+            pruned.push(PrunedLog {
+                name: name.clone(),
+                file,
+                action: PruneAction::Trimmed {
+                    from: metadata.len(),
+                    to,
+                },
+            });
+        }
+    }
+    Ok(pruned)
+}
+
+/// Cut the log at `path` down to its newest whole lines totalling at most
+/// `keep` bytes, in place under the same lock appenders take. Returns the new
+/// size.
+fn trim_log(path: &Path, keep: u64) -> Result<u64> {
     use nix::fcntl::{flock, FlockArg};
     use std::os::unix::io::AsRawFd;
 
-    let path = invocation_log_path(name)?;
-
     let mut file = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(&path)
-        .with_context(|| format!("Failed to open invocation log: {:?}", path))?;
+        .read(true)
+        .write(true)
+        .open(path)
+        .with_context(|| format!("Failed to open log: {:?}", path))?;
+    flock(file.as_raw_fd(), FlockArg::LockExclusive)
+        .with_context(|| format!("Failed to lock log: {:?}", path))?;
+
+    let mut contents = Vec::new();
+    file.read_to_end(&mut contents)?;
+    let tail = contents.len() as u64 - trimmed_len(&contents, keep);
+    let kept = contents.split_off(tail as usize);
+    file.seek(SeekFrom::Start(0))?;
+    file.set_len(0)?;
+    file.write_all(&kept)?;
+    file.sync_all()?;
+    Ok(kept.len() as u64)
+}
+
+/// Length of the longest run of whole lines at the end of `contents` that
+/// fits in `keep` bytes.
+fn trimmed_len(contents: &[u8], keep: u64) -> u64 {
+    let len = contents.len() as u64;
+    if len <= keep {
+        return len;
+    }
+    let cut = (len - keep) as usize;
+    // Start after a newline, so the first kept line is whole.
+    match contents[cut - 1..].iter().position(|&byte| byte == b'\n') {
+        Some(newline) => len - (cut + newline) as u64,
+        None => 0,
+    }
+}
+
+/// A lock acquisition that took longer than the reporting threshold (see
+/// [`lock_warn_threshold`](super::lockfile::lock_warn_threshold)).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LockWait {
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    /// The process that waited.
+    pub pid: i32,
+    pub path: PathBuf,
+    /// "shared" or "exclusive".
+    pub mode: String,
+    /// Source location of the code that took the lock.
+    pub caller: String,
+    pub waited_ms: u64,
+    /// Who held the lock when the wait began, where the platform can tell.
+    pub holders: Vec<i32>,
+}
+
+/// Get path to the lock contention log, shared by all servers
+pub fn contention_log_path() -> Result<PathBuf> {
+    Ok(super::lockfile::ensure_lockfile_dir()?.join("contention.log"))
+}
+
+/// Append a slow lock acquisition to the contention log
+pub fn log_lock_wait(wait: &LockWait) -> Result<()> {
+    append_record(&contention_log_path()?, wait)
+}
+
+/// Append one JSON record as a line of `path`.
+fn append_record<T: Serialize>(path: &Path, record: &T) -> Result<()> {
+    use nix::fcntl::{flock, FlockArg};
+    use std::os::unix::io::AsRawFd;
+
+    let mut file = open_lockfile(OpenOptions::new().create(true).append(true), path)
+        .with_context(|| format!("Failed to open log: {:?}", path))?;
 
     // Serialize the whole record (with its newline) once and write it in a
     // single call under an exclusive lock, so concurrent writers can never
-    // interleave partial lines into the audit log.
+    // interleave partial lines into the log.
     let _ = flock(file.as_raw_fd(), FlockArg::LockExclusive);
-    let line = format!("{}\n", serde_json::to_string(log)?);
+    let line = format!("{}\n", serde_json::to_string(record)?);
     file.write_all(line.as_bytes())?;
 
     Ok(())
 }
 
-/// Read recent invocations (last N lines)
-pub fn read_recent_invocations(name: &str, count: usize) -> Result<Vec<InvocationLog>> {
-    let path = invocation_log_path(name)?;
-
+/// Parse the last `count` lines of a JSONL log, skipping unreadable ones.
+fn read_recent_records<T: DeserializeOwned>(path: &Path, count: usize) -> Result<Vec<T>> {
     if !path.exists() {
         return Ok(Vec::new());
     }
 
-    let contents = std::fs::read_to_string(&path)
-        .with_context(|| format!("Failed to read invocation log: {:?}", path))?;
+    let contents =
+        std::fs::read_to_string(path).with_context(|| format!("Failed to read log: {:?}", path))?;
 
     let lines: Vec<&str> = contents.lines().collect();
     let start = lines.len().saturating_sub(count);
 
-    let mut logs = Vec::new();
-    for line in &lines[start..] {
-        if let Ok(log) = serde_json::from_str::<InvocationLog>(line) {
-            logs.push(log);
-        }
+    Ok(lines[start..]
+        .iter()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_trimmed_len_keeps_whole_lines() {
+        let contents = b"aaaa\nbbbb\ncccc\n";
+        assert_eq!(trimmed_len(contents, 100), 15);
+        assert_eq!(trimmed_len(contents, 10), 10); // "bbbb\ncccc\n"
+        assert_eq!(trimmed_len(contents, 9), 5); // "cccc\n"
+        assert_eq!(trimmed_len(contents, 4), 0);
     }
 
-    Ok(logs)
+    #[test]
+    fn test_replay_clients_since_last_start() {
+        let entries = vec![
+            // An earlier instance's history is ignored.
+            InvocationLog::success(
+                "start",
+                &[],
+                Some(json!({ "clients": { "1": ClientInfo::new(1, None) } })),
+            ),
+            InvocationLog::success("incref", &[], Some(json!({ "client_pid": 2 }))),
+            InvocationLog::success(
+                "start",
+                &[],
+                Some(json!({ "clients": { "10": ClientInfo::new(1, None) } })),
+            ),
+            InvocationLog::success(
+                "incref",
+                &[],
+                Some(json!({ "client_pid": 11, "metadata": "nvim" })),
+            ),
+            InvocationLog::success("incref", &[], Some(json!({ "client_pid": 12 }))),
+            InvocationLog::success("decref", &[], Some(json!({ "client_pid": 10 }))),
+            InvocationLog::error("incref", &[], "refused".to_string()),
+        ];
+
+        let clients = replay(&entries).unwrap();
+        let mut pids: Vec<i32> = clients.keys().copied().collect();
+        pids.sort_unstable();
+        assert_eq!(pids, vec![11, 12]);
+        assert_eq!(clients[&11].metadata.as_deref(), Some("nvim"));
+
+        assert!(replay(&entries[1..2]).is_none(), "no start to replay from");
+    }
 }
@@ -0,0 +1,266 @@
+//! Timestamped capture of server output (`--log-timestamps`).
+//!
+//! Normally the server's stdout/stderr are dup'd straight onto the log file.
+//! In capture mode they are pipes instead, and a small relay process reads
+//! them and appends each line to the log prefixed with an RFC 3339 timestamp
+//! and a stream tag:
+//!
+//! ```text
+//! 2026-01-02T03:04:05.678Z [stdout] listening on :8080
+//! 2026-01-02T03:04:06.001Z [stderr] warning: cache miss
+//! ```
+//!
+//! The relay is a separate process rather than a loop in the watcher because
+//! the watcher blocks (SIGTERM → SIGKILL escalation, health probes) and must
+//! stay single-threaded so it can fork restarts; a server writing into a pipe
+//! nobody drains would stall once the pipe buffer filled. The watcher keeps the
+//! write ends and hands them to every server it launches, so one relay covers
+//! restarts too. The relay exits once every writer (watcher, server, and any
+//! stray grandchildren) has gone away.
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::fs::File;
+use std::io::Write;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+
+/// A line longer than this is written out in pieces rather than buffered
+/// without bound.
+const MAX_LINE: usize = 64 * 1024;
+
+/// Splits one stream's output into lines and stamps each with the time it was
+/// read.
+#[derive(Debug)]
+pub struct LineStamper {
+    tag: &'static str,
+    pending: Vec<u8>,
+}
+
+impl LineStamper {
+    pub fn new(tag: &'static str) -> Self {
+        Self {
+            tag,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Append every complete line in `data` to `out`; a trailing partial line
+    /// is held until the rest of it arrives.
+    pub fn feed(&mut self, data: &[u8], now: DateTime<Utc>, out: &mut Vec<u8>) {
+        for &byte in data {
+            if byte == b'\n' {
+                self.emit(now, out);
+            } else {
+                self.pending.push(byte);
+                if self.pending.len() >= MAX_LINE {
+                    self.emit(now, out);
+                }
+            }
+        }
+    }
+
+    /// Flush a final line that never got its newline (the stream closed).
+    pub fn finish(&mut self, now: DateTime<Utc>, out: &mut Vec<u8>) {
+        if !self.pending.is_empty() {
+            self.emit(now, out);
+        }
+    }
+
+    fn emit(&mut self, now: DateTime<Utc>, out: &mut Vec<u8>) {
+        out.extend_from_slice(now.to_rfc3339_opts(SecondsFormat::Millis, true).as_bytes());
+        out.extend_from_slice(format!(" [{}] ", self.tag).as_bytes());
+        out.append(&mut self.pending);
+        out.push(b'\n');
+    }
+}
+
+/// The write ends of the capture pipes, held by the watcher and dup'd onto
+/// each server's stdout/stderr.
+#[derive(Debug)]
+pub struct LogCapture {
+    stdout: OwnedFd,
+    stderr: OwnedFd,
+    relay: i32,
+}
+
+impl LogCapture {
+    /// Open `log_path`, create the pipes, and fork the relay. Fails (leaving
+    /// nothing behind) if the log can't be opened or the fork fails, so the
+    /// caller can fall back to plain redirection.
+    ///
+    /// SAFETY: forks; the relay child only reads, formats, and writes, but like
+    /// the rest of the watcher this relies on the process being
+    /// single-threaded.
+    pub fn spawn(log_path: &str) -> Result<Self> {
+        let log = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(log_path)
+            .with_context(|| format!("Failed to open log file: {}", log_path))?;
+        let (stdout_read, stdout) = cloexec_pipe()?;
+        let (stderr_read, stderr) = cloexec_pipe()?;
+
+        match unsafe { nix::unistd::fork() } {
+            Ok(nix::unistd::ForkResult::Parent { child }) => Ok(Self {
+                stdout,
+                stderr,
+                relay: child.as_raw(),
+            }),
+            Ok(nix::unistd::ForkResult::Child) => {
+                // The relay must not hold write ends, or it would never see EOF.
+                drop(stdout);
+                drop(stderr);
+                run_relay(stdout_read, stderr_read, log);
+                std::process::exit(0);
+            }
+            Err(e) => bail!("Failed to fork log relay: {}", e),
+        }
+    }
+
+    pub fn stdout_fd(&self) -> RawFd {
+        self.stdout.as_raw_fd()
+    }
+
+    pub fn stderr_fd(&self) -> RawFd {
+        self.stderr.as_raw_fd()
+    }
+
+    /// The relay process, a child of whoever spawned it.
+    pub fn relay_pid(&self) -> i32 {
+        self.relay
+    }
+}
+
+fn cloexec_pipe() -> Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0; 2];
+    // SAFETY: pipe writes two new descriptors into `fds`.
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } < 0 {
+        return Err(std::io::Error::last_os_error()).context("Failed to create log pipe");
+    }
+    // Not inherited by anything the watcher execs (health probes); the server
+    // gets its copies via dup2, which clears the flag. pipe2(O_CLOEXEC) would
+    // close the window, but macOS lacks it and the watcher is single-threaded.
+    for fd in fds {
+        // SAFETY: fcntl on a descriptor we own.
+        unsafe {
+            let flags = libc::fcntl(fd, libc::F_GETFD);
+            libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC);
+        }
+    }
+    // SAFETY: both descriptors were just created and are owned by nobody else.
+    Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+}
+
+/// Copy both pipes into `log` line by line until both reach EOF.
+fn run_relay(stdout: OwnedFd, stderr: OwnedFd, mut log: File) {
+    let mut streams = [
+        (Some(stdout), LineStamper::new("stdout")),
+        (Some(stderr), LineStamper::new("stderr")),
+    ];
+    let mut buf = [0u8; 8192];
+    let mut out = Vec::new();
+
+    while streams.iter().any(|(fd, _)| fd.is_some()) {
+        let mut fds: Vec<libc::pollfd> = streams
+            .iter()
+            .map(|(fd, _)| libc::pollfd {
+                // poll ignores negative descriptors, so closed streams stay in
+                // place and indices keep lining up.
+                fd: fd.as_ref().map_or(-1, |fd| fd.as_raw_fd()),
+                events: libc::POLLIN,
+                revents: 0,
+            })
+            .collect();
+        // SAFETY: `fds` is a valid, correctly-sized array of pollfd.
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } < 0 {
+            if std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted {
+                continue;
+            }
+            return;
+        }
+
+        for ((fd, stamper), polled) in streams.iter_mut().zip(&fds) {
+            let Some(raw) = fd.as_ref().map(|fd| fd.as_raw_fd()) else {
+                continue;
+            };
+            if polled.revents == 0 {
+                continue;
+            }
+            // SAFETY: reading into a local buffer of the given length.
+            let n = unsafe { libc::read(raw, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+            if n > 0 {
+                stamper.feed(&buf[..n as usize], Utc::now(), &mut out);
+            } else if n == 0
+                || std::io::Error::last_os_error().kind() != std::io::ErrorKind::Interrupted
+            {
+                stamper.finish(Utc::now(), &mut out);
+                *fd = None;
+            }
+        }
+
+        if !out.is_empty() {
+            // Nowhere to report a failed write; keep draining so the server
+            // never blocks on a full pipe.
+            let _ = log.write_all(&out);
+            out.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(secs, 0).unwrap()
+    }
+
+    #[test]
+    fn test_stamps_complete_lines_and_holds_partial() {
+        let mut stamper = LineStamper::new("stdout");
+        let mut out = Vec::new();
+
+        stamper.feed(b"hello\nwor", at(0), &mut out);
+        assert_eq!(
+            String::from_utf8_lossy(&out),
+            "1970-01-01T00:00:00.000Z [stdout] hello\n"
+        );
+
+        out.clear();
+        stamper.feed(b"ld\n", at(1), &mut out);
+        assert_eq!(
+            String::from_utf8_lossy(&out),
+            "1970-01-01T00:00:01.000Z [stdout] world\n"
+        );
+    }
+
+    #[test]
+    fn test_finish_flushes_unterminated_line() {
+        let mut stamper = LineStamper::new("stderr");
+        let mut out = Vec::new();
+
+        stamper.feed(b"no newline", at(0), &mut out);
+        assert!(out.is_empty());
+        stamper.finish(at(0), &mut out);
+        assert_eq!(
+            String::from_utf8_lossy(&out),
+            "1970-01-01T00:00:00.000Z [stderr] no newline\n"
+        );
+
+        // Nothing pending: nothing written.
+        out.clear();
+        stamper.finish(at(0), &mut out);
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn test_overlong_line_is_split() {
+        let mut stamper = LineStamper::new("stdout");
+        let mut out = Vec::new();
+
+        stamper.feed(&vec![b'x'; MAX_LINE + 10], at(0), &mut out);
+        assert_eq!(out.iter().filter(|&&b| b == b'\n').count(), 1);
+        stamper.finish(at(0), &mut out);
+        assert_eq!(out.iter().filter(|&&b| b == b'\n').count(), 2);
+    }
+}
@@ -0,0 +1,349 @@
+//! The high-level library API: what the CLI's `use`, `unuse`, `list`,
+//! `info`, `stop`, and `admin doctor` do, with typed results, for Rust tools
+//! (build systems, test harnesses) that would otherwise shell out.
+//!
+//! ```no_run
+//! use sharedserver::{ServerManager, UseOptions};
+//!
+//! let manager = ServerManager::new();
+//! let handle = manager.use_server(
+//!     "chroma",
+//!     &UseOptions {
+//!         command: vec!["chroma".into(), "run".into()],
+//!         grace_period: Some("10m".into()),
+//!         ..Default::default()
+//!     },
+//! )?;
+//! let info = manager.info("chroma")?;
+//! println!("{} clients", info.refcount);
+//! drop(handle); // releases the reference
+//! # anyhow::Ok(())
+//! ```
+//!
+//! Errors are `anyhow` errors. Those a caller would tell apart carry an
+//! [`ErrorKind`] (see [`ErrorKind::of`]), the same classification behind the
+//! CLI's exit codes.
+//!
+//! A server's watcher is part of the `sharedserver` binary, so starting a
+//! server runs `sharedserver use`, and `doctor`, whose repairs live there too,
+//! runs `sharedserver admin doctor`. Everything else happens in this process.
+
+use super::error::ErrorKind;
+use super::handle::{attach_client, attachable, AttachOptions, ServerHandle};
+use super::lockfile::{read_clients_lock, read_server_lock, servers_with, ClientInfo, ServerLock};
+use super::state::{get_server_state, ServerState};
+use super::stop::{request_stop, torn_down};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// Names the `sharedserver` binary to run, when it isn't the one on `PATH`.
+pub const BINARY_ENV: &str = "SHAREDSERVER_BIN";
+
+/// Entry point to the library API.
+#[derive(Debug, Clone)]
+pub struct ServerManager {
+    binary: PathBuf,
+}
+
+impl Default for ServerManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// How to start a server [`ServerManager::use_server`] finds stopped. Ignored
+/// if it is already running, as for `sharedserver use`.
+#[derive(Debug, Clone, Default)]
+pub struct UseOptions {
+    /// The command to start the server with. Without one, the server must
+    /// already be running.
+    pub command: Vec<String>,
+    /// e.g. `"10m"` [default: the CLI's, 5m]
+    pub grace_period: Option<String>,
+    /// Environment overrides, `KEY=VALUE`.
+    pub env: Vec<String>,
+    pub log_file: Option<String>,
+    /// Any further `sharedserver use` flags, e.g. `["--restart", "on-failure"]`.
+    pub args: Vec<String>,
+    /// The client process the reference stands for. Defaults to the current
+    /// process.
+    pub pid: Option<i32>,
+    /// Shown next to the client in `info`.
+    pub metadata: Option<String>,
+}
+
+/// A running server, as `info` reports it.
+#[derive(Debug, Clone, Serialize)]
+pub struct ServerInfo {
+    pub name: String,
+    /// `Active`, `Grace`, or `Defunct`: stopped servers aren't reported.
+    pub state: ServerState,
+    pub refcount: u32,
+    /// The attached clients, by PID.
+    pub clients: HashMap<i32, ClientInfo>,
+    /// Everything recorded about the server (its PID, command, address,
+    /// health, ...).
+    pub server: ServerLock,
+}
+
+/// What `admin doctor` found and did.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DoctorReport {
+    pub servers: Vec<DoctorServer>,
+    pub summary: DoctorSummary,
+}
+
+/// One server's checkup.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DoctorServer {
+    pub name: String,
+    pub state: Option<String>,
+    /// `healthy`, `fixed`, or `unhealthy`.
+    pub status: String,
+    pub issues_found: u32,
+    pub issues_fixed: u32,
+    pub issues_unfixed: u32,
+    pub findings: Vec<DoctorFinding>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DoctorFinding {
+    /// The check it came from, e.g. `server_process`.
+    pub check: String,
+    /// `ok`, `issue`, `fixed`, `error`, or `note`.
+    pub kind: String,
+    /// `ok`, `info`, `warning`, or `error`.
+    pub severity: String,
+    /// For a repair, what doctor did, e.g. `remove_server_lock`.
+    #[serde(default)]
+    pub action: Option<String>,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct DoctorSummary {
+    pub servers_checked: usize,
+    pub healthy: usize,
+    pub issues_found: u32,
+    pub issues_fixed: u32,
+    /// Issues still present; the CLI exits non-zero for any.
+    pub issues_unfixed: u32,
+}
+
+impl ServerManager {
+    /// A manager that runs the `sharedserver` named by `SHAREDSERVER_BIN`, or
+    /// else the one on `PATH`, when it needs the binary.
+    pub fn new() -> Self {
+        let binary = std::env::var_os(BINARY_ENV)
+            .filter(|binary| !binary.is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("sharedserver"));
+        Self { binary }
+    }
+
+    /// A manager that runs `binary` when it needs the `sharedserver` binary.
+    pub fn with_binary(binary: impl Into<PathBuf>) -> Self {
+        Self {
+            binary: binary.into(),
+        }
+    }
+
+    /// Attach to `name`, starting it with `opts.command` if it isn't running.
+    /// The reference is released when the handle is dropped.
+    pub fn use_server(&self, name: &str, opts: &UseOptions) -> Result<ServerHandle> {
+        // If this starts it, the handle takes over the reference `use` took.
+        self.start(name, opts)?;
+        ServerHandle::attach(
+            name,
+            AttachOptions {
+                pid: opts.pid,
+                metadata: opts.metadata.clone(),
+            },
+        )
+    }
+
+    /// Like [`use_server`](Self::use_server), but the reference stays until
+    /// [`unuse`](Self::unuse) releases it (or the client dies), as with
+    /// `sharedserver use --pid`, rather than being tied to a handle. Returns
+    /// the refcount.
+    pub fn use_pid(&self, name: &str, opts: &UseOptions) -> Result<u32> {
+        if self.start(name, opts)? {
+            return Ok(read_clients_lock(name)?.refcount());
+        }
+        attachable(name, get_server_state(name)?)?;
+        let pid = opts.pid.unwrap_or(std::process::id() as i32);
+        attach_client(name, pid, opts.metadata.clone())
+    }
+
+    /// Run `sharedserver use` for `opts.pid` if `name` isn't running and
+    /// there is a command to start it with. It starts the server, or attaches
+    /// if another client got there first. Returns whether it ran.
+    fn start(&self, name: &str, opts: &UseOptions) -> Result<bool> {
+        let running = matches!(
+            get_server_state(name)?,
+            ServerState::Active | ServerState::Grace
+        );
+        if !running && !opts.command.is_empty() {
+            let pid = opts.pid.unwrap_or(std::process::id() as i32);
+            let mut args = vec!["use".to_string(), "--pid".to_string(), pid.to_string()];
+            if let Some(metadata) = &opts.metadata {
+                args.extend(["--metadata".to_string(), metadata.clone()]);
+            }
+            if let Some(grace_period) = &opts.grace_period {
+                args.extend(["--grace-period".to_string(), grace_period.clone()]);
+            }
+            for var in &opts.env {
+                args.extend(["--env".to_string(), var.clone()]);
+            }
+            if let Some(log_file) = &opts.log_file {
+                args.extend(["--log-file".to_string(), log_file.clone()]);
+            }
+            args.extend(opts.args.iter().cloned());
+            args.push(name.to_string());
+            args.push("--".to_string());
+            args.extend(opts.command.iter().cloned());
+            self.run(&args)?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// Release client `pid`'s reference on `name` (default: this process's),
+    /// e.g. one taken by `sharedserver use`. Returns the refcount left; zero
+    /// starts the grace period. Handles release their own.
+    pub fn unuse(&self, name: &str, pid: Option<i32>) -> Result<u32> {
+        let pid = pid.unwrap_or(std::process::id() as i32);
+        super::handle::detach_client(name, pid)
+    }
+
+    /// Every running server, by name.
+    pub fn list(&self) -> Result<Vec<ServerInfo>> {
+        let mut servers = Vec::new();
+        for name in servers_with(&["server.json"])? {
+            match self.info(&name) {
+                Ok(info) => servers.push(info),
+                Err(e) if ErrorKind::of(&e) == Some(ErrorKind::NotRunning) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(servers)
+    }
+
+    /// `name`'s state, clients, and recorded details.
+    pub fn info(&self, name: &str) -> Result<ServerInfo> {
+        let state = get_server_state(name)?;
+        if state == ServerState::Stopped {
+            return Err(ErrorKind::NotRunning.error(format!("Server '{}' is not running", name)));
+        }
+        let server = read_server_lock(name)?;
+        let clients = read_clients_lock(name)
+            .map(|clients| clients.clients)
+            .unwrap_or_default();
+        Ok(ServerInfo {
+            name: name.to_string(),
+            state,
+            refcount: clients.len() as u32,
+            clients,
+            server,
+        })
+    }
+
+    /// Stop `name` whatever its refcount, waiting up to `timeout` for its
+    /// watcher to tear it down.
+    pub fn stop(&self, name: &str, timeout: Duration) -> Result<()> {
+        if get_server_state(name)? == ServerState::Stopped {
+            return Err(ErrorKind::NotRunning.error(format!("Server '{}' is not running", name)));
+        }
+        let server = read_server_lock(name)?;
+        request_stop(name, &server)?;
+        let start = Instant::now();
+        while !torn_down(name, &server) {
+            if start.elapsed() >= timeout {
+                anyhow::bail!("Server '{}' did not stop within {:?}", name, timeout);
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        Ok(())
+    }
+
+    /// Check every server's lockfiles, processes, and clients, repairing what
+    /// can be repaired, as `sharedserver admin doctor` does. Issues that
+    /// remain are in the report rather than an error.
+    pub fn doctor(&self) -> Result<DoctorReport> {
+        let output = self.output(&["admin", "doctor", "--format", "json"])?;
+        if !output.status.success()
+            && output.status.code() != Some(ErrorKind::IssuesRemain.exit_code())
+        {
+            return Err(cli_error(&output));
+        }
+        serde_json::from_slice(&output.stdout).context("Failed to read the doctor report")
+    }
+
+    /// Run the binary with `args`, failing with its error if it fails.
+    fn run(&self, args: &[String]) -> Result<()> {
+        let output = self.output(args)?;
+        if !output.status.success() {
+            return Err(cli_error(&output));
+        }
+        Ok(())
+    }
+
+    fn output<S: AsRef<std::ffi::OsStr>>(&self, args: &[S]) -> Result<std::process::Output> {
+        Command::new(&self.binary)
+            .args(args)
+            .stdin(Stdio::null())
+            .output()
+            .with_context(|| format!("Failed to run {}", self.binary.display()))
+    }
+}
+
+/// The binary's failure as an error, classified by its exit code.
+fn cli_error(output: &std::process::Output) -> anyhow::Error {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    // The CLI reports a failure as `Error: ...`, after any warnings.
+    let message = stderr
+        .lines()
+        .find_map(|line| line.strip_prefix("Error: "))
+        .unwrap_or(stderr.trim())
+        .to_string();
+    let kind = output
+        .status
+        .code()
+        .and_then(|code| ErrorKind::ALL.into_iter().find(|k| k.exit_code() == code));
+    match kind {
+        Some(kind) => kind.error(message),
+        None => anyhow::anyhow!("{}", message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::process::ExitStatusExt;
+
+    fn output(code: i32, stderr: &str) -> std::process::Output {
+        std::process::Output {
+            status: std::process::ExitStatus::from_raw(code << 8),
+            stdout: Vec::new(),
+            stderr: stderr.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_cli_error_is_classified_by_exit_code() {
+        let err = cli_error(&output(
+            11,
+            "Warning: noise\nError: Server 'db' is shutting down, retry\n\nCaused by:\n    x\n",
+        ));
+        assert_eq!(ErrorKind::of(&err), Some(ErrorKind::ShuttingDown));
+        assert_eq!(err.to_string(), "Server 'db' is shutting down, retry");
+
+        let err = cli_error(&output(1, "boom\n"));
+        assert_eq!(ErrorKind::of(&err), None);
+        assert_eq!(err.to_string(), "boom");
+    }
+}
@@ -1,16 +1,58 @@
+pub mod address;
+#[cfg(feature = "async")]
+pub mod aio;
+pub mod capacity;
+pub mod config;
+pub mod container;
+pub mod desktop;
 pub mod duration;
+pub mod error;
+pub mod events;
+pub mod exe;
+pub mod exit_notify;
+pub mod filter;
+pub mod front;
+pub mod fsutil;
+pub mod grace;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod handle;
 pub mod health;
+pub mod heartbeat;
+pub mod limits;
+pub mod lock_format;
 pub mod lockfile;
 pub mod log;
+pub mod log_capture;
+pub mod manager;
+pub mod notify;
+pub mod pool;
+pub mod probe;
+pub mod registry;
+pub mod restart;
+pub mod sd_notify;
+pub mod shared;
 pub mod state;
+pub mod stdio_mux;
+pub mod stop;
+pub mod telemetry;
+pub mod tombstone;
+pub mod webhook;
 
 pub use duration::parse_duration;
+pub use error::ErrorKind;
+pub use filter::ServerFilter;
 pub use health::{
-    is_process_alive, process_liveness, process_liveness_checked, process_start_stamp, Liveness,
+    boot_id, child_pids, is_descendant, is_process_alive, parent_pid, process_cmdline, process_cwd,
+    process_liveness, process_liveness_checked, process_name, process_start_stamp, Liveness,
 };
+pub use limits::{LimitAction, ResourceLimits, ResourceUsage};
 pub use lockfile::{
     clients_lock_exists, delete_clients_lock, delete_locks_owned_by, delete_server_lock,
-    read_clients_lock, read_server_lock, server_lock_exists, with_lock, write_clients_lock,
-    write_server_lock, ClientInfo, ClientsLock, ServerLock,
+    read_clients_lock, read_server_lock, server_lock_exists, update_clients_lock,
+    update_server_lock, with_lock, write_clients_lock, write_server_lock, ClientInfo, ClientsLock,
+    LockUpdate, ServerLock,
 };
-pub use state::{get_server_state, watcher_alive, ServerState};
+pub use probe::{HealthCheck, HealthProbe, HealthStatus};
+pub use restart::{RestartPolicy, ServerExit};
+pub use state::{get_server_state, state_from_locks, watcher_alive, ServerState};
@@ -0,0 +1,80 @@
+use super::health::is_process_alive;
+use super::lockfile::read_clients_lock;
+use super::log::{log_invocation, InvocationLog};
+use anyhow::{bail, Result};
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use std::str::FromStr;
+
+/// Parse a signal given as "SIGUSR1", "USR1", "usr1", or a number ("10").
+pub fn parse_signal(s: &str) -> Result<Signal> {
+    let s = s.trim();
+    if let Ok(number) = s.parse::<i32>() {
+        return match Signal::try_from(number) {
+            Ok(signal) => Ok(signal),
+            Err(_) => bail!("Invalid signal number: {}", number),
+        };
+    }
+    let upper = s.to_ascii_uppercase();
+    let name = if upper.starts_with("SIG") {
+        upper
+    } else {
+        format!("SIG{}", upper)
+    };
+    match Signal::from_str(&name) {
+        Ok(signal) => Ok(signal),
+        Err(_) => bail!("Invalid signal '{}': expected e.g. SIGUSR1, USR1, or 10", s),
+    }
+}
+
+/// Send `signal` to every live client of `name` ahead of a shutdown, so
+/// editors can detach or tell the user. Best-effort: an unreadable clients
+/// lock or a client that can't be signalled is skipped. Returns the PIDs that
+/// were signalled.
+pub fn notify_clients(name: &str, signal: &str, reason: &str) -> Vec<i32> {
+    let Ok(signal) = parse_signal(signal) else {
+        return Vec::new();
+    };
+    let Ok(clients) = read_clients_lock(name) else {
+        return Vec::new();
+    };
+
+    let mut notified: Vec<i32> = clients
+        .clients
+        .keys()
+        .copied()
+        .filter(|pid| is_process_alive(*pid) && kill(Pid::from_raw(*pid), signal).is_ok())
+        .collect();
+    notified.sort_unstable();
+
+    if !notified.is_empty() {
+        let _ = log_invocation(
+            name,
+            &InvocationLog::success(
+                "notify-clients",
+                &[name.to_string()],
+                Some(serde_json::json!({
+                    "signal": signal.as_str(),
+                    "clients": notified,
+                    "reason": reason,
+                })),
+            ),
+        );
+    }
+    notified
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_signal() {
+        assert_eq!(parse_signal("SIGUSR1").unwrap(), Signal::SIGUSR1);
+        assert_eq!(parse_signal("usr2").unwrap(), Signal::SIGUSR2);
+        assert_eq!(parse_signal("HUP").unwrap(), Signal::SIGHUP);
+        assert_eq!(parse_signal("15").unwrap(), Signal::SIGTERM);
+        assert!(parse_signal("SIGNOPE").is_err());
+        assert!(parse_signal("999").is_err());
+    }
+}
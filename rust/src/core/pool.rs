@@ -0,0 +1,100 @@
+//! Per-server work spread over a few threads.
+//!
+//! Checking a server mostly waits: on its locks, on a process to answer a
+//! signal, on a heartbeat. With dozens of servers that waiting adds up, so
+//! sweeps like `admin doctor` run servers side by side. Results still come
+//! back in the order the servers were given, so output doesn't depend on
+//! which thread finished first.
+
+use std::collections::BTreeMap;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+/// How many servers to work on at once when not told: one per CPU.
+pub fn default_jobs() -> usize {
+    thread::available_parallelism().map_or(1, NonZeroUsize::get)
+}
+
+/// Run `work` on each of `items`, up to `jobs` at a time, and hand each
+/// result to `done` in the order of `items` — as soon as it and every result
+/// before it are ready, so a long sweep still shows progress.
+pub fn for_each_ordered<T, R>(
+    items: &[T],
+    jobs: usize,
+    work: impl Fn(&T) -> R + Sync,
+    mut done: impl FnMut(R),
+) where
+    T: Sync,
+    R: Send,
+{
+    let jobs = jobs.clamp(1, items.len().max(1));
+    if jobs == 1 {
+        items.iter().map(&work).for_each(done);
+        return;
+    }
+
+    let next = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..jobs {
+            let sender = sender.clone();
+            let (next, work) = (&next, &work);
+            scope.spawn(move || loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(item) = items.get(index) else {
+                    break;
+                };
+                if sender.send((index, work(item))).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+
+        // Results that arrived ahead of one still being worked on.
+        let mut waiting = BTreeMap::new();
+        let mut wanted = 0;
+        for (index, result) in receiver {
+            waiting.insert(index, result);
+            while let Some(result) = waiting.remove(&wanted) {
+                done(result);
+                wanted += 1;
+            }
+        }
+    });
+}
+
+/// [`for_each_ordered`], collecting the results.
+pub fn map<T, R>(items: &[T], jobs: usize, work: impl Fn(&T) -> R + Sync) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+{
+    let mut results = Vec::with_capacity(items.len());
+    for_each_ordered(items, jobs, work, |result| results.push(result));
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_results_keep_item_order() {
+        // Later items finish first, so out-of-order arrivals must be held.
+        let items: Vec<u64> = (0..12).collect();
+        let results = map(&items, 4, |&item| {
+            thread::sleep(Duration::from_millis(5 * (12 - item)));
+            item * 2
+        });
+        assert_eq!(
+            results,
+            items.iter().map(|item| item * 2).collect::<Vec<_>>()
+        );
+        assert_eq!(map(&items, 0, |&item| item), items);
+        assert!(map(&[] as &[u64], 4, |&item| item).is_empty());
+    }
+}
@@ -0,0 +1,436 @@
+use super::duration::parse_duration;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// What a health probe checks. Process liveness only tells us the server
+/// exists; a probe tells us it is actually serving.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum HealthProbe {
+    /// Run a shell command (`bash -c`); exit status 0 means healthy.
+    Command { command: String },
+    /// `GET` a plain-`http://` URL. Healthy if the response status equals
+    /// `expected_status`, or is any 2xx when that is `None`.
+    Http {
+        url: String,
+        #[serde(default)]
+        expected_status: Option<u16>,
+    },
+    /// Connect to `host:port`; healthy if the connection is accepted.
+    Tcp { address: String },
+}
+
+/// A health check attached to a server: the probe plus its schedule.
+///
+/// Durations are kept as the user wrote them (like `grace_period`) and parsed
+/// when used, so the lockfile stays human-readable.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HealthCheck {
+    pub probe: HealthProbe,
+    /// How often the watcher runs the probe (e.g. "30s").
+    pub interval: String,
+    /// How long a single probe may take before it counts as a failure.
+    pub timeout: String,
+    /// Consecutive failures before the server is marked unhealthy.
+    pub retries: u32,
+    /// Restart the server once it is marked unhealthy.
+    #[serde(default)]
+    pub restart: bool,
+}
+
+impl HealthCheck {
+    pub fn interval(&self) -> Result<Duration> {
+        parse_duration(&self.interval)
+            .with_context(|| format!("Invalid health interval: {}", self.interval))
+    }
+
+    pub fn timeout(&self) -> Result<Duration> {
+        parse_duration(&self.timeout)
+            .with_context(|| format!("Invalid health timeout: {}", self.timeout))
+    }
+
+    /// Validate the durations up front so a bad value is reported at start
+    /// rather than silently disabling the probe inside the watcher.
+    pub fn validate(&self) -> Result<()> {
+        self.interval()?;
+        self.timeout()?;
+        match &self.probe {
+            HealthProbe::Http { url, .. } => {
+                HttpTarget::parse(url)?;
+            }
+            HealthProbe::Tcp { address } => {
+                split_host_port(address)?;
+            }
+            HealthProbe::Command { .. } => {}
+        }
+        Ok(())
+    }
+}
+
+/// The most recent probe outcome, recorded in the server lock by the watcher.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HealthStatus {
+    /// `false` once `retries` consecutive probes have failed.
+    pub healthy: bool,
+    pub consecutive_failures: u32,
+    pub last_checked: chrono::DateTime<chrono::Utc>,
+    /// Why the most recent probe failed, if it did.
+    pub last_error: Option<String>,
+}
+
+impl HealthStatus {
+    /// Fold one probe `result` into the previous status (if any).
+    pub fn next(previous: Option<&HealthStatus>, result: Result<(), String>, retries: u32) -> Self {
+        let (consecutive_failures, last_error) = match result {
+            Ok(()) => (0, None),
+            Err(e) => (
+                previous.map(|p| p.consecutive_failures).unwrap_or(0) + 1,
+                Some(e),
+            ),
+        };
+        Self {
+            healthy: consecutive_failures < retries.max(1),
+            consecutive_failures,
+            last_checked: chrono::Utc::now(),
+            last_error,
+        }
+    }
+}
+
+impl HealthProbe {
+    /// Run the probe once, giving up after `timeout`. `Err` carries a short,
+    /// human-readable reason for the failure.
+    pub fn run(&self, timeout: Duration) -> std::result::Result<(), String> {
+        match self {
+            HealthProbe::Command { command } => run_command_probe(command, timeout),
+            HealthProbe::Http {
+                url,
+                expected_status,
+            } => run_http_probe(url, *expected_status, timeout),
+            HealthProbe::Tcp { address } => run_tcp_probe(address, timeout),
+        }
+    }
+
+    /// One-line description for `info`.
+    pub fn describe(&self) -> String {
+        match self {
+            HealthProbe::Command { command } => format!("cmd: {}", command),
+            HealthProbe::Http {
+                url,
+                expected_status: Some(status),
+            } => format!("http: {} expecting {}", url, status),
+            HealthProbe::Http { url, .. } => format!("http: {}", url),
+            HealthProbe::Tcp { address } => format!("tcp: {}", address),
+        }
+    }
+
+    /// Where the server listens, as `tcp:HOST:PORT`, if the probe connects to
+    /// it (TCP and HTTP probes). See [`super::address`].
+    pub fn address(&self) -> Option<String> {
+        match self {
+            HealthProbe::Command { .. } => None,
+            HealthProbe::Http { url, .. } => {
+                let target = HttpTarget::parse(url).ok()?;
+                Some(format!("tcp:{}:{}", target.host, target.port))
+            }
+            HealthProbe::Tcp { address } => {
+                split_host_port(address).ok()?;
+                Some(format!("tcp:{}", address))
+            }
+        }
+    }
+}
+
+fn run_command_probe(command: &str, timeout: Duration) -> std::result::Result<(), String> {
+    let mut child = Command::new("/bin/bash")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("failed to run health command: {}", e))?;
+
+    let start = Instant::now();
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) if status.success() => return Ok(()),
+            Ok(Some(status)) => return Err(format!("health command failed ({})", status)),
+            Ok(None) => {}
+            Err(e) => return Err(format!("failed to wait for health command: {}", e)),
+        }
+        if start.elapsed() >= timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(format!("health command timed out after {:?}", timeout));
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+/// The parts of a `http://host[:port][/path]` URL a probe needs.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct HttpTarget {
+    pub(crate) host: String,
+    pub(crate) port: u16,
+    pub(crate) path: String,
+}
+
+impl HttpTarget {
+    /// Parse a plain-HTTP URL. TLS isn't supported: health endpoints of local
+    /// shared servers are loopback HTTP in practice, and this keeps the probe
+    /// dependency-free.
+    pub(crate) fn parse(url: &str) -> Result<Self> {
+        let Some(rest) = url.strip_prefix("http://") else {
+            bail!("URL must start with http:// (got '{}')", url);
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        // `[::1]:8080` style IPv6 literals keep their brackets for the Host
+        // header but not for the socket address.
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => (
+                host,
+                port.parse()
+                    .with_context(|| format!("Invalid port in URL '{}'", url))?,
+            ),
+            _ => (authority, 80),
+        };
+        if host.is_empty() {
+            bail!("URL has no host: '{}'", url);
+        }
+        Ok(Self {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+}
+
+fn run_http_probe(
+    url: &str,
+    expected_status: Option<u16>,
+    timeout: Duration,
+) -> std::result::Result<(), String> {
+    let target = HttpTarget::parse(url).map_err(|e| e.to_string())?;
+    let host = target.host.trim_start_matches('[').trim_end_matches(']');
+    let addr = (host, target.port)
+        .to_socket_addrs()
+        .map_err(|e| format!("cannot resolve {}: {}", target.host, e))?
+        .next()
+        .ok_or_else(|| format!("cannot resolve {}", target.host))?;
+
+    let mut stream = TcpStream::connect_timeout(&addr, timeout)
+        .map_err(|e| format!("connect to {} failed: {}", addr, e))?;
+    let _ = stream.set_read_timeout(Some(timeout));
+    let _ = stream.set_write_timeout(Some(timeout));
+
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}:{}\r\nUser-Agent: sharedserver\r\nConnection: close\r\n\r\n",
+        target.path, target.host, target.port
+    );
+    stream
+        .write_all(request.as_bytes())
+        .map_err(|e| format!("request to {} failed: {}", url, e))?;
+
+    // Only the status line matters; read until we have it.
+    let mut response = Vec::new();
+    let mut buf = [0u8; 512];
+    while !response.contains(&b'\n') {
+        match stream.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => response.extend_from_slice(&buf[..n]),
+            Err(e) => return Err(format!("reading response from {} failed: {}", url, e)),
+        }
+    }
+
+    let status = parse_status_line(&response)
+        .ok_or_else(|| format!("malformed HTTP response from {}", url))?;
+    let healthy = match expected_status {
+        Some(expected) => status == expected,
+        None => (200..300).contains(&status),
+    };
+    if healthy {
+        Ok(())
+    } else {
+        Err(format!("{} returned HTTP {}", url, status))
+    }
+}
+
+/// Split `host:port` (IPv6 as `[::1]:port`) for a TCP probe.
+fn split_host_port(address: &str) -> Result<(&str, u16)> {
+    let Some((host, port)) = address.rsplit_once(':') else {
+        bail!("TCP health address must be host:port (got '{}')", address);
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if host.is_empty() {
+        bail!("TCP health address has no host: '{}'", address);
+    }
+    let port = port
+        .parse()
+        .with_context(|| format!("Invalid port in TCP health address '{}'", address))?;
+    Ok((host, port))
+}
+
+/// Healthy if something accepts a TCP connection at `address` within
+/// `timeout`. Every resolved address is tried, so `localhost` works whether the
+/// server bound IPv4 or IPv6.
+fn run_tcp_probe(address: &str, timeout: Duration) -> std::result::Result<(), String> {
+    let (host, port) = split_host_port(address).map_err(|e| e.to_string())?;
+    let addrs: Vec<_> = (host, port)
+        .to_socket_addrs()
+        .map_err(|e| format!("cannot resolve {}: {}", address, e))?
+        .collect();
+
+    let mut last_error = format!("cannot resolve {}", address);
+    for addr in addrs {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(_) => return Ok(()),
+            Err(e) => last_error = format!("connect to {} failed: {}", addr, e),
+        }
+    }
+    Err(last_error)
+}
+
+/// Extract the status code from `HTTP/1.x <code> <reason>`.
+fn parse_status_line(response: &[u8]) -> Option<u16> {
+    let line = response.split(|&b| b == b'\n').next()?;
+    let line = std::str::from_utf8(line).ok()?;
+    let mut parts = line.split_whitespace();
+    if !parts.next()?.starts_with("HTTP/") {
+        return None;
+    }
+    parts.next()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_probe() {
+        let ok = HealthProbe::Command {
+            command: "true".to_string(),
+        };
+        let fail = HealthProbe::Command {
+            command: "exit 3".to_string(),
+        };
+        let slow = HealthProbe::Command {
+            command: "sleep 5".to_string(),
+        };
+        assert!(ok.run(Duration::from_secs(5)).is_ok());
+        assert!(fail.run(Duration::from_secs(5)).is_err());
+        assert!(slow
+            .run(Duration::from_millis(200))
+            .unwrap_err()
+            .contains("timed out"));
+    }
+
+    #[test]
+    fn test_parse_http_target() {
+        assert_eq!(
+            HttpTarget::parse("http://127.0.0.1:8080/healthz").unwrap(),
+            HttpTarget {
+                host: "127.0.0.1".to_string(),
+                port: 8080,
+                path: "/healthz".to_string(),
+            }
+        );
+        let bare = HttpTarget::parse("http://localhost").unwrap();
+        assert_eq!((bare.port, bare.path.as_str()), (80, "/"));
+        let v6 = HttpTarget::parse("http://[::1]:9000/").unwrap();
+        assert_eq!((v6.host.as_str(), v6.port), ("[::1]", 9000));
+
+        assert!(HttpTarget::parse("https://example.com").is_err());
+        assert!(HttpTarget::parse("http://:80/").is_err());
+        assert!(HttpTarget::parse("http://host:notaport/").is_err());
+    }
+
+    #[test]
+    fn test_probe_address() {
+        let http = HealthProbe::Http {
+            url: "http://localhost/healthz".to_string(),
+            expected_status: None,
+        };
+        assert_eq!(http.address().as_deref(), Some("tcp:localhost:80"));
+        let tcp = HealthProbe::Tcp {
+            address: "[::1]:8432".to_string(),
+        };
+        assert_eq!(tcp.address().as_deref(), Some("tcp:[::1]:8432"));
+        let cmd = HealthProbe::Command {
+            command: "true".to_string(),
+        };
+        assert_eq!(cmd.address(), None);
+    }
+
+    #[test]
+    fn test_parse_status_line() {
+        assert_eq!(parse_status_line(b"HTTP/1.1 204 No Content\r\n"), Some(204));
+        assert_eq!(parse_status_line(b"HTTP/1.0 503\r\n\r\n"), Some(503));
+        assert_eq!(parse_status_line(b"garbage"), None);
+    }
+
+    #[test]
+    fn test_http_probe_against_local_listener() {
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            for status in ["200 OK", "500 Internal Server Error"] {
+                let (mut conn, _) = listener.accept().unwrap();
+                let mut buf = [0u8; 1024];
+                let _ = conn.read(&mut buf);
+                let _ = write!(conn, "HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status);
+            }
+        });
+
+        let url = format!("http://127.0.0.1:{}/healthz", port);
+        let timeout = Duration::from_secs(5);
+        assert!(run_http_probe(&url, None, timeout).is_ok());
+        assert!(run_http_probe(&url, None, timeout)
+            .unwrap_err()
+            .contains("500"));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_tcp_probe() {
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let timeout = Duration::from_secs(2);
+        assert!(run_tcp_probe(&address, timeout).is_ok());
+
+        // Nothing listens once the listener is gone.
+        drop(listener);
+        assert!(run_tcp_probe(&address, timeout).is_err());
+
+        assert!(split_host_port("localhost").is_err());
+        assert!(split_host_port(":80").is_err());
+        assert_eq!(split_host_port("[::1]:5432").unwrap(), ("::1", 5432));
+    }
+
+    #[test]
+    fn test_status_turns_unhealthy_after_retries() {
+        let first = HealthStatus::next(None, Err("down".into()), 2);
+        assert!(first.healthy);
+        assert_eq!(first.consecutive_failures, 1);
+
+        let second = HealthStatus::next(Some(&first), Err("down".into()), 2);
+        assert!(!second.healthy);
+        assert_eq!(second.last_error.as_deref(), Some("down"));
+
+        let recovered = HealthStatus::next(Some(&second), Ok(()), 2);
+        assert!(recovered.healthy);
+        assert_eq!(recovered.consecutive_failures, 0);
+    }
+}
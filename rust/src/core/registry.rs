@@ -0,0 +1,123 @@
+//! `registry.json`: one index of every server in the lock directory, so `list`
+//! can show them all with a single read instead of a readdir plus several
+//! locked reads per server.
+//!
+//! The per-server lockfiles remain the source of truth. Each entry is a
+//! snapshot of a server's two locks, refreshed (under the registry's own lock)
+//! whenever they are written and dropped when the server lock is deleted.
+//! Updates are best-effort: a failed refresh leaves a stale entry, never a
+//! failed command, and `admin doctor` rebuilds the index from the lockfiles.
+
+use super::lockfile::{
+    ensure_lockfile_dir, lockfile_dir, read_clients_lock, read_json, read_server_lock,
+    server_lock_exists, with_lock, with_shared_lock, write_json, ClientsLock, ServerLock,
+};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// The whole index, keyed by server name.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Registry {
+    pub servers: BTreeMap<String, RegistryEntry>,
+}
+
+/// A snapshot of one server's locks.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegistryEntry {
+    pub server: ServerLock,
+    /// `None` if the clients lock couldn't be read when the entry was taken.
+    pub clients: Option<ClientsLock>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Get path to the registry
+pub fn registry_path() -> Result<PathBuf> {
+    Ok(ensure_lockfile_dir()?.join("registry.json"))
+}
+
+/// Read the registry with a shared lock. Fails if it doesn't exist (e.g. no
+/// server has been started since it was introduced) or is corrupt; callers
+/// fall back to scanning the lock directory.
+pub fn read_registry() -> Result<Registry> {
+    let path = lockfile_dir()?.join("registry.json");
+    with_shared_lock(&path, read_json)
+}
+
+/// Read the registry without taking its lock, for callers that must never
+/// wait (a shell prompt). A read that races a write fails to decode rather
+/// than returning a torn registry; callers then fall back to
+/// [`read_registry`].
+pub fn peek_registry() -> Result<Registry> {
+    let path = lockfile_dir()?.join("registry.json");
+    read_json(&mut std::fs::File::open(path)?)
+}
+
+/// Re-snapshot `name`'s locks into its registry entry, or drop the entry if
+/// the server lock is gone.
+pub fn refresh(name: &str) {
+    let entry = read_server_lock(name).ok().map(|server| RegistryEntry {
+        server,
+        clients: read_clients_lock(name).ok(),
+        updated_at: Utc::now(),
+    });
+    let _ = update(|registry| match entry {
+        Some(entry) => {
+            registry.servers.insert(name.to_string(), entry);
+        }
+        None => {
+            registry.servers.remove(name);
+        }
+    });
+}
+
+/// Drop `name`'s registry entry.
+pub fn remove(name: &str) {
+    let _ = update(|registry| {
+        registry.servers.remove(name);
+    });
+}
+
+/// Rebuild the registry from the lockfiles of `names`, discarding every other
+/// entry. Used by `admin doctor` after its sweep.
+pub fn rebuild<'a>(names: impl IntoIterator<Item = &'a str>) -> Result<usize> {
+    let mut servers = BTreeMap::new();
+    for name in names {
+        if let Ok(server) = read_server_lock(name) {
+            let entry = RegistryEntry {
+                server,
+                clients: read_clients_lock(name).ok(),
+                updated_at: Utc::now(),
+            };
+            servers.insert(name.to_string(), entry);
+        }
+    }
+    let count = servers.len();
+    update(|registry| registry.servers = servers)?;
+    Ok(count)
+}
+
+/// Read-modify-write the registry under its exclusive lock. The per-server
+/// locks are never held at the same time, so this can't deadlock with them.
+fn update(modify: impl FnOnce(&mut Registry)) -> Result<()> {
+    let path = registry_path()?;
+    with_lock(&path, |file| {
+        // A missing or corrupt registry starts over; refreshes repopulate it.
+        let mut registry: Registry = read_json(file).unwrap_or_default();
+        modify(&mut registry);
+        write_json(file, &registry)
+    })
+}
+
+impl Registry {
+    /// Entries whose server lock still exists. An entry can briefly outlive
+    /// its lockfile if something removed the file without going through
+    /// [`delete_server_lock`](super::lockfile::delete_server_lock).
+    pub fn live_entries(&self) -> impl Iterator<Item = (&String, &RegistryEntry)> {
+        self.servers
+            .iter()
+            .filter(|(name, _)| server_lock_exists(name))
+    }
+}
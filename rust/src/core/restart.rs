@@ -0,0 +1,119 @@
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// How the server process ended, as observed by the watcher's `waitpid`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ServerExit {
+    /// Exited normally with this status code.
+    Exited { code: i32 },
+    /// Terminated by this signal number.
+    Signaled { signal: i32 },
+    /// Gone, but its status couldn't be collected (e.g. it was already reaped
+    /// or was never our child).
+    Unknown,
+}
+
+impl ServerExit {
+    /// A clean `exit(0)`. Signals and unknown exits count as failures.
+    pub fn is_success(&self) -> bool {
+        matches!(self, ServerExit::Exited { code: 0 })
+    }
+}
+
+impl fmt::Display for ServerExit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServerExit::Exited { code } => write!(f, "exit code {}", code),
+            ServerExit::Signaled { signal } => write!(f, "signal {}", signal),
+            ServerExit::Unknown => write!(f, "unknown status"),
+        }
+    }
+}
+
+/// What the watcher does when the server exits on its own while clients are
+/// still attached. Deliberate shutdowns (`stop`, grace expiry, `kill`) never
+/// trigger a restart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    /// Clean up and exit (the historical behaviour).
+    #[default]
+    Never,
+    /// Restart only if the server exited non-zero or was killed by a signal.
+    OnFailure,
+    /// Restart whenever the server exits.
+    Always,
+}
+
+impl RestartPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RestartPolicy::Never => "never",
+            RestartPolicy::OnFailure => "on-failure",
+            RestartPolicy::Always => "always",
+        }
+    }
+
+    /// Whether a server that ended with `exit` should be relaunched.
+    pub fn should_restart(&self, exit: &ServerExit) -> bool {
+        match self {
+            RestartPolicy::Never => false,
+            RestartPolicy::OnFailure => !exit.is_success(),
+            RestartPolicy::Always => true,
+        }
+    }
+}
+
+impl FromStr for RestartPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "never" | "no" => Ok(RestartPolicy::Never),
+            "on-failure" => Ok(RestartPolicy::OnFailure),
+            "always" => Ok(RestartPolicy::Always),
+            other => bail!(
+                "Invalid restart policy '{}': expected never, on-failure, or always",
+                other
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_restart_policy() {
+        assert_eq!(
+            "never".parse::<RestartPolicy>().unwrap(),
+            RestartPolicy::Never
+        );
+        assert_eq!(
+            "on-failure".parse::<RestartPolicy>().unwrap(),
+            RestartPolicy::OnFailure
+        );
+        assert_eq!(
+            "always".parse::<RestartPolicy>().unwrap(),
+            RestartPolicy::Always
+        );
+        assert!("sometimes".parse::<RestartPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_should_restart() {
+        let ok = ServerExit::Exited { code: 0 };
+        let failed = ServerExit::Exited { code: 1 };
+        let killed = ServerExit::Signaled { signal: 9 };
+
+        assert!(!RestartPolicy::Never.should_restart(&failed));
+        assert!(!RestartPolicy::OnFailure.should_restart(&ok));
+        assert!(RestartPolicy::OnFailure.should_restart(&failed));
+        assert!(RestartPolicy::OnFailure.should_restart(&killed));
+        assert!(RestartPolicy::Always.should_restart(&ok));
+    }
+}
@@ -0,0 +1,135 @@
+//! Minimal `sd_notify(3)` support, so a watcher launched from a systemd unit
+//! (`Type=notify`, `NotifyAccess=all`) can be supervised by systemd: it
+//! reports `READY=1` (with its own PID as `MAINPID`) once the server is
+//! launched, `WATCHDOG=1` as it loops, and `STOPPING=1` when it tears down.
+//!
+//! Everything is a no-op when `NOTIFY_SOCKET` is unset, i.e. outside systemd.
+//!
+//! [`listen_fds`] is the other half of `sd_listen_fds(3)`: the sockets systemd
+//! passes a socket-activated daemon.
+
+use std::os::fd::RawFd;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::UnixDatagram;
+use std::time::Duration;
+
+/// Environment variables systemd uses to talk to the unit's main process.
+/// The watcher consumes them; the server must not inherit them, or its own
+/// notifications would be mistaken for the watcher's.
+pub const NOTIFY_ENV_VARS: [&str; 3] = ["NOTIFY_SOCKET", "WATCHDOG_USEC", "WATCHDOG_PID"];
+
+/// Send `state` (newline-separated `KEY=VALUE` assignments) to systemd.
+/// Returns `false` if not running under systemd or the send failed.
+pub fn notify(state: &str) -> bool {
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return false;
+    };
+    let Ok(socket) = UnixDatagram::unbound() else {
+        return false;
+    };
+    let path = path.as_encoded_bytes();
+
+    // A leading '@' names a socket in Linux's abstract namespace.
+    #[cfg(target_os = "linux")]
+    if let Some(abstract_name) = path.strip_prefix(b"@") {
+        use std::os::linux::net::SocketAddrExt;
+        return std::os::unix::net::SocketAddr::from_abstract_name(abstract_name)
+            .and_then(|addr| socket.send_to_addr(state.as_bytes(), &addr))
+            .is_ok();
+    }
+
+    let path = std::path::Path::new(std::ffi::OsStr::from_bytes(path));
+    socket.send_to(state.as_bytes(), path).is_ok()
+}
+
+/// Report that the server is launched and the calling process (the watcher)
+/// is the one systemd should track from now on.
+pub fn notify_ready() -> bool {
+    notify(&format!("READY=1\nMAINPID={}", std::process::id()))
+}
+
+/// Pet the systemd watchdog.
+pub fn notify_watchdog() -> bool {
+    notify("WATCHDOG=1")
+}
+
+/// Report that shutdown has begun.
+pub fn notify_stopping() -> bool {
+    notify("STOPPING=1")
+}
+
+/// The unit's `WatchdogSec=`, if systemd enabled the watchdog.
+///
+/// `WATCHDOG_PID` is deliberately not checked: systemd sets it to the process
+/// it exec'd (the CLI), and the watcher takes over as main PID via `MAINPID`.
+pub fn watchdog_interval() -> Option<Duration> {
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec))
+}
+
+/// The first file descriptor systemd passes (`SD_LISTEN_FDS_START`).
+const LISTEN_FDS_START: RawFd = 3;
+
+/// The sockets systemd passed this process by socket activation, in the order
+/// of the socket unit's `Listen*=` lines. Empty unless `LISTEN_PID` names this
+/// process.
+///
+/// Like `sd_listen_fds(1)`, it unsets `LISTEN_PID`, `LISTEN_FDS`, and
+/// `LISTEN_FDNAMES`, and marks the descriptors close-on-exec, so the servers
+/// the caller launches inherit neither.
+pub fn listen_fds() -> Vec<RawFd> {
+    let pid = std::env::var("LISTEN_PID")
+        .ok()
+        .and_then(|v| v.parse::<u32>().ok());
+    let count = std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|v| v.parse::<RawFd>().ok());
+    for var in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        std::env::remove_var(var);
+    }
+    let (Some(pid), Some(count)) = (pid, count) else {
+        return Vec::new();
+    };
+    if pid != std::process::id() {
+        return Vec::new();
+    }
+    (LISTEN_FDS_START..LISTEN_FDS_START + count.max(0))
+        // SAFETY: only sets a descriptor flag; one that isn't open fails, and
+        // is left out rather than handed to the caller to own.
+        .filter(|&fd| unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } == 0)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notify_sends_datagram() {
+        let dir = std::env::temp_dir().join(format!("sd-notify-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("notify.sock");
+        let _ = std::fs::remove_file(&path);
+        let receiver = UnixDatagram::bind(&path).unwrap();
+
+        std::env::set_var("NOTIFY_SOCKET", &path);
+        assert!(notify_watchdog());
+        std::env::remove_var("NOTIFY_SOCKET");
+        assert!(!notify_watchdog(), "no socket: no-op");
+
+        let mut buf = [0u8; 64];
+        let n = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"WATCHDOG=1");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_listen_fds_ignores_other_processes() {
+        std::env::set_var("LISTEN_PID", "1");
+        std::env::set_var("LISTEN_FDS", "1");
+        assert!(listen_fds().is_empty());
+        assert!(std::env::var("LISTEN_FDS").is_err());
+        assert!(listen_fds().is_empty());
+    }
+}
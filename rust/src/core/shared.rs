@@ -0,0 +1,178 @@
+//! Machine-wide servers shared by the members of a group (`--shared
+//! --allow-group <grp>`).
+//!
+//! Normally every user has a private lock directory. In shared mode the locks
+//! live in `<shared root>/<group>/` instead: a directory owned by the group,
+//! mode 2770 (setgid, so everything created in it stays in the group), holding
+//! files mode 0660. Any member can then attach to, detach from, and inspect the
+//! group's servers; nobody else can even list them.
+//!
+//! The mode is process-wide and carried in `SHAREDSERVER_SHARED_GROUP`, so the
+//! watcher (a fork of the CLI) resolves the same lock directory.
+
+use anyhow::{bail, Context, Result};
+use std::ffi::{CStr, CString};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+/// Environment variable naming the group whose shared lock directory is in use.
+pub const SHARED_GROUP_ENV: &str = "SHAREDSERVER_SHARED_GROUP";
+
+/// Where the per-group directories live, unless overridden with
+/// `SHAREDSERVER_SHARED_DIR`.
+pub const DEFAULT_SHARED_ROOT: &str = "/tmp/sharedserver-shared";
+
+/// Mode of a group's shared lock directory: setgid, group-writable.
+pub const SHARED_DIR_MODE: u32 = 0o2770;
+
+/// Mode of the files in a group's shared lock directory.
+pub const SHARED_FILE_MODE: u32 = 0o660;
+
+/// The group whose servers this process operates on, if in shared mode.
+pub fn shared_group() -> Option<String> {
+    std::env::var(SHARED_GROUP_ENV)
+        .ok()
+        .filter(|group| !group.is_empty())
+}
+
+/// Switch this process (and everything it forks) to `group`'s shared servers.
+pub fn enter_shared_mode(group: &str) -> Result<()> {
+    if !is_member(group_id(group)?) {
+        bail!("Not a member of group '{}'", group);
+    }
+    std::env::set_var(SHARED_GROUP_ENV, group);
+    Ok(())
+}
+
+/// `<shared root>/<group>`.
+pub fn shared_lockdir(group: &str) -> PathBuf {
+    let root = std::env::var("SHAREDSERVER_SHARED_DIR")
+        .unwrap_or_else(|_| DEFAULT_SHARED_ROOT.to_string());
+    PathBuf::from(root).join(group)
+}
+
+/// Look up a group's GID by name.
+pub fn group_id(group: &str) -> Result<u32> {
+    let c_group = CString::new(group).context("Invalid group name")?;
+    // SAFETY: getgrnam returns NULL or a pointer to static storage, which is
+    // read before any other call could overwrite it.
+    let entry = unsafe { libc::getgrnam(c_group.as_ptr()) };
+    if entry.is_null() {
+        bail!("No such group: '{}'", group);
+    }
+    Ok(unsafe { (*entry).gr_gid })
+}
+
+/// Look up a group's name by GID.
+pub fn group_name(gid: u32) -> Option<String> {
+    // SAFETY: as in `group_id`.
+    let entry = unsafe { libc::getgrgid(gid) };
+    if entry.is_null() {
+        return None;
+    }
+    let name = unsafe { CStr::from_ptr((*entry).gr_name) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+/// Whether the current process belongs to `gid` (as its effective group or a
+/// supplementary one).
+pub fn is_member(gid: u32) -> bool {
+    // SAFETY: getegid cannot fail; getgroups is given a buffer of the size it
+    // reported.
+    if unsafe { libc::getegid() } == gid {
+        return true;
+    }
+    let count = unsafe { libc::getgroups(0, std::ptr::null_mut()) };
+    if count <= 0 {
+        return false;
+    }
+    let mut groups = vec![0; count as usize];
+    let count = unsafe { libc::getgroups(count, groups.as_mut_ptr()) };
+    groups.truncate(count.max(0) as usize);
+    groups.contains(&gid)
+}
+
+/// Create `group`'s shared lock directory (and the world-writable, sticky
+/// root above it) if missing. Only the creator can set the modes, so existing
+/// directories are left as they are and vetted by [`check_shared_dir`].
+pub fn create_shared_dir(dir: &Path, group: &str) -> Result<()> {
+    let gid = group_id(group)?;
+    if let Some(root) = dir.parent() {
+        if std::fs::DirBuilder::new().mode(0o1777).create(root).is_ok() {
+            // The umask has stripped the write bits; restore them, like /tmp.
+            let _ = std::fs::set_permissions(root, std::fs::Permissions::from_mode(0o1777));
+        }
+    }
+    match std::fs::DirBuilder::new().mode(SHARED_DIR_MODE).create(dir) {
+        Ok(()) => {
+            std::os::unix::fs::chown(dir, None, Some(gid))
+                .with_context(|| format!("Failed to hand {:?} to group '{}'", dir, group))?;
+            std::fs::set_permissions(dir, std::fs::Permissions::from_mode(SHARED_DIR_MODE))?;
+            super::fsutil::sync_parent(dir)?;
+            Ok(())
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => Ok(()),
+        Err(e) => {
+            Err(e).with_context(|| format!("Failed to create shared lock directory: {:?}", dir))
+        }
+    }
+}
+
+/// Refuse a shared lock directory that isn't `group`'s, is open to users
+/// outside it, or that the current user isn't a member of.
+pub fn check_shared_dir(dir: &Path, group: &str) -> Result<()> {
+    let Ok(metadata) = std::fs::metadata(dir) else {
+        return Ok(());
+    };
+    let gid = group_id(group)?;
+    if metadata.gid() != gid {
+        bail!(
+            "Shared lock directory {:?} belongs to group {}, not '{}'; refusing to use it",
+            dir,
+            group_name(metadata.gid()).unwrap_or_else(|| metadata.gid().to_string()),
+            group
+        );
+    }
+    if metadata.mode() & 0o007 != 0 {
+        bail!(
+            "Shared lock directory {:?} is accessible to users outside group '{}'; \
+             refusing to use it",
+            dir,
+            group
+        );
+    }
+    if !is_member(gid) {
+        bail!("Not a member of group '{}'", group);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shared_dir_is_group_private() {
+        // SAFETY: getegid cannot fail.
+        let gid = unsafe { libc::getegid() };
+        let Some(group) = group_name(gid) else {
+            return;
+        };
+        assert_eq!(group_id(&group).unwrap(), gid);
+        assert!(is_member(gid));
+
+        let root = std::env::temp_dir().join(format!("shared-root-{}", std::process::id()));
+        let dir = root.join(&group);
+        create_shared_dir(&dir, &group).unwrap();
+        let metadata = std::fs::metadata(&dir).unwrap();
+        assert_eq!(metadata.mode() & 0o7777, SHARED_DIR_MODE);
+        assert_eq!(metadata.gid(), gid);
+        assert!(check_shared_dir(&dir, &group).is_ok());
+
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o2777)).unwrap();
+        let err = check_shared_dir(&dir, &group).unwrap_err();
+        assert!(err.to_string().contains("outside group"), "{}", err);
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
@@ -1,17 +1,23 @@
 use super::health::{process_liveness_checked, Liveness};
 use super::lockfile::{read_clients_lock, read_server_lock, server_lock_exists, ServerLock};
-use anyhow::Result;
+use anyhow::{bail, Result};
+use serde::Serialize;
+use std::str::FromStr;
 
 /// Whether the lock's watcher process is alive, guarded against PID reuse via
 /// its recorded start stamp. `false` if there is no recorded watcher.
 pub fn watcher_alive(lock: &ServerLock) -> bool {
+    if lock.from_previous_boot() {
+        return false;
+    }
     match lock.watcher_pid {
         Some(wp) => process_liveness_checked(wp, lock.watcher_start_time) == Liveness::Alive,
         None => false,
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum ServerState {
     Stopped,
     Active,
@@ -42,6 +48,23 @@ impl ServerState {
     }
 }
 
+impl FromStr for ServerState {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "stopped" => Ok(ServerState::Stopped),
+            "active" => Ok(ServerState::Active),
+            "grace" => Ok(ServerState::Grace),
+            "defunct" => Ok(ServerState::Defunct),
+            other => bail!(
+                "Invalid server state '{}': expected active, grace, stopped, or defunct",
+                other
+            ),
+        }
+    }
+}
+
 /// Get current server state
 pub fn get_server_state(name: &str) -> Result<ServerState> {
     if !server_lock_exists(name) {
@@ -54,27 +77,50 @@ pub fn get_server_state(name: &str) -> Result<ServerState> {
     // caller — doctor/start can then clean up any leftover file.
     let server_lock = match read_server_lock(name) {
         Ok(lock) => lock,
-        Err(_) => return Ok(ServerState::Stopped),
+        Err(e) => {
+            log::debug!(
+                "'{}': unreadable server lock, treating as stopped: {:#}",
+                name,
+                e
+            );
+            return Ok(ServerState::Stopped);
+        }
     };
 
+    let state = state_from_locks(&server_lock, || {
+        read_clients_lock(name).map(|c| c.refcount()).unwrap_or(0)
+    });
+    log::trace!(
+        "'{}': {} (server PID {})",
+        name,
+        state.as_str(),
+        server_lock.pid
+    );
+    Ok(state)
+}
+
+/// Derive a server's state from its already-read server lock; `refcount` is
+/// only consulted if the server is alive. Shared by [`get_server_state`] and
+/// `list`'s registry snapshots.
+pub fn state_from_locks(server_lock: &ServerLock, refcount: impl FnOnce() -> u32) -> ServerState {
     // Identity-checked so a recycled PID (some unrelated process now owning the
-    // old server's PID) reads as Gone rather than masquerading as the server.
-    match process_liveness_checked(server_lock.pid, server_lock.start_time) {
+    // old server's PID), or any PID recorded before a reboot, reads as Gone
+    // rather than masquerading as the server.
+    match server_lock.server_liveness() {
         // Server is dead but lockfile exists - stale lock
-        Liveness::Gone => Ok(ServerState::Stopped),
+        Liveness::Gone => ServerState::Stopped,
         // Server died but hasn't been reaped yet - lockfile cleanup pending
-        Liveness::Zombie => Ok(ServerState::Defunct),
+        Liveness::Zombie => ServerState::Defunct,
         Liveness::Alive => {
             // Active iff at least one client holds a reference. The clients
             // lockfile is kept for the whole life of the server (it is no longer
             // deleted when the refcount hits zero), so Grace is signalled by
             // refcount == 0, not by the file's absence. A missing/unreadable
             // clients lock is treated as zero references (Grace).
-            let refcount = read_clients_lock(name).map(|c| c.refcount).unwrap_or(0);
-            if refcount > 0 {
-                Ok(ServerState::Active)
+            if refcount() > 0 {
+                ServerState::Active
             } else {
-                Ok(ServerState::Grace)
+                ServerState::Grace
             }
         }
     }
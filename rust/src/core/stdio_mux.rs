@@ -0,0 +1,680 @@
+//! Sharing a stdio server between clients (`sharedserver proxy`).
+//!
+//! Servers that speak newline-delimited JSON-RPC over stdin/stdout (MCP
+//! servers, language servers run in that mode) can't normally be shared: each
+//! client spawns its own. Here the server's stdin and stdout are pipes, and a
+//! small mux process bridges them to a unix socket in the server's directory.
+//! Each `proxy` connects to the socket and relays its own stdin/stdout, so N
+//! clients talk to one server process.
+//!
+//! The mux rewrites request IDs so clients can't collide, and routes each
+//! response back to the client that asked. Notifications from the server go
+//! to every client. Requests from the server go to the client that was most
+//! recently active. The server is initialized once: later clients'
+//! `initialize` requests are answered from the first response, and their
+//! `notifications/initialized` are dropped.
+//!
+//! Like the `--log-timestamps` relay (see `log_capture`), the mux is a process
+//! of its own: the watcher blocks and must stay single-threaded. The watcher
+//! keeps the server's ends of the pipes and hands them to every server it
+//! launches, so one mux covers restarts; it is sent SIGHUP after one, and
+//! drops its clients, whose sessions the new server knows nothing about. The
+//! mux exits once the server's stdout has no writers left.
+
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// The socket in the server's directory that `proxy` connects to.
+pub const SOCKET_FILE: &str = "stdio.sock";
+
+/// Identifies a connected client within the mux.
+pub type ClientId = u64;
+
+/// Where a message goes.
+#[derive(Debug, PartialEq)]
+pub enum Route {
+    Server(Vec<u8>),
+    Client(ClientId, Vec<u8>),
+    AllClients(Vec<u8>),
+}
+
+/// How far the shared server's `initialize` handshake has got.
+#[derive(Debug)]
+enum Handshake {
+    NotStarted,
+    /// Sent to the server under this ID; these clients' requests wait on it.
+    Pending(u64, Vec<(ClientId, Value)>),
+    /// The server's answer, replayed to every later client.
+    Done(Value),
+}
+
+/// Routes JSON-RPC messages between clients and the one server.
+#[derive(Debug)]
+pub struct Router {
+    next_id: u64,
+    /// Proxied request ID -> the client that sent it and its own ID.
+    pending: HashMap<u64, (ClientId, Value)>,
+    handshake: Handshake,
+    initialized_sent: bool,
+    last_active: Option<ClientId>,
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self {
+            next_id: 1,
+            pending: HashMap::new(),
+            handshake: Handshake::NotStarted,
+            initialized_sent: false,
+            last_active: None,
+        }
+    }
+
+    /// Route one line `client` sent.
+    pub fn from_client(&mut self, client: ClientId, line: &[u8]) -> Vec<Route> {
+        self.last_active = Some(client);
+        let Ok(mut message) = serde_json::from_slice::<Value>(line) else {
+            // Not something we can route; the server can make of it what it will.
+            return vec![Route::Server(raw(line))];
+        };
+        let method = message["method"].as_str().map(str::to_string);
+        let id = message.get("id").cloned();
+        match (method.as_deref(), id) {
+            (Some("initialize"), Some(id)) => match &mut self.handshake {
+                Handshake::Done(result) => {
+                    vec![Route::Client(client, response(&id, result.clone()))]
+                }
+                Handshake::Pending(_, waiting) => {
+                    waiting.push((client, id));
+                    Vec::new()
+                }
+                Handshake::NotStarted => {
+                    let proxied = self.proxy_id(client, id);
+                    self.handshake = Handshake::Pending(proxied, Vec::new());
+                    message["id"] = json!(proxied);
+                    vec![Route::Server(encode(&message))]
+                }
+            },
+            (Some("notifications/initialized"), None) => {
+                if std::mem::replace(&mut self.initialized_sent, true) {
+                    Vec::new()
+                } else {
+                    vec![Route::Server(raw(line))]
+                }
+            }
+            (Some("notifications/cancelled"), None) => {
+                let original = message["params"]["requestId"].clone();
+                match self.find_proxied(client, &original) {
+                    Some(proxied) => {
+                        message["params"]["requestId"] = json!(proxied);
+                        vec![Route::Server(encode(&message))]
+                    }
+                    // Already answered, or never ours.
+                    None => Vec::new(),
+                }
+            }
+            (Some(_), Some(id)) => {
+                message["id"] = json!(self.proxy_id(client, id));
+                vec![Route::Server(encode(&message))]
+            }
+            // A notification, or the answer to a server-initiated request
+            // (whose ID was passed through untouched).
+            _ => vec![Route::Server(raw(line))],
+        }
+    }
+
+    /// Route one line the server wrote. `clients` are those connected.
+    pub fn from_server(&mut self, line: &[u8], clients: &[ClientId]) -> Vec<Route> {
+        let Ok(mut message) = serde_json::from_slice::<Value>(line) else {
+            return vec![Route::AllClients(raw(line))];
+        };
+        let has_method = message.get("method").is_some();
+        let id = message.get("id").cloned();
+        match (has_method, id) {
+            // A response to one of the clients.
+            (false, Some(id)) => {
+                let Some(proxied) = id.as_u64() else {
+                    return Vec::new();
+                };
+                let Some((client, original)) = self.pending.remove(&proxied) else {
+                    return Vec::new();
+                };
+                let mut routes = Vec::new();
+                if let Handshake::Pending(init_id, waiting) = &mut self.handshake {
+                    if *init_id == proxied {
+                        let waiting = std::mem::take(waiting);
+                        match message.get("result") {
+                            Some(result) => {
+                                for (waiter, waiter_id) in &waiting {
+                                    routes.push(Route::Client(
+                                        *waiter,
+                                        response(waiter_id, result.clone()),
+                                    ));
+                                }
+                                self.handshake = Handshake::Done(result.clone());
+                            }
+                            // It failed; let the next client try again.
+                            None => {
+                                for (waiter, waiter_id) in &waiting {
+                                    let mut failed = message.clone();
+                                    failed["id"] = waiter_id.clone();
+                                    routes.push(Route::Client(*waiter, encode(&failed)));
+                                }
+                                self.handshake = Handshake::NotStarted;
+                            }
+                        }
+                    }
+                }
+                message["id"] = original;
+                routes.insert(0, Route::Client(client, encode(&message)));
+                routes
+            }
+            // A request of the server's own (e.g. for sampling or roots).
+            (true, Some(id)) => {
+                let target = self
+                    .last_active
+                    .filter(|client| clients.contains(client))
+                    .or_else(|| clients.first().copied());
+                match target {
+                    Some(client) => vec![Route::Client(client, raw(line))],
+                    None => vec![Route::Server(encode(&json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "error": { "code": -32603, "message": "No client is connected" },
+                    })))],
+                }
+            }
+            _ => vec![Route::AllClients(raw(line))],
+        }
+    }
+
+    /// Forget `client`'s outstanding requests; their answers are dropped.
+    pub fn disconnect(&mut self, client: ClientId) {
+        self.pending.retain(|_, (owner, _)| *owner != client);
+        if let Handshake::Pending(_, waiting) = &mut self.handshake {
+            waiting.retain(|(owner, _)| *owner != client);
+        }
+        if self.last_active == Some(client) {
+            self.last_active = None;
+        }
+    }
+
+    fn proxy_id(&mut self, client: ClientId, original: Value) -> u64 {
+        let proxied = self.next_id;
+        self.next_id += 1;
+        self.pending.insert(proxied, (client, original));
+        proxied
+    }
+
+    fn find_proxied(&self, client: ClientId, original: &Value) -> Option<u64> {
+        self.pending
+            .iter()
+            .find(|(_, (owner, id))| *owner == client && id == original)
+            .map(|(proxied, _)| *proxied)
+    }
+}
+
+fn response(id: &Value, result: Value) -> Vec<u8> {
+    encode(&json!({ "jsonrpc": "2.0", "id": id, "result": result }))
+}
+
+/// `line` passed through as is, newline restored.
+fn raw(line: &[u8]) -> Vec<u8> {
+    let mut line = line.to_vec();
+    line.push(b'\n');
+    line
+}
+
+fn encode(message: &Value) -> Vec<u8> {
+    let mut line = serde_json::to_vec(message).unwrap_or_default();
+    line.push(b'\n');
+    line
+}
+
+/// The server's ends of the stdio pipes, held by the watcher and dup'd onto
+/// each server's stdin/stdout.
+#[derive(Debug)]
+pub struct StdioMux {
+    stdin: OwnedFd,
+    stdout: OwnedFd,
+    mux: i32,
+}
+
+/// Listen on `socket`, as privately as the lockfiles. Done by the process
+/// starting the server, so a failure is reported to the user rather than lost
+/// in the watcher.
+pub fn listen(socket: &Path) -> Result<UnixListener> {
+    let _ = std::fs::remove_file(socket);
+    let listener = UnixListener::bind(socket)
+        .with_context(|| format!("Failed to listen on {}", socket.display()))?;
+    std::fs::set_permissions(
+        socket,
+        std::fs::Permissions::from_mode(super::lockfile::lockfile_mode()),
+    )
+    .with_context(|| format!("Failed to restrict {}", socket.display()))?;
+    Ok(listener)
+}
+
+impl StdioMux {
+    /// Create the pipes and fork the mux, serving `listener` (bound to
+    /// `socket`, which it removes when it exits).
+    ///
+    /// SAFETY: forks; like the rest of the watcher this relies on the process
+    /// being single-threaded.
+    pub fn spawn(listener: UnixListener, socket: &Path) -> Result<Self> {
+        let (stdin, to_server) = pipe()?;
+        let (from_server, stdout) = pipe()?;
+
+        match unsafe { nix::unistd::fork() } {
+            Ok(nix::unistd::ForkResult::Parent { child }) => Ok(Self {
+                stdin,
+                stdout,
+                mux: child.as_raw(),
+            }),
+            Ok(nix::unistd::ForkResult::Child) => {
+                // Holding the server's ends would keep its stdout from ever
+                // reaching EOF.
+                drop(stdin);
+                drop(stdout);
+                run_mux(listener, to_server, from_server, socket.to_path_buf());
+                std::process::exit(0);
+            }
+            Err(e) => bail!("Failed to fork stdio mux: {}", e),
+        }
+    }
+
+    pub fn stdin_fd(&self) -> RawFd {
+        self.stdin.as_raw_fd()
+    }
+
+    pub fn stdout_fd(&self) -> RawFd {
+        self.stdout.as_raw_fd()
+    }
+
+    /// Tell the mux the server was relaunched, so it drops its clients.
+    pub fn reset(&self) {
+        let _ = nix::sys::signal::kill(
+            nix::unistd::Pid::from_raw(self.mux),
+            nix::sys::signal::Signal::SIGHUP,
+        );
+    }
+}
+
+fn pipe() -> Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0; 2];
+    // SAFETY: pipe writes two new descriptors into `fds`.
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } < 0 {
+        return Err(std::io::Error::last_os_error()).context("Failed to create stdio pipe");
+    }
+    // As for the log capture pipes: the server gets its copies via dup2.
+    for fd in fds {
+        // SAFETY: fcntl on a descriptor we own.
+        unsafe {
+            let flags = libc::fcntl(fd, libc::F_GETFD);
+            libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC);
+        }
+    }
+    // SAFETY: both descriptors were just created and are owned by nobody else.
+    Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+}
+
+static RESET_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_sighup(_: libc::c_int) {
+    RESET_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// A connected `proxy`.
+struct Client {
+    id: ClientId,
+    stream: UnixStream,
+    input: Vec<u8>,
+    output: Vec<u8>,
+}
+
+/// Split complete lines off the front of `buffer`.
+fn take_lines(buffer: &mut Vec<u8>) -> Vec<Vec<u8>> {
+    let mut lines = Vec::new();
+    while let Some(end) = buffer.iter().position(|&b| b == b'\n') {
+        let mut line: Vec<u8> = buffer.drain(..=end).collect();
+        line.pop();
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        if !line.is_empty() {
+            lines.push(line);
+        }
+    }
+    lines
+}
+
+/// Write as much of `pending` to `fd` as it takes without blocking. Returns
+/// `false` if the other end is gone.
+fn flush(fd: RawFd, pending: &mut Vec<u8>) -> bool {
+    while !pending.is_empty() {
+        // SAFETY: writing from a live buffer of the given length.
+        let n = unsafe { libc::write(fd, pending.as_ptr() as *const libc::c_void, pending.len()) };
+        if n > 0 {
+            pending.drain(..n as usize);
+            continue;
+        }
+        let err = std::io::Error::last_os_error();
+        return matches!(
+            err.kind(),
+            std::io::ErrorKind::WouldBlock | std::io::ErrorKind::Interrupted
+        );
+    }
+    true
+}
+
+fn set_nonblocking(fd: RawFd) {
+    // SAFETY: fcntl on a descriptor we own.
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFL);
+        libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK);
+    }
+}
+
+/// Bridge the server's pipes and the clients on `listener` until the server's
+/// stdout closes.
+fn run_mux(listener: UnixListener, to_server: OwnedFd, from_server: OwnedFd, socket: PathBuf) {
+    // SAFETY: the handler only sets an atomic flag. No SA_RESTART, so the
+    // signal interrupts poll.
+    unsafe {
+        let action = nix::sys::signal::SigAction::new(
+            nix::sys::signal::SigHandler::Handler(on_sighup),
+            nix::sys::signal::SaFlags::empty(),
+            nix::sys::signal::SigSet::empty(),
+        );
+        let _ = nix::sys::signal::sigaction(nix::sys::signal::Signal::SIGHUP, &action);
+    }
+    let _ = listener.set_nonblocking(true);
+    set_nonblocking(to_server.as_raw_fd());
+    set_nonblocking(from_server.as_raw_fd());
+
+    let mut router = Router::new();
+    let mut clients: Vec<Client> = Vec::new();
+    let mut next_client: ClientId = 1;
+    let mut server_input: Vec<u8> = Vec::new();
+    let mut server_output: Vec<u8> = Vec::new();
+    let mut buf = [0u8; 65536];
+
+    loop {
+        if RESET_REQUESTED.swap(false, Ordering::SeqCst) {
+            clients.clear();
+            router = Router::new();
+            server_input.clear();
+        }
+
+        let mut fds = vec![
+            libc::pollfd {
+                fd: listener.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: from_server.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                // poll ignores negative descriptors.
+                fd: if server_input.is_empty() {
+                    -1
+                } else {
+                    to_server.as_raw_fd()
+                },
+                events: libc::POLLOUT,
+                revents: 0,
+            },
+        ];
+        for client in &clients {
+            fds.push(libc::pollfd {
+                fd: client.stream.as_raw_fd(),
+                events: if client.output.is_empty() {
+                    libc::POLLIN
+                } else {
+                    libc::POLLIN | libc::POLLOUT
+                },
+                revents: 0,
+            });
+        }
+        // SAFETY: `fds` is a valid, correctly-sized array of pollfd.
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } < 0 {
+            if std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted {
+                continue;
+            }
+            break;
+        }
+
+        let mut routes = Vec::new();
+
+        // New clients.
+        if fds[0].revents != 0 {
+            while let Ok((stream, _)) = listener.accept() {
+                let _ = stream.set_nonblocking(true);
+                clients.push(Client {
+                    id: next_client,
+                    stream,
+                    input: Vec::new(),
+                    output: Vec::new(),
+                });
+                next_client += 1;
+            }
+        }
+
+        // What the clients sent.
+        let mut gone = Vec::new();
+        for (client, polled) in clients.iter_mut().zip(&fds[3..]) {
+            if polled.revents == 0 {
+                continue;
+            }
+            if polled.revents & libc::POLLOUT != 0
+                && !flush(client.stream.as_raw_fd(), &mut client.output)
+            {
+                gone.push(client.id);
+                continue;
+            }
+            if polled.revents & (libc::POLLIN | libc::POLLHUP | libc::POLLERR) == 0 {
+                continue;
+            }
+            match client.stream.read(&mut buf) {
+                Ok(0) => gone.push(client.id),
+                Ok(n) => {
+                    client.input.extend_from_slice(&buf[..n]);
+                    for line in take_lines(&mut client.input) {
+                        routes.extend(router.from_client(client.id, &line));
+                    }
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(_) => gone.push(client.id),
+            }
+        }
+
+        // What the server wrote. EOF means every writer is gone: the watcher
+        // has finished, and there is no server to relaunch.
+        if fds[1].revents != 0 {
+            // SAFETY: reading into a local buffer of the given length.
+            let n = unsafe {
+                libc::read(
+                    from_server.as_raw_fd(),
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                )
+            };
+            if n > 0 {
+                server_output.extend_from_slice(&buf[..n as usize]);
+                let ids: Vec<ClientId> = clients.iter().map(|c| c.id).collect();
+                for line in take_lines(&mut server_output) {
+                    routes.extend(router.from_server(&line, &ids));
+                }
+            } else if n == 0 {
+                break;
+            } else {
+                let kind = std::io::Error::last_os_error().kind();
+                if !matches!(
+                    kind,
+                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::Interrupted
+                ) {
+                    break;
+                }
+            }
+        }
+
+        for route in routes {
+            match route {
+                Route::Server(line) => server_input.extend_from_slice(&line),
+                Route::Client(id, line) => {
+                    if let Some(client) = clients.iter_mut().find(|c| c.id == id) {
+                        client.output.extend_from_slice(&line);
+                    }
+                }
+                Route::AllClients(line) => {
+                    for client in &mut clients {
+                        client.output.extend_from_slice(&line);
+                    }
+                }
+            }
+        }
+
+        // A server that has stopped reading only loses what is sent to it
+        // from here on; it never blocks the clients.
+        if !flush(to_server.as_raw_fd(), &mut server_input) {
+            server_input.clear();
+        }
+        for client in &mut clients {
+            if !flush(client.stream.as_raw_fd(), &mut client.output) {
+                gone.push(client.id);
+            }
+        }
+        for id in gone {
+            router.disconnect(id);
+            clients.retain(|c| c.id != id);
+        }
+    }
+
+    // Flush what is left for the clients, best-effort, so they see the last
+    // words of a server that is going away.
+    for client in &mut clients {
+        let _ = client.stream.set_nonblocking(false);
+        let _ = client.stream.write_all(&client.output);
+    }
+    let _ = std::fs::remove_file(socket);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(route: &Route) -> (Option<ClientId>, Value) {
+        let (client, line) = match route {
+            Route::Server(line) => (None, line),
+            Route::Client(id, line) => (Some(*id), line),
+            Route::AllClients(line) => (None, line),
+        };
+        (client, serde_json::from_slice(line).unwrap())
+    }
+
+    #[test]
+    fn test_requests_are_renumbered_and_answered_to_their_client() {
+        let mut router = Router::new();
+        let a = router.from_client(1, br#"{"jsonrpc":"2.0","id":1,"method":"tools/list"}"#);
+        let b = router.from_client(2, br#"{"jsonrpc":"2.0","id":1,"method":"tools/list"}"#);
+        let a_id = value(&a[0]).1["id"].as_u64().unwrap();
+        let b_id = value(&b[0]).1["id"].as_u64().unwrap();
+        assert_ne!(a_id, b_id);
+
+        let reply = format!(r#"{{"jsonrpc":"2.0","id":{},"result":{{}}}}"#, b_id);
+        let routes = router.from_server(reply.as_bytes(), &[1, 2]);
+        assert_eq!(routes.len(), 1);
+        let (client, message) = value(&routes[0]);
+        assert_eq!(client, Some(2));
+        assert_eq!(message["id"], 1);
+
+        // Answered once only.
+        assert!(router.from_server(reply.as_bytes(), &[1, 2]).is_empty());
+    }
+
+    #[test]
+    fn test_initialize_is_sent_once_and_replayed() {
+        let mut router = Router::new();
+        let first = router.from_client(1, br#"{"jsonrpc":"2.0","id":0,"method":"initialize"}"#);
+        assert_eq!(first.len(), 1);
+        let init_id = value(&first[0]).1["id"].clone();
+        // A second client initializing meanwhile waits for the same answer.
+        assert!(router
+            .from_client(2, br#"{"jsonrpc":"2.0","id":"x","method":"initialize"}"#)
+            .is_empty());
+
+        let reply = format!(
+            r#"{{"jsonrpc":"2.0","id":{},"result":{{"serverInfo":{{"name":"s"}}}}}}"#,
+            init_id
+        );
+        let routes = router.from_server(reply.as_bytes(), &[1, 2]);
+        assert_eq!(routes.len(), 2);
+        assert_eq!(value(&routes[0]).0, Some(1));
+        assert_eq!(value(&routes[0]).1["id"], 0);
+        assert_eq!(value(&routes[1]).0, Some(2));
+        assert_eq!(value(&routes[1]).1["id"], "x");
+
+        // Later clients are answered without the server.
+        let third = router.from_client(3, br#"{"jsonrpc":"2.0","id":5,"method":"initialize"}"#);
+        assert_eq!(value(&third[0]).0, Some(3));
+        assert_eq!(value(&third[0]).1["result"]["serverInfo"]["name"], "s");
+
+        let initialized = br#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#;
+        assert_eq!(router.from_client(1, initialized).len(), 1);
+        assert!(router.from_client(2, initialized).is_empty());
+    }
+
+    #[test]
+    fn test_server_messages_without_a_request_are_broadcast_or_routed() {
+        let mut router = Router::new();
+        let note = br#"{"jsonrpc":"2.0","method":"notifications/tools/list_changed"}"#;
+        assert_eq!(
+            router.from_server(note, &[1, 2]),
+            vec![Route::AllClients(raw(note))]
+        );
+
+        router.from_client(2, br#"{"jsonrpc":"2.0","method":"notifications/progress"}"#);
+        let request = br#"{"jsonrpc":"2.0","id":"s1","method":"roots/list"}"#;
+        assert_eq!(
+            router.from_server(request, &[1, 2]),
+            vec![Route::Client(2, raw(request))]
+        );
+        // With nobody to ask, the server gets an error back.
+        let routes = router.from_server(request, &[]);
+        assert!(value(&routes[0]).1["error"].is_object());
+    }
+
+    #[test]
+    fn test_disconnect_drops_pending_answers() {
+        let mut router = Router::new();
+        let sent = router.from_client(1, br#"{"jsonrpc":"2.0","id":1,"method":"ping"}"#);
+        let proxied = value(&sent[0]).1["id"].clone();
+        router.disconnect(1);
+        let reply = format!(r#"{{"jsonrpc":"2.0","id":{},"result":{{}}}}"#, proxied);
+        assert!(router.from_server(reply.as_bytes(), &[]).is_empty());
+    }
+
+    #[test]
+    fn test_take_lines() {
+        let mut buffer = b"a\r\n\nb\nrest".to_vec();
+        assert_eq!(take_lines(&mut buffer), vec![b"a".to_vec(), b"b".to_vec()]);
+        assert_eq!(buffer, b"rest");
+    }
+}
@@ -0,0 +1,81 @@
+//! Asking a server to stop, shared by `stop`, the daemon, and the library's
+//! [`ServerManager::stop`](super::manager::ServerManager::stop).
+//!
+//! Stopping only signals: the server's watcher reaps it and removes its
+//! lockfiles, so nothing here races the watcher's teardown.
+
+use super::lockfile::{
+    clients_lock_exists, delete_locks_owned_by, server_lock_exists, update_server_lock, LockUpdate,
+    ServerLock,
+};
+use super::notify::notify_clients;
+use super::state::watcher_alive;
+use super::Liveness;
+use anyhow::{Context, Result};
+use nix::sys::signal::{kill, killpg, Signal};
+use nix::unistd::Pid;
+
+/// Ask `server` to exit without waiting for it: mark the stop as deliberate,
+/// notify the clients if it was started with `--notify-clients`, and SIGTERM
+/// it. Returns how many clients were notified.
+pub fn request_stop(name: &str, server: &ServerLock) -> Result<usize> {
+    // Tell the watcher this exit is deliberate, so a restart policy doesn't
+    // relaunch the server as soon as it dies.
+    mark_stop_requested(name, server.pid);
+
+    // Give attached clients a heads-up (--notify-clients) before the server
+    // goes away.
+    let notified = match &server.notify_signal {
+        Some(signal) => notify_clients(name, signal, "stop").len(),
+        None => 0,
+    };
+
+    // A container is asked through its runtime; its client exits with it.
+    if let Some(container) = &server.container {
+        match container.signal("TERM") {
+            Ok(()) => return Ok(notified),
+            Err(e) => log::warn!("{:#}; signalling its client instead", e),
+        }
+    }
+
+    // Ask the server to exit. It runs in its own process group, so signal the
+    // whole group; fall back to a single-PID kill for servers started before
+    // the setpgid change.
+    let pid = Pid::from_raw(server.pid);
+    if killpg(pid, Signal::SIGTERM).is_err() {
+        kill(pid, Signal::SIGTERM).context("Failed to send SIGTERM")?;
+    }
+    Ok(notified)
+}
+
+/// Set `stop_requested` on the server lock, provided it still names `pid`.
+/// Best-effort: if the lock can't be updated the stop still proceeds, it just
+/// can't suppress a restart-policy relaunch.
+pub fn mark_stop_requested(name: &str, pid: i32) {
+    let _ = update_server_lock(name, |lock| {
+        if lock.pid != pid || lock.stop_requested {
+            return Ok(LockUpdate::Keep(()));
+        }
+        lock.stop_requested = true;
+        Ok(LockUpdate::Write(()))
+    });
+}
+
+/// Whether `server` has been fully torn down: the watcher has exited (or, for
+/// a daemon's server, finished with it) and both lockfiles are gone.
+///
+/// While a live watcher exists cleanup is left entirely to it. If there is no
+/// live watcher and the server is dead, the lockfiles are removed here —
+/// pid-guarded so a restarted instance is never touched — because nothing
+/// else will.
+pub fn torn_down(name: &str, server: &ServerLock) -> bool {
+    let watcher_alive = watcher_alive(server);
+    if !watcher_alive && server.server_liveness() != Liveness::Alive {
+        delete_locks_owned_by(name, server.pid);
+    }
+
+    // The daemon outlives the servers it supervises; for those, its removing
+    // the lockfiles is the end of teardown.
+    let watcher_done = !watcher_alive || server.daemon;
+    watcher_done && !server_lock_exists(name) && !clients_lock_exists(name)
+}
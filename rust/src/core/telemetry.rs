@@ -0,0 +1,400 @@
+//! Minimal OpenTelemetry tracing: spans for commands and watcher decisions,
+//! exported as OTLP/HTTP JSON when an endpoint is configured.
+//!
+//! Configured the standard way: `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT` (the
+//! full URL) or `OTEL_EXPORTER_OTLP_ENDPOINT` (with `/v1/traces` appended),
+//! `OTEL_EXPORTER_OTLP_HEADERS`, and `OTEL_SERVICE_NAME`. A `TRACEPARENT`
+//! in the environment (W3C trace context, as CI systems set it) makes the
+//! command's span a child of the caller's. Only plain `http://` endpoints are
+//! supported, as for health probes: point it at a local collector.
+//!
+//! Everything is a no-op when no endpoint is set, or `OTEL_SDK_DISABLED` is
+//! `true`.
+
+use serde_json::{json, Value};
+use std::io::Read;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::probe::HttpTarget;
+use super::webhook::post_json;
+
+/// How long an export may take before its spans are dropped.
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(2);
+
+type TraceId = [u8; 16];
+type SpanId = [u8; 8];
+
+/// Something noted during a span: when, what, and its attributes.
+type Event = (SystemTime, String, Vec<(String, Value)>);
+
+/// Where and how to export, read once from the environment.
+struct Exporter {
+    target: HttpTarget,
+    headers: Vec<(String, String)>,
+    service: String,
+}
+
+/// A span in progress. Recorded when [`Span::end`] is called (or it is
+/// dropped); the next [`flush`] exports it.
+pub struct Span {
+    name: String,
+    trace_id: TraceId,
+    span_id: SpanId,
+    parent: Option<SpanId>,
+    /// The span that was current before this one, restored when it ends.
+    previous: Option<(TraceId, SpanId)>,
+    start: SystemTime,
+    attributes: Vec<(String, Value)>,
+    events: Vec<Event>,
+    error: Option<String>,
+    ended: bool,
+}
+
+/// Ended spans awaiting export, each with the PID that recorded it, so a
+/// forked watcher never re-exports its parent's.
+static PENDING: Mutex<Vec<(u32, Value)>> = Mutex::new(Vec::new());
+
+/// The span new spans are children of.
+static CURRENT: Mutex<Option<(TraceId, SpanId)>> = Mutex::new(None);
+
+fn exporter() -> Option<&'static Exporter> {
+    static EXPORTER: OnceLock<Option<Exporter>> = OnceLock::new();
+    EXPORTER
+        .get_or_init(|| {
+            if std::env::var("OTEL_SDK_DISABLED").is_ok_and(|v| v.eq_ignore_ascii_case("true")) {
+                return None;
+            }
+            let url = std::env::var("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT")
+                .ok()
+                .or_else(|| {
+                    std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT")
+                        .ok()
+                        .map(|base| format!("{}/v1/traces", base.trim_end_matches('/')))
+                })?;
+            let target = match HttpTarget::parse(&url) {
+                Ok(target) => target,
+                Err(_) => {
+                    log::warn!("OTLP endpoint '{}' is not an http:// URL; not tracing", url);
+                    return None;
+                }
+            };
+            let headers = std::env::var("OTEL_EXPORTER_OTLP_HEADERS")
+                .unwrap_or_default()
+                .split(',')
+                .filter_map(|pair| pair.split_once('='))
+                .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+                .collect();
+            let service =
+                std::env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| "sharedserver".to_string());
+            Some(Exporter {
+                target,
+                headers,
+                service,
+            })
+        })
+        .as_ref()
+}
+
+/// Whether spans are being exported.
+pub fn enabled() -> bool {
+    exporter().is_some()
+}
+
+/// Start a span named `name`: a child of the current span, or else of the
+/// caller's `TRACEPARENT`, or else the root of a new trace. It is current
+/// until it ends.
+pub fn span(name: &str) -> Span {
+    if !enabled() {
+        // Never recorded, so it needs no identity.
+        return Span {
+            name: name.to_string(),
+            trace_id: [0; 16],
+            span_id: [0; 8],
+            parent: None,
+            previous: None,
+            start: SystemTime::now(),
+            attributes: Vec::new(),
+            events: Vec::new(),
+            error: None,
+            ended: true,
+        };
+    }
+    let mut current = CURRENT.lock().unwrap_or_else(|e| e.into_inner());
+    let previous = *current;
+    let (trace_id, parent) = match previous.or_else(traceparent) {
+        Some((trace_id, parent)) => (trace_id, Some(parent)),
+        None => (random(), None),
+    };
+    let span_id = random();
+    *current = Some((trace_id, span_id));
+    Span {
+        name: name.to_string(),
+        trace_id,
+        span_id,
+        parent,
+        previous,
+        start: SystemTime::now(),
+        attributes: Vec::new(),
+        events: Vec::new(),
+        error: None,
+        ended: false,
+    }
+}
+
+/// Record a finished, zero-length span named `name` (a decision, rather than
+/// work that takes time) with `details` as its attributes.
+pub fn mark(name: &str, details: &Value) {
+    if !enabled() {
+        return;
+    }
+    let mut span = span(name);
+    if let Some(details) = details.as_object() {
+        for (key, value) in details {
+            span.attr(key, value.clone());
+        }
+    }
+    span.end();
+}
+
+impl Span {
+    pub fn attr(&mut self, key: &str, value: impl Into<Value>) -> &mut Self {
+        self.attributes.push((key.to_string(), value.into()));
+        self
+    }
+
+    /// Note something that happened during the span.
+    pub fn event(&mut self, name: &str, attributes: &[(&str, Value)]) -> &mut Self {
+        self.events.push((
+            SystemTime::now(),
+            name.to_string(),
+            attributes
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.clone()))
+                .collect(),
+        ));
+        self
+    }
+
+    /// Mark the span as failed with `message`.
+    pub fn fail(&mut self, message: impl std::fmt::Display) -> &mut Self {
+        self.error = Some(message.to_string());
+        self
+    }
+
+    /// Mark the span failed if `result` is an error, and pass it through.
+    pub fn record<T>(&mut self, result: anyhow::Result<T>) -> anyhow::Result<T> {
+        if let Err(e) = &result {
+            self.fail(format!("{:#}", e));
+        }
+        result
+    }
+
+    pub fn end(mut self) {
+        self.finish();
+    }
+
+    fn finish(&mut self) {
+        if self.ended {
+            return;
+        }
+        self.ended = true;
+        {
+            let mut current = CURRENT.lock().unwrap_or_else(|e| e.into_inner());
+            if *current == Some((self.trace_id, self.span_id)) {
+                *current = self.previous;
+            }
+        }
+        let span = self.to_otlp(SystemTime::now());
+        PENDING
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push((std::process::id(), span));
+    }
+
+    fn to_otlp(&self, end: SystemTime) -> Value {
+        let mut span = json!({
+            "traceId": hex(&self.trace_id),
+            "spanId": hex(&self.span_id),
+            "name": self.name,
+            "kind": 1,
+            "startTimeUnixNano": nanos(self.start),
+            "endTimeUnixNano": nanos(end),
+            "attributes": attributes(&self.attributes),
+            "events": self.events.iter().map(|(at, name, attrs)| json!({
+                "timeUnixNano": nanos(*at),
+                "name": name,
+                "attributes": attributes(attrs),
+            })).collect::<Vec<_>>(),
+            "status": match &self.error {
+                Some(message) => json!({ "code": 2, "message": message }),
+                None => json!({ "code": 1 }),
+            },
+        });
+        if let Some(parent) = self.parent {
+            span["parentSpanId"] = json!(hex(&parent));
+        }
+        span
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+/// Export the spans this process has ended since the last flush. Best-effort:
+/// spans that can't be delivered within [`EXPORT_TIMEOUT`] are dropped.
+pub fn flush() {
+    let Some(exporter) = exporter() else {
+        return;
+    };
+    let pid = std::process::id();
+    let spans: Vec<Value> = {
+        let mut pending = PENDING.lock().unwrap_or_else(|e| e.into_inner());
+        std::mem::take(&mut *pending)
+            .into_iter()
+            .filter(|(recorded_by, _)| *recorded_by == pid)
+            .map(|(_, span)| span)
+            .collect()
+    };
+    if spans.is_empty() {
+        return;
+    }
+    let body = json!({
+        "resourceSpans": [{
+            "resource": { "attributes": attributes(&[
+                ("service.name".to_string(), json!(exporter.service)),
+                ("service.version".to_string(), json!(env!("CARGO_PKG_VERSION"))),
+                ("process.pid".to_string(), json!(pid)),
+            ]) },
+            "scopeSpans": [{
+                "scope": { "name": "sharedserver", "version": env!("CARGO_PKG_VERSION") },
+                "spans": spans,
+            }],
+        }],
+    });
+    if let Err(e) = post_json(
+        &exporter.target,
+        &exporter.headers,
+        &body.to_string(),
+        EXPORT_TIMEOUT,
+    ) {
+        log::debug!("OTLP export failed: {}", e);
+    }
+}
+
+/// The caller's span, from a W3C `TRACEPARENT` (`00-<trace>-<span>-<flags>`).
+fn traceparent() -> Option<(TraceId, SpanId)> {
+    parse_traceparent(&std::env::var("TRACEPARENT").ok()?)
+}
+
+fn parse_traceparent(value: &str) -> Option<(TraceId, SpanId)> {
+    let mut parts = value.trim().split('-');
+    if parts.next()? != "00" {
+        return None;
+    }
+    let trace_id = unhex(parts.next()?)?;
+    let span_id = unhex(parts.next()?)?;
+    if trace_id == [0; 16] || span_id == [0; 8] {
+        return None;
+    }
+    Some((trace_id, span_id))
+}
+
+fn attributes(attributes: &[(String, Value)]) -> Vec<Value> {
+    attributes
+        .iter()
+        .filter(|(_, value)| !value.is_null())
+        .map(|(key, value)| {
+            let value = match value {
+                Value::String(s) => json!({ "stringValue": s }),
+                Value::Bool(b) => json!({ "boolValue": b }),
+                Value::Number(n) if n.is_i64() || n.is_u64() => {
+                    json!({ "intValue": n.to_string() })
+                }
+                Value::Number(n) => json!({ "doubleValue": n.as_f64() }),
+                other => json!({ "stringValue": other.to_string() }),
+            };
+            json!({ "key": key, "value": value })
+        })
+        .collect()
+}
+
+fn nanos(at: SystemTime) -> String {
+    at.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
+
+fn random<const N: usize>() -> [u8; N] {
+    let mut bytes = [0u8; N];
+    if std::fs::File::open("/dev/urandom")
+        .and_then(|mut random| random.read_exact(&mut bytes))
+        .is_err()
+    {
+        // Unique enough for tracing if /dev/urandom is unavailable.
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos()
+            ^ ((std::process::id() as u128) << 64);
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = (seed >> ((i % 16) * 8)) as u8;
+        }
+    }
+    bytes
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex<const N: usize>(s: &str) -> Option<[u8; N]> {
+    if s.len() != N * 2 {
+        return None;
+    }
+    let mut bytes = [0u8; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(s.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_traceparent() {
+        let (trace, span) =
+            parse_traceparent("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01").unwrap();
+        assert_eq!(hex(&trace), "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(hex(&span), "00f067aa0ba902b7");
+        assert!(
+            parse_traceparent("01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01").is_none()
+        );
+        assert!(
+            parse_traceparent("00-00000000000000000000000000000000-00f067aa0ba902b7-01").is_none()
+        );
+        assert!(parse_traceparent("00-xyz-00f067aa0ba902b7-01").is_none());
+    }
+
+    #[test]
+    fn test_otlp_span_shape() {
+        let mut span = span("use");
+        span.attr("server.name", "db").attr("client.pid", 42);
+        span.event("launched", &[("server.pid", json!(7))]);
+        span.fail("boom");
+        let otlp = span.to_otlp(SystemTime::now());
+        assert_eq!(otlp["name"], "use");
+        assert_eq!(otlp["traceId"].as_str().unwrap().len(), 32);
+        assert!(otlp.get("parentSpanId").is_none());
+        assert_eq!(otlp["attributes"][0]["value"]["stringValue"], "db");
+        assert_eq!(otlp["attributes"][1]["value"]["intValue"], "42");
+        assert_eq!(otlp["events"][0]["name"], "launched");
+        assert_eq!(otlp["status"]["code"], 2);
+    }
+}
@@ -0,0 +1,197 @@
+use super::restart::ServerExit;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Why the server went down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DeathReason {
+    /// Exited on its own with status 0.
+    Exited,
+    /// Exited on its own with a failure status or signal.
+    Crashed,
+    /// Taken down by `stop`.
+    Stopped,
+    /// Taken down by the watcher when its grace period ran out.
+    GraceExpired,
+    /// Taken down by the watcher after failing its health check.
+    Unhealthy,
+    /// SIGKILLed by `kill`, watcher and all.
+    Killed,
+    /// Taken down by the watcher for exceeding a memory or CPU limit.
+    ResourceLimit,
+    /// Taken down by its watcher after the watcher itself was sent SIGTERM
+    /// (logout, system shutdown).
+    Shutdown,
+}
+
+impl DeathReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeathReason::Exited => "exited",
+            DeathReason::Crashed => "crashed",
+            DeathReason::Stopped => "stopped",
+            DeathReason::GraceExpired => "grace-expired",
+            DeathReason::Unhealthy => "unhealthy",
+            DeathReason::Killed => "killed",
+            DeathReason::ResourceLimit => "resource-limit",
+            DeathReason::Shutdown => "shutdown",
+        }
+    }
+
+    /// Classify an exit the server made on its own (nobody asked it to go).
+    pub fn unrequested(exit: &ServerExit) -> Self {
+        if exit.is_success() {
+            DeathReason::Exited
+        } else {
+            DeathReason::Crashed
+        }
+    }
+}
+
+impl fmt::Display for DeathReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Record of how a server instance ended, kept after its lockfiles are gone
+/// (`<name>.exit.json`). Each death overwrites the previous one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Tombstone {
+    pub pid: i32,
+    pub command: Vec<String>,
+    pub reason: DeathReason,
+    pub exit: ServerExit,
+    pub started_at: DateTime<Utc>,
+    pub exited_at: DateTime<Utc>,
+    #[serde(default)]
+    pub restart_count: u32,
+    /// How many clients were attached when it went down. Zero on older
+    /// records.
+    #[serde(default)]
+    pub last_refcount: u32,
+    /// The server's tags, so it can still be selected by them.
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+}
+
+/// How long exit records are kept, unless overridden with
+/// `SHAREDSERVER_TOMBSTONE_RETENTION` (e.g. "48h").
+pub const DEFAULT_RETENTION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// The retention in effect: `SHAREDSERVER_TOMBSTONE_RETENTION` if set and
+/// valid, else [`DEFAULT_RETENTION`].
+pub fn retention() -> Duration {
+    std::env::var("SHAREDSERVER_TOMBSTONE_RETENTION")
+        .ok()
+        .and_then(|r| super::duration::parse_duration(&r).ok())
+        .unwrap_or(DEFAULT_RETENTION)
+}
+
+impl Tombstone {
+    /// Whether the record is older than the retention period.
+    pub fn is_expired(&self) -> bool {
+        chrono::Duration::from_std(retention())
+            .map(|retention| Utc::now() - self.exited_at > retention)
+            .unwrap_or(false)
+    }
+}
+
+/// Get path to the exit record
+pub fn tombstone_path(name: &str) -> Result<PathBuf> {
+    super::lockfile::server_file(name, "exit.json")
+}
+
+/// Write the exit record for `name`, replacing any earlier one
+pub fn write_tombstone(name: &str, tombstone: &Tombstone) -> Result<()> {
+    let path = tombstone_path(name)?;
+    let json = serde_json::to_string_pretty(tombstone)?;
+    super::lockfile::write_private(&path, json)
+        .with_context(|| format!("Failed to write exit record: {:?}", path))
+}
+
+/// Read the exit record for `name`, or `None` if it has never died (or the
+/// record is unreadable).
+pub fn read_tombstone(name: &str) -> Option<Tombstone> {
+    read_tombstone_at(&tombstone_path(name).ok()?).filter(|t| !t.is_expired())
+}
+
+fn read_tombstone_at(path: &Path) -> Option<Tombstone> {
+    let contents = std::fs::read_to_string(path).ok()?;
+    serde_json::from_str(&contents).ok()
+}
+
+/// Every server's exit record still within the retention period, most recent
+/// first. Expired records are deleted along the way.
+pub fn recent_tombstones() -> Result<Vec<(String, Tombstone)>> {
+    let mut recent = Vec::new();
+    for name in super::lockfile::servers_with(&["exit.json"])? {
+        let path = tombstone_path(&name)?;
+        let Some(tombstone) = read_tombstone_at(&path) else {
+            continue;
+        };
+        if tombstone.is_expired() {
+            let _ = super::fsutil::remove_file(&path);
+        } else {
+            recent.push((name, tombstone));
+        }
+    }
+    recent.sort_by_key(|(_, tombstone)| std::cmp::Reverse(tombstone.exited_at));
+    Ok(recent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unrequested_reason() {
+        assert_eq!(
+            DeathReason::unrequested(&ServerExit::Exited { code: 0 }),
+            DeathReason::Exited
+        );
+        assert_eq!(
+            DeathReason::unrequested(&ServerExit::Exited { code: 2 }),
+            DeathReason::Crashed
+        );
+        assert_eq!(
+            DeathReason::unrequested(&ServerExit::Signaled { signal: 11 }),
+            DeathReason::Crashed
+        );
+    }
+
+    #[test]
+    fn test_tombstone_round_trip() {
+        let tombstone = Tombstone {
+            pid: 42,
+            command: vec!["sleep".into(), "1".into()],
+            reason: DeathReason::GraceExpired,
+            exit: ServerExit::Signaled { signal: 15 },
+            started_at: Utc::now(),
+            exited_at: Utc::now(),
+            restart_count: 0,
+            last_refcount: 2,
+            tags: BTreeMap::new(),
+        };
+        assert!(!tombstone.is_expired());
+        let json = serde_json::to_value(&tombstone).unwrap();
+        assert_eq!(json["reason"], "grace-expired");
+        assert_eq!(json["exit"]["kind"], "signaled");
+        let back: Tombstone = serde_json::from_value(json).unwrap();
+        assert_eq!(back.reason, DeathReason::GraceExpired);
+        assert_eq!(back.exit, ServerExit::Signaled { signal: 15 });
+        assert_eq!(back.last_refcount, 2);
+
+        let old = Tombstone {
+            exited_at: Utc::now() - chrono::Duration::days(30),
+            ..back
+        };
+        assert!(old.is_expired());
+    }
+}
@@ -0,0 +1,232 @@
+//! Lifecycle webhooks (`--on-event-webhook URL`): the watcher POSTs a JSON
+//! payload when a server starts, crashes, restarts, enters its grace period,
+//! or shuts down, so chat and paging tools hear about it without polling.
+//!
+//! `http://` URLs are posted directly. `https://` ones (Slack, PagerDuty, and
+//! the like) are handed to `curl`, as there is no TLS stack here. Either way
+//! the watcher delivers from a detached process ([`send_detached`]), so a slow
+//! endpoint never holds up its loop.
+
+use anyhow::{bail, Context, Result};
+use nix::sys::wait::waitpid;
+use nix::unistd::{fork, ForkResult};
+use serde_json::{json, Value};
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use super::probe::HttpTarget;
+
+/// The environment variable naming a webhook for servers started without
+/// `--on-event-webhook`.
+pub const WEBHOOK_ENV: &str = "SHAREDSERVER_WEBHOOK";
+
+/// How long a delivery may take once connected.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The lifecycle events a webhook is told about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LifecycleEvent {
+    Start,
+    /// The server exited on its own with a failure status or signal.
+    Crash,
+    /// The watcher relaunched the server under its restart policy.
+    Restart,
+    /// The last client detached and the grace period began.
+    Grace,
+    /// The server went down for any other reason (see `details.reason`).
+    Shutdown,
+}
+
+impl LifecycleEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LifecycleEvent::Start => "start",
+            LifecycleEvent::Crash => "crash",
+            LifecycleEvent::Restart => "restart",
+            LifecycleEvent::Grace => "grace",
+            LifecycleEvent::Shutdown => "shutdown",
+        }
+    }
+
+    /// What happened, as a phrase following the server's name.
+    fn describe(&self, details: &Value) -> String {
+        match self {
+            LifecycleEvent::Start => "started".to_string(),
+            LifecycleEvent::Crash => match details["exit"].as_str() {
+                Some(exit) => format!("crashed ({})", exit),
+                None => "crashed".to_string(),
+            },
+            LifecycleEvent::Restart => "was restarted".to_string(),
+            LifecycleEvent::Grace => "has no clients and entered its grace period".to_string(),
+            LifecycleEvent::Shutdown => match details["reason"].as_str() {
+                Some(reason) => format!("shut down ({})", reason),
+                None => "shut down".to_string(),
+            },
+        }
+    }
+}
+
+/// Reject a webhook URL that could never be delivered to.
+pub fn validate(url: &str) -> Result<()> {
+    match url.strip_prefix("https://") {
+        Some(rest) if rest.split('/').next().is_some_and(|host| !host.is_empty()) => Ok(()),
+        Some(_) => bail!("Webhook URL has no host: '{}'", url),
+        None if url.starts_with("http://") => HttpTarget::parse(url).map(|_| ()),
+        None => bail!(
+            "Webhook URL must start with http:// or https:// (got '{}')",
+            url
+        ),
+    }
+}
+
+/// The JSON body POSTed for `event` on server `name`. `text` is a one-line
+/// summary, which Slack-compatible incoming webhooks display as is.
+pub fn payload(name: &str, event: LifecycleEvent, details: &Value) -> Value {
+    let host = nix::unistd::gethostname()
+        .map(|h| h.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    json!({
+        "event": event.as_str(),
+        "server": name,
+        "host": host,
+        "timestamp": chrono::Utc::now().to_rfc3339(),
+        "details": details,
+        "text": format!("sharedserver: {} {} on {}", name, event.describe(details), host),
+    })
+}
+
+/// POST `payload` to `url`, failing on a non-2xx answer.
+pub fn send(url: &str, payload: &Value) -> Result<()> {
+    let body = payload.to_string();
+    if url.starts_with("https://") {
+        return send_with_curl(url, &body);
+    }
+    let target = HttpTarget::parse(url)?;
+    let status = post_json(&target, &[], &body, TIMEOUT)
+        .with_context(|| format!("Failed to POST to {}", url))?;
+    if !(200..300).contains(&status) {
+        bail!("{} answered HTTP {}", url, status);
+    }
+    Ok(())
+}
+
+/// [`send`] from a detached grandchild, which the caller never waits on, so a
+/// slow endpoint (or slow DNS) can't stall it. `on_failure` runs in that
+/// process with the error.
+///
+/// SAFETY: forks, so the caller must be single-threaded (the watcher and the
+/// daemon are).
+pub fn send_detached(url: &str, payload: &Value, on_failure: impl FnOnce(anyhow::Error)) {
+    match unsafe { fork() } {
+        // The middle process exits at once, so this wait is immediate and the
+        // deliverer is nobody's child to reap.
+        Ok(ForkResult::Parent { child }) => {
+            let _ = waitpid(child, None);
+        }
+        Ok(ForkResult::Child) => {
+            if let Ok(ForkResult::Child) = unsafe { fork() } {
+                super::front::close_inherited(-1);
+                if let Err(e) = send(url, payload) {
+                    on_failure(e);
+                }
+                std::process::exit(0);
+            }
+            // SAFETY: skips the parent's atexit handlers and buffered output,
+            // which belong to it.
+            unsafe { libc::_exit(0) };
+        }
+        Err(e) => on_failure(anyhow::anyhow!("Failed to fork the delivery: {}", e)),
+    }
+}
+
+fn send_with_curl(url: &str, body: &str) -> Result<()> {
+    let mut curl = Command::new("curl")
+        .args(["--silent", "--show-error", "--fail", "--max-time"])
+        .arg(TIMEOUT.as_secs().to_string())
+        .args(["--header", "Content-Type: application/json"])
+        .args(["--data-binary", "@-", url])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to run curl (needed for https:// webhooks)")?;
+    if let Some(mut stdin) = curl.stdin.take() {
+        stdin.write_all(body.as_bytes())?;
+    }
+    let output = curl.wait_with_output()?;
+    if !output.status.success() {
+        bail!(
+            "curl failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// POST a JSON `body` to `target` with extra `headers`, returning the
+/// response's status code.
+pub(crate) fn post_json(
+    target: &HttpTarget,
+    headers: &[(String, String)],
+    body: &str,
+    timeout: Duration,
+) -> std::io::Result<u16> {
+    let host = target.host.trim_start_matches('[').trim_end_matches(']');
+    let addr = (host, target.port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| std::io::Error::other(format!("cannot resolve {}", target.host)))?;
+    let mut stream = TcpStream::connect_timeout(&addr, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    let mut request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}:{}\r\nUser-Agent: sharedserver\r\n\
+         Content-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+        target.path,
+        target.host,
+        target.port,
+        body.len()
+    );
+    for (key, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", key, value));
+    }
+    request.push_str("\r\n");
+    request.push_str(body);
+    stream.write_all(request.as_bytes())?;
+    // Wait for the status line, so the process doesn't exit (and reset the
+    // connection) before the receiver has read the body.
+    let mut status = [0u8; 12];
+    stream.read_exact(&mut status)?;
+    std::str::from_utf8(&status[9..12])
+        .ok()
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| std::io::Error::other("malformed HTTP response"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_webhook_urls() {
+        assert!(validate("http://127.0.0.1:9000/hook").is_ok());
+        assert!(validate("https://hooks.slack.com/services/T0/B0/x").is_ok());
+        assert!(validate("https:///path").is_err());
+        assert!(validate("ftp://example.com").is_err());
+    }
+
+    #[test]
+    fn test_payload_summarises_event() {
+        let details = json!({ "server_pid": 7, "exit": "exit code 1" });
+        let payload = payload("db", LifecycleEvent::Crash, &details);
+        assert_eq!(payload["event"], "crash");
+        assert_eq!(payload["server"], "db");
+        assert_eq!(payload["details"]["server_pid"], 7);
+        assert!(payload["text"]
+            .as_str()
+            .unwrap()
+            .starts_with("sharedserver: db crashed (exit code 1) on "));
+    }
+}
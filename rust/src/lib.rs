@@ -5,6 +5,11 @@ pub mod core;
 pub use core::{
     clients_lock_exists, delete_clients_lock, delete_server_lock, get_server_state,
     is_process_alive, parse_duration, read_clients_lock, read_server_lock, server_lock_exists,
-    with_lock, write_clients_lock, write_server_lock, ClientInfo, ClientsLock, ServerLock,
-    ServerState,
+    update_clients_lock, update_server_lock, with_lock, write_clients_lock, write_server_lock,
+    ClientInfo, ClientsLock, LockUpdate, ServerLock, ServerState,
 };
+
+// The high-level API (see `core::manager`)
+pub use core::error::ErrorKind;
+pub use core::handle::ServerHandle;
+pub use core::manager::{DoctorReport, ServerInfo, ServerManager, UseOptions};
@@ -1,9 +1,23 @@
 use anyhow::Result;
-use clap::{CommandFactory, Parser, Subcommand};
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
 use clap_complete::Shell;
+use sharedserver::core::config::{Config, Defaults};
+use sharedserver::core::filter::{is_pattern, parse_tag};
+use sharedserver::core::pool;
+use sharedserver::core::{
+    telemetry, ErrorKind, HealthCheck, HealthProbe, LimitAction, ResourceLimits, ServerFilter,
+    ServerState,
+};
+use std::ffi::OsString;
+use std::process::ExitCode;
 
 mod cli;
-use cli::{commands, output, watcher};
+use cli::commands::hook::HookShell;
+use cli::commands::list::ListSort;
+#[cfg(feature = "grpc")]
+use cli::grpc;
+use cli::output::{ColorChoice, OutputFormat};
+use cli::{commands, daemon, http, logging, output, watcher};
 
 const LONG_ABOUT: &str = "\
 sharedserver - Manage shared servers with reference counting
@@ -15,13 +29,25 @@ down gracefully when no clients remain (after a configurable grace period).
 EVERYDAY COMMANDS:
   use         Attach to a server (starts if needed)
   unuse       Detach from a server
+  up / down   Start or stop every server defined in the config
+  proxy       Share a stdio (e.g. MCP) server between clients
+  lsp         Share a TCP language server with stdio-only editors
   list        Show all running servers
   info        Get detailed server information
   check       Check if server is running
+  last        Show how a server last went down
+  events      Stream a server's state changes
   completion  Generate shell completions
+  daemon      Supervise servers from one process, over a unix socket
+  export      Generate a service definition for another supervisor (systemd, launchd)
+  config      Check the config file for problems
+  prompt      Summarize this shell's attached servers for PS1
+  hook        Detach the shell from its servers when it exits
+  direnv      Use a server while the shell is in a directory (.envrc)
+  tmux-attach Tail a server's log in a tmux window
 
 ADMIN COMMANDS:
-  admin       Low-level server operations (start, stop, incref, decref, debug, doctor, kill)
+  admin       Low-level server operations (start, stop, incref, decref, debug, doctor, kill, prune)
   
 See 'sharedserver <command> --help' for detailed command information.
 See 'sharedserver admin --help' for administrative operations.
@@ -34,8 +60,293 @@ See 'sharedserver admin --help' for administrative operations.
 #[command(long_about = LONG_ABOUT)]
 #[command(arg_required_else_help = true)]
 struct Cli {
+    /// Operate on the servers shared by the members of --allow-group, in a
+    /// group-owned system directory, instead of your own private ones
+    #[arg(long, global = true, requires = "allow_group")]
+    shared: bool,
+
+    /// Group whose members share the servers (with --shared)
+    #[arg(long, global = true, value_name = "GROUP", requires = "shared")]
+    allow_group: Option<String>,
+
+    /// Apply this profile from the config to its servers
+    /// [default: $SHAREDSERVER_PROFILE]
+    #[arg(long, global = true, value_name = "NAME")]
+    profile: Option<String>,
+
+    /// Output format for list, info, check, prompt, last, events, config
+    /// check, and admin debug/doctor; tsv and csv are for list only [default: table, or
+    /// $SHAREDSERVER_FORMAT]
+    #[arg(long, global = true, value_enum)]
+    format: Option<OutputFormat>,
+
+    /// When to color output: auto (only on a terminal, and not if NO_COLOR
+    /// is set), always, or never [default: the config's, or auto]
+    #[arg(long, global = true, value_enum)]
+    color: Option<ColorChoice>,
+
+    /// Draw symbols, rules, and trees in plain ASCII instead of Unicode
+    /// (the default when the locale isn't UTF-8)
+    #[arg(long, global = true)]
+    ascii: bool,
+
+    /// Log what sharedserver decides and why to stderr (-vv for more); a
+    /// watcher started this way logs to its watcher log instead. RUST_LOG
+    /// overrides this
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Send use, unuse, and admin start to the running daemon (see
+    /// 'sharedserver daemon'); without one they act directly as usual
+    #[arg(long, global = true)]
+    via_daemon: bool,
+
+    /// Print the exit codes sharedserver reports failures with, and exit
+    #[arg(long)]
+    print_exit_codes: bool,
+
     #[command(subcommand)]
-    command: Commands,
+    command: Option<Commands>,
+}
+
+/// Health-probe flags shared by `use` and `admin start`
+#[derive(Args)]
+struct HealthArgs {
+    /// Shell command the watcher runs to probe health (exit 0 = healthy)
+    #[arg(long, value_name = "COMMAND", conflicts_with_all = ["health_http", "health_tcp"])]
+    health_cmd: Option<String>,
+    /// http:// URL the watcher GETs to probe health (2xx = healthy)
+    #[arg(long, value_name = "URL", conflicts_with = "health_tcp")]
+    health_http: Option<String>,
+    /// host:port the watcher connects to to probe health (accepted = healthy)
+    #[arg(long, value_name = "HOST:PORT")]
+    health_tcp: Option<String>,
+    /// HTTP status the --health-http probe must return (default: any 2xx)
+    #[arg(long, value_name = "CODE", requires = "health_http")]
+    health_expect_status: Option<u16>,
+    /// How often the watcher runs the health probe
+    #[arg(long, default_value = "30s")]
+    health_interval: String,
+    /// How long a single probe may take before it counts as failed
+    #[arg(long, default_value = "5s")]
+    health_timeout: String,
+    /// Consecutive failed probes before the server is marked unhealthy
+    #[arg(long, default_value_t = 3)]
+    health_retries: u32,
+    /// Restart the server once it is marked unhealthy
+    #[arg(long)]
+    health_restart: bool,
+}
+
+impl HealthArgs {
+    fn into_check(self) -> Option<HealthCheck> {
+        let probe = if let Some(command) = self.health_cmd {
+            HealthProbe::Command { command }
+        } else if let Some(url) = self.health_http {
+            HealthProbe::Http {
+                url,
+                expected_status: self.health_expect_status,
+            }
+        } else if let Some(address) = self.health_tcp {
+            HealthProbe::Tcp { address }
+        } else {
+            return None;
+        };
+        Some(HealthCheck {
+            probe,
+            interval: self.health_interval,
+            timeout: self.health_timeout,
+            retries: self.health_retries,
+            restart: self.health_restart,
+        })
+    }
+}
+
+/// Resource-limit flags shared by `use` and `admin start`
+#[derive(Args)]
+struct FilterArgs {
+    /// Only servers in these states: active, grace, stopped, or defunct
+    /// (repeatable, or comma-separated)
+    #[arg(long, value_name = "STATE", value_delimiter = ',')]
+    state: Vec<ServerState>,
+    /// Only servers whose name matches this pattern (`*` and `?` wildcards),
+    /// e.g. 'lsp-*'
+    #[arg(long = "name", value_name = "PATTERN")]
+    name_pattern: Option<String>,
+    /// Only servers with this tag (repeatable: all must match)
+    #[arg(long = "tag", value_name = "KEY=VALUE", value_parser = parse_tag)]
+    tags: Vec<(String, String)>,
+}
+
+impl FilterArgs {
+    fn into_filter(self) -> ServerFilter {
+        ServerFilter {
+            states: self.state,
+            name: self.name_pattern,
+            tags: self.tags,
+        }
+    }
+}
+
+#[derive(Args)]
+struct ExportArgs {
+    /// Describe the server as `[servers.<name>]` in the config defines it,
+    /// rather than the running one
+    #[arg(long)]
+    defined: bool,
+    /// Grace period [default: the running or configured server's, or 5m]
+    #[arg(long)]
+    grace_period: Option<String>,
+    /// Environment variables in KEY=VALUE format, added to the running
+    /// server's (can be specified multiple times)
+    #[arg(long = "env", value_name = "KEY=VALUE")]
+    env_vars: Vec<String>,
+    /// Log file for server stdout/stderr
+    #[arg(long)]
+    log_file: Option<String>,
+    /// Restart policy: never, on-failure, or always [default: the running or
+    /// configured server's, or never]
+    #[arg(long)]
+    restart: Option<String>,
+}
+
+impl ExportArgs {
+    fn into_options(self) -> commands::export::ExportOptions {
+        commands::export::ExportOptions {
+            grace_period: self.grace_period,
+            env_vars: self.env_vars,
+            log_file: self.log_file,
+            restart: self.restart,
+            defined: self.defined,
+        }
+    }
+}
+
+#[derive(Args)]
+struct LimitArgs {
+    /// Memory (RSS) ceiling for the server's process group, e.g. "512M", "2G"
+    #[arg(long, value_name = "SIZE")]
+    memory_limit: Option<String>,
+    /// What to do once --memory-limit is exceeded: log, restart, or stop
+    #[arg(long, value_name = "ACTION", default_value = "log")]
+    memory_action: LimitAction,
+    /// CPU ceiling as a percentage of one core, e.g. "90" or "150%"
+    #[arg(long, value_name = "PERCENT")]
+    cpu_limit: Option<String>,
+    /// What to do once --cpu-limit is exceeded: log, restart, or stop
+    #[arg(long, value_name = "ACTION", default_value = "log")]
+    cpu_action: LimitAction,
+    /// How long a limit must stay exceeded before its action is taken
+    #[arg(long, value_name = "DURATION", default_value = "30s")]
+    limit_sustained: String,
+}
+
+impl LimitArgs {
+    fn into_limits(self) -> Option<ResourceLimits> {
+        if self.memory_limit.is_none() && self.cpu_limit.is_none() {
+            return None;
+        }
+        Some(ResourceLimits {
+            memory_limit: self.memory_limit,
+            memory_action: self.memory_action,
+            cpu_limit: self.cpu_limit,
+            cpu_action: self.cpu_action,
+            sustained: self.limit_sustained,
+        })
+    }
+}
+
+/// Launch flags shared by `use` and `admin start`
+#[derive(Args)]
+struct StartArgs {
+    /// Grace period before shutdown when refcount reaches 0 (e.g., "5m", "1.5h", "2d"; units ms, s, m, h, d, w), "none" to stop at once, or "infinite" to run until stopped [default: the config's, or 5m]
+    #[arg(long)]
+    grace_period: Option<String>,
+    /// Clock the grace period runs on: monotonic (pauses while the
+    /// machine sleeps) or wall (sleep counts toward it)
+    #[arg(long, default_value = "monotonic")]
+    grace_clock: String,
+    /// Environment variables in KEY=VALUE format (can be specified multiple times)
+    #[arg(long = "env", value_name = "KEY=VALUE")]
+    env_vars: Vec<String>,
+    /// Optional log file path for server stdout/stderr
+    #[arg(long)]
+    log_file: Option<String>,
+    /// Prefix each line in --log-file with a timestamp and [stdout]/[stderr]
+    #[arg(long, requires = "log_file")]
+    log_timestamps: bool,
+    /// Restart the server if it exits while clients are attached:
+    /// never, on-failure, or always [default: the config's, or never]
+    #[arg(long)]
+    restart: Option<String>,
+    #[command(flatten)]
+    health: HealthArgs,
+    #[command(flatten)]
+    limits: LimitArgs,
+    /// Signal every attached client with SIGNAL (e.g. SIGUSR1) just before
+    /// the server is shut down
+    #[arg(long, value_name = "SIGNAL")]
+    notify_clients: Option<String>,
+    /// If the watcher is sent SIGTERM (logout, shutdown), leave the server
+    /// running unsupervised instead of stopping it
+    #[arg(long)]
+    linger: bool,
+    /// Where the server can be reached (tcp:HOST:PORT, HOST:PORT, PORT, or
+    /// unix:PATH), shown by list and info [default: the health probe's
+    /// address, if any]
+    #[arg(long)]
+    address: Option<String>,
+    /// Listen on this address too (same forms as --address) and forward
+    /// each connection to the server's own --address, keeping the
+    /// address stable while the server is relaunched
+    #[arg(long, value_name = "ADDRESS")]
+    listen: Option<String>,
+    /// POST a JSON payload to URL when the server starts, crashes,
+    /// restarts, enters its grace period, or shuts down
+    /// [default: $SHAREDSERVER_WEBHOOK]
+    #[arg(long, value_name = "URL")]
+    on_event_webhook: Option<String>,
+    /// Show a desktop notification when the server enters its grace
+    /// period and when it is shut down
+    #[arg(long)]
+    notify_desktop: bool,
+    /// Run the server as a local process, or as a docker or podman
+    /// container of --image
+    #[arg(long, default_value = "process", value_name = "BACKEND")]
+    backend: String,
+    /// Image to run with --backend docker or podman; the command after --
+    /// (if any) replaces the image's default
+    #[arg(long)]
+    image: Option<String>,
+    /// Label the server KEY=VALUE, for selecting it with --tag in list,
+    /// stop, doctor, and prune (repeatable)
+    #[arg(long = "tag", value_name = "KEY=VALUE", value_parser = parse_tag)]
+    tags: Vec<(String, String)>,
+}
+
+impl StartArgs {
+    /// The options. The grace period and restart policy are left for the
+    /// caller to resolve against the config.
+    fn into_options(self) -> commands::start::StartOptions {
+        commands::start::StartOptions {
+            grace_clock: self.grace_clock,
+            env_vars: self.env_vars,
+            log_file: self.log_file,
+            log_timestamps: self.log_timestamps,
+            health_check: self.health.into_check(),
+            limits: self.limits.into_limits(),
+            notify_clients: self.notify_clients,
+            linger: self.linger,
+            address: self.address,
+            listen: self.listen,
+            on_event_webhook: self.on_event_webhook,
+            notify_desktop: self.notify_desktop,
+            backend: self.backend,
+            image: self.image,
+            tags: self.tags.into_iter().collect(),
+            ..Default::default()
+        }
+    }
 }
 
 #[derive(Subcommand)]
@@ -44,51 +355,268 @@ enum Commands {
     Use {
         /// Server name
         name: String,
-        /// Grace period before shutdown when refcount reaches 0 (e.g., "5m", "1h", "30s")
-        #[arg(long, default_value = "5m")]
-        grace_period: String,
         /// Optional client metadata
         #[arg(long)]
         metadata: Option<String>,
         /// Client PID (defaults to parent process - the caller)
         #[arg(long)]
         pid: Option<i32>,
+        #[command(flatten)]
+        start: StartArgs,
+        /// If the server is running with a different command or environment,
+        /// drain it and restart with this one (attached clients are kept)
+        #[arg(long)]
+        replace: bool,
+        /// Don't start the servers the config says this one depends on
+        #[arg(long)]
+        no_deps: bool,
+        /// If starting it would run more servers than the config's
+        /// max_running (or max_running_tags) allows, wait until one stops
+        /// instead of failing
+        #[arg(long)]
+        queue: bool,
+        /// Print nothing on success; report only through the exit code
+        /// (errors still go to stderr)
+        #[arg(short, long)]
+        quiet: bool,
+        /// Report progress as JSON lines on stdout instead (starting,
+        /// pid-assigned, ready-probe-passed, attached; error on failure),
+        /// holding attached until a health check, if any, passes
+        #[arg(long, conflicts_with = "quiet")]
+        events_json: bool,
+        /// Server command and arguments (required if server not running)
+        #[arg(last = true)]
+        command: Vec<String>,
+    },
+    /// Share a stdio server: attach (starting it if needed) and relay this
+    /// process's stdin/stdout to it for as long as the session lasts
+    Proxy {
+        /// Server name
+        name: String,
+        /// Grace period before shutdown when the last proxy exits (e.g. "5m")
+        /// [default: the config's, or 5m]
+        #[arg(long)]
+        grace_period: Option<String>,
+        /// Clock the grace period runs on: monotonic or wall
+        #[arg(long, default_value = "monotonic")]
+        grace_clock: String,
+        /// Optional client metadata
+        #[arg(long)]
+        metadata: Option<String>,
+        /// Environment variables in KEY=VALUE format (can be specified multiple times)
+        #[arg(long = "env", value_name = "KEY=VALUE")]
+        env_vars: Vec<String>,
+        /// Optional log file path for the server's stderr
+        #[arg(long)]
+        log_file: Option<String>,
+        /// Restart the server if it exits while proxies are attached:
+        /// never, on-failure, or always (proxies are disconnected when it is)
+        #[arg(long, default_value = "never")]
+        restart: String,
+        /// Run the server as a local process, or as a docker or podman
+        /// container of --image
+        #[arg(long, default_value = "process", value_name = "BACKEND")]
+        backend: String,
+        /// Image to run with --backend docker or podman
+        #[arg(long)]
+        image: Option<String>,
+        /// Server command and arguments (required if server not running)
+        #[arg(last = true)]
+        command: Vec<String>,
+    },
+    /// Share a language server over TCP with editors that only speak stdio:
+    /// attach (starting it on a free port if needed) and relay this process's
+    /// stdin/stdout to a connection to it for as long as the session lasts
+    Lsp {
+        /// Server name
+        name: String,
+        /// Environment variable the server reads its port from
+        #[arg(long, value_name = "VAR")]
+        port_env: String,
+        /// Grace period before shutdown when the last editor exits (e.g. "5m")
+        /// [default: the config's, or 5m]
+        #[arg(long)]
+        grace_period: Option<String>,
+        /// Optional client metadata
+        #[arg(long)]
+        metadata: Option<String>,
+        /// Environment variables in KEY=VALUE format (can be specified multiple times)
+        #[arg(long = "env", value_name = "KEY=VALUE")]
+        env_vars: Vec<String>,
+        /// Optional log file path for server output
+        #[arg(long)]
+        log_file: Option<String>,
+        /// How long to wait for the server to accept a connection
+        #[arg(long, default_value = "30s")]
+        connect_timeout: String,
+        /// Server command and arguments (required if server not running)
+        #[arg(last = true)]
+        command: Vec<String>,
+    },
+    /// Use a server while the shell is in this directory: for .envrc, as
+    /// `eval "$(sharedserver direnv <name> -- <cmd>)"`. Prints `export`
+    /// lines for the server's PID and address
+    Direnv {
+        /// Server name
+        name: String,
+        /// Grace period before shutdown when the last client leaves (e.g. "5m")
+        /// [default: the config's, or 5m]
+        #[arg(long)]
+        grace_period: Option<String>,
         /// Environment variables in KEY=VALUE format (can be specified multiple times)
         #[arg(long = "env", value_name = "KEY=VALUE")]
         env_vars: Vec<String>,
         /// Optional log file path for server stdout/stderr
         #[arg(long)]
         log_file: Option<String>,
+        /// Where the server can be reached (tcp:HOST:PORT, HOST:PORT, PORT, or
+        /// unix:PATH), exported as SHAREDSERVER_<NAME>_ADDRESS (and _HOST and
+        /// _PORT, or _SOCKET) [default: the health probe's address, if any]
+        #[arg(long)]
+        address: Option<String>,
+        #[command(flatten)]
+        health: HealthArgs,
         /// Server command and arguments (required if server not running)
         #[arg(last = true)]
         command: Vec<String>,
     },
+    /// Use every server defined in the config (or in GROUP), starting any
+    /// that aren't running, like `use <name>` for each, dependencies first
+    Up {
+        /// Only the servers listing this group in their `groups`
+        group: Option<String>,
+        /// Client PID to hold the references (defaults to the caller)
+        #[arg(long)]
+        pid: Option<i32>,
+        /// Start the servers in the order the config lists them, without
+        /// adding their dependencies or waiting for any to be ready
+        #[arg(long)]
+        no_deps: bool,
+    },
+    /// Stop every server defined in the config (or in GROUP) that is running
+    Down {
+        /// Only the servers listing this group in their `groups`
+        group: Option<String>,
+        /// Escalate to SIGKILL if a server doesn't stop within the timeout
+        #[arg(long)]
+        force: bool,
+        /// How long to wait for each server to stop (e.g. "10s", "1m")
+        /// [default: the config's shutdown_timeout, or 10s]
+        #[arg(long)]
+        timeout: Option<String>,
+    },
     /// Detach from a server (decrement reference count)
     Unuse {
         /// Server name
-        name: String,
+        #[arg(required_unless_present = "all")]
+        name: Option<String>,
+        /// Detach from every server the client is attached to (what the
+        /// shell hooks from 'hook' run on exit)
+        #[arg(long, conflicts_with = "name")]
+        all: bool,
         /// Client PID (defaults to parent process - the caller)
         #[arg(long)]
         pid: Option<i32>,
+        /// Print nothing on success; report only through the exit code
+        /// (errors still go to stderr)
+        #[arg(short, long)]
+        quiet: bool,
     },
     /// List all servers
     List {
-        /// Output as JSON (for programmatic use)
+        /// Output as JSON (same as --format json)
         #[arg(long)]
         json: bool,
+        /// Also show servers that stopped recently, and how they went down
+        #[arg(long)]
+        recent: bool,
+        /// Order servers by name, uptime (longest first), refcount (most
+        /// first), or state
+        #[arg(long, value_enum, default_value = "name")]
+        sort: ListSort,
+        /// Sample each server's memory (RSS) and CPU use and show them
+        #[arg(long)]
+        resources: bool,
+        /// Show each server's processes (watcher, server, and the server's
+        /// children) and its attached clients as a tree
+        #[arg(long)]
+        tree: bool,
+        /// Read up to N servers' locks and processes at once [default: the
+        /// number of CPUs]
+        #[arg(long, value_name = "N")]
+        jobs: Option<usize>,
+        #[command(flatten)]
+        filter: FilterArgs,
     },
     /// Get detailed server information
     Info {
-        /// Server name
+        /// Server name, or a pattern ('lsp-*') for every matching server
         name: String,
-        /// Output as JSON (for programmatic use)
+        /// Output as JSON (same as --format json)
         #[arg(long)]
         json: bool,
+        /// Keep refreshing the view, every INTERVAL (default 2s), until
+        /// interrupted
+        #[arg(
+            long,
+            value_name = "INTERVAL",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "2s"
+        )]
+        watch: Option<String>,
     },
     /// Check server status
     Check {
         /// Server name
         name: String,
+        /// Print nothing; report the state only through the exit code
+        #[arg(short, long)]
+        quiet: bool,
+    },
+    /// Print the servers this shell is attached to, e.g. `[lsp✓ db⚠]`, for
+    /// a prompt (PS1, starship); prints nothing if there are none
+    Prompt {
+        /// Shell PID whose process tree to look for clients in (defaults to
+        /// the parent process - the shell)
+        #[arg(long)]
+        pid: Option<i32>,
+    },
+    /// Open a tmux window named after the server, tailing its log file, or
+    /// switch to the one opened before
+    TmuxAttach {
+        /// Server name
+        name: String,
+        /// Hold a reference on the server for as long as the window's pane
+        /// is open
+        #[arg(long)]
+        hold: bool,
+    },
+    /// Print shell code that detaches the shell from its servers when it
+    /// exits; add `eval "$(sharedserver hook bash)"` to ~/.bashrc (zsh
+    /// likewise; fish: `sharedserver hook fish | source`)
+    Hook {
+        #[arg(value_enum)]
+        shell: HookShell,
+    },
+    /// Show how a server last went down (exit code/signal and reason)
+    Last {
+        /// Server name
+        name: String,
+        /// Output as JSON (same as --format json)
+        #[arg(long)]
+        json: bool,
+    },
+    /// Stream a server's state changes (start, stop, grace, clients) as they happen
+    Events {
+        /// Server name
+        name: String,
+        /// Output one JSON object per line (same as --format json)
+        #[arg(long)]
+        json: bool,
+        /// Exit after this many events (default: run until interrupted)
+        #[arg(long, value_name = "N")]
+        count: Option<usize>,
     },
     /// Generate shell completion scripts
     Completion {
@@ -96,6 +624,40 @@ enum Commands {
         #[arg(value_enum)]
         shell: Shell,
     },
+    /// Run the daemon that supervises servers started with --via-daemon, in
+    /// the foreground, until SIGTERM or 'daemon stop'
+    #[command(args_conflicts_with_subcommands = true)]
+    Daemon {
+        /// Also serve the REST API on ADDR (e.g. 127.0.0.1:7070). Requests
+        /// need `Authorization: Bearer <token>`, the token being
+        /// $SHAREDSERVER_HTTP_TOKEN or else a new one written to http.token
+        /// in the lock directory
+        #[arg(long, value_name = "ADDR")]
+        http: Option<String>,
+        /// Also serve the gRPC API (proto/sharedserver.proto) on ADDR, with
+        /// the same token as --http, sent as `authorization: Bearer <token>`
+        /// metadata. Needs a build with the `grpc` feature
+        #[arg(long, value_name = "ADDR")]
+        grpc: Option<String>,
+        /// Exit after DURATION (e.g. "10m") with no servers and no requests.
+        /// Meant for socket activation, which starts it again on the next
+        /// request
+        #[arg(long, value_name = "DURATION")]
+        idle_exit: Option<String>,
+        #[command(subcommand)]
+        action: Option<DaemonAction>,
+    },
+    /// Generate a service definition that runs a server under another
+    /// supervisor
+    Export {
+        #[command(subcommand)]
+        target: ExportTarget,
+    },
+    /// Work with the config file
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
     /// Administrative commands for low-level server operations
     Admin {
         #[command(subcommand)]
@@ -103,6 +665,54 @@ enum Commands {
     },
 }
 
+#[derive(Subcommand)]
+enum DaemonAction {
+    /// Show whether the daemon is running, and what it supervises
+    Status,
+    /// Ask the daemon to stop its servers and exit, and wait until it has
+    Stop,
+}
+
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Check every server the config defines before anything is started:
+    /// durations parse, programs exist, no two claim a port. Reports each
+    /// problem with its file and line, and fails if there are any
+    Check,
+}
+
+#[derive(Subcommand)]
+enum ExportTarget {
+    /// Print a systemd user unit that runs the server with 'use' and stops it
+    /// with 'admin stop'. Describes the running server, or with --defined the
+    /// config's, unless its command is given after --
+    Systemd {
+        /// Server name
+        name: String,
+        #[command(flatten)]
+        options: ExportArgs,
+        /// Server command and arguments [default: the running or configured
+        /// server's]
+        #[arg(last = true)]
+        command: Vec<String>,
+    },
+    /// Print a launchd agent plist that starts the server with 'use' at
+    /// login (macOS). Describes the running server, or with --defined the
+    /// config's, unless its command is given after --
+    Launchd {
+        /// Server name
+        name: String,
+        #[command(flatten)]
+        options: ExportArgs,
+        /// Server command and arguments [default: the running or configured
+        /// server's]
+        #[arg(last = true)]
+        command: Vec<String>,
+    },
+}
+
+// Parsed once per process, so variant size doesn't matter.
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand)]
 enum AdminCommands {
     /// Start a new server with NO clients (low-level - use 'sharedserver use' instead)
@@ -114,29 +724,31 @@ enum AdminCommands {
     Start {
         /// Server name
         name: String,
-        /// Grace period before shutdown when refcount reaches 0 (e.g., "5m", "1h", "30s")
-        #[arg(long, default_value = "5m")]
-        grace_period: String,
-        /// Environment variables in KEY=VALUE format (can be specified multiple times)
-        #[arg(long = "env", value_name = "KEY=VALUE")]
-        env_vars: Vec<String>,
-        /// Optional log file path for server stdout/stderr
-        #[arg(long)]
-        log_file: Option<String>,
+        #[command(flatten)]
+        start: StartArgs,
         /// Server command and arguments
-        #[arg(last = true, required = true)]
+        #[arg(last = true, required_unless_present = "image")]
         command: Vec<String>,
     },
     /// Stop a server: SIGTERM, then wait for the watcher to tear it down
     Stop {
-        /// Server name
-        name: String,
+        /// Server name, or a pattern ('test_*') for every matching server
+        #[arg(required_unless_present = "tags")]
+        name: Option<String>,
+        /// Stop every running server with this tag (repeatable: all must
+        /// match)
+        #[arg(long = "tag", value_name = "KEY=VALUE", value_parser = parse_tag)]
+        tags: Vec<(String, String)>,
+        /// Stop every server a pattern or --tag matches without asking
+        #[arg(short, long)]
+        yes: bool,
         /// Escalate to SIGKILL if the server doesn't stop within the timeout
         #[arg(long)]
         force: bool,
         /// How long to wait for teardown to converge (e.g. "10s", "1m", "500ms")
-        #[arg(long, default_value = "10s")]
-        timeout: String,
+        /// [default: the config's shutdown_timeout, or 10s]
+        #[arg(long)]
+        timeout: Option<String>,
     },
     /// Increment reference count (low-level - use 'sharedserver use' instead)
     Incref {
@@ -160,80 +772,520 @@ enum AdminCommands {
     },
     /// Show invocation log for debugging
     Debug {
-        /// Server name
+        /// Server name, or a pattern ('lsp-*') for every matching server
         name: String,
+        /// Show the watcher's own event log (grace, dead clients, kills, ...)
+        /// instead of the invocation log
+        #[arg(long)]
+        watcher: bool,
+        /// Print every entry in full with its UTC timestamp, as logged,
+        /// instead of one summarized line each
+        #[arg(long)]
+        raw: bool,
     },
     /// Validate server state and clean up inconsistencies
     Doctor {
-        /// Server name (if omitted, checks all servers)
+        /// Server name, or a pattern ('lsp-*') (if omitted, checks all
+        /// servers)
+        #[arg(conflicts_with_all = ["state", "name_pattern", "tags"])]
         name: Option<String>,
+
+        /// Narrow the all-servers check
+        #[command(flatten)]
+        filter: FilterArgs,
+
+        /// Repair the server's lockfiles first: restore a corrupt server lock
+        /// from its backup and rebuild the clients from the invocation log
+        #[arg(long, requires = "name")]
+        restore: bool,
+
+        /// Check up to N servers at once [default: the number of CPUs]
+        #[arg(long, value_name = "N")]
+        jobs: Option<usize>,
+
+        /// Output the findings as JSON (same as --format json)
+        #[arg(long)]
+        json: bool,
     },
     /// Force kill a server and clean up all state
     Kill {
-        /// Server name
+        /// Server name, or a pattern ('test_*') for every matching server
         name: String,
+        /// Kill every server a pattern matches without asking
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Delete the logs of servers gone longer than SHAREDSERVER_LOG_RETENTION
+    /// (default 30 days) and trim logs over SHAREDSERVER_LOG_MAX_SIZE bytes
+    /// (default 1 MiB)
+    Prune {
+        /// Only show what would be removed or trimmed
+        #[arg(long)]
+        dry_run: bool,
+        /// Only the logs of servers with this tag (repeatable: all must match)
+        #[arg(long = "tag", value_name = "KEY=VALUE", value_parser = parse_tag)]
+        tags: Vec<(String, String)>,
     },
 }
 
-fn main() -> Result<()> {
-    let cli = Cli::parse();
+fn main() -> ExitCode {
+    let cli = Cli::parse_from(expand_alias(std::env::args_os().collect()));
+    logging::init(cli.verbose);
+    let result = run(cli);
+    telemetry::flush();
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            ExitCode::from(sharedserver::core::error::exit_code(&e) as u8)
+        }
+    }
+}
+
+/// `args` with an alias from the config's `[aliases]` in the subcommand's
+/// place replaced by what it stands for. The built-in subcommands can't be
+/// redefined, and an alias can't use another.
+fn expand_alias(mut args: Vec<OsString>) -> Vec<OsString> {
+    let cli = Cli::command();
+    // Skip the global options, and the values of those that take one.
+    let mut index = 1;
+    while let Some(arg) = args.get(index).and_then(|arg| arg.to_str()) {
+        let takes_value = if let Some(long) = arg.strip_prefix("--") {
+            !long.is_empty()
+                && !long.contains('=')
+                && cli
+                    .get_arguments()
+                    .find(|option| option.get_long() == Some(long))
+                    .is_some_and(|option| option.get_action().takes_values())
+        } else if arg.starts_with('-') {
+            false
+        } else {
+            break;
+        };
+        index += if takes_value { 2 } else { 1 };
+    }
+    let Some(name) = args.get(index).and_then(|arg| arg.to_str()) else {
+        return args;
+    };
+    if cli.find_subcommand(name).is_some() {
+        return args;
+    }
+    // A config that can't be read is reported once the command runs.
+    let Some(alias) = Config::load()
+        .ok()
+        .and_then(|mut config| config.aliases.remove(name))
+    else {
+        return args;
+    };
+    args.splice(index..=index, alias.args().into_iter().map(OsString::from));
+    args
+}
+
+/// The config's `[defaults]`, with those the whole process shares applied:
+/// the lock directory (through the environment, so watchers started from
+/// here see it too) and the watchers' timings. A config that can't be read
+/// is ignored with a warning.
+fn load_defaults() -> Defaults {
+    let defaults = match Config::load() {
+        Ok(config) => config.defaults,
+        Err(e) => {
+            output::print_warning(&format!("Ignoring the config: {:#}", e));
+            Defaults::default()
+        }
+    };
+    if let Some(lockdir) = &defaults.lockdir {
+        if std::env::var_os("SHAREDSERVER_LOCKDIR").is_none() {
+            std::env::set_var("SHAREDSERVER_LOCKDIR", lockdir);
+        }
+    }
+    watcher::set_timing(defaults.poll_interval(), defaults.shutdown_timeout());
+    commands::start::set_capacity(defaults.capacity());
+    defaults
+}
 
-    match cli.command {
+/// `stop`'s and `down`'s `--timeout`, or else the config's default.
+fn stop_timeout(timeout: Option<String>, defaults: &Defaults) -> String {
+    timeout
+        .or_else(|| defaults.shutdown_timeout.clone())
+        .unwrap_or_else(|| commands::stop::DEFAULT_TIMEOUT.to_string())
+}
+
+fn run(cli: Cli) -> Result<()> {
+    if let Some(group) = &cli.allow_group {
+        sharedserver::core::shared::enter_shared_mode(group)?;
+    }
+    if let Some(profile) = &cli.profile {
+        // Through the environment, so watchers started from here see it too.
+        std::env::set_var(sharedserver::core::config::PROFILE_ENV, profile);
+    }
+
+    let format = OutputFormat::resolve(cli.format)?;
+    let json_flag = match &cli.command {
+        Some(Commands::List { json, .. })
+        | Some(Commands::Info { json, .. })
+        | Some(Commands::Last { json, .. })
+        | Some(Commands::Events { json, .. })
+        | Some(Commands::Admin {
+            command: AdminCommands::Doctor { json, .. },
+        }) => *json,
+        _ => false,
+    };
+    let format = format.or_json(json_flag);
+    // Checked before running anything: the other commands' results aren't
+    // rows, and some (`admin doctor`) act before they print.
+    if format.is_delimited() && !matches!(cli.command, Some(Commands::List { .. })) {
+        return Err(ErrorKind::InvalidArgs.error(format!(
+            "--format {} is only supported by list; use json or yaml here",
+            if format == OutputFormat::Tsv {
+                "tsv"
+            } else {
+                "csv"
+            }
+        )));
+    }
+    if let Some(
+        Commands::Use { quiet: true, .. }
+        | Commands::Use {
+            events_json: true, ..
+        }
+        | Commands::Direnv { .. }
+        | Commands::Unuse { quiet: true, .. }
+        | Commands::Check { quiet: true, .. },
+    ) = &cli.command
+    {
+        output::set_quiet(true);
+    }
+    let defaults = load_defaults();
+    let color = cli.color.or_else(|| {
+        let color = defaults.color.as_deref()?;
+        ColorChoice::from_str(color, true).ok()
+    });
+    // Documents for programs never carry terminal escapes.
+    output::init_color(if format == OutputFormat::Table {
+        color.unwrap_or_default()
+    } else {
+        ColorChoice::Never
+    });
+    output::init_ascii(cli.ascii);
+
+    if cli.print_exit_codes {
+        return commands::exit_codes::execute(format);
+    }
+    let Some(command) = cli.command else {
+        Cli::command()
+            .error(
+                clap::error::ErrorKind::MissingSubcommand,
+                "a subcommand is required",
+            )
+            .exit();
+    };
+
+    match command {
         Commands::Use {
             name,
-            grace_period,
             metadata,
             pid,
+            mut start,
+            replace,
+            no_deps,
+            queue,
+            events_json,
+            command,
+            ..
+        } => traced("use", &name, || {
+            let flags = commands::r#use::UseFlags {
+                replace,
+                via_daemon: cli.via_daemon,
+                events_json,
+            };
+            if !no_deps {
+                commands::r#use::use_dependencies(&name, pid, flags)?;
+            }
+            let (grace_period, restart) = (start.grace_period.take(), start.restart.take());
+            let mut opts = commands::start::StartOptions {
+                queue,
+                ..start.into_options()
+            };
+            let mut command = command;
+            commands::r#use::apply_config(&name, grace_period, restart, &mut opts, &mut command)?;
+            commands::r#use::execute(&name, &opts, metadata, pid, flags, &command)
+        }),
+        Commands::Up {
+            group,
+            pid,
+            no_deps,
+        } => commands::up::execute(group.as_deref(), pid, no_deps, cli.via_daemon),
+        Commands::Down {
+            group,
+            force,
+            timeout,
+        } => commands::up::down(group.as_deref(), force, &stop_timeout(timeout, &defaults)),
+        Commands::Proxy {
+            name,
+            grace_period,
+            grace_clock,
+            metadata,
             env_vars,
             log_file,
+            restart,
+            backend,
+            image,
             command,
-        } => commands::r#use::execute(
+        } => commands::proxy::execute(
             &name,
-            &grace_period,
+            &commands::start::StartOptions {
+                grace_period: commands::r#use::config_grace_period(&name, grace_period),
+                grace_clock,
+                env_vars,
+                log_file,
+                restart,
+                backend,
+                image,
+                ..Default::default()
+            },
             metadata,
-            pid,
-            &env_vars,
-            log_file.as_deref(),
             &command,
         ),
-        Commands::Unuse { name, pid } => commands::unuse::execute(&name, pid),
-        Commands::List { json } => commands::list::execute(json),
-        Commands::Info { name, json } => commands::info::execute(&name, json),
-        Commands::Check { name } => commands::check::execute(&name),
+        Commands::Lsp {
+            name,
+            port_env,
+            grace_period,
+            metadata,
+            env_vars,
+            log_file,
+            connect_timeout,
+            command,
+        } => {
+            let connect_timeout = sharedserver::core::parse_duration(&connect_timeout)
+                .map_err(|e| ErrorKind::InvalidArgs.wrap(e, "Invalid --connect-timeout"))?;
+            commands::lsp::execute(
+                &name,
+                &port_env,
+                &commands::start::StartOptions {
+                    grace_period: commands::r#use::config_grace_period(&name, grace_period),
+                    env_vars,
+                    log_file,
+                    ..Default::default()
+                },
+                connect_timeout,
+                metadata,
+                &command,
+            )
+        }
+        Commands::Direnv {
+            name,
+            grace_period,
+            env_vars,
+            log_file,
+            address,
+            health,
+            command,
+        } => traced("direnv", &name, || {
+            commands::direnv::execute(
+                &name,
+                &commands::start::StartOptions {
+                    grace_period: commands::r#use::config_grace_period(&name, grace_period),
+                    env_vars,
+                    log_file,
+                    health_check: health.into_check(),
+                    address,
+                    ..Default::default()
+                },
+                cli.via_daemon,
+                &command,
+            )
+        }),
+        Commands::Unuse {
+            name: Some(name),
+            pid,
+            ..
+        } => traced("unuse", &name, || {
+            commands::unuse::execute(&name, pid, cli.via_daemon)
+        }),
+        Commands::Unuse { pid, .. } => commands::unuse::execute_all(pid, cli.via_daemon),
+        Commands::List {
+            recent,
+            filter,
+            sort,
+            resources,
+            tree,
+            jobs,
+            ..
+        } => commands::list::execute(
+            format,
+            recent,
+            filter.into_filter(),
+            sort,
+            resources,
+            tree,
+            jobs.unwrap_or_else(pool::default_jobs),
+        ),
+        Commands::Info { name, watch, .. } if is_pattern(&name) => {
+            if watch.is_some() {
+                return Err(
+                    ErrorKind::InvalidArgs.error("--watch needs a server name, not a pattern")
+                );
+            }
+            commands::info::execute_matching(&name, format)
+        }
+        Commands::Info { name, watch, .. } => {
+            commands::info::execute(&name, format, watch.as_deref())
+        }
+        Commands::Check { name, .. } => commands::check::execute(&name, format),
+        Commands::Prompt { pid } => commands::prompt::execute(pid, format),
+        Commands::TmuxAttach { name, hold } => traced("tmux-attach", &name, || {
+            commands::tmux::execute(&name, hold)
+        }),
+        Commands::Hook { shell } => commands::hook::execute(shell),
+        Commands::Last { name, .. } => commands::last::execute(&name, format),
+        Commands::Events { name, count, .. } => commands::events::execute(&name, format, count),
         Commands::Completion { shell } => {
             let mut cmd = Cli::command();
             let bin_name = cmd.get_name().to_string();
             clap_complete::generate(shell, &mut cmd, bin_name, &mut std::io::stdout());
             Ok(())
         }
+        Commands::Daemon {
+            http,
+            grpc,
+            idle_exit,
+            action,
+        } => match action {
+            None => {
+                let idle_exit = idle_exit
+                    .as_deref()
+                    .map(sharedserver::core::parse_duration)
+                    .transpose()
+                    .map_err(|e| ErrorKind::InvalidArgs.wrap(e, "Invalid --idle-exit"))?;
+                daemon::run(http.as_deref(), grpc.as_deref(), idle_exit)
+            }
+            Some(DaemonAction::Status) => commands::daemon::status(format),
+            Some(DaemonAction::Stop) => commands::daemon::stop(),
+        },
+        Commands::Config {
+            action: ConfigAction::Check,
+        } => {
+            let cli = Cli::command();
+            let builtins: Vec<&str> = cli.get_subcommands().map(|c| c.get_name()).collect();
+            commands::config::check(format, &builtins)
+        }
+        Commands::Export { target } => match target {
+            ExportTarget::Systemd {
+                name,
+                options,
+                command,
+            } => commands::export::systemd(&name, &options.into_options(), &command),
+            ExportTarget::Launchd {
+                name,
+                options,
+                command,
+            } => commands::export::launchd(&name, &options.into_options(), &command),
+        },
         Commands::Admin { command } => match command {
             AdminCommands::Start {
                 name,
-                grace_period,
-                env_vars,
-                log_file,
+                mut start,
                 command,
-            } => commands::start::execute(
-                &name,
-                &grace_period,
-                &env_vars,
-                &command,
-                log_file.as_deref(),
-            ),
+            } => traced("start", &name, || {
+                let (grace_period, restart) = commands::r#use::config_start_settings(
+                    &name,
+                    start.grace_period.take(),
+                    start.restart.take(),
+                );
+                commands::start::execute(
+                    &name,
+                    &commands::start::StartOptions {
+                        grace_period,
+                        restart,
+                        ..start.into_options()
+                    },
+                    &command,
+                    cli.via_daemon,
+                )
+            }),
+            AdminCommands::Stop {
+                name: Some(name),
+                tags,
+                force,
+                timeout,
+                ..
+            } if tags.is_empty() && !is_pattern(&name) => traced("stop", &name, || {
+                commands::stop::execute(&name, force, &stop_timeout(timeout, &defaults))
+            }),
             AdminCommands::Stop {
                 name,
+                tags,
+                yes,
                 force,
                 timeout,
-            } => commands::stop::execute(&name, force, &timeout),
+            } => commands::stop::execute_matching(
+                &ServerFilter {
+                    name,
+                    tags,
+                    ..Default::default()
+                },
+                force,
+                &stop_timeout(timeout, &defaults),
+                yes,
+            ),
             AdminCommands::Incref {
                 name,
                 metadata,
                 pid,
             } => commands::incref::execute(&name, metadata, pid),
             AdminCommands::Decref { name, pid } => commands::decref::execute(&name, pid),
-            AdminCommands::Debug { name } => commands::debug::execute(&name, 50),
-            AdminCommands::Doctor { name } => commands::doctor::execute(name),
-            AdminCommands::Kill { name } => commands::kill::execute(&name),
+            AdminCommands::Debug { name, watcher, raw } if is_pattern(&name) => {
+                commands::debug::execute_matching(&name, watcher, 50, raw, format)
+            }
+            AdminCommands::Debug { name, watcher, raw } => {
+                if watcher {
+                    commands::debug::execute_watcher(&name, 50, raw, format)
+                } else {
+                    commands::debug::execute(&name, 50, raw, format)
+                }
+            }
+            AdminCommands::Doctor {
+                name,
+                filter,
+                restore,
+                jobs,
+                json: _,
+            } => {
+                let mut filter = filter.into_filter();
+                // A pattern narrows the all-servers check, as `--name` does.
+                let name = match name {
+                    Some(pattern) if is_pattern(&pattern) => {
+                        if restore {
+                            return Err(ErrorKind::InvalidArgs
+                                .error("--restore needs a server name, not a pattern"));
+                        }
+                        filter.name = Some(pattern);
+                        None
+                    }
+                    name => name,
+                };
+                let jobs = jobs.unwrap_or_else(pool::default_jobs);
+                commands::doctor::execute(name, filter, restore, jobs, format)
+            }
+            AdminCommands::Kill { name, yes } if is_pattern(&name) => {
+                commands::kill::execute_matching(&name, yes)
+            }
+            AdminCommands::Kill { name, .. } => commands::kill::execute(&name),
+            AdminCommands::Prune { dry_run, tags } => commands::prune::execute(
+                dry_run,
+                &ServerFilter {
+                    tags,
+                    ..Default::default()
+                },
+            ),
         },
     }
 }
+
+/// Run `command` on server `name` inside a span named after it, so traces
+/// show each invocation with what it did beneath it.
+fn traced(command: &str, name: &str, run: impl FnOnce() -> Result<()>) -> Result<()> {
+    let mut span = telemetry::span(&format!("sharedserver {}", command));
+    span.attr("server.name", name);
+    span.record(run())
+}
@@ -46,13 +46,20 @@ fn get_test_helper_path(script_name: &str) -> PathBuf {
 fn cleanup_lock_files(server_name: &str) {
     let temp_dir = test_lockdir();
 
-    let server_lock = temp_dir.join(format!("{}.server.json", server_name));
-    let clients_lock = temp_dir.join(format!("{}.clients.json", server_name));
-    let invocations_log = temp_dir.join(format!("{}.invocations.log", server_name));
-
-    let _ = fs::remove_file(server_lock);
-    let _ = fs::remove_file(clients_lock);
-    let _ = fs::remove_file(invocations_log);
+    let _ = fs::remove_dir_all(temp_dir.join(server_name));
+    // Flat files from before per-server directories.
+    for file in [
+        "server.json",
+        "server.json.bak",
+        "clients.json",
+        "clients.json.bak",
+        "invocations.log",
+        "watcher.log",
+        "watcher.heartbeat",
+        "exit.json",
+    ] {
+        let _ = fs::remove_file(temp_dir.join(format!("{}.{}", server_name, file)));
+    }
 }
 
 /// Run a command with a timeout and return its output
@@ -68,6 +75,9 @@ fn run_command_with_timeout(args: &[&str], timeout: Duration) -> std::process::O
     let child = Command::new(&binary)
         .args(args)
         .env("SHAREDSERVER_LOCKDIR", &lockdir)
+        // Output is checked for Unicode symbols, which a non-UTF-8 locale
+        // running the tests would turn to ASCII.
+        .env("LC_ALL", "C.UTF-8")
         .stdin(std::process::Stdio::null())
         .stdout(std::process::Stdio::piped())
         .stderr(std::process::Stdio::piped())
@@ -128,8 +138,8 @@ fn test_quick_death_cleanup() {
 
     // CRITICAL: Both lock files must be deleted
     let temp_dir = test_lockdir();
-    let server_lock = temp_dir.join(format!("{}.server.json", server_name));
-    let clients_lock = temp_dir.join(format!("{}.clients.json", server_name));
+    let server_lock = temp_dir.join(server_name).join("server.json");
+    let clients_lock = temp_dir.join(server_name).join("clients.json");
 
     assert!(
         !server_lock.exists(),
@@ -217,8 +227,8 @@ fn test_server_lifecycle() {
 
     // Precondition: the server is actually running.
     let temp_dir = test_lockdir();
-    let server_lock = temp_dir.join(format!("{}.server.json", server_name));
-    let clients_lock = temp_dir.join(format!("{}.clients.json", server_name));
+    let server_lock = temp_dir.join(server_name).join("server.json");
+    let clients_lock = temp_dir.join(server_name).join("clients.json");
     assert!(
         server_lock.exists(),
         "Server lock should exist while running"
@@ -368,7 +378,7 @@ fn test_admin_doctor_stale_lockfile() {
     // watcher now reaps the server and removes the lockfiles itself, so if it
     // were left alive it (not doctor) would do the cleanup.
     let temp_dir = test_lockdir();
-    let server_lock_path = temp_dir.join(format!("{}.server.json", server_name));
+    let server_lock_path = temp_dir.join(server_name).join("server.json");
     let lock_json = fs::read_to_string(&server_lock_path).expect("server lock should exist");
     let extract = |key: &str| -> Option<i32> {
         lock_json
@@ -425,7 +435,7 @@ fn test_admin_doctor_stale_lockfile() {
 
     // Verify lockfiles are actually cleaned up
     let temp_dir = test_lockdir();
-    let server_lock = temp_dir.join(format!("{}.server.json", server_name));
+    let server_lock = temp_dir.join(server_name).join("server.json");
 
     // After doctor runs, server lockfile should be cleaned up
     assert!(
@@ -516,8 +526,8 @@ fn test_admin_kill_command() {
 
     // Verify lockfiles are cleaned up
     let temp_dir = test_lockdir();
-    let server_lock = temp_dir.join(format!("{}.server.json", server_name));
-    let clients_lock = temp_dir.join(format!("{}.clients.json", server_name));
+    let server_lock = temp_dir.join(server_name).join("server.json");
+    let clients_lock = temp_dir.join(server_name).join("clients.json");
 
     assert!(!server_lock.exists(), "Kill should remove server lockfile");
     assert!(
@@ -559,7 +569,7 @@ fn test_stop_force_escalation() {
 
     // Precondition: it's really running.
     let temp_dir = test_lockdir();
-    let server_lock = temp_dir.join(format!("{}.server.json", server_name));
+    let server_lock = temp_dir.join(server_name).join("server.json");
     assert!(server_lock.exists(), "Server lock should exist before stop");
 
     // Plain stop must FAIL (SIGTERM ignored) and not escalate.
@@ -593,7 +603,7 @@ fn test_stop_force_escalation() {
         !server_lock.exists(),
         "Forced stop must remove the server lockfile"
     );
-    let clients_lock = temp_dir.join(format!("{}.clients.json", server_name));
+    let clients_lock = temp_dir.join(server_name).join("clients.json");
     assert!(
         !clients_lock.exists(),
         "Forced stop must remove the clients lockfile"
@@ -638,7 +648,7 @@ fn test_restart_after_force_stop() {
 
     // Locks must be gone immediately after a successful stop.
     let temp_dir = test_lockdir();
-    let server_lock = temp_dir.join(format!("{}.server.json", server_name));
+    let server_lock = temp_dir.join(server_name).join("server.json");
     assert!(
         !server_lock.exists(),
         "Server lockfile must be gone after successful force stop"
@@ -739,7 +749,7 @@ fn test_incref_idempotent_and_grace_keeps_clients_lock() {
         Some(1),
         "after decref to 0 the server should be in grace (exit 1), not stopped"
     );
-    let clients_lock = test_lockdir().join(format!("{}.clients.json", server_name));
+    let clients_lock = test_lockdir().join(server_name).join("clients.json");
     assert!(
         clients_lock.exists(),
         "clients lockfile must persist during grace (H3)"
@@ -787,9 +797,9 @@ fn test_corrupt_server_lock_is_tolerated_and_cleaned() {
     let server_name = "test_corrupt";
     cleanup_lock_files(server_name);
 
-    let lockdir = test_lockdir();
-    let _ = fs::create_dir_all(&lockdir);
-    let server_lock = lockdir.join(format!("{}.server.json", server_name));
+    let server_dir = test_lockdir().join(server_name);
+    let _ = fs::create_dir_all(&server_dir);
+    let server_lock = server_dir.join("server.json");
     fs::write(&server_lock, b"this is not valid json {{{").expect("write corrupt lock");
 
     // `check` must report stopped (exit 2), not crash with a parse error.
@@ -838,3 +848,5457 @@ fn test_admin_kill_already_stopped() {
 
     cleanup_lock_files(server_name);
 }
+
+#[test]
+#[serial]
+fn test_use_replace_restarts_changed_command() {
+    // `use --replace` with a different command must drain the running instance
+    // and start a new one (new PID) while carrying the attached clients over.
+    let server_name = "test_use_replace";
+    cleanup_lock_files(server_name);
+
+    let long_running = get_test_helper_path("long_running.sh");
+    let long_running = long_running.to_str().unwrap();
+    let test_pid = std::process::id().to_string();
+
+    let out = run_command(&["use", server_name, "--pid", &test_pid, "--", long_running]);
+    assert!(
+        out.status.success(),
+        "use should succeed: {}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    let info = run_command(&["info", server_name, "--json"]);
+    let before: serde_json::Value =
+        serde_json::from_slice(&info.stdout).expect("info --json should be valid JSON");
+
+    // Same command: --replace is a no-op attach, the PID must not change.
+    let same = run_command(&[
+        "use",
+        server_name,
+        "--pid",
+        &test_pid,
+        "--replace",
+        "--",
+        long_running,
+    ]);
+    assert!(
+        same.status.success(),
+        "same-command --replace should attach"
+    );
+    let info = run_command(&["info", server_name, "--json"]);
+    let unchanged: serde_json::Value = serde_json::from_slice(&info.stdout).unwrap();
+    assert_eq!(
+        before["pid"], unchanged["pid"],
+        "same command must not restart"
+    );
+
+    // Different command: the server is replaced and the client is kept.
+    let replaced = run_command(&[
+        "use",
+        server_name,
+        "--pid",
+        &test_pid,
+        "--replace",
+        "--",
+        long_running,
+        "v2",
+    ]);
+    assert!(
+        replaced.status.success(),
+        "--replace should succeed: {}",
+        String::from_utf8_lossy(&replaced.stderr)
+    );
+    let info = run_command(&["info", server_name, "--json"]);
+    let after: serde_json::Value = serde_json::from_slice(&info.stdout).unwrap();
+    assert_ne!(
+        before["pid"], after["pid"],
+        "replacement must have a new PID"
+    );
+    assert_eq!(after["command"][1], "v2");
+    assert_eq!(after["refcount"], 1, "client must be carried over");
+
+    run_command(&["admin", "kill", server_name]);
+    thread::sleep(Duration::from_secs(1));
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+#[serial]
+fn test_restart_policy_relaunches_crashed_server() {
+    // With --restart on-failure, a server killed out from under an attached
+    // client is relaunched by the watcher (new PID, restart_count bumped)
+    // instead of the lockfiles being torn down.
+    let server_name = "test_restart_policy";
+    cleanup_lock_files(server_name);
+
+    let long_running = get_test_helper_path("long_running.sh");
+    let test_pid = std::process::id().to_string();
+
+    let out = run_command(&[
+        "use",
+        server_name,
+        "--pid",
+        &test_pid,
+        "--restart",
+        "on-failure",
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert!(
+        out.status.success(),
+        "use should succeed: {}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    let info = run_command(&["info", server_name, "--json"]);
+    let before: serde_json::Value = serde_json::from_slice(&info.stdout).unwrap();
+    let old_pid = before["pid"].as_i64().unwrap() as i32;
+
+    unsafe {
+        libc::kill(old_pid, libc::SIGKILL);
+    }
+    // Crash-loop backoff for a server that died young is ~2s.
+    thread::sleep(Duration::from_secs(5));
+
+    let info = run_command(&["info", server_name, "--json"]);
+    let after: serde_json::Value = serde_json::from_slice(&info.stdout).unwrap();
+    assert_eq!(
+        after["state"], "active",
+        "restarted server should be active"
+    );
+    assert_ne!(
+        after["pid"], before["pid"],
+        "restart must produce a new PID"
+    );
+    assert_eq!(after["restart_count"], 1);
+
+    // A deliberate stop must not be answered with another restart.
+    let stop = run_command(&["admin", "stop", server_name, "--timeout", "8s"]);
+    assert!(
+        stop.status.success(),
+        "stop should converge despite the restart policy: {}",
+        String::from_utf8_lossy(&stop.stderr)
+    );
+
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+#[serial]
+fn test_failing_health_cmd_marks_server_unhealthy() {
+    // A failing --health-cmd must flip the server to unhealthy once the retry
+    // budget is spent: `check` exits 4 and `info --json` reports it.
+    let server_name = "test_health_cmd";
+    cleanup_lock_files(server_name);
+
+    let long_running = get_test_helper_path("long_running.sh");
+    let test_pid = std::process::id().to_string();
+
+    let out = run_command(&[
+        "use",
+        server_name,
+        "--pid",
+        &test_pid,
+        "--health-cmd",
+        "exit 1",
+        "--health-interval",
+        "1s",
+        "--health-retries",
+        "2",
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert!(
+        out.status.success(),
+        "use should succeed: {}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+
+    thread::sleep(Duration::from_secs(4));
+
+    let chk = run_command(&["check", server_name]);
+    assert_eq!(
+        chk.status.code(),
+        Some(4),
+        "unhealthy server should exit 4. stdout: {}",
+        String::from_utf8_lossy(&chk.stdout)
+    );
+    let info = run_command(&["info", server_name, "--json"]);
+    let info: serde_json::Value = serde_json::from_slice(&info.stdout).unwrap();
+    assert_eq!(info["health"], "unhealthy");
+    assert_eq!(
+        info["state"], "active",
+        "health does not change refcount state"
+    );
+
+    run_command(&["admin", "kill", server_name]);
+    thread::sleep(Duration::from_secs(1));
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+#[serial]
+fn test_use_events_json() {
+    // `use --events-json` narrates the attach as JSON lines on stdout, ending
+    // in "attached" (or "error"), with the readiness probe in between.
+    let server_name = "test_use_events_json";
+    cleanup_lock_files(server_name);
+
+    let long_running = get_test_helper_path("long_running.sh");
+    let test_pid = std::process::id().to_string();
+    let events = |out: &std::process::Output| -> Vec<serde_json::Value> {
+        String::from_utf8_lossy(&out.stdout)
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("each line should be JSON"))
+            .collect()
+    };
+
+    let out = run_command(&[
+        "use",
+        server_name,
+        "--events-json",
+        "--pid",
+        &test_pid,
+        "--health-cmd",
+        "true",
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert!(
+        out.status.success(),
+        "use should succeed: {}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    let started = events(&out);
+    let names: Vec<&str> = started
+        .iter()
+        .map(|e| e["event"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        names,
+        ["starting", "pid-assigned", "ready-probe-passed", "attached"]
+    );
+    assert!(started.iter().all(|e| e["name"] == server_name));
+    let pid = &started[1]["pid"];
+    assert!(pid.as_i64().unwrap() > 0);
+    assert_eq!(started[3]["pid"], *pid);
+    assert_eq!(started[3]["action"], "started");
+    assert_eq!(started[3]["refcount"], 1);
+
+    // Attaching to the running server skips straight to readiness.
+    let mut client = Command::new("sleep").arg("60").spawn().unwrap();
+    let client_pid = client.id().to_string();
+    let out = run_command(&["use", server_name, "--events-json", "--pid", &client_pid]);
+    assert!(out.status.success());
+    let attached = events(&out);
+    assert_eq!(attached.last().unwrap()["event"], "attached");
+    assert_eq!(attached.last().unwrap()["action"], "attached");
+    assert_eq!(attached.last().unwrap()["refcount"], 2);
+    let _ = client.kill();
+    let _ = client.wait();
+
+    // Failures are events too, and still set the exit code.
+    let missing = "test_use_events_json_missing";
+    cleanup_lock_files(missing);
+    let out = run_command(&["use", missing, "--events-json"]);
+    assert_eq!(out.status.code(), Some(10));
+    let failed = events(&out);
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0]["event"], "error");
+    assert_eq!(failed[0]["kind"], "not-running");
+    assert_eq!(failed[0]["exit_code"], 10);
+
+    run_command(&["admin", "kill", server_name]);
+    thread::sleep(Duration::from_secs(1));
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+#[serial]
+fn test_shell_hook_detaches_on_exit() {
+    // A shell that evaluates `hook bash` runs `unuse --all --pid $$` as it
+    // exits, releasing every reference it took, and still runs the EXIT trap
+    // it had before.
+    let names = ["test_shell_hook_a", "test_shell_hook_b"];
+    for name in names {
+        cleanup_lock_files(name);
+    }
+
+    let binary = get_binary_path();
+    let long_running = get_test_helper_path("long_running.sh");
+    let script = format!(
+        "trap 'echo prior trap ran' EXIT\n\
+         eval \"$('{bin}' hook bash)\"\n\
+         '{bin}' use {a} --grace-period 30s -- '{cmd}'\n\
+         '{bin}' use {b} --grace-period 30s -- '{cmd}'\n\
+         '{bin}' check {a} && '{bin}' check {b} || exit 1\n\
+         __sharedserver_detach\n\
+         '{bin}' check {a} --quiet || echo detached while alive\n",
+        bin = binary.display(),
+        a = names[0],
+        b = names[1],
+        cmd = long_running.display(),
+    );
+    // A shell without the hook is told about it when it first attaches.
+    let unhooked = format!(
+        "'{bin}' use {a} --grace-period 30s -- '{cmd}'\n'{bin}' unuse {a} --pid $$\n",
+        bin = binary.display(),
+        a = names[0],
+        cmd = long_running.display(),
+    );
+    let out = Command::new("bash")
+        .arg("-c")
+        .arg(&unhooked)
+        .env("SHAREDSERVER_LOCKDIR", test_lockdir())
+        .env_remove("SHAREDSERVER_HOOK")
+        .output()
+        .expect("Failed to run bash");
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(out.status.success(), "{}", stdout);
+    assert!(
+        stdout.contains("eval \"$(sharedserver hook bash)\""),
+        "{}",
+        stdout
+    );
+
+    let out = Command::new("bash")
+        .arg("-c")
+        .arg(&script)
+        .env("SHAREDSERVER_LOCKDIR", test_lockdir())
+        .output()
+        .expect("Failed to run bash");
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(
+        out.status.success(),
+        "both servers should have been active while the shell ran: {}{}",
+        stdout,
+        String::from_utf8_lossy(&out.stderr)
+    );
+    assert!(
+        !stdout.contains("hook bash"),
+        "the hook shouldn't be suggested to a shell that has it: {}",
+        stdout
+    );
+    // The watcher drops dead clients promptly too, so check the trap's
+    // command works while the shell is still alive.
+    assert!(stdout.contains("detached while alive"), "{}", stdout);
+    assert!(stdout.ends_with("prior trap ran\n"), "{}", stdout);
+
+    for name in names {
+        let info = run_command(&["info", name, "--json"]);
+        let info: serde_json::Value = serde_json::from_slice(&info.stdout).unwrap();
+        assert_eq!(
+            info["state"], "grace",
+            "{} should have lost its client",
+            name
+        );
+        assert_eq!(info["refcount"], 0);
+    }
+
+    // Nothing left to release is not an error.
+    let out = run_command(&["unuse", "--all", "--pid", "1"]);
+    assert!(out.status.success());
+
+    for name in names {
+        run_command(&["admin", "kill", name]);
+    }
+    thread::sleep(Duration::from_secs(1));
+    for name in names {
+        cleanup_lock_files(name);
+    }
+}
+
+#[test]
+#[serial]
+fn test_direnv_holds_while_in_directory() {
+    // `direnv` exports the server's address and holds a reference (through
+    // a background holder, reused on reload) until the shell leaves the
+    // directory.
+    let server_name = "test_direnv";
+    cleanup_lock_files(server_name);
+
+    let binary = get_binary_path();
+    let long_running = get_test_helper_path("long_running.sh");
+    let project = env::temp_dir().join("sharedserver-inttest-direnv");
+    let _ = fs::create_dir_all(project.join("sub"));
+    let envrc = format!(
+        "eval \"$('{bin}' direnv {name} --address 5599 -- '{cmd}')\"",
+        bin = binary.display(),
+        name = server_name,
+        cmd = long_running.display(),
+    );
+    let script = format!(
+        "cd '{dir}' || exit 1\n\
+         {envrc} || exit 1\n\
+         echo \"port=$SHAREDSERVER_TEST_DIRENV_PORT\"\n\
+         {envrc} || exit 1\n\
+         cd sub\n\
+         sleep 2\n\
+         '{bin}' info {name} --json > '{dir}/inside.json'\n\
+         cd /\n\
+         sleep 2.5\n\
+         '{bin}' info {name} --json > '{dir}/outside.json'\n",
+        dir = project.display(),
+        bin = binary.display(),
+        name = server_name,
+    );
+    let out = Command::new("bash")
+        .arg("-c")
+        .arg(&script)
+        .env("SHAREDSERVER_LOCKDIR", test_lockdir())
+        .output()
+        .expect("Failed to run bash");
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(
+        out.status.success(),
+        "{}{}",
+        stdout,
+        String::from_utf8_lossy(&out.stderr)
+    );
+    assert!(stdout.contains("port=5599"), "{}", stdout);
+
+    let read = |file: &str| -> serde_json::Value {
+        serde_json::from_slice(&fs::read(project.join(file)).unwrap()).unwrap()
+    };
+    let inside = read("inside.json");
+    assert_eq!(inside["state"], "active");
+    assert_eq!(inside["refcount"], 1, "a reload should reuse the holder");
+    let outside = read("outside.json");
+    assert_eq!(outside["state"], "grace", "{}", outside);
+    assert_eq!(outside["refcount"], 0);
+
+    run_command(&["admin", "kill", server_name]);
+    thread::sleep(Duration::from_secs(1));
+    cleanup_lock_files(server_name);
+    let _ = fs::remove_dir_all(&project);
+}
+
+#[test]
+#[serial]
+fn test_tmux_attach_tails_log_and_holds() {
+    // `tmux-attach` opens one window per server tailing its log, finds it
+    // again on the next call, and with --hold the pane keeps a reference
+    // until it is closed.
+    if Command::new("tmux").arg("-V").output().is_err() {
+        eprintln!("tmux is not installed; skipping");
+        return;
+    }
+    let server_name = "test_tmux_attach";
+    cleanup_lock_files(server_name);
+
+    // A tmux server of our own, away from the user's.
+    let tmux_dir = env::temp_dir().join("sharedserver-inttest-tmux");
+    let _ = fs::create_dir_all(&tmux_dir);
+    let tmux = |args: &[&str]| {
+        Command::new("tmux")
+            .args(args)
+            .env("TMUX_TMPDIR", &tmux_dir)
+            .env_remove("TMUX")
+            .output()
+            .expect("Failed to run tmux")
+    };
+    let attach = |args: &[&str]| {
+        Command::new(get_binary_path())
+            .arg("tmux-attach")
+            .arg(server_name)
+            .args(args)
+            .env("SHAREDSERVER_LOCKDIR", test_lockdir())
+            .env("TMUX_TMPDIR", &tmux_dir)
+            .env_remove("TMUX")
+            .output()
+            .expect("Failed to run tmux-attach")
+    };
+
+    let log = test_lockdir().join("test_tmux_attach.log");
+    let long_running = get_test_helper_path("long_running.sh");
+    let mut client = Command::new("sleep").arg("60").spawn().unwrap();
+    let client_pid = client.id().to_string();
+    let out = run_command(&[
+        "use",
+        server_name,
+        "--pid",
+        &client_pid,
+        "--log-file",
+        log.to_str().unwrap(),
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert!(out.status.success());
+    assert!(tmux(&["new-session", "-d", "-s", "inttest"])
+        .status
+        .success());
+
+    let out = attach(&["--hold"]);
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    let out = attach(&[]);
+    assert!(out.status.success());
+    assert!(String::from_utf8_lossy(&out.stdout).contains("Switched"));
+
+    let windows = tmux(&[
+        "list-windows",
+        "-a",
+        "-F",
+        "#{window_name} #{pane_current_command}",
+    ]);
+    let windows = String::from_utf8_lossy(&windows.stdout);
+    assert_eq!(
+        windows
+            .lines()
+            .filter(|w| w.starts_with(server_name))
+            .count(),
+        1,
+        "one window for the server: {}",
+        windows
+    );
+    let info = run_command(&["info", server_name, "--json"]);
+    let info: serde_json::Value = serde_json::from_slice(&info.stdout).unwrap();
+    assert_eq!(info["refcount"], 2, "the pane should hold a reference");
+
+    tmux(&["kill-window", "-t", &format!("inttest:{}", server_name)]);
+    thread::sleep(Duration::from_secs(2));
+    let info = run_command(&["info", server_name, "--json"]);
+    let info: serde_json::Value = serde_json::from_slice(&info.stdout).unwrap();
+    assert_eq!(info["refcount"], 1, "closing the pane should release it");
+
+    tmux(&["kill-server"]);
+    let _ = client.kill();
+    let _ = client.wait();
+    run_command(&["admin", "kill", server_name]);
+    thread::sleep(Duration::from_secs(1));
+    cleanup_lock_files(server_name);
+    let _ = fs::remove_file(&log);
+    let _ = fs::remove_dir_all(&tmux_dir);
+}
+
+#[test]
+#[serial]
+fn test_dead_client_enters_grace_promptly() {
+    // The watcher holds an exit watch on each client, so a client that dies
+    // drops the refcount to 0 (grace) right away rather than on the next poll.
+    let server_name = "test_client_exit_event";
+    cleanup_lock_files(server_name);
+
+    let long_running = get_test_helper_path("long_running.sh");
+    let mut client = Command::new("sleep").arg("60").spawn().unwrap();
+    let client_pid = client.id().to_string();
+
+    let out = run_command(&[
+        "use",
+        server_name,
+        "--pid",
+        &client_pid,
+        "--grace-period",
+        "30s",
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert!(
+        out.status.success(),
+        "use should succeed: {}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    // Let the watcher pick up the client.
+    thread::sleep(Duration::from_secs(1));
+    assert_eq!(run_command(&["check", server_name]).status.code(), Some(0));
+
+    client.kill().unwrap();
+    client.wait().unwrap();
+    let killed_at = std::time::Instant::now();
+    while run_command(&["check", server_name]).status.code() != Some(1) {
+        assert!(
+            killed_at.elapsed() < Duration::from_secs(5),
+            "server never entered grace after its only client died"
+        );
+        thread::sleep(Duration::from_millis(20));
+    }
+    assert!(
+        killed_at.elapsed() < Duration::from_millis(400),
+        "grace should start as soon as the client dies, took {:?}",
+        killed_at.elapsed()
+    );
+
+    run_command(&["admin", "kill", server_name]);
+    thread::sleep(Duration::from_secs(1));
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+#[serial]
+fn test_decref_starts_short_grace_immediately() {
+    // The watcher watches the clients lock, so a decref starts the grace timer
+    // at once and a 1s grace period ends the server after ~1s, not ~1s plus
+    // up to two poll intervals.
+    let server_name = "test_decref_event";
+    cleanup_lock_files(server_name);
+
+    let long_running = get_test_helper_path("long_running.sh");
+    let test_pid = std::process::id().to_string();
+
+    let out = run_command(&[
+        "use",
+        server_name,
+        "--pid",
+        &test_pid,
+        "--grace-period",
+        "1s",
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert!(
+        out.status.success(),
+        "use should succeed: {}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    thread::sleep(Duration::from_secs(1));
+
+    let dec = run_command(&["admin", "decref", server_name, "--pid", &test_pid]);
+    assert!(dec.status.success());
+    let decref_at = std::time::Instant::now();
+    while run_command(&["check", server_name]).status.code() != Some(2) {
+        assert!(
+            decref_at.elapsed() < Duration::from_secs(5),
+            "server never stopped after its grace period"
+        );
+        thread::sleep(Duration::from_millis(20));
+    }
+    assert!(
+        decref_at.elapsed() < Duration::from_millis(1400),
+        "grace should start at the decref, took {:?} to stop",
+        decref_at.elapsed()
+    );
+
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+#[serial]
+fn test_watcher_heartbeat_reported_and_cleaned_up() {
+    // The watcher heartbeats while it loops; `info --json` reports it as fresh,
+    // and the heartbeat file goes away with the lockfiles on teardown.
+    let server_name = "test_heartbeat";
+    cleanup_lock_files(server_name);
+
+    let long_running = get_test_helper_path("long_running.sh");
+    let test_pid = std::process::id().to_string();
+
+    let out = run_command(&[
+        "use",
+        server_name,
+        "--pid",
+        &test_pid,
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert!(
+        out.status.success(),
+        "use should succeed: {}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    thread::sleep(Duration::from_secs(2));
+
+    let info = run_command(&["info", server_name, "--json"]);
+    let info: serde_json::Value = serde_json::from_slice(&info.stdout).unwrap();
+    assert!(
+        info["watcher_heartbeat"].is_string(),
+        "watcher should have heartbeat: {}",
+        info
+    );
+    assert!(info["watcher_heartbeat_age_secs"].as_u64().unwrap() <= 2);
+    assert_eq!(info["watcher_stale"], false);
+
+    let heartbeat = test_lockdir().join(server_name).join("watcher.heartbeat");
+    assert!(heartbeat.exists());
+
+    let stop = run_command(&["admin", "stop", server_name]);
+    assert!(
+        stop.status.success(),
+        "stop should succeed: {}",
+        String::from_utf8_lossy(&stop.stderr)
+    );
+    assert!(
+        !heartbeat.exists(),
+        "heartbeat must be removed along with the lockfiles"
+    );
+
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+#[serial]
+fn test_last_records_exit_reason() {
+    // The watcher writes <name>.exit.json when the server goes down, and
+    // `last` reports why: a non-zero exit is a crash, a `stop` is a stop.
+    let server_name = "test_last_exit";
+    cleanup_lock_files(server_name);
+
+    let missing = run_command(&["last", server_name]);
+    assert!(
+        !missing.status.success(),
+        "no record yet should be an error"
+    );
+
+    let test_pid = std::process::id().to_string();
+    let out = run_command(&[
+        "use",
+        server_name,
+        "--pid",
+        &test_pid,
+        "--",
+        "sleep 1; exit 3",
+    ]);
+    assert!(
+        out.status.success(),
+        "use should succeed: {}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    thread::sleep(Duration::from_secs(3));
+
+    let last = run_command(&["last", server_name, "--json"]);
+    assert!(last.status.success());
+    let last: serde_json::Value = serde_json::from_slice(&last.stdout).unwrap();
+    assert_eq!(last["reason"], "crashed");
+    assert_eq!(last["exit"]["kind"], "exited");
+    assert_eq!(last["exit"]["code"], 3);
+
+    let info = run_command(&["info", server_name, "--json"]);
+    let info: serde_json::Value = serde_json::from_slice(&info.stdout).unwrap();
+    assert_eq!(info["state"], "stopped");
+    assert_eq!(info["last_exit"]["reason"], "crashed");
+
+    let long_running = get_test_helper_path("long_running.sh");
+    let out = run_command(&[
+        "use",
+        server_name,
+        "--pid",
+        &test_pid,
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert!(out.status.success());
+    thread::sleep(Duration::from_secs(1));
+    let stop = run_command(&["admin", "stop", server_name]);
+    assert!(stop.status.success());
+
+    let last = run_command(&["last", server_name, "--json"]);
+    let last: serde_json::Value = serde_json::from_slice(&last.stdout).unwrap();
+    assert_eq!(last["reason"], "stopped");
+    assert_eq!(last["exit"]["kind"], "signaled");
+
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+#[serial]
+fn test_memory_limit_stop_action() {
+    // A server over --memory-limit for the whole sustain window is taken down
+    // when the action is "stop", and the exit is recorded as a resource limit.
+    let server_name = "test_memory_limit";
+    cleanup_lock_files(server_name);
+
+    let long_running = get_test_helper_path("long_running.sh");
+    let test_pid = std::process::id().to_string();
+
+    let out = run_command(&[
+        "use",
+        server_name,
+        "--pid",
+        &test_pid,
+        "--memory-limit",
+        "1K",
+        "--memory-action",
+        "stop",
+        "--limit-sustained",
+        "1s",
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert!(
+        out.status.success(),
+        "use should succeed: {}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+
+    thread::sleep(Duration::from_secs(1));
+    let info = run_command(&["info", server_name, "--json"]);
+    let info: serde_json::Value = serde_json::from_slice(&info.stdout).unwrap();
+    assert!(
+        info["resources"]["rss_bytes"].as_u64().unwrap() > 1024,
+        "watcher should record a memory sample: {}",
+        info
+    );
+
+    // Samples are 5s apart, so the breach is sustained at the second one.
+    thread::sleep(Duration::from_secs(6));
+    assert_eq!(
+        run_command(&["check", server_name]).status.code(),
+        Some(2),
+        "server over its memory limit should have been stopped"
+    );
+    let last = run_command(&["last", server_name, "--json"]);
+    let last: serde_json::Value = serde_json::from_slice(&last.stdout).unwrap();
+    assert_eq!(last["reason"], "resource-limit");
+
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+fn test_log_timestamps_prefixes_each_line() {
+    // With --log-timestamps the server's output reaches the log through a
+    // relay that stamps every line and tags which stream it came from.
+    let server_name = "test_log_timestamps";
+    cleanup_lock_files(server_name);
+    let log_path = test_lockdir().join(format!("{}.log", server_name));
+    let _ = fs::remove_file(&log_path);
+
+    let out = run_command(&[
+        "admin",
+        "start",
+        server_name,
+        "--log-file",
+        log_path.to_str().unwrap(),
+        "--log-timestamps",
+        "--",
+        "echo hello; echo oops >&2; printf partial; sleep 30",
+    ]);
+    assert!(
+        out.status.success(),
+        "start should succeed: {}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    thread::sleep(Duration::from_secs(1));
+    run_command(&["admin", "stop", server_name]);
+    // The relay flushes the unterminated line once the pipes close.
+    thread::sleep(Duration::from_millis(500));
+
+    let log = fs::read_to_string(&log_path).expect("log file should exist");
+    let lines: Vec<&str> = log.lines().collect();
+    for (line, expected) in
+        lines
+            .iter()
+            .zip(["[stdout] hello", "[stderr] oops", "[stdout] partial"])
+    {
+        let (stamp, rest) = line.split_once(' ').expect("line should be stamped");
+        assert!(
+            chrono::DateTime::parse_from_rfc3339(stamp).is_ok(),
+            "bad timestamp in {:?}",
+            line
+        );
+        assert_eq!(rest, expected);
+    }
+    assert_eq!(lines.len(), 3, "unexpected log contents: {:?}", log);
+
+    let _ = fs::remove_file(&log_path);
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+fn test_grace_deadline_recorded_and_cleared() {
+    // The watcher publishes when grace will end, so info/list/check can show
+    // a countdown, and clears it again when a client attaches.
+    let server_name = "test_grace_deadline";
+    cleanup_lock_files(server_name);
+
+    let long_running = get_test_helper_path("long_running.sh");
+    let out = run_command(&[
+        "admin",
+        "start",
+        server_name,
+        "--grace-period",
+        "1h",
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert!(
+        out.status.success(),
+        "start should succeed: {}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    thread::sleep(Duration::from_secs(1));
+
+    let info = run_command(&["info", server_name, "--json"]);
+    let info: serde_json::Value = serde_json::from_slice(&info.stdout).unwrap();
+    assert_eq!(info["state"], "grace");
+    assert!(info["grace_deadline"].is_string(), "deadline not recorded");
+    let remaining = info["grace_remaining_secs"].as_u64().unwrap();
+    assert!(
+        (3500..=3600).contains(&remaining),
+        "remaining {}",
+        remaining
+    );
+    assert!(info["uptime_secs"].is_u64());
+
+    let table = run_command(&["info", server_name]);
+    let table = String::from_utf8_lossy(&table.stdout);
+    assert!(table.contains("Shutting down in: 59m"), "{}", table);
+    assert!(table.contains("(up "), "{}", table);
+
+    let check = run_command(&["check", server_name]);
+    assert_eq!(check.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&check.stdout).contains("shutting down in"));
+
+    let test_pid = std::process::id().to_string();
+    let inc = run_command(&["admin", "incref", server_name, "--pid", &test_pid]);
+    assert!(inc.status.success());
+    thread::sleep(Duration::from_secs(1));
+
+    let info = run_command(&["info", server_name, "--json"]);
+    let info: serde_json::Value = serde_json::from_slice(&info.stdout).unwrap();
+    assert_eq!(info["state"], "active");
+    assert!(info["grace_deadline"].is_null(), "deadline not cleared");
+
+    run_command(&["admin", "stop", server_name]);
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+fn test_notify_clients_before_stop() {
+    // With --notify-clients, `stop` signals every attached client before it
+    // takes the server down.
+    let server_name = "test_notify_clients";
+    cleanup_lock_files(server_name);
+    let marker = test_lockdir().join(format!("{}.notified", server_name));
+    let _ = fs::remove_file(&marker);
+
+    // A stand-in editor that records SIGUSR1 and exits.
+    let mut client = Command::new("bash")
+        .arg("-c")
+        .arg(format!(
+            "trap 'touch {}; exit 0' USR1; while true; do sleep 0.1; done",
+            marker.display()
+        ))
+        .spawn()
+        .expect("failed to spawn client");
+    let client_pid = client.id().to_string();
+
+    let long_running = get_test_helper_path("long_running.sh");
+    let out = run_command(&[
+        "use",
+        server_name,
+        "--pid",
+        &client_pid,
+        "--notify-clients",
+        "SIGUSR1",
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert!(
+        out.status.success(),
+        "use should succeed: {}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    thread::sleep(Duration::from_secs(1));
+
+    let stop = run_command(&["admin", "stop", server_name]);
+    assert!(stop.status.success());
+
+    let status = client.wait().expect("client should exit");
+    assert!(status.success(), "client should exit via its USR1 trap");
+    assert!(marker.exists(), "client was never notified");
+
+    let _ = fs::remove_file(&marker);
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+fn test_notify_clients_rejects_bad_signal() {
+    let server_name = "test_notify_bad_signal";
+    cleanup_lock_files(server_name);
+
+    let out = run_command(&[
+        "admin",
+        "start",
+        server_name,
+        "--notify-clients",
+        "SIGNOPE",
+        "--",
+        "sleep 30",
+    ]);
+    assert!(!out.status.success(), "bad signal should be rejected");
+    assert!(String::from_utf8_lossy(&out.stderr).contains("Invalid signal"));
+
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+fn test_sd_notify_ready_watchdog_stopping() {
+    // Launched with NOTIFY_SOCKET set (as under a systemd Type=notify unit),
+    // the watcher reports READY=1 with itself as MAINPID, pets the watchdog,
+    // and reports STOPPING=1 on teardown. The server doesn't see the socket.
+    use std::os::unix::net::UnixDatagram;
+
+    let server_name = "test_sd_notify";
+    cleanup_lock_files(server_name);
+    let socket_path = test_lockdir().join(format!("{}.notify.sock", server_name));
+    let marker = test_lockdir().join(format!("{}.env", server_name));
+    let _ = fs::remove_file(&socket_path);
+    let _ = fs::remove_file(&marker);
+    let _ = fs::create_dir_all(test_lockdir());
+    let socket = UnixDatagram::bind(&socket_path).expect("bind notify socket");
+    socket
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+
+    let out = Command::new(get_binary_path())
+        .args([
+            "admin",
+            "start",
+            server_name,
+            "--",
+            &format!(
+                "echo ${{NOTIFY_SOCKET:-unset}} > {}; sleep 30",
+                marker.display()
+            ),
+        ])
+        .env("SHAREDSERVER_LOCKDIR", test_lockdir())
+        .env("NOTIFY_SOCKET", &socket_path)
+        .env("WATCHDOG_USEC", "2000000")
+        .output()
+        .expect("failed to run start");
+    assert!(
+        out.status.success(),
+        "start should succeed: {}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+
+    let mut buf = [0u8; 256];
+    let n = socket.recv(&mut buf).expect("no READY notification");
+    let ready = String::from_utf8_lossy(&buf[..n]).to_string();
+    let info = run_command(&["info", server_name, "--json"]);
+    let info: serde_json::Value = serde_json::from_slice(&info.stdout).unwrap();
+    assert_eq!(
+        ready,
+        format!("READY=1\nMAINPID={}", info["watcher_pid"]),
+        "READY should name the watcher"
+    );
+
+    let n = socket.recv(&mut buf).expect("no watchdog ping");
+    assert_eq!(&buf[..n], b"WATCHDOG=1");
+
+    let stop = run_command(&["admin", "stop", server_name]);
+    assert!(stop.status.success());
+    let mut saw_stopping = false;
+    while let Ok(n) = socket.recv(&mut buf) {
+        if &buf[..n] == b"STOPPING=1" {
+            saw_stopping = true;
+            break;
+        }
+    }
+    assert!(saw_stopping, "no STOPPING notification");
+
+    let env_seen = fs::read_to_string(&marker).expect("server should have run");
+    assert_eq!(env_seen.trim(), "unset", "server inherited NOTIFY_SOCKET");
+
+    let _ = fs::remove_file(&socket_path);
+    let _ = fs::remove_file(&marker);
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+fn test_watcher_event_log() {
+    // The watcher records what it sees and decides in <name>.watcher.log,
+    // shown by `admin debug --watcher`.
+    let server_name = "test_watcher_events";
+    cleanup_lock_files(server_name);
+
+    let long_running = get_test_helper_path("long_running.sh");
+    let out = run_command(&[
+        "admin",
+        "start",
+        server_name,
+        "--grace-period",
+        "1s",
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert!(
+        out.status.success(),
+        "start should succeed: {}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    // No clients: grace starts, expires, and the watcher takes it down.
+    let started = std::time::Instant::now();
+    while run_command(&["check", server_name]).status.code() != Some(2) {
+        assert!(
+            started.elapsed() < Duration::from_secs(10),
+            "server never stopped after its grace period"
+        );
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    let log = fs::read_to_string(test_lockdir().join(server_name).join("watcher.log"))
+        .expect("watcher log should exist");
+    let events: Vec<String> = log
+        .lines()
+        .map(|line| {
+            let event: serde_json::Value = serde_json::from_str(line).expect("JSONL");
+            event["event"].as_str().unwrap().to_string()
+        })
+        .collect();
+    for expected in [
+        "started",
+        "grace-start",
+        "grace-expired",
+        "terminate",
+        "server-exit",
+        "exit",
+    ] {
+        assert!(
+            events.iter().any(|e| e == expected),
+            "missing {} in {:?}",
+            expected,
+            events
+        );
+    }
+
+    let debug = run_command(&["admin", "debug", server_name, "--watcher"]);
+    assert!(debug.status.success());
+    assert!(String::from_utf8_lossy(&debug.stdout).contains("grace-expired"));
+
+    cleanup_lock_files(server_name);
+}
+
+/// Wait up to 5s for `pid` to exit: gone, or a zombie nobody has reaped yet.
+fn wait_for_exit(pid: i32) -> bool {
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    while std::time::Instant::now() < deadline {
+        let zombie = fs::read_to_string(format!("/proc/{}/stat", pid))
+            .ok()
+            .and_then(|stat| Some(stat.rsplit_once(')')?.1.trim_start().starts_with('Z')));
+        if unsafe { libc::kill(pid, 0) } != 0 || zombie == Some(true) {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    false
+}
+
+/// Read the watcher PID recorded for a running server.
+fn watcher_pid_of(server_name: &str) -> i32 {
+    let info = run_command(&["info", server_name, "--json"]);
+    let info: serde_json::Value = serde_json::from_slice(&info.stdout).unwrap();
+    info["watcher_pid"].as_i64().expect("watcher_pid") as i32
+}
+
+#[test]
+fn test_watcher_sigterm_stops_server() {
+    // A watcher sent SIGTERM (logout, shutdown) takes its server down and
+    // cleans up instead of dying with the lockfiles left behind.
+    let server_name = "test_watcher_sigterm";
+    cleanup_lock_files(server_name);
+
+    let long_running = get_test_helper_path("long_running.sh");
+    let test_pid = std::process::id().to_string();
+    let out = run_command(&[
+        "use",
+        server_name,
+        "--pid",
+        &test_pid,
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert!(out.status.success());
+    thread::sleep(Duration::from_secs(1));
+
+    unsafe {
+        libc::kill(watcher_pid_of(server_name), libc::SIGTERM);
+    }
+    let sent = std::time::Instant::now();
+    while run_command(&["check", server_name]).status.code() != Some(2) {
+        assert!(
+            sent.elapsed() < Duration::from_secs(10),
+            "server not stopped after watcher SIGTERM"
+        );
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    let last = run_command(&["last", server_name, "--json"]);
+    let last: serde_json::Value = serde_json::from_slice(&last.stdout).unwrap();
+    assert_eq!(last["reason"], "shutdown");
+
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+fn test_watcher_sigterm_linger_hands_off() {
+    // With --linger the server outlives its watcher; the lock records that it
+    // is unsupervised and `stop` can still take it down.
+    let server_name = "test_watcher_linger";
+    cleanup_lock_files(server_name);
+
+    let long_running = get_test_helper_path("long_running.sh");
+    let test_pid = std::process::id().to_string();
+    let out = run_command(&[
+        "use",
+        server_name,
+        "--pid",
+        &test_pid,
+        "--linger",
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert!(out.status.success());
+    thread::sleep(Duration::from_secs(1));
+
+    let watcher_pid = watcher_pid_of(server_name);
+    unsafe {
+        libc::kill(watcher_pid, libc::SIGTERM);
+    }
+    let sent = std::time::Instant::now();
+    while unsafe { libc::kill(watcher_pid, 0) } == 0 {
+        assert!(
+            sent.elapsed() < Duration::from_secs(5),
+            "watcher did not exit"
+        );
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    let info = run_command(&["info", server_name, "--json"]);
+    let info: serde_json::Value = serde_json::from_slice(&info.stdout).unwrap();
+    assert_eq!(info["state"], "active", "server should still be running");
+    assert!(info["watcher_pid"].is_null(), "lock should show no watcher");
+
+    let stop = run_command(&["admin", "stop", server_name]);
+    assert!(
+        stop.status.success(),
+        "stop should work without a watcher: {}",
+        String::from_utf8_lossy(&stop.stderr)
+    );
+    assert_eq!(run_command(&["check", server_name]).status.code(), Some(2));
+
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+fn test_incref_refused_once_shutdown_committed() {
+    // Once the watcher has committed to a grace-expiry shutdown (bumped
+    // shutdown_generation under the clients lock), a late rescue must fail
+    // with a retry error rather than attach to a server about to die.
+    let server_name = "test_shutdown_generation";
+    cleanup_lock_files(server_name);
+
+    let long_running = get_test_helper_path("long_running.sh");
+    let out = run_command(&[
+        "admin",
+        "start",
+        server_name,
+        "--grace-period",
+        "1h",
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert!(out.status.success());
+    thread::sleep(Duration::from_secs(1));
+
+    let clients_path = test_lockdir().join(server_name).join("clients.json");
+    let mut clients: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&clients_path).unwrap()).unwrap();
+    clients["shutdown_generation"] = serde_json::json!(1);
+    // Swap the file in whole: the watcher may read it at any moment, and a
+    // half-written lock would be recovered from its backup, undoing this.
+    let staged = clients_path.with_extension("json.tmp");
+    fs::write(&staged, clients.to_string()).unwrap();
+    fs::rename(&staged, &clients_path).unwrap();
+
+    let test_pid = std::process::id().to_string();
+    let inc = run_command(&["admin", "incref", server_name, "--pid", &test_pid]);
+    assert!(!inc.status.success(), "incref should be refused");
+    assert!(
+        String::from_utf8_lossy(&inc.stderr).contains("shutting down, retry"),
+        "stderr: {}",
+        String::from_utf8_lossy(&inc.stderr)
+    );
+
+    let _ = run_command(&["admin", "kill", server_name]);
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+fn test_grace_clock_option() {
+    let server_name = "test_grace_clock";
+    cleanup_lock_files(server_name);
+
+    let long_running = get_test_helper_path("long_running.sh");
+    let bad = run_command(&[
+        "admin",
+        "start",
+        server_name,
+        "--grace-clock",
+        "sundial",
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert!(!bad.status.success(), "unknown clock should be rejected");
+    assert!(String::from_utf8_lossy(&bad.stderr).contains("Invalid grace clock"));
+
+    let out = run_command(&[
+        "admin",
+        "start",
+        server_name,
+        "--grace-period",
+        "1h",
+        "--grace-clock",
+        "wall",
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert!(out.status.success());
+    thread::sleep(Duration::from_secs(1));
+
+    let info = run_command(&["info", server_name, "--json"]);
+    let info: serde_json::Value = serde_json::from_slice(&info.stdout).unwrap();
+    assert_eq!(info["grace_clock"], "wall");
+    assert!(info["grace_deadline"].is_string(), "deadline not recorded");
+
+    let _ = run_command(&["admin", "kill", server_name]);
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+fn test_lock_from_previous_boot_is_stale() {
+    // A lock left behind by an earlier boot names PIDs that mean nothing now.
+    // Point it at a live process (this test) to prove the boot ID, not
+    // liveness, is what marks it stale.
+    let server_name = "test_previous_boot";
+    cleanup_lock_files(server_name);
+
+    let me = std::process::id();
+    let lock = serde_json::json!({
+        "pid": me,
+        "command": ["sleep", "1000"],
+        "grace_period": "5m",
+        "watcher_pid": me,
+        "started_at": "2020-01-01T00:00:00Z",
+        "boot_id": "00000000-0000-0000-0000-000000000000",
+    });
+    let dir = test_lockdir();
+    fs::create_dir_all(dir.join(server_name)).unwrap();
+    fs::write(dir.join(server_name).join("server.json"), lock.to_string()).unwrap();
+    fs::write(
+        dir.join(server_name).join("clients.json"),
+        r#"{"refcount":0,"clients":{}}"#,
+    )
+    .unwrap();
+
+    let check = run_command(&["check", server_name]);
+    assert_eq!(check.status.code(), Some(2), "lock should read as stopped");
+
+    let doctor = run_command(&["admin", "doctor", server_name]);
+    assert!(String::from_utf8_lossy(&doctor.stdout).contains("previous boot"));
+    assert!(!dir.join(server_name).join("server.json").exists());
+
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+fn test_registry_tracks_start_and_stop() {
+    // `list` reads registry.json instead of every lockfile; it must follow
+    // starts, refcount changes, and teardown.
+    let server_name = "test_registry_index";
+    cleanup_lock_files(server_name);
+
+    let registry_entry = || -> Option<serde_json::Value> {
+        let registry = fs::read_to_string(test_lockdir().join("registry.json")).ok()?;
+        let registry: serde_json::Value = serde_json::from_str(&registry).ok()?;
+        Some(registry["servers"][server_name].clone()).filter(|entry| !entry.is_null())
+    };
+
+    let long_running = get_test_helper_path("long_running.sh");
+    let out = run_command(&[
+        "admin",
+        "start",
+        server_name,
+        "--grace-period",
+        "1h",
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert!(out.status.success());
+    thread::sleep(Duration::from_secs(1));
+
+    let entry = registry_entry().expect("started server should be registered");
+    let info = run_command(&["info", server_name, "--json"]);
+    let info: serde_json::Value = serde_json::from_slice(&info.stdout).unwrap();
+    assert_eq!(entry["server"]["pid"], info["pid"]);
+
+    let test_pid = std::process::id().to_string();
+    let inc = run_command(&["admin", "incref", server_name, "--pid", &test_pid]);
+    assert!(inc.status.success());
+    assert_eq!(registry_entry().unwrap()["clients"]["refcount"], 1);
+
+    let list = run_command(&["list", "--json"]);
+    let list: serde_json::Value = serde_json::from_slice(&list.stdout).unwrap();
+    let listed = list
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|s| s["name"] == server_name)
+        .expect("server listed");
+    assert_eq!(listed["state"], "active");
+    assert_eq!(listed["refcount"], 1);
+
+    let stop = run_command(&["admin", "stop", server_name]);
+    assert!(stop.status.success());
+    assert!(
+        registry_entry().is_none(),
+        "stopped server still registered"
+    );
+
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+fn test_lock_timeout_reports_holder() {
+    // A process sitting on a lock must not hang every other command: they
+    // give up after SHAREDSERVER_LOCK_TIMEOUT and say who holds it.
+    use std::os::unix::io::AsRawFd;
+
+    let server_name = "test_lock_timeout";
+    cleanup_lock_files(server_name);
+
+    let long_running = get_test_helper_path("long_running.sh");
+    let out = run_command(&[
+        "admin",
+        "start",
+        server_name,
+        "--grace-period",
+        "1h",
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert!(out.status.success());
+    thread::sleep(Duration::from_secs(1));
+
+    let clients_path = test_lockdir().join(server_name).join("clients.json");
+    let held = fs::File::open(&clients_path).unwrap();
+    assert_eq!(unsafe { libc::flock(held.as_raw_fd(), libc::LOCK_EX) }, 0);
+
+    let test_pid = std::process::id().to_string();
+    let started = std::time::Instant::now();
+    let inc = Command::new(get_binary_path())
+        .args(["admin", "incref", server_name, "--pid", &test_pid])
+        .env("SHAREDSERVER_LOCKDIR", test_lockdir())
+        .env("SHAREDSERVER_LOCK_TIMEOUT", "1s")
+        .output()
+        .unwrap();
+    assert!(started.elapsed() < Duration::from_secs(4), "incref hung");
+    assert!(!inc.status.success());
+    let stderr = String::from_utf8_lossy(&inc.stderr);
+    assert!(stderr.contains("lock held by"), "stderr: {}", stderr);
+    #[cfg(target_os = "linux")]
+    assert!(
+        stderr.contains(&format!("PID {}", test_pid)),
+        "stderr: {}",
+        stderr
+    );
+
+    drop(held);
+    let _ = run_command(&["admin", "kill", server_name]);
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+fn test_corrupt_clients_lock_recovered() {
+    // A corrupt clients lock reads from its last good copy, and `doctor
+    // --restore` rebuilds it from the invocation log when both are lost.
+    let server_name = "test_clients_recovery";
+    cleanup_lock_files(server_name);
+
+    let long_running = get_test_helper_path("long_running.sh");
+    let test_pid = std::process::id().to_string();
+    let out = run_command(&[
+        "use",
+        server_name,
+        "--pid",
+        &test_pid,
+        "--grace-period",
+        "1h",
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert!(out.status.success());
+    thread::sleep(Duration::from_secs(1));
+
+    let refcount = || {
+        let info = run_command(&["info", server_name, "--json"]);
+        let info: serde_json::Value = serde_json::from_slice(&info.stdout).unwrap();
+        info["refcount"].as_u64()
+    };
+
+    let clients_path = test_lockdir().join(server_name).join("clients.json");
+    fs::write(&clients_path, b"{ not json").unwrap();
+    assert_eq!(refcount(), Some(1), "should fall back to the .bak copy");
+
+    fs::write(&clients_path, b"").unwrap();
+    let _ = fs::remove_file(test_lockdir().join(server_name).join("clients.json.bak"));
+    let doc = run_command(&["admin", "doctor", server_name, "--restore"]);
+    assert!(
+        doc.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&doc.stderr)
+    );
+    let stdout = String::from_utf8_lossy(&doc.stdout);
+    assert!(stdout.contains(&test_pid), "stdout: {}", stdout);
+    assert_eq!(refcount(), Some(1), "should be rebuilt from the log");
+
+    let _ = run_command(&["admin", "kill", server_name]);
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+fn test_foreign_lockdir_refused() {
+    // A lock directory owned by another user is refused outright. Only root
+    // can create one to test with.
+    if unsafe { libc::geteuid() } != 0 {
+        return;
+    }
+    let lockdir = std::env::temp_dir().join(format!("sharedserver-foreign-{}", std::process::id()));
+    fs::create_dir_all(&lockdir).unwrap();
+    std::os::unix::fs::chown(&lockdir, Some(65534), Some(65534)).unwrap();
+
+    let out = Command::new(get_binary_path())
+        .args(["list"])
+        .env("SHAREDSERVER_LOCKDIR", &lockdir)
+        .output()
+        .unwrap();
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("owned by UID 65534"), "stderr: {}", stderr);
+
+    let _ = fs::remove_dir_all(&lockdir);
+}
+
+#[test]
+fn test_shared_server_group_directory() {
+    // `--shared --allow-group` keeps the server in a group-owned directory
+    // that only members may use.
+    use std::os::unix::fs::MetadataExt;
+
+    let server_name = "test_shared";
+    let shared_root =
+        std::env::temp_dir().join(format!("sharedserver-shared-{}", std::process::id()));
+    let group = fs::read_to_string("/etc/group")
+        .unwrap()
+        .lines()
+        .find_map(|line| {
+            let fields: Vec<&str> = line.split(':').collect();
+            (fields.get(2)? == &unsafe { libc::getegid() }.to_string())
+                .then(|| fields[0].to_string())
+        })
+        .expect("current group has a name");
+    let shared = |args: &[&str]| {
+        Command::new(get_binary_path())
+            .args(["--shared", "--allow-group", &group])
+            .args(args)
+            .env("SHAREDSERVER_SHARED_DIR", &shared_root)
+            .output()
+            .unwrap()
+    };
+
+    let long_running = get_test_helper_path("long_running.sh");
+    let test_pid = std::process::id().to_string();
+    let out = shared(&[
+        "use",
+        server_name,
+        "--pid",
+        &test_pid,
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert!(
+        out.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+
+    let dir = shared_root.join(&group);
+    assert_eq!(fs::metadata(&dir).unwrap().mode() & 0o7777, 0o2770);
+    let server_lock = dir.join(server_name).join("server.json");
+    assert_eq!(fs::metadata(&server_lock).unwrap().mode() & 0o777, 0o660);
+    // Not in the private lock directory.
+    assert!(!test_lockdir()
+        .join(server_name)
+        .join("server.json")
+        .exists());
+
+    let info = shared(&["info", server_name, "--json"]);
+    let info: serde_json::Value = serde_json::from_slice(&info.stdout).unwrap();
+    assert_eq!(info["shared_group"], group.as_str());
+    assert_eq!(info["owner_uid"], unsafe { libc::geteuid() });
+
+    let _ = shared(&["admin", "kill", server_name]);
+    let _ = fs::remove_dir_all(&shared_root);
+}
+
+#[test]
+fn test_events_streams_state_changes() {
+    let server_name = "test_events";
+    cleanup_lock_files(server_name);
+
+    let events = Command::new(get_binary_path())
+        .args(["events", server_name, "--json", "--count", "3"])
+        .env("SHAREDSERVER_LOCKDIR", test_lockdir())
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_millis(500));
+
+    let long_running = get_test_helper_path("long_running.sh");
+    let out = run_command(&[
+        "admin",
+        "start",
+        server_name,
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert!(out.status.success());
+    thread::sleep(Duration::from_millis(500));
+    let test_pid = std::process::id().to_string();
+    assert!(
+        run_command(&["admin", "incref", server_name, "--pid", &test_pid])
+            .status
+            .success()
+    );
+
+    let output = events.wait_with_output().unwrap();
+    let events: Vec<serde_json::Value> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(events.len(), 3, "events: {:?}", events);
+    assert_eq!(
+        (events[0]["from"].as_str(), events[0]["to"].as_str()),
+        (Some("stopped"), Some("grace"))
+    );
+    assert_eq!(
+        (events[1]["from"].as_str(), events[1]["to"].as_str()),
+        (Some("grace"), Some("active"))
+    );
+    assert_eq!(events[2]["event"], "client-attached");
+    assert_eq!(events[2]["pid"], std::process::id());
+
+    let _ = run_command(&["admin", "kill", server_name]);
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+fn test_list_recent_shows_stopped_servers() {
+    let server_name = "test_list_recent";
+    cleanup_lock_files(server_name);
+
+    let long_running = get_test_helper_path("long_running.sh");
+    let test_pid = std::process::id().to_string();
+    let out = run_command(&[
+        "use",
+        server_name,
+        "--pid",
+        &test_pid,
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert!(out.status.success());
+    thread::sleep(Duration::from_millis(500));
+    assert!(run_command(&["admin", "kill", server_name])
+        .status
+        .success());
+
+    let recent = |retention: &str| -> Option<serde_json::Value> {
+        let out = Command::new(get_binary_path())
+            .args(["list", "--recent", "--json"])
+            .env("SHAREDSERVER_LOCKDIR", test_lockdir())
+            .env("SHAREDSERVER_TOMBSTONE_RETENTION", retention)
+            .output()
+            .unwrap();
+        let listed: Vec<serde_json::Value> = serde_json::from_slice(&out.stdout).unwrap();
+        listed.into_iter().find(|s| s["name"] == server_name)
+    };
+
+    let listed = recent("1h").expect("killed server listed as recent");
+    assert_eq!(listed["state"], "stopped");
+    assert_eq!(listed["last_exit"]["reason"], "killed");
+    assert_eq!(listed["last_exit"]["last_refcount"], 1);
+    // Plain `list` leaves it out.
+    let plain = run_command(&["list", "--json"]);
+    assert!(!String::from_utf8_lossy(&plain.stdout).contains(server_name));
+
+    // Past the retention period the record is dropped.
+    thread::sleep(Duration::from_secs(2));
+    assert!(recent("1s").is_none());
+    assert!(!test_lockdir().join(server_name).join("exit.json").exists());
+
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+fn test_replace_restarts_rebuilt_executable() {
+    // The server lock snapshots the binary; once it changes on disk, `info`
+    // says so and `use --replace` restarts the server even with an identical
+    // command line.
+    use std::os::unix::fs::PermissionsExt;
+
+    let server_name = "test_rebuilt_exe";
+    cleanup_lock_files(server_name);
+
+    let exe = std::env::temp_dir().join(format!("sharedserver-rebuilt-{}.sh", std::process::id()));
+    fs::write(&exe, "#!/bin/sh\nexec sleep 300\n").unwrap();
+    fs::set_permissions(&exe, fs::Permissions::from_mode(0o755)).unwrap();
+    let exe_str = exe.to_str().unwrap();
+    let test_pid = std::process::id().to_string();
+    let use_it = || {
+        run_command(&[
+            "use",
+            server_name,
+            "--pid",
+            &test_pid,
+            "--replace",
+            "--",
+            exe_str,
+        ])
+    };
+
+    assert!(use_it().status.success());
+    thread::sleep(Duration::from_millis(500));
+    let info = || -> serde_json::Value {
+        serde_json::from_slice(&run_command(&["info", server_name, "--json"]).stdout).unwrap()
+    };
+    let before = info();
+    assert_eq!(
+        before["executable"]["path"],
+        fs::canonicalize(&exe).unwrap().to_str().unwrap()
+    );
+    assert_eq!(before["executable_changed"], false);
+    assert!(before["cwd"].is_string());
+
+    // Unchanged binary: attaching again leaves the instance alone.
+    assert!(use_it().status.success());
+    assert_eq!(info()["pid"], before["pid"]);
+
+    fs::write(&exe, "#!/bin/sh\nexec sleep 301\n").unwrap();
+    assert_eq!(info()["executable_changed"], true);
+    let replaced = use_it();
+    assert!(replaced.status.success());
+    assert!(String::from_utf8_lossy(&replaced.stdout).contains("executable changed on disk"));
+    thread::sleep(Duration::from_millis(500));
+    let after = info();
+    assert_ne!(after["pid"], before["pid"]);
+    assert_eq!(after["executable_changed"], false);
+
+    let _ = run_command(&["admin", "kill", server_name]);
+    cleanup_lock_files(server_name);
+    let _ = fs::remove_file(&exe);
+}
+
+#[test]
+fn test_flat_layout_migrated_on_first_use() {
+    // State left by a version that kept `<name>.<file>` directly in the lock
+    // directory is moved into `<name>/` the first time the server is touched.
+    let server_name = "test_flat_layout";
+    cleanup_lock_files(server_name);
+
+    let lockdir = test_lockdir();
+    let _ = fs::create_dir_all(&lockdir);
+    let tombstone = serde_json::json!({
+        "pid": 4242,
+        "command": ["old-server"],
+        "reason": "crashed",
+        "exit": { "kind": "exited", "code": 3 },
+        "started_at": chrono::Utc::now().to_rfc3339(),
+        "exited_at": chrono::Utc::now().to_rfc3339(),
+    });
+    fs::write(
+        lockdir.join(format!("{}.exit.json", server_name)),
+        tombstone.to_string(),
+    )
+    .unwrap();
+    fs::write(lockdir.join(format!("{}.invocations.log", server_name)), "").unwrap();
+
+    let last = run_command(&["last", server_name, "--json"]);
+    assert!(
+        last.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&last.stderr)
+    );
+    let record: serde_json::Value = serde_json::from_slice(&last.stdout).unwrap();
+    assert_eq!(record["pid"], 4242);
+
+    assert!(lockdir.join(server_name).join("exit.json").exists());
+    assert!(lockdir.join(server_name).join("invocations.log").exists());
+    assert!(!lockdir.join(format!("{}.exit.json", server_name)).exists());
+    assert!(!lockdir
+        .join(format!("{}.invocations.log", server_name))
+        .exists());
+
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+#[serial]
+fn test_info_names_client_processes() {
+    // incref records the client's program name and command line, so info can
+    // say who is holding the server.
+    let server_name = "test_client_names";
+    cleanup_lock_files(server_name);
+
+    let long_running = get_test_helper_path("long_running.sh");
+    let mut client = Command::new("sleep").arg("60").spawn().unwrap();
+    let client_pid = client.id().to_string();
+
+    let out = run_command(&[
+        "use",
+        server_name,
+        "--pid",
+        &client_pid,
+        "--metadata",
+        "project foo",
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert!(
+        out.status.success(),
+        "use should succeed: {}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+
+    let out = run_command(&["info", server_name, "--json"]);
+    let info: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    let client_info = &info["clients"][0];
+    assert_eq!(client_info["command"], "sleep");
+    assert_eq!(client_info["cmdline"], serde_json::json!(["sleep", "60"]));
+
+    let out = run_command(&["info", server_name]);
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(
+        stdout.contains(&format!("sleep (PID: {}, project foo)", client_pid)),
+        "info should name the client: {}",
+        stdout
+    );
+
+    client.kill().unwrap();
+    client.wait().unwrap();
+    run_command(&["admin", "kill", server_name]);
+    thread::sleep(Duration::from_secs(1));
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+#[serial]
+fn test_admin_prune_removes_orphaned_and_trims_large_logs() {
+    let orphan = "test_prune_orphan";
+    let large = "test_prune_large";
+    cleanup_lock_files(orphan);
+    cleanup_lock_files(large);
+    let _ = run_command(&["list"]); // creates the lock directory
+
+    let orphan_dir = test_lockdir().join(orphan);
+    fs::create_dir_all(&orphan_dir).unwrap();
+    fs::write(orphan_dir.join("invocations.log"), "{}\n").unwrap();
+    fs::write(orphan_dir.join("watcher.log"), "{}\n").unwrap();
+
+    let large_dir = test_lockdir().join(large);
+    fs::create_dir_all(&large_dir).unwrap();
+    fs::write(large_dir.join("server.json"), "{}").unwrap();
+    let line = format!("{{\"entry\": \"{}\"}}\n", "x".repeat(90));
+    fs::write(large_dir.join("invocations.log"), line.repeat(30)).unwrap();
+
+    thread::sleep(Duration::from_millis(1500));
+    let prune = |dry_run: bool| {
+        let mut args = vec!["admin", "prune"];
+        if dry_run {
+            args.push("--dry-run");
+        }
+        Command::new(get_binary_path())
+            .args(args)
+            .env("SHAREDSERVER_LOCKDIR", test_lockdir())
+            .env("SHAREDSERVER_LOG_RETENTION", "1s")
+            .env("SHAREDSERVER_LOG_MAX_SIZE", "1000")
+            .output()
+            .unwrap()
+    };
+
+    let out = prune(true);
+    assert!(out.status.success());
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(stdout.contains("would remove"), "{}", stdout);
+    assert!(stdout.contains("would trim"), "{}", stdout);
+    assert!(orphan_dir.join("invocations.log").exists());
+
+    let out = prune(false);
+    assert!(out.status.success());
+    // The orphan's logs and (now empty) directory are gone.
+    assert!(!orphan_dir.exists());
+    // The server that still has a lock keeps its newest whole entries.
+    let trimmed = fs::read_to_string(large_dir.join("invocations.log")).unwrap();
+    assert!(trimmed.len() <= 500 && !trimmed.is_empty());
+    assert!(trimmed.lines().all(|l| l == line.trim_end()));
+
+    cleanup_lock_files(orphan);
+    cleanup_lock_files(large);
+}
+
+#[test]
+#[serial]
+fn test_slow_lock_wait_reported() {
+    use std::os::unix::io::AsRawFd;
+
+    let server_name = "test_lock_wait_report";
+    cleanup_lock_files(server_name);
+    let _ = fs::remove_file(test_lockdir().join("contention.log"));
+
+    let long_running = get_test_helper_path("long_running.sh");
+    let test_pid = std::process::id().to_string();
+    let out = run_command(&[
+        "use",
+        server_name,
+        "--pid",
+        &test_pid,
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert!(out.status.success());
+
+    // Hold the clients lock while an incref queues up behind it.
+    let clients_path = test_lockdir().join(server_name).join("clients.json");
+    let holder = fs::File::open(&clients_path).unwrap();
+    nix::fcntl::flock(holder.as_raw_fd(), nix::fcntl::FlockArg::LockExclusive).unwrap();
+    let incref = Command::new(get_binary_path())
+        .args(["admin", "incref", server_name, "--pid", &test_pid])
+        .env("SHAREDSERVER_LOCKDIR", test_lockdir())
+        .env("SHAREDSERVER_LOCK_WARN_MS", "100")
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_millis(500));
+    drop(holder);
+    let out = incref.wait_with_output().unwrap();
+    assert!(out.status.success());
+
+    // The first lock the command wanted was the one held, whichever code
+    // path that was; it is named along with the holder.
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains("clients.json") && stderr.contains(".rs:"),
+        "slow wait should be reported: {}",
+        stderr
+    );
+    let log = fs::read_to_string(test_lockdir().join("contention.log")).unwrap();
+    let wait: serde_json::Value = serde_json::from_str(log.lines().next().unwrap()).unwrap();
+    assert!(wait["waited_ms"].as_u64().unwrap() >= 100);
+    assert!(wait["path"].as_str().unwrap().ends_with("clients.json"));
+    #[cfg(target_os = "linux")]
+    assert_eq!(wait["holders"], serde_json::json!([std::process::id()]));
+
+    run_command(&["admin", "kill", server_name]);
+    thread::sleep(Duration::from_secs(1));
+    let _ = fs::remove_file(test_lockdir().join("contention.log"));
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+#[serial]
+fn test_server_handle_releases_on_drop() {
+    use sharedserver::core::handle::{AttachOptions, ServerHandle};
+
+    let server_name = "test_server_handle";
+    cleanup_lock_files(server_name);
+    env::set_var("SHAREDSERVER_LOCKDIR", test_lockdir());
+
+    let long_running = get_test_helper_path("long_running.sh");
+    let out = run_command(&[
+        "admin",
+        "start",
+        server_name,
+        "--grace-period",
+        "30s",
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert!(out.status.success());
+    let refcount = || {
+        sharedserver::read_clients_lock(server_name)
+            .unwrap()
+            .refcount()
+    };
+
+    let first = ServerHandle::attach(server_name, AttachOptions::default()).unwrap();
+    assert_eq!(first.pid(), std::process::id() as i32);
+    assert_eq!(refcount(), 1);
+    // A second handle for the same PID shares the reference.
+    let second = ServerHandle::attach(server_name, AttachOptions::default()).unwrap();
+    drop(first);
+    assert_eq!(refcount(), 1);
+    assert_eq!(second.detach().unwrap(), 0);
+
+    // A panic unwinding past the handle still releases it.
+    let result = std::panic::catch_unwind(|| {
+        let _handle = ServerHandle::attach(server_name, AttachOptions::default()).unwrap();
+        assert_eq!(refcount(), 1);
+        panic!("early exit");
+    });
+    assert!(result.is_err());
+    assert_eq!(refcount(), 0);
+
+    assert!(ServerHandle::attach("test_server_handle_missing", AttachOptions::default()).is_err());
+
+    run_command(&["admin", "kill", server_name]);
+    thread::sleep(Duration::from_secs(1));
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+#[serial]
+fn test_global_format_flag() {
+    let server_name = "test_global_format";
+    cleanup_lock_files(server_name);
+
+    let long_running = get_test_helper_path("long_running.sh");
+    let out = run_command(&[
+        "use",
+        server_name,
+        "--grace-period",
+        "30s",
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert!(out.status.success());
+
+    let list = run_command(&["--format", "yaml", "list"]);
+    assert!(list.status.success());
+    let stdout = String::from_utf8_lossy(&list.stdout);
+    assert!(
+        stdout.contains(&format!("name: {}", server_name)),
+        "{}",
+        stdout
+    );
+
+    // The flag is global, so it can follow the subcommand too.
+    let info = run_command(&["info", server_name, "--format", "json"]);
+    let info: serde_json::Value = serde_json::from_slice(&info.stdout).unwrap();
+    assert_eq!(info["state"], "active");
+
+    let check = run_command(&["check", server_name, "--format", "json"]);
+    assert_eq!(check.status.code(), Some(0));
+    let check: serde_json::Value = serde_json::from_slice(&check.stdout).unwrap();
+    assert_eq!(check["exit_code"], 0);
+
+    let doctor = run_command(&["admin", "doctor", server_name, "--format", "json"]);
+    let doctor: serde_json::Value = serde_json::from_slice(&doctor.stdout).unwrap();
+    let checkup = &doctor["servers"][0];
+    assert_eq!(checkup["name"], server_name);
+    assert_eq!(checkup["issues_found"], 0);
+    assert!(!checkup["findings"].as_array().unwrap().is_empty());
+    // No color codes leak into the messages.
+    assert!(!doctor.to_string().contains('\u{1b}'));
+
+    run_command(&["admin", "kill", server_name]);
+    thread::sleep(Duration::from_secs(1));
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+#[serial]
+fn test_quiet_mode_prints_nothing() {
+    let server_name = "test_quiet_mode";
+    cleanup_lock_files(server_name);
+
+    let long_running = get_test_helper_path("long_running.sh");
+    let pid = std::process::id().to_string();
+    let out = run_command(&[
+        "use",
+        server_name,
+        "-q",
+        "--pid",
+        &pid,
+        "--grace-period",
+        "30s",
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert!(out.status.success());
+    assert!(
+        out.stdout.is_empty(),
+        "{}",
+        String::from_utf8_lossy(&out.stdout)
+    );
+
+    let check = run_command(&["check", server_name, "--quiet"]);
+    assert_eq!(check.status.code(), Some(0));
+    assert!(check.stdout.is_empty());
+
+    let out = run_command(&["unuse", server_name, "-q", "--pid", &pid]);
+    assert!(out.status.success());
+    assert!(out.stdout.is_empty());
+
+    // Failures still explain themselves on stderr.
+    let out = run_command(&["unuse", "test_quiet_mode_missing", "-q"]);
+    assert!(!out.status.success());
+    assert!(out.stdout.is_empty());
+    assert!(!out.stderr.is_empty());
+
+    run_command(&["admin", "kill", server_name]);
+    thread::sleep(Duration::from_secs(1));
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+#[serial]
+fn test_color_follows_flag_and_no_color() {
+    let server_name = "test_color_missing";
+    // Piped stdout: plain by default.
+    let plain = run_command(&["check", server_name]);
+    assert!(!plain.stdout.contains(&0x1b));
+
+    let forced = run_command(&["check", server_name, "--color", "always"]);
+    assert!(forced.stdout.contains(&0x1b));
+    assert_eq!(forced.status.code(), plain.status.code());
+
+    let no_color = Command::new(get_binary_path())
+        .args(["check", server_name, "--color", "auto"])
+        .env("SHAREDSERVER_LOCKDIR", test_lockdir())
+        .env("NO_COLOR", "1")
+        .output()
+        .unwrap();
+    assert!(!no_color.stdout.contains(&0x1b));
+    assert!(String::from_utf8_lossy(&no_color.stdout).contains("not running"));
+}
+
+#[test]
+#[serial]
+fn test_list_and_doctor_filters() {
+    let names = ["test_filter_lsp_a", "test_filter_db"];
+    let long_running = get_test_helper_path("long_running.sh");
+    for name in names {
+        cleanup_lock_files(name);
+        let out = run_command(&[
+            "use",
+            name,
+            "--grace-period",
+            "30s",
+            "--",
+            long_running.to_str().unwrap(),
+        ]);
+        assert!(out.status.success());
+    }
+
+    let listed = |args: &[&str]| -> Vec<String> {
+        let out = run_command(&[&["list", "--format", "json"], args].concat());
+        assert!(out.status.success(), "{:?}", out);
+        let servers: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+        servers
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|s| s["name"].as_str().unwrap().to_string())
+            .filter(|name| name.starts_with("test_filter_"))
+            .collect()
+    };
+
+    assert_eq!(
+        listed(&["--name", "test_filter_lsp_*"]),
+        ["test_filter_lsp_a"]
+    );
+    assert_eq!(
+        listed(&["--state", "active", "--name", "test_filter_*"]).len(),
+        2
+    );
+    assert!(listed(&["--state", "grace,defunct", "--name", "test_filter_*"]).is_empty());
+
+    let bad = run_command(&["list", "--state", "sleeping"]);
+    assert!(!bad.status.success());
+
+    let doctor = run_command(&[
+        "admin",
+        "doctor",
+        "--name",
+        "test_filter_db",
+        "--format",
+        "json",
+    ]);
+    let doctor: serde_json::Value = serde_json::from_slice(&doctor.stdout).unwrap();
+    let checked: Vec<_> = doctor["servers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|s| s["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(checked, ["test_filter_db"]);
+    // A filtered sweep doesn't rebuild the registry other servers share.
+    assert!(doctor.get("registry_entries").is_none());
+
+    for name in names {
+        run_command(&["admin", "kill", name]);
+    }
+    thread::sleep(Duration::from_secs(1));
+    for name in names {
+        cleanup_lock_files(name);
+    }
+}
+
+#[test]
+#[serial]
+fn test_list_sort_by_uptime() {
+    // Started oldest first, so uptime order is the reverse of name order.
+    let names = ["test_sort_b", "test_sort_a"];
+    let long_running = get_test_helper_path("long_running.sh");
+    for name in names {
+        cleanup_lock_files(name);
+        let out = run_command(&[
+            "use",
+            name,
+            "--grace-period",
+            "30s",
+            "--",
+            long_running.to_str().unwrap(),
+        ]);
+        assert!(out.status.success());
+        thread::sleep(Duration::from_millis(1100));
+    }
+
+    let listed = |sort: &str| -> Vec<serde_json::Value> {
+        let out = run_command(&[
+            "list",
+            "--format",
+            "json",
+            "--name",
+            "test_sort_*",
+            "--sort",
+            sort,
+        ]);
+        assert!(out.status.success(), "{:?}", out);
+        serde_json::from_slice::<Vec<serde_json::Value>>(&out.stdout).unwrap()
+    };
+    let names_of = |servers: &[serde_json::Value]| -> Vec<String> {
+        servers
+            .iter()
+            .map(|s| s["name"].as_str().unwrap().to_string())
+            .collect()
+    };
+
+    assert_eq!(names_of(&listed("name")), ["test_sort_a", "test_sort_b"]);
+    let by_uptime = listed("uptime");
+    assert_eq!(names_of(&by_uptime), ["test_sort_b", "test_sort_a"]);
+    assert!(by_uptime[0]["uptime_secs"].as_u64().unwrap() >= 1);
+
+    let table = run_command(&["list", "--name", "test_sort_*"]);
+    assert!(String::from_utf8_lossy(&table.stdout).contains("UPTIME"));
+
+    for name in names {
+        run_command(&["admin", "kill", name]);
+    }
+    thread::sleep(Duration::from_secs(1));
+    for name in names {
+        cleanup_lock_files(name);
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+#[serial]
+fn test_list_resources_samples_every_server() {
+    let server_name = "test_list_resources";
+    cleanup_lock_files(server_name);
+
+    // No limits, so the watcher records nothing: the numbers come from list.
+    let long_running = get_test_helper_path("long_running.sh");
+    let out = run_command(&[
+        "use",
+        server_name,
+        "--grace-period",
+        "30s",
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert!(out.status.success());
+
+    let out = run_command(&[
+        "list",
+        "--resources",
+        "--format",
+        "json",
+        "--name",
+        server_name,
+    ]);
+    let servers: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    let resources = &servers[0]["resources"];
+    assert!(resources["rss_bytes"].as_u64().unwrap() > 0, "{}", servers);
+    assert!(resources["cpu_percent"].is_number());
+
+    let table = run_command(&["list", "--resources", "--name", server_name]);
+    let stdout = String::from_utf8_lossy(&table.stdout);
+    assert!(
+        stdout.contains("MEM") && stdout.contains("CPU"),
+        "{}",
+        stdout
+    );
+
+    run_command(&["admin", "kill", server_name]);
+    thread::sleep(Duration::from_secs(1));
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+#[serial]
+fn test_list_tsv_and_csv() {
+    let server_name = "test_list_tsv";
+    cleanup_lock_files(server_name);
+
+    let long_running = get_test_helper_path("long_running.sh");
+    let out = run_command(&[
+        "use",
+        server_name,
+        "--grace-period",
+        "30s",
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert!(out.status.success());
+
+    let tsv = run_command(&["list", "--format", "tsv", "--name", server_name]);
+    assert!(tsv.status.success());
+    let stdout = String::from_utf8_lossy(&tsv.stdout);
+    let lines: Vec<Vec<&str>> = stdout.lines().map(|l| l.split('\t').collect()).collect();
+    assert_eq!(
+        lines[0],
+        ["name", "state", "pid", "uptime_secs", "refcount", "clients"]
+    );
+    assert_eq!(lines.len(), 2, "{}", stdout);
+    assert_eq!(lines[1][0], server_name);
+    assert_eq!(lines[1][1], "active");
+    assert!(lines[1][2].parse::<i32>().is_ok());
+    assert_eq!(lines[1][4], "1");
+    assert!(!stdout.contains('\u{1b}'));
+
+    let csv = run_command(&["list", "--format", "csv", "--name", server_name]);
+    let stdout = String::from_utf8_lossy(&csv.stdout);
+    assert!(stdout.starts_with("name,state,pid,uptime_secs,refcount,clients\n"));
+
+    // Nothing else has rows to print.
+    let info = run_command(&["info", server_name, "--format", "tsv"]);
+    assert!(!info.status.success());
+    assert!(String::from_utf8_lossy(&info.stderr).contains("only supported by list"));
+
+    run_command(&["admin", "kill", server_name]);
+    thread::sleep(Duration::from_secs(1));
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+#[serial]
+fn test_info_watch_refreshes() {
+    let server_name = "test_info_watch";
+    cleanup_lock_files(server_name);
+
+    let long_running = get_test_helper_path("long_running.sh");
+    let out = run_command(&[
+        "admin",
+        "start",
+        server_name,
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert!(out.status.success());
+
+    let mut watch = Command::new(get_binary_path())
+        .args(["info", server_name, "--watch=1s"])
+        .env("SHAREDSERVER_LOCKDIR", test_lockdir())
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_millis(2500));
+    watch.kill().unwrap();
+    let output = watch.wait_with_output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    // Not a terminal: each refresh is appended rather than redrawn.
+    assert!(stdout.matches("Every 1s").count() >= 2, "{}", stdout);
+    assert!(stdout.matches("Server: ").count() >= 2, "{}", stdout);
+    assert!(!stdout.contains("\u{1b}[2J"));
+
+    run_command(&["admin", "kill", server_name]);
+    thread::sleep(Duration::from_secs(1));
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+#[serial]
+fn test_verbose_diagnostics() {
+    let server_name = "test_verbose";
+    cleanup_lock_files(server_name);
+
+    let long_running = get_test_helper_path("long_running.sh");
+    let test_pid = std::process::id().to_string();
+    let out = run_command(&[
+        "-v",
+        "use",
+        server_name,
+        "--pid",
+        &test_pid,
+        "--grace-period",
+        "1h",
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert!(out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains("is stopped; attaching client PID"),
+        "{}",
+        stderr
+    );
+
+    // Without -v, nothing extra.
+    let out = run_command(&["info", server_name]);
+    assert!(!String::from_utf8_lossy(&out.stderr).contains("DEBUG"));
+
+    // The watcher it started records why it entered grace in its own log.
+    let out = run_command(&["unuse", server_name, "--pid", &test_pid]);
+    assert!(out.status.success());
+    thread::sleep(Duration::from_secs(1));
+    let log = fs::read_to_string(test_lockdir().join(server_name).join("watcher.log")).unwrap();
+    assert!(
+        log.lines()
+            .any(|line| line.contains("\"diagnostic\"") && line.contains("starting 1h grace")),
+        "{}",
+        log
+    );
+
+    run_command(&["admin", "kill", server_name]);
+    thread::sleep(Duration::from_secs(1));
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+#[serial]
+fn test_exit_code_contract() {
+    let server_name = "test_exit_codes";
+    cleanup_lock_files(server_name);
+
+    let stopped = run_command(&["unuse", server_name, "--pid", "1"]);
+    assert_eq!(stopped.status.code(), Some(10), "not running");
+    assert_eq!(
+        run_command(&["admin", "stop", server_name]).status.code(),
+        Some(10)
+    );
+
+    let bad_timeout = run_command(&["admin", "stop", server_name, "--timeout", "soon"]);
+    assert_eq!(bad_timeout.status.code(), Some(2), "invalid args");
+
+    let long_running = get_test_helper_path("long_running.sh");
+    let out = run_command(&[
+        "use",
+        server_name,
+        "--grace-period",
+        "30s",
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert!(out.status.success());
+
+    let again = run_command(&[
+        "admin",
+        "start",
+        server_name,
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert_eq!(again.status.code(), Some(12), "already running");
+
+    let stranger = run_command(&["admin", "decref", server_name, "--pid", "1"]);
+    assert_eq!(stranger.status.code(), Some(13), "not attached");
+
+    // The contract itself, as a document.
+    let codes = run_command(&["--print-exit-codes", "--format", "json"]);
+    assert!(codes.status.success());
+    let codes: serde_json::Value = serde_json::from_slice(&codes.stdout).unwrap();
+    let named = |name: &str| {
+        codes
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|c| c["name"] == name)
+            .map(|c| c["code"].as_i64().unwrap())
+    };
+    assert_eq!(named("not-running"), Some(10));
+    assert_eq!(named("lock-timeout"), Some(14));
+    assert_eq!(named("unhealthy"), Some(4));
+
+    run_command(&["admin", "kill", server_name]);
+    thread::sleep(Duration::from_secs(1));
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+#[serial]
+fn test_list_and_info_show_address() {
+    let server_name = "test_address";
+    let probed_name = "test_address_probed";
+    cleanup_lock_files(server_name);
+    cleanup_lock_files(probed_name);
+
+    let long_running = get_test_helper_path("long_running.sh");
+    let bad = run_command(&[
+        "use",
+        server_name,
+        "--address",
+        "localhost",
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert_eq!(bad.status.code(), Some(2), "an address needs a port");
+
+    let out = run_command(&[
+        "use",
+        server_name,
+        "--address",
+        "8432",
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert!(out.status.success());
+    // No --address: the health probe says where it listens.
+    let out = run_command(&[
+        "use",
+        probed_name,
+        "--health-tcp",
+        "127.0.0.1:8433",
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert!(out.status.success());
+
+    let list = run_command(&["list", "--name", "test_address*"]);
+    let stdout = String::from_utf8_lossy(&list.stdout);
+    assert!(
+        stdout.lines().next().unwrap().contains("ADDRESS"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("tcp:127.0.0.1:8432"), "{}", stdout);
+    assert!(stdout.contains("tcp:127.0.0.1:8433"), "{}", stdout);
+
+    let info = run_command(&["info", server_name, "--json"]);
+    let info: serde_json::Value = serde_json::from_slice(&info.stdout).unwrap();
+    assert_eq!(info["address"], "tcp:127.0.0.1:8432");
+    let info = run_command(&["info", probed_name]);
+    assert!(String::from_utf8_lossy(&info.stdout)
+        .contains("Address: tcp:127.0.0.1:8433 (from the health probe)"));
+
+    for name in [server_name, probed_name] {
+        run_command(&["admin", "kill", name]);
+    }
+    thread::sleep(Duration::from_secs(1));
+    cleanup_lock_files(server_name);
+    cleanup_lock_files(probed_name);
+}
+
+#[test]
+#[serial]
+fn test_grace_period_none_and_infinite() {
+    let none_name = "test_grace_none";
+    let infinite_name = "test_grace_infinite";
+    cleanup_lock_files(none_name);
+    cleanup_lock_files(infinite_name);
+
+    let long_running = get_test_helper_path("long_running.sh");
+    let test_pid = std::process::id().to_string();
+    for (name, grace) in [(none_name, "none"), (infinite_name, "infinite")] {
+        let out = run_command(&[
+            "use",
+            name,
+            "--pid",
+            &test_pid,
+            "--grace-period",
+            grace,
+            "--",
+            long_running.to_str().unwrap(),
+        ]);
+        assert!(
+            out.status.success(),
+            "{}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+    }
+    let info = run_command(&["info", infinite_name]);
+    assert!(String::from_utf8_lossy(&info.stdout).contains("Grace Period: infinite"));
+
+    // `none`: gone as soon as the last client detaches.
+    let out = run_command(&["unuse", none_name, "--pid", &test_pid]);
+    assert!(out.status.success());
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(stdout.contains("refcount: 0, shutting down"), "{}", stdout);
+    let detached_at = std::time::Instant::now();
+    while run_command(&["check", none_name]).status.code() != Some(2) {
+        assert!(
+            detached_at.elapsed() < Duration::from_secs(3),
+            "grace none should stop the server at once"
+        );
+        thread::sleep(Duration::from_millis(20));
+    }
+
+    // `infinite`: idles with no clients and no countdown.
+    let out = run_command(&["unuse", infinite_name, "--pid", &test_pid]);
+    assert!(out.status.success());
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(
+        stdout.contains("refcount: 0, kept running until stopped"),
+        "{}",
+        stdout
+    );
+    thread::sleep(Duration::from_secs(2));
+    assert_eq!(
+        run_command(&["check", infinite_name]).status.code(),
+        Some(1)
+    );
+    let info = run_command(&["info", infinite_name, "--json"]);
+    let info: serde_json::Value = serde_json::from_slice(&info.stdout).unwrap();
+    assert!(info["grace_deadline"].is_null(), "{}", info);
+
+    // Only an explicit stop ends it.
+    let stop = run_command(&["admin", "stop", infinite_name]);
+    assert!(stop.status.success());
+    assert_eq!(
+        run_command(&["check", infinite_name]).status.code(),
+        Some(2)
+    );
+
+    cleanup_lock_files(none_name);
+    cleanup_lock_files(infinite_name);
+}
+
+#[test]
+#[serial]
+fn test_admin_debug_summarizes_unless_raw() {
+    let server_name = "test_debug_humanized";
+    cleanup_lock_files(server_name);
+
+    let long_running = get_test_helper_path("long_running.sh");
+    let out = run_command(&[
+        "use",
+        server_name,
+        "--grace-period",
+        "30s",
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert!(out.status.success());
+
+    let debug = run_command(&["admin", "debug", server_name]);
+    let stdout = String::from_utf8_lossy(&debug.stdout);
+    let start = stdout
+        .lines()
+        .find(|line| line.contains("start test_debug_humanized"))
+        .unwrap_or_else(|| panic!("{}", stdout));
+    assert!(start.contains("s ago"), "{}", start);
+    assert!(start.contains("✓ ok"), "{}", start);
+    assert!(start.contains("grace_period=30s"), "{}", start);
+    assert!(!stdout.contains("UTC]"), "{}", stdout);
+
+    let raw = run_command(&["admin", "debug", server_name, "--raw"]);
+    let stdout = String::from_utf8_lossy(&raw.stdout);
+    assert!(
+        stdout.contains("UTC] start test_debug_humanized"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("  Result: success"), "{}", stdout);
+
+    run_command(&["admin", "kill", server_name]);
+    thread::sleep(Duration::from_secs(1));
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+#[serial]
+fn test_admin_doctor_summary_and_exit_code() {
+    // Doctor totals what it found and fixed, and exits non-zero while an
+    // issue it can't repair remains.
+    let server_name = "test_doctor_summary";
+    cleanup_lock_files(server_name);
+
+    let long_running = get_test_helper_path("long_running.sh");
+    let test_pid = std::process::id().to_string();
+    let out = run_command(&[
+        "use",
+        server_name,
+        "--pid",
+        &test_pid,
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert!(out.status.success());
+    thread::sleep(Duration::from_secs(1));
+
+    let healthy = run_command(&["admin", "doctor", server_name, "--format", "json"]);
+    assert_eq!(healthy.status.code(), Some(0));
+    let healthy: serde_json::Value = serde_json::from_slice(&healthy.stdout).unwrap();
+    assert_eq!(healthy["summary"]["servers_checked"], 1);
+    assert_eq!(healthy["summary"]["healthy"], 1);
+    assert_eq!(healthy["summary"]["issues_unfixed"], 0);
+
+    // Doctor reports a dead watcher but leaves it alone.
+    unsafe {
+        libc::kill(watcher_pid_of(server_name), libc::SIGKILL);
+    }
+    thread::sleep(Duration::from_millis(500));
+
+    let doctor = run_command(&["admin", "doctor", server_name, "--format", "json"]);
+    assert_eq!(
+        doctor.status.code(),
+        Some(16),
+        "stderr: {}",
+        String::from_utf8_lossy(&doctor.stderr)
+    );
+    let doctor: serde_json::Value = serde_json::from_slice(&doctor.stdout).unwrap();
+    let summary = &doctor["summary"];
+    assert_eq!(summary["servers_checked"], 1);
+    assert_eq!(summary["healthy"], 0);
+    assert_eq!(summary["issues_found"], 1);
+    assert_eq!(summary["issues_fixed"], 0);
+    assert_eq!(summary["issues_unfixed"], 1);
+
+    run_command(&["admin", "kill", server_name]);
+    thread::sleep(Duration::from_secs(1));
+
+    // What's left is stale and doctor can fix all of it.
+    let stale = run_command(&["admin", "doctor", server_name, "--format", "json"]);
+    assert_eq!(stale.status.code(), Some(0));
+    let stale: serde_json::Value = serde_json::from_slice(&stale.stdout).unwrap();
+    assert_eq!(stale["summary"]["issues_unfixed"], 0);
+
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+#[serial]
+fn test_admin_doctor_json_findings() {
+    // `--json` reports each finding with its check and severity, and the
+    // repairs doctor made, for tooling that aggregates many machines.
+    let server_name = "test_doctor_json";
+    cleanup_lock_files(server_name);
+
+    let long_running = get_test_helper_path("long_running.sh");
+    let test_pid = std::process::id().to_string();
+    let out = run_command(&[
+        "use",
+        server_name,
+        "--pid",
+        &test_pid,
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert!(out.status.success());
+    thread::sleep(Duration::from_secs(1));
+
+    let info = run_command(&["info", server_name, "--json"]);
+    let info: serde_json::Value = serde_json::from_slice(&info.stdout).unwrap();
+    let pid = info["pid"].as_i64().expect("pid") as i32;
+    // Take out the watcher and then the server, leaving the lockfiles stale.
+    let watcher_pid = watcher_pid_of(server_name);
+    unsafe { libc::kill(watcher_pid, libc::SIGKILL) };
+    assert!(
+        wait_for_exit(watcher_pid),
+        "watcher {} outlived SIGKILL",
+        watcher_pid
+    );
+    unsafe { libc::kill(pid, libc::SIGKILL) };
+    assert!(wait_for_exit(pid), "server {} outlived SIGKILL", pid);
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    loop {
+        let info = run_command(&["info", server_name, "--json"]);
+        let info: serde_json::Value = serde_json::from_slice(&info.stdout).unwrap();
+        if info["state"] == "defunct" {
+            break;
+        }
+        assert!(
+            std::time::Instant::now() < deadline,
+            "lock never went stale: {}",
+            info
+        );
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    let doctor = run_command(&["admin", "doctor", server_name, "--json"]);
+    assert_eq!(
+        doctor.status.code(),
+        Some(0),
+        "{}",
+        String::from_utf8_lossy(&doctor.stdout)
+    );
+    let doctor: serde_json::Value = serde_json::from_slice(&doctor.stdout).unwrap();
+    assert!(!doctor["host"].as_str().unwrap().is_empty());
+    assert!(doctor["checked_at"].is_string());
+
+    let checkup = &doctor["servers"][0];
+    assert_eq!(checkup["status"], "fixed");
+    let findings = checkup["findings"].as_array().unwrap();
+    let issue = findings.iter().find(|f| f["kind"] == "issue").unwrap();
+    assert_eq!(issue["check"], "server_process");
+    assert_eq!(issue["severity"], "warning");
+    let actions: Vec<_> = findings
+        .iter()
+        .filter_map(|f| f["action"].as_str())
+        .collect();
+    assert_eq!(actions, ["remove_server_lock", "remove_clients_lock"]);
+
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+#[serial]
+fn test_prompt_shows_servers_attached_from_the_shell() {
+    // `prompt` lists the servers a shell's process tree holds, and prints
+    // nothing for a shell attached to none.
+    let server_name = "test_prompt";
+    cleanup_lock_files(server_name);
+
+    let long_running = get_test_helper_path("long_running.sh");
+    let test_pid = std::process::id().to_string();
+    let out = run_command(&[
+        "use",
+        server_name,
+        "--pid",
+        &test_pid,
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert!(out.status.success());
+
+    let prompt = run_command(&["prompt", "--pid", &test_pid]);
+    assert!(prompt.status.success());
+    let stdout = String::from_utf8_lossy(&prompt.stdout);
+    assert!(
+        stdout.contains(&format!("{}✓", server_name)),
+        "stdout: {}",
+        stdout
+    );
+
+    let json = run_command(&["prompt", "--pid", &test_pid, "--format", "json"]);
+    let json: serde_json::Value = serde_json::from_slice(&json.stdout).unwrap();
+    let entry = json
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|s| s["name"] == server_name)
+        .expect("server in prompt JSON");
+    assert_eq!(entry["state"], "active");
+    assert_eq!(entry["clients"][0], std::process::id());
+
+    // The server runs apart from this process tree, so nothing holds it from
+    // there.
+    let info = run_command(&["info", server_name, "--json"]);
+    let info: serde_json::Value = serde_json::from_slice(&info.stdout).unwrap();
+    let server_pid = info["pid"].to_string();
+    let unrelated = run_command(&["prompt", "--pid", &server_pid]);
+    assert!(unrelated.status.success());
+    assert!(
+        !String::from_utf8_lossy(&unrelated.stdout).contains(server_name),
+        "stdout: {}",
+        String::from_utf8_lossy(&unrelated.stdout)
+    );
+
+    run_command(&["admin", "kill", server_name]);
+    thread::sleep(Duration::from_secs(1));
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+#[serial]
+fn test_list_tree_shows_processes_and_clients() {
+    // The tree shows the watcher, the server under it, and the attached
+    // clients by name.
+    let server_name = "test_list_tree";
+    cleanup_lock_files(server_name);
+
+    let long_running = get_test_helper_path("long_running.sh");
+    let mut client = Command::new("sleep").arg("60").spawn().unwrap();
+    let client_pid = client.id().to_string();
+    let out = run_command(&[
+        "use",
+        server_name,
+        "--pid",
+        &client_pid,
+        "--metadata",
+        "project foo",
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert!(out.status.success());
+    let watcher_pid = watcher_pid_of(server_name);
+
+    let out = run_command(&["list", "--tree", "--json", "--name", server_name]);
+    let servers: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    let processes = &servers[0]["processes"];
+    assert_eq!(processes["pid"], watcher_pid, "{}", servers);
+    assert_eq!(processes["children"][0]["pid"], servers[0]["pid"]);
+    assert_eq!(servers[0]["clients"][0]["name"], "sleep");
+
+    let out = run_command(&["list", "--tree", "--name", server_name]);
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(
+        stdout.contains(&format!("watcher {}", watcher_pid)),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("server "), "{}", stdout);
+    assert!(
+        stdout.contains(&format!("{} sleep  project foo", client_pid)),
+        "{}",
+        stdout
+    );
+
+    run_command(&["admin", "kill", server_name]);
+    let _ = client.kill();
+    let _ = client.wait();
+    thread::sleep(Duration::from_secs(1));
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+#[serial]
+fn test_ascii_output() {
+    // --ascii, or a locale that isn't UTF-8, draws everything in plain ASCII.
+    let server_name = "test_ascii_output";
+    cleanup_lock_files(server_name);
+
+    let long_running = get_test_helper_path("long_running.sh");
+    let out = run_command(&[
+        "--ascii",
+        "use",
+        server_name,
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert!(out.status.success());
+    assert!(String::from_utf8_lossy(&out.stdout).starts_with("+ Started"));
+
+    for args in [
+        &["--ascii", "list", "--name", server_name][..],
+        &["--ascii", "list", "--tree", "--name", server_name],
+        &["--ascii", "check", server_name],
+        &["--ascii", "info", server_name],
+    ] {
+        let out = run_command(args);
+        assert!(out.status.success(), "{:?}", args);
+        assert!(
+            out.stdout.is_ascii(),
+            "{:?}: {}",
+            args,
+            String::from_utf8_lossy(&out.stdout)
+        );
+    }
+
+    let out = Command::new(get_binary_path())
+        .args(["list", "--tree", "--name", server_name])
+        .env("SHAREDSERVER_LOCKDIR", test_lockdir())
+        .env("LC_ALL", "C")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(out.stdout.is_ascii(), "{}", stdout);
+    assert!(
+        stdout.contains("* Active") && stdout.contains("`- "),
+        "{}",
+        stdout
+    );
+
+    run_command(&["admin", "kill", server_name]);
+    thread::sleep(Duration::from_secs(1));
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+#[serial]
+fn test_daemon_supervises_servers_used_via_daemon() {
+    // With a daemon running, --via-daemon hands use/unuse to it: it launches
+    // and supervises the server itself, and the lockfiles say so. Without
+    // one, --via-daemon acts directly.
+    let server_name = "test_daemon";
+    cleanup_lock_files(server_name);
+    let socket = test_lockdir().join("daemon.sock");
+    let _ = fs::create_dir_all(test_lockdir());
+    let _ = fs::remove_file(&socket);
+
+    let mut daemon = Command::new(get_binary_path())
+        .arg("daemon")
+        .env("SHAREDSERVER_LOCKDIR", test_lockdir())
+        .stdout(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+    for _ in 0..50 {
+        if run_command(&["daemon", "status"]).status.success() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    let long_running = get_test_helper_path("long_running.sh");
+    let test_pid = std::process::id().to_string();
+    let out = run_command(&[
+        "--via-daemon",
+        "use",
+        server_name,
+        "--pid",
+        &test_pid,
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert!(
+        out.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    assert!(String::from_utf8_lossy(&out.stdout).contains("Started server"));
+    assert_eq!(watcher_pid_of(server_name), daemon.id() as i32);
+
+    let status = run_command(&["daemon", "status", "--format", "json"]);
+    let status: serde_json::Value = serde_json::from_slice(&status.stdout).unwrap();
+    assert_eq!(status["pid"], daemon.id());
+    assert_eq!(status["servers"][0]["name"], server_name);
+
+    let out = run_command(&["--via-daemon", "unuse", server_name, "--pid", &test_pid]);
+    assert!(out.status.success());
+    assert!(String::from_utf8_lossy(&out.stdout).contains("entering grace period"));
+    let out = run_command(&["--via-daemon", "use", server_name, "--pid", &test_pid]);
+    assert!(String::from_utf8_lossy(&out.stdout).contains("Rescued server"));
+
+    // admin stop works on the daemon's servers without waiting for the
+    // daemon itself to exit.
+    let out = run_command(&["admin", "stop", server_name]);
+    assert!(
+        out.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    assert!(!test_lockdir()
+        .join(server_name)
+        .join("server.json")
+        .exists());
+    assert!(daemon.try_wait().unwrap().is_none());
+
+    let out = run_command(&["daemon", "stop"]);
+    assert!(out.status.success());
+    assert!(daemon.wait().unwrap().success());
+    assert!(!socket.exists());
+    assert_eq!(run_command(&["daemon", "status"]).status.code(), Some(10));
+
+    let out = run_command(&[
+        "--via-daemon",
+        "use",
+        server_name,
+        "--pid",
+        &test_pid,
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert!(out.status.success());
+    assert_ne!(watcher_pid_of(server_name), daemon.id() as i32);
+
+    run_command(&["admin", "kill", server_name]);
+    thread::sleep(Duration::from_secs(1));
+    cleanup_lock_files(server_name);
+}
+
+/// Send one HTTP request to `address` and return the status and JSON body.
+fn http_request(
+    address: &str,
+    method: &str,
+    path: &str,
+    token: Option<&str>,
+    body: &str,
+) -> (u16, serde_json::Value) {
+    use std::io::{Read, Write};
+    let mut stream = std::net::TcpStream::connect(address).unwrap();
+    let auth = token.map_or(String::new(), |t| {
+        format!("Authorization: Bearer {}\r\n", t)
+    });
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: {}\r\n{}Content-Length: {}\r\n\r\n{}",
+        method,
+        path,
+        address,
+        auth,
+        body.len(),
+        body
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let status = response[9..12].parse().unwrap();
+    let (_, body) = response.split_once("\r\n\r\n").unwrap();
+    (status, serde_json::from_str(body).unwrap())
+}
+
+#[test]
+#[serial]
+fn test_daemon_http_api() {
+    // `daemon --http` serves the REST API, and only to requests carrying the
+    // token.
+    use std::io::BufRead;
+    let server_name = "test_daemon_http";
+    cleanup_lock_files(server_name);
+    let _ = fs::create_dir_all(test_lockdir());
+    let _ = fs::remove_file(test_lockdir().join("daemon.sock"));
+
+    let token = "test-token";
+    let mut daemon = Command::new(get_binary_path())
+        .args(["daemon", "--http", "127.0.0.1:0"])
+        .env("SHAREDSERVER_LOCKDIR", test_lockdir())
+        .env("SHAREDSERVER_HTTP_TOKEN", token)
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut lines = std::io::BufReader::new(daemon.stdout.take().unwrap()).lines();
+    let address = lines
+        .by_ref()
+        .map_while(|line| line.ok())
+        .find_map(|line| {
+            let (_, rest) = line.split_once("http://")?;
+            Some(rest.split_whitespace().next()?.to_string())
+        })
+        .expect("HTTP address in the daemon's output");
+
+    let (status, _) = http_request(&address, "GET", "/servers", None, "");
+    assert_eq!(status, 401);
+    let (status, _) = http_request(&address, "GET", "/servers", Some("wrong"), "");
+    assert_eq!(status, 401);
+
+    let long_running = get_test_helper_path("long_running.sh");
+    let body = serde_json::json!({
+        "pid": std::process::id(),
+        "command": [long_running],
+        "options": { "grace_period": "1s" },
+    })
+    .to_string();
+    let use_path = format!("/servers/{}/use", server_name);
+    let (status, result) = http_request(&address, "POST", &use_path, Some(token), &body);
+    assert_eq!(status, 200, "{}", result);
+    assert_eq!(result["action"], "started");
+
+    let (status, servers) = http_request(&address, "GET", "/servers", Some(token), "");
+    assert_eq!(status, 200);
+    assert!(servers
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|s| s["name"] == server_name && s["watcher_pid"] == daemon.id()));
+    let clients_path = format!("/servers/{}/clients", server_name);
+    let (_, clients) = http_request(&address, "GET", &clients_path, Some(token), "");
+    assert_eq!(clients["refcount"], 1);
+
+    let stop_path = format!("/servers/{}/stop", server_name);
+    let (status, _) = http_request(&address, "POST", &stop_path, Some(token), "");
+    assert_eq!(status, 202);
+    for _ in 0..50 {
+        if !test_lockdir()
+            .join(server_name)
+            .join("server.json")
+            .exists()
+        {
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    let (status, result) = http_request(&address, "GET", &clients_path, Some(token), "");
+    assert_eq!(status, 404);
+    assert_eq!(result["exit_code"], 10);
+
+    assert!(run_command(&["daemon", "stop"]).status.success());
+    let _ = daemon.wait();
+    cleanup_lock_files(server_name);
+}
+
+#[cfg(feature = "grpc")]
+#[test]
+#[serial]
+fn test_daemon_grpc_api() {
+    // `daemon --grpc` serves proto/sharedserver.proto with the HTTP API's
+    // token, and failures carry the CLI's exit codes.
+    use sharedserver::core::grpc::{self, proto};
+    use std::io::BufRead;
+    let server_name = "test_daemon_grpc";
+    cleanup_lock_files(server_name);
+    let _ = fs::create_dir_all(test_lockdir());
+    let _ = fs::remove_file(test_lockdir().join("daemon.sock"));
+
+    let token = "test-token";
+    let mut daemon = Command::new(get_binary_path())
+        .args(["daemon", "--grpc", "127.0.0.1:0"])
+        .env("SHAREDSERVER_LOCKDIR", test_lockdir())
+        .env("SHAREDSERVER_HTTP_TOKEN", token)
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut lines = std::io::BufReader::new(daemon.stdout.take().unwrap()).lines();
+    let address = lines
+        .by_ref()
+        .map_while(|line| line.ok())
+        .find_map(|line| {
+            let (_, rest) = line.split_once("gRPC API on ")?;
+            Some(rest.split_whitespace().next()?.to_string())
+        })
+        .expect("gRPC address in the daemon's output");
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let channel = tonic::transport::Endpoint::from_shared(format!("http://{}", address))
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        let bearer = format!("Bearer {}", token);
+        let mut client = proto::shared_server_client::SharedServerClient::with_interceptor(
+            channel.clone(),
+            move |mut request: tonic::Request<()>| {
+                request
+                    .metadata_mut()
+                    .insert("authorization", bearer.parse().unwrap());
+                Ok(request)
+            },
+        );
+
+        let mut anonymous = proto::shared_server_client::SharedServerClient::new(channel);
+        let status = anonymous
+            .list(proto::ListRequest::default())
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unauthenticated);
+
+        let long_running = get_test_helper_path("long_running.sh");
+        let used = client
+            .r#use(proto::UseRequest {
+                name: server_name.to_string(),
+                client_pid: std::process::id() as i32,
+                command: vec![long_running.to_string_lossy().into_owned()],
+                options: Some(proto::StartOptions {
+                    grace_period: Some("1s".to_string()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(used.action(), proto::use_response::Action::Started);
+        assert_eq!(used.refcount, 1);
+
+        let servers = client
+            .list(proto::ListRequest {
+                name: Some(server_name.to_string()),
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner()
+            .servers;
+        assert_eq!(servers.len(), 1);
+        assert_eq!(servers[0].watcher_pid, Some(daemon.id() as i32));
+        let info = client
+            .info(proto::InfoRequest {
+                name: server_name.to_string(),
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(info.state(), proto::State::Active);
+        assert_eq!(info.clients[0].pid, std::process::id() as i32);
+
+        let unused = client
+            .unuse(proto::UnuseRequest {
+                name: server_name.to_string(),
+                client_pid: std::process::id() as i32,
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(unused.refcount, 0);
+        client
+            .stop(proto::StopRequest {
+                name: server_name.to_string(),
+            })
+            .await
+            .unwrap();
+        for _ in 0..50 {
+            if !test_lockdir()
+                .join(server_name)
+                .join("server.json")
+                .exists()
+            {
+                break;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        let status = client
+            .stop(proto::StopRequest {
+                name: server_name.to_string(),
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+        assert_eq!(grpc::exit_code(&status), Some(10));
+    });
+
+    assert!(run_command(&["daemon", "stop"]).status.success());
+    let _ = daemon.wait();
+    cleanup_lock_files(server_name);
+}
+
+/// Accept JSON POSTs (OTLP exports, webhooks) on an ephemeral port, passing
+/// each request's body on to the returned receiver.
+fn json_collector() -> (String, std::sync::mpsc::Receiver<serde_json::Value>) {
+    use std::io::{BufRead, Read, Write};
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let endpoint = format!("http://{}", listener.local_addr().unwrap());
+    let (sender, receiver) = std::sync::mpsc::channel();
+    thread::spawn(move || {
+        for stream in listener.incoming().map_while(Result::ok) {
+            let mut reader = std::io::BufReader::new(&stream);
+            let mut length = 0;
+            let mut line = String::new();
+            while reader.read_line(&mut line).is_ok_and(|n| n > 0) && line.trim() != "" {
+                if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                    length = value.trim().parse().unwrap_or(0);
+                }
+                line.clear();
+            }
+            let mut body = vec![0; length];
+            if reader.read_exact(&mut body).is_ok() {
+                let _ = (&stream).write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n");
+                if let Ok(body) = serde_json::from_slice(&body) {
+                    let _ = sender.send(body);
+                }
+            }
+        }
+    });
+    (endpoint, receiver)
+}
+
+#[test]
+#[serial]
+fn test_use_exports_otlp_spans() {
+    let server_name = "test-otlp";
+    cleanup_lock_files(server_name);
+    let (endpoint, exports) = json_collector();
+
+    let out = Command::new(get_binary_path())
+        .args(["use", server_name, "--"])
+        .arg(get_test_helper_path("long_running.sh"))
+        .env("SHAREDSERVER_LOCKDIR", test_lockdir())
+        .env("OTEL_EXPORTER_OTLP_ENDPOINT", &endpoint)
+        .env(
+            "TRACEPARENT",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+        )
+        .output()
+        .expect("failed to run use");
+    assert!(
+        out.status.success(),
+        "use should succeed: {}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+
+    // The CLI and the watcher export separately; gather spans until both the
+    // command's and the watcher's have arrived.
+    let mut spans = Vec::new();
+    let deadline = std::time::Instant::now() + Duration::from_secs(10);
+    while !(spans
+        .iter()
+        .any(|s: &serde_json::Value| s["name"] == "sharedserver use")
+        && spans.iter().any(|s| s["name"] == "watcher.started"))
+    {
+        let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+        let Ok(export) = exports.recv_timeout(remaining) else {
+            break;
+        };
+        for resource in export["resourceSpans"].as_array().unwrap() {
+            for scope in resource["scopeSpans"].as_array().unwrap() {
+                spans.extend(scope["spans"].as_array().unwrap().iter().cloned());
+            }
+        }
+    }
+
+    let command = spans
+        .iter()
+        .find(|s| s["name"] == "sharedserver use")
+        .expect("a span for the use command");
+    assert_eq!(command["traceId"], "4bf92f3577b34da6a3ce929d0e0e4736");
+    assert_eq!(command["parentSpanId"], "00f067aa0ba902b7");
+    for name in ["launch", "watcher.started"] {
+        let span = spans
+            .iter()
+            .find(|s| s["name"] == name)
+            .unwrap_or_else(|| panic!("a {} span", name));
+        assert_eq!(span["parentSpanId"], command["spanId"]);
+    }
+
+    let _ = run_command(&["admin", "stop", server_name]);
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+#[serial]
+fn test_webhook_posts_lifecycle_events() {
+    let server_name = "test-webhook";
+    cleanup_lock_files(server_name);
+    let (endpoint, posts) = json_collector();
+    let url = format!("{}/hook", endpoint);
+
+    let long_running = get_test_helper_path("long_running.sh");
+    let output = run_command(&[
+        "use",
+        "--grace-period",
+        "1s",
+        "--on-event-webhook",
+        &url,
+        server_name,
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert!(output.status.success());
+    let output = run_command(&["unuse", server_name]);
+    assert!(output.status.success());
+
+    let mut events = Vec::new();
+    while !events.contains(&"shutdown".to_string()) {
+        let payload = posts
+            .recv_timeout(Duration::from_secs(10))
+            .expect("a webhook for every lifecycle event");
+        assert_eq!(payload["server"], server_name);
+        if payload["event"] == "shutdown" {
+            assert_eq!(payload["details"]["reason"], "grace-expired");
+        }
+        events.push(payload["event"].as_str().unwrap().to_string());
+    }
+    assert_eq!(events, ["start", "grace", "shutdown"]);
+
+    let output = run_command(&[
+        "use",
+        "--on-event-webhook",
+        "ftp://example.com",
+        server_name,
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+#[serial]
+fn test_slow_webhook_does_not_hold_up_the_watcher() {
+    // An endpoint that accepts connections but never answers: each delivery
+    // would take the full timeout, which the grace countdown mustn't wait on.
+    let server_name = "test-webhook-slow";
+    cleanup_lock_files(server_name);
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/hook", listener.local_addr().unwrap());
+
+    let long_running = get_test_helper_path("long_running.sh");
+    let output = run_command(&[
+        "use",
+        "--grace-period",
+        "1s",
+        "--on-event-webhook",
+        &url,
+        server_name,
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert!(output.status.success());
+    let started = std::time::Instant::now();
+    assert!(run_command(&["unuse", server_name]).status.success());
+    let lock = test_lockdir().join(server_name).join("server.json");
+    while lock.exists() && started.elapsed() < Duration::from_secs(10) {
+        thread::sleep(Duration::from_millis(100));
+    }
+    assert!(!lock.exists());
+    assert!(
+        started.elapsed() < Duration::from_secs(4),
+        "shut down after {:?}",
+        started.elapsed()
+    );
+    drop(listener);
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+#[serial]
+fn test_notify_desktop_on_grace_and_shutdown() {
+    use std::os::unix::fs::PermissionsExt;
+    let server_name = "test-notify-desktop";
+    cleanup_lock_files(server_name);
+
+    // A stand-in notify-send that records what it was asked to show.
+    let bin = env::temp_dir().join("sharedserver-inttest-bin");
+    let notes = env::temp_dir().join("sharedserver-inttest-notes.log");
+    let _ = fs::create_dir_all(&bin);
+    let _ = fs::remove_file(&notes);
+    let notifier = bin.join("notify-send");
+    fs::write(
+        &notifier,
+        format!("#!/bin/sh\necho \"$@\" >> {}\n", notes.display()),
+    )
+    .unwrap();
+    fs::set_permissions(&notifier, fs::Permissions::from_mode(0o755)).unwrap();
+    let path = format!("{}:{}", bin.display(), env::var("PATH").unwrap_or_default());
+
+    let out = Command::new(get_binary_path())
+        .args([
+            "use",
+            "--grace-period",
+            "1s",
+            "--notify-desktop",
+            server_name,
+            "--",
+        ])
+        .arg(get_test_helper_path("long_running.sh"))
+        .env("SHAREDSERVER_LOCKDIR", test_lockdir())
+        .env("PATH", &path)
+        .output()
+        .expect("failed to run use");
+    assert!(out.status.success());
+    assert!(run_command(&["unuse", server_name]).status.success());
+
+    let mut shown = String::new();
+    for _ in 0..50 {
+        shown = fs::read_to_string(&notes).unwrap_or_default();
+        if shown.contains("Shutting down") {
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    assert!(
+        shown.contains(&format!("{} is idle", server_name)),
+        "{}",
+        shown
+    );
+    assert!(
+        shown.contains(&format!("Shutting down {}", server_name)),
+        "{}",
+        shown
+    );
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+#[serial]
+fn test_export_systemd_unit() {
+    let server_name = "test-export-systemd";
+    cleanup_lock_files(server_name);
+
+    let output = run_command(&[
+        "export",
+        "systemd",
+        server_name,
+        "--restart",
+        "on-failure",
+        "--env",
+        "PORT=8080",
+        "--",
+        "python3",
+        "-m",
+        "http.server",
+    ]);
+    assert!(output.status.success());
+    let unit = String::from_utf8_lossy(&output.stdout);
+    let binary = get_binary_path();
+    assert!(unit.contains("Type=notify\n"), "{}", unit);
+    assert!(unit.contains("Environment=PORT=8080\n"), "{}", unit);
+    assert!(
+        unit.contains(&format!(
+            "ExecStart={} use --grace-period 5m --restart on-failure {} -- python3 -m http.server\n",
+            binary.display(),
+            server_name
+        )),
+        "{}",
+        unit
+    );
+    assert!(unit.contains(&format!(
+        "ExecStop=-{} admin stop {}\n",
+        binary.display(),
+        server_name
+    )));
+    assert!(unit.contains("Restart=on-failure\n"));
+
+    // Without a command it describes the running server.
+    let output = run_command(&["export", "systemd", server_name]);
+    assert_eq!(output.status.code(), Some(10));
+    let long_running = get_test_helper_path("long_running.sh");
+    let output = run_command(&[
+        "use",
+        "--grace-period",
+        "2m",
+        server_name,
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert!(output.status.success());
+    let output = run_command(&["export", "systemd", server_name]);
+    assert!(output.status.success());
+    let unit = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        unit.contains(&format!(
+            "use --grace-period 2m {} -- {}\n",
+            server_name,
+            long_running.display()
+        )),
+        "{}",
+        unit
+    );
+    assert!(unit.contains("Restart=no\n"));
+
+    let _ = run_command(&["admin", "stop", server_name]);
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+#[serial]
+fn test_export_launchd_plist() {
+    let server_name = "test-export-launchd";
+    cleanup_lock_files(server_name);
+
+    let output = run_command(&[
+        "export",
+        "launchd",
+        server_name,
+        "--env",
+        "MODE=a&b",
+        "--",
+        "python3",
+        "-m",
+        "http.server",
+    ]);
+    assert!(output.status.success());
+    let plist = String::from_utf8_lossy(&output.stdout);
+    assert!(plist.starts_with("<?xml"), "{}", plist);
+    assert!(plist.contains(&format!("<string>sharedserver.{}</string>", server_name)));
+    assert!(plist.contains(&format!(
+        "    <string>{}</string>\n    <string>use</string>\n",
+        get_binary_path().display()
+    )));
+    assert!(plist.contains("    <string>--</string>\n    <string>python3</string>\n"));
+    assert!(plist.contains("    <key>MODE</key>\n    <string>a&amp;b</string>\n"));
+    assert!(plist.contains("  <key>RunAtLoad</key>\n  <true/>\n"));
+    assert!(plist.contains("  <key>KeepAlive</key>\n  <false/>\n"));
+
+    let output = run_command(&["export", "launchd", server_name]);
+    assert_eq!(output.status.code(), Some(10));
+}
+
+#[test]
+#[serial]
+fn test_export_defined_server() {
+    // `--defined` exports `[servers.<name>]` as `use` would start it: the
+    // config's command, environment, directory, and settings.
+    let server_name = "test-export-defined";
+    let dir = env::temp_dir().join("sharedserver-inttest-export");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("svc")).unwrap();
+    let config = dir.join("config.toml");
+    fs::write(
+        &config,
+        format!(
+            "[servers.{}]\n\
+             command = ['python3', '-m', 'http.server']\n\
+             cwd = 'svc'\n\
+             env = {{ MODE = 'dev' }}\n\
+             grace_period = '7m'\n\
+             restart = 'on-failure'\n",
+            server_name
+        ),
+    )
+    .unwrap();
+    let export = |args: &[&str]| {
+        Command::new(get_binary_path())
+            .arg("export")
+            .args(args)
+            .env("SHAREDSERVER_LOCKDIR", test_lockdir())
+            .env("SHAREDSERVER_CONFIG", &config)
+            .output()
+            .expect("Failed to run sharedserver export")
+    };
+    let svc = dir.join("svc").canonicalize().unwrap();
+
+    let output = export(&["systemd", "--defined", server_name]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let unit = String::from_utf8_lossy(&output.stdout);
+    assert!(unit.contains("Environment=MODE=dev\n"), "{}", unit);
+    assert!(
+        unit.contains(&format!("WorkingDirectory={}\n", svc.display())),
+        "{}",
+        unit
+    );
+    assert!(
+        unit.contains(&format!(
+            "use --grace-period 7m --restart on-failure {} -- python3 -m http.server\n",
+            server_name
+        )),
+        "{}",
+        unit
+    );
+    assert!(unit.contains("Restart=on-failure\n"), "{}", unit);
+
+    let output = export(&["launchd", "--defined", "--grace-period", "1h", server_name]);
+    assert!(output.status.success());
+    let plist = String::from_utf8_lossy(&output.stdout);
+    assert!(plist.contains("    <string>--grace-period</string>\n    <string>1h</string>\n"));
+    assert!(plist.contains("    <key>MODE</key>\n    <string>dev</string>\n"));
+    assert!(plist.contains(&format!("<string>{}</string>", svc.display())));
+
+    let output = export(&["systemd", "--defined", "test-export-undefined"]);
+    assert_eq!(output.status.code(), Some(2));
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+#[serial]
+fn test_daemon_socket_activation_and_idle_exit() {
+    // Started as systemd would: the listening socket passed as fd 3, named by
+    // LISTEN_PID/LISTEN_FDS. It serves on that socket, exits when idle, and
+    // leaves the socket for the next activation.
+    use std::os::fd::AsRawFd;
+    use std::os::unix::process::CommandExt;
+    let socket = test_lockdir().join("daemon.sock");
+    let _ = fs::create_dir_all(test_lockdir());
+    let _ = fs::remove_file(&socket);
+    let listener = std::os::unix::net::UnixListener::bind(&socket).unwrap();
+    let fd = listener.as_raw_fd();
+
+    let mut command = Command::new("sh");
+    command
+        .args([
+            "-c",
+            "LISTEN_PID=$$ LISTEN_FDS=1 exec \"$0\" daemon --idle-exit 1s",
+        ])
+        .arg(get_binary_path())
+        .env("SHAREDSERVER_LOCKDIR", test_lockdir())
+        .stdout(std::process::Stdio::null());
+    // SAFETY: dup2 and fcntl are async-signal-safe.
+    unsafe {
+        command.pre_exec(move || {
+            if fd == 3 {
+                libc::fcntl(3, libc::F_SETFD, 0);
+            } else {
+                libc::dup2(fd, 3);
+            }
+            Ok(())
+        });
+    }
+    let mut daemon = command.spawn().unwrap();
+    drop(listener);
+
+    let status = run_command(&["daemon", "status", "--format", "json"]);
+    assert!(status.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&status.stdout).unwrap();
+    assert_eq!(report["pid"], daemon.id());
+
+    let mut exited = None;
+    for _ in 0..50 {
+        exited = daemon.try_wait().unwrap();
+        if exited.is_some() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    if exited.is_none() {
+        let _ = daemon.kill();
+    }
+    assert!(exited.expect("daemon exits when idle").success());
+    assert!(socket.exists(), "the activation socket is left in place");
+    let _ = fs::remove_file(&socket);
+}
+
+#[test]
+#[serial]
+fn test_docker_backend_runs_and_stops_container() {
+    use std::os::unix::fs::PermissionsExt;
+    let server_name = "test-docker-backend";
+    cleanup_lock_files(server_name);
+
+    // A stand-in docker CLI: `run` writes the cidfile and execs the command
+    // in the foreground, `kill`/`stop` signal it, and every call is recorded.
+    let bin = env::temp_dir().join("sharedserver-inttest-docker");
+    let _ = fs::create_dir_all(&bin);
+    let calls = bin.join("calls.log");
+    let pidfile = bin.join("container.pid");
+    let _ = fs::remove_file(&calls);
+    let docker = bin.join("docker");
+    fs::write(
+        &docker,
+        format!(
+            r#"#!/bin/sh
+echo "$@" >> {calls}
+case "$1" in
+  run)
+    shift
+    while [ $# -gt 0 ]; do
+      case "$1" in
+        --rm) shift ;;
+        --cidfile) echo cafef00d0123456789 > "$2"; shift 2 ;;
+        --name|--label|--env) shift 2 ;;
+        *) break ;;
+      esac
+    done
+    shift
+    echo $$ > {pidfile}
+    exec "$@" ;;
+  kill) if [ "$2" = --signal ]; then kill -"$3" "$(cat {pidfile})"; else kill -KILL "$(cat {pidfile})"; fi ;;
+  stop) kill -TERM "$(cat {pidfile})" ;;
+  inspect) echo true ;;
+esac
+"#,
+            calls = calls.display(),
+            pidfile = pidfile.display()
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&docker, fs::Permissions::from_mode(0o755)).unwrap();
+    let path = format!("{}:{}", bin.display(), env::var("PATH").unwrap_or_default());
+    let sharedserver = |args: &[&str]| {
+        Command::new(get_binary_path())
+            .args(args)
+            .env("SHAREDSERVER_LOCKDIR", test_lockdir())
+            .env("PATH", &path)
+            .output()
+            .expect("failed to run sharedserver")
+    };
+
+    // A container backend needs an image, and an image needs one.
+    let out = sharedserver(&[
+        "admin",
+        "start",
+        "--backend",
+        "docker",
+        server_name,
+        "--",
+        "x",
+    ]);
+    assert_eq!(out.status.code(), Some(2));
+    let out = sharedserver(&[
+        "admin",
+        "start",
+        "--image",
+        "redis:7",
+        server_name,
+        "--",
+        "x",
+    ]);
+    assert_eq!(out.status.code(), Some(2));
+
+    let out = sharedserver(&[
+        "admin",
+        "start",
+        "--backend",
+        "docker",
+        "--image",
+        "redis:7",
+        "--env",
+        "A=1",
+        server_name,
+        "--",
+        "sleep",
+        "30",
+    ]);
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+
+    // The watcher records the container's ID once the runtime writes it.
+    let mut container = serde_json::Value::Null;
+    for _ in 0..50 {
+        let info = sharedserver(&["info", server_name, "--json"]);
+        let info: serde_json::Value = serde_json::from_slice(&info.stdout).unwrap_or_default();
+        container = info["container"].clone();
+        if container["id"].is_string() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    assert_eq!(container["backend"], "docker");
+    assert_eq!(container["image"], "redis:7");
+    assert_eq!(container["id"], "cafef00d0123456789");
+    let recorded = fs::read_to_string(&calls).unwrap();
+    assert!(
+        recorded.contains(&format!(
+            "run --rm --name sharedserver-{0} --label sharedserver.server={0} --cidfile",
+            server_name
+        )),
+        "{}",
+        recorded
+    );
+    assert!(
+        recorded.contains("--env A=1 redis:7 sleep 30"),
+        "{}",
+        recorded
+    );
+
+    // Stopping goes through the runtime, by container ID.
+    assert!(sharedserver(&["admin", "stop", server_name])
+        .status
+        .success());
+    let recorded = fs::read_to_string(&calls).unwrap();
+    assert!(
+        recorded.contains("kill --signal TERM cafef00d0123456789"),
+        "{}",
+        recorded
+    );
+    let info = sharedserver(&["info", server_name, "--json"]);
+    let info: serde_json::Value = serde_json::from_slice(&info.stdout).unwrap();
+    assert_eq!(info["state"], "stopped");
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+#[serial]
+fn test_proxy_shares_stdio_server() {
+    use std::io::{BufRead, BufReader, Write};
+    use std::process::Stdio;
+    let server_name = "test-proxy-stdio";
+    cleanup_lock_files(server_name);
+    let script = get_test_helper_path("jsonrpc_echo.sh");
+
+    let proxy = |args: &[&str]| {
+        Command::new(get_binary_path())
+            .arg("proxy")
+            .args(args)
+            .env("SHAREDSERVER_LOCKDIR", test_lockdir())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("failed to run sharedserver proxy")
+    };
+    // Send an initialize and a request, returning the two responses.
+    let session = |child: &mut std::process::Child| {
+        let stdin = child.stdin.as_mut().unwrap();
+        stdin
+            .write_all(
+                b"{\"jsonrpc\":\"2.0\",\"id\":1,\"method\":\"initialize\"}\n\
+                  {\"jsonrpc\":\"2.0\",\"method\":\"notifications/initialized\"}\n\
+                  {\"jsonrpc\":\"2.0\",\"id\":2,\"method\":\"tools/list\"}\n",
+            )
+            .unwrap();
+        stdin.flush().unwrap();
+        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+        let mut responses = Vec::new();
+        for _ in 0..2 {
+            let mut line = String::new();
+            stdout.read_line(&mut line).unwrap();
+            responses.push(serde_json::from_str::<serde_json::Value>(&line).unwrap());
+        }
+        responses
+    };
+
+    let mut first = proxy(&[
+        "--grace-period",
+        "1s",
+        server_name,
+        "--",
+        script.to_str().unwrap(),
+    ]);
+    let first_responses = session(&mut first);
+    let mut second = proxy(&[server_name]);
+    let second_responses = session(&mut second);
+
+    // Both sessions talk to the one server, with their own request IDs, and
+    // the second initialize is answered without reaching it.
+    for responses in [&first_responses, &second_responses] {
+        assert_eq!(responses[0]["id"], 1, "{:?}", responses);
+        assert_eq!(responses[1]["id"], 2, "{:?}", responses);
+        assert_eq!(responses[1]["result"]["inits"], 1, "{:?}", responses);
+    }
+    assert_eq!(
+        first_responses[1]["result"]["pid"],
+        second_responses[1]["result"]["pid"]
+    );
+    let info = run_command(&["info", server_name, "--json"]);
+    let info: serde_json::Value = serde_json::from_slice(&info.stdout).unwrap();
+    assert_eq!(info["refcount"], 2, "{}", info);
+
+    // Each session holds a reference until its stdin closes.
+    drop(first.stdin.take());
+    assert!(first.wait().unwrap().success());
+    drop(second.stdin.take());
+    assert!(second.wait().unwrap().success());
+    thread::sleep(Duration::from_millis(2500));
+    let info = run_command(&["info", server_name, "--json"]);
+    let info: serde_json::Value = serde_json::from_slice(&info.stdout).unwrap();
+    assert_eq!(info["state"], "stopped", "{}", info);
+
+    // A server that wasn't started by proxy has no stdio to share.
+    let out = run_command(&["use", server_name, "--", "sleep", "30"]);
+    assert!(out.status.success());
+    let mut child = proxy(&[server_name]);
+    drop(child.stdin.take());
+    assert_eq!(child.wait().unwrap().code(), Some(2));
+    let _ = run_command(&["admin", "kill", server_name]);
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+#[serial]
+fn test_listen_front_forwards_to_server_address() {
+    use std::io::{Read, Write};
+    use std::os::unix::net::{UnixListener, UnixStream};
+    let server_name = "test-listen-front";
+    cleanup_lock_files(server_name);
+    let long_running = get_test_helper_path("long_running.sh");
+    let dir = env::temp_dir().join("sharedserver-inttest-front");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let upstream = dir.join("server.sock");
+    let front = dir.join("front.sock");
+
+    // Nothing to forward to.
+    let out = run_command(&[
+        "admin",
+        "start",
+        server_name,
+        "--listen",
+        &format!("unix:{}", front.display()),
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert_eq!(out.status.code(), Some(2));
+
+    let out = run_command(&[
+        "admin",
+        "start",
+        server_name,
+        "--grace-period",
+        "1m",
+        "--address",
+        &format!("unix:{}", upstream.display()),
+        "--listen",
+        &format!("unix:{}", front.display()),
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert!(out.status.success(), "{:?}", out);
+    let info = run_command(&["info", server_name, "--json"]);
+    let info: serde_json::Value = serde_json::from_slice(&info.stdout).unwrap();
+    assert_eq!(info["address"], format!("unix:{}", front.display()));
+    assert_eq!(
+        info["server_address"],
+        format!("unix:{}", upstream.display())
+    );
+
+    // The "server" answers on its own address; a connection made before it
+    // is listening waits for it, as during a relaunch.
+    let mut client = UnixStream::connect(&front).unwrap();
+    client.write_all(b"ping").unwrap();
+    thread::sleep(Duration::from_millis(500));
+    let listener = UnixListener::bind(&upstream).unwrap();
+    let (mut accepted, _) = listener.accept().unwrap();
+    let mut buf = [0u8; 4];
+    accepted.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"ping");
+    accepted.write_all(b"pong").unwrap();
+    client.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"pong");
+    drop(accepted);
+    assert_eq!(client.read(&mut buf).unwrap(), 0);
+
+    // The front goes with the server.
+    assert!(run_command(&["admin", "stop", server_name])
+        .status
+        .success());
+    thread::sleep(Duration::from_secs(1));
+    assert!(!front.exists());
+    cleanup_lock_files(server_name);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+#[serial]
+fn test_server_manager_api() {
+    use sharedserver::{ErrorKind, ServerManager, ServerState, UseOptions};
+
+    let server_name = "test_server_manager";
+    cleanup_lock_files(server_name);
+    env::set_var("SHAREDSERVER_LOCKDIR", test_lockdir());
+    let manager = ServerManager::with_binary(get_binary_path());
+
+    // Nothing to start it with.
+    let err = manager
+        .use_server(server_name, &UseOptions::default())
+        .unwrap_err();
+    assert_eq!(ErrorKind::of(&err), Some(ErrorKind::NotRunning));
+    let err = manager.info(server_name).unwrap_err();
+    assert_eq!(ErrorKind::of(&err), Some(ErrorKind::NotRunning));
+
+    let long_running = get_test_helper_path("long_running.sh");
+    let opts = UseOptions {
+        command: vec![long_running.to_str().unwrap().to_string()],
+        grace_period: Some("30s".to_string()),
+        metadata: Some("harness".to_string()),
+        ..Default::default()
+    };
+    let handle = manager.use_server(server_name, &opts).unwrap();
+    let info = manager.info(server_name).unwrap();
+    assert_eq!(info.state, ServerState::Active);
+    assert_eq!(info.refcount, 1);
+    assert_eq!(
+        info.clients[&handle.pid()].metadata.as_deref(),
+        Some("harness")
+    );
+    assert_eq!(info.server.command, opts.command);
+    assert!(manager
+        .list()
+        .unwrap()
+        .iter()
+        .any(|server| server.name == server_name));
+
+    // Using it again shares the reference; dropping the handle releases it.
+    let again = manager.use_server(server_name, &opts).unwrap();
+    drop(again);
+    assert_eq!(manager.info(server_name).unwrap().refcount, 1);
+    drop(handle);
+    assert_eq!(manager.info(server_name).unwrap().state, ServerState::Grace);
+    let err = manager.unuse(server_name, None).unwrap_err();
+    assert_eq!(ErrorKind::of(&err), Some(ErrorKind::NotAttached));
+
+    let report = manager.doctor().unwrap();
+    let checkup = report
+        .servers
+        .iter()
+        .find(|server| server.name == server_name)
+        .unwrap();
+    assert_eq!(checkup.status, "healthy", "{:?}", checkup);
+
+    manager.stop(server_name, Duration::from_secs(10)).unwrap();
+    let err = manager.info(server_name).unwrap_err();
+    assert_eq!(ErrorKind::of(&err), Some(ErrorKind::NotRunning));
+    cleanup_lock_files(server_name);
+}
+
+#[cfg(feature = "async")]
+#[test]
+#[serial]
+fn test_async_api() {
+    use sharedserver::core::aio;
+    use sharedserver::core::events::StateEvent;
+    use sharedserver::core::handle::AttachOptions;
+    use sharedserver::{ServerManager, ServerState, UseOptions};
+
+    let server_name = "test_async_api";
+    cleanup_lock_files(server_name);
+    env::set_var("SHAREDSERVER_LOCKDIR", test_lockdir());
+    let manager = ServerManager::with_binary(get_binary_path());
+    let long_running = get_test_helper_path("long_running.sh");
+    let handle = manager
+        .use_server(
+            server_name,
+            &UseOptions {
+                command: vec![long_running.to_str().unwrap().to_string()],
+                grace_period: Some("30s".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    let server_pid = manager.info(server_name).unwrap().server.pid;
+    let mut client = Command::new("sleep").arg("30").spawn().unwrap();
+    let client_pid = client.id() as i32;
+
+    async fn next(events: &mut aio::Subscription) -> StateEvent {
+        tokio::time::timeout(Duration::from_secs(5), events.next())
+            .await
+            .expect("no event")
+    }
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let mut events = aio::subscribe(server_name).unwrap();
+        assert_eq!(events.state(), ServerState::Active);
+
+        let attached = aio::attach(
+            server_name,
+            AttachOptions {
+                pid: Some(client_pid),
+                metadata: Some("async".to_string()),
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            next(&mut events).await,
+            StateEvent::ClientAttached { pid: client_pid }
+        );
+        let clients = aio::read_clients_lock(server_name).await.unwrap();
+        assert_eq!(clients.refcount(), 2);
+        assert_eq!(
+            clients.clients[&client_pid].metadata.as_deref(),
+            Some("async")
+        );
+
+        assert_eq!(
+            aio::detach_client(server_name, client_pid).await.unwrap(),
+            1
+        );
+        assert_eq!(
+            next(&mut events).await,
+            StateEvent::ClientDetached { pid: client_pid }
+        );
+        // Already released, so dropping the handle has nothing left to do.
+        drop(attached);
+        assert_eq!(
+            aio::get_server_state(server_name).await.unwrap(),
+            ServerState::Active
+        );
+
+        drop(handle);
+        assert_eq!(
+            next(&mut events).await,
+            StateEvent::ClientDetached {
+                pid: std::process::id() as i32
+            }
+        );
+        assert_eq!(
+            next(&mut events).await,
+            StateEvent::State {
+                from: ServerState::Active,
+                to: ServerState::Grace
+            }
+        );
+
+        let stopper = {
+            let manager = manager.clone();
+            thread::spawn(move || manager.stop(server_name, Duration::from_secs(10)))
+        };
+        tokio::time::timeout(Duration::from_secs(10), aio::wait_for_exit(server_pid))
+            .await
+            .expect("server did not exit");
+        stopper.join().unwrap().unwrap();
+    });
+
+    let _ = client.kill();
+    let _ = client.wait();
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+#[serial]
+fn test_lsp_relays_stdio_to_tcp_server() {
+    use std::io::{BufRead, BufReader, Write};
+    use std::process::Stdio;
+    let server_name = "test-lsp-tcp";
+    cleanup_lock_files(server_name);
+    let script = get_test_helper_path("tcp_echo.pl");
+
+    let lsp = |args: &[&str]| {
+        Command::new(get_binary_path())
+            .arg("lsp")
+            .args(args)
+            .env("SHAREDSERVER_LOCKDIR", test_lockdir())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("failed to run sharedserver lsp")
+    };
+    // Send a line, returning the server's answer.
+    let session = |child: &mut std::process::Child, line: &str| {
+        let stdin = child.stdin.as_mut().unwrap();
+        writeln!(stdin, "{}", line).unwrap();
+        stdin.flush().unwrap();
+        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+        let mut answer = String::new();
+        stdout.read_line(&mut answer).unwrap();
+        answer
+    };
+
+    let mut first = lsp(&[
+        "--port-env",
+        "PORT",
+        "--grace-period",
+        "1s",
+        server_name,
+        "--",
+        script.to_str().unwrap(),
+    ]);
+    let first_answer = session(&mut first, "hello");
+    let mut second = lsp(&["--port-env", "PORT", server_name]);
+    let second_answer = session(&mut second, "again");
+
+    // Both editors reach the one server, which was started on the port it
+    // was given, and each holds a reference.
+    let info = run_command(&["info", server_name, "--json"]);
+    let info: serde_json::Value = serde_json::from_slice(&info.stdout).unwrap();
+    let pid = info["pid"].to_string();
+    assert_eq!(first_answer, format!("{} hello\n", pid));
+    assert_eq!(second_answer, format!("{} again\n", pid));
+    assert_eq!(info["refcount"], 2, "{}", info);
+    let address = info["address"].as_str().unwrap();
+    assert!(address.starts_with("tcp:127.0.0.1:"), "{}", info);
+
+    // Each session holds its reference until its stdin closes.
+    drop(first.stdin.take());
+    assert!(first.wait().unwrap().success());
+    drop(second.stdin.take());
+    assert!(second.wait().unwrap().success());
+    thread::sleep(Duration::from_millis(2500));
+    let info = run_command(&["info", server_name, "--json"]);
+    let info: serde_json::Value = serde_json::from_slice(&info.stdout).unwrap();
+    assert_eq!(info["state"], "stopped", "{}", info);
+
+    // A server with no TCP address can't be connected to.
+    let out = run_command(&["use", server_name, "--", "sleep", "30"]);
+    assert!(out.status.success());
+    let mut child = lsp(&["--port-env", "PORT", server_name]);
+    drop(child.stdin.take());
+    assert_eq!(child.wait().unwrap().code(), Some(2));
+    let _ = run_command(&["admin", "kill", server_name]);
+    cleanup_lock_files(server_name);
+}
+
+#[test]
+#[serial]
+fn test_use_starts_config_defined_server() {
+    // `use <name>` with no command starts the server as `sharedserver.toml`
+    // defines it, found above the working directory; flags override it.
+    let server_name = "test_config_server";
+    cleanup_lock_files(server_name);
+    let long_running = get_test_helper_path("long_running.sh");
+    let project = env::temp_dir().join("sharedserver-inttest-config");
+    let _ = fs::remove_dir_all(&project);
+    fs::create_dir_all(project.join("svc")).unwrap();
+    fs::create_dir_all(project.join("sub")).unwrap();
+    fs::write(
+        project.join("sharedserver.toml"),
+        format!(
+            "[servers.{name}]\n\
+             command = ['{cmd}']\n\
+             cwd = 'svc'\n\
+             env = {{ TEST_VAR = 'config', ANOTHER_VAR = 'kept' }}\n\
+             grace_period = '7m'\n\
+             log_file = 'server.log'\n",
+            name = server_name,
+            cmd = long_running.display(),
+        ),
+    )
+    .unwrap();
+
+    let use_server = |args: &[&str]| {
+        Command::new(get_binary_path())
+            .arg("use")
+            .args(args)
+            .current_dir(project.join("sub"))
+            .env("SHAREDSERVER_LOCKDIR", test_lockdir())
+            .env("XDG_CONFIG_HOME", &project)
+            .env_remove("SHAREDSERVER_CONFIG")
+            .output()
+            .expect("Failed to run sharedserver use")
+    };
+    let out = use_server(&[server_name, "--pid", "1", "--env", "TEST_VAR=cli"]);
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+
+    let info = run_command(&["info", server_name, "--json"]);
+    let info: serde_json::Value = serde_json::from_slice(&info.stdout).unwrap();
+    assert_eq!(info["command"][0], long_running.to_str().unwrap());
+    assert_eq!(info["grace_period"], "7m");
+    let cwd = PathBuf::from(info["cwd"].as_str().unwrap());
+    assert_eq!(
+        cwd.canonicalize().unwrap(),
+        project.join("svc").canonicalize().unwrap()
+    );
+    // --env comes after the config's, so it wins.
+    assert_eq!(
+        info["env"],
+        serde_json::json!(["ANOTHER_VAR=kept", "TEST_VAR=config", "TEST_VAR=cli"])
+    );
+    assert!(project.join("svc/server.log").exists());
+    let _ = run_command(&["admin", "kill", server_name]);
+    cleanup_lock_files(server_name);
+
+    // A name the config doesn't define still needs a command.
+    let out = use_server(&["test_config_undefined", "--pid", "1"]);
+    assert_eq!(out.status.code(), Some(10));
+    let _ = fs::remove_dir_all(&project);
+}
+
+#[test]
+#[serial]
+fn test_up_and_down_config_servers() {
+    // `up` uses every config-defined server in a group, reporting each in a
+    // table; `down` stops them.
+    let names = ["test_up_a", "test_up_b", "test_up_other"];
+    for name in names {
+        cleanup_lock_files(name);
+    }
+    let long_running = get_test_helper_path("long_running.sh");
+    let dir = env::temp_dir().join("sharedserver-inttest-up");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let config = dir.join("config.toml");
+    fs::write(
+        &config,
+        format!(
+            "[servers.test_up_a]\ncommand = ['{cmd}']\ngroups = ['stack']\n\
+             [servers.test_up_b]\ncommand = ['{cmd}']\ngroups = ['stack']\n\
+             [servers.test_up_other]\ncommand = ['{cmd}']\n",
+            cmd = long_running.display(),
+        ),
+    )
+    .unwrap();
+    let sharedserver = |args: &[&str]| {
+        Command::new(get_binary_path())
+            .args(args)
+            .env("SHAREDSERVER_LOCKDIR", test_lockdir())
+            .env("SHAREDSERVER_CONFIG", &config)
+            .output()
+            .expect("Failed to run sharedserver")
+    };
+
+    let out = sharedserver(&["up", "stack", "--pid", "1"]);
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(out.status.success(), "{}", stdout);
+    assert!(stdout.contains("test_up_a") && stdout.contains("started"));
+    assert!(!stdout.contains("test_up_other"), "{}", stdout);
+    for name in ["test_up_a", "test_up_b"] {
+        let info = run_command(&["info", name, "--json"]);
+        let info: serde_json::Value = serde_json::from_slice(&info.stdout).unwrap();
+        assert_eq!(info["state"], "active", "{}", info);
+        assert_eq!(info["clients"][0]["metadata"], "sharedserver up");
+    }
+    assert!(!run_command(&["check", "test_up_other", "-q"])
+        .status
+        .success());
+
+    // Again: already running, so just attached.
+    let out = sharedserver(&["up", "stack", "--pid", "1"]);
+    assert!(String::from_utf8_lossy(&out.stdout).contains("running"));
+
+    let out = sharedserver(&["down"]);
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(out.status.success(), "{}", stdout);
+    assert!(stdout.contains("stopped") && stdout.contains("not running"));
+    for name in names {
+        assert!(!run_command(&["check", name, "-q"]).status.success());
+    }
+
+    let out = sharedserver(&["up", "nope"]);
+    assert_eq!(out.status.code(), Some(2));
+    for name in names {
+        cleanup_lock_files(name);
+    }
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+#[serial]
+fn test_profile_overrides_config_servers() {
+    // A profile, selected with --profile or SHAREDSERVER_PROFILE, overrides
+    // the servers' settings.
+    let server_name = "test_profile_server";
+    cleanup_lock_files(server_name);
+    let long_running = get_test_helper_path("long_running.sh");
+    let dir = env::temp_dir().join("sharedserver-inttest-profile");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let config = dir.join("config.toml");
+    fs::write(
+        &config,
+        format!(
+            "[servers.{name}]\ncommand = ['{cmd}']\nenv = {{ TEST_VAR = 'dev' }}\n\
+             grace_period = '30m'\n\
+             [profiles.ci.servers.{name}]\nenv = {{ TEST_VAR = 'ci' }}\ngrace_period = '1s'\n",
+            name = server_name,
+            cmd = long_running.display(),
+        ),
+    )
+    .unwrap();
+    let use_server = |args: &[&str], profile_env: Option<&str>| {
+        let mut cmd = Command::new(get_binary_path());
+        cmd.args(args)
+            .env("SHAREDSERVER_LOCKDIR", test_lockdir())
+            .env("SHAREDSERVER_CONFIG", &config)
+            .env_remove("SHAREDSERVER_PROFILE");
+        if let Some(profile) = profile_env {
+            cmd.env("SHAREDSERVER_PROFILE", profile);
+        }
+        cmd.output().expect("Failed to run sharedserver")
+    };
+    let started = |expected_env: &str, expected_grace: &str| {
+        let info = run_command(&["info", server_name, "--json"]);
+        let info: serde_json::Value = serde_json::from_slice(&info.stdout).unwrap();
+        assert_eq!(info["env"], serde_json::json!([expected_env]), "{}", info);
+        assert_eq!(info["grace_period"], expected_grace, "{}", info);
+        let _ = run_command(&["admin", "kill", server_name]);
+        cleanup_lock_files(server_name);
+    };
+
+    let out = use_server(&["use", server_name, "--pid", "1"], None);
+    assert!(out.status.success());
+    started("TEST_VAR=dev", "30m");
+
+    let out = use_server(&["--profile", "ci", "use", server_name, "--pid", "1"], None);
+    assert!(out.status.success());
+    started("TEST_VAR=ci", "1s");
+
+    let out = use_server(&["use", server_name, "--pid", "1"], Some("ci"));
+    assert!(out.status.success());
+    started("TEST_VAR=ci", "1s");
+
+    // An unknown profile is an error, not silently the defaults.
+    let out = use_server(&["use", server_name, "--pid", "1"], Some("staging"));
+    assert_eq!(out.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("No profile 'staging'"));
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+#[serial]
+fn test_config_grace_period_default_for_direnv() {
+    // Commands that only take the grace period from the config (here
+    // `direnv`) still default to it, and the flag still overrides it.
+    let server_name = "test_config_grace_direnv";
+    cleanup_lock_files(server_name);
+    let long_running = get_test_helper_path("long_running.sh");
+    let dir = env::temp_dir().join("sharedserver-inttest-config-grace");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let config = dir.join("config.toml");
+    fs::write(
+        &config,
+        format!("[servers.{}]\ngrace_period = '45m'\n", server_name),
+    )
+    .unwrap();
+    let direnv = |extra: &[&str]| {
+        let cmd = long_running.to_string_lossy();
+        let mut args = vec!["direnv", server_name];
+        args.extend_from_slice(extra);
+        args.extend_from_slice(&["--", &cmd]);
+        let out = Command::new(get_binary_path())
+            .args(&args)
+            .current_dir(&dir)
+            .env("SHAREDSERVER_LOCKDIR", test_lockdir())
+            .env("SHAREDSERVER_CONFIG", &config)
+            .output()
+            .expect("Failed to run sharedserver");
+        assert!(
+            out.status.success(),
+            "{}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+        let info = run_command(&["info", server_name, "--json"]);
+        let info: serde_json::Value = serde_json::from_slice(&info.stdout).unwrap();
+        let _ = run_command(&["admin", "kill", server_name]);
+        thread::sleep(Duration::from_millis(500));
+        cleanup_lock_files(server_name);
+        info["grace_period"].clone()
+    };
+
+    assert_eq!(direnv(&[]), "45m");
+    assert_eq!(direnv(&["--grace-period", "2m"]), "2m");
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+#[serial]
+fn test_config_defaults_apply_to_cli_and_watcher() {
+    // `[defaults]` sets the lock directory (for the watcher too, or `info`
+    // wouldn't find the server), the grace period of servers that don't set
+    // one, and the color mode, which `--color` overrides.
+    let server_name = "test_config_defaults";
+    let long_running = get_test_helper_path("long_running.sh");
+    let dir = env::temp_dir().join("sharedserver-inttest-defaults");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let config = dir.join("config.toml");
+    fs::write(
+        &config,
+        format!(
+            "[defaults]\nlockdir = 'locks'\ngrace_period = '42m'\ncolor = 'always'\n\
+             shutdown_timeout = '2s'\npoll_interval = '200ms'\n\
+             [servers.{}]\ncommand = ['{}']\n",
+            server_name,
+            long_running.display(),
+        ),
+    )
+    .unwrap();
+    let sharedserver = |args: &[&str]| {
+        Command::new(get_binary_path())
+            .args(args)
+            .env("SHAREDSERVER_CONFIG", &config)
+            .env_remove("SHAREDSERVER_LOCKDIR")
+            .env_remove("NO_COLOR")
+            .output()
+            .expect("Failed to run sharedserver")
+    };
+
+    let out = sharedserver(&["use", server_name, "--pid", "1"]);
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    assert!(dir
+        .join("locks")
+        .join(server_name)
+        .join("server.json")
+        .exists());
+
+    let info = sharedserver(&["info", server_name, "--json"]);
+    let info: serde_json::Value = serde_json::from_slice(&info.stdout).unwrap();
+    assert_eq!(info["grace_period"], "42m", "{}", info);
+    // `admin start` takes the default too.
+    let started = "test_config_defaults_start";
+    let out = sharedserver(&[
+        "admin",
+        "start",
+        started,
+        "--",
+        long_running.to_str().unwrap(),
+    ]);
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    let info = sharedserver(&["info", started, "--json"]);
+    let info: serde_json::Value = serde_json::from_slice(&info.stdout).unwrap();
+    assert_eq!(info["grace_period"], "42m", "{}", info);
+    let _ = sharedserver(&["admin", "kill", started]);
+    let list = sharedserver(&["list"]);
+    assert!(
+        String::from_utf8_lossy(&list.stdout).contains('\u{1b}'),
+        "color = 'always' should color list"
+    );
+    let list = sharedserver(&["--color", "never", "list"]);
+    assert!(!String::from_utf8_lossy(&list.stdout).contains('\u{1b}'));
+
+    let _ = sharedserver(&["admin", "kill", server_name]);
+    thread::sleep(Duration::from_millis(500));
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+#[serial]
+fn test_config_variables_expand_at_start() {
+    // `{name}`, `{port}`, `{lockdir}`, `{home}`, and `${VAR}` expand in a
+    // config-defined server's command, env, and log file; `{port}` is a free
+    // port that becomes the address, and is kept by `--replace`.
+    let server_name = "test_config_variables";
+    cleanup_lock_files(server_name);
+    let long_running = get_test_helper_path("long_running.sh");
+    let dir = env::temp_dir().join("sharedserver-inttest-variables");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let config = dir.join("config.toml");
+    fs::write(
+        &config,
+        format!(
+            "[servers.{}]\ncommand = ['{}']\nlog_file = '{{lockdir}}/{{name}}.log'\n\
+             env = {{ PORT = '{{port}}', WHO = '{{name}}@{{home}}', FROM = '${{TEST_FROM}}' }}\n",
+            server_name,
+            long_running.display(),
+        ),
+    )
+    .unwrap();
+    let use_server = |extra: &[&str]| {
+        let out = Command::new(get_binary_path())
+            .args(["use", server_name, "--pid", "1"])
+            .args(extra)
+            .env("SHAREDSERVER_LOCKDIR", test_lockdir())
+            .env("SHAREDSERVER_CONFIG", &config)
+            .env("HOME", "/home/tester")
+            .env("TEST_FROM", "outside")
+            .output()
+            .expect("Failed to run sharedserver");
+        assert!(
+            out.status.success(),
+            "{}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+        let info = run_command(&["info", server_name, "--json"]);
+        serde_json::from_slice::<serde_json::Value>(&info.stdout).unwrap()
+    };
+
+    let info = use_server(&[]);
+    let env_vars: Vec<&str> = info["env"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|v| v.as_str())
+        .collect();
+    let port = env_vars
+        .iter()
+        .find_map(|var| var.strip_prefix("PORT="))
+        .expect("PORT set")
+        .to_string();
+    assert!(port.parse::<u16>().is_ok(), "{}", port);
+    assert_eq!(info["address"], format!("tcp:127.0.0.1:{}", port));
+    assert!(env_vars.contains(&"WHO=test_config_variables@/home/tester"));
+    assert!(env_vars.contains(&"FROM=outside"));
+    assert!(test_lockdir().join(format!("{}.log", server_name)).exists());
+
+    let replaced = use_server(&["--replace"]);
+    assert_eq!(
+        replaced["pid"], info["pid"],
+        "same port, nothing to replace"
+    );
+
+    let _ = run_command(&["admin", "kill", server_name]);
+    thread::sleep(Duration::from_millis(500));
+    cleanup_lock_files(server_name);
+    let _ = fs::remove_file(test_lockdir().join(format!("{}.log", server_name)));
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+#[serial]
+fn test_config_alias_expands_before_parsing() {
+    // An alias in the subcommand's place stands for its arguments, with the
+    // rest of the command line after them; built-in subcommands win.
+    let server_name = "test_config_alias";
+    cleanup_lock_files(server_name);
+    let long_running = get_test_helper_path("long_running.sh");
+    let dir = env::temp_dir().join("sharedserver-inttest-alias");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let config = dir.join("config.toml");
+    fs::write(
+        &config,
+        format!(
+            "[aliases]\nsleeper = 'use {name} --grace-period 7m'\nlist = 'info {name}'\n\
+             [servers.{name}]\ncommand = ['{cmd}']\n",
+            name = server_name,
+            cmd = long_running.display(),
+        ),
+    )
+    .unwrap();
+    let sharedserver = |args: &[&str]| {
+        Command::new(get_binary_path())
+            .args(args)
+            .env("SHAREDSERVER_LOCKDIR", test_lockdir())
+            .env("SHAREDSERVER_CONFIG", &config)
+            .output()
+            .expect("Failed to run sharedserver")
+    };
+
+    let out = sharedserver(&["--color", "never", "sleeper", "--pid", "1"]);
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    let info = run_command(&["info", server_name, "--json"]);
+    let info: serde_json::Value = serde_json::from_slice(&info.stdout).unwrap();
+    assert_eq!(info["grace_period"], "7m", "{}", info);
+
+    let out = sharedserver(&["list", "--json"]);
+    let list: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert!(list.is_array(), "`list` is still the built-in: {}", list);
+
+    let _ = run_command(&["admin", "kill", server_name]);
+    thread::sleep(Duration::from_millis(500));
+    cleanup_lock_files(server_name);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_config_check_reports_problems_with_lines() {
+    // `config check` reports each bad server setting with its file and line,
+    // and fails; a good config passes.
+    let dir = env::temp_dir().join("sharedserver-inttest-config-check");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let config = dir.join("config.toml");
+    let check = |text: &str| {
+        fs::write(&config, text).unwrap();
+        Command::new(get_binary_path())
+            .args(["--color", "never", "config", "check"])
+            .env("SHAREDSERVER_CONFIG", &config)
+            .output()
+            .expect("Failed to run sharedserver")
+    };
+
+    let out = check(
+        "[servers.db]\ncommand = ['sh', '-c', 'true']\nenv = { PGPORT = '5433' }\n\
+         \n[servers.web]\ncommand = 'no-such-program-xyz --port 1'\n\
+         grace_period = '5 minutes'\n\
+         \n[servers.cache]\ncommand = 'exec sleep 1'\nenv = { PORT = '5433' }\n\
+         \n[aliases]\nlist = 'use db'\n",
+    );
+    assert_eq!(out.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    let at = |line: usize| format!("{}:{}: ", config.display(), line);
+    assert!(
+        stderr.contains(&format!("{}server 'web': invalid grace_period", at(7))),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains(&format!("{}server 'web': 'no-such-program-xyz'", at(6))),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains(&format!(
+            "{}server 'db': port 5433 (PGPORT) is also used by server 'cache'",
+            at(3)
+        )),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("alias 'list' is never used"), "{}", stderr);
+    assert!(stderr.contains("4 problem(s) in the config"), "{}", stderr);
+
+    let out = check("[servers.db]\ncommand = ['sh', '-c', 'true']\ngrace_period = '1h'\n");
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    assert!(String::from_utf8_lossy(&out.stdout).contains("Config OK: 1 server(s)"));
+
+    // A syntax error is reported with its line by the parser.
+    let out = check("[servers.db]\ncommand = \n");
+    assert_eq!(out.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("line 2"));
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+#[serial]
+fn test_watcher_reloads_settings_when_config_changes() {
+    // Editing a running server's grace period and restart policy in the
+    // config reaches its watcher, and the lock, without a restart.
+    let server_name = "test_config_reload";
+    cleanup_lock_files(server_name);
+    let long_running = get_test_helper_path("long_running.sh");
+    let dir = env::temp_dir().join("sharedserver-inttest-reload");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let config = dir.join("config.toml");
+    let write_config = |grace_period: &str, restart: &str| {
+        fs::write(
+            &config,
+            format!(
+                "[servers.{}]\ncommand = ['{}']\ngrace_period = '{}'\nrestart = '{}'\n",
+                server_name,
+                long_running.display(),
+                grace_period,
+                restart,
+            ),
+        )
+        .unwrap();
+    };
+    write_config("5m", "never");
+    let sharedserver = |args: &[&str]| {
+        Command::new(get_binary_path())
+            .args(args)
+            .env("SHAREDSERVER_CONFIG", &config)
+            .output()
+            .expect("Failed to run sharedserver")
+    };
+    let info = || {
+        let out = sharedserver(&["info", server_name, "--json"]);
+        serde_json::from_slice::<serde_json::Value>(&out.stdout).unwrap()
+    };
+
+    let out = sharedserver(&["use", server_name, "--pid", "1"]);
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    assert_eq!(info()["grace_period"], "5m");
+    let server_pid = info()["pid"].clone();
+
+    thread::sleep(Duration::from_millis(500));
+    write_config("7m", "on-failure");
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    while info()["grace_period"] != "7m" && std::time::Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(100));
+    }
+    let reloaded = info();
+    assert_eq!(reloaded["grace_period"], "7m", "{}", reloaded);
+    assert_eq!(reloaded["restart"], "on-failure", "{}", reloaded);
+    assert_eq!(reloaded["pid"], server_pid, "the server kept running");
+
+    let _ = sharedserver(&["admin", "kill", server_name]);
+    thread::sleep(Duration::from_millis(500));
+    cleanup_lock_files(server_name);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+#[serial]
+fn test_watcher_reloads_project_config_from_outside_cwd() {
+    // The watcher runs in the server's `cwd`; when that is outside the
+    // project, it still reloads the project's `sharedserver.toml`.
+    let server_name = "test_config_reload_cwd";
+    cleanup_lock_files(server_name);
+    let long_running = get_test_helper_path("long_running.sh");
+    let project = env::temp_dir().join("sharedserver-inttest-reload-project");
+    let elsewhere = env::temp_dir().join("sharedserver-inttest-reload-elsewhere");
+    for dir in [&project, &elsewhere] {
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+    }
+    let config = project.join("sharedserver.toml");
+    let write_config = |grace_period: &str| {
+        fs::write(
+            &config,
+            format!(
+                "[servers.{}]\ncommand = ['{}']\ncwd = '{}'\ngrace_period = '{}'\n",
+                server_name,
+                long_running.display(),
+                elsewhere.display(),
+                grace_period,
+            ),
+        )
+        .unwrap();
+    };
+    write_config("5m");
+    let out = Command::new(get_binary_path())
+        .args(["use", server_name, "--pid", "1"])
+        .current_dir(&project)
+        .env("SHAREDSERVER_LOCKDIR", test_lockdir())
+        .env("XDG_CONFIG_HOME", &elsewhere)
+        .env_remove("SHAREDSERVER_CONFIG")
+        .output()
+        .expect("Failed to run sharedserver use");
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    let grace_period = || {
+        let out = run_command(&["info", server_name, "--json"]);
+        serde_json::from_slice::<serde_json::Value>(&out.stdout).unwrap()["grace_period"].clone()
+    };
+    assert_eq!(grace_period(), "5m");
+
+    thread::sleep(Duration::from_millis(500));
+    write_config("7m");
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    while grace_period() != "7m" && std::time::Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(100));
+    }
+    assert_eq!(grace_period(), "7m");
+
+    let _ = run_command(&["admin", "kill", server_name]);
+    thread::sleep(Duration::from_millis(500));
+    cleanup_lock_files(server_name);
+    for dir in [&project, &elsewhere] {
+        let _ = fs::remove_dir_all(dir);
+    }
+}
+
+#[test]
+#[serial]
+fn test_tags_filter_list_and_stop() {
+    // `--tag` labels a server, and `list --tag` and `admin stop --tag` select
+    // by it; a config's tags are merged under the flags'.
+    let names = ["test_tag_a", "test_tag_b", "test_tag_c"];
+    for name in names {
+        cleanup_lock_files(name);
+    }
+    let long_running = get_test_helper_path("long_running.sh");
+    let dir = env::temp_dir().join("sharedserver-inttest-tags");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let config = dir.join("config.toml");
+    fs::write(
+        &config,
+        format!(
+            "[servers.test_tag_c]\ncommand = ['{}']\ntags = {{ project = 'foo', kind = 'db' }}\n",
+            long_running.display()
+        ),
+    )
+    .unwrap();
+    let sharedserver = |args: &[&str]| {
+        Command::new(get_binary_path())
+            .args(args)
+            .env("SHAREDSERVER_CONFIG", &config)
+            .output()
+            .expect("Failed to run sharedserver")
+    };
+    let long_running = long_running.to_str().unwrap();
+
+    for (name, tags) in [
+        ("test_tag_a", ["project=foo", "kind=lsp"]),
+        ("test_tag_b", ["project=bar", "kind=lsp"]),
+    ] {
+        let out = sharedserver(&[
+            "use",
+            name,
+            "--pid",
+            "1",
+            "--tag",
+            tags[0],
+            "--tag",
+            tags[1],
+            "--",
+            long_running,
+        ]);
+        assert!(
+            out.status.success(),
+            "{}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+    }
+    let out = sharedserver(&["use", "test_tag_c", "--pid", "1", "--tag", "kind=sql"]);
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+
+    let listed = |args: &[&str]| {
+        let out = sharedserver(&[&["list", "--json"][..], args].concat());
+        let list: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+        let mut names: Vec<String> = list
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|server| server["name"].as_str().unwrap().to_string())
+            .filter(|name| name.starts_with("test_tag_"))
+            .collect();
+        names.sort();
+        names
+    };
+    assert_eq!(
+        listed(&["--tag", "project=foo"]),
+        ["test_tag_a", "test_tag_c"]
+    );
+    assert_eq!(listed(&["--tag", "kind=lsp"]), ["test_tag_a", "test_tag_b"]);
+    assert_eq!(listed(&["--tag", "kind=sql"]), ["test_tag_c"]);
+    assert!(listed(&["--tag", "project=foo", "--tag", "kind=db"]).is_empty());
+
+    let out = sharedserver(&["list", "--tag", "nonsense"]);
+    assert_eq!(out.status.code(), Some(2));
+
+    let out = sharedserver(&["admin", "stop", "--tag", "project=foo", "--force", "--yes"]);
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    assert_eq!(listed(&[]), ["test_tag_b"]);
+
+    let out = sharedserver(&["admin", "stop", "--tag", "project=foo", "--yes"]);
+    assert!(!out.status.success(), "nothing left to stop");
+
+    let _ = sharedserver(&["admin", "kill", "test_tag_b"]);
+    thread::sleep(Duration::from_millis(500));
+    for name in names {
+        cleanup_lock_files(name);
+    }
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+#[serial]
+fn test_glob_patterns_select_servers() {
+    // A pattern in place of a name selects every matching server; stopping
+    // or killing them all needs --yes when there is nobody to ask.
+    let names = ["test_glob_1", "test_glob_2"];
+    for name in names {
+        cleanup_lock_files(name);
+    }
+    let long_running = get_test_helper_path("long_running.sh");
+    let long_running = long_running.to_str().unwrap();
+    for name in names {
+        let out = run_command(&["use", name, "--pid", "1", "--", long_running]);
+        assert!(
+            out.status.success(),
+            "{}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+    }
+
+    let out = run_command(&["info", "test_glob_*", "--json"]);
+    let info: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(info["test_glob_1"]["state"], "active", "{}", info);
+    assert_eq!(info["test_glob_2"]["state"], "active", "{}", info);
+
+    // stdin isn't a terminal, so there is nobody to confirm with.
+    let out = run_command(&["admin", "stop", "test_glob_*", "--force"]);
+    assert_eq!(out.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains("test_glob_1, test_glob_2") && stderr.contains("--yes"),
+        "{}",
+        stderr
+    );
+    assert!(run_command(&["check", "test_glob_1", "-q"])
+        .status
+        .success());
+
+    let out = run_command(&["admin", "kill", "test_glob_*", "--yes"]);
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    for name in names {
+        assert!(!run_command(&["check", name, "-q"]).status.success());
+    }
+
+    let out = run_command(&["info", "test_glob_*"]);
+    assert!(String::from_utf8_lossy(&out.stderr).contains("No server matches"));
+    for name in names {
+        cleanup_lock_files(name);
+    }
+}
+
+#[test]
+#[serial]
+fn test_doctor_jobs_keeps_server_order() {
+    // Servers checked side by side still report in name order, each one's
+    // findings together.
+    let names = ["test_jobs_c", "test_jobs_a", "test_jobs_b"];
+    let long_running = get_test_helper_path("long_running.sh");
+    for name in names {
+        cleanup_lock_files(name);
+        let out = run_command(&[
+            "use",
+            name,
+            "--pid",
+            "1",
+            "--",
+            long_running.to_str().unwrap(),
+        ]);
+        assert!(
+            out.status.success(),
+            "{}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+    }
+
+    let out = run_command(&["admin", "doctor", "--name", "test_jobs_*", "--jobs", "3"]);
+    assert!(out.status.success(), "{:?}", out);
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    let headers: Vec<&str> = stdout
+        .lines()
+        .filter(|line| line.starts_with("Checking"))
+        .collect();
+    assert_eq!(headers.len(), 3, "{}", stdout);
+    for (header, name) in headers
+        .iter()
+        .zip(["test_jobs_a", "test_jobs_b", "test_jobs_c"])
+    {
+        assert!(header.contains(name), "{}", stdout);
+    }
+    let blocks: Vec<&str> = stdout.split("Checking").skip(1).collect();
+    assert!(
+        blocks.iter().all(|block| block.contains("No issues found")),
+        "{}",
+        stdout
+    );
+
+    let out = run_command(&["list", "--jobs", "2", "--name", "test_jobs_*", "--json"]);
+    let servers: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(servers.as_array().unwrap().len(), 3, "{}", servers);
+
+    for name in names {
+        let _ = run_command(&["admin", "kill", name]);
+        cleanup_lock_files(name);
+    }
+}
+
+#[test]
+#[serial]
+fn test_dependencies_start_first_and_gate_dependents() {
+    // `use` and `up` start what a server depends on first, and a dependent
+    // only once its dependencies pass their health checks.
+    let names = [
+        "test_deps_db",
+        "test_deps_web",
+        "test_deps_bad",
+        "test_deps_after",
+    ];
+    for name in names {
+        cleanup_lock_files(name);
+    }
+    let long_running = get_test_helper_path("long_running.sh");
+    let dir = env::temp_dir().join("sharedserver-inttest-deps");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let config = dir.join("config.toml");
+    fs::write(
+        &config,
+        format!(
+            "[servers.test_deps_db]\ncommand = ['{cmd}']\nhealth = {{ cmd = 'true' }}\n\
+             [servers.test_deps_web]\ncommand = ['{cmd}']\ndepends_on = ['test_deps_db']\n\
+             [servers.test_deps_bad]\ncommand = ['{cmd}']\ngroups = ['bad']\n\
+             health = {{ cmd = 'false', interval = '1s', timeout = '1s', retries = 1 }}\n\
+             [servers.test_deps_after]\ncommand = ['{cmd}']\ngroups = ['bad']\n\
+             depends_on = ['test_deps_bad']\n",
+            cmd = long_running.display(),
+        ),
+    )
+    .unwrap();
+    let sharedserver = |args: &[&str]| {
+        Command::new(get_binary_path())
+            .args(args)
+            .env("SHAREDSERVER_LOCKDIR", test_lockdir())
+            .env("SHAREDSERVER_CONFIG", &config)
+            .output()
+            .expect("Failed to run sharedserver")
+    };
+    let running = |name: &str| run_command(&["check", name, "-q"]).status.success();
+
+    let out = sharedserver(&["use", "test_deps_web", "--pid", "1"]);
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    assert!(running("test_deps_db") && running("test_deps_web"));
+    let info = run_command(&["info", "test_deps_db", "--json"]);
+    let info: serde_json::Value = serde_json::from_slice(&info.stdout).unwrap();
+    assert_eq!(
+        info["clients"][0]["metadata"], "dependency of test_deps_web",
+        "{}",
+        info
+    );
+    let _ = sharedserver(&["down", "--force"]);
+    assert!(!running("test_deps_db"));
+
+    let out = sharedserver(&["use", "test_deps_web", "--pid", "1", "--no-deps"]);
+    assert!(out.status.success());
+    assert!(running("test_deps_web") && !running("test_deps_db"));
+    let _ = sharedserver(&["down", "--force"]);
+
+    // A dependency that never passes its health check holds back its
+    // dependents.
+    let out = sharedserver(&["up", "bad", "--pid", "1"]);
+    assert!(!out.status.success());
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(stdout.contains("skipped"), "{}", stdout);
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains("test_deps_after: its dependency 'test_deps_bad' failed"),
+        "{}",
+        stderr
+    );
+    assert!(!running("test_deps_after"));
+    let _ = sharedserver(&["down", "--force"]);
+
+    fs::write(
+        &config,
+        "[servers.a]\ndepends_on = ['b']\n[servers.b]\ndepends_on = ['a']\n",
+    )
+    .unwrap();
+    let out = sharedserver(&["config", "check"]);
+    assert_eq!(out.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains("Dependency cycle: a -> b -> a"),
+        "{}",
+        stderr
+    );
+    let out = sharedserver(&["up", "--pid", "1"]);
+    assert_eq!(out.status.code(), Some(2));
+
+    for name in names {
+        cleanup_lock_files(name);
+    }
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+#[serial]
+fn test_address_conflicts_refused_and_flagged() {
+    // A server can't start on an address another running server has, and
+    // doctor flags two that got there anyway.
+    let names = ["test_port_a", "test_port_b"];
+    for name in names {
+        cleanup_lock_files(name);
+    }
+    let long_running = get_test_helper_path("long_running.sh");
+    let long_running = long_running.to_str().unwrap();
+    let use_at = |name: &str, address: &str| {
+        run_command(&[
+            "use",
+            name,
+            "--pid",
+            "1",
+            "--address",
+            address,
+            "--",
+            long_running,
+        ])
+    };
+
+    assert!(use_at("test_port_a", "18431").status.success());
+    let out = use_at("test_port_b", "0.0.0.0:18431");
+    assert_eq!(out.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains("which server 'test_port_a' is already using"),
+        "{}",
+        stderr
+    );
+    assert!(!run_command(&["check", "test_port_b", "-q"])
+        .status
+        .success());
+
+    // Edit b's lock to a's address, as if two had started at once.
+    assert!(use_at("test_port_b", "18432").status.success());
+    let lock = test_lockdir().join("test_port_b").join("server.json");
+    let text = fs::read_to_string(&lock).unwrap();
+    fs::write(&lock, text.replace("127.0.0.1:18432", "127.0.0.1:18431")).unwrap();
+    let out = run_command(&["admin", "doctor", "test_port_b", "--json"]);
+    assert_eq!(out.status.code(), Some(16));
+    let report: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    let findings = report["servers"][0]["findings"].as_array().unwrap();
+    assert!(
+        findings.iter().any(|finding| finding["check"] == "address"
+            && finding["message"]
+                .as_str()
+                .unwrap()
+                .contains("claimed by running server 'test_port_a'")),
+        "{}",
+        report
+    );
+
+    // Starts racing for one address: only one gets it, even without a cap.
+    let racers = [
+        "test_port_r1",
+        "test_port_r2",
+        "test_port_r3",
+        "test_port_r4",
+    ];
+    for name in racers {
+        cleanup_lock_files(name);
+    }
+    let started = std::thread::scope(|scope| {
+        let handles: Vec<_> = racers
+            .iter()
+            .map(|name| scope.spawn(move || use_at(name, "18433").status.success()))
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .filter(|&success| success)
+            .count()
+    });
+    assert_eq!(started, 1, "exactly one racer should claim the address");
+
+    for name in names.iter().chain(&racers) {
+        let _ = run_command(&["admin", "kill", name]);
+        cleanup_lock_files(name);
+    }
+}
+
+#[test]
+#[serial]
+fn test_max_running_fails_fast_or_queues() {
+    // Over the config's max_running, `use` fails with its own exit code, or
+    // with --queue waits until a server stops.
+    let names = ["test_cap_a", "test_cap_b"];
+    for name in names {
+        cleanup_lock_files(name);
+    }
+    // Servers other tests left running would count toward the cap.
+    let lockdir = env::temp_dir().join("sharedserver-inttest-cap-locks");
+    let _ = fs::remove_dir_all(&lockdir);
+    fs::create_dir_all(&lockdir).unwrap();
+    let dir = env::temp_dir().join("sharedserver-inttest-cap");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let config = dir.join("config.toml");
+    fs::write(&config, "[defaults]\nmax_running = 1\n").unwrap();
+    let long_running = get_test_helper_path("long_running.sh");
+    let long_running = long_running.to_str().unwrap();
+    let sharedserver = |args: &[&str]| {
+        let mut command = Command::new(get_binary_path());
+        command
+            .args(args)
+            .env("SHAREDSERVER_LOCKDIR", &lockdir)
+            .env("SHAREDSERVER_CONFIG", &config)
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped());
+        command
+    };
+    let use_server = |name: &str, extra: &[&str]| {
+        let mut args = vec!["use", name, "--pid", "1", "--grace-period", "none"];
+        args.extend_from_slice(extra);
+        args.extend_from_slice(&["--", long_running]);
+        sharedserver(&args)
+    };
+    let running = |name: &str| {
+        sharedserver(&["check", name, "-q"])
+            .status()
+            .unwrap()
+            .success()
+    };
+
+    assert!(use_server("test_cap_a", &[]).status().unwrap().success());
+    let out = use_server("test_cap_b", &[]).output().unwrap();
+    assert_eq!(out.status.code(), Some(17));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains("as many as max_running (1) allows") && stderr.contains("test_cap_a"),
+        "{}",
+        stderr
+    );
+    assert!(!running("test_cap_b"));
+
+    let mut queued = use_server("test_cap_b", &["--queue"]).spawn().unwrap();
+    thread::sleep(Duration::from_millis(1500));
+    assert!(queued.try_wait().unwrap().is_none(), "--queue should wait");
+    assert!(sharedserver(&["admin", "stop", "test_cap_a", "--force"])
+        .status()
+        .unwrap()
+        .success());
+    let out = queued.wait_with_output().unwrap();
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    assert!(running("test_cap_b"));
+
+    let _ = sharedserver(&["admin", "kill", "test_cap_b"]).output();
+    let _ = fs::remove_dir_all(&lockdir);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+#[serial]
+fn test_invalid_server_names_rejected() {
+    let outside = test_lockdir().with_file_name("sharedserver-inttest-escape");
+    let _ = fs::remove_dir_all(&outside);
+
+    for name in ["../sharedserver-inttest-escape", "", "..", "registry.json"] {
+        let output = run_command(&["admin", "start", name, "--", "sleep", "30"]);
+        assert_eq!(
+            output.status.code(),
+            Some(2),
+            "{:?} should be refused",
+            name
+        );
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("Invalid server name"), "{}", stderr);
+    }
+    assert!(
+        !outside.exists(),
+        "nothing is written outside the lock directory"
+    );
+    assert!(!test_lockdir().join("server.json").exists());
+}
@@ -0,0 +1,70 @@
+-- Tests for the native module (rust/lua), once built with `make native`
+-- Run with: make test
+
+local ok, native = pcall(require, "sharedserver.native")
+
+-- Attaching starts servers through the CLI; a debug build is enough.
+if not os.getenv("SHAREDSERVER_BIN") then
+    vim.fn.setenv("SHAREDSERVER_BIN", vim.fn.getcwd() .. "/rust/target/debug/sharedserver")
+end
+
+local lockdir = vim.fn.tempname()
+vim.fn.setenv("SHAREDSERVER_LOCKDIR", lockdir)
+
+describe("sharedserver.native", function()
+    if not ok then
+        pending("not built (run `make native`)")
+        return
+    end
+
+    after_each(function()
+        native.detach("native-spec")
+    end)
+
+    it("returns nil, message, and kind on failure", function()
+        local result, err, kind = native.info("native-spec")
+        assert.is_nil(result)
+        assert.is_truthy(err:match("not running"))
+        assert.equals("not-running", kind)
+
+        result, err, kind = native.attach("native-spec", { command = { "sleep", 30 } })
+        assert.is_nil(result)
+        assert.equals("opts.command[2] must be a string", err)
+        assert.equals("invalid-args", kind)
+    end)
+
+    it("attaches, reports, and detaches", function()
+        local refcount = assert(native.attach("native-spec", {
+            command = { "sleep", "30" },
+            grace_period = "1s",
+            metadata = "spec",
+        }))
+        assert.equals(1, refcount)
+
+        local info = assert(native.info("native-spec"))
+        assert.equals("active", info.state)
+        assert.equals("sleep", info.server.command[1])
+        assert.equals("spec", info.clients[tostring(vim.fn.getpid())].metadata)
+
+        assert.equals(0, native.detach("native-spec"))
+        assert.equals("grace", native.info("native-spec").state)
+    end)
+
+    it("calls back with state changes on the main loop", function()
+        local events = {}
+        local sub = assert(native.subscribe("native-spec", function(event)
+            table.insert(events, event)
+        end))
+        assert.equals("stopped", sub:state())
+
+        assert(native.attach("native-spec", { command = { "sleep", "30" }, grace_period = "1s" }))
+        assert.is_true(vim.wait(5000, function()
+            return #events > 0 and events[#events].event == "client-attached"
+        end))
+        assert.same({ event = "client-attached", pid = vim.fn.getpid() }, events[#events])
+        assert.equals("active", sub:state())
+
+        sub:close()
+        sub:close()
+    end)
+end)
@@ -60,8 +60,8 @@ describe("sharedserver._get_lockdir", function()
         end)
     end)
 
-    it("falls back to /tmp/sharedserver when no env vars set", function()
-        assert.equals("/tmp/sharedserver", sharedserver._get_lockdir())
+    it("falls back to a per-user /tmp/sharedserver-<uid> when no env vars set", function()
+        assert.equals("/tmp/sharedserver-" .. vim.uv.getuid(), sharedserver._get_lockdir())
     end)
 end)
 
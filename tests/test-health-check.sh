@@ -47,13 +47,13 @@ wait $USE_PID 2>/dev/null || true
 sleep 1
 
 # Check if server lock file exists
-if [ -f "$SHAREDSERVER_LOCKDIR/$TEST_SERVER_NAME.server.json" ]; then
+if [ -f "$SHAREDSERVER_LOCKDIR/$TEST_SERVER_NAME/server.json" ]; then
 	echo -e "${RED}✗${NC} Server lock file still exists (server didn't exit or timeout didn't work)"
 	exit 1
 fi
 
 # Check if clients lock file exists
-if [ -f "$SHAREDSERVER_LOCKDIR/$TEST_SERVER_NAME.clients.json" ]; then
+if [ -f "$SHAREDSERVER_LOCKDIR/$TEST_SERVER_NAME/clients.json" ]; then
 	echo -e "${RED}✗${NC} Clients lock file still exists (watcher didn't clean up)"
 	exit 1
 fi
@@ -95,12 +95,12 @@ fi
 echo -e "${GREEN}✓${NC} Server exited as expected"
 
 # Check if lock files were cleaned up
-if [ -f "$SHAREDSERVER_LOCKDIR/$TEST_SERVER_NAME.server.json" ]; then
+if [ -f "$SHAREDSERVER_LOCKDIR/$TEST_SERVER_NAME/server.json" ]; then
 	echo -e "${RED}✗${NC} Server lock file still exists after death"
 	exit 1
 fi
 
-if [ -f "$SHAREDSERVER_LOCKDIR/$TEST_SERVER_NAME.clients.json" ]; then
+if [ -f "$SHAREDSERVER_LOCKDIR/$TEST_SERVER_NAME/clients.json" ]; then
 	echo -e "${RED}✗${NC} Clients lock file still exists after death"
 	exit 1
 fi
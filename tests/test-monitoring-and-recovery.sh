@@ -105,8 +105,8 @@ info "Test 1.1: Server fails to start (invalid command) - lock files must not ex
 "$SHAREDSERVER" use --grace-period 5m crash-test-fail /nonexistent/command arg1 2>/dev/null || true
 sleep 1
 
-if [ -f "$TEST_LOCKDIR/crash-test-fail.server.json" ]; then
-	SERVER_CONTENT=$(cat "$TEST_LOCKDIR/crash-test-fail.server.json" 2>/dev/null || echo "{}")
+if [ -f "$TEST_LOCKDIR/crash-test-fail/server.json" ]; then
+	SERVER_CONTENT=$(cat "$TEST_LOCKDIR/crash-test-fail/server.json" 2>/dev/null || echo "{}")
 	fail "Test 1.1: server lockfile should not exist after failed start. Content: $SERVER_CONTENT"
 else
 	pass "Test 1.1: server lockfile correctly not created after failed start"
@@ -126,14 +126,14 @@ wait $START_PID 2>/dev/null || true
 sleep 0.5
 
 # Verify server started (lock file should exist)
-if [ ! -f "$TEST_LOCKDIR/crash-test-immediate.server.json" ]; then
+if [ ! -f "$TEST_LOCKDIR/crash-test-immediate/server.json" ]; then
 	fail "Test 1.2: server lockfile should exist after successful start"
 	# Don't continue if server didn't start
 else
 	# Wait for server to crash and watcher to clean up (3s server runtime + 5s watcher poll + buffer)
 	sleep 9
 
-	if [ -f "$TEST_LOCKDIR/crash-test-immediate.server.json" ]; then
+	if [ -f "$TEST_LOCKDIR/crash-test-immediate/server.json" ]; then
 		fail "Test 1.2: server lockfile should be cleaned up after crash"
 	else
 		pass "Test 1.2: server lockfile correctly cleaned up after crash"
@@ -161,12 +161,12 @@ sleep 0.5
 sleep 0.5
 
 # Verify lock files exist and are valid
-if [ ! -f "$TEST_LOCKDIR/crash-test-with-client.server.json" ]; then
+if [ ! -f "$TEST_LOCKDIR/crash-test-with-client/server.json" ]; then
 	fail "Test 1.3: server lockfile missing before crash"
 else
 	# Check server lockfile is valid JSON and not empty
-	if jq -e '.pid' "$TEST_LOCKDIR/crash-test-with-client.server.json" >/dev/null 2>&1; then
-		SERVER_PID=$(jq -r '.pid' "$TEST_LOCKDIR/crash-test-with-client.server.json")
+	if jq -e '.pid' "$TEST_LOCKDIR/crash-test-with-client/server.json" >/dev/null 2>&1; then
+		SERVER_PID=$(jq -r '.pid' "$TEST_LOCKDIR/crash-test-with-client/server.json")
 		if [ -z "$SERVER_PID" ] || [ "$SERVER_PID" = "null" ]; then
 			fail "Test 1.3: server lockfile contains empty/null pid"
 		else
@@ -177,12 +177,12 @@ else
 	fi
 fi
 
-if [ ! -f "$TEST_LOCKDIR/crash-test-with-client.clients.json" ]; then
+if [ ! -f "$TEST_LOCKDIR/crash-test-with-client/clients.json" ]; then
 	fail "Test 1.3: clients lockfile missing before crash"
 else
 	# Check clients lockfile is valid JSON with correct refcount (should be 2: test script + client 12345)
-	if jq -e '.refcount' "$TEST_LOCKDIR/crash-test-with-client.clients.json" >/dev/null 2>&1; then
-		REFCOUNT=$(jq -r '.refcount' "$TEST_LOCKDIR/crash-test-with-client.clients.json")
+	if jq -e '.refcount' "$TEST_LOCKDIR/crash-test-with-client/clients.json" >/dev/null 2>&1; then
+		REFCOUNT=$(jq -r '.refcount' "$TEST_LOCKDIR/crash-test-with-client/clients.json")
 		if [ "$REFCOUNT" = "2" ]; then
 			pass "Test 1.3b: clients lockfile valid with refcount=1"
 		else
@@ -194,12 +194,12 @@ else
 fi
 
 # Kill the server process to simulate crash
-SERVER_REAL_PID=$(jq -r '.pid' "$TEST_LOCKDIR/crash-test-with-client.server.json")
+SERVER_REAL_PID=$(jq -r '.pid' "$TEST_LOCKDIR/crash-test-with-client/server.json")
 kill -9 $SERVER_REAL_PID 2>/dev/null || true
 sleep 6 # Wait for watcher to detect and cleanup
 
 # Verify cleanup happened
-if [ -f "$TEST_LOCKDIR/crash-test-with-client.server.json" ]; then
+if [ -f "$TEST_LOCKDIR/crash-test-with-client/server.json" ]; then
 	fail "Test 1.3c: server lockfile not cleaned up after crash"
 else
 	pass "Test 1.3c: server lockfile cleaned up after crash"
@@ -214,17 +214,19 @@ info "Test 1.4: Empty/corrupted lock files are handled gracefully"
 ((TESTS_TOTAL++))
 
 # Create empty lock files
-touch "$TEST_LOCKDIR/corrupt-test.server.json"
-touch "$TEST_LOCKDIR/corrupt-test.clients.json"
+mkdir -p "$TEST_LOCKDIR/corrupt-test"
+touch "$TEST_LOCKDIR/corrupt-test/server.json"
+touch "$TEST_LOCKDIR/corrupt-test/clients.json"
 
-# Try to get info (should fail gracefully)
-if "$SHAREDSERVER" info corrupt-test 2>/dev/null; then
-	fail "Test 1.4: info should fail for corrupted lockfile"
+# An unreadable lock reads as stopped, so info reports that rather than erroring
+if "$SHAREDSERVER" info corrupt-test 2>/dev/null | grep -q "Stopped"; then
+	pass "Test 1.4: corrupted lockfile handled gracefully (reported as stopped)"
 else
-	pass "Test 1.4: corrupted lockfile handled gracefully (command failed as expected)"
+	fail "Test 1.4: info should report a corrupted lockfile as stopped"
 fi
 
-rm -f "$TEST_LOCKDIR/corrupt-test.server.json" "$TEST_LOCKDIR/corrupt-test.clients.json"
+rm -f "$TEST_LOCKDIR/corrupt-test/server.json" "$TEST_LOCKDIR/corrupt-test/clients.json"
+rmdir "$TEST_LOCKDIR/corrupt-test" 2>/dev/null || true
 
 # ============================================================================
 # Test Category 2: Server Use/Unuse Tracking
@@ -253,7 +255,7 @@ CLIENT3_PID=$!
 sleep 0.5
 
 # Check refcount (should be 4: test script $$ + 3 clients)
-REFCOUNT=$(jq -r '.refcount' "$TEST_LOCKDIR/usetest.clients.json")
+REFCOUNT=$(jq -r '.refcount' "$TEST_LOCKDIR/usetest/clients.json")
 if [ "$REFCOUNT" = "4" ]; then
 	pass "Test 2.1a: refcount correct after 3 increfs (refcount=4: test script + 3 clients)"
 else
@@ -264,7 +266,7 @@ fi
 "$SHAREDSERVER" admin decref --pid $CLIENT2_PID usetest
 sleep 0.5
 
-REFCOUNT_AFTER=$(jq -r '.refcount' "$TEST_LOCKDIR/usetest.clients.json")
+REFCOUNT_AFTER=$(jq -r '.refcount' "$TEST_LOCKDIR/usetest/clients.json")
 if [ "$REFCOUNT_AFTER" = "3" ]; then
 	pass "Test 2.1b: refcount correct after 1 decref (refcount=3)"
 else
@@ -278,7 +280,7 @@ fi
 sleep 1
 
 # Should enter grace period
-if [ -f "$TEST_LOCKDIR/usetest.clients.json" ]; then
+if [ -f "$TEST_LOCKDIR/usetest/clients.json" ]; then
 	fail "Test 2.1c: clients lockfile should be deleted when refcount reaches 0"
 else
 	pass "Test 2.1c: clients lockfile correctly deleted when refcount=0"
@@ -339,7 +341,7 @@ sleep 4
 # Watcher should detect exit and clean up
 sleep 2
 
-if [ -f "$TEST_LOCKDIR/watcher-exit-test.server.json" ]; then
+if [ -f "$TEST_LOCKDIR/watcher-exit-test/server.json" ]; then
 	fail "Test 3.1: watcher failed to clean up after server exit"
 else
 	pass "Test 3.1: watcher correctly cleaned up after server exit"
@@ -356,14 +358,14 @@ SIGTERM_PID=$!
 sleep 1
 
 # Get actual server PID
-SERVER_PID=$(jq -r '.pid' "$TEST_LOCKDIR/sigterm-test.server.json")
+SERVER_PID=$(jq -r '.pid' "$TEST_LOCKDIR/sigterm-test/server.json")
 
 # Kill server with SIGTERM
 kill -TERM $SERVER_PID 2>/dev/null || true
 sleep 6
 
 # Watcher should clean up
-if [ -f "$TEST_LOCKDIR/sigterm-test.server.json" ]; then
+if [ -f "$TEST_LOCKDIR/sigterm-test/server.json" ]; then
 	fail "Test 3.2: watcher failed to clean up after SIGTERM"
 else
 	pass "Test 3.2: watcher correctly cleaned up after SIGTERM"
@@ -381,14 +383,14 @@ SIGKILL_PID=$!
 sleep 1
 
 # Get actual server PID
-SERVER_PID=$(jq -r '.pid' "$TEST_LOCKDIR/sigkill-test.server.json")
+SERVER_PID=$(jq -r '.pid' "$TEST_LOCKDIR/sigkill-test/server.json")
 
 # Kill server with SIGKILL
 kill -9 $SERVER_PID 2>/dev/null || true
 sleep 6
 
 # Watcher should clean up
-if [ -f "$TEST_LOCKDIR/sigkill-test.server.json" ]; then
+if [ -f "$TEST_LOCKDIR/sigkill-test/server.json" ]; then
 	fail "Test 3.3: watcher failed to clean up after SIGKILL"
 else
 	pass "Test 3.3: watcher correctly cleaned up after SIGKILL"
@@ -419,7 +421,7 @@ CLIENT_PID=$!
 sleep 0.5
 
 # Verify client is in the list
-CLIENTS_BEFORE=$(jq -r '.clients | keys | length' "$TEST_LOCKDIR/dead-client-test.clients.json")
+CLIENTS_BEFORE=$(jq -r '.clients | keys | length' "$TEST_LOCKDIR/dead-client-test/clients.json")
 if [ "$CLIENTS_BEFORE" -ge "1" ]; then
 	pass "Test 4.1a: client added successfully"
 else
@@ -433,8 +435,8 @@ sleep 3
 sleep 6
 
 # Check if dead client was removed
-if [ -f "$TEST_LOCKDIR/dead-client-test.clients.json" ]; then
-	REFCOUNT_AFTER=$(jq -r '.refcount' "$TEST_LOCKDIR/dead-client-test.clients.json")
+if [ -f "$TEST_LOCKDIR/dead-client-test/clients.json" ]; then
+	REFCOUNT_AFTER=$(jq -r '.refcount' "$TEST_LOCKDIR/dead-client-test/clients.json")
 	# Should have decremented (our use command client is still there)
 	if [ "$REFCOUNT_AFTER" = "1" ]; then
 		pass "Test 4.1b: dead client removed, refcount adjusted to 1"
@@ -469,7 +471,7 @@ GRACE_CLIENT2=$!
 sleep 0.5
 
 # Server should be ACTIVE
-if [ -f "$TEST_LOCKDIR/grace-test.clients.json" ]; then
+if [ -f "$TEST_LOCKDIR/grace-test/clients.json" ]; then
 	pass "Test 4.2a: server in ACTIVE state with clients"
 else
 	fail "Test 4.2a: server should be ACTIVE with clients"
@@ -482,7 +484,7 @@ fi
 sleep 1
 
 # Should enter grace period (clients.json deleted)
-if [ -f "$TEST_LOCKDIR/grace-test.clients.json" ]; then
+if [ -f "$TEST_LOCKDIR/grace-test/clients.json" ]; then
 	fail "Test 4.2b: should enter grace period (clients.json should be deleted)"
 else
 	pass "Test 4.2b: grace period started (clients.json deleted)"
@@ -518,7 +520,7 @@ sleep 1
 sleep 1
 
 # Verify grace period started
-if [ ! -f "$TEST_LOCKDIR/timeout-test.clients.json" ]; then
+if [ ! -f "$TEST_LOCKDIR/timeout-test/clients.json" ]; then
 	pass "Test 4.3a: grace period started"
 else
 	fail "Test 4.3a: grace period not started"
@@ -531,7 +533,7 @@ info "Waiting 16 seconds for grace period to expire and cleanup..."
 sleep 16
 
 # Server should be stopped and cleaned up
-if [ -f "$TEST_LOCKDIR/timeout-test.server.json" ]; then
+if [ -f "$TEST_LOCKDIR/timeout-test/server.json" ]; then
 	fail "Test 4.3b: server not shut down after grace period"
 else
 	pass "Test 4.3b: server shut down after grace period expired"
@@ -567,8 +569,8 @@ CANCEL_GRACE_CLIENT=$!
 sleep 1
 
 # Should be back to ACTIVE
-if [ -f "$TEST_LOCKDIR/cancel-grace-test.clients.json" ]; then
-	REFCOUNT=$(jq -r '.refcount' "$TEST_LOCKDIR/cancel-grace-test.clients.json")
+if [ -f "$TEST_LOCKDIR/cancel-grace-test/clients.json" ]; then
+	REFCOUNT=$(jq -r '.refcount' "$TEST_LOCKDIR/cancel-grace-test/clients.json")
 	if [ "$REFCOUNT" = "1" ]; then
 		pass "Test 4.4a: grace period cancelled, back to ACTIVE state"
 	else
@@ -0,0 +1,12 @@
+#!/bin/sh
+# Minimal line-delimited JSON-RPC server for stdio tests: answers every request
+# with its PID and how many initialize requests it has seen.
+inits=0
+while IFS= read -r line; do
+    id=$(printf '%s' "$line" | sed -n 's/.*"id":\([0-9][0-9]*\).*/\1/p')
+    [ -z "$id" ] && continue
+    case "$line" in
+        *'"method":"initialize"'*) inits=$((inits + 1)) ;;
+    esac
+    printf '{"jsonrpc":"2.0","id":%s,"result":{"pid":%s,"inits":%s}}\n' "$id" "$$" "$inits"
+done
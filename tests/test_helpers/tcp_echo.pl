@@ -0,0 +1,25 @@
+#!/usr/bin/env perl
+# Minimal TCP server for `lsp` tests: listens on 127.0.0.1:$PORT and answers
+# each line on each connection with the server's PID and the line.
+use strict;
+use warnings;
+use IO::Socket::INET;
+
+$SIG{CHLD} = 'IGNORE';
+my $pid = $$;
+my $server = IO::Socket::INET->new(
+    LocalAddr => '127.0.0.1',
+    LocalPort => $ENV{PORT},
+    Listen    => 16,
+    ReuseAddr => 1,
+) or die "listen: $!";
+while (my $conn = $server->accept) {
+    if (fork() == 0) {
+        $conn->autoflush(1);
+        while (my $line = <$conn>) {
+            print $conn "$pid $line";
+        }
+        exit 0;
+    }
+    close $conn;
+}